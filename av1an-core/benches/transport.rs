@@ -0,0 +1,114 @@
+//! Compares the transports of the frames of a chunk from the source to the
+//! encoder: a default pipe, a pipe enlarged like `--pipe-size 1024`, and a
+//! memfd buffer like `--memfd-buffer`.
+//!
+//! Every worker moves a chunk of uncompressed 4K 10-bit frames from `dd`
+//! reading `/dev/zero` to `dd` writing to `/dev/null`, which reads a frame at
+//! a time like an encoder. Run with `cargo bench -p av1an-core --features
+//! memfd`.
+
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{self, Seek},
+    iter,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Bytes of a 3840x2160 4:2:0 frame with 2 bytes per sample
+const FRAME_BYTES: usize = 3840 * 2160 * 3;
+const FRAMES: usize = 48;
+const RUNS: usize = 5;
+
+#[derive(Clone, Copy, Debug)]
+enum Transport {
+    Pipe,
+    LargePipe,
+    Memfd,
+}
+
+fn source() -> Command {
+    let mut command = Command::new("dd");
+    command.args([
+        "if=/dev/zero",
+        &format!("bs={FRAME_BYTES}"),
+        &format!("count={FRAMES}"),
+        "status=none",
+    ]);
+    command
+}
+
+fn encoder() -> Command {
+    let mut command = Command::new("dd");
+    command.args(["of=/dev/null", &format!("bs={FRAME_BYTES}"), "status=none"]);
+    command
+}
+
+fn memfd() -> io::Result<File> {
+    let name = CString::new("av1an-bench").map_err(io::Error::other)?;
+    // SAFETY: the name is a valid C string
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: memfd_create returned a new open descriptor
+    Ok(File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+/// Moves one chunk from the source to the encoder
+fn move_chunk(transport: Transport) -> io::Result<()> {
+    let mut encoder = encoder();
+    match transport {
+        Transport::Pipe | Transport::LargePipe => {
+            let mut source = source().stdout(Stdio::piped()).spawn()?;
+            let stdout = source.stdout.take().expect("source should have stdout");
+            if matches!(transport, Transport::LargePipe) {
+                // SAFETY: the descriptor stays open, and F_SETPIPE_SZ takes a
+                // single int argument
+                unsafe { libc::fcntl(stdout.as_raw_fd(), libc::F_SETPIPE_SZ, 1024 * 1024) };
+            }
+            encoder.stdin(stdout).status()?;
+            source.wait()?;
+        },
+        Transport::Memfd => {
+            let mut buffer = memfd()?;
+            source().stdout(buffer.try_clone()?).status()?;
+            buffer.rewind()?;
+            encoder.stdin(buffer).status()?;
+        },
+    }
+    Ok(())
+}
+
+/// Time `workers` take to move a chunk each at once
+fn run(transport: Transport, workers: usize) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| move_chunk(transport).expect("should move the chunk"));
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    let workers = thread::available_parallelism().map_or(4, |n| n.get().min(8));
+    println!("{FRAMES} frames of {FRAME_BYTES} bytes per chunk, best of {RUNS} runs");
+    let mut worker_counts = vec![1, workers];
+    worker_counts.dedup();
+    for workers in worker_counts {
+        for transport in [Transport::Pipe, Transport::LargePipe, Transport::Memfd] {
+            let best = iter::repeat_with(|| run(transport, workers))
+                .take(RUNS)
+                .min()
+                .expect("should run at least once");
+            let frames_per_second = (workers * FRAMES) as f64 / best.as_secs_f64();
+            println!(
+                "{workers} workers, {transport:?}: {best:.2?}, {frames_per_second:.0} frames/s"
+            );
+        }
+    }
+}
@@ -0,0 +1,411 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    ffi::OsString,
+    fs,
+    hash::{Hash, Hasher},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use num_traits::ToPrimitive;
+use tracing::{debug, error, info};
+
+use crate::{
+    create_dir,
+    ffmpeg::{get_clip_info, get_keyframes, get_num_frames, select_frames_args},
+    into_vec,
+    split::{keyframe_splits, segment, trim_input},
+    vapoursynth::{create_vs_file, CacheSource, LoadscriptArgs, VapoursynthPlugins},
+    ChunkMethod,
+};
+
+/// Result of decoding the benchmark samples with a single chunk method
+#[derive(Debug, Clone)]
+pub struct ChunkMethodBenchmark {
+    pub method:             ChunkMethod,
+    /// Time spent before the first frame could be decoded, such as indexing or
+    /// splitting the source into intermediate files
+    pub setup_time:         Duration,
+    /// Time spent decoding the samples
+    pub decode_time:        Duration,
+    pub frames_decoded:     usize,
+    pub frames_expected:    usize,
+    /// Number of samples whose frame count or content did not match
+    pub mismatched_samples: usize,
+    pub error:              Option<String>,
+}
+
+impl ChunkMethodBenchmark {
+    #[inline]
+    pub fn fps(&self) -> f64 {
+        self.frames_decoded as f64 / self.decode_time.as_secs_f64().max(f64::EPSILON)
+    }
+
+    #[inline]
+    pub fn is_frame_accurate(&self) -> bool {
+        self.error.is_none() && self.mismatched_samples == 0
+    }
+}
+
+/// Decoded frame count and a hash of the first frame of a single sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SampleDecode {
+    frames:      usize,
+    first_frame: Option<u64>,
+}
+
+/// Chunk methods that can be used on a video input with the installed tools
+#[inline]
+pub fn available_chunk_methods(plugins: Option<VapoursynthPlugins>) -> Vec<ChunkMethod> {
+    let mut methods = Vec::new();
+    if let Some(plugins) = plugins {
+        for (available, method) in [
+            (plugins.lsmash, ChunkMethod::LSMASH),
+            (plugins.ffms2, ChunkMethod::FFMS2),
+            (plugins.dgdecnv, ChunkMethod::DGDECNV),
+            (plugins.bestsource, ChunkMethod::BESTSOURCE),
+        ] {
+            if available {
+                methods.push(method);
+            }
+        }
+    }
+    methods.extend([
+        ChunkMethod::Hybrid,
+        ChunkMethod::Select,
+        ChunkMethod::Segment,
+        ChunkMethod::Trim,
+    ]);
+    methods
+}
+
+/// Evenly spreads `samples` ranges of `sample_frames` frames over a clip of
+/// `total_frames` frames. Ranges are half-open and never overlap.
+pub(crate) fn sample_ranges(
+    total_frames: usize,
+    samples: usize,
+    sample_frames: usize,
+) -> Vec<(usize, usize)> {
+    if total_frames == 0 || samples == 0 || sample_frames == 0 {
+        return Vec::new();
+    }
+    let sample_frames = sample_frames.min(total_frames);
+    let samples = samples.min(total_frames / sample_frames);
+    if samples <= 1 {
+        return vec![(0, sample_frames)];
+    }
+
+    let stride = (total_frames - sample_frames) / (samples - 1);
+    (0..samples)
+        .map(|i| {
+            let start = i * stride;
+            (start, start + sample_frames)
+        })
+        .collect()
+}
+
+/// Decodes a sample of chunks from `input` with each of `methods`, measuring
+/// throughput and checking that every method returns the same frames.
+#[inline]
+pub fn bench_chunk_methods(
+    input: &Path,
+    temp: &str,
+    methods: &[ChunkMethod],
+    samples: usize,
+    sample_frames: usize,
+    cache_mode: CacheSource,
+) -> anyhow::Result<Vec<ChunkMethodBenchmark>> {
+    let total_frames = get_num_frames(input)?;
+    let ranges = sample_ranges(total_frames, samples, sample_frames);
+    if ranges.is_empty() {
+        bail!("Input {} has no frames to benchmark", input.display());
+    }
+    debug!("benchmark samples: {ranges:?}");
+
+    let mut results = Vec::with_capacity(methods.len());
+    let mut decodes = Vec::with_capacity(methods.len());
+    for &method in methods {
+        info!("Benchmarking chunk method {method}");
+        let method_temp = Path::new(temp).join(method.to_string());
+        create_dir!(method_temp.join("split"))?;
+
+        let frames_expected = ranges.iter().map(|(start, end)| end - start).sum();
+        match bench_method(input, &method_temp, method, &ranges, cache_mode) {
+            Ok((setup_time, decode_time, sample_decodes)) => {
+                results.push(ChunkMethodBenchmark {
+                    method,
+                    setup_time,
+                    decode_time,
+                    frames_decoded: sample_decodes.iter().map(|decode| decode.frames).sum(),
+                    frames_expected,
+                    mismatched_samples: 0,
+                    error: None,
+                });
+                decodes.push(Some(sample_decodes));
+            },
+            Err(e) => {
+                error!("Chunk method {method} failed: {e:#}");
+                results.push(ChunkMethodBenchmark {
+                    method,
+                    setup_time: Duration::ZERO,
+                    decode_time: Duration::ZERO,
+                    frames_decoded: 0,
+                    frames_expected,
+                    mismatched_samples: ranges.len(),
+                    error: Some(format!("{e:#}")),
+                });
+                decodes.push(None);
+            },
+        }
+    }
+
+    // A sample is only accurate if it has the expected number of frames and
+    // its first frame matches what most of the other methods decoded
+    for (index, (start, end)) in ranges.iter().enumerate() {
+        let mut votes: HashMap<Option<u64>, usize> = HashMap::new();
+        for decode in decodes.iter().flatten() {
+            *votes.entry(decode[index].first_frame).or_default() += 1;
+        }
+        let consensus = votes.into_iter().max_by_key(|(_, count)| *count).map(|(hash, _)| hash);
+
+        for (result, decode) in results.iter_mut().zip(&decodes) {
+            if let Some(decode) = decode
+                && (decode[index].frames != end - start
+                    || Some(decode[index].first_frame) != consensus)
+            {
+                result.mismatched_samples += 1;
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+/// Picks the fastest method that decoded every sample accurately
+#[inline]
+pub fn recommend_chunk_method(results: &[ChunkMethodBenchmark]) -> Option<ChunkMethod> {
+    results
+        .iter()
+        .filter(|result| result.is_frame_accurate())
+        .max_by(|a, b| a.fps().total_cmp(&b.fps()))
+        .map(|result| result.method)
+}
+
+fn bench_method(
+    input: &Path,
+    temp: &Path,
+    method: ChunkMethod,
+    ranges: &[(usize, usize)],
+    cache_mode: CacheSource,
+) -> anyhow::Result<(Duration, Duration, Vec<SampleDecode>)> {
+    let setup_start = Instant::now();
+    let commands: Vec<Vec<OsString>> = match method {
+        ChunkMethod::LSMASH
+        | ChunkMethod::FFMS2
+        | ChunkMethod::DGDECNV
+        | ChunkMethod::BESTSOURCE => {
+            let (script, _) = create_vs_file(&LoadscriptArgs {
+                temp: &temp.to_string_lossy(),
+                source: input,
+                chunk_method: method,
+                is_proxy: false,
+                cache_mode,
+            })?;
+            // Decoding a single frame forces the source filter to index the input
+            decode_sample(&vspipe_command(&script, 0, 1))?;
+            ranges.iter().map(|&(start, end)| vspipe_command(&script, start, end)).collect()
+        },
+        ChunkMethod::Select => {
+            let frame_rate = frame_rate(input)?;
+            ranges
+                .iter()
+                .map(|&(start, end)| ffmpeg_command(input, Some((start, end, frame_rate))))
+                .collect()
+        },
+        ChunkMethod::Segment => {
+            // the first range may start at frame 0, which is not a valid split
+            let mut splits: Vec<usize> = ranges
+                .iter()
+                .flat_map(|&(start, end)| [start, end])
+                .filter(|&f| f > 0)
+                .collect();
+            splits.dedup();
+            let files = segment_files(input, temp, &splits)?;
+            ranges
+                .iter()
+                .map(|(start, _)| {
+                    let index = usize::from(*start > 0) + splits.partition_point(|s| s < start);
+                    files
+                        .get(index)
+                        .map(|file| ffmpeg_command(file, None))
+                        .context("ffmpeg produced fewer segments than requested")
+                })
+                .collect::<anyhow::Result<_>>()?
+        },
+        ChunkMethod::Hybrid => {
+            let frame_rate = frame_rate(input)?;
+            let keyframes = get_keyframes(input)?;
+            // split on the keyframe at or before each sample, unless that
+            // keyframe is inside an earlier sample
+            let mut splits: Vec<usize> = ranges
+                .iter()
+                .filter_map(|(start, _)| {
+                    let index = keyframes.partition_point(|kf| kf <= start);
+                    index.checked_sub(1).map(|index| keyframes[index])
+                })
+                .filter(|&kf| kf > 0 && !ranges.iter().any(|(s, e)| kf > *s && kf < *e))
+                .collect();
+            splits.dedup();
+            let files = segment_files(input, temp, &splits)?;
+            ranges
+                .iter()
+                .map(|&(start, end)| {
+                    let index = splits.partition_point(|&s| s <= start);
+                    let offset = index.checked_sub(1).map_or(0, |index| splits[index]);
+                    files
+                        .get(index)
+                        .map(|file| {
+                            ffmpeg_command(file, Some((start - offset, end - offset, frame_rate)))
+                        })
+                        .context("ffmpeg produced fewer segments than requested")
+                })
+                .collect::<anyhow::Result<_>>()?
+        },
+        ChunkMethod::Trim => {
+            let keyframes = get_keyframes(input)?;
+            let splits = keyframe_splits(ranges.iter().map(|&(start, _)| start), &keyframes);
+            let files = segment_files(input, temp, &splits)?;
+            ranges
+                .iter()
+                .enumerate()
+                .map(|(index, &(start, end))| {
+                    let (input_args, filter) = trim_input(&files, &splits, index, start, end)?;
+                    let mut command: Vec<OsString> =
+                        into_vec!["ffmpeg", "-y", "-hide_banner", "-loglevel", "error"];
+                    command.extend(input_args);
+                    command.extend(into_vec![
+                        "-vf",
+                        filter,
+                        "-strict",
+                        "-1",
+                        "-f",
+                        "yuv4mpegpipe",
+                        "-"
+                    ]);
+                    Ok(command)
+                })
+                .collect::<anyhow::Result<_>>()?
+        },
+    };
+    let setup_time = setup_start.elapsed();
+
+    let decode_start = Instant::now();
+    let decodes = commands
+        .iter()
+        .map(Vec::as_slice)
+        .map(decode_sample)
+        .collect::<Result<_, _>>()?;
+
+    Ok((setup_time, decode_start.elapsed(), decodes))
+}
+
+fn segment_files(input: &Path, temp: &Path, splits: &[usize]) -> anyhow::Result<Vec<PathBuf>> {
+    segment(input, temp, splits)?;
+
+    let mut files: Vec<PathBuf> = fs::read_dir(temp.join("split"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    files.retain(|file| file.extension().is_some_and(|ext| ext == "mkv"));
+    crate::concat::sort_files_by_filename(&mut files);
+
+    Ok(files)
+}
+
+fn vspipe_command(script: &Path, start: usize, end: usize) -> Vec<OsString> {
+    into_vec![
+        "vspipe",
+        script,
+        "-c",
+        "y4m",
+        "-",
+        "-s",
+        start.to_string(),
+        "-e",
+        (end - 1).to_string(),
+    ]
+}
+
+/// Decodes `range` of `file` the way the select chunk method does, or the
+/// whole file if `range` is `None`
+fn ffmpeg_command(file: &Path, range: Option<(usize, usize, f64)>) -> Vec<OsString> {
+    let mut command: Vec<OsString> =
+        into_vec!["ffmpeg", "-y", "-hide_banner", "-loglevel", "error"];
+    if let Some((start, end, frame_rate)) = range {
+        command.extend(select_frames_args(file, start, end, frame_rate));
+    } else {
+        command.extend(into_vec!["-i", file]);
+    }
+    command.extend(into_vec!["-strict", "-1", "-f", "yuv4mpegpipe", "-"]);
+    command
+}
+
+fn frame_rate(input: &Path) -> anyhow::Result<f64> {
+    get_clip_info(input)?
+        .frame_rate
+        .to_f64()
+        .context("frame rate should not be NaN")
+}
+
+fn decode_sample(command: &[OsString]) -> anyhow::Result<SampleDecode> {
+    let [program, args @ ..] = command else {
+        unreachable!()
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to spawn {}", program.to_string_lossy()))?;
+
+    let stdout = child.stdout.take().expect("child should have stdout");
+    let mut frames = 0;
+    let mut first_frame = None;
+    if let Ok(mut decoder) = y4m::decode(stdout) {
+        while let Ok(frame) = decoder.read_frame() {
+            if first_frame.is_none() {
+                let mut hasher = DefaultHasher::new();
+                frame.get_y_plane().hash(&mut hasher);
+                frame.get_u_plane().hash(&mut hasher);
+                frame.get_v_plane().hash(&mut hasher);
+                first_frame = Some(hasher.finish());
+            }
+            frames += 1;
+        }
+    }
+
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .expect("child should have stderr")
+        .read_to_string(&mut stderr)?;
+    let status = child.wait()?;
+    if !status.success() {
+        bail!(
+            "{} exited with {status}: {}",
+            program.to_string_lossy(),
+            stderr.trim()
+        );
+    }
+
+    Ok(SampleDecode {
+        frames,
+        first_frame,
+    })
+}
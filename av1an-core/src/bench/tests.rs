@@ -0,0 +1,50 @@
+use super::*;
+
+#[test]
+fn sample_ranges_spread_over_clip() {
+    assert_eq!(sample_ranges(1000, 3, 100), vec![
+        (0, 100),
+        (450, 550),
+        (900, 1000)
+    ]);
+}
+
+#[test]
+fn sample_ranges_short_clip() {
+    assert_eq!(sample_ranges(50, 5, 100), vec![(0, 50)]);
+    assert_eq!(sample_ranges(250, 5, 100), vec![(0, 100), (150, 250)]);
+    assert!(sample_ranges(0, 5, 100).is_empty());
+}
+
+fn benchmark(
+    method: ChunkMethod,
+    decode_secs: u64,
+    mismatched_samples: usize,
+) -> ChunkMethodBenchmark {
+    ChunkMethodBenchmark {
+        method,
+        setup_time: Duration::ZERO,
+        decode_time: Duration::from_secs(decode_secs),
+        frames_decoded: 1000,
+        frames_expected: 1000,
+        mismatched_samples,
+        error: None,
+    }
+}
+
+#[test]
+fn recommend_fastest_accurate_method() {
+    let results = [
+        benchmark(ChunkMethod::LSMASH, 4, 0),
+        benchmark(ChunkMethod::Segment, 1, 2),
+        benchmark(ChunkMethod::Hybrid, 2, 0),
+        benchmark(ChunkMethod::Select, 10, 0),
+    ];
+    assert_eq!(recommend_chunk_method(&results), Some(ChunkMethod::Hybrid));
+}
+
+#[test]
+fn recommend_nothing_if_all_inaccurate() {
+    let results = [benchmark(ChunkMethod::Segment, 1, 1)];
+    assert_eq!(recommend_chunk_method(&results), None);
+}
@@ -1,44 +1,208 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
     fmt::{Debug, Display},
-    fs::File,
-    io::Write,
+    fs,
+    io,
+    mem,
+    num::NonZero,
     path::Path,
     process::ExitStatus,
     sync::{
-        atomic::{AtomicU8, Ordering},
-        mpsc::Sender,
+        atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering},
+        mpsc::{Receiver, Sender},
         Arc,
+        Condvar,
+        Mutex,
+        OnceLock,
     },
-    thread::available_parallelism,
+    thread::{self, available_parallelism},
+    time::Duration,
 };
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use cfg_if::cfg_if;
 use smallvec::SmallVec;
 use thiserror::Error;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::{
     context::Av1anContext,
+    control::{self, ControlCommands, CONTROL_FILE},
+    error::Av1anError,
+    ffmpeg::get_num_frames,
     finish_progress_bar,
     get_done,
+    parse::EncoderSummary,
     progress_bar::{
-        dec_bar,
-        inc_mp_bar,
+        add_mp_worker_bar,
         update_mp_chunk,
         update_mp_msg,
         update_progress_bar_estimates,
+        ChunkProgress,
     },
+    save_done,
+    scratch_dir,
+    shared_temp,
+    split::cut_passthrough,
     util::printable_base10_digits,
     Chunk,
+    ChunkScheduler,
+    ChunkScore,
     DoneChunk,
     Instant,
 };
 
 #[derive(Debug)]
 pub struct Broker<'a> {
-    pub chunk_queue: Vec<Chunk>,
-    pub project:     &'a Av1anContext,
+    pub chunk_queue:        Vec<Chunk>,
+    pub scheduler:          Mutex<Box<dyn ChunkScheduler>>,
+    pub project:            &'a Av1anContext,
+    /// Chunks whose first pass an afterburner worker finished, or that a
+    /// probe worker probed
+    second_passes:          Mutex<SecondPasses>,
+    gpus:                   GpuPool,
+    /// More chunks pushed to the scheduler while encoding, see
+    /// [`Self::with_feed`]
+    feed:                   Mutex<Option<Receiver<Vec<Chunk>>>>,
+    /// Whether chunks can still arrive from the feed
+    feeding:                AtomicBool,
+    /// Notified when chunks arrive from the feed, or the feed is closed
+    chunks_pushed:          Condvar,
+    total_chunks:           AtomicU32,
+    /// Number of workers that take chunks, not counting afterburner workers,
+    /// which can be changed through the control file while encoding
+    active_workers:         AtomicUsize,
+    /// Number of worker threads that have not exited yet
+    running_workers:        AtomicUsize,
+    /// Number of times Ctrl+C was pressed
+    terminations_requested: Arc<AtomicU8>,
+}
+
+/// Number of times Ctrl+C was pressed. The handler can only be set once per
+/// process, so every broker shares the counter.
+fn termination_counter() -> Arc<AtomicU8> {
+    static COUNTER: OnceLock<Arc<AtomicU8>> = OnceLock::new();
+    Arc::clone(COUNTER.get_or_init(|| {
+        let counter = Arc::new(AtomicU8::new(0));
+        let handler_counter = Arc::clone(&counter);
+        ctrlc::set_handler(move || {
+            let count = handler_counter.fetch_add(1, Ordering::SeqCst) + 1;
+            if count == 1 {
+                error!("Shutting down. Waiting for current workers to finish...");
+            } else {
+                error!("Shutting down all workers...");
+            }
+        })
+        .expect("should set ctrlc handler");
+        counter
+    }))
+}
+
+/// How often idle workers check whether encoding was interrupted while they
+/// wait for chunks from the feed
+const FEED_POLL: Duration = Duration::from_millis(500);
+
+/// How often the control file is checked for commands while encoding
+const CONTROL_POLL: Duration = Duration::from_millis(500);
+
+/// Which passes of a chunk a worker runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkPasses {
+    All,
+    /// Run only the first pass of a multi-pass chunk, see
+    /// [`EncodeArgs::afterburner`](crate::EncodeArgs::afterburner)
+    FirstOnly,
+    /// Run the passes after the first one, which an afterburner worker
+    /// already ran
+    AfterFirst,
+    /// Run only Target Quality, see
+    /// [`EncodeArgs::probe_workers`](crate::EncodeArgs::probe_workers)
+    ProbeOnly,
+    /// Run all passes of a chunk whose Target Quality a probe worker already
+    /// ran
+    AfterProbe,
+}
+
+/// What a worker does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WorkerRole {
+    /// Encodes chunks, as the worker in `slot` of the workers that the
+    /// control file can pause
+    Main { slot: usize },
+    /// Runs only the first pass of multi-pass chunks
+    Afterburner,
+    /// Runs only Target Quality
+    Probe,
+}
+
+/// Chunks that a worker started and another one finishes
+#[derive(Debug, Default)]
+struct SecondPasses {
+    /// The chunks and the worker that ran their first pass
+    chunks:        VecDeque<(Chunk, usize)>,
+    /// The chunks a probe worker ran Target Quality for
+    probed:        VecDeque<Chunk>,
+    /// Number of workers that still take chunks, and can therefore run the
+    /// later passes of the chunks in `chunks` and the chunks in `probed`
+    main_workers:  usize,
+    /// Number of probe workers that still probe chunks
+    probe_workers: usize,
+}
+
+/// Assigns chunks to the GPUs of [`EncodeArgs::gpus`](crate::EncodeArgs::gpus)
+/// in turn, keeping at most
+/// [`EncodeArgs::gpu_workers`](crate::EncodeArgs::gpu_workers) chunks on each
+#[derive(Debug, Default)]
+struct GpuPool {
+    state: Mutex<GpuState>,
+    freed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct GpuState {
+    /// Number of chunks being encoded on each GPU
+    active: Vec<usize>,
+    /// GPU to try first for the next chunk
+    next:   usize,
+}
+
+impl GpuPool {
+    fn new(gpus: usize) -> Self {
+        Self {
+            state: Mutex::new(GpuState {
+                active: vec![0; gpus],
+                next:   0,
+            }),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Returns the index of the GPU the next chunk is encoded on, waiting
+    /// until one of them has fewer than `limit` chunks, or `None` if no GPUs
+    /// are used
+    fn acquire(&self, limit: Option<usize>) -> Option<usize> {
+        let mut state = self.state.lock().expect("mutex should acquire lock");
+        let gpus = state.active.len();
+        if gpus == 0 {
+            return None;
+        }
+        loop {
+            let free = (0..gpus)
+                .map(|offset| (state.next + offset) % gpus)
+                .find(|&gpu| limit.is_none_or(|limit| state.active[gpu] < limit));
+            if let Some(gpu) = free {
+                state.active[gpu] += 1;
+                state.next = (gpu + 1) % gpus;
+                return Some(gpu);
+            }
+            state = self.freed.wait(state).expect("mutex should acquire lock");
+        }
+    }
+
+    fn release(&self, gpu: usize) {
+        self.state.lock().expect("mutex should acquire lock").active[gpu] -= 1;
+        self.freed.notify_all();
+    }
 }
 
 #[derive(Clone)]
@@ -109,92 +273,308 @@ impl Display for EncoderCrash {
     }
 }
 
-impl Broker<'_> {
+impl EncoderCrash {
+    /// Returns whether the crash looks like the source filter did not decode
+    /// the chunk correctly, as either the number of frames encoded did not
+    /// match or vspipe reported an error
+    fn is_source_failure(&self) -> bool {
+        matches!(&self.stdout, StringOrBytes::String(stdout) if stdout.starts_with("FRAME MISMATCH"))
+            || matches!(
+                &self.source_pipe_stderr,
+                StringOrBytes::String(stderr) if stderr.contains("Error")
+            )
+    }
+}
+
+/// Returns the thread count for a chunk that normally uses `threads` threads
+/// and is started while `waiting` chunks have yet to be started, or `None` if
+/// every worker will still have a chunk to encode.
+///
+/// The chunk and the waiting chunks are the last ones of the encode, so they
+/// share the threads of all workers between them, up to the number of
+/// threads available.
+fn tail_boost_threads(
+    threads: usize,
+    waiting: usize,
+    workers: usize,
+    parallelism: usize,
+) -> Option<usize> {
+    let last = waiting + 1;
+    if last >= workers {
+        return None;
+    }
+    let boosted = (threads * workers / last).min(parallelism);
+    (boosted > threads).then_some(boosted)
+}
+
+/// Minimum number of encoder failures before worker correlations are reported
+const MIN_FAILURES_TO_CORRELATE: usize = 3;
+
+#[derive(Debug, Default)]
+struct WorkerRecord {
+    /// Logical cores the worker was pinned to, if thread affinity was set
+    affinity:       Option<SmallVec<[usize; 16]>>,
+    /// Index of the chunk for every failed encoder run on this worker
+    failed_chunks:  Vec<usize>,
+    finished_count: usize,
+}
+
+/// Records which worker (and which cores) each encoder failure happened on,
+/// so that crashes which follow the hardware rather than the chunk can be
+/// told apart.
+#[derive(Debug, Default)]
+pub(crate) struct WorkerSupervisor {
+    workers: Mutex<BTreeMap<usize, WorkerRecord>>,
+}
+
+impl WorkerSupervisor {
+    fn register(&self, worker_id: usize, affinity: Option<SmallVec<[usize; 16]>>) {
+        self.workers
+            .lock()
+            .expect("mutex should acquire lock")
+            .entry(worker_id)
+            .or_default()
+            .affinity = affinity;
+    }
+
+    fn record_failure(&self, worker_id: usize, chunk_index: usize) {
+        self.workers
+            .lock()
+            .expect("mutex should acquire lock")
+            .entry(worker_id)
+            .or_default()
+            .failed_chunks
+            .push(chunk_index);
+    }
+
+    fn record_success(&self, worker_id: usize) {
+        self.workers
+            .lock()
+            .expect("mutex should acquire lock")
+            .entry(worker_id)
+            .or_default()
+            .finished_count += 1;
+    }
+
+    /// Describes any pattern found in the recorded failures.
+    ///
+    /// Failures concentrated on one worker across several chunks point at the
+    /// cores it is pinned to (e.g. an unstable overclock), otherwise a chunk
+    /// that keeps failing points at the chunk itself.
+    fn correlations(&self) -> Vec<String> {
+        let workers = self.workers.lock().expect("mutex should acquire lock");
+        let total_failures: usize = workers.values().map(|w| w.failed_chunks.len()).sum();
+        if total_failures < MIN_FAILURES_TO_CORRELATE {
+            return Vec::new();
+        }
+
+        let mut findings = Vec::new();
+
+        if workers.len() > 1
+            && let Some((worker_id, record)) =
+                workers.iter().max_by_key(|(_, record)| record.failed_chunks.len())
+        {
+            let distinct_chunks = record.failed_chunks.iter().collect::<BTreeSet<_>>().len();
+            // at least 3/4 of all failures, spread over more than one chunk
+            if record.failed_chunks.len() * 4 >= total_failures * 3 && distinct_chunks > 1 {
+                findings.push(format!(
+                    "worker {worker_id}{cores} accounted for {failures}/{total_failures} encoder \
+                     failures across {distinct_chunks} different chunks while other workers \
+                     finished {others} chunks; this usually points at unstable hardware on those \
+                     cores (e.g. an overclock) rather than at the source",
+                    cores = record
+                        .affinity
+                        .as_ref()
+                        .map(|cores| format!(" (pinned to cores {cores:?})"))
+                        .unwrap_or_default(),
+                    failures = record.failed_chunks.len(),
+                    others = workers
+                        .iter()
+                        .filter(|(id, _)| *id != worker_id)
+                        .map(|(_, record)| record.finished_count)
+                        .sum::<usize>(),
+                ));
+            }
+        }
+
+        if findings.is_empty() {
+            let mut chunk_failures: BTreeMap<usize, (usize, BTreeSet<usize>)> = BTreeMap::new();
+            for (worker_id, record) in workers.iter() {
+                for chunk_index in &record.failed_chunks {
+                    let (count, failed_on) = chunk_failures.entry(*chunk_index).or_default();
+                    *count += 1;
+                    failed_on.insert(*worker_id);
+                }
+            }
+            for (chunk_index, (count, failed_on)) in
+                chunk_failures.iter().filter(|(_, (count, _))| *count > 1)
+            {
+                findings.push(format!(
+                    "chunk {chunk_index} failed {count} times on workers {failed_on:?}; the \
+                     problem is likely in the chunk or its parameters rather than in a specific \
+                     worker"
+                ));
+            }
+        }
+
+        findings
+    }
+
+    fn report(&self) {
+        for finding in self.correlations() {
+            warn!("Worker supervision: {finding}");
+        }
+    }
+}
+
+impl<'a> Broker<'a> {
+    #[inline]
+    pub fn new(
+        chunk_queue: Vec<Chunk>,
+        scheduler: Box<dyn ChunkScheduler>,
+        project: &'a Av1anContext,
+    ) -> Self {
+        Self {
+            chunk_queue,
+            scheduler: Mutex::new(scheduler),
+            gpus: GpuPool::new(project.args.gpus.len()),
+            project,
+            second_passes: Mutex::default(),
+            feed: Mutex::new(None),
+            feeding: AtomicBool::new(false),
+            chunks_pushed: Condvar::new(),
+            total_chunks: AtomicU32::new(0),
+            active_workers: AtomicUsize::new(0),
+            running_workers: AtomicUsize::new(0),
+            terminations_requested: termination_counter(),
+        }
+    }
+
+    /// Keep adding the chunks received from `feed` to the scheduler while
+    /// encoding, for chunks that are created while the first ones are already
+    /// being encoded. Idle workers wait for more chunks until the sender is
+    /// dropped.
+    #[inline]
+    #[must_use]
+    pub fn with_feed(self, feed: Receiver<Vec<Chunk>>) -> Self {
+        *self.feed.lock().expect("mutex should acquire lock") = Some(feed);
+        self.feeding.store(true, Ordering::SeqCst);
+        self
+    }
+
     /// Main encoding loop. set_thread_affinity may be ignored if the value is
-    /// invalid.
+    /// invalid. `total_chunks` grows by the chunks received from the feed.
     #[tracing::instrument(skip(self))]
     #[allow(clippy::needless_pass_by_value)]
     pub fn encoding_loop(
         self,
-        tx: Sender<()>,
+        tx: Sender<anyhow::Error>,
         set_thread_affinity: Option<usize>,
         total_chunks: u32,
     ) -> anyhow::Result<()> {
-        if !self.chunk_queue.is_empty() {
-            let (sender, receiver) = crossbeam_channel::bounded(self.chunk_queue.len());
-
-            for chunk in &self.chunk_queue {
-                sender.send(chunk.clone())?;
+        let feed = self.feed.lock().expect("mutex should acquire lock").take();
+        if !self.chunk_queue.is_empty() || feed.is_some() {
+            self.scheduler
+                .lock()
+                .expect("mutex should acquire lock")
+                .push(self.chunk_queue.clone());
+            {
+                let mut second_passes =
+                    self.second_passes.lock().expect("mutex should acquire lock");
+                second_passes.main_workers = self.project.args.workers;
+                second_passes.probe_workers = self.project.args.probe_workers;
             }
-            drop(sender);
+            self.total_chunks.store(total_chunks, Ordering::SeqCst);
+
+            let supervisor = WorkerSupervisor::default();
 
             crossbeam_utils::thread::scope(|s| {
-                let terminations_requested = Arc::new(AtomicU8::new(0));
-                let terminations_requested_clone = Arc::clone(&terminations_requested);
-                ctrlc::set_handler(move || {
-                    let count = terminations_requested_clone.fetch_add(1, Ordering::SeqCst) + 1;
-                    if count == 1 {
-                        error!("Shutting down. Waiting for current workers to finish...");
-                    } else {
-                        error!("Shutting down all workers...");
-                    }
-                })
-                .expect("should set ctrlc handler");
-
-                let consumers: Vec<_> = (0..self.project.args.workers)
-                    .map(|idx| (receiver.clone(), &self, idx, Arc::clone(&terminations_requested)))
-                    .map(|(rx, queue, worker_id, terminations_requested)| {
-                        let tx = tx.clone();
-                        s.spawn(move |_| {
-                            cfg_if! {
-                                if #[cfg(any(target_os = "linux", target_os = "windows"))] {
-                                    if let Some(threads) = set_thread_affinity {
-                                        if threads == 0 {
-                                            warn!("Ignoring set_thread_affinity: Requested 0 threads");
-                                        } else {
-                                            match available_parallelism() {
-                                                Ok(parallelism) => {
-                                                    let available_threads = parallelism.get();
-                                                    let mut cpu_set = SmallVec::<[usize; 16]>::new();
-                                                    let start_thread = (threads * worker_id) % available_threads;
-                                                    cpu_set.extend((start_thread..start_thread + threads).map(|t| t % available_threads));
-                                                    if let Err(e) = affinity::set_thread_affinity(&cpu_set) {
-                                                        warn!("Failed to set thread affinity for worker {worker_id}: {e}");
-                                                    }
-                                                },
-                                                Err(e) => {
-                                                    warn!("Failed to get thread count: {e}. Thread affinity will not be set");
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+                if let Some(feed) = feed {
+                    let broker = &self;
+                    s.spawn(move |_| broker.receive_chunks(&feed));
+                }
 
-                            while let Ok(mut chunk) = rx.recv() {
-                                if terminations_requested.load(Ordering::SeqCst) == 0
-                                    && let Err(e) = queue.encode_chunk(
-                                        &mut chunk,
-                                        worker_id,
-                                        &terminations_requested,
-                                        total_chunks,
-                                    )
-                                {
-                                    error!("[chunk {index}] {e}", index = chunk.index);
-                                    tx.send(()).expect("should send successfully");
-                                    return Err(());
-                                }
+                let workers = self.project.args.workers;
+                let afterburner = self.project.args.afterburner;
+                let probe_workers = self.project.args.probe_workers;
+                self.active_workers.store(workers, Ordering::SeqCst);
+                self.running_workers
+                    .store(workers + afterburner + probe_workers, Ordering::SeqCst);
+                let mut spawned = workers;
+                let spawn_worker = |worker_id: usize, role: WorkerRole| {
+                    let broker = &self;
+                    let tx = tx.clone();
+                    let supervisor = &supervisor;
+                    s.spawn(move |_| {
+                        let result = broker.run_worker(
+                            worker_id,
+                            role,
+                            &tx,
+                            supervisor,
+                            set_thread_affinity,
+                        );
+                        if role == WorkerRole::Probe {
+                            broker.finish_probing();
+                        }
+                        broker.running_workers.fetch_sub(1, Ordering::SeqCst);
+                        result
+                    })
+                };
+                let mut consumers: Vec<_> = (0..workers + afterburner + probe_workers)
+                    .map(|worker_id| {
+                        let role = if worker_id < workers {
+                            WorkerRole::Main {
+                                slot: worker_id
                             }
-                            Ok(())
-                        })
+                        } else if worker_id < workers + afterburner {
+                            WorkerRole::Afterburner
+                        } else {
+                            WorkerRole::Probe
+                        };
+                        spawn_worker(worker_id, role)
                     })
                     .collect();
+
+                let temp = Path::new(&self.project.args.temp);
+                info!(
+                    "write `workers=N` to {} to change the number of workers while encoding",
+                    temp.join(CONTROL_FILE).display()
+                );
+                while self.running_workers.load(Ordering::SeqCst) > 0 {
+                    thread::sleep(CONTROL_POLL);
+                    let workers = match control::take_commands(temp) {
+                        Ok(Some(ControlCommands {
+                            workers: Some(workers),
+                        })) => workers,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            warn!("{e:#}");
+                            continue;
+                        },
+                    };
+                    self.set_workers(workers);
+                    while spawned < workers {
+                        let worker_id = consumers.len();
+                        self.running_workers.fetch_add(1, Ordering::SeqCst);
+                        self.second_passes
+                            .lock()
+                            .expect("mutex should acquire lock")
+                            .main_workers += 1;
+                        add_mp_worker_bar(worker_id, self.total_chunks.load(Ordering::SeqCst));
+                        consumers.push(spawn_worker(worker_id, WorkerRole::Main {
+                            slot: spawned
+                        }));
+                        spawned += 1;
+                    }
+                }
                 for consumer in consumers {
                     consumer.join().expect("consumer should join successfully").ok();
                 }
 
-                if terminations_requested.load(Ordering::SeqCst) > 0 {
-                    tx.send(()).expect("should send successfully");
+                supervisor.report();
+
+                if self.terminations_requested.load(Ordering::SeqCst) > 0 {
+                    tx.send(anyhow!("encoding was interrupted")).expect("should send successfully");
                 }
             })
             .expect("thread should spawn successfully");
@@ -205,21 +585,469 @@ impl Broker<'_> {
         Ok(())
     }
 
-    #[tracing::instrument(skip(self, chunk, terminations_requested), fields(chunk_index = format!("{:>05}", chunk.index)))]
+    /// Encodes chunks on worker `worker_id` until none are left. Main workers
+    /// pause while their slot is beyond the number of workers set through the
+    /// control file. Errors are sent to `tx`.
+    fn run_worker(
+        &self,
+        worker_id: usize,
+        role: WorkerRole,
+        tx: &Sender<anyhow::Error>,
+        supervisor: &WorkerSupervisor,
+        set_thread_affinity: Option<usize>,
+    ) -> Result<(), ()> {
+        #[allow(unused_mut)]
+        let mut affinity = None;
+        cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "windows"))] {
+                if let Some(threads) = set_thread_affinity {
+                    if threads == 0 {
+                        warn!("Ignoring set_thread_affinity: Requested 0 threads");
+                    } else {
+                        match available_parallelism() {
+                            Ok(parallelism) => {
+                                let available_threads = parallelism.get();
+                                let mut cpu_set = SmallVec::<[usize; 16]>::new();
+                                let start_thread = (threads * worker_id) % available_threads;
+                                cpu_set.extend((start_thread..start_thread + threads).map(|t| t % available_threads));
+                                if let Err(e) = affinity::set_thread_affinity(&cpu_set) {
+                                    warn!("Failed to set thread affinity for worker {worker_id}: {e}");
+                                } else {
+                                    affinity = Some(cpu_set);
+                                }
+                            },
+                            Err(e) => {
+                                warn!("Failed to get thread count: {e}. Thread affinity will not be set");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let pinned = affinity.is_some();
+        supervisor.register(worker_id, affinity);
+
+        let scratch = scratch_dir(&self.project.args.temp, Some(worker_id));
+        if let Err(e) = fs::create_dir_all(&scratch) {
+            supervisor.report();
+            tx.send(anyhow!(
+                "Failed to create the scratch directory of worker {worker_id}: {e}"
+            ))
+            .expect("should send successfully");
+            return Err(());
+        }
+
+        loop {
+            let next = match role {
+                WorkerRole::Afterburner => self
+                    .next_chunk(worker_id)
+                    .map(|(chunk, waiting)| (chunk, waiting, ChunkPasses::FirstOnly)),
+                WorkerRole::Probe => self
+                    .next_chunk(worker_id)
+                    .map(|(chunk, waiting)| (chunk, waiting, ChunkPasses::ProbeOnly)),
+                WorkerRole::Main {
+                    slot,
+                } => {
+                    if !self.wait_for_slot(slot, worker_id) {
+                        break;
+                    }
+                    self.next_main_chunk(worker_id)
+                },
+            };
+            let Some((mut chunk, waiting, passes)) = next else {
+                break;
+            };
+            // chunks handed off by an afterburner worker were already boosted,
+            // probes are encoded with the threads of the final encode, and the
+            // queue is not at its tail while scenes still arrive
+            if self.project.args.tail_boost
+                && !pinned
+                && !matches!(passes, ChunkPasses::AfterFirst | ChunkPasses::ProbeOnly)
+                && !self.feeding.load(Ordering::SeqCst)
+            {
+                self.boost_tail_chunk(&mut chunk, waiting);
+            }
+            if self.terminations_requested.load(Ordering::SeqCst) > 0 {
+                continue;
+            }
+            // the rest of a chunk belongs to the instance that started it
+            let claimed = self.project.args.shared_temp
+                && !matches!(passes, ChunkPasses::AfterFirst | ChunkPasses::AfterProbe);
+            if claimed {
+                let temp = Path::new(&self.project.args.temp);
+                match shared_temp::claim_chunk(temp, &chunk.name()) {
+                    Ok(true) => {},
+                    Ok(false) => {
+                        debug!(
+                            "chunk {index:05} was claimed by another instance",
+                            index = chunk.index
+                        );
+                        continue;
+                    },
+                    Err(e) => {
+                        supervisor.report();
+                        tx.send(e).expect("should send successfully");
+                        return Err(());
+                    },
+                }
+            }
+
+            let index = chunk.index;
+            let name = chunk.name();
+            let gpu = self.gpus.acquire(self.project.args.gpu_workers);
+            chunk.gpu = gpu.map(|gpu| self.project.args.gpus[gpu]);
+            let mut result = self.encode_chunk(
+                &mut chunk,
+                passes,
+                worker_id,
+                &self.terminations_requested,
+                self.total_chunks.load(Ordering::SeqCst),
+                supervisor,
+            );
+            if matches!(result, Ok(false))
+                && let Some((mut chunk, passes)) = self.hand_off(chunk, passes, worker_id)
+            {
+                // no worker is left to finish the chunk
+                result = self.encode_chunk(
+                    &mut chunk,
+                    passes,
+                    worker_id,
+                    &self.terminations_requested,
+                    self.total_chunks.load(Ordering::SeqCst),
+                    supervisor,
+                );
+            }
+            if let Some(gpu) = gpu {
+                self.gpus.release(gpu);
+            }
+            if claimed && result.is_err() {
+                shared_temp::release_chunk(Path::new(&self.project.args.temp), &name);
+            }
+            if let Err(e) = result {
+                if Av1anError::find(&e).is_some() {
+                    error!("{e}");
+                } else {
+                    error!("[chunk {index}] {e}");
+                }
+                // the statistics and probes of the failed chunk are of no
+                // use on resume, which starts it over. Other instances may
+                // use the same directory with a shared temporary directory.
+                if !self.project.args.keep && !self.project.args.shared_temp {
+                    fs::remove_dir_all(&scratch).ok();
+                }
+                // the main thread exits as soon as this is received
+                supervisor.report();
+                tx.send(e).expect("should send successfully");
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the chunks received from `feed` to the scheduler until the sender
+    /// is dropped
+    fn receive_chunks(&self, feed: &Receiver<Vec<Chunk>>) {
+        for chunks in feed {
+            let mut scheduler = self.scheduler.lock().expect("mutex should acquire lock");
+            self.total_chunks.fetch_add(chunks.len() as u32, Ordering::SeqCst);
+            scheduler.push(chunks);
+            self.chunks_pushed.notify_all();
+        }
+        let _scheduler = self.scheduler.lock().expect("mutex should acquire lock");
+        self.feeding.store(false, Ordering::SeqCst);
+        self.chunks_pushed.notify_all();
+    }
+
+    /// Returns whether idle workers should wait for more chunks from the feed
+    fn waiting_for_feed(&self) -> bool {
+        self.feeding.load(Ordering::SeqCst)
+            && self.terminations_requested.load(Ordering::SeqCst) == 0
+    }
+
+    /// Returns the chunk the scheduler picked for `worker_id` and the number of
+    /// chunks still waiting after it, waiting for the feed if the scheduler
+    /// has none
+    fn next_chunk(&self, worker_id: usize) -> Option<(Chunk, usize)> {
+        let mut scheduler = self.scheduler.lock().expect("mutex should acquire lock");
+        loop {
+            if let Some(chunk) = scheduler.next(worker_id) {
+                return Some((chunk, scheduler.len()));
+            }
+            if !self.waiting_for_feed() {
+                return None;
+            }
+            scheduler = self
+                .chunks_pushed
+                .wait_timeout(scheduler, FEED_POLL)
+                .expect("mutex should acquire lock")
+                .0;
+        }
+    }
+
+    /// Changes the number of workers that take chunks. Workers beyond the new
+    /// number pause once they finished their current chunk, and paused
+    /// workers within it resume.
+    fn set_workers(&self, workers: usize) {
+        let previous = self.active_workers.swap(workers, Ordering::SeqCst);
+        if workers < previous {
+            info!(
+                "pausing {} workers once they finish their chunks",
+                previous - workers
+            );
+        } else if workers > previous {
+            info!("encoding with {workers} workers");
+        }
+        let _scheduler = self.scheduler.lock().expect("mutex should acquire lock");
+        self.chunks_pushed.notify_all();
+    }
+
+    /// Waits while `slot` is beyond the number of workers set through the
+    /// control file. Returns whether the worker should take another chunk,
+    /// which is not the case once the other workers ran out of chunks or
+    /// encoding was interrupted.
+    fn wait_for_slot(&self, slot: usize, worker_id: usize) -> bool {
+        if slot < self.active_workers.load(Ordering::SeqCst) {
+            return true;
+        }
+        // a paused worker cannot run the later passes of handed off chunks
+        self.second_passes.lock().expect("mutex should acquire lock").main_workers -= 1;
+        update_mp_msg(worker_id, "Paused".to_owned());
+        loop {
+            let mut second_passes = self.second_passes.lock().expect("mutex should acquire lock");
+            let scheduler = self.scheduler.lock().expect("mutex should acquire lock");
+            if slot < self.active_workers.load(Ordering::SeqCst) {
+                second_passes.main_workers += 1;
+                update_mp_msg(worker_id, String::new());
+                return true;
+            }
+            if second_passes.main_workers == 0
+                || self.terminations_requested.load(Ordering::SeqCst) > 0
+            {
+                return false;
+            }
+            drop(second_passes);
+            drop(
+                self.chunks_pushed
+                    .wait_timeout(scheduler, FEED_POLL)
+                    .expect("mutex should acquire lock"),
+            );
+        }
+    }
+
+    /// Returns the next chunk for a main worker, preferring the chunks whose
+    /// first pass is done, then the probed chunks, and which passes of it to
+    /// run. With probe workers, main workers only encode probed chunks.
+    fn next_main_chunk(&self, worker_id: usize) -> Option<(Chunk, usize, ChunkPasses)> {
+        let probing = self.project.args.probe_workers > 0;
+        loop {
+            let mut second_passes = self.second_passes.lock().expect("mutex should acquire lock");
+            let mut scheduler = self.scheduler.lock().expect("mutex should acquire lock");
+            if let Some((chunk, first_pass_worker)) = second_passes.chunks.pop_front() {
+                if let Err(e) = self.take_first_pass(&chunk, first_pass_worker, worker_id) {
+                    warn!(
+                        "Failed to move the first pass statistics of chunk {index:05} to worker \
+                         {worker_id}: {e}",
+                        index = chunk.index
+                    );
+                }
+                return Some((chunk, scheduler.len(), ChunkPasses::AfterFirst));
+            }
+            if let Some(chunk) = second_passes.probed.pop_front() {
+                let waiting = second_passes.probed.len() + scheduler.len();
+                return Some((chunk, waiting, ChunkPasses::AfterProbe));
+            }
+            if !probing && let Some(chunk) = scheduler.next(worker_id) {
+                return Some((chunk, scheduler.len(), ChunkPasses::All));
+            }
+            let more = if probing {
+                second_passes.probe_workers > 0
+            } else {
+                self.waiting_for_feed()
+            };
+            if !more {
+                // checked while holding the lock, so that no chunk is handed off
+                // after the last worker stopped
+                second_passes.main_workers -= 1;
+                return None;
+            }
+            // afterburner workers hand off chunks while this one waits
+            drop(second_passes);
+            drop(
+                self.chunks_pushed
+                    .wait_timeout(scheduler, FEED_POLL)
+                    .expect("mutex should acquire lock"),
+            );
+        }
+    }
+
+    /// Queue the rest of a chunk whose first pass an afterburner worker
+    /// finished, or that a probe worker probed. Returns the chunk back with
+    /// the passes left to run if no main worker is left to run them.
+    fn hand_off(
+        &self,
+        chunk: Chunk,
+        passes: ChunkPasses,
+        worker_id: usize,
+    ) -> Option<(Chunk, ChunkPasses)> {
+        let mut second_passes = self.second_passes.lock().expect("mutex should acquire lock");
+        let probed = passes == ChunkPasses::ProbeOnly;
+        if second_passes.main_workers == 0 {
+            let rest = if probed {
+                ChunkPasses::AfterProbe
+            } else {
+                ChunkPasses::AfterFirst
+            };
+            return Some((chunk, rest));
+        }
+        if probed {
+            second_passes.probed.push_back(chunk);
+        } else {
+            second_passes.chunks.push_back((chunk, worker_id));
+        }
+        self.chunks_pushed.notify_all();
+        None
+    }
+
+    /// Records that a probe worker exited, so that the main workers stop
+    /// waiting for probed chunks once none is left
+    fn finish_probing(&self) {
+        self.second_passes.lock().expect("mutex should acquire lock").probe_workers -= 1;
+        let _scheduler = self.scheduler.lock().expect("mutex should acquire lock");
+        self.chunks_pushed.notify_all();
+    }
+
+    /// Moves the first pass statistics of `chunk` from the scratch directory
+    /// of the worker that ran the first pass to the one of `worker_id`
+    fn take_first_pass(&self, chunk: &Chunk, from: usize, worker_id: usize) -> io::Result<()> {
+        let source = scratch_dir(&self.project.args.temp, Some(from));
+        let destination = scratch_dir(&self.project.args.temp, Some(worker_id));
+        let prefix = format!("{name}_fpf", name = chunk.name());
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                fs::rename(entry.path(), destination.join(entry.file_name()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Raise the encoder's thread count of a chunk that is started while fewer
+    /// chunks are waiting than there are workers, so that the threads of the
+    /// workers that are about to go idle are not left unused
+    fn boost_tail_chunk(&self, chunk: &mut Chunk, waiting: usize) {
+        let Some(threads) = chunk.encoder.get_threads(&chunk.video_params) else {
+            // without an explicit thread count, the encoder already scales to
+            // the available threads on its own
+            return;
+        };
+        let parallelism = available_parallelism().map_or(threads, NonZero::get);
+        if let Some(boosted) = tail_boost_threads(
+            threads,
+            waiting,
+            self.active_workers.load(Ordering::SeqCst),
+            parallelism,
+        ) {
+            debug!(
+                "boosting chunk {index:05} from {threads} to {boosted} threads, {waiting} chunks \
+                 left to start",
+                index = chunk.index
+            );
+            chunk.video_params =
+                chunk.encoder.with_threads(mem::take(&mut chunk.video_params), boosted);
+        }
+    }
+
+    /// Scores a finished chunk for
+    /// [`EncodeArgs::min_chunk_score`](crate::EncodeArgs::min_chunk_score)
+    fn score_chunk(&self, chunk: &Chunk, worker_id: usize) -> anyhow::Result<Option<ChunkScore>> {
+        if self.project.args.min_chunk_score.is_none() {
+            return Ok(None);
+        }
+        update_mp_msg(
+            worker_id,
+            format!("Scoring with {}", chunk.target_quality.metric),
+        );
+        let score = self
+            .project
+            .reference_pipe(chunk)
+            .and_then(|reference| {
+                chunk.target_quality.score_chunk(
+                    chunk,
+                    reference.as_ref(),
+                    Some(worker_id),
+                    self.project.args.vapoursynth_plugins,
+                )
+            })
+            .map_err(|e| {
+                Av1anError::Metrics(anyhow!("Failed to score chunk {}: {e}", chunk.index))
+            })?;
+        debug!(
+            "chunk {index:05} scored {score:.3} {metric} at q {quantizer}",
+            index = chunk.index,
+            score = score.score,
+            metric = chunk.target_quality.metric,
+            quantizer = score.quantizer
+        );
+        Ok(Some(score))
+    }
+
+    /// Encode the `passes` of a chunk. Returns whether the chunk is finished,
+    /// which is only not the case after running only its first pass.
+    #[tracing::instrument(skip(self, chunk, terminations_requested, supervisor), fields(chunk_index = format!("{:>05}", chunk.index)))]
     fn encode_chunk(
         &self,
         chunk: &mut Chunk,
+        passes: ChunkPasses,
         worker_id: usize,
         terminations_requested: &Arc<AtomicU8>,
         total_chunks: u32,
-    ) -> anyhow::Result<()> {
+        supervisor: &WorkerSupervisor,
+    ) -> anyhow::Result<bool> {
         let st_time = Instant::now();
 
         // we display the index, so we need to subtract 1 to get the max index
-        let padding = printable_base10_digits(self.chunk_queue.len() - 1) as usize;
+        let padding = printable_base10_digits(total_chunks.saturating_sub(1) as usize) as usize;
         update_mp_chunk(worker_id, chunk.index, padding);
 
-        if let Some((min, max)) = chunk.target_quality.target {
+        if chunk.passthrough {
+            update_mp_msg(worker_id, "Copying passthrough zone".to_string());
+            let output = chunk.output();
+            cut_passthrough(
+                chunk.input.as_path(),
+                Path::new(&output),
+                chunk.start_frame,
+                chunk.end_frame,
+            )?;
+            let frames = get_num_frames(Path::new(&output))?;
+            if frames != chunk.frames() && !chunk.ignore_frame_mismatch {
+                bail!(
+                    "Passthrough zone was copied with {frames} frames, but it has {} frames. Make \
+                     sure it starts and ends on keyframes",
+                    chunk.frames()
+                );
+            }
+
+            get_done().done.insert(chunk.name(), DoneChunk {
+                frames,
+                size_bytes: Path::new(&output).metadata()?.len(),
+                summary: EncoderSummary::default(),
+                score: None,
+            });
+
+            save_done(
+                Path::new(&self.project.args.temp),
+                self.project.args.shared_temp,
+            )?;
+            ChunkProgress::new(self.project.args.verbosity).commit(frames as u64);
+
+            supervisor.record_success(worker_id);
+            return Ok(true);
+        }
+
+        // Target Quality already ran with the first pass or on a probe worker
+        if !matches!(passes, ChunkPasses::AfterFirst | ChunkPasses::AfterProbe)
+            && let Some((min, max)) = chunk.target_quality.target
+        {
             update_mp_msg(
                 worker_id,
                 format!(
@@ -229,11 +1057,13 @@ impl Broker<'_> {
                     max = max
                 ),
             );
+            let tq_start = Instant::now();
             for r#try in 1..=self.project.args.max_tries {
                 let res = chunk.target_quality.per_shot_target_quality(
                     chunk,
                     Some(worker_id),
                     self.project.args.vapoursynth_plugins,
+                    &self.project.res_correction,
                 );
                 match res {
                     Ok(cq) => {
@@ -242,64 +1072,84 @@ impl Broker<'_> {
                     },
                     Err(e) => {
                         if r#try >= self.project.args.max_tries {
-                            bail!(
+                            return Err(Av1anError::Metrics(anyhow!(
                                 "Target Quality failed after {} tries on chunk {}:\n{}",
                                 r#try,
                                 chunk.index,
                                 e
-                            );
+                            ))
+                            .into());
                         }
                     },
                 }
             }
+            *self.project.probe_time.lock().expect("mutex should acquire lock") +=
+                tq_start.elapsed();
 
+            // probes encoded at the probe resolution cannot be used as the
+            // final encode
             if chunk.target_quality.params_copied
                 && chunk.target_quality.probing_rate == 1
+                && chunk.target_quality.probe_res.is_none()
                 && self.project.args.ffmpeg_filter_args.is_empty()
+                && self.project.args.deband.is_none()
+                && self.project.args.burn_subs.is_none()
+                && chunk.ffmpeg_filter.is_none()
                 && chunk.proxy.is_none()
                 && let Some(optimal_q) = chunk.tq_cq
             {
-                let extension = match self.project.args.encoder {
-                    crate::encoder::Encoder::x264 => "264",
-                    crate::encoder::Encoder::x265 => "hevc",
-                    _ => "ivf",
-                };
-                let probe_file =
-                    std::path::Path::new(&self.project.args.temp).join("split").join({
-                        let q_str = crate::encoder::format_q(optimal_q);
-                        format!("v_{:05}_{}.{}", chunk.index, q_str, extension)
-                    });
+                let probe_file = scratch_dir(&self.project.args.temp, Some(worker_id)).join({
+                    let q_str = crate::encoder::format_q(optimal_q);
+                    format!(
+                        "v_{:05}_{}.{}",
+                        chunk.index,
+                        q_str,
+                        chunk.encoder.output_extension()
+                    )
+                });
 
                 if probe_file.exists() {
-                    let encode_dir = std::path::Path::new(&self.project.args.temp).join("encode");
-                    std::fs::create_dir_all(&encode_dir)?;
-                    let output_file =
-                        encode_dir.join(format!("{index:05}.{extension}", index = chunk.index));
+                    let output_file = chunk.output_path();
+                    if let Some(dir) = output_file.parent() {
+                        std::fs::create_dir_all(dir)?;
+                    }
                     std::fs::copy(&probe_file, &output_file)?;
 
-                    inc_mp_bar(chunk.frames() as u64);
-
-                    let progress_file = Path::new(&self.project.args.temp).join("done.json");
                     get_done().done.insert(chunk.name(), DoneChunk {
                         frames:     chunk.frames(),
                         size_bytes: output_file.metadata()?.len(),
+                        summary:    EncoderSummary::default(),
+                        score:      self.score_chunk(chunk, worker_id)?,
                     });
 
-                    let mut progress_file = File::create(progress_file)?;
-                    progress_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+                    save_done(
+                        Path::new(&self.project.args.temp),
+                        self.project.args.shared_temp,
+                    )?;
+                    ChunkProgress::new(self.project.args.verbosity)
+                        .commit(chunk.output_frames() as u64);
 
                     update_progress_bar_estimates(
-                        chunk.frame_rate,
-                        self.project.frames,
+                        chunk.encoded_frame_rate(),
+                        self.project.output_frames(),
                         self.project.args.verbosity,
                         (get_done().done.len() as u32, total_chunks),
                     );
 
-                    return Ok(());
+                    supervisor.record_success(worker_id);
+                    return Ok(true);
                 }
             }
         }
 
+        if passes == ChunkPasses::ProbeOnly {
+            debug!(
+                "probed chunk {index:05}, queueing its encode",
+                index = chunk.index
+            );
+            return Ok(false);
+        }
+
         if terminations_requested.load(Ordering::SeqCst) > 0 {
             bail!(
                 "Termination requested after Target Quality. Skipping chunk {}",
@@ -314,69 +1164,223 @@ impl Broker<'_> {
             frames = chunk.frames()
         );
 
-        let passes = chunk.passes;
-        for current_pass in 1..=passes {
+        let last_pass = if passes == ChunkPasses::FirstOnly {
+            1
+        } else {
+            chunk.passes
+        };
+        let mut summary = EncoderSummary::default();
+        let mut current_pass = if passes == ChunkPasses::AfterFirst {
+            2
+        } else {
+            1
+        };
+        // the frames of the chunk stay on the progress bar once it is done
+        let progress = ChunkProgress::new(self.project.args.verbosity);
+        'passes: while current_pass <= last_pass {
             for r#try in 1..=self.project.args.max_tries {
-                let res = self.project.create_pipes(chunk, current_pass, worker_id, padding);
-                if let Err((e, frames)) = res {
-                    dec_bar(frames);
-
-                    // If user presses CTRL+C more than once, do not let the worker finish
-                    if terminations_requested.load(Ordering::SeqCst) > 1 {
-                        bail!(
-                            "Termination requested after Worker restart. Skipping chunk {}",
-                            chunk.index
-                        );
-                    }
+                let res =
+                    self.project.create_pipes(chunk, current_pass, worker_id, padding, &progress);
+                match res {
+                    Ok(pass_summary) => {
+                        summary = pass_summary;
+                        break;
+                    },
+                    Err(e) => {
+                        progress.rollback();
+                        supervisor.record_failure(worker_id, chunk.index);
+
+                        // If user presses CTRL+C more than once, do not let the worker finish
+                        if terminations_requested.load(Ordering::SeqCst) > 1 {
+                            bail!(
+                                "Termination requested after Worker restart. Skipping chunk {}",
+                                chunk.index
+                            );
+                        }
+
+                        // the source filter rather than the encoder is likely at fault, so
+                        // start over from the first pass with BestSource without counting
+                        // the failure as a try
+                        if e.downcast_ref::<EncoderCrash>()
+                            .is_some_and(EncoderCrash::is_source_failure)
+                            && self.project.fall_back_to_bestsource(chunk)?
+                        {
+                            warn!(
+                                "[chunk {index}] {chunk_method} failed to decode the chunk, \
+                                 retrying with BestSource:\n{e}",
+                                index = chunk.index,
+                                chunk_method = self.project.args.chunk_method
+                            );
+                            current_pass = 1;
+                            continue 'passes;
+                        }
 
-                    if r#try == self.project.args.max_tries {
-                        bail!(
-                            "[chunk {index}] encoder failed {tries} times, shutting down worker: \
-                             {e}",
-                            index = chunk.index,
-                            tries = self.project.args.max_tries
+                        if r#try == self.project.args.max_tries {
+                            return Err(Av1anError::Encoder {
+                                chunk: chunk.index,
+                                pass:  current_pass,
+                                error: anyhow!(
+                                    "encoder failed {tries} times, shutting down worker: {e}",
+                                    tries = self.project.args.max_tries
+                                ),
+                            }
+                            .into());
+                        }
+                        // avoids double-print of the error message as both a WARN and ERROR,
+                        // since `Broker::encoding_loop` will print the error message as well
+                        warn!(
+                            "Encoder failed (on chunk {index}):\n{e}",
+                            index = chunk.index
                         );
-                    }
-                    // avoids double-print of the error message as both a WARN and ERROR,
-                    // since `Broker::encoding_loop` will print the error message as well
-                    warn!(
-                        "Encoder failed (on chunk {index}):\n{e}",
-                        index = chunk.index
-                    );
-                } else {
-                    break;
+                    },
                 }
             }
+            current_pass += 1;
         }
 
+        if last_pass < chunk.passes {
+            debug!(
+                "finished the first pass of chunk {index:05}, queueing the later passes",
+                index = chunk.index
+            );
+            return Ok(false);
+        }
+
+        supervisor.record_success(worker_id);
+
         let enc_time = st_time.elapsed();
         let fps = chunk.frames() as f64 / enc_time.as_secs_f64();
 
-        let progress_file = Path::new(&self.project.args.temp).join("done.json");
+        let score = self.score_chunk(chunk, worker_id)?;
         get_done().done.insert(chunk.name(), DoneChunk {
-            frames:     chunk.frames(),
+            frames: chunk.output_frames(),
             size_bytes: Path::new(&chunk.output())
                 .metadata()
                 .expect("Unable to get size of finished chunk")
                 .len(),
+            summary,
+            score,
         });
 
-        let mut progress_file = File::create(progress_file)?;
-        progress_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+        save_done(
+            Path::new(&self.project.args.temp),
+            self.project.args.shared_temp,
+        )?;
+        progress.commit(chunk.output_frames() as u64);
 
         update_progress_bar_estimates(
-            chunk.frame_rate,
-            self.project.frames,
+            chunk.encoded_frame_rate(),
+            self.project.output_frames(),
             self.project.args.verbosity,
             (get_done().done.len() as u32, total_chunks),
         );
 
         debug!(
-            "finished chunk {index:05}: {frames} frames, {fps:.2} fps, took {enc_time:.2?}",
+            "finished chunk {index:05}: {frames} frames, {fps:.2} fps, took {enc_time:.2?}{stats}",
             index = chunk.index,
-            frames = chunk.frames()
+            frames = chunk.frames(),
+            stats = summary.describe()
         );
 
-        Ok(())
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+
+    #[test]
+    fn supervisor_ignores_few_failures() {
+        let supervisor = WorkerSupervisor::default();
+        supervisor.register(0, None);
+        supervisor.register(1, None);
+        supervisor.record_failure(0, 3);
+        supervisor.record_failure(0, 7);
+        assert!(supervisor.correlations().is_empty());
+    }
+
+    #[test]
+    fn supervisor_flags_failing_worker() {
+        let supervisor = WorkerSupervisor::default();
+        supervisor.register(0, Some(smallvec![0, 1]));
+        supervisor.register(1, Some(smallvec![2, 3]));
+        for chunk_index in [1, 4, 9] {
+            supervisor.record_failure(1, chunk_index);
+        }
+        supervisor.record_success(0);
+        supervisor.record_success(0);
+
+        let findings = supervisor.correlations();
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].starts_with("worker 1 (pinned to cores [2, 3])"));
+    }
+
+    #[test]
+    fn supervisor_flags_failing_chunk() {
+        let supervisor = WorkerSupervisor::default();
+        supervisor.register(0, None);
+        supervisor.register(1, None);
+        for _ in 0..3 {
+            supervisor.record_failure(0, 5);
+        }
+        supervisor.record_success(1);
+
+        let findings = supervisor.correlations();
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].starts_with("chunk 5 failed 3 times on workers {0}"));
+    }
+
+    #[test]
+    fn source_failures() {
+        let crash = |stdout: &str, source_pipe_stderr: &str| EncoderCrash {
+            exit_status:        ExitStatus::default(),
+            stdout:             stdout.to_string().into(),
+            stderr:             String::new().into(),
+            source_pipe_stderr: source_pipe_stderr.to_string().into(),
+            ffmpeg_pipe_stderr: None,
+        };
+        assert!(crash(
+            "FRAME MISMATCH: chunk 3: 40/48 (actual/expected frames)",
+            ""
+        )
+        .is_source_failure());
+        assert!(crash(
+            "",
+            "Error: Failed to retrieve frame 12 with error: LWLibavSource: failed to decode"
+        )
+        .is_source_failure());
+        assert!(!crash("", "Output 48 frames in 1.20 seconds (40.00 fps)").is_source_failure());
+    }
+
+    #[test]
+    fn tail_boost_only_for_last_chunks() {
+        // plenty of chunks left for every worker
+        assert_eq!(tail_boost_threads(4, 10, 4, 16), None);
+        assert_eq!(tail_boost_threads(4, 3, 4, 16), None);
+        // two chunks left for four workers
+        assert_eq!(tail_boost_threads(4, 1, 4, 16), Some(8));
+        // the very last chunk gets every thread, but no more than available
+        assert_eq!(tail_boost_threads(4, 0, 4, 16), Some(16));
+        assert_eq!(tail_boost_threads(8, 0, 4, 16), Some(16));
+        assert_eq!(tail_boost_threads(16, 0, 4, 16), None);
+    }
+
+    #[test]
+    fn gpus_assigned_in_turn() {
+        assert_eq!(GpuPool::new(0).acquire(Some(1)), None);
+
+        let pool = GpuPool::new(2);
+        assert_eq!(pool.acquire(Some(2)), Some(0));
+        assert_eq!(pool.acquire(Some(2)), Some(1));
+        assert_eq!(pool.acquire(Some(2)), Some(0));
+        pool.release(0);
+        pool.release(0);
+        // GPU 1 is next in turn, and still below the limit
+        assert_eq!(pool.acquire(Some(2)), Some(1));
+        // GPU 1 is full now
+        assert_eq!(pool.acquire(Some(2)), Some(0));
     }
 }
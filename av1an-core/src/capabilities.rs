@@ -0,0 +1,186 @@
+//! What the encoders, VapourSynth plugins and other tools installed on this
+//! system support, for frontends that build their options from it
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use strum::IntoEnumIterator;
+
+use crate::{
+    bench::available_chunk_methods,
+    concat::ConcatMethod,
+    encoder::Encoder,
+    ffmpeg::FFPixelFormat,
+    metrics::{
+        vmaf::{has_libvmaf_cuda, validate_libvmaf},
+        xpsnr::validate_libxpsnr,
+    },
+    vapoursynth::{get_vapoursynth_plugins, VSZipVersion},
+};
+
+/// Everything Av1an found on this system
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub av1an_version:  &'static str,
+    pub encoders:       Vec<EncoderCapabilities>,
+    /// `None` if VapourSynth could not be loaded
+    pub vapoursynth:    Option<BTreeMap<&'static str, bool>>,
+    /// Chunk methods that can be used, in order of preference
+    pub chunk_methods:  Vec<&'static str>,
+    pub concat_methods: BTreeMap<&'static str, bool>,
+    /// External programs, and whether they were found in PATH
+    pub tools:          BTreeMap<&'static str, bool>,
+    /// Metrics FFmpeg was built with, which target quality and plotting need
+    pub ffmpeg_metrics: BTreeMap<&'static str, bool>,
+}
+
+/// An encoder, and what its installed binary supports
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderCapabilities {
+    /// Name of the encoder for `--encoder`
+    pub name:          &'static str,
+    pub binary:        &'static str,
+    pub format:        &'static str,
+    /// `None` if the binary was not found
+    pub version:       Option<String>,
+    /// Bit depths of the installed binary, or the ones the encoder supports in
+    /// general if they could not be determined
+    pub bit_depths:    Vec<usize>,
+    /// Pixel formats for `--pix-format` at those bit depths
+    pub pixel_formats: Vec<&'static str>,
+}
+
+impl EncoderCapabilities {
+    /// Checks what the installed binary of `encoder` supports
+    #[inline]
+    pub fn detect(encoder: Encoder) -> Self {
+        let version = encoder.version_text();
+        let bit_depths = version.as_ref().and_then(|_| encoder.supported_bit_depths());
+        Self::new(encoder, version, bit_depths)
+    }
+
+    /// Capabilities of `encoder` with the `version` and `bit_depths` of its
+    /// installed binary, as far as they are known
+    fn new(encoder: Encoder, version: Option<String>, bit_depths: Option<Vec<usize>>) -> Self {
+        let depths = |formats: &[FFPixelFormat]| -> Vec<usize> {
+            let mut depths = formats
+                .iter()
+                .filter_map(|&format| encoder.get_format_bit_depth(format).ok())
+                .collect::<Vec<_>>();
+            depths.sort_unstable();
+            depths.dedup();
+            depths
+        };
+        let all_formats = FFPixelFormat::iter().collect::<Vec<_>>();
+        let bit_depths = bit_depths.unwrap_or_else(|| depths(&all_formats));
+        let pixel_formats = all_formats
+            .into_iter()
+            .filter(|&format| {
+                encoder
+                    .get_format_bit_depth(format)
+                    .is_ok_and(|depth| bit_depths.contains(&depth))
+            })
+            .map(|format| format.to_pix_fmt_string())
+            .collect();
+
+        Self {
+            name: encoder.into(),
+            binary: encoder.bin(),
+            format: encoder.format(),
+            version,
+            bit_depths,
+            pixel_formats,
+        }
+    }
+}
+
+impl Capabilities {
+    /// Checks every encoder, plugin and tool. This runs every encoder binary,
+    /// so it takes a moment.
+    #[inline]
+    pub fn detect() -> Self {
+        let plugins = get_vapoursynth_plugins().ok();
+        let found = |tool: &str| which::which(tool).is_ok();
+
+        let vapoursynth = plugins.map(|plugins| {
+            BTreeMap::from([
+                ("systems.innocent.lsmas", plugins.lsmash),
+                ("com.vapoursynth.ffms2", plugins.ffms2),
+                ("com.vapoursynth.dgdecodenv", plugins.dgdecnv),
+                ("com.vapoursynth.bestsource", plugins.bestsource),
+                ("com.julek.plugin", plugins.julek),
+                ("com.julek.vszip", plugins.vszip != VSZipVersion::None),
+                ("com.lumen.vship", plugins.vship),
+                ("com.vapoursynth.neo_f3kdb", plugins.neo_f3kdb),
+            ])
+        });
+        let tools = ["ffmpeg", "ffprobe", "vspipe", "mkvmerge", "mkvextract", "dgindexnv"]
+            .into_iter()
+            .map(|tool| (tool, found(tool)))
+            .collect::<BTreeMap<_, _>>();
+        let concat_methods = BTreeMap::from([
+            (ConcatMethod::MKVMerge.into(), tools["mkvmerge"]),
+            (ConcatMethod::FFmpeg.into(), tools["ffmpeg"]),
+            (ConcatMethod::Ivf.into(), true),
+        ]);
+        let ffmpeg_metrics = if tools["ffmpeg"] {
+            BTreeMap::from([
+                ("vmaf", validate_libvmaf().is_ok()),
+                ("vmaf-cuda", has_libvmaf_cuda()),
+                ("xpsnr", validate_libxpsnr().is_ok()),
+            ])
+        } else {
+            BTreeMap::new()
+        };
+
+        Self {
+            av1an_version: env!("CARGO_PKG_VERSION"),
+            encoders: Encoder::iter().map(EncoderCapabilities::detect).collect(),
+            vapoursynth,
+            chunk_methods: available_chunk_methods(plugins).into_iter().map(Into::into).collect(),
+            concat_methods,
+            tools,
+            ffmpeg_metrics,
+        }
+    }
+
+    /// Serializes the capabilities as pretty-printed JSON
+    #[inline]
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::parse_x264_bit_depths;
+
+    #[test]
+    fn encoder_pixel_formats() {
+        let svt = EncoderCapabilities::new(Encoder::svt_av1, None, None);
+        let supported = ["yuv420p", "yuv420p10le"];
+        assert!(svt.pixel_formats.iter().all(|format| supported.contains(format)));
+        assert!(!svt.pixel_formats.is_empty());
+
+        let aom = EncoderCapabilities::new(
+            Encoder::aom,
+            Some("v3.12.1".to_string()),
+            Some(vec![8, 10, 12]),
+        );
+        assert_eq!(aom.bit_depths, [8, 10, 12]);
+        assert!(aom.pixel_formats.contains(&"yuv444p12le"));
+
+        // an 8-bit only build of x264
+        let version =
+            "x264 0.164.3108 31e19f9\nx264 configuration: --bit-depth=8 --chroma-format=all\n";
+        let x264 = EncoderCapabilities::new(
+            Encoder::x264,
+            Some(version.to_string()),
+            parse_x264_bit_depths(version),
+        );
+        assert_eq!(x264.bit_depths, [8]);
+        assert!(x264.pixel_formats.contains(&"yuv420p"));
+        assert!(!x264.pixel_formats.contains(&"yuv420p10le"));
+    }
+}
@@ -1,10 +1,18 @@
 #[cfg(test)]
 mod tests;
 
-use std::{ffi::OsString, path::Path};
+use std::{
+    ffi::OsString,
+    fmt::{self, Display},
+    mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use av1_grain::{generate_photon_noise_params, write_grain_table, NoiseGenArgs};
+use anyhow::{bail, ensure};
+use av1_grain::{generate_photon_noise_params, write_grain_table, NoiseGenArgs, TransferFunction};
 use serde::{Deserialize, Serialize};
+use strum::{EnumString, IntoStaticStr};
 use tracing::debug;
 
 use crate::{
@@ -15,6 +23,195 @@ use crate::{
     TargetQuality,
 };
 
+/// Transfer characteristics that photon noise grain tables are generated for
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NoiseTransfer {
+    /// SDR content
+    #[strum(serialize = "bt1886")]
+    Bt1886,
+    /// HDR content using the SMPTE ST 2084 (PQ) transfer function
+    #[strum(serialize = "pq")]
+    Pq,
+}
+
+impl Display for NoiseTransfer {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+impl From<TransferFunction> for NoiseTransfer {
+    #[inline]
+    fn from(transfer_function: TransferFunction) -> Self {
+        match transfer_function {
+            TransferFunction::BT1886 => Self::Bt1886,
+            TransferFunction::SMPTE2084 => Self::Pq,
+        }
+    }
+}
+
+/// Writes the photon noise grain table that `--photon-noise` uses for ISO
+/// `iso_setting` to `path`, covering the whole video
+#[inline]
+pub fn write_photon_noise_table(
+    path: &Path,
+    iso_setting: u32,
+    (width, height): (u32, u32),
+    transfer: NoiseTransfer,
+    chroma_grain: bool,
+    full_range: bool,
+) -> anyhow::Result<()> {
+    let params = generate_photon_noise_params(0, u64::MAX, NoiseGenArgs {
+        iso_setting,
+        width,
+        height,
+        transfer_function: match transfer {
+            NoiseTransfer::Bt1886 => TransferFunction::BT1886,
+            NoiseTransfer::Pq => TransferFunction::SMPTE2084,
+        },
+        chroma_grain,
+        full_range,
+        random_seed: None,
+    });
+    write_grain_table(path, &[params])
+}
+
+/// Number of chunks in each directory of the `{shard}` placeholder of
+/// [`ChunkNames`]
+pub const CHUNKS_PER_SHARD: usize = 1000;
+
+/// Template of the paths of the encoded chunks in the `encode` directory of
+/// the temporary directory, without their extension.
+///
+/// `{index}` is replaced by the index of the chunk and `{shard}` by the index
+/// divided by [`CHUNKS_PER_SHARD`], both zero padded to N digits if written as
+/// `{index:0N}`. `/` separates directories, so that e.g.
+/// `{shard:03}/{index:05}` spreads the chunks of huge inputs over directories
+/// of 1000 chunks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChunkNames(String);
+
+/// Part of a [`ChunkNames`] template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NamePart<'a> {
+    Text(&'a str),
+    Index { width: usize },
+    Shard { width: usize },
+}
+
+impl ChunkNames {
+    /// Splits the template into its text and placeholders
+    fn parts(template: &str) -> anyhow::Result<Vec<NamePart<'_>>> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let (text, placeholder) = rest.split_at(start);
+            if !text.is_empty() {
+                parts.push(NamePart::Text(text));
+            }
+            let Some(end) = placeholder.find('}') else {
+                bail!("`{template}` has an unclosed placeholder");
+            };
+            let (placeholder, after) = placeholder.split_at(end + 1);
+            let inner = placeholder.trim_start_matches('{').trim_end_matches('}');
+            let (name, width) = match inner.split_once(':') {
+                Some((name, width)) => {
+                    let Some(digits) = width.strip_prefix('0') else {
+                        bail!("the width of `{placeholder}` has to be written as 0N");
+                    };
+                    (name, digits.parse::<usize>()?)
+                },
+                None => (inner, 0),
+            };
+            parts.push(match name {
+                "index" => NamePart::Index {
+                    width,
+                },
+                "shard" => NamePart::Shard {
+                    width,
+                },
+                _ => bail!("`{placeholder}` is not a placeholder, use {{index}} or {{shard}}"),
+            });
+            rest = after;
+        }
+        if !rest.is_empty() {
+            parts.push(NamePart::Text(rest));
+        }
+        Ok(parts)
+    }
+
+    /// Returns the path of the chunk with `index`, relative to the `encode`
+    /// directory and without its extension
+    #[inline]
+    pub fn render(&self, index: usize) -> String {
+        let parts = Self::parts(&self.0).expect("template should have been validated");
+        parts
+            .into_iter()
+            .map(|part| match part {
+                NamePart::Text(text) => text.to_string(),
+                NamePart::Index {
+                    width,
+                } => format!("{index:0width$}"),
+                NamePart::Shard {
+                    width,
+                } => format!("{:0width$}", index / CHUNKS_PER_SHARD),
+            })
+            .collect()
+    }
+}
+
+impl Default for ChunkNames {
+    #[inline]
+    fn default() -> Self {
+        Self("{index:05}".to_string())
+    }
+}
+
+impl FromStr for ChunkNames {
+    type Err = anyhow::Error;
+
+    #[inline]
+    fn from_str(template: &str) -> anyhow::Result<Self> {
+        let parts = Self::parts(template)?;
+        ensure!(
+            parts.iter().any(|part| matches!(part, NamePart::Index { .. })),
+            "`{template}` has no {{index}} placeholder, so chunks would overwrite each other"
+        );
+        ensure!(
+            !template.contains('\\')
+                && template.split('/').all(|component| !component.is_empty() && component != ".."),
+            "`{template}` has to be a relative path separated by `/`, without `..` or empty \
+             directory names"
+        );
+        Ok(Self(template.to_string()))
+    }
+}
+
+impl TryFrom<String> for ChunkNames {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(template: String) -> anyhow::Result<Self> {
+        template.parse()
+    }
+}
+
+impl From<ChunkNames> for String {
+    #[inline]
+    fn from(names: ChunkNames) -> Self {
+        names.0
+    }
+}
+
+impl Display for ChunkNames {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chunk {
     pub temp:                  String,
@@ -37,27 +234,136 @@ pub struct Chunk {
     /// Optional target quality CQ level
     #[serde(rename = "per_shot_target_quality_cq")]
     pub tq_cq:                 Option<f32>,
+    /// Extra FFmpeg filter graph from the zone overrides, applied on top of the
+    /// global filter args
+    #[serde(default)]
+    pub ffmpeg_filter:         Option<String>,
     pub ignore_frame_mismatch: bool,
+    /// Frame rate of the encoded chunk if the filters change it, see
+    /// [`EncodeArgs::output_fps`](crate::EncodeArgs::output_fps)
+    #[serde(default)]
+    pub output_frame_rate:     Option<f64>,
+    /// Copy the frames of this chunk from the source bitstream instead of
+    /// encoding them, see [`crate::split::cut_passthrough`]
+    #[serde(default)]
+    pub passthrough:           bool,
+    /// GPU the broker assigned the chunk to while it is being encoded, see
+    /// [`EncodeArgs::gpus`](crate::EncodeArgs::gpus)
+    #[serde(skip)]
+    pub gpu:                   Option<u32>,
+    /// Path of the encoded chunk, see [`ChunkNames`]
+    #[serde(default)]
+    pub output_names:          ChunkNames,
 }
 
 impl Chunk {
+    /// Environment variable that makes CUDA applications, such as DGDecNV,
+    /// use the GPU assigned to the chunk
+    #[inline]
+    pub fn gpu_env(&self) -> Option<(&'static str, String)> {
+        self.gpu.map(|gpu| ("CUDA_VISIBLE_DEVICES", gpu.to_string()))
+    }
+
     /// Returns numeric name of chunk `00001`
+    #[inline]
     pub fn name(&self) -> String {
         format!("{:05}", self.index)
     }
 
+    #[inline]
     pub fn output(&self) -> String {
-        Path::new(&self.temp)
-            .join("encode")
-            .join(format!("{}.{}", self.name(), self.output_ext))
-            .to_string_lossy()
-            .to_string()
+        self.output_path().to_string_lossy().to_string()
+    }
+
+    /// Path of the encoded chunk relative to the `encode` directory, with `/`
+    /// separating its directories
+    #[inline]
+    pub fn output_name(&self) -> String {
+        format!(
+            "{}.{}",
+            self.output_names.render(self.index),
+            self.output_ext
+        )
     }
 
+    /// Path of the encoded chunk, which may be in a subdirectory of the
+    /// `encode` directory
+    #[inline]
+    pub fn output_path(&self) -> PathBuf {
+        let mut path = Path::new(&self.temp).join("encode");
+        path.extend(self.output_name().split('/'));
+        path
+    }
+
+    #[inline]
     pub const fn frames(&self) -> usize {
         self.end_frame - self.start_frame
     }
 
+    /// Number of frames the encoded chunk has, which differs from
+    /// [`Self::frames`] if the filters change the frame rate
+    #[inline]
+    pub fn output_frames(&self) -> usize {
+        let frames = self.frames();
+        self.output_frame_rate.map_or(frames, |output_frame_rate| {
+            (frames as f64 * output_frame_rate / self.frame_rate).round() as usize
+        })
+    }
+
+    /// Frame rate of the encoded chunk
+    #[inline]
+    pub fn encoded_frame_rate(&self) -> f64 {
+        self.output_frame_rate.unwrap_or(self.frame_rate)
+    }
+
+    /// Whether an encoded chunk with `encoded_frames` frames has as many
+    /// frames as expected. Frame rate conversions may round the number of
+    /// frames of each chunk either way, so they are allowed to be one frame
+    /// off.
+    #[inline]
+    pub fn frames_match(&self, encoded_frames: usize) -> bool {
+        self.ignore_frame_mismatch
+            || if self.output_frame_rate.is_some() {
+                encoded_frames.abs_diff(self.output_frames()) <= 1
+            } else {
+                encoded_frames == self.frames()
+            }
+    }
+
+    /// Caps the bitrate of the chunk at `peak` kbps, with a buffer of
+    /// `buffer` seconds that is never longer than the chunk. Every chunk is
+    /// encoded with a full buffer, so a buffer longer than the chunk would let
+    /// the peaks of consecutive chunks add up at their joins. Returns `false`
+    /// if the encoder of the chunk cannot cap its bitrate.
+    pub(crate) fn apply_bitrate_cap(&mut self, peak: u32, buffer: f64) -> bool {
+        if !self.encoder.supports_bitrate_cap() {
+            return false;
+        }
+        let duration = self.output_frames() as f64 / self.encoded_frame_rate();
+        let bufsize = (f64::from(peak) * buffer.min(duration)).round().max(1.0) as u32;
+        if let Some(params) =
+            self.encoder.with_bitrate_cap(mem::take(&mut self.video_params), peak, bufsize)
+        {
+            self.video_params = params;
+        }
+        true
+    }
+
+    /// Sets the chunk to encode again at a lower quantizer than `quantizer`,
+    /// the one it was encoded at, without Target Quality. Returns `false` if
+    /// it was already encoded at the lowest quantizer.
+    pub(crate) fn lower_quantizer(&mut self, quantizer: f32) -> bool {
+        if quantizer <= 0.0 {
+            return false;
+        }
+        let step = (self.encoder.get_max_q() / 16.0).round();
+        let q = (quantizer - step).max(0.0);
+        self.video_params = self.encoder.man_command(mem::take(&mut self.video_params), q);
+        self.target_quality.target = None;
+        self.tq_cq = None;
+        true
+    }
+
     pub(crate) fn apply_photon_noise_args(
         &mut self,
         photon_noise: Option<u8>,
@@ -79,16 +385,14 @@ impl Chunk {
                 }
                 let transfer_function =
                     clip_info.transfer_function_params_adjusted(&self.video_params);
-                let params = generate_photon_noise_params(0, u64::MAX, NoiseGenArgs {
+                write_photon_noise_table(
+                    &grain_table,
                     iso_setting,
-                    width,
-                    height,
-                    transfer_function,
-                    chroma_grain: chroma_noise,
-                    full_range: matches!(color_range, Some(ColorRange::Full)),
-                    random_seed: None,
-                });
-                write_grain_table(&grain_table, &[params])?;
+                    (width, height),
+                    transfer_function.into(),
+                    chroma_noise,
+                    matches!(color_range, Some(ColorRange::Full)),
+                )?;
             }
 
             insert_noise_table_params(self.encoder, &mut self.video_params, &grain_table)?;
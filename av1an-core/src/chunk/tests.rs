@@ -24,11 +24,16 @@ fn chunk_name_1() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        ffmpeg_filter:         None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
         noise_size:            (None, None),
         ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
     };
     assert_eq!("00001", ch.name());
 }
@@ -53,11 +58,16 @@ fn chunk_name_10000() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        ffmpeg_filter:         None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
         noise_size:            (None, None),
         ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
     };
     assert_eq!("10000", ch.name());
 }
@@ -83,11 +93,16 @@ fn chunk_output() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("d", Encoder::x264),
         tq_cq:                 None,
+        ffmpeg_filter:         None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
         noise_size:            (None, None),
         ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
     };
 
     // Convert output path to PathBuf for comparison
@@ -96,6 +111,33 @@ fn chunk_output() {
     assert_eq!(expected_output.to_string_lossy(), ch.output());
 }
 
+#[test]
+fn chunk_names() {
+    assert_eq!(ChunkNames::default().render(42), "00042");
+    let sharded: ChunkNames = "{shard:03}/{index:05}".parse().expect("should parse");
+    assert_eq!(sharded.render(42), "000/00042");
+    assert_eq!(sharded.render(12345), "012/12345");
+    let plain: ChunkNames = "chunk_{index}".parse().expect("should parse");
+    assert_eq!(plain.render(7), "chunk_7");
+
+    for invalid in [
+        "{shard}",
+        "{index",
+        "{frame}",
+        "{index:5}",
+        "../{index}",
+        "/{index}",
+        "a//{index}",
+        "a\\{index}",
+    ] {
+        assert!(invalid.parse::<ChunkNames>().is_err(), "{invalid}");
+    }
+
+    let json = serde_json::to_string(&sharded).expect("should serialize");
+    assert_eq!(json, r#""{shard:03}/{index:05}""#);
+    assert!(serde_json::from_str::<ChunkNames>(r#""{shard}""#).is_err());
+}
+
 #[test]
 fn chunk_frames() {
     let ch = Chunk {
@@ -117,13 +159,135 @@ fn chunk_frames() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        ffmpeg_filter:         None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
         noise_size:            (None, None),
         ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
     };
     assert_eq!(15, ch.frames());
+    assert_eq!(15, ch.output_frames());
+    assert!(ch.frames_match(15));
+    assert!(!ch.frames_match(16));
+
+    let interpolated = Chunk {
+        output_frame_rate: Some(60.0),
+        ..ch
+    };
+    assert_eq!(15, interpolated.frames());
+    assert_eq!(30, interpolated.output_frames());
+    assert!(interpolated.frames_match(29));
+    assert!(!interpolated.frames_match(15));
+}
+
+#[test]
+fn apply_bitrate_cap_limits_buffer_to_chunk() {
+    let mut ch = Chunk {
+        temp:                  "none".to_owned(),
+        index:                 1,
+        input:                 Input::Video {
+            path:         "test.mkv".into(),
+            temp:         "none".to_owned(),
+            chunk_method: ChunkMethod::LSMASH,
+            is_proxy:     false,
+            cache_mode:   vapoursynth::CacheSource::SOURCE,
+        },
+        proxy:                 None,
+        source_cmd:            vec!["".into()],
+        proxy_cmd:             None,
+        output_ext:            "264".to_owned(),
+        start_frame:           0,
+        end_frame:             300,
+        frame_rate:            30.0,
+        target_quality:        TargetQuality::default("none", Encoder::x264),
+        tq_cq:                 None,
+        ffmpeg_filter:         None,
+        passes:                1,
+        video_params:          vec!["--crf".to_owned(), "18".to_owned()],
+        encoder:               Encoder::x264,
+        noise_size:            (None, None),
+        ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
+    };
+    let mut short = Chunk {
+        end_frame: 15,
+        ..ch.clone()
+    };
+
+    assert!(ch.apply_bitrate_cap(10000, 2.0));
+    assert_eq!(ch.video_params, [
+        "--crf",
+        "18",
+        "--vbv-maxrate",
+        "10000",
+        "--vbv-bufsize",
+        "20000"
+    ]);
+
+    // half a second long, so the buffer is too
+    assert!(short.apply_bitrate_cap(10000, 2.0));
+    assert_eq!(short.video_params[5], "5000");
+
+    let mut unsupported = Chunk {
+        encoder: Encoder::aom,
+        video_params: vec!["--cq-level=30".to_owned()],
+        ..short
+    };
+    assert!(!unsupported.apply_bitrate_cap(10000, 2.0));
+    assert_eq!(unsupported.video_params, ["--cq-level=30"]);
+}
+
+#[test]
+fn lower_quantizer_steps_down_to_zero() {
+    let mut ch = Chunk {
+        temp:                  "none".to_owned(),
+        index:                 1,
+        input:                 Input::Video {
+            path:         "test.mkv".into(),
+            temp:         "none".to_owned(),
+            chunk_method: ChunkMethod::LSMASH,
+            is_proxy:     false,
+            cache_mode:   vapoursynth::CacheSource::SOURCE,
+        },
+        proxy:                 None,
+        source_cmd:            vec!["".into()],
+        proxy_cmd:             None,
+        output_ext:            "264".to_owned(),
+        start_frame:           0,
+        end_frame:             300,
+        frame_rate:            30.0,
+        target_quality:        TargetQuality::default("none", Encoder::x264),
+        tq_cq:                 Some(20.0),
+        ffmpeg_filter:         None,
+        passes:                1,
+        video_params:          vec!["--crf".to_owned(), "18".to_owned()],
+        encoder:               Encoder::x264,
+        noise_size:            (None, None),
+        ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
+    };
+    ch.target_quality.target = Some((90.0, 95.0));
+
+    // encoded at the quantizer Target Quality found, not the one of the params
+    assert!(ch.lower_quantizer(20.0));
+    assert_eq!(ch.video_params, ["--crf", "17"]);
+    assert_eq!(ch.tq_cq, None);
+    assert_eq!(ch.target_quality.target, None);
+
+    assert!(ch.lower_quantizer(2.0));
+    assert_eq!(ch.video_params, ["--crf", "0"]);
+    assert!(!ch.lower_quantizer(0.0));
 }
 
 #[test]
@@ -152,11 +316,16 @@ fn apply_photon_noise_args_with_noise() -> anyhow::Result<()> {
             Encoder::svt_av1,
         ),
         tq_cq:                 None,
+        ffmpeg_filter:         None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::svt_av1,
         noise_size:            (Some(1920), Some(1080)),
         ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
     };
 
     ch.apply_photon_noise_args(Some(8), true, None)?;
@@ -190,11 +359,16 @@ fn apply_photon_noise_args_no_noise() -> anyhow::Result<()> {
             Encoder::svt_av1,
         ),
         tq_cq:                 None,
+        ffmpeg_filter:         None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::svt_av1,
         noise_size:            (None, None),
         ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
     };
 
     ch.apply_photon_noise_args(None, false, None)?;
@@ -228,13 +402,34 @@ fn apply_photon_noise_args_unsupported_encoder() -> anyhow::Result<()> {
             Encoder::x264,
         ),
         tq_cq:                 None,
+        ffmpeg_filter:         None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
         noise_size:            (Some(1920), Some(1080)),
         ignore_frame_mismatch: false,
+        output_frame_rate:     None,
+        passthrough:           false,
+        gpu:                   None,
+        output_names:          ChunkNames::default(),
     };
 
     assert!(ch.apply_photon_noise_args(Some(8), true, None).is_err());
     Ok(())
 }
+
+#[test]
+fn write_photon_noise_table_file() {
+    let dir = tempfile::tempdir().expect("should create temp dir");
+    let sdr = dir.path().join("sdr.tbl");
+    let hdr = dir.path().join("hdr.tbl");
+    write_photon_noise_table(&sdr, 800, (3840, 2160), NoiseTransfer::Bt1886, false, false)
+        .expect("should write grain table");
+    write_photon_noise_table(&hdr, 800, (3840, 2160), NoiseTransfer::Pq, false, false)
+        .expect("should write grain table");
+
+    let sdr = std::fs::read_to_string(sdr).expect("should read grain table");
+    let hdr = std::fs::read_to_string(hdr).expect("should read grain table");
+    assert!(sdr.starts_with("filmgrn1"));
+    assert_ne!(sdr, hdr);
+}
@@ -0,0 +1,496 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use av_format::rational::Rational64;
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
+use tracing::{info, warn};
+
+use crate::{
+    ffmpeg::{self, FFPixelFormat},
+    metrics::{
+        reference::ReferencePipe,
+        ssimulacra2,
+        statistics::MetricStatistics,
+        vmaf::{read_vmaf_file, run_vmaf, validate_libvmaf},
+        xpsnr::{read_xpsnr_file, run_xpsnr, validate_libxpsnr, XPSNRSubMetric},
+    },
+    util::printable_base10_digits,
+    vapoursynth::{get_vapoursynth_plugins, measure_ssimulacra2},
+    Input,
+};
+
+/// Both videos are read at this frame rate by the FFmpeg based metrics, so
+/// that frames are compared by their index
+const FRAMERATE: f64 = 60.0;
+
+/// Colors of the encodes in the plots, repeated if there are more encodes
+const COLORS: [RGBColor; 5] = [BLUE, RED, GREEN, MAGENTA, CYAN];
+
+/// A metric that [`compare_encodes`] scores the encodes with
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    EnumString,
+    IntoStaticStr,
+    Display,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareMetric {
+    #[strum(serialize = "vmaf")]
+    VMAF,
+    #[strum(serialize = "ssimulacra2")]
+    SSIMULACRA2,
+    /// Minimum XPSNR of the three planes
+    #[strum(serialize = "xpsnr")]
+    XPSNR,
+}
+
+/// How [`compare_encodes`] scores the encodes
+#[derive(Debug, Clone)]
+pub struct CompareOptions {
+    pub metrics:    Vec<CompareMetric>,
+    /// Model of VMAF, FFmpeg's default if `None`
+    pub vmaf_model: Option<PathBuf>,
+    /// Resolution that VMAF and XPSNR compare the videos at, as
+    /// `widthxheight`
+    pub res:        String,
+    pub scaler:     String,
+    pub threads:    usize,
+}
+
+/// Scores of an encode with a single metric
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricScores {
+    pub mean:         f64,
+    pub median:       f64,
+    /// Score that 5% of the frames are worse than
+    pub percentile_5: f64,
+    pub minimum:      f64,
+    /// Score of every compared frame
+    pub frames:       Vec<f64>,
+}
+
+impl MetricScores {
+    /// Summarizes the frame `scores` of an encode
+    #[inline]
+    pub fn new(scores: Vec<f64>) -> Self {
+        let mut statistics = MetricStatistics::new(scores.clone());
+        Self {
+            mean:         statistics.mean(),
+            median:       statistics.median(),
+            percentile_5: statistics.percentile(5),
+            minimum:      statistics.minimum(),
+            frames:       scores,
+        }
+    }
+}
+
+/// A statistic of [`MetricScores`] with its label in the comparison table
+type Statistic = (&'static str, fn(&MetricScores) -> f64);
+
+/// An encode scored by [`compare_encodes`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncodeScores {
+    pub path:         PathBuf,
+    pub size:         u64,
+    pub bitrate_kbps: f64,
+    pub frames:       usize,
+    pub scores:       BTreeMap<CompareMetric, MetricScores>,
+}
+
+/// Result of [`compare_encodes`], which is written as JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    pub reference: PathBuf,
+    /// Number of frames of every encode that were compared, which is the
+    /// number of frames of the shortest encode
+    pub frames:    usize,
+    pub encodes:   Vec<EncodeScores>,
+}
+
+impl Comparison {
+    /// Returns the index of the encode with the highest mean score of
+    /// `metric`, if any encode was scored with it
+    #[inline]
+    pub fn best(&self, metric: CompareMetric) -> Option<usize> {
+        self.encodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, encode)| Some((index, encode.scores.get(&metric)?.mean)))
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Less))
+            .map(|(index, _)| index)
+    }
+
+    /// Returns the comparison as a table of the size and the scores of every
+    /// encode, marking the best mean score of each metric with `*`
+    #[inline]
+    pub fn table(&self) -> String {
+        let mut rows = vec![
+            (
+                "size (MB)".to_string(),
+                self.encodes
+                    .iter()
+                    .map(|encode| format!("{:.2}", encode.size as f64 / 1_000_000.0))
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                "bitrate (kbps)".to_string(),
+                self.encodes
+                    .iter()
+                    .map(|encode| format!("{:.0}", encode.bitrate_kbps))
+                    .collect(),
+            ),
+            (
+                "frames".to_string(),
+                self.encodes.iter().map(|encode| encode.frames.to_string()).collect(),
+            ),
+        ];
+
+        let metrics = self
+            .encodes
+            .iter()
+            .flat_map(|encode| encode.scores.keys().copied())
+            .collect::<BTreeSet<_>>();
+        let statistics: [Statistic; 4] = [
+            ("mean", |scores| scores.mean),
+            ("median", |scores| scores.median),
+            ("5%", |scores| scores.percentile_5),
+            ("minimum", |scores| scores.minimum),
+        ];
+        for metric in metrics {
+            let best = self.best(metric).filter(|_| self.encodes.len() > 1);
+            for (label, statistic) in statistics {
+                let cells = self
+                    .encodes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, encode)| {
+                        encode.scores.get(&metric).map_or_else(
+                            || "-".to_string(),
+                            |scores| {
+                                let marker = if label == "mean" && best == Some(index) {
+                                    "* "
+                                } else {
+                                    ""
+                                };
+                                format!("{marker}{:.3}", statistic(scores))
+                            },
+                        )
+                    })
+                    .collect();
+                rows.push((format!("{metric} {label}"), cells));
+            }
+        }
+
+        let mut table = format!("{:<24}", "");
+        for encode in &self.encodes {
+            let name = encode.path.file_name().unwrap_or_default().to_string_lossy();
+            let _ = write!(table, " {name:>24}");
+        }
+        table.push('\n');
+        for (label, cells) in rows {
+            let _ = write!(table, "{label:<24}");
+            for cell in cells {
+                let _ = write!(table, " {cell:>24}");
+            }
+            table.push('\n');
+        }
+        table
+    }
+
+    /// Writes the comparison as JSON to `path`
+    #[inline]
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write the comparison to {}", path.display()))
+    }
+
+    /// Plots the frame scores of `metric` of every encode to the SVG file
+    /// `path`
+    #[inline]
+    pub fn plot(&self, metric: CompareMetric, path: &Path) -> anyhow::Result<()> {
+        let series = self
+            .encodes
+            .iter()
+            .filter_map(|encode| Some((encode, &encode.scores.get(&metric)?.frames)))
+            .collect::<Vec<_>>();
+        if series.is_empty() {
+            bail!("no encode was scored with {metric}");
+        }
+        let (lowest, highest) = series
+            .iter()
+            .flat_map(|(_, scores)| scores.iter().copied())
+            .filter(|score| score.is_finite())
+            .fold(
+                (f64::INFINITY, f64::NEG_INFINITY),
+                |(lowest, highest), score| (lowest.min(score), highest.max(score)),
+            );
+        let (lowest, highest) = if lowest < highest {
+            (lowest.floor(), highest.ceil())
+        } else {
+            (0.0, 100.0)
+        };
+
+        let length = self.frames as u32;
+        let plot_width = 1600 + (printable_base10_digits(self.frames) * 200);
+        let root = SVGBackend::new(path.as_os_str(), (plot_width, 600)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption(metric.to_string(), ("sans-serif", 24))
+            .set_label_area_size(LabelAreaPosition::Bottom, (5).percent())
+            .set_label_area_size(LabelAreaPosition::Left, (5).percent())
+            .set_label_area_size(LabelAreaPosition::Right, (7).percent())
+            .set_label_area_size(LabelAreaPosition::Top, (5).percent())
+            .margin((1).percent())
+            .build_cartesian_2d(0_u32..length, lowest..highest)?;
+
+        chart.configure_mesh().draw()?;
+
+        for (index, (encode, scores)) in series.into_iter().enumerate() {
+            let color = COLORS[index % COLORS.len()];
+            let mean = encode.scores[&metric].mean;
+            chart
+                .draw_series(LineSeries::new(
+                    (0..).zip(scores.iter()).map(|(x, y)| (x, *y)),
+                    color,
+                ))?
+                .label(format!(
+                    "{} (mean {mean:.3})",
+                    encode.path.file_name().unwrap_or_default().to_string_lossy()
+                ))
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+
+        root.present()?;
+        Ok(())
+    }
+}
+
+/// Number of frames the reference has once it is converted from the
+/// `reference` to the `encoded` frame rate
+fn converted_frames(frames: usize, reference: Rational64, encoded: Rational64) -> usize {
+    if reference == encoded {
+        return frames;
+    }
+    (frames as f64 * (*encoded.numer() as f64 / *encoded.denom() as f64)
+        / (*reference.numer() as f64 / *reference.denom() as f64))
+        .round() as usize
+}
+
+/// Cuts every list of frame scores to the number of frames of the shortest
+/// encode, so that the encodes are compared over the same frames, and returns
+/// that number
+fn align_scores(encodes: &mut [EncodeScores]) -> usize {
+    let frames = encodes
+        .iter()
+        .flat_map(|encode| encode.scores.values().map(|scores| scores.frames.len()))
+        .min()
+        .unwrap_or(0);
+    for encode in encodes {
+        for scores in encode.scores.values_mut() {
+            if scores.frames.len() > frames {
+                scores.frames.truncate(frames);
+                *scores = MetricScores::new(std::mem::take(&mut scores.frames));
+            }
+        }
+    }
+    frames
+}
+
+/// Scores `encoded` against `reference` with `metric`, writing the statistics
+/// files of FFmpeg to `temp`
+fn score_encode(
+    encoded: &Path,
+    reference: &Input,
+    reference_pipe: &ReferencePipe,
+    frames: usize,
+    metric: CompareMetric,
+    options: &CompareOptions,
+    temp: &Path,
+) -> anyhow::Result<Vec<f64>> {
+    let stem = encoded.file_stem().unwrap_or_default().to_string_lossy();
+    match metric {
+        CompareMetric::VMAF => {
+            let stat_file = temp.join(format!("{stem}.vmaf.json"));
+            run_vmaf(
+                encoded,
+                reference_pipe,
+                &stat_file,
+                options.vmaf_model.as_ref(),
+                &options.res,
+                &options.scaler,
+                1,
+                None,
+                None,
+                options.threads,
+                false,
+                FRAMERATE,
+                false,
+                &[],
+            )?;
+            read_vmaf_file(&stat_file)
+        },
+        CompareMetric::XPSNR => {
+            let stat_file = temp.join(format!("{stem}.xpsnr.log"));
+            run_xpsnr(
+                encoded,
+                reference_pipe,
+                &stat_file,
+                &options.res,
+                &options.scaler,
+                1,
+                None,
+                FRAMERATE,
+            )?;
+            Ok(read_xpsnr_file(&stat_file, XPSNRSubMetric::Minimum)?.1)
+        },
+        CompareMetric::SSIMULACRA2 => {
+            // the plugins read the reference themselves, without its filters
+            ssimulacra2::usable_plugins(reference, get_vapoursynth_plugins().ok())
+                .filter(|_| !reference_pipe.is_filtered())
+                .map_or_else(
+                    || {
+                        ssimulacra2::measure_native(
+                            encoded,
+                            reference_pipe,
+                            &options.res,
+                            &options.scaler,
+                            1,
+                        )
+                    },
+                    |plugins| {
+                        measure_ssimulacra2(
+                            reference,
+                            encoded,
+                            (0, frames as u32),
+                            None,
+                            1,
+                            plugins,
+                        )
+                    },
+                )
+        },
+    }
+}
+
+/// Scores each of `encodes` against `reference` with the metrics of
+/// `options`.
+///
+/// The frames of the encodes are compared by their index. An encode with a
+/// different frame rate than the reference is compared against the reference
+/// converted to its frame rate, and encodes with a different number of
+/// frames are compared over the frames they all have.
+///
+/// # Errors
+///
+/// Returns an error if an encode could not be read or scored.
+#[inline]
+pub fn compare_encodes(
+    reference: &Input,
+    encodes: &[PathBuf],
+    options: &CompareOptions,
+    temp: &Path,
+) -> anyhow::Result<Comparison> {
+    if options.metrics.contains(&CompareMetric::VMAF) {
+        validate_libvmaf()?;
+    }
+    if options.metrics.contains(&CompareMetric::XPSNR) {
+        validate_libxpsnr()?;
+    }
+    fs::create_dir_all(temp)?;
+
+    let reference_info = reference.clip_info()?;
+    let mut scored = Vec::with_capacity(encodes.len());
+    for encoded in encodes {
+        let info = ffmpeg::get_clip_info(encoded)
+            .with_context(|| format!("Failed to read {}", encoded.display()))?;
+        let reference_frames = converted_frames(
+            reference_info.num_frames,
+            reference_info.frame_rate,
+            info.frame_rate,
+        );
+        if info.num_frames != reference_frames {
+            warn!(
+                "{} has {} frames, but the reference has {reference_frames}",
+                encoded.display(),
+                info.num_frames
+            );
+        }
+        let mut reference_pipe = ReferencePipe::from_input(reference);
+        if info.frame_rate != reference_info.frame_rate {
+            info!(
+                "converting the reference from {} to {} fps to compare it to {}",
+                reference_info.frame_rate,
+                info.frame_rate,
+                encoded.display()
+            );
+            reference_pipe = reference_pipe.ffmpeg_filters(
+                vec!["-vf".to_string(), format!("fps={}", info.frame_rate)],
+                FFPixelFormat::YUV420P10LE,
+            );
+        }
+
+        let mut scores = BTreeMap::new();
+        for &metric in &options.metrics {
+            println!(":: {metric} {}", encoded.display());
+            let frames = score_encode(
+                encoded,
+                reference,
+                &reference_pipe,
+                info.num_frames.min(reference_frames),
+                metric,
+                options,
+                temp,
+            )
+            .with_context(|| format!("Failed to score {} with {metric}", encoded.display()))?;
+            if frames.is_empty() {
+                bail!("{metric} scored no frames of {}", encoded.display());
+            }
+            scores.insert(metric, MetricScores::new(frames));
+        }
+
+        let size = fs::metadata(encoded)?.len();
+        let seconds = info.num_frames as f64 * *info.frame_rate.denom() as f64
+            / *info.frame_rate.numer() as f64;
+        scored.push(EncodeScores {
+            path: encoded.clone(),
+            size,
+            bitrate_kbps: size as f64 * 8.0 / 1000.0 / seconds.max(f64::EPSILON),
+            frames: info.num_frames,
+            scores,
+        });
+    }
+
+    let frames = align_scores(&mut scored);
+    Ok(Comparison {
+        reference: reference.as_path().to_path_buf(),
+        frames,
+        encodes: scored,
+    })
+}
@@ -0,0 +1,63 @@
+use super::*;
+
+fn encode(name: &str, vmaf: Vec<f64>) -> EncodeScores {
+    EncodeScores {
+        path:         PathBuf::from(name),
+        size:         1_000_000,
+        bitrate_kbps: 800.0,
+        frames:       vmaf.len(),
+        scores:       BTreeMap::from([(CompareMetric::VMAF, MetricScores::new(vmaf))]),
+    }
+}
+
+#[test]
+fn scores_are_aligned_to_the_shortest_encode() {
+    let mut encodes = vec![
+        encode("a.mkv", vec![90.0, 92.0, 94.0, 10.0]),
+        encode("b.mkv", vec![80.0, 82.0, 84.0]),
+    ];
+    assert_eq!(align_scores(&mut encodes), 3);
+    let scores = &encodes[0].scores[&CompareMetric::VMAF];
+    assert_eq!(scores.frames, vec![90.0, 92.0, 94.0]);
+    assert!((scores.mean - 92.0).abs() < f64::EPSILON);
+    assert!((scores.minimum - 90.0).abs() < f64::EPSILON);
+}
+
+#[test]
+fn best_encode_is_marked_in_the_table() {
+    let comparison = Comparison {
+        reference: PathBuf::from("source.mkv"),
+        frames:    2,
+        encodes:   vec![encode("a.mkv", vec![90.0, 92.0]), encode("b.mkv", vec![95.0, 97.0])],
+    };
+    assert_eq!(comparison.best(CompareMetric::VMAF), Some(1));
+    assert_eq!(comparison.best(CompareMetric::XPSNR), None);
+
+    let table = comparison.table();
+    let mean = table
+        .lines()
+        .find(|line| line.starts_with("vmaf mean"))
+        .expect("row should exist");
+    assert!(mean.ends_with("* 96.000"));
+    assert!(!mean.contains("* 91.000"));
+}
+
+#[test]
+fn reference_frames_follow_the_encoded_frame_rate() {
+    assert_eq!(
+        converted_frames(240, Rational64::new(24, 1), Rational64::new(24, 1)),
+        240
+    );
+    assert_eq!(
+        converted_frames(240, Rational64::new(24, 1), Rational64::new(48, 1)),
+        480
+    );
+    assert_eq!(
+        converted_frames(
+            1001,
+            Rational64::new(30000, 1001),
+            Rational64::new(24000, 1001)
+        ),
+        801
+    );
+}
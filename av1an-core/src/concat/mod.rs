@@ -2,15 +2,18 @@
 mod tests;
 
 use std::{
+    collections::HashMap,
     fmt::{Display, Write as FmtWrite},
-    fs::{self, DirEntry, File},
+    fs::{self, File},
     io::Write,
+    ops::Range,
     path::{Path, PathBuf},
     process::{Command, Stdio},
+    str::FromStr,
     sync::Arc,
 };
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
 use av_format::{
     buffer::AccReader,
     demuxer::{Context as DemuxerContext, Event},
@@ -22,7 +25,7 @@ use path_abs::{PathAbs, PathInfo};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, trace, warn};
 
-use crate::{encoder::Encoder, util::read_in_dir};
+use crate::{encoder::Encoder, ffmpeg::FFPixelFormat};
 
 #[derive(
     PartialEq,
@@ -51,6 +54,42 @@ impl Display for ConcatMethod {
     }
 }
 
+/// Tags written to the output when concatenating, so that it does not need a
+/// separate tagging pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputTags {
+    /// Title of the output file
+    pub title:            Option<String>,
+    /// Language of the video track, as an ISO 639-2 code or a BCP 47 tag
+    pub language:         Option<String>,
+    /// The encoder and its parameters, written as `ENCODER` and
+    /// `ENCODER_SETTINGS` tags of the video track
+    pub encoder_settings: Option<(String, String)>,
+}
+
+impl OutputTags {
+    /// Matroska tags file with the encoder settings of the video track
+    fn tags_xml(&self) -> Option<String> {
+        fn escape(value: &str) -> String {
+            value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        }
+
+        let (encoder, settings) = self.encoder_settings.as_ref()?;
+        Some(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Tags>\n  <Tag>\n    <Targets />\n    \
+             <Simple>\n      <Name>ENCODER</Name>\n      <String>{}</String>\n    </Simple>\n    \
+             <Simple>\n      <Name>ENCODER_SETTINGS</Name>\n      <String>{}</String>\n    \
+             </Simple>\n  </Tag>\n</Tags>\n",
+            escape(encoder),
+            escape(settings)
+        ))
+    }
+}
+
 #[tracing::instrument(level = "debug")]
 pub fn sort_files_by_filename(files: &mut [PathBuf]) {
     files.sort_unstable_by_key(|x| {
@@ -64,12 +103,102 @@ pub fn sort_files_by_filename(files: &mut [PathBuf]) {
     });
 }
 
-#[tracing::instrument(level = "debug")]
-pub fn ivf(input: &Path, out: &Path) -> anyhow::Result<()> {
-    let mut files: Vec<PathBuf> = read_in_dir(input)?.collect();
+/// Returns whether the AV1 temporal unit `data` holds a key frame, by reading
+/// the frame type from the header of its first frame
+fn av1_is_keyframe(data: &[u8]) -> bool {
+    const OBU_FRAME_HEADER: u8 = 3;
+    const OBU_FRAME: u8 = 6;
+
+    let mut rest = data;
+    while let Some((&header, payload)) = rest.split_first() {
+        let obu_type = (header >> 3) & 0xf;
+        let has_extension = header & 0b100 != 0;
+        let has_size = header & 0b10 != 0;
+        let Some(payload) = payload.get(usize::from(has_extension)..) else {
+            return false;
+        };
+        let (size, size_len) = if has_size {
+            let mut size = 0;
+            let mut len = 0;
+            loop {
+                let Some(&byte) = payload.get(len) else {
+                    return false;
+                };
+                size |= usize::from(byte & 0x7f) << (7 * len);
+                len += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                if len == 8 {
+                    return false;
+                }
+            }
+            (size, len)
+        } else {
+            (payload.len(), 0)
+        };
+        if matches!(obu_type, OBU_FRAME_HEADER | OBU_FRAME) {
+            // show_existing_frame, then frame_type, which is 0 for key frames
+            return payload.get(size_len).is_some_and(|&byte| byte & 0xe0 == 0);
+        }
+        let Some(next) = payload.get(size_len + size..) else {
+            return false;
+        };
+        rest = next;
+    }
+    false
+}
 
-    sort_files_by_filename(&mut files);
+/// Returns whether the VP9 frame `data` is a key frame, by reading the frame
+/// type from its uncompressed header
+fn vp9_is_keyframe(data: &[u8]) -> bool {
+    let Some(&byte) = data.first() else {
+        return false;
+    };
+    if byte >> 6 != 0b10 {
+        return false;
+    }
+    let profile = ((byte >> 5) & 1) | (((byte >> 4) & 1) << 1);
+    // profile 3 has a reserved bit before show_existing_frame
+    let show_existing_frame = if profile == 3 { 2 } else { 3 };
+    byte & (1 << show_existing_frame) == 0 && byte & (1 << (show_existing_frame - 1)) == 0
+}
+
+/// Returns the range of the `frames` of a chunk to keep when it has more than
+/// the `expected` number of frames, which some encoders produce at scene
+/// changes. Leading frames are dropped while they repeat `previous`, the last
+/// frame of the chunk before, or are a key frame followed by another one.
+/// Trailing frames are dropped while they repeat the frame before them.
+fn kept_frames(
+    frames: &[&[u8]],
+    expected: usize,
+    previous: Option<&[u8]>,
+    is_keyframe: impl Fn(&[u8]) -> bool,
+) -> Range<usize> {
+    let mut kept = 0..frames.len();
+    while kept.len() > expected && kept.len() > 1 {
+        let first = frames[kept.start];
+        if previous == Some(first) || (is_keyframe(first) && is_keyframe(frames[kept.start + 1])) {
+            kept.start += 1;
+        } else if frames[kept.end - 1] == frames[kept.end - 2] {
+            kept.end -= 1;
+        } else {
+            break;
+        }
+    }
+    kept
+}
 
+/// Concatenates the IVF chunks `files` into `out`. Chunks with more frames
+/// than their number in `expected_frames` lose the duplicated frames at their
+/// start or end (see [`kept_frames`]).
+#[tracing::instrument(level = "debug", skip(files, expected_frames))]
+pub fn ivf(
+    files: &[PathBuf],
+    out: &Path,
+    encoder: Encoder,
+    expected_frames: &HashMap<PathBuf, usize>,
+) -> anyhow::Result<()> {
     assert!(!files.is_empty());
 
     let output = File::create(out)?;
@@ -102,9 +231,14 @@ pub fn ivf(input: &Path, out: &Path) -> anyhow::Result<()> {
     muxer.configure()?;
     muxer.write_header()?;
 
+    let is_keyframe = if encoder == Encoder::vpx {
+        vp9_is_keyframe
+    } else {
+        av1_is_keyframe
+    };
     let mut pos_offset: usize = 0;
-    for file in &files {
-        let mut last_pos: usize = 0;
+    let mut previous: Option<Vec<u8>> = None;
+    for file in files {
         let input = std::fs::File::open(file)?;
 
         let acc = AccReader::new(input);
@@ -114,19 +248,15 @@ pub fn ivf(input: &Path, out: &Path) -> anyhow::Result<()> {
 
         trace!("global info: {:#?}", demuxer.info);
 
+        let mut packets = Vec::new();
         loop {
             match demuxer.read_event() {
                 Ok(event) => match event {
                     Event::MoreDataNeeded(sz) => panic!("needed more data: {sz} bytes"),
                     Event::NewStream(s) => panic!("new stream: {s:?}"),
-                    Event::NewPacket(mut packet) => {
-                        if let Some(p) = packet.pos.as_mut() {
-                            last_pos = *p;
-                            *p += pos_offset;
-                        }
-
+                    Event::NewPacket(packet) => {
                         trace!("received packet with pos: {:?}", packet.pos);
-                        muxer.write_packet(Arc::new(packet))?;
+                        packets.push(packet);
                     },
                     Event::Continue => {
                         // do nothing
@@ -143,7 +273,39 @@ pub fn ivf(input: &Path, out: &Path) -> anyhow::Result<()> {
                 },
             }
         }
-        pos_offset += last_pos + 1;
+
+        if let Some(&expected) = expected_frames.get(file)
+            && packets.len() > expected
+        {
+            let frames = packets.iter().map(|packet| packet.data.as_slice()).collect::<Vec<_>>();
+            let kept = kept_frames(&frames, expected, previous.as_deref(), is_keyframe);
+            let dropped = packets.len() - kept.len();
+            if dropped > 0 {
+                warn!(
+                    "dropped {dropped} duplicated frame(s) at the start or end of {}",
+                    file.display()
+                );
+            }
+            if kept.len() != expected {
+                warn!(
+                    "{} has {} frames instead of {expected}",
+                    file.display(),
+                    kept.len()
+                );
+            }
+            packets.truncate(kept.end);
+            packets.drain(..kept.start);
+        }
+        previous = packets.last().map(|packet| packet.data.clone());
+
+        let frames = packets.len();
+        for (index, mut packet) in packets.into_iter().enumerate() {
+            if let Some(p) = packet.pos.as_mut() {
+                *p = pos_offset + index;
+            }
+            muxer.write_packet(Arc::new(packet))?;
+        }
+        pos_offset += frames;
     }
 
     muxer.write_trailer()?;
@@ -151,25 +313,254 @@ pub fn ivf(input: &Path, out: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Codec parameters of an encoded chunk, as reported by ffprobe
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChunkParams {
+    pub codec_name:      String,
+    pub width:           u32,
+    pub height:          u32,
+    pub pix_fmt:         Option<String>,
+    pub color_range:     Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_transfer:  Option<String>,
+    pub color_space:     Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChunkProbe {
+    streams: Vec<ChunkParams>,
+}
+
+impl ChunkParams {
+    fn bit_depth(&self) -> Option<usize> {
+        self.pix_fmt
+            .as_deref()
+            .and_then(|pix_fmt| FFPixelFormat::from_str(pix_fmt).ok())
+            .map(|format| format.get_format_bit_depth_usize())
+    }
+}
+
+/// Read the codec parameters of the first video stream of an encoded chunk
 #[tracing::instrument(level = "debug")]
-fn read_encoded_chunks(encode_dir: &Path) -> anyhow::Result<Vec<DirEntry>> {
-    Ok(fs::read_dir(encode_dir)
-        .with_context(|| {
-            format!(
-                "Failed to read encoded chunks from {}",
-                encode_dir.display()
-            )
-        })?
-        .collect::<Result<Vec<_>, _>>()?)
+pub fn probe_chunk(chunk: &Path) -> anyhow::Result<ChunkParams> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0", "-print_format", "json"])
+        .args([
+            "-show_entries",
+            "stream=codec_name,width,height,pix_fmt,color_range,color_primaries,color_transfer,\
+             color_space",
+        ])
+        .arg(chunk)
+        .output()
+        .with_context(|| format!("Failed to run ffprobe on {}", chunk.display()))?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed on {}: {}",
+            chunk.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let probe: ChunkProbe = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("Failed to parse ffprobe output for {}", chunk.display()))?;
+    probe
+        .streams
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("{} has no video stream", chunk.display()))
+}
+
+/// ffprobe codec names that `encoder` can produce
+const fn expected_codecs(encoder: Encoder) -> &'static [&'static str] {
+    match encoder {
+        Encoder::aom | Encoder::rav1e | Encoder::svt_av1 | Encoder::av1_nvenc => &["av1"],
+        Encoder::vpx => &["vp8", "vp9"],
+        Encoder::x264 => &["h264"],
+        Encoder::x265 | Encoder::hevc_nvenc => &["hevc"],
+    }
+}
+
+/// Compare the parameters of a chunk against what the encoder is expected to
+/// produce and against a reference chunk, returning a description of each
+/// mismatch
+#[inline]
+pub fn chunk_param_mismatches(
+    params: &ChunkParams,
+    reference: &ChunkParams,
+    encoder: Encoder,
+    bit_depth: usize,
+) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    if !expected_codecs(encoder).contains(&params.codec_name.as_str()) {
+        mismatches.push(format!(
+            "codec is {}, expected {} output",
+            params.codec_name,
+            encoder.format()
+        ));
+    }
+    if let Some(depth) = params.bit_depth()
+        && depth != bit_depth
+    {
+        mismatches.push(format!("bit depth is {depth}, expected {bit_depth}"));
+    }
+    if (params.width, params.height) != (reference.width, reference.height) {
+        mismatches.push(format!(
+            "resolution is {}x{}, other chunks are {}x{}",
+            params.width, params.height, reference.width, reference.height
+        ));
+    }
+
+    let fields = [
+        ("pixel format", &params.pix_fmt, &reference.pix_fmt),
+        ("color range", &params.color_range, &reference.color_range),
+        (
+            "color primaries",
+            &params.color_primaries,
+            &reference.color_primaries,
+        ),
+        (
+            "transfer characteristics",
+            &params.color_transfer,
+            &reference.color_transfer,
+        ),
+        (
+            "matrix coefficients",
+            &params.color_space,
+            &reference.color_space,
+        ),
+    ];
+    for (name, value, expected) in fields {
+        if value != expected {
+            mismatches.push(format!(
+                "{name} is {}, other chunks are {}",
+                value.as_deref().unwrap_or("unset"),
+                expected.as_deref().unwrap_or("unset")
+            ));
+        }
+    }
+
+    mismatches
+}
+
+/// Probe every encoded chunk and make sure they all share the same codec
+/// parameters, which also match the output of the encoder each chunk was
+/// encoded with.
+///
+/// Concatenating chunks with mixed parameters usually succeeds, but produces
+/// an output that does not play back correctly.
+#[tracing::instrument(level = "debug", skip(files))]
+pub fn verify_chunks(files: &[(PathBuf, Encoder)], bit_depth: usize) -> anyhow::Result<()> {
+    let params = files
+        .iter()
+        .map(|(file, _)| probe_chunk(file))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let Some(reference) = params.first() else {
+        bail!("No encoded chunks found");
+    };
+
+    let mut report = String::new();
+    for ((file, encoder), chunk_params) in files.iter().zip(&params) {
+        for mismatch in chunk_param_mismatches(chunk_params, reference, *encoder, bit_depth) {
+            writeln!(
+                report,
+                "  {}: {mismatch}",
+                file.file_name().unwrap_or_default().to_string_lossy()
+            )?;
+        }
+    }
+    if !report.is_empty() {
+        bail!(
+            "Encoded chunks have inconsistent codec parameters, concatenating them would produce \
+             a broken output. Check the encoder parameters of your zones.\n{report}"
+        );
+    }
+
+    debug!("verified codec parameters of {} chunks", files.len());
+    Ok(())
+}
+
+/// How thoroughly every chunk is checked right after it is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkCheck {
+    /// Check the codec parameters, and parse every packet without decoding
+    Quick,
+    /// Also decode every frame
+    Strict,
 }
 
+/// Returns the command that decodes every frame of `chunk`, using dav1d for
+/// AV1 if it is installed, as it reports more bitstream errors than FFmpeg
+fn decode_command(chunk: &Path, codec_name: &str, dav1d: bool) -> Command {
+    if codec_name == "av1" && dav1d {
+        let mut command = Command::new("dav1d");
+        command.args(["--quiet", "--muxer", "null", "-i"]).arg(chunk);
+        command
+    } else {
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-v", "error", "-xerror", "-i"])
+            .arg(chunk)
+            .args(["-map", "0:v:0", "-f", "null", "-"]);
+        command
+    }
+}
+
+/// Check an encoded chunk for corruption, so that it can be encoded again
+/// immediately instead of producing an output that does not play back.
+///
+/// The codec and bit depth in the headers must match `encoder` and
+/// `bit_depth`, and every packet must parse, or with [`ChunkCheck::Strict`]
+/// decode, without errors.
 #[tracing::instrument(level = "debug")]
+pub fn check_chunk(
+    chunk: &Path,
+    encoder: Encoder,
+    bit_depth: usize,
+    check: ChunkCheck,
+) -> anyhow::Result<()> {
+    let params = probe_chunk(chunk)?;
+    let mut mismatches = chunk_param_mismatches(&params, &params, encoder, bit_depth);
+    if params.width == 0 || params.height == 0 {
+        mismatches.push(format!("resolution is {}x{}", params.width, params.height));
+    }
+    if !mismatches.is_empty() {
+        bail!("{}: {}", chunk.display(), mismatches.join(", "));
+    }
+
+    let mut command = match check {
+        ChunkCheck::Quick => {
+            let mut command = Command::new("ffmpeg");
+            command
+                .args(["-v", "error", "-i"])
+                .arg(chunk)
+                .args(["-map", "0:v:0", "-c", "copy", "-f", "null", "-"]);
+            command
+        },
+        ChunkCheck::Strict => {
+            decode_command(chunk, &params.codec_name, which::which("dav1d").is_ok())
+        },
+    };
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to check {}", chunk.display()))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !stderr.trim().is_empty() {
+        bail!("{} is corrupt: {}", chunk.display(), stderr.trim());
+    }
+
+    Ok(())
+}
+
+/// Concatenates the `chunks` in the `encode` directory of `temp_dir`
+#[tracing::instrument(level = "debug", skip(chunks))]
 pub fn mkvmerge(
     temp_dir: &Path,
+    chunks: &[PathBuf],
     output: &Path,
-    encoder: Encoder,
-    num_chunks: usize,
     output_fps: Option<Rational64>,
+    tags: &OutputTags,
 ) -> anyhow::Result<()> {
     #[cfg(windows)]
     const MAXIMUM_CHUNKS_PER_MERGE: usize = usize::MAX;
@@ -204,27 +595,35 @@ pub fn mkvmerge(
 
     let output = PathAbs::new(output)?;
 
-    assert!(num_chunks != 0);
-
-    let num_chunk_groups = (num_chunks as f64 / MAXIMUM_CHUNKS_PER_MERGE as f64).ceil() as usize;
-    let chunk_groups: Vec<Vec<String>> = (0..num_chunk_groups)
-        .map(|group_index| {
-            let start = group_index * MAXIMUM_CHUNKS_PER_MERGE;
-            let end = (start + MAXIMUM_CHUNKS_PER_MERGE).min(num_chunks);
-            (start..end)
-                .map(|i| {
-                    format!(
-                        "{i:05}.{ext}",
-                        ext = match encoder {
-                            Encoder::x264 => "264",
-                            Encoder::x265 => "hevc",
-                            _ => "ivf",
-                        }
-                    )
+    let tags_file = tags
+        .tags_xml()
+        .map(|xml| -> anyhow::Result<String> {
+            let tags_file = PathAbs::new(PathBuf::from(&temp_dir).join("tags.xml"))?;
+            fs::write(&tags_file, xml).context("Failed to write the output tags")?;
+            Ok(fix_path(tags_file))
+        })
+        .transpose()?;
+
+    assert!(!chunks.is_empty());
+
+    // mkvmerge runs in the encode directory, so the chunks are passed relative
+    // to it, separated by `/` as it is also the separator on Windows
+    let chunk_groups: Vec<Vec<String>> = chunks
+        .chunks(MAXIMUM_CHUNKS_PER_MERGE)
+        .map(|group| {
+            group
+                .iter()
+                .map(|chunk| {
+                    chunk
+                        .strip_prefix(&encode_dir)
+                        .unwrap_or(chunk)
+                        .to_string_lossy()
+                        .replace('\\', "/")
                 })
                 .collect()
         })
         .collect();
+    let num_chunk_groups = chunk_groups.len();
 
     // If there is only one chunk group, we can skip the intermediate merge/file
     // creation
@@ -235,6 +634,8 @@ pub fn mkvmerge(
             &fix_path(output.to_string_lossy().as_ref()),
             audio_file.as_deref(),
             output_fps,
+            tags,
+            tags_file.as_deref(),
         );
 
         let mut options_json = File::create(options_path)?;
@@ -271,6 +672,8 @@ pub fn mkvmerge(
             &fix_path(group_options_output_path.to_string_lossy().as_ref()),
             None,
             output_fps,
+            &OutputTags::default(),
+            None,
         );
 
         let mut group_options_json = File::create(group_options_path)?;
@@ -304,6 +707,8 @@ pub fn mkvmerge(
         &fix_path(output.to_string_lossy().as_ref()),
         audio_file.as_deref(),
         output_fps,
+        tags,
+        tags_file.as_deref(),
     );
 
     let mut options_json = File::create(options_path)?;
@@ -337,6 +742,8 @@ pub fn mkvmerge_options_json(
     output: &str,
     audio: Option<&str>,
     output_fps: Option<Rational64>,
+    tags: &OutputTags,
+    tags_file: Option<&str>,
 ) -> anyhow::Result<String> {
     let mut file_string = String::with_capacity(
         64 + output.len()
@@ -344,19 +751,33 @@ pub fn mkvmerge_options_json(
             + chunks.iter().map(|s| s.len() + 4).sum::<usize>(),
     );
     write!(file_string, "[\"-o\", {output:?}")?;
+    if let Some(title) = &tags.title {
+        write!(file_string, ", \"--title\", {title:?}")?;
+    }
     if let Some(audio) = audio {
         write!(file_string, ", {audio:?}")?;
     }
     if let Some(output_fps) = output_fps {
         write!(
             file_string,
-            ", \"--default-duration\", \"0:{}/{}fps\", \"[\"",
+            ", \"--default-duration\", \"0:{}/{}fps\"",
             output_fps.numer(),
             output_fps.denom()
         )?;
-    } else {
-        file_string.push_str(", \"[\"");
     }
+    // Track options before the opening bracket apply to the video track of the
+    // first chunk, which the other chunks are appended to
+    if let Some(language) = &tags.language {
+        write!(
+            file_string,
+            ", \"--language\", {:?}",
+            format!("0:{language}")
+        )?;
+    }
+    if let Some(tags_file) = tags_file {
+        write!(file_string, ", \"--tags\", {:?}", format!("0:{tags_file}"))?;
+    }
+    file_string.push_str(", \"[\"");
     for chunk in chunks {
         write!(file_string, ", \"{chunk}\"")?;
     }
@@ -365,25 +786,47 @@ pub fn mkvmerge_options_json(
     Ok(file_string)
 }
 
-/// Concatenates using ffmpeg (does not work with x265, and may have incorrect
-/// FPS with vpx)
-#[tracing::instrument(level = "debug")]
-pub fn ffmpeg(temp: &Path, output: &Path) -> anyhow::Result<()> {
-    fn write_concat_file(temp_folder: &Path) -> anyhow::Result<()> {
-        let concat_file = temp_folder.join("concat");
-        let encode_folder = temp_folder.join("encode");
-
-        let mut files = read_encoded_chunks(&encode_folder)?;
+/// FFmpeg arguments that write the output tags. The video is the first stream
+/// of the output.
+fn ffmpeg_metadata_args(tags: &OutputTags) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(title) = &tags.title {
+        args.extend(["-metadata".to_string(), format!("title={title}")]);
+    }
+    if let Some(language) = &tags.language {
+        args.extend(["-metadata:s:0".to_string(), format!("language={language}")]);
+    }
+    if let Some((encoder, settings)) = &tags.encoder_settings {
+        args.extend([
+            "-metadata:s:0".to_string(),
+            format!("ENCODER={encoder}"),
+            "-metadata:s:0".to_string(),
+            format!("ENCODER_SETTINGS={settings}"),
+        ]);
+    }
+    args
+}
 
-        files.sort_by_key(DirEntry::path);
+/// Concatenates the `chunks` using ffmpeg (does not work with x265, and may
+/// have incorrect FPS with vpx)
+#[tracing::instrument(level = "debug", skip(chunks))]
+pub fn ffmpeg(
+    temp: &Path,
+    chunks: &[PathBuf],
+    output: &Path,
+    tags: &OutputTags,
+) -> anyhow::Result<()> {
+    fn write_concat_file(temp_folder: &Path, chunks: &[PathBuf]) -> anyhow::Result<()> {
+        let concat_file = temp_folder.join("concat");
 
-        let mut contents = String::with_capacity(24 * files.len());
+        let mut contents = String::with_capacity(24 * chunks.len());
 
-        for i in files {
+        for chunk in chunks {
+            let chunk = PathAbs::new(chunk)?;
             writeln!(
                 contents,
                 "file {}",
-                format!("{path}", path = i.path().display())
+                format!("{path}", path = chunk.as_path().display())
                     .replace('\\', r"\\")
                     .replace(' ', r"\ ")
                     .replace('\'', r"\'")
@@ -402,7 +845,7 @@ pub fn ffmpeg(temp: &Path, output: &Path) -> anyhow::Result<()> {
     let concat = temp.join("concat");
     let concat_file = concat.to_string_lossy();
 
-    write_concat_file(temp)?;
+    write_concat_file(temp, chunks)?;
 
     let audio_file = {
         let file = temp.join("audio.mkv");
@@ -431,6 +874,7 @@ pub fn ffmpeg(temp: &Path, output: &Path) -> anyhow::Result<()> {
         ])
         .arg(file)
         .args(["-map", "0", "-map", "1", "-c", "copy"])
+        .args(ffmpeg_metadata_args(tags))
         .arg(output);
     } else {
         cmd.args([
@@ -446,6 +890,7 @@ pub fn ffmpeg(temp: &Path, output: &Path) -> anyhow::Result<()> {
             &concat_file,
         ])
         .args(["-map", "0", "-c", "copy"])
+        .args(ffmpeg_metadata_args(tags))
         .arg(output);
     }
 
@@ -465,3 +910,97 @@ pub fn ffmpeg(temp: &Path, output: &Path) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Muxes the video stream of `input` with the audio encoded into `temp`,
+/// copying both, for `--copy-video`
+pub fn copy_video(
+    input: &Path,
+    temp: &Path,
+    output: &Path,
+    tags: &OutputTags,
+) -> anyhow::Result<()> {
+    let audio_file = temp.join("audio.mkv");
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-y", "-hide_banner", "-loglevel", "error", "-i"]).arg(input);
+    if audio_file.exists() {
+        cmd.arg("-i").arg(&audio_file).args(["-map", "0:v", "-map", "1"]);
+    } else {
+        cmd.args(["-map", "0:v"]);
+    }
+    cmd.args(["-c", "copy"]).args(ffmpeg_metadata_args(tags)).arg(output);
+    debug!("FFmpeg copy command: {:?}", cmd);
+
+    let out = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .context("Failed to execute FFmpeg command to copy the video")?;
+    if !out.status.success() {
+        bail!(
+            "FFmpeg failed to copy the video stream of {}: {}",
+            input.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// FFmpeg arguments that move the seek index of the container of `output` to
+/// the start of the file, or `None` if the container has no index that can be
+/// moved
+fn index_to_front_args(output: &Path) -> Option<&'static [&'static str]> {
+    let extension = output.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "mkv" | "mka" | "webm" => Some(&["-cues_to_front", "1"]),
+        "mp4" | "m4v" | "mov" => Some(&["-movflags", "+faststart"]),
+        _ => None,
+    }
+}
+
+/// Whether [`optimize_mux`] can move the seek index of `output` to the front
+#[inline]
+pub fn can_optimize_mux(output: &Path) -> bool {
+    index_to_front_args(output).is_some()
+}
+
+/// Remuxes the concatenated `output` with its seek index (the cues of
+/// Matroska, the moov atom of MP4) at the start of the file, so that players
+/// reading it over a network share can start and seek without first reading
+/// the end of the file. mkvmerge always writes the cues after the clusters.
+///
+/// The streams are copied to a file next to `output`, which then replaces it.
+#[tracing::instrument(level = "debug")]
+pub fn optimize_mux(output: &Path) -> anyhow::Result<()> {
+    let index_args = index_to_front_args(output)
+        .with_context(|| format!("{} has no seek index to move", output.display()))?;
+    let extension = output.extension().unwrap_or_default().to_string_lossy();
+    let optimized = output.with_extension(format!("optimized.{extension}"));
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-y", "-hide_banner", "-loglevel", "error", "-i"])
+        .arg(output)
+        .args(["-map", "0", "-c", "copy"])
+        .args(index_args)
+        .arg(&optimized);
+    debug!("FFmpeg mux optimization command: {:?}", cmd);
+
+    let out = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .context("Failed to execute FFmpeg command to optimize the output")?;
+    if !out.status.success() {
+        let _ = fs::remove_file(&optimized);
+        bail!(
+            "FFmpeg failed to move the seek index of {} to the front: {}",
+            output.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+
+    fs::rename(&optimized, output)
+        .with_context(|| format!("Failed to replace {}", output.display()))?;
+    Ok(())
+}
@@ -9,6 +9,8 @@ fn mkvmerge_options_json_no_audio() {
         "output.mkv",
         None,
         Some(Rational64::new(30, 1)),
+        &OutputTags::default(),
+        None,
     )
     .expect("options call should succeed");
     assert_eq!(
@@ -24,6 +26,8 @@ fn mkvmerge_options_json_with_audio() {
         "output.mkv",
         Some("audio.mkv"),
         Some(Rational64::new(30, 1)),
+        &OutputTags::default(),
+        None,
     )
     .expect("options call should succeed");
     assert_eq!(
@@ -31,3 +35,155 @@ fn mkvmerge_options_json_with_audio() {
         r#"["-o", "output.mkv", "audio.mkv", "--default-duration", "0:30/1fps", "[", "00000.ivf", "00001.ivf","]"]"#
     );
 }
+
+#[test]
+fn mkvmerge_options_json_with_tags() {
+    let tags = OutputTags {
+        title:            Some("My \"Movie\"".to_string()),
+        language:         Some("jpn".to_string()),
+        encoder_settings: Some(("svt-av1".to_string(), "--crf 30".to_string())),
+    };
+    let result = mkvmerge_options_json(
+        &["00000.ivf".to_string(), "00001.ivf".to_string()],
+        "output.mkv",
+        Some("audio.mkv"),
+        None,
+        &tags,
+        Some("/tmp/tags.xml"),
+    )
+    .expect("options call should succeed");
+    assert_eq!(
+        result,
+        r#"["-o", "output.mkv", "--title", "My \"Movie\"", "audio.mkv", "--language", "0:jpn", "--tags", "0:/tmp/tags.xml", "[", "00000.ivf", "00001.ivf","]"]"#
+    );
+}
+
+#[test]
+fn tags_xml_escapes_settings() {
+    assert_eq!(OutputTags::default().tags_xml(), None);
+
+    let tags = OutputTags {
+        encoder_settings: Some((
+            "aom".to_string(),
+            "--tune-content=<default> & more".to_string(),
+        )),
+        ..OutputTags::default()
+    };
+    let xml = tags.tags_xml().expect("tags should have encoder settings");
+    assert!(xml.contains("<Name>ENCODER</Name>\n      <String>aom</String>"));
+    assert!(xml.contains("<String>--tune-content=&lt;default&gt; &amp; more</String>"));
+}
+
+#[test]
+fn ffmpeg_metadata_args_for_tags() {
+    assert!(ffmpeg_metadata_args(&OutputTags::default()).is_empty());
+
+    let tags = OutputTags {
+        title:            Some("Title".to_string()),
+        language:         Some("en".to_string()),
+        encoder_settings: None,
+    };
+    assert_eq!(ffmpeg_metadata_args(&tags), vec![
+        "-metadata",
+        "title=Title",
+        "-metadata:s:0",
+        "language=en",
+    ]);
+}
+
+fn chunk_params() -> ChunkParams {
+    ChunkParams {
+        codec_name:      "av1".to_string(),
+        width:           1920,
+        height:          1080,
+        pix_fmt:         Some("yuv420p10le".to_string()),
+        color_range:     Some("tv".to_string()),
+        color_primaries: Some("bt709".to_string()),
+        color_transfer:  Some("bt709".to_string()),
+        color_space:     Some("bt709".to_string()),
+    }
+}
+
+#[test]
+fn chunk_param_mismatches_consistent() {
+    let params = chunk_params();
+    assert!(chunk_param_mismatches(&params, &params, Encoder::svt_av1, 10).is_empty());
+}
+
+#[test]
+fn chunk_param_mismatches_reports_each_difference() {
+    let reference = chunk_params();
+    let params = ChunkParams {
+        width: 1280,
+        height: 720,
+        pix_fmt: Some("yuv420p".to_string()),
+        color_primaries: None,
+        ..chunk_params()
+    };
+
+    let mismatches = chunk_param_mismatches(&params, &reference, Encoder::aom, 10);
+    assert_eq!(mismatches, vec![
+        "bit depth is 8, expected 10",
+        "resolution is 1280x720, other chunks are 1920x1080",
+        "pixel format is yuv420p, other chunks are yuv420p10le",
+        "color primaries is unset, other chunks are bt709",
+    ]);
+
+    let mismatches = chunk_param_mismatches(&reference, &reference, Encoder::x265, 10);
+    assert_eq!(mismatches, vec!["codec is av1, expected h265 output"]);
+}
+
+#[test]
+fn decode_command_prefers_dav1d_for_av1() {
+    let chunk = Path::new("00000.ivf");
+    let program =
+        |codec_name, dav1d| decode_command(chunk, codec_name, dav1d).get_program().to_owned();
+    assert_eq!(program("av1", true), "dav1d");
+    assert_eq!(program("av1", false), "ffmpeg");
+    assert_eq!(program("hevc", true), "ffmpeg");
+}
+
+#[test]
+fn keyframes_of_av1_and_vp9() {
+    // temporal delimiter, then a frame OBU with its size field
+    let av1_key: &[u8] = &[0x12, 0x00, 0x32, 0x01, 0x10];
+    let av1_inter: &[u8] = &[0x12, 0x00, 0x32, 0x01, 0x30];
+    assert!(av1_is_keyframe(av1_key));
+    assert!(!av1_is_keyframe(av1_inter));
+    assert!(!av1_is_keyframe(&[0x12, 0x00]));
+    assert!(!av1_is_keyframe(&[0x12, 0x05]));
+
+    assert!(vp9_is_keyframe(&[0x82]));
+    assert!(!vp9_is_keyframe(&[0x86]));
+    assert!(!vp9_is_keyframe(&[0x02]));
+}
+
+#[test]
+fn kept_frames_drop_duplicates_at_chunk_joins() {
+    let is_keyframe = |frame: &[u8]| frame[0] == b'k';
+    let frames: [&[u8]; 4] = [b"k1", b"k2", b"i3", b"i4"];
+    assert_eq!(kept_frames(&frames, 3, None, is_keyframe), 1..4);
+    assert_eq!(kept_frames(&frames, 4, None, is_keyframe), 0..4);
+
+    let frames: [&[u8]; 4] = [b"i0", b"k1", b"i2", b"i2"];
+    assert_eq!(kept_frames(&frames, 3, None, is_keyframe), 0..3);
+    assert_eq!(kept_frames(&frames, 2, Some(b"i0"), is_keyframe), 1..3);
+
+    // frames that are not duplicates are kept
+    let frames: [&[u8]; 3] = [b"k1", b"i2", b"i3"];
+    assert_eq!(kept_frames(&frames, 2, Some(b"i0"), is_keyframe), 0..3);
+}
+
+#[test]
+fn index_to_front_by_container() {
+    assert_eq!(
+        index_to_front_args(Path::new("output.MKV")),
+        Some(&["-cues_to_front", "1"][..])
+    );
+    assert_eq!(
+        index_to_front_args(Path::new("output.mp4")),
+        Some(&["-movflags", "+faststart"][..])
+    );
+    assert_eq!(index_to_front_args(Path::new("output.ivf")), None);
+    assert_eq!(index_to_front_args(Path::new("output")), None);
+}
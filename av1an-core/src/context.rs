@@ -1,12 +1,13 @@
 use std::{
-    borrow::Cow,
     cmp::{self, Reverse},
+    collections::HashMap,
     ffi::OsString,
-    fs::{self, File},
-    io::{BufRead, BufReader, Write},
+    fs,
+    io::{self, BufRead, BufReader, IsTerminal, PipeReader, Read, Write},
     iter,
+    ops::Range,
     path::{Path, PathBuf},
-    process::{exit, ChildStderr, Command, Stdio},
+    process::{exit, Command, Stdio},
     sync::{
         atomic::{self, AtomicBool, AtomicUsize},
         mpsc,
@@ -14,12 +15,15 @@ use std::{
         Mutex,
     },
     thread::{self, available_parallelism},
+    time::{Duration, Instant},
 };
 
-use anyhow::Context;
+use anyhow::{ensure, Context};
 use av1_grain::TransferFunction;
 use av_decoders::VapoursynthDecoder;
+use av_format::rational::Rational64;
 use colored::*;
+use indicatif::{HumanBytes, HumanDuration};
 use itertools::Itertools;
 use num_traits::cast::ToPrimitive;
 use rand::{prelude::SliceRandom, rng};
@@ -28,18 +32,35 @@ use tracing::{debug, error, info, warn};
 use crate::{
     broker::{Broker, EncoderCrash},
     chunk::Chunk,
-    concat::{self, ConcatMethod},
+    concat::{self, ConcatMethod, OutputTags},
     create_dir,
     determine_workers,
-    ffmpeg::{compose_ffmpeg_pipe, get_num_frames},
+    error::Av1anError,
+    ffmpeg::{
+        burn_subtitles_args,
+        chunk_filter_args,
+        compose_ffmpeg_pipe,
+        create_intermediate,
+        get_num_frames,
+        select_frames_args,
+        IntermediateCodec,
+    },
     get_done,
     init_done,
     into_vec,
-    metrics::vmaf,
+    metrics::{
+        custom,
+        reference::ReferencePipe,
+        ssimulacra2,
+        statistics::FrameScores,
+        vmaf::{self, get_vmaf_model_version},
+        xpsnr,
+    },
+    output_log::OutputLog,
+    parse::EncoderSummary,
+    pipe::{self, FrameBuffer},
     progress_bar::{
         finish_progress_bar,
-        inc_bar,
-        inc_mp_bar,
         init_multi_progress_bar,
         init_progress_bar,
         reset_bar_at,
@@ -48,52 +69,185 @@ use crate::{
         update_mp_chunk,
         update_mp_msg,
         update_progress_bar_estimates,
+        ChunkProgress,
     },
     read_chunk_queue,
+    report::{self, ChunkStats, Report, StageTiming},
     save_chunk_queue,
+    save_done,
+    scene_detect::sc_proxy_path,
+    scene_preview,
     scenes::{Scene, SceneFactory, ZoneOptions},
-    settings::{EncodeArgs, InputPixelFormat},
-    split::segment,
-    vapoursynth::{create_vs_file, LoadscriptArgs},
-    zones::{parse_zones, validate_zones},
+    schema,
+    scratch_dir,
+    settings::{merge_params, DebandStrength, EncodeArgs, InputPixelFormat},
+    shared_temp,
+    split::{keyframe_splits, segment, trim_input, unaligned_splits},
+    target_quality::ProbeResCorrection,
+    temp_check,
+    temp_registry::register_temp_dir,
+    two_stage::q_offsets,
+    util::printable_base10_digits,
+    vapoursynth::{
+        check_source_bit_depth,
+        create_bestsource_vs_file,
+        create_vs_file,
+        LoadscriptArgs,
+    },
+    vs_params::chunk_vspipe_args,
+    vs_server::VsServer,
+    zones::{parse_zones, scenes_in_ranges, validate_zones},
     ChunkMethod,
     ChunkOrdering,
+    ChunkScheduler,
+    ChunkScore,
+    ClipInfo,
     DashMap,
     DoneJson,
     Input,
+    OutputMetric,
     PixelFormatConverter,
+    QueueScheduler,
+    TargetMetric,
     Verbosity,
 };
 
+/// Tracing target of the command lines that are run for every chunk, so that
+/// the log file can capture them regardless of the log level of the rest of
+/// Av1an
+pub const COMMAND_LOG_TARGET: &str = "av1an_core::commands";
+
 #[derive(Debug)]
 pub struct Av1anContext {
-    pub frames:               usize,
-    pub vs_script:            Option<PathBuf>,
-    pub vs_proxy_script:      Option<PathBuf>,
-    pub args:                 EncodeArgs,
-    pub(crate) scene_factory: SceneFactory,
+    pub frames:                 usize,
+    /// Clip info of the input, probed once when the context is created. Use
+    /// [`Input::provide_clip_info`] to skip probing when it is already known.
+    pub clip_info:              ClipInfo,
+    pub vs_script:              Option<PathBuf>,
+    pub vs_proxy_script:        Option<PathBuf>,
+    pub args:                   EncodeArgs,
+    pub(crate) scene_factory:   SceneFactory,
+    /// Report of the previous run that used the same temporary directory
+    pub(crate) previous_report: Option<Report>,
+    pub(crate) speed_probe:     Option<SpeedProbe>,
+    /// Scheduler set with [`Self::set_chunk_scheduler`]
+    pub(crate) chunk_scheduler: Mutex<Option<Box<dyn ChunkScheduler>>>,
+    /// Persistent vspipe servers of the workers, by worker id
+    pub(crate) vs_servers:      Mutex<HashMap<usize, VsServer>>,
+    /// Whether the VapourSynth scripts were created, before the first stage
+    pub(crate) prepared:        bool,
+    /// Scenes found by [`Self::detect_scenes`]
+    pub(crate) scenes:          Option<Vec<Scene>>,
+    /// Frame ranges encoded with [`EncodeArgs::zones_only`] or
+    /// [`EncodeArgs::trim`], set by [`Self::build_chunks`]
+    pub(crate) zone_ranges:     Option<Vec<Range<usize>>>,
+    /// Chunks left to encode and the number of chunks of the encode, from
+    /// [`Self::build_chunks`]
+    pub(crate) chunk_queue:     Option<(Vec<Chunk>, usize)>,
+    /// Number of chunks of the encode, once [`Self::encode_chunks`] finished
+    pub(crate) total_chunks:    Option<usize>,
+    /// Video the audio and closed captions are read from, which stays the
+    /// input when it is replaced with a lossless intermediate
+    pub(crate) audio_source:    Option<PathBuf>,
+    /// When the context was created, which the stage timings are relative to
+    pub(crate) started:         Instant,
+    /// Stages timed by this run, logged once the encode finished
+    pub(crate) timings:         Vec<StageTiming>,
+    /// Time the workers spent on Target Quality, summed over the workers
+    pub(crate) probe_time:      Mutex<Duration>,
+    /// Probe resolution correction learned by the chunks of this encode
+    pub(crate) res_correction:  Mutex<ProbeResCorrection>,
+}
+
+/// Result of encoding a short section of the input with the chosen encoder
+/// settings
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SpeedProbe {
+    pub fps:             f64,
+    pub bytes_per_frame: f64,
 }
 
 impl Av1anContext {
     #[tracing::instrument(level = "debug")]
     #[inline]
     pub fn new(mut args: EncodeArgs) -> anyhow::Result<Self> {
-        args.validate()?;
+        let started = Instant::now();
+        args.validate().map_err(Av1anError::Input)?;
 
+        let clip_info = args
+            .input
+            .clip_info_overriding_fps(args.fps_override)
+            .map_err(Av1anError::Input)?;
         let mut this = Self {
-            frames: args.input.clip_info()?.num_frames,
+            frames: clip_info.num_frames,
+            clip_info,
             vs_script: None,
             vs_proxy_script: None,
+            previous_report: Report::read(Path::new(&args.temp)).ok(),
+            speed_probe: None,
+            chunk_scheduler: Mutex::new(None),
+            vs_servers: Mutex::new(HashMap::new()),
+            prepared: false,
+            scenes: None,
+            zone_ranges: None,
+            chunk_queue: None,
+            total_chunks: None,
+            audio_source: args.input.is_video().then(|| args.input.as_path().to_path_buf()),
+            started,
+            timings: Vec::new(),
+            probe_time: Mutex::new(Duration::ZERO),
+            res_correction: Mutex::new(ProbeResCorrection::default()),
             args,
             scene_factory: SceneFactory::new(),
         };
         this.initialize()?;
+        this.record_timing("initialization", started.elapsed(), false);
         Ok(this)
     }
 
+    /// Encode the chunks in the order chosen by `scheduler` instead of
+    /// handing them to the workers in queue order
+    #[inline]
+    pub fn set_chunk_scheduler(&mut self, scheduler: impl ChunkScheduler + 'static) {
+        *self.chunk_scheduler.get_mut().expect("mutex should acquire lock") =
+            Some(Box::new(scheduler));
+    }
+
+    /// Frame rate of the encoded chunks, if [`EncodeArgs::output_fps`] differs
+    /// from the frame rate of the input
+    fn output_frame_rate(&self) -> Option<f64> {
+        self.args
+            .output_fps
+            .filter(|&output_fps| output_fps != self.clip_info.frame_rate)
+            .map(|output_fps| output_fps.to_f64().expect("output_fps is not NaN"))
+    }
+
+    /// Number of frames of the output, which differs from [`Self::frames`] if
+    /// the filters change the frame rate
+    pub(crate) fn output_frames(&self) -> usize {
+        self.args.output_fps.map_or(self.frames, |output_fps| {
+            (Rational64::from_integer(self.frames as i64) * output_fps / self.clip_info.frame_rate)
+                .round()
+                .to_integer() as usize
+        })
+    }
+
     /// Initialize logging routines and create temporary directories
     #[tracing::instrument(level = "debug")]
     fn initialize(&mut self) -> anyhow::Result<()> {
+        // other instances join an encode with a shared temporary directory
+        // once it has chunks
+        if self.args.shared_temp
+            && !self.args.resume
+            && Path::new(&self.args.temp).join("chunks.json").exists()
+        {
+            info!(
+                "joining the encode in shared temporary directory {temp}",
+                temp = self.args.temp
+            );
+            self.args.resume = true;
+        }
+
         if !self.args.resume && Path::new(&self.args.temp).is_dir() {
             fs::remove_dir_all(&self.args.temp).with_context(|| {
                 format!(
@@ -107,6 +261,20 @@ impl Av1anContext {
         create_dir!(Path::new(&self.args.temp).join("split"))?;
         create_dir!(Path::new(&self.args.temp).join("encode"))?;
 
+        if let Some(root) = &self.args.temp_registry {
+            let temp = self
+                .args
+                .relocated_from
+                .as_deref()
+                .unwrap_or_else(|| Path::new(&self.args.temp));
+            register_temp_dir(
+                root,
+                temp,
+                self.args.input.as_path(),
+                Path::new(&self.args.output_file),
+            )?;
+        }
+
         debug!("temporary directory: {temp}", temp = &self.args.temp);
 
         let done_path = Path::new(&self.args.temp).join("done.json");
@@ -146,85 +314,464 @@ impl Av1anContext {
         if self.args.resume && done_json_exists {
             let done = fs::read_to_string(done_path)
                 .with_context(|| "Failed to read contents of done.json")?;
-            let done: DoneJson =
-                serde_json::from_str(&done).with_context(|| "Failed to parse done.json")?;
+            let done: DoneJson = schema::DONE.load(&done)?;
             self.frames = done.frames.load(atomic::Ordering::Relaxed);
 
             // frames need to be recalculated in this case
             if self.frames == 0 {
-                self.frames = self.args.input.clip_info()?.num_frames;
+                self.frames = self.clip_info.num_frames;
                 done.frames.store(self.frames, atomic::Ordering::Relaxed);
             }
 
             init_done(done);
         } else {
             init_done(DoneJson {
+                version:    schema::DONE.version(),
                 frames:     AtomicUsize::new(0),
                 done:       DashMap::new(),
                 audio_done: AtomicBool::new(false),
             });
 
-            let mut done_file = File::create(&done_path)?;
-            done_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+            save_done(Path::new(&self.args.temp), self.args.shared_temp)?;
+        };
+
+        if self.args.shared_temp {
+            shared_temp::clear_stale_claims(Path::new(&self.args.temp))?;
+            shared_temp::start_heartbeat(Path::new(&self.args.temp));
+        }
+
+        let mut report = Report::new(&self.args);
+        report.log_versions();
+        if self.args.resume
+            && let Some(previous) = &self.previous_report
+        {
+            report.warn_changed_versions(previous);
+            report.timings.clone_from(&previous.timings);
+        }
+        report.write(Path::new(&self.args.temp))?;
+
+        Ok(())
+    }
+
+    /// Create the VapourSynth script file for an input and evaluate it, which
+    /// produces the VapourSynth cache file. Returns the path to the script.
+    fn cache_vs_input(&self, vs_input: &Input) -> anyhow::Result<PathBuf> {
+        let script_path = match vs_input {
+            Input::VapourSynth {
+                path, ..
+            } => path.clone(),
+            Input::Video {
+                path,
+                is_proxy,
+                ..
+            } => {
+                let (script_path, _) = create_vs_file(&LoadscriptArgs {
+                    temp:         &self.args.temp,
+                    source:       path,
+                    chunk_method: self.args.chunk_method,
+                    is_proxy:     *is_proxy,
+                    cache_mode:   self.args.cache_mode,
+                })?;
+                script_path
+            },
+        };
+
+        let variables_map = vs_input.as_vspipe_args_hashmap()?;
+        let decoder = match vs_input {
+            Input::VapourSynth {
+                path, ..
+            } => {
+                let dec = VapoursynthDecoder::from_file(path, variables_map)?;
+                av_scenechange::Decoder::from_decoder_impl(av_decoders::DecoderImpl::Vapoursynth(
+                    dec,
+                ))?
+            },
+            video_input => {
+                av_scenechange::Decoder::from_script(&video_input.as_script_text()?, variables_map)?
+            },
         };
+        // Getting the details will evaluate the script and produce the VapourSynth
+        // cache file
+        let details = decoder.get_video_details();
+        if vs_input.is_video() {
+            let source = vs_input.clip_info()?;
+            if let InputPixelFormat::FFmpeg {
+                format,
+            } = source.format_info
+            {
+                check_source_bit_depth(
+                    self.args.chunk_method,
+                    format.get_format_bit_depth_usize(),
+                    details.bit_depth,
+                    source.transfer_characteristics,
+                )?;
+            }
+        }
+
+        Ok(script_path)
+    }
+
+    /// Whether VMAF is calculated, for Target Quality, `--min-chunk-score` or
+    /// `--vmaf`
+    fn uses_vmaf(&self) -> bool {
+        self.args.vmaf
+            || (self.args.target_quality.metric == TargetMetric::VMAF
+                && (self.args.target_quality.target.is_some()
+                    || self.args.min_chunk_score.is_some()))
+    }
+
+    /// Replaces the input with a lossless intermediate of the filtered input,
+    /// which is created unless an earlier run with the same temporary
+    /// directory already did. The filters only run once, so they are removed
+    /// from the settings.
+    fn use_intermediate(&mut self, codec: IntermediateCodec) -> anyhow::Result<()> {
+        let path = Path::new(&self.args.temp).join("intermediate.mkv");
+        if path.exists() {
+            info!("using the lossless intermediate of the previous run");
+        } else {
+            let mut filter_args = self.args.ffmpeg_filter_args.clone();
+            if let Some(deband) = self.args.deband {
+                filter_args = chunk_filter_args(&filter_args, Some(deband.gradfun_filter()));
+            }
+            if let Some(subtitles) = &self.args.burn_subs {
+                filter_args = burn_subtitles_args(
+                    &filter_args,
+                    subtitles,
+                    self.args.input.as_path(),
+                    0,
+                    self.clip_info.frame_rate.to_f64().expect("frame rate is not NaN"),
+                )?;
+            }
+            info!("creating a {codec} lossless intermediate of the filtered input");
+            let start = Instant::now();
+            create_intermediate(&self.args.input, &filter_args, codec, &path)?;
+            info!(
+                "lossless intermediate created in {}",
+                HumanDuration(start.elapsed())
+            );
+        }
+
+        self.args.input = Input::new(
+            path,
+            Vec::new(),
+            &self.args.temp,
+            self.args.chunk_method,
+            false,
+            self.args.cache_mode,
+        )?;
+        self.clip_info = self.args.input.clip_info_overriding_fps(self.args.fps_override)?;
+        self.frames = self.clip_info.num_frames;
+        self.args.ffmpeg_filter_args.clear();
+        self.args.deband = None;
+        self.args.burn_subs = None;
+        // the intermediate already has the frame rate of the filters
+        self.args.output_fps = None;
 
         Ok(())
     }
 
+    /// Encode the input. If it fails, the error is also recorded in
+    /// `report.json` in the temporary directory.
     #[tracing::instrument(skip(self))]
     #[inline]
     pub fn encode_file(&mut self) -> anyhow::Result<()> {
-        let initial_frames =
-            get_done().done.iter().map(|ref_multi| ref_multi.frames).sum::<usize>();
+        self.encode()
+            .inspect_err(|e| Report::record_failure(Path::new(&self.args.temp), e))
+    }
 
-        // Create the VapourSynth script file and store the path to it and evaluate it
-        let cache_vs_input = |vs_input: &Input| {
-            let script_path = match vs_input {
-                Input::VapourSynth {
-                    path, ..
-                } => path.clone(),
-                Input::Video {
-                    path,
-                    is_proxy,
-                    ..
-                } => {
-                    let (script_path, _) = create_vs_file(&LoadscriptArgs {
-                        temp:         &self.args.temp,
-                        source:       path,
-                        chunk_method: self.args.chunk_method,
-                        is_proxy:     *is_proxy,
-                        cache_mode:   self.args.cache_mode,
-                    })?;
-                    script_path
-                },
+    /// Runs every stage in order, which is what [`Self::encode_file`] does
+    fn encode(&mut self) -> anyhow::Result<()> {
+        if self.args.copy_video {
+            self.copy_video()?;
+            self.remove_temp();
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        self.detect_scenes()?;
+        self.record_timing("scene detection", start.elapsed(), false);
+
+        if self.args.sc_only {
+            debug!("scene detection only");
+
+            if let Err(e) = fs::remove_dir_all(&self.args.temp) {
+                warn!("Failed to delete temp directory: {e}");
+            }
+
+            exit(0);
+        }
+
+        let start = Instant::now();
+        self.build_chunks()?;
+        self.record_timing("chunking", start.elapsed(), false);
+        if self.args.interactive {
+            let chunks = self.chunk_queue.take().unwrap_or_default();
+            let confirmed = self.confirm_plan(&chunks.0);
+            self.chunk_queue = Some(chunks);
+            if !confirmed? {
+                println!("Aborting.");
+                // a resumed encode keeps what it encoded so far
+                if !self.args.resume
+                    && let Err(e) = fs::remove_dir_all(&self.args.temp)
+                {
+                    warn!("Failed to delete temp directory: {e}");
+                }
+                return Ok(());
+            }
+        }
+
+        let start = Instant::now();
+        self.encode_chunks()?;
+        self.record_timing("encoding", start.elapsed(), false);
+        let probe_time = *self.probe_time.get_mut().expect("mutex should acquire lock");
+        if !probe_time.is_zero() {
+            self.record_timing("Target Quality probing", probe_time, true);
+        }
+        if self.args.shared_temp && !self.finishes_shared_encode()? {
+            return Ok(());
+        }
+        let start = Instant::now();
+        self.concat()?;
+        self.record_timing("concatenation", start.elapsed(), false);
+
+        let vmaf = self.args.vmaf.then_some(OutputMetric::VMAF);
+        for metric in vmaf.into_iter().chain(self.args.metrics.clone()) {
+            let start = Instant::now();
+            let name = metric.to_string().to_uppercase();
+            if let Err(e) = self.score_output(metric) {
+                error!("{name} calculation failed with error: {e}");
+            }
+            self.record_timing(&name, start.elapsed(), false);
+        }
+
+        if self.args.html_report
+            && let Err(e) = self.write_html_report()
+        {
+            warn!("Failed to write the HTML report: {e:#}");
+        }
+
+        for line in report::timing_breakdown(&self.timings, self.started.elapsed()) {
+            info!("{line}");
+        }
+
+        self.remove_temp();
+        Ok(())
+    }
+
+    /// Records that `stage` took `elapsed` in the report and for the timing
+    /// breakdown of this run. `concurrent` stages ran alongside the others on
+    /// the workers, with `elapsed` summed over the workers.
+    fn record_timing(&mut self, stage: &str, elapsed: Duration, concurrent: bool) {
+        let timing = StageTiming {
+            stage: stage.to_string(),
+            seconds: elapsed.as_secs_f64(),
+            concurrent,
+        };
+        Report::record_timing(Path::new(&self.args.temp), timing.clone());
+        self.timings.push(timing);
+    }
+
+    /// Renders the report with the encoded chunks and the VMAF scores of the
+    /// output, or the scores of the chunks if it was not measured, as an HTML
+    /// page next to the output
+    fn write_html_report(&self) -> anyhow::Result<()> {
+        let temp = Path::new(&self.args.temp);
+        let report = Report::read(temp)?;
+        let queue = read_chunk_queue(temp)?;
+        let done = get_done();
+        let mut chunks = Vec::new();
+        let mut chunk_scores = Vec::new();
+        for chunk in &queue {
+            let Some(done) = done.done.get(&chunk.name()) else {
+                continue;
             };
+            chunks.push(ChunkStats {
+                index:      chunk.index,
+                frames:     done.frames,
+                duration:   chunk.frames() as f64 / chunk.frame_rate,
+                size_bytes: done.size_bytes,
+                encode_fps: done.summary.fps,
+            });
+            if let Some(score) = &done.score {
+                chunk_scores.push(score.score);
+            }
+        }
+        chunks.sort_by_key(|chunk| chunk.index);
+
+        let output = Path::new(&self.args.output_file);
+        let vmaf_file = output.with_extension("json");
+        let frame_scores = if self.args.vmaf && vmaf_file.exists() {
+            vmaf::read_vmaf_file(&vmaf_file).ok().map(|scores| ("VMAF", scores))
+        } else if self.args.metrics.contains(&OutputMetric::SSIMULACRA2) {
+            FrameScores::read(&ssimulacra2::stats_file(output))
+                .ok()
+                .map(|scores| ("SSIMULACRA2", scores.frames))
+        } else if self.args.metrics.contains(&OutputMetric::XPSNR) {
+            FrameScores::read(&xpsnr::stats_file(output))
+                .ok()
+                .map(|scores| ("XPSNR", scores.frames))
+        } else if let Some(metric) = self.args.metrics.iter().find_map(|metric| match metric {
+            OutputMetric::Custom(metric) => Some(*metric),
+            _ => None,
+        }) {
+            FrameScores::read(&custom::stats_file(output, metric))
+                .ok()
+                .map(|scores| (metric.name(), scores.frames))
+        } else {
+            None
+        };
+        let chunk_metric = format!("{} per chunk", self.args.target_quality.metric);
+        let scores = match &frame_scores {
+            Some((name, scores)) => Some((*name, scores.as_slice())),
+            None if !chunk_scores.is_empty() => {
+                Some((chunk_metric.as_str(), chunk_scores.as_slice()))
+            },
+            None => None,
+        };
 
-            let variables_map = vs_input.as_vspipe_args_hashmap()?;
-            let decoder = match vs_input {
-                Input::VapourSynth {
-                    path, ..
-                } => {
-                    let dec = VapoursynthDecoder::from_file(path, variables_map)?;
-                    av_scenechange::Decoder::from_decoder_impl(
-                        av_decoders::DecoderImpl::Vapoursynth(dec),
-                    )?
-                },
-                video_input => av_scenechange::Decoder::from_script(
-                    &video_input.as_script_text()?,
-                    variables_map,
-                )?,
+        let title = output.file_name().map_or_else(
+            || self.args.output_file.clone(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let html = report.to_html(&title, &chunks, scores)?;
+        let path = output.with_extension("html");
+        fs::write(&path, html).with_context(|| format!("Failed to write {}", path.display()))?;
+        info!("HTML report written to {}", path.display());
+        Ok(())
+    }
+
+    /// Copies the video stream of the input to the output instead of encoding
+    /// it, muxing it with the audio encoded with `--audio-params`. Closed
+    /// captions stay in the copied video stream.
+    fn copy_video(&self) -> anyhow::Result<()> {
+        let input = self.audio_source.as_deref().context("--copy-video requires a video input")?;
+        info!("copying the video stream, only the audio is encoded");
+        crate::ffmpeg::encode_audio(
+            input,
+            &self.args.temp,
+            self.args.audio_params.as_slice(),
+            None,
+            self.args.audio_normalize,
+            None,
+        )?;
+        let tags = OutputTags {
+            // the copied video was not encoded with the encoder of the settings
+            encoder_settings: None,
+            ..self.args.output_tags()
+        };
+        concat::copy_video(
+            input,
+            self.args.temp.as_ref(),
+            self.args.output_file.as_ref(),
+            &tags,
+        )
+        .map_err(Av1anError::Concat)?;
+        self.optimize_mux();
+        Ok(())
+    }
+
+    /// Removes the temporary directory once the output was written, or copies
+    /// it back with `--keep`
+    fn remove_temp(&self) {
+        if !Path::new(&self.args.output_file).exists() {
+            warn!(
+                "Concatenation failed for unknown reasons! Temp folder will not be deleted: {temp}",
+                temp = self.args.temp
+            );
+        } else if self.args.keep {
+            self.copy_back_temp();
+        } else if self.args.target_quality.keep_probes {
+            self.remove_temp_except_probes();
+        } else if let Err(e) = fs::remove_dir_all(&self.args.temp) {
+            warn!("Failed to delete temp directory: {e}");
+        }
+    }
+
+    /// Deletes everything in the temporary directory apart from the probes
+    /// kept with `--keep-probes`
+    fn remove_temp_except_probes(&self) {
+        let temp = Path::new(&self.args.temp);
+        let entries = match fs::read_dir(temp) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to delete temp directory: {e}");
+                return;
+            },
+        };
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_name() == "probes" {
+                continue;
+            }
+            let path = entry.path();
+            let result = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
             };
-            // Getting the details will evaluate the script and produce the VapourSynth
-            // cache file
-            let _ = decoder.get_video_details();
+            if let Err(e) = result {
+                warn!("Failed to delete {}: {e}", path.display());
+            }
+        }
+        let probes = self.args.relocated_from.as_deref().unwrap_or(temp).join("probes");
+        self.copy_back_temp();
+        info!("kept the probes in {}", probes.display());
+    }
 
-            Ok::<PathBuf, anyhow::Error>(script_path)
+    /// Copies the temporary directory back to the one that was asked for, if
+    /// it was moved to local storage, and removes the local copy
+    fn copy_back_temp(&self) {
+        let Some(requested) = &self.args.relocated_from else {
+            return;
         };
+        info!("copying the temporary directory to {}", requested.display());
+        match temp_check::copy_dir_all(Path::new(&self.args.temp), requested) {
+            Ok(()) => {
+                if let Err(e) = fs::remove_dir_all(&self.args.temp) {
+                    warn!("Failed to delete temp directory: {e}");
+                }
+            },
+            Err(e) => warn!(
+                "Failed to copy the temporary directory to {}, it is kept in {}: {e}",
+                requested.display(),
+                self.args.temp
+            ),
+        }
+    }
+
+    /// Creates the lossless intermediate and the VapourSynth scripts of the
+    /// input and the proxy, and fits the settings to `--deadline`. Only runs
+    /// once, before the first stage.
+    fn prepare(&mut self) -> anyhow::Result<()> {
+        if self.prepared {
+            return Ok(());
+        }
+        self.prepared = true;
+
+        if let Some(codec) = self.args.intermediate {
+            self.use_intermediate(codec).map_err(Av1anError::Input)?;
+        }
+        if self.uses_vmaf()
+            && self.args.vmaf_path.is_none()
+            && self.args.target_quality.model.is_none()
+            && !vmaf::has_builtin_models()
+        {
+            let version = get_vmaf_model_version(&self.args.target_quality.probing_vmaf_features);
+            info!("libvmaf has no built-in models, using a downloaded {version} model");
+            let cache_dir = vmaf::default_model_cache_dir()
+                .context("Failed to find the cache directory, pass a VMAF model with --vmaf-path")
+                .map_err(Av1anError::Metrics)?;
+            self.args.target_quality.model =
+                Some(vmaf::fetch_model(version, &cache_dir).map_err(Av1anError::Metrics)?);
+        }
 
         // Technically we should check if the vapoursynth cache file exists rather than
         // !self.resume, but the code still works if we are resuming and the
         // cache file doesn't exist (as it gets generated when vspipe is first
-        // called), so it's not worth adding all the extra complexity.
+        // called), so it's not worth adding all the extra complexity. Resumed
+        // encodes without scenes.json, whose scene detection was interrupted
+        // while encoding with --sc-lookahead, still create chunks.
+        let creates_chunks =
+            !self.args.resume || !Path::new(&self.args.temp).join("scenes.json").exists();
         if (self.args.input.is_vapoursynth()
             || (self.args.input.is_video()
                 && matches!(
@@ -234,9 +781,10 @@ impl Av1anContext {
                         | ChunkMethod::DGDECNV
                         | ChunkMethod::BESTSOURCE
                 )))
-            && !self.args.resume
+            && creates_chunks
         {
-            self.vs_script = Some(cache_vs_input(&self.args.input)?);
+            self.vs_script =
+                Some(self.cache_vs_input(&self.args.input).map_err(Av1anError::Input)?);
         }
         if let Some(proxy) = &self.args.proxy
             && (proxy.is_vapoursynth()
@@ -248,12 +796,12 @@ impl Av1anContext {
                             | ChunkMethod::DGDECNV
                             | ChunkMethod::BESTSOURCE
                     )
-                    && !self.args.resume))
+                    && creates_chunks))
         {
-            self.vs_proxy_script = Some(cache_vs_input(proxy)?);
+            self.vs_proxy_script = Some(self.cache_vs_input(proxy).map_err(Av1anError::Input)?);
         }
 
-        let clip_info = self.args.input.clip_info()?;
+        let clip_info = self.clip_info;
         let res = clip_info.resolution;
         let fps_ratio = clip_info.frame_rate;
         let fps = fps_ratio.to_f64().expect("fps_ratio is not NaN");
@@ -277,23 +825,132 @@ impl Av1anContext {
                 TransferFunction::BT1886 => "SDR",
             }
         );
+        let output_fps = self.args.output_fps.unwrap_or(fps_ratio);
+        if output_fps != fps_ratio {
+            info!(
+                "Output: {:.3} fps, {} frames",
+                output_fps.to_f64().expect("output_fps is not NaN"),
+                self.output_frames()
+            );
+        }
 
-        let splits = self.split_routine()?.to_vec();
+        if let Some(deadline) = self.args.deadline {
+            if self.args.resume {
+                warn!("--deadline has no effect when resuming, the encode keeps its settings");
+            } else if !self.args.sc_only {
+                self.fit_deadline(deadline)?;
+            }
+        }
 
-        if self.args.sc_only {
-            debug!("scene detection only");
+        Ok(())
+    }
 
-            if let Err(e) = fs::remove_dir_all(&self.args.temp) {
-                warn!("Failed to delete temp directory: {e}");
+    /// Detects the scenes of the input, or loads them from the scenes file,
+    /// and returns the scenes the chunks are made from. Running it again
+    /// returns the same scenes.
+    ///
+    /// With `--sc-lookahead`, the scenes are detected while the chunks are
+    /// encoded by [`Self::encode_chunks`] instead, and none are returned.
+    #[inline]
+    pub fn detect_scenes(&mut self) -> anyhow::Result<&[Scene]> {
+        if self.scenes.is_none() {
+            self.prepare()?;
+
+            let scenes = if self.sc_lookahead().is_some() {
+                self.apply_target_chunk_time().map_err(Av1anError::Scd)?;
+                Vec::new()
+            } else {
+                self.split_routine().map_err(Av1anError::Scd)?.to_vec()
+            };
+
+            let sc_proxy = sc_proxy_path(&self.args.temp);
+            if self.args.sc_proxy && self.args.proxy.is_none() && sc_proxy.exists() {
+                let proxy = Input::new(
+                    &sc_proxy,
+                    Vec::new(),
+                    &self.args.temp,
+                    self.args.chunk_method,
+                    true,
+                    self.args.cache_mode,
+                )?;
+                let proxy_frames = proxy.clip_info()?.num_frames;
+                if proxy_frames == self.frames {
+                    info!("Target Quality probes use the scene detection proxy");
+                    self.vs_proxy_script = Some(self.cache_vs_input(&proxy)?);
+                    self.args.proxy = Some(proxy);
+                } else {
+                    warn!(
+                        "The scene detection proxy has {proxy_frames} frames instead of {}, \
+                         Target Quality will use the source",
+                        self.frames
+                    );
+                }
             }
 
-            exit(0);
+            self.scenes = Some(scenes);
+        }
+
+        Ok(self.scenes.as_deref().unwrap_or_default())
+    }
+
+    /// Creates the chunks from the scenes of [`Self::detect_scenes`], which is
+    /// run first if it has not been, and returns the chunks that are left to
+    /// encode. When resuming, the chunks are loaded from the temporary
+    /// directory instead.
+    ///
+    /// With `--sc-lookahead`, the chunks are created while encoding instead,
+    /// and none are returned.
+    #[inline]
+    pub fn build_chunks(&mut self) -> anyhow::Result<&[Chunk]> {
+        if self.chunk_queue.is_none() {
+            let splits = self.detect_scenes()?.to_vec();
+            let splits = if self.args.zones_only || self.args.trim.is_some() {
+                let (splits, ranges) = self.partial_splits(&splits).map_err(Av1anError::Scd)?;
+                self.zone_ranges = Some(ranges);
+                splits
+            } else {
+                splits
+            };
+
+            // with --sc-lookahead, the chunks are created while encoding
+            self.chunk_queue = Some(if self.sc_lookahead().is_some() {
+                (Vec::new(), 0)
+            } else {
+                self.load_or_gen_chunk_queue(&splits).map_err(Av1anError::Chunking)?
+            });
         }
 
-        let (chunk_queue, total_chunks) = self.load_or_gen_chunk_queue(&splits)?;
+        Ok(self.chunk_queue.as_ref().map_or(&[], |(chunks, _)| chunks.as_slice()))
+    }
+
+    /// Encodes the chunks of [`Self::build_chunks`], which is run first if it
+    /// has not been, along with the audio. Exits the process if a chunk keeps
+    /// failing after `--max-tries`.
+    #[inline]
+    pub fn encode_chunks(&mut self) -> anyhow::Result<()> {
+        self.build_chunks()?;
+        let (chunk_queue, total_chunks) = self.chunk_queue.take().unwrap_or_default();
+
+        let initial_frames =
+            get_done().done.iter().map(|ref_multi| ref_multi.frames).sum::<usize>();
+        let fps_ratio = self.clip_info.frame_rate;
+        let output_fps = self.args.output_fps.unwrap_or(fps_ratio);
+        let sc_lookahead = self.sc_lookahead();
+
+        let scheduler = self
+            .chunk_scheduler
+            .get_mut()
+            .expect("mutex should acquire lock")
+            .take()
+            .unwrap_or_else(|| Box::new(QueueScheduler::default()));
 
         let mut chunks_done = 0;
-        if self.args.resume {
+        if self.args.resume && sc_lookahead.is_some() {
+            chunks_done = get_done().done.len();
+            info!(
+                "encoding resumed with {chunks_done} chunks completed, continuing scene detection"
+            );
+        } else if self.args.resume {
             chunks_done = get_done().done.len();
             info!(
                 "encoding resumed with {}/{} chunks completed ({} remaining)",
@@ -303,21 +960,42 @@ impl Av1anContext {
             );
         }
 
-        crossbeam_utils::thread::scope(|s| -> anyhow::Result<()> {
-            // vapoursynth audio is currently unsupported
-            let audio_thread = (self.args.input.is_video()
+        let zone_ranges = self.zone_ranges.clone();
+        // vapoursynth audio is currently unsupported, and with a shared temporary
+        // directory only one instance encodes the audio
+        let claims_audio = self.audio_source.is_some()
+            && (!self.args.shared_temp
+                || get_done().audio_done.load(atomic::Ordering::SeqCst)
+                || shared_temp::claim_chunk(Path::new(&self.args.temp), "audio")?);
+        let total_chunks = crossbeam_utils::thread::scope(|s| -> anyhow::Result<usize> {
+            let audio_thread = (claims_audio
                 && (!self.args.resume || !get_done().audio_done.load(atomic::Ordering::SeqCst)))
             .then(|| {
-                let input = self.args.input.as_video_path();
+                let input = self.audio_source.as_deref().expect("audio is only read from video");
                 let temp = self.args.temp.as_str();
+                let shared_temp = self.args.shared_temp;
                 let audio_params = self.args.audio_params.as_slice();
+                let output_file = Path::new(&self.args.output_file);
+                let captions = self.args.captions;
+                let normalization = self.args.audio_normalize;
+                let trim = zone_ranges.as_deref().map(|ranges| (ranges, fps_ratio));
                 s.spawn(move |_| -> anyhow::Result<_> {
-                    let audio_output = crate::ffmpeg::encode_audio(input, temp, audio_params)?;
+                    let captions = crate::ffmpeg::handle_closed_captions(
+                        input,
+                        Path::new(temp),
+                        output_file,
+                        captions,
+                    )?;
+                    let audio_output = crate::ffmpeg::encode_audio(
+                        input,
+                        temp,
+                        audio_params,
+                        captions.as_deref(),
+                        normalization,
+                        trim,
+                    )?;
                     get_done().audio_done.store(true, atomic::Ordering::SeqCst);
-
-                    let progress_file = Path::new(temp).join("done.json");
-                    let mut progress_file = File::create(progress_file)?;
-                    progress_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+                    save_done(Path::new(temp), shared_temp)?;
 
                     if let Some(ref audio_output) = audio_output {
                         let audio_size = audio_output.metadata()?.len();
@@ -331,7 +1009,9 @@ impl Av1anContext {
             if self.args.workers == 0 {
                 self.args.workers = determine_workers(&self.args)? as usize;
             }
-            self.args.workers = cmp::min(self.args.workers, chunk_queue.len());
+            if sc_lookahead.is_none() {
+                self.args.workers = cmp::min(self.args.workers, chunk_queue.len());
+            }
 
             info!(
                 "\n{}{} {} {}{} {} {}{} {} {}{} {}\n{}: {}",
@@ -353,15 +1033,15 @@ impl Av1anContext {
 
             if self.args.verbosity == Verbosity::Normal {
                 init_progress_bar(
-                    self.frames as u64,
+                    self.output_frames() as u64,
                     initial_frames as u64,
                     Some((chunks_done as u32, total_chunks as u32)),
                 );
                 reset_bar_at(initial_frames as u64);
             } else if self.args.verbosity == Verbosity::Verbose {
                 init_multi_progress_bar(
-                    self.frames as u64,
-                    self.args.workers,
+                    self.output_frames() as u64,
+                    self.args.workers + self.args.afterburner + self.args.probe_workers,
                     initial_frames as u64,
                     (chunks_done as u32, total_chunks as u32),
                 );
@@ -370,19 +1050,34 @@ impl Av1anContext {
 
             if chunks_done > 0 {
                 update_progress_bar_estimates(
-                    fps,
-                    self.frames,
+                    output_fps.to_f64().expect("output_fps is not NaN"),
+                    self.output_frames(),
                     self.args.verbosity,
                     (chunks_done as u32, total_chunks as u32),
                 );
             }
 
-            let broker = Broker {
-                chunk_queue,
-                project: self,
-            };
-
             let (tx, rx) = mpsc::channel();
+            let mut broker = Broker::new(chunk_queue, scheduler, self);
+            let mut detection = None;
+            if let Some(window) = sc_lookahead {
+                let (chunks_tx, chunks_rx) = mpsc::channel();
+                broker = broker.with_feed(chunks_rx);
+                let tx = tx.clone();
+                let context = &*self;
+                detection = Some(s.spawn(move |_| {
+                    match context.detect_while_encoding(window, &chunks_tx) {
+                        Ok(total_chunks) => Some(total_chunks),
+                        Err(e) => {
+                            error!("{e}");
+                            // the main thread exits as soon as this is received
+                            tx.send(Av1anError::Scd(e).into()).expect("should send successfully");
+                            None
+                        },
+                    }
+                }));
+            }
+
             let handle = s.spawn(|_| -> anyhow::Result<()> {
                 broker.encoding_loop(tx, self.args.set_thread_affinity, total_chunks as u32)?;
                 Ok(())
@@ -391,11 +1086,16 @@ impl Av1anContext {
             // Queue::encoding_loop only sends a message if there was an error (meaning a
             // chunk crashed) more than MAX_TRIES. So, we have to explicitly
             // exit the program if that happens.
-            if rx.recv().is_ok() {
-                exit(1);
+            if let Ok(error) = rx.recv() {
+                Report::record_failure(Path::new(&self.args.temp), &error);
+                exit(Av1anError::code_of(&error).into());
             }
 
             handle.join().expect("thread should join successfully")?;
+            let total_chunks = detection.map_or(Some(total_chunks), |detection| {
+                detection.join().expect("thread should join successfully")
+            });
+            let total_chunks = total_chunks.expect("scene detection failures exit above");
 
             finish_progress_bar();
 
@@ -407,99 +1107,378 @@ impl Av1anContext {
                 false
             };
 
-            debug!(
-                "encoding finished, concatenating with {concat}",
-                concat = self.args.concat
-            );
+            Ok(total_chunks)
+        })
+        .expect("thread should spawn successfully")?;
 
-            match self.args.concat {
-                ConcatMethod::Ivf => {
-                    concat::ivf(
-                        &Path::new(&self.args.temp).join("encode"),
-                        self.args.output_file.as_ref(),
-                    )?;
-                },
-                ConcatMethod::MKVMerge => {
-                    concat::mkvmerge(
-                        self.args.temp.as_ref(),
-                        self.args.output_file.as_ref(),
-                        self.args.encoder,
-                        total_chunks,
-                        if self.args.ignore_frame_mismatch {
-                            info!(
-                                "`--ignore-frame-mismatch` set. Don't force output FPS, as an FPS \
-                                 changing filter might have been applied."
-                            );
-                            None
-                        } else {
-                            debug!(
-                                "`--ignore-frame-mismatch` not set. Forcing output FPS to \
-                                 {fps_ratio} with mkvmerge."
-                            );
-                            Some(fps_ratio)
-                        },
-                    )?;
-                },
-                ConcatMethod::FFmpeg => {
-                    concat::ffmpeg(self.args.temp.as_ref(), self.args.output_file.as_ref())?;
-                },
-            }
+        self.total_chunks = Some(total_chunks);
+        Ok(())
+    }
 
-            if self.args.vmaf {
-                let vmaf_res = if self.args.target_quality.vmaf_res == "inputres" {
-                    let inputres = self.args.input.clip_info()?.resolution;
-                    format!("{width}x{height}", width = inputres.0, height = inputres.1)
-                } else {
-                    self.args.target_quality.vmaf_res.clone()
-                };
+    /// Returns whether every chunk and the audio of an encode with a shared
+    /// temporary directory are done, and this instance is the one that
+    /// concatenates them. The other instances leave the temporary directory
+    /// as it is.
+    fn finishes_shared_encode(&self) -> anyhow::Result<bool> {
+        let temp = Path::new(&self.args.temp);
+        shared_temp::refresh_done(temp)?;
+        let done = get_done();
+        let total_chunks = self.total_chunks.unwrap_or_default();
+        let chunks_left = total_chunks.saturating_sub(done.done.len());
+        let audio_left =
+            self.audio_source.is_some() && !done.audio_done.load(atomic::Ordering::SeqCst);
+        if chunks_left > 0 || audio_left {
+            info!(
+                "{chunks_left} chunks{} are still being encoded by other instances, the last one \
+                 to finish concatenates the output",
+                if audio_left { " and the audio" } else { "" }
+            );
+            return Ok(false);
+        }
+        if !shared_temp::claim_concat(temp)? {
+            info!("another instance is concatenating the output");
+            return Ok(false);
+        }
+        Ok(true)
+    }
 
-                let vmaf_model =
-                    self.args.vmaf_path.as_deref().or(self.args.target_quality.model.as_deref());
-                let vmaf_scaler = "bicubic";
-                let vmaf_filter = self.args.vmaf_filter.as_deref().or(self
-                    .args
-                    .target_quality
-                    .vmaf_filter
-                    .as_deref());
+    /// Concatenates the encoded chunks and the audio into the output. The
+    /// chunks can also have been encoded by an earlier run with the same
+    /// temporary directory.
+    #[inline]
+    pub fn concat(&mut self) -> anyhow::Result<()> {
+        let total_chunks = self.total_chunks.unwrap_or_else(|| get_done().done.len());
+        if total_chunks == 0 {
+            return Err(
+                Av1anError::Concat(anyhow::anyhow!("No chunks have been encoded yet")).into(),
+            );
+        }
+        if let Some(threshold) = self.args.min_chunk_score {
+            self.check_chunk_scores(threshold, total_chunks)?;
+        }
+        let output_fps = self.args.output_fps.unwrap_or(self.clip_info.frame_rate);
 
-                if self.args.vmaf {
-                    let vmaf_threads = available_parallelism().map_or(1, std::num::NonZero::get);
+        debug!(
+            "encoding finished, concatenating with {concat}",
+            concat = self.args.concat
+        );
 
-                    if let Err(e) = vmaf::plot(
-                        self.args.output_file.as_ref(),
-                        &self.args.input,
-                        vmaf_model,
-                        &vmaf_res,
-                        vmaf_scaler,
-                        1,
-                        vmaf_filter,
-                        vmaf_threads,
-                        &self.args.target_quality.probing_vmaf_features,
-                    ) {
-                        error!("VMAF calculation failed with error: {e}");
-                    }
-                }
-            }
+        let mut chunks = read_chunk_queue(self.args.temp.as_ref())?;
+        chunks.sort_unstable_by_key(|chunk| chunk.index);
+        let encoded = self.encoded_chunks(&chunks);
+        if self.args.verify_chunks {
+            // zones may encode chunks with a different encoder
+            let encoders = chunks.iter().map(|chunk| chunk.encoder);
+            concat::verify_chunks(
+                &encoded.iter().cloned().zip(encoders).collect::<Vec<_>>(),
+                self.args.output_pix_format.bit_depth,
+            )
+            .map_err(Av1anError::Concat)?;
+        }
 
-            if !Path::new(&self.args.output_file).exists() {
-                warn!(
-                    "Concatenation failed for unknown reasons! Temp folder will not be deleted: \
-                     {temp}",
-                    temp = self.args.temp
+        let tags = self.args.output_tags();
+        match self.args.concat {
+            ConcatMethod::Ivf => {
+                // chunks are only checked for duplicated frames if their frame count is known
+                let expected_frames = if self.args.ignore_frame_mismatch {
+                    HashMap::new()
+                } else {
+                    encoded
+                        .iter()
+                        .zip(&chunks)
+                        .filter(|(_, chunk)| chunk.output_frame_rate.is_none())
+                        .map(|(path, chunk)| (path.clone(), chunk.output_frames()))
+                        .collect()
+                };
+                concat::ivf(
+                    &encoded,
+                    self.args.output_file.as_ref(),
+                    self.args.encoder,
+                    &expected_frames,
+                )
+                .map_err(Av1anError::Concat)?;
+            },
+            ConcatMethod::MKVMerge => {
+                let result = concat::mkvmerge(
+                    self.args.temp.as_ref(),
+                    &encoded,
+                    self.args.output_file.as_ref(),
+                    if self.args.ignore_frame_mismatch && self.args.output_fps.is_none() {
+                        info!(
+                            "`--ignore-frame-mismatch` set. Don't force output FPS, as an FPS \
+                             changing filter might have been applied."
+                        );
+                        None
+                    } else {
+                        debug!("Forcing output FPS to {output_fps} with mkvmerge.");
+                        Some(output_fps)
+                    },
+                    &tags,
                 );
-            } else if !self.args.keep
-                && let Err(e) = fs::remove_dir_all(&self.args.temp)
-            {
-                warn!("Failed to delete temp directory: {e}");
-            }
-
-            Ok(())
-        })
-        .expect("thread should spawn successfully")?;
+                if let Err(e) = result {
+                    if !self.args.falls_back_to_ffmpeg() {
+                        return Err(Av1anError::Concat(e).into());
+                    }
+                    warn!("Concatenating with mkvmerge failed, falling back to FFmpeg: {e:#}");
+                    // mkvmerge may have left an incomplete output behind
+                    if Path::new(&self.args.output_file).exists() {
+                        let _ = fs::remove_file(&self.args.output_file);
+                    }
+                    concat::ffmpeg(
+                        self.args.temp.as_ref(),
+                        &encoded,
+                        self.args.output_file.as_ref(),
+                        &tags,
+                    )
+                    .map_err(|fallback| {
+                        Av1anError::Concat(fallback.context(format!(
+                            "Concatenating with mkvmerge failed ({e:#}), and so did the FFmpeg \
+                             fallback"
+                        )))
+                    })?;
+                }
+            },
+            ConcatMethod::FFmpeg => {
+                concat::ffmpeg(
+                    self.args.temp.as_ref(),
+                    &encoded,
+                    self.args.output_file.as_ref(),
+                    &tags,
+                )
+                .map_err(Av1anError::Concat)?;
+            },
+        }
+        self.optimize_mux();
 
         Ok(())
     }
 
+    /// Moves the seek index of the output to the front with
+    /// [`EncodeArgs::optimize_mux`]. The output plays as is if that fails, so
+    /// the encode does not fail with it.
+    fn optimize_mux(&self) {
+        if !self.args.optimize_mux {
+            return;
+        }
+        debug!("moving the seek index of the output to the front");
+        if let Err(e) = concat::optimize_mux(Path::new(&self.args.output_file)) {
+            warn!("Failed to optimize the output for seeking, keeping it as muxed: {e:#}");
+        }
+    }
+
+    /// Returns the paths of the encoded `chunks`. The paths are rebuilt in the
+    /// current temporary directory, which may have moved since the chunks
+    /// were queued.
+    fn encoded_chunks(&self, chunks: &[Chunk]) -> Vec<PathBuf> {
+        let encode_dir = Path::new(&self.args.temp).join("encode");
+        chunks
+            .iter()
+            .map(|chunk| {
+                let mut path = encode_dir.clone();
+                path.extend(chunk.output_name().split('/'));
+                path
+            })
+            .collect()
+    }
+
+    /// Refuses to concatenate while any chunk scores worse than
+    /// `--min-chunk-score`. In a terminal, offers to encode those chunks again
+    /// at a lower quantizer until all of them pass.
+    fn check_chunk_scores(&mut self, threshold: f64, total_chunks: usize) -> anyhow::Result<()> {
+        loop {
+            let mut chunks = read_chunk_queue(self.args.temp.as_ref())?;
+            let failing = self.failing_chunks(&chunks, threshold)?;
+            if failing.is_empty() {
+                return Ok(());
+            }
+
+            warn!(
+                "{} chunk(s) score worse than --min-chunk-score {threshold}:",
+                failing.len()
+            );
+            for &(index, score) in &failing {
+                warn!(
+                    "  chunk {index:05}: {:.3} {} at q {}",
+                    score.score, chunks[index].target_quality.metric, score.quantizer
+                );
+            }
+
+            let mut retries = Vec::new();
+            for &(index, score) in &failing {
+                if chunks[index].lower_quantizer(score.quantizer) {
+                    retries.push(chunks[index].clone());
+                }
+            }
+            // other instances would add the removed chunks back to done.json
+            let can_retry = !retries.is_empty()
+                && !self.args.shared_temp
+                && !self.args.assume_yes
+                && io::stdin().is_terminal();
+            if !can_retry || !ask_reencode(retries.len())? {
+                return Err(Av1anError::Concat(anyhow::anyhow!(
+                    "Not concatenating chunks that score worse than --min-chunk-score \
+                     {threshold}, temp folder will not be deleted: {}",
+                    self.args.temp
+                ))
+                .into());
+            }
+
+            let done = get_done();
+            for chunk in &retries {
+                done.done.remove(&chunk.name());
+            }
+            save_done(Path::new(&self.args.temp), self.args.shared_temp)?;
+            // resuming later keeps the lower quantizers
+            save_chunk_queue(&self.args.temp, &chunks)?;
+
+            // the audio and scene detection are done, so this is a resume
+            self.args.resume = true;
+            self.chunk_queue = Some((retries, total_chunks));
+            self.encode_chunks()?;
+        }
+    }
+
+    /// Returns the positions in `chunks` of the chunks that score worse than
+    /// `threshold`, along with their scores. Chunks encoded before
+    /// `--min-chunk-score` was set are scored first.
+    fn failing_chunks(
+        &self,
+        chunks: &[Chunk],
+        threshold: f64,
+    ) -> anyhow::Result<Vec<(usize, ChunkScore)>> {
+        let done = get_done();
+        let mut failing = Vec::new();
+        let mut scored = false;
+        for (index, chunk) in chunks.iter().enumerate() {
+            if chunk.passthrough {
+                continue;
+            }
+            let Some(mut chunk_done) = done.done.get_mut(&chunk.name()) else {
+                continue;
+            };
+            let score = match chunk_done.score {
+                Some(score) => score,
+                None => {
+                    let reference = self.reference_pipe(chunk).map_err(Av1anError::Metrics)?;
+                    let score = chunk
+                        .target_quality
+                        .score_chunk(
+                            chunk,
+                            reference.as_ref(),
+                            None,
+                            self.args.vapoursynth_plugins,
+                        )
+                        .map_err(Av1anError::Metrics)?;
+                    chunk_done.score = Some(score);
+                    scored = true;
+                    score
+                },
+            };
+            let worse = if chunk.target_quality.metric.lower_is_better() {
+                score.score > threshold
+            } else {
+                score.score < threshold
+            };
+            if worse {
+                failing.push((index, score));
+            }
+        }
+        if scored {
+            save_done(Path::new(&self.args.temp), self.args.shared_temp)?;
+        }
+
+        Ok(failing)
+    }
+
+    /// Calculates the VMAF of the output against the input, and plots it next
+    /// to the output. Runs whether or not `--vmaf` is set.
+    #[inline]
+    pub fn compute_metrics(&self) -> anyhow::Result<()> {
+        let fps_ratio = self.clip_info.frame_rate;
+        let vmaf_res = self.metric_resolution();
+
+        let vmaf_model =
+            self.args.vmaf_path.as_deref().or(self.args.target_quality.model.as_deref());
+        let vmaf_scaler = "bicubic";
+        let vmaf_filter = self.metric_filter();
+        let vmaf_threads = available_parallelism().map_or(1, std::num::NonZero::get);
+
+        vmaf::plot(
+            self.args.output_file.as_ref(),
+            &self.args.input,
+            vmaf_model,
+            &vmaf_res,
+            vmaf_scaler,
+            1,
+            vmaf_filter,
+            self.args.vmaf_tonemap,
+            vmaf_threads,
+            self.args.vmaf_cuda,
+            &self.args.target_quality.probing_vmaf_features,
+            self.args
+                .output_fps
+                .filter(|&output_fps| output_fps != fps_ratio)
+                .map(|output_fps| (fps_ratio, output_fps)),
+        )
+    }
+
+    /// Resolution the output is scored at against the input, `--vmaf-res`
+    /// with `inputres` replaced by the resolution of the input
+    fn metric_resolution(&self) -> String {
+        if self.args.target_quality.vmaf_res == "inputres" {
+            let inputres = self.clip_info.resolution;
+            format!("{width}x{height}", width = inputres.0, height = inputres.1)
+        } else {
+            self.args.target_quality.vmaf_res.clone()
+        }
+    }
+
+    /// Filter applied to the input before the output is scored against it,
+    /// `--vmaf-filter`
+    fn metric_filter(&self) -> Option<&str> {
+        self.args
+            .vmaf_filter
+            .as_deref()
+            .or(self.args.target_quality.vmaf_filter.as_deref())
+    }
+
+    /// Scores the output against the input with `metric`, writing the scores
+    /// next to the output
+    fn score_output(&self, metric: OutputMetric) -> anyhow::Result<()> {
+        match metric {
+            OutputMetric::VMAF => self.compute_metrics(),
+            OutputMetric::SSIMULACRA2 => {
+                ssimulacra2::score_output(
+                    &self.args.input,
+                    Path::new(&self.args.output_file),
+                    self.frames,
+                    self.args.vapoursynth_plugins,
+                    &self.metric_resolution(),
+                )?;
+                Ok(())
+            },
+            OutputMetric::XPSNR => {
+                xpsnr::score_output(
+                    &self.args.input,
+                    Path::new(&self.args.output_file),
+                    Path::new(&self.args.temp),
+                    &self.metric_resolution(),
+                    self.metric_filter(),
+                )?;
+                Ok(())
+            },
+            OutputMetric::Custom(metric) => {
+                custom::score_output(
+                    metric,
+                    &self.args.input,
+                    Path::new(&self.args.output_file),
+                    &self.metric_resolution(),
+                )?;
+                Ok(())
+            },
+        }
+    }
+
     #[tracing::instrument(level = "debug")]
     fn read_queue_files(source_path: &Path) -> anyhow::Result<Vec<PathBuf>> {
         let mut queue_files = fs::read_dir(source_path)
@@ -520,23 +1499,142 @@ impl Av1anContext {
         Ok(queue_files)
     }
 
-    /// Returns the number of frames encoded if crashed, to reset the progress
-    /// bar.
-    #[inline]
-    pub fn create_pipes(
+    /// Requests the frames of `chunk` from the persistent vspipe server of the
+    /// worker, starting a server if the worker has none for the chunk's script.
+    /// Returns `None` if the chunk has to be read by a new vspipe process.
+    fn request_from_vs_server(
+        &self,
+        chunk: &Chunk,
+        worker_id: usize,
+    ) -> anyhow::Result<Option<(PipeReader, PipeReader)>> {
+        if !self.args.vs_server
+            || !chunk.input.is_vapoursynth()
+            || chunk.source_cmd.first().is_none_or(|source| source != "vspipe")
+        {
+            return Ok(None);
+        }
+        // the script is only evaluated once, so every chunk would get the
+        // built-in variables of the first one
+        if chunk.input.as_script_text()?.contains("AV1AN_CHUNK_") {
+            return Ok(None);
+        }
+
+        let mut servers = self.vs_servers.lock().expect("mutex should acquire lock");
+        if servers
+            .get(&worker_id)
+            .is_none_or(|server| server.script() != chunk.input.as_path())
+        {
+            servers.insert(worker_id, VsServer::spawn(chunk.input.clone())?);
+        }
+        servers[&worker_id].request(chunk.start_frame, chunk.end_frame - 1).map(Some)
+    }
+
+    /// Returns the FFmpeg filter arguments that the source of `chunk` is piped
+    /// through before encoding, and the strength of the debanding done in the
+    /// VapourSynth script of the chunk instead of FFmpeg if any
+    fn chunk_filters(
+        &self,
+        chunk: &Chunk,
+    ) -> anyhow::Result<(Vec<String>, Option<DebandStrength>)> {
+        let mut ffmpeg_filter_args = chunk_filter_args(
+            &self.args.ffmpeg_filter_args,
+            chunk.ffmpeg_filter.as_deref(),
+        );
+        // deband in the VapourSynth script when the chunks are read through
+        // it and f3kdb is installed, otherwise fall back to FFmpeg's gradfun
+        let vs_deband = self.args.deband.filter(|_| {
+            self.args.input.is_video()
+                && matches!(
+                    self.args.chunk_method,
+                    ChunkMethod::LSMASH
+                        | ChunkMethod::FFMS2
+                        | ChunkMethod::DGDECNV
+                        | ChunkMethod::BESTSOURCE
+                )
+                && self.args.vapoursynth_plugins.is_some_and(|p| p.neo_f3kdb)
+        });
+        if vs_deband.is_none()
+            && let Some(deband) = self.args.deband
+        {
+            ffmpeg_filter_args =
+                chunk_filter_args(&ffmpeg_filter_args, Some(deband.gradfun_filter()));
+        }
+        if let Some(subtitles) = &self.args.burn_subs {
+            ffmpeg_filter_args = burn_subtitles_args(
+                &ffmpeg_filter_args,
+                subtitles,
+                self.args.input.as_path(),
+                chunk.start_frame,
+                chunk.frame_rate,
+            )?;
+        }
+
+        Ok((ffmpeg_filter_args, vs_deband))
+    }
+
+    /// Returns the source frames that the finished `chunk` is scored against
+    /// if they were filtered for the encoder, piped through the same filters
+    /// so that they line up with the frames of the encode. Chunks scored
+    /// against a proxy use the proxy unchanged.
+    pub(crate) fn reference_pipe(&self, chunk: &Chunk) -> anyhow::Result<Option<ReferencePipe>> {
+        if chunk.proxy_cmd.is_some() {
+            return Ok(None);
+        }
+        let (ffmpeg_filter_args, vs_deband) = self.chunk_filters(chunk)?;
+        let mut reference =
+            ReferencePipe::new(&chunk.source_cmd, chunk.input.as_vspipe_args_vec()?)
+                .ffmpeg_filters(ffmpeg_filter_args, self.args.output_pix_format.format);
+        if let Some(deband) = vs_deband {
+            reference = reference.env("AV1AN_DEBAND", deband.to_string());
+        }
+        Ok(reference.is_filtered().then_some(reference))
+    }
+
+    /// Returns the statistics printed by the encoder at the end of the last
+    /// pass. The frames encoded in the last pass are counted on `progress`.
+    pub(crate) fn create_pipes(
         &self,
         chunk: &Chunk,
         current_pass: u8,
         worker_id: usize,
         padding: usize,
-    ) -> Result<(), (anyhow::Error, u64)> {
+        progress: &ChunkProgress,
+    ) -> anyhow::Result<EncoderSummary> {
         update_mp_chunk(worker_id, chunk.index, padding);
 
-        let fpf_file = Path::new(&chunk.temp)
-            .join("split")
-            .join(format!("{name}_fpf", name = chunk.name()));
+        // the previews of two-stage encoding and the speed probe are encoded
+        // outside of the broker, which creates the scratch directories
+        let scratch = scratch_dir(&chunk.temp, Some(worker_id));
+        fs::create_dir_all(&scratch)?;
+        // chunk names with directories shard the encode directory
+        if let Some(dir) = chunk.output_path().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let fpf_file = scratch.join(format!("{name}_fpf", name = chunk.name()));
+        let logs_dir = Path::new(&self.args.temp).join("logs");
+        if self.args.spill_output {
+            fs::create_dir_all(&logs_dir)?;
+        }
+        let output_log = |process: &str| {
+            OutputLog::new(
+                self.args.output_limit,
+                self.args.spill_output.then(|| {
+                    logs_dir.join(format!(
+                        "{name}_pass{current_pass}_{process}.log",
+                        name = chunk.name()
+                    ))
+                }),
+            )
+        };
 
-        let video_params = chunk.video_params.clone();
+        // zones with another encoder keep their parameters in the first pass
+        let video_params =
+            if chunk.passes > 1 && current_pass == 1 && chunk.encoder == self.args.encoder {
+                merge_params(chunk.video_params.clone(), &self.args.first_pass_params)
+            } else {
+                chunk.video_params.clone()
+            };
+        let (ffmpeg_filter_args, vs_deband) = self.chunk_filters(chunk)?;
 
         let mut enc_cmd = if chunk.passes == 1 {
             chunk.encoder.compose_1_1_pass(video_params, chunk.output())
@@ -556,18 +1654,21 @@ impl Av1anContext {
             enc_cmd = chunk.encoder.man_command(enc_cmd, per_shot_target_quality_cq);
         }
 
-        let (source_pipe_stderr, ffmpeg_pipe_stderr, enc_output, enc_stderr, frame) =
-            thread::scope(|scope| -> Result<_, (anyhow::Error, u64)> {
+        let (source_pipe_stderr, ffmpeg_pipe_stderr, enc_output, enc_stderr) =
+            thread::scope(|scope| -> anyhow::Result<_> {
                 let mut use_vs_resize_converter = false;
-                let mut source_pipe = if let [source, args @ ..] = &*chunk.source_cmd {
+                let mut source_command = if let [source, args @ ..] = &*chunk.source_cmd {
                     let mut command = Command::new(source);
 
-                    for arg in chunk.input.as_vspipe_args_vec().map_err(|e| (e, 0))? {
+                    for arg in chunk.input.as_vspipe_args_vec()? {
                         command.args(["-a", &arg]);
                     }
 
-                    command.args(args);
-                    if self.args.ffmpeg_filter_args.is_empty() {
+                    command.args(args).envs(chunk.gpu_env());
+                    if let Some(deband) = vs_deband {
+                        command.env("AV1AN_DEBAND", deband.to_string());
+                    }
+                    if ffmpeg_filter_args.is_empty() {
                         match &self.args.input_pix_format {
                             InputPixelFormat::FFmpeg {
                                 format,
@@ -582,8 +1683,7 @@ impl Av1anContext {
                                         self.args
                                             .output_pix_format
                                             .format
-                                            .to_vapoursynth_string()
-                                            .map_err(|e| (e, 0))?,
+                                            .to_vapoursynth_string()?,
                                     );
                                     use_vs_resize_converter = true;
                                 }
@@ -601,8 +1701,7 @@ impl Av1anContext {
                                         self.args
                                             .output_pix_format
                                             .format
-                                            .to_vapoursynth_string()
-                                            .map_err(|e| (e, 0))?,
+                                            .to_vapoursynth_string()?,
                                     );
                                     use_vs_resize_converter = true;
                                 }
@@ -611,96 +1710,152 @@ impl Av1anContext {
                     }
 
                     command
-                        .stdout(Stdio::piped())
-                        .stderr(Stdio::piped())
-                        .spawn()
-                        .map_err(|e| (e.into(), 0))?
                 } else {
                     unreachable!()
                 };
 
-                let source_pipe_stdout: Stdio =
-                    source_pipe.stdout.take().expect("source_pipe should have stdout").into();
-                let source_pipe_stderr =
-                    source_pipe.stderr.take().expect("source_pipe should have stderr");
-
-                // converts the pixel format
-                let create_ffmpeg_pipe = |pipe_from: Stdio, source_pipe_stderr: ChildStderr| {
-                    let ffmpeg_pipe = compose_ffmpeg_pipe(
-                        self.args.ffmpeg_filter_args.as_slice(),
-                        self.args.output_pix_format.format,
-                    );
-
-                    let mut ffmpeg_pipe = if let [ffmpeg, args @ ..] = &*ffmpeg_pipe {
-                        Command::new(ffmpeg)
-                            .args(args)
-                            .stdin(pipe_from)
-                            .stdout(Stdio::piped())
-                            .stderr(Stdio::piped())
-                            .spawn()
-                            .map_err(|e| (e.into(), 0))?
-                    } else {
-                        unreachable!()
-                    };
-
-                    let ffmpeg_pipe_stdout: Stdio =
-                        ffmpeg_pipe.stdout.take().expect("ffmpeg_pipe should have stdout").into();
-                    let ffmpeg_pipe_stderr =
-                        ffmpeg_pipe.stderr.take().expect("ffmpeg_pipe should have stderr");
-                    Ok((
-                        ffmpeg_pipe_stdout,
-                        source_pipe_stderr,
-                        Some(ffmpeg_pipe_stderr),
-                    ))
-                };
-
-                let (y4m_pipe, source_pipe_stderr, mut ffmpeg_pipe_stderr) =
-                    if self.args.ffmpeg_filter_args.is_empty() {
-                        match &self.args.input_pix_format {
+                // FFmpeg filters the frames or converts their pixel format
+                let needs_ffmpeg_pipe = !ffmpeg_filter_args.is_empty()
+                    || !use_vs_resize_converter
+                        && match &self.args.input_pix_format {
                             InputPixelFormat::FFmpeg {
                                 format,
-                            } => {
-                                if use_vs_resize_converter
-                                    || self.args.output_pix_format.format == *format
-                                {
-                                    (source_pipe_stdout, source_pipe_stderr, None)
-                                } else {
-                                    create_ffmpeg_pipe(source_pipe_stdout, source_pipe_stderr)?
-                                }
-                            },
+                            } => self.args.output_pix_format.format != *format,
                             InputPixelFormat::VapourSynth {
                                 bit_depth,
-                            } => {
-                                if use_vs_resize_converter
-                                    || self.args.output_pix_format.bit_depth == *bit_depth
-                                {
-                                    (source_pipe_stdout, source_pipe_stderr, None)
-                                } else {
-                                    create_ffmpeg_pipe(source_pipe_stdout, source_pipe_stderr)?
-                                }
-                            },
-                        }
+                            } => self.args.output_pix_format.bit_depth != *bit_depth,
+                        };
+
+                let buffer = FrameBuffer::new(
+                    &chunk.name(),
+                    chunk.frames()
+                        * pipe::frame_bytes(
+                            self.clip_info.resolution,
+                            self.args.output_pix_format.format,
+                            self.args.output_pix_format.bit_depth,
+                        ),
+                    self.args.memfd_buffer,
+                )?;
+                // the last process before the encoder, which writes the buffer
+                let mut buffer_writer = None;
+
+                // the server passes no environment variables to the script, and
+                // can only pipe the frames
+                let served = if vs_deband.is_none()
+                    && !use_vs_resize_converter
+                    && chunk.gpu.is_none()
+                    && buffer.is_none()
+                {
+                    self.request_from_vs_server(chunk, worker_id)?
+                } else {
+                    None
+                };
+                let (source_pipe_stdout, source_pipe_stderr): (Stdio, Box<dyn Read + Send>) =
+                    if let Some((frames, errors)) = served {
+                        debug!(
+                            target: COMMAND_LOG_TARGET,
+                            "chunk {index:05} pass {current_pass}: source: vspipe server of worker \
+                             {worker_id}",
+                            index = chunk.index
+                        );
+                        pipe::resize(&frames, self.args.pipe_size, "source");
+                        (frames.into(), Box::new(errors))
                     } else {
-                        create_ffmpeg_pipe(source_pipe_stdout, source_pipe_stderr)?
+                        let stdout = match &buffer {
+                            Some(buffer) if !needs_ffmpeg_pipe => buffer.writer()?,
+                            _ => Stdio::piped(),
+                        };
+                        source_command.stdout(stdout).stderr(Stdio::piped());
+                        debug!(
+                            target: COMMAND_LOG_TARGET,
+                            "chunk {index:05} pass {current_pass}: source: {source_command:?}",
+                            index = chunk.index
+                        );
+                        let mut source_pipe = source_command.spawn()?;
+                        let stderr =
+                            source_pipe.stderr.take().expect("source_pipe should have stderr");
+                        let stdout = source_pipe.stdout.take().map_or_else(
+                            || {
+                                buffer_writer = Some(source_pipe);
+                                Stdio::null()
+                            },
+                            |stdout| {
+                                pipe::resize(&stdout, self.args.pipe_size, "source");
+                                stdout.into()
+                            },
+                        );
+                        (stdout, Box::new(stderr))
+                    };
+
+                // converts the pixel format
+                let create_ffmpeg_pipe =
+                    |pipe_from: Stdio, source_pipe_stderr: Box<dyn Read + Send>| {
+                        let ffmpeg_pipe = compose_ffmpeg_pipe(
+                            ffmpeg_filter_args.as_slice(),
+                            self.args.output_pix_format.format,
+                        );
+
+                        let mut ffmpeg_pipe = if let [ffmpeg, args @ ..] = &*ffmpeg_pipe {
+                            let mut command = Command::new(ffmpeg);
+                            command
+                                .args(args)
+                                .stdin(pipe_from)
+                                .stdout(match &buffer {
+                                    Some(buffer) => buffer.writer()?,
+                                    None => Stdio::piped(),
+                                })
+                                .stderr(Stdio::piped());
+                            debug!(
+                                target: COMMAND_LOG_TARGET,
+                                "chunk {index:05} pass {current_pass}: ffmpeg: {command:?}",
+                                index = chunk.index
+                            );
+                            command.spawn()?
+                        } else {
+                            unreachable!()
+                        };
+
+                        let ffmpeg_pipe_stdout =
+                            ffmpeg_pipe.stdout.take().map_or_else(Stdio::null, |stdout| {
+                                pipe::resize(&stdout, self.args.pipe_size, "ffmpeg");
+                                stdout.into()
+                            });
+                        let ffmpeg_pipe_stderr =
+                            ffmpeg_pipe.stderr.take().expect("ffmpeg_pipe should have stderr");
+                        Ok::<_, anyhow::Error>((
+                            ffmpeg_pipe_stdout,
+                            source_pipe_stderr,
+                            ffmpeg_pipe_stderr,
+                            ffmpeg_pipe,
+                        ))
                     };
 
+                let (y4m_pipe, source_pipe_stderr, mut ffmpeg_pipe_stderr) = if needs_ffmpeg_pipe {
+                    let (stdout, source_pipe_stderr, ffmpeg_pipe_stderr, ffmpeg_pipe) =
+                        create_ffmpeg_pipe(source_pipe_stdout, source_pipe_stderr)?;
+                    if buffer.is_some() {
+                        buffer_writer = Some(ffmpeg_pipe);
+                    }
+                    (stdout, source_pipe_stderr, Some(ffmpeg_pipe_stderr))
+                } else {
+                    (source_pipe_stdout, source_pipe_stderr, None)
+                };
+
                 let source_reader = BufReader::new(source_pipe_stderr);
                 let ffmpeg_reader = ffmpeg_pipe_stderr.take().map(BufReader::new);
 
-                let pipe_stderr = Arc::new(Mutex::new(String::with_capacity(128)));
+                let pipe_stderr = Arc::new(Mutex::new(output_log("source")));
                 let p_stdr2 = Arc::clone(&pipe_stderr);
 
-                let ffmpeg_stderr = ffmpeg_reader
-                    .is_some()
-                    .then(|| Arc::new(Mutex::new(String::with_capacity(128))));
+                let ffmpeg_stderr =
+                    ffmpeg_reader.is_some().then(|| Arc::new(Mutex::new(output_log("ffmpeg"))));
 
                 let f_stdr2 = ffmpeg_stderr.clone();
 
                 scope.spawn(move || {
                     for line in source_reader.lines() {
                         let mut lock = p_stdr2.lock().expect("mutex should acquire lock");
-                        lock.push_str(&line.expect("should read line successfully"));
-                        lock.push('\n');
+                        lock.push(&line.expect("should read line successfully"));
                     }
                 });
                 if let Some(ffmpeg_reader) = ffmpeg_reader {
@@ -708,20 +1863,33 @@ impl Av1anContext {
                     scope.spawn(move || {
                         for line in ffmpeg_reader.lines() {
                             let mut lock = f_stdr2.lock().expect("mutex should acquire lock");
-                            lock.push_str(&line.expect("should read line successfully"));
-                            lock.push('\n');
+                            lock.push(&line.expect("should read line successfully"));
                         }
                     });
                 }
 
+                // the encoder reads the buffer once every frame was written to it
+                let y4m_pipe = if let Some(buffer) = buffer {
+                    buffer_writer.expect("the buffer should have a writer").wait()?;
+                    buffer.into_reader()?
+                } else {
+                    y4m_pipe
+                };
+
                 let mut enc_pipe = if let [encoder, args @ ..] = &*enc_cmd {
-                    Command::new(encoder)
+                    let mut command = Command::new(encoder);
+                    command
                         .args(args)
+                        .envs(chunk.gpu_env())
                         .stdin(y4m_pipe)
                         .stdout(Stdio::piped())
-                        .stderr(Stdio::piped())
-                        .spawn()
-                        .map_err(|e| (e.into(), 0))?
+                        .stderr(Stdio::piped());
+                    debug!(
+                        target: COMMAND_LOG_TARGET,
+                        "chunk {index:05} pass {current_pass}: encoder: {command:?}",
+                        index = chunk.index
+                    );
+                    command.spawn()?
                 } else {
                     unreachable!()
                 };
@@ -732,7 +1900,7 @@ impl Av1anContext {
                     BufReader::new(enc_pipe.stderr.take().expect("enc_pipe should have stderr"));
 
                 let mut buf = Vec::with_capacity(128);
-                let mut enc_stderr = String::with_capacity(128);
+                let mut enc_stderr = output_log("encoder");
 
                 while let Ok(read) = reader.read_until(b'\r', &mut buf) {
                     if read == 0 {
@@ -745,18 +1913,13 @@ impl Av1anContext {
                         }
                         // This needs to be done before parse_encoded_frames, as it potentially
                         // mutates the string
-                        enc_stderr.push_str(line);
-                        enc_stderr.push('\n');
+                        enc_stderr.push(line);
 
                         if current_pass == chunk.passes
                             && let Some(new) = chunk.encoder.parse_encoded_frames(line)
                             && new > frame
                         {
-                            if self.args.verbosity == Verbosity::Normal {
-                                inc_bar(new - frame);
-                            } else if self.args.verbosity == Verbosity::Verbose {
-                                inc_mp_bar(new - frame);
-                            }
+                            progress.inc(new - frame);
                             frame = new;
                         }
                     }
@@ -767,60 +1930,46 @@ impl Av1anContext {
                 let enc_output = enc_pipe.wait_with_output().expect("enc_pipe should finish");
 
                 let source_pipe_stderr =
-                    pipe_stderr.lock().expect("mutex should acquire lock").clone();
+                    pipe_stderr.lock().expect("mutex should acquire lock").finish();
                 let ffmpeg_pipe_stderr =
-                    ffmpeg_stderr.map(|x| x.lock().expect("mutex should acquire lock").clone());
+                    ffmpeg_stderr.map(|x| x.lock().expect("mutex should acquire lock").finish());
                 Ok((
                     source_pipe_stderr,
                     ffmpeg_pipe_stderr,
                     enc_output,
-                    enc_stderr,
-                    frame,
+                    enc_stderr.finish(),
                 ))
             })?;
 
         if !enc_output.status.success() {
-            return Err((
-                EncoderCrash {
-                    exit_status:        enc_output.status,
-                    source_pipe_stderr: source_pipe_stderr.into(),
-                    ffmpeg_pipe_stderr: ffmpeg_pipe_stderr.map(Into::into),
-                    stderr:             enc_stderr.into(),
-                    stdout:             enc_output.stdout.into(),
-                }
-                .into(),
-                frame,
-            ));
+            return Err(EncoderCrash {
+                exit_status:        enc_output.status,
+                source_pipe_stderr: source_pipe_stderr.into(),
+                ffmpeg_pipe_stderr: ffmpeg_pipe_stderr.map(Into::into),
+                stderr:             enc_stderr.into(),
+                stdout:             enc_output.stdout.into(),
+            }
+            .into());
         }
 
         if current_pass == chunk.passes {
-            if !fs::exists(chunk.output()).map_err(|e| (anyhow::anyhow!("{e}"), frame))?
-                || fs::metadata(chunk.output()).map_err(|e| (anyhow::anyhow!("{e}"), frame))?.len()
-                    == 0
-            {
-                return Err((
-                    anyhow::anyhow!(
-                        "ERROR: Output chunk file {} could not be created. Possible permissions \
-                         or disk space issue?",
-                        chunk.output()
-                    ),
-                    frame,
-                ));
+            if !fs::exists(chunk.output())? || fs::metadata(chunk.output())?.len() == 0 {
+                anyhow::bail!(
+                    "ERROR: Output chunk file {} could not be created. Possible permissions or \
+                     disk space issue?",
+                    chunk.output()
+                );
             }
 
             let encoded_frames = get_num_frames(chunk.output().as_ref());
 
             let err_str = match encoded_frames {
-                Ok(encoded_frames)
-                    if !chunk.ignore_frame_mismatch && encoded_frames != chunk.frames() =>
-                {
-                    Some(format!(
-                        "FRAME MISMATCH: chunk {index}: {encoded_frames}/{expected} \
-                         (actual/expected frames)",
-                        index = chunk.index,
-                        expected = chunk.frames()
-                    ))
-                },
+                Ok(encoded_frames) if !chunk.frames_match(encoded_frames) => Some(format!(
+                    "FRAME MISMATCH: chunk {index}: {encoded_frames}/{expected} (actual/expected \
+                     frames)",
+                    index = chunk.index,
+                    expected = chunk.output_frames()
+                )),
                 Err(error) => Some(format!(
                     "FAILED TO COUNT FRAMES: chunk {index}: {error}",
                     index = chunk.index
@@ -828,25 +1977,49 @@ impl Av1anContext {
                 _ => None,
             };
 
+            let err_str = err_str.or_else(|| {
+                let check = self.args.chunk_check?;
+                concat::check_chunk(
+                    Path::new(&chunk.output()),
+                    chunk.encoder,
+                    self.args.output_pix_format.bit_depth,
+                    check,
+                )
+                .err()
+                .map(|error| {
+                    format!(
+                        "CHUNK CHECK FAILED: chunk {index}: {error:#}",
+                        index = chunk.index
+                    )
+                })
+            });
+
             if let Some(err_str) = err_str {
-                return Err((
-                    EncoderCrash {
-                        exit_status:        enc_output.status,
-                        source_pipe_stderr: source_pipe_stderr.into(),
-                        ffmpeg_pipe_stderr: ffmpeg_pipe_stderr.map(Into::into),
-                        stderr:             enc_stderr.into(),
-                        stdout:             err_str.into(),
-                    }
-                    .into(),
-                    frame,
-                ));
+                return Err(EncoderCrash {
+                    exit_status:        enc_output.status,
+                    source_pipe_stderr: source_pipe_stderr.into(),
+                    ffmpeg_pipe_stderr: ffmpeg_pipe_stderr.map(Into::into),
+                    stderr:             enc_stderr.into(),
+                    stdout:             err_str.into(),
+                }
+                .into());
             }
+
+            return Ok(chunk.encoder.parse_summary(&enc_stderr));
         }
 
-        Ok(())
+        Ok(EncoderSummary::default())
     }
 
-    fn create_encoding_queue(&self, scenes: &[Scene]) -> anyhow::Result<Vec<Chunk>> {
+    /// Creates the chunks of `scenes`, numbered from `first_index`, which is
+    /// only more than 0 for the later batches of scenes of `--sc-lookahead`.
+    /// The segment, hybrid and trim chunk methods split the whole input, so
+    /// they always create every chunk at once.
+    fn create_encoding_queue(
+        &self,
+        scenes: &[Scene],
+        first_index: usize,
+    ) -> anyhow::Result<Vec<Chunk>> {
         let mut chunks = match &self.args.input {
             Input::Video {
                 ..
@@ -858,11 +2031,13 @@ impl Av1anContext {
                     let vs_script =
                         self.vs_script.as_ref().expect("vs_script should exist").as_path();
                     let vs_proxy_script = self.vs_proxy_script.as_deref();
-                    self.create_video_queue_vs(scenes, vs_script, vs_proxy_script, &[])?
+                    self.create_video_queue_vs(scenes, first_index, vs_script, vs_proxy_script, &[
+                    ])?
                 },
                 ChunkMethod::Hybrid => self.create_video_queue_hybrid(scenes)?,
-                ChunkMethod::Select => self.create_video_queue_select(scenes)?,
+                ChunkMethod::Select => self.create_video_queue_select(scenes, first_index)?,
                 ChunkMethod::Segment => self.create_video_queue_segment(scenes)?,
+                ChunkMethod::Trim => self.create_video_queue_trim(scenes)?,
             },
             Input::VapourSynth {
                 path,
@@ -870,12 +2045,30 @@ impl Av1anContext {
                 ..
             } => self.create_video_queue_vs(
                 scenes,
+                first_index,
                 path.as_path(),
                 self.vs_proxy_script.as_deref(),
                 vspipe_args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>().as_slice(),
             )?,
         };
 
+        for chunk in &mut chunks {
+            chunk.passthrough =
+                scenes.get(chunk.index - first_index).is_some_and(Scene::is_passthrough);
+        }
+
+        if let Some(peak) = self.args.peak_bitrate {
+            for chunk in chunks.iter_mut().filter(|chunk| !chunk.passthrough) {
+                if !chunk.apply_bitrate_cap(peak, self.args.peak_buffer) {
+                    warn!(
+                        "{} cannot cap the bitrate, chunk {} is encoded without --peak-bitrate",
+                        chunk.encoder,
+                        chunk.name()
+                    );
+                }
+            }
+        }
+
         match self.args.chunk_order {
             ChunkOrdering::LongestFirst => {
                 chunks.sort_unstable_by_key(|chunk| Reverse(chunk.frames()));
@@ -897,22 +2090,160 @@ impl Av1anContext {
     // If we are not resuming, then do scene detection. Otherwise: get scenes from
     // scenes.json and return that.
     fn split_routine(&mut self) -> anyhow::Result<&[Scene]> {
-        let scene_file = self.args.scenes.as_ref().map_or_else(
-            || Cow::Owned(Path::new(&self.args.temp).join("scenes.json")),
-            |path| Cow::Borrowed(path.as_path()),
-        );
+        let scene_file = self
+            .args
+            .scenes
+            .clone()
+            .unwrap_or_else(|| Path::new(&self.args.temp).join("scenes.json"));
+        let edit_file = self.args.edit_scenes.clone();
+        let edit_file = edit_file.as_deref();
         if scene_file.exists() && (self.args.scenes.is_some() || self.args.resume) {
             self.scene_factory = SceneFactory::from_scenes_file(&scene_file)?;
+        } else if let Some(edit_file) = edit_file.filter(|path| path.exists()) {
+            let frames = self.clip_info.num_frames;
+            self.scene_factory =
+                SceneFactory::from_editable_scenes_file(&edit_file, &self.args, frames)?;
+            self.scene_factory.write_scenes_to_file(scene_file)?;
         } else {
+            self.apply_target_chunk_time()?;
             let zones = parse_zones(&self.args, self.frames)?;
-            validate_zones(&self.args, &zones)?;
+            validate_zones(&self.args, &zones.scenes)?;
             self.scene_factory.compute_scenes(&self.args, &zones)?;
+            if let Some(preview) = &self.args.sc_preview {
+                let sheets = scene_preview::write_contact_sheets(
+                    &self.args.input,
+                    self.scene_factory.get_scenecuts()?,
+                    self.scene_factory.scores(),
+                    preview,
+                    Path::new(&self.args.temp),
+                )
+                .context("Failed to write the scene cut contact sheet")?;
+                for sheet in sheets {
+                    info!("scene cut contact sheet written to {}", sheet.display());
+                }
+            }
+            if let Some(edit_file) = edit_file {
+                self.scene_factory.write_editable_scenes_file(edit_file)?;
+                if !self.args.sc_only && !self.args.assume_yes && io::stdin().is_terminal() {
+                    println!(
+                        "Scenes written to {}. Edit the file if needed, then press Enter to \
+                         continue.",
+                        edit_file.display()
+                    );
+                    io::stdin().read_line(&mut String::new())?;
+                    let frames = self.scene_factory.get_frame_count();
+                    self.scene_factory =
+                        SceneFactory::from_editable_scenes_file(&edit_file, &self.args, frames)?;
+                }
+            }
             self.scene_factory.write_scenes_to_file(scene_file)?;
         }
         self.frames = self.scene_factory.get_frame_count();
         self.scene_factory.get_split_scenes()
     }
 
+    /// Returns the parts of `splits` inside the zones for
+    /// [`EncodeArgs::zones_only`] and inside [`EncodeArgs::trim`], along with
+    /// the frame ranges they cover, and counts only those frames from now on
+    fn partial_splits(
+        &mut self,
+        splits: &[Scene],
+    ) -> anyhow::Result<(Vec<Scene>, Vec<Range<usize>>)> {
+        let mut ranges = if self.args.zones_only {
+            parse_zones(&self.args, self.frames)?.frame_ranges()
+        } else {
+            iter::once(0..self.frames).collect()
+        };
+        if let Some(trim) = &self.args.trim {
+            ranges = ranges
+                .into_iter()
+                .map(|range| range.start.max(trim.start)..range.end.min(trim.end))
+                .filter(|range| !range.is_empty())
+                .collect();
+            ensure!(
+                !ranges.is_empty(),
+                "no zone of --zones-only is within --trim-start and --trim-end"
+            );
+        }
+        let splits = scenes_in_ranges(splits, &ranges);
+        self.frames = splits.iter().map(|scene| scene.end_frame - scene.start_frame).sum();
+        info!(
+            "encoding only the {} frames of {} range(s)",
+            self.frames,
+            ranges.len()
+        );
+        Ok((splits, ranges))
+    }
+
+    /// Sets the extra split length from the measured encoder speed for
+    /// `--target-chunk-time`
+    fn apply_target_chunk_time(&mut self) -> anyhow::Result<()> {
+        let Some(target) = self.args.target_chunk_time else {
+            return Ok(());
+        };
+        let probe = match self.speed_probe {
+            Some(probe) => probe,
+            None => self.probe_encoder_speed()?,
+        };
+        self.speed_probe = Some(probe);
+        let fps = probe.fps;
+        let split_len = ((fps * target.as_secs_f64()).round() as usize).max(1);
+        info!(
+            "encoder runs at {fps:.2} fps, splitting scenes longer than {split_len} frames to \
+             encode each chunk in about {}s",
+            target.as_secs()
+        );
+        self.args.extra_splits_len = Some(split_len);
+        Ok(())
+    }
+
+    /// Returns the window of [`EncodeArgs::sc_lookahead`] if scene detection
+    /// runs while encoding, which it does not when resuming an encode whose
+    /// scene detection finished
+    fn sc_lookahead(&self) -> Option<usize> {
+        self.args.sc_lookahead.filter(|_| {
+            !(self.args.resume && Path::new(&self.args.temp).join("scenes.json").exists())
+        })
+    }
+
+    /// Detects the scenes in windows of `window` frames while the workers
+    /// encode, sending the chunks of every batch of scenes that are not done
+    /// yet to `chunks`. Returns the number of chunks of the encode.
+    fn detect_while_encoding(
+        &self,
+        window: usize,
+        chunks: &mpsc::Sender<Vec<Chunk>>,
+    ) -> anyhow::Result<usize> {
+        let zones = parse_zones(&self.args, self.frames)?;
+        validate_zones(&self.args, &zones.scenes)?;
+
+        let mut scene_factory = SceneFactory::new();
+        let mut queue = Vec::new();
+        scene_factory.compute_scenes_pipelined(&self.args, &zones, window, &mut |scenes| {
+            let first = queue.len();
+            let mut batch = self.create_encoding_queue(scenes, first)?;
+            queue.extend(batch.iter().cloned());
+            // kept up to date, so that an interrupted encode can be resumed
+            save_chunk_queue(&self.args.temp, &queue)?;
+
+            let done = get_done();
+            batch.retain(|chunk| !done.done.contains_key(&chunk.name()));
+            debug!(
+                "scene detection queued chunks {first} to {} ({} to encode)",
+                queue.len() - 1,
+                batch.len()
+            );
+            if !batch.is_empty() {
+                // the workers no longer take chunks if encoding was interrupted
+                let _ = chunks.send(batch);
+            }
+            Ok(())
+        })?;
+        scene_factory.write_scenes_to_file(Path::new(&self.args.temp).join("scenes.json"))?;
+
+        Ok(queue.len())
+    }
+
     fn create_select_chunk(
         &self,
         index: usize,
@@ -920,27 +2251,22 @@ impl Av1anContext {
         start_frame: usize,
         end_frame: usize,
         frame_rate: f64,
-        overrides: Option<ZoneOptions>,
+        overrides: Option<&ZoneOptions>,
     ) -> anyhow::Result<Chunk> {
         assert!(
             start_frame < end_frame,
             "Can't make a chunk with <= 0 frames!"
         );
 
-        let ffmpeg_gen_cmd: Vec<OsString> = into_vec![
-            "ffmpeg",
-            "-y",
-            "-hide_banner",
-            "-loglevel",
-            "error",
-            "-i",
+        let mut ffmpeg_gen_cmd: Vec<OsString> =
+            into_vec!["ffmpeg", "-y", "-hide_banner", "-loglevel", "error"];
+        ffmpeg_gen_cmd.extend(select_frames_args(
             src_path,
-            "-vf",
-            format!(
-                r"select=between(n\,{start}\,{end})",
-                start = start_frame,
-                end = end_frame - 1
-            ),
+            start_frame,
+            end_frame,
+            frame_rate,
+        ));
+        ffmpeg_gen_cmd.extend(into_vec![
             "-pix_fmt",
             self.args.output_pix_format.format.to_pix_fmt_string(),
             "-strict",
@@ -948,65 +2274,72 @@ impl Av1anContext {
             "-f",
             "yuv4mpegpipe",
             "-",
-        ];
+        ]);
 
-        let output_ext = self.args.encoder.output_extension();
-
-        let mut chunk = Chunk {
-            temp: self.args.temp.clone(),
+        let (input, proxy) = self.video_chunk_inputs(src_path, ChunkMethod::Select);
+        let mut chunk = self.new_chunk(
             index,
-            input: Input::Video {
-                path:         src_path.to_path_buf(),
-                temp:         self.args.temp.clone(),
-                chunk_method: ChunkMethod::Select,
-                is_proxy:     false,
-                cache_mode:   self.args.cache_mode,
-            },
-            proxy: self.args.proxy.as_ref().map(|proxy| Input::Video {
-                path:         proxy.as_path().to_path_buf(),
-                temp:         self.args.temp.clone(),
-                chunk_method: ChunkMethod::Select,
-                is_proxy:     true,
-                cache_mode:   self.args.cache_mode,
-            }),
-            source_cmd: ffmpeg_gen_cmd,
-            proxy_cmd: None,
-            output_ext: output_ext.to_owned(),
-            start_frame,
-            end_frame,
+            (start_frame, end_frame),
             frame_rate,
-            video_params: overrides.as_ref().map_or_else(
-                || self.args.video_params.clone(),
-                |ovr| ovr.video_params.clone(),
-            ),
-            passes: overrides.as_ref().map_or(self.args.passes, |ovr| ovr.passes),
-            encoder: overrides.as_ref().map_or(self.args.encoder, |ovr| ovr.encoder),
-            noise_size: self.args.photon_noise_size,
-            target_quality: overrides.as_ref().map_or_else(
-                || self.args.target_quality.clone(),
-                |ovr| {
-                    ovr.target_quality.clone().unwrap_or_else(|| self.args.target_quality.clone())
-                },
-            ),
-            tq_cq: None,
-            ignore_frame_mismatch: self.args.ignore_frame_mismatch,
-        };
-        let color_range = self.args.input.clip_info()?.color_range;
-        chunk.apply_photon_noise_args(
-            overrides.map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
-            self.args.chroma_noise,
-            color_range,
+            overrides,
+            input,
+            proxy,
+            ffmpeg_gen_cmd,
+            None,
         )?;
         if chunk.target_quality.target.is_some() {
             chunk.tq_cq = Some(chunk.target_quality.per_shot_target_quality(
                 &chunk,
                 None,
                 self.args.vapoursynth_plugins,
+                &self.res_correction,
             )?);
         }
         Ok(chunk)
     }
 
+    /// Switch a chunk read through the LSMASH or FFMS2 loadscript over to a
+    /// BestSource loadscript, for when the chunk method decodes it wrongly.
+    /// Returns whether the chunk was switched.
+    pub(crate) fn fall_back_to_bestsource(&self, chunk: &mut Chunk) -> anyhow::Result<bool> {
+        if !self.args.input.is_video()
+            || !matches!(
+                self.args.chunk_method,
+                ChunkMethod::LSMASH | ChunkMethod::FFMS2
+            )
+            || !self.args.vapoursynth_plugins.is_some_and(|p| p.bestsource)
+        {
+            return Ok(false);
+        }
+        // only chunks that still read the loadscript of the chunk method
+        let Some(vs_script) = self.vs_script.as_deref() else {
+            return Ok(false);
+        };
+        let Some(script_arg) = chunk.source_cmd.get_mut(1) else {
+            return Ok(false);
+        };
+        if Path::new(script_arg.as_os_str()) != vs_script {
+            return Ok(false);
+        }
+
+        let (script_path, text) = create_bestsource_vs_file(
+            &self.args.temp,
+            self.args.input.as_path(),
+            self.args.cache_mode,
+        )?;
+        *script_arg = script_path.clone().into_os_string();
+        if let Input::VapourSynth {
+            path,
+            script_text,
+            ..
+        } = &mut chunk.input
+        {
+            *path = script_path;
+            *script_text = text;
+        }
+        Ok(true)
+    }
+
     fn create_vs_chunk(
         &self,
         index: usize,
@@ -1023,6 +2356,7 @@ impl Av1anContext {
         fn gen_vspipe_cmd(
             vs_script: &Path,
             vs_args: &[&str],
+            chunk_args: &[String],
             scene_start: usize,
             scene_end: usize,
         ) -> Vec<OsString> {
@@ -1037,84 +2371,149 @@ impl Av1anContext {
                 "-e",
                 scene_end.to_string(),
             ];
-            for arg in vs_args {
+            for arg in vs_args.iter().copied().chain(chunk_args.iter().map(String::as_str)) {
                 command.push("-a".into());
                 command.push(arg.into());
             }
             command
         }
 
-        let vspipe_cmd_gen = gen_vspipe_cmd(vs_script, vspipe_args, scene.start_frame, frame_end);
+        // Built-in variables describing this chunk, for scripts that filter
+        // differently depending on the frames being encoded
+        let chunk_args = chunk_vspipe_args(
+            index,
+            scene.start_frame,
+            scene.end_frame,
+            Path::new(&self.args.temp),
+        );
+        let vspipe_cmd_gen = gen_vspipe_cmd(
+            vs_script,
+            vspipe_args,
+            &chunk_args,
+            scene.start_frame,
+            frame_end,
+        );
         let vspipe_proxy_cmd_gen = vs_proxy_script.map(|vs_proxy_script| {
-            gen_vspipe_cmd(vs_proxy_script, vspipe_args, scene.start_frame, frame_end)
+            gen_vspipe_cmd(
+                vs_proxy_script,
+                vspipe_args,
+                &chunk_args,
+                scene.start_frame,
+                frame_end,
+            )
         });
 
-        let output_ext = self.args.encoder.output_extension();
+        let input = Input::VapourSynth {
+            path:        vs_script.to_path_buf(),
+            vspipe_args: self.args.input.as_vspipe_args_vec()?,
+            script_text: self.args.input.as_script_text()?,
+            is_proxy:    false,
+        };
+        let proxy = if let Some(vs_proxy_script) = vs_proxy_script {
+            Some(Input::VapourSynth {
+                path:        vs_proxy_script.to_path_buf(),
+                vspipe_args: self
+                    .args
+                    .proxy
+                    .as_ref()
+                    .expect("proxy should be set")
+                    .as_vspipe_args_vec()?,
+                script_text: self
+                    .args
+                    .proxy
+                    .as_ref()
+                    .expect("proxy should be set")
+                    .as_script_text()?,
+                is_proxy:    true,
+            })
+        } else {
+            None
+        };
+        self.new_chunk(
+            index,
+            (scene.start_frame, scene.end_frame),
+            frame_rate,
+            scene.zone_overrides.as_ref(),
+            input,
+            proxy,
+            vspipe_cmd_gen,
+            vspipe_proxy_cmd_gen,
+        )
+    }
+
+    /// The input of a chunk read from a video file with `chunk_method`, and
+    /// of its proxy if `--proxy` is set.
+    fn video_chunk_inputs(&self, path: &Path, chunk_method: ChunkMethod) -> (Input, Option<Input>) {
+        let input = Input::Video {
+            path: path.to_path_buf(),
+            temp: self.args.temp.clone(),
+            chunk_method,
+            is_proxy: false,
+            cache_mode: self.args.cache_mode,
+        };
+        let proxy = self.args.proxy.as_ref().map(|proxy| Input::Video {
+            path: proxy.as_path().to_path_buf(),
+            temp: self.args.temp.clone(),
+            chunk_method,
+            is_proxy: true,
+            cache_mode: self.args.cache_mode,
+        });
+        (input, proxy)
+    }
 
+    /// Builds the chunk of the frames `start_frame..end_frame` out of its
+    /// source commands, taking the encoder settings from the zone `overrides`
+    /// if there are any, or from the global arguments otherwise.
+    #[expect(clippy::too_many_arguments)]
+    fn new_chunk(
+        &self,
+        index: usize,
+        (start_frame, end_frame): (usize, usize),
+        frame_rate: f64,
+        overrides: Option<&ZoneOptions>,
+        input: Input,
+        proxy: Option<Input>,
+        source_cmd: Vec<OsString>,
+        proxy_cmd: Option<Vec<OsString>>,
+    ) -> anyhow::Result<Chunk> {
         let mut chunk = Chunk {
             temp: self.args.temp.clone(),
             index,
-            input: Input::VapourSynth {
-                path:        vs_script.to_path_buf(),
-                vspipe_args: self.args.input.as_vspipe_args_vec()?,
-                script_text: self.args.input.as_script_text()?,
-                is_proxy:    false,
-            },
-            proxy: if let Some(vs_proxy_script) = vs_proxy_script {
-                Some(Input::VapourSynth {
-                    path:        vs_proxy_script.to_path_buf(),
-                    vspipe_args: self
-                        .args
-                        .proxy
-                        .as_ref()
-                        .expect("proxy should be set")
-                        .as_vspipe_args_vec()?,
-                    script_text: self
-                        .args
-                        .proxy
-                        .as_ref()
-                        .expect("proxy should be set")
-                        .as_script_text()?,
-                    is_proxy:    true,
-                })
-            } else {
-                None
-            },
-            source_cmd: vspipe_cmd_gen,
-            proxy_cmd: vspipe_proxy_cmd_gen,
-            output_ext: output_ext.to_owned(),
-            start_frame: scene.start_frame,
-            end_frame: scene.end_frame,
+            input,
+            proxy,
+            source_cmd,
+            proxy_cmd,
+            output_ext: self.args.encoder.output_extension().to_owned(),
+            start_frame,
+            end_frame,
             frame_rate,
-            video_params: scene.zone_overrides.as_ref().map_or_else(
+            video_params: overrides.map_or_else(
                 || self.args.video_params.clone(),
                 |ovr| ovr.video_params.clone(),
             ),
-            passes: scene.zone_overrides.as_ref().map_or(self.args.passes, |ovr| ovr.passes),
-            encoder: scene.zone_overrides.as_ref().map_or(self.args.encoder, |ovr| ovr.encoder),
-            noise_size: scene.zone_overrides.as_ref().map_or(self.args.photon_noise_size, |ovr| {
+            passes: overrides.map_or(self.args.passes, |ovr| ovr.passes),
+            encoder: overrides.map_or(self.args.encoder, |ovr| ovr.encoder),
+            noise_size: overrides.map_or(self.args.photon_noise_size, |ovr| {
                 (ovr.photon_noise_width, ovr.photon_noise_height)
             }),
-            target_quality: scene.zone_overrides.as_ref().map_or_else(
+            target_quality: overrides.map_or_else(
                 || self.args.target_quality.clone(),
                 |ovr| {
                     ovr.target_quality.clone().unwrap_or_else(|| self.args.target_quality.clone())
                 },
             ),
             tq_cq: None,
+            ffmpeg_filter: overrides.and_then(|ovr| ovr.ffmpeg_filter.clone()),
             ignore_frame_mismatch: self.args.ignore_frame_mismatch,
+            output_frame_rate: self.output_frame_rate(),
+            passthrough: false,
+            gpu: None,
+            output_names: self.args.chunk_names.clone(),
         };
-        let color_range = self.args.input.clip_info()?.color_range;
         chunk.apply_photon_noise_args(
-            scene
-                .zone_overrides
-                .as_ref()
-                .map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
-            scene
-                .zone_overrides
-                .as_ref()
-                .map_or(self.args.chroma_noise, |ovr| ovr.chroma_noise),
-            color_range,
+            overrides.map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
+            overrides.map_or(self.args.chroma_noise, |ovr| ovr.chroma_noise),
+            self.clip_info.color_range,
         )?;
         Ok(chunk)
     }
@@ -1122,23 +2521,18 @@ impl Av1anContext {
     fn create_video_queue_vs(
         &self,
         scenes: &[Scene],
+        first_index: usize,
         vs_script: &Path,
         vs_proxy_script: Option<&Path>,
         vspipe_args: &[&str],
     ) -> anyhow::Result<Vec<Chunk>> {
-        let frame_rate = self
-            .args
-            .input
-            .clip_info()?
-            .frame_rate
-            .to_f64()
-            .expect("frame rate should not be NaN");
+        let frame_rate = self.clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
         let chunk_queue: Vec<Chunk> = scenes
             .iter()
             .enumerate()
             .map(|(index, scene)| {
                 self.create_vs_chunk(
-                    index,
+                    first_index + index,
                     vs_script,
                     vs_proxy_script,
                     vspipe_args,
@@ -1151,27 +2545,25 @@ impl Av1anContext {
         Ok(chunk_queue)
     }
 
-    fn create_video_queue_select(&self, scenes: &[Scene]) -> anyhow::Result<Vec<Chunk>> {
+    fn create_video_queue_select(
+        &self,
+        scenes: &[Scene],
+        first_index: usize,
+    ) -> anyhow::Result<Vec<Chunk>> {
         let input = self.args.input.as_video_path();
-        let frame_rate = self
-            .args
-            .input
-            .clip_info()?
-            .frame_rate
-            .to_f64()
-            .expect("frame rate should not be NaN");
+        let frame_rate = self.clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
 
         let chunk_queue: Vec<Chunk> = scenes
             .iter()
             .enumerate()
             .map(|(index, scene)| {
                 self.create_select_chunk(
-                    index,
+                    first_index + index,
                     input,
                     scene.start_frame,
                     scene.end_frame,
                     frame_rate,
-                    scene.zone_overrides.clone(),
+                    scene.zone_overrides.as_ref(),
                 )
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -1181,20 +2573,25 @@ impl Av1anContext {
 
     fn create_video_queue_segment(&self, scenes: &[Scene]) -> anyhow::Result<Vec<Chunk>> {
         let input = self.args.input.as_video_path();
-        let frame_rate = self
-            .args
-            .input
-            .clip_info()?
-            .frame_rate
-            .to_f64()
-            .expect("frame rate should not be NaN");
+        let frame_rate = self.clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
+
+        let splits = scenes.iter().skip(1).map(|scene| scene.start_frame).collect::<Vec<usize>>();
+
+        // ffmpeg can only segment on keyframes, so scene boundaries that fall
+        // between keyframes would end up in the wrong chunk
+        let keyframes = crate::ffmpeg::get_keyframes(input)?;
+        let unaligned = unaligned_splits(&splits, &keyframes);
+        if !unaligned.is_empty() {
+            warn!(
+                "{count} scene boundaries do not fall on keyframes, falling back to hybrid \
+                 chunking: {unaligned:?}",
+                count = unaligned.len()
+            );
+            return self.create_video_queue_hybrid(scenes);
+        }
 
         debug!("Splitting video");
-        segment(
-            input,
-            &self.args.temp,
-            &scenes.iter().skip(1).map(|scene| scene.start_frame).collect::<Vec<usize>>(),
-        )?;
+        segment(input, &self.args.temp, &splits)?;
         debug!("Splitting done");
 
         let source_path = Path::new(&self.args.temp).join("split");
@@ -1205,6 +2602,19 @@ impl Av1anContext {
             "Error: No files found in temp/split, probably splitting not working"
         );
 
+        if queue_files.len() != scenes.len() {
+            warn!(
+                "Segmenting produced {segments} segments for {expected} scenes, falling back to \
+                 hybrid chunking",
+                segments = queue_files.len(),
+                expected = scenes.len()
+            );
+            for file in &queue_files {
+                fs::remove_file(file)?;
+            }
+            return self.create_video_queue_hybrid(scenes);
+        }
+
         let chunk_queue: Vec<Chunk> = queue_files
             .iter()
             .enumerate()
@@ -1213,23 +2623,35 @@ impl Av1anContext {
                     index,
                     &file.as_path().to_string_lossy(),
                     frame_rate,
-                    scenes[index].zone_overrides.clone(),
+                    scenes[index].zone_overrides.as_ref(),
                 )
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
 
+        if let Some((chunk, scene)) = chunk_queue
+            .iter()
+            .zip(scenes)
+            .find(|(chunk, scene)| chunk.frames() != scene.end_frame - scene.start_frame)
+        {
+            warn!(
+                "Segment {index} has {actual} frames but its scene has {expected}, falling back \
+                 to hybrid chunking",
+                index = chunk.index,
+                actual = chunk.frames(),
+                expected = scene.end_frame - scene.start_frame
+            );
+            for file in &queue_files {
+                fs::remove_file(file)?;
+            }
+            return self.create_video_queue_hybrid(scenes);
+        }
+
         Ok(chunk_queue)
     }
 
     fn create_video_queue_hybrid(&self, scenes: &[Scene]) -> anyhow::Result<Vec<Chunk>> {
         let input = self.args.input.as_video_path();
-        let frame_rate = self
-            .args
-            .input
-            .clip_info()?
-            .frame_rate
-            .to_f64()
-            .expect("frame rate should not be NaN");
+        let frame_rate = self.clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
 
         let keyframes = crate::ffmpeg::get_keyframes(input)?;
 
@@ -1269,7 +2691,7 @@ impl Av1anContext {
                     start,
                     end,
                     frame_rate,
-                    scene.zone_overrides.clone(),
+                    scene.zone_overrides.as_ref(),
                 )
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -1277,13 +2699,100 @@ impl Av1anContext {
         Ok(chunk_queue)
     }
 
+    fn create_video_queue_trim(&self, scenes: &[Scene]) -> anyhow::Result<Vec<Chunk>> {
+        let input = self.args.input.as_video_path();
+        let frame_rate = self.clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
+
+        let keyframes = crate::ffmpeg::get_keyframes(input)?;
+        let splits = keyframe_splits(scenes.iter().map(|scene| scene.start_frame), &keyframes);
+
+        debug!("Segmenting video");
+        segment(input, &self.args.temp, &splits)?;
+        debug!("Segment done");
+
+        let source_path = Path::new(&self.args.temp).join("split");
+        let queue_files = Self::read_queue_files(&source_path)?;
+        if queue_files.len() != splits.len() + 1 {
+            warn!(
+                "Segmenting produced {segments} segments instead of {expected}, falling back to \
+                 select chunking",
+                segments = queue_files.len(),
+                expected = splits.len() + 1
+            );
+            for file in &queue_files {
+                fs::remove_file(file)?;
+            }
+            return self.create_video_queue_select(scenes, 0);
+        }
+
+        scenes
+            .iter()
+            .enumerate()
+            .map(|(index, scene)| {
+                let (input_args, filter) = trim_input(
+                    &queue_files,
+                    &splits,
+                    index,
+                    scene.start_frame,
+                    scene.end_frame,
+                )?;
+                self.create_trim_chunk(index, input_args, &filter, scene, frame_rate)
+            })
+            .collect()
+    }
+
+    fn create_trim_chunk(
+        &self,
+        index: usize,
+        input_args: Vec<OsString>,
+        filter: &str,
+        scene: &Scene,
+        frame_rate: f64,
+    ) -> anyhow::Result<Chunk> {
+        let mut ffmpeg_gen_cmd: Vec<OsString> =
+            into_vec!["ffmpeg", "-y", "-hide_banner", "-loglevel", "error"];
+        ffmpeg_gen_cmd.extend(input_args);
+        ffmpeg_gen_cmd.extend(into_vec![
+            "-vf",
+            filter,
+            "-pix_fmt",
+            self.args.output_pix_format.format.to_pix_fmt_string(),
+            "-strict",
+            "-1",
+            "-f",
+            "yuv4mpegpipe",
+            "-",
+        ]);
+
+        let (input, proxy) = self.video_chunk_inputs(self.args.input.as_path(), ChunkMethod::Trim);
+        let mut chunk = self.new_chunk(
+            index,
+            (scene.start_frame, scene.end_frame),
+            frame_rate,
+            scene.zone_overrides.as_ref(),
+            input,
+            proxy,
+            ffmpeg_gen_cmd,
+            None,
+        )?;
+        if chunk.target_quality.target.is_some() {
+            chunk.tq_cq = Some(chunk.target_quality.per_shot_target_quality(
+                &chunk,
+                None,
+                self.args.vapoursynth_plugins,
+                &self.res_correction,
+            )?);
+        }
+        Ok(chunk)
+    }
+
     #[tracing::instrument(level = "debug")]
     fn create_chunk_from_segment(
         &self,
         index: usize,
         file: &str,
         frame_rate: f64,
-        overrides: Option<ZoneOptions>,
+        overrides: Option<&ZoneOptions>,
     ) -> anyhow::Result<Chunk> {
         let ffmpeg_gen_cmd: Vec<OsString> = into_vec![
             "ffmpeg",
@@ -1302,62 +2811,264 @@ impl Av1anContext {
             "-",
         ];
 
-        let output_ext = self.args.encoder.output_extension();
-
         let num_frames = get_num_frames(Path::new(file))?;
 
-        let mut chunk = Chunk {
-            temp: self.args.temp.clone(),
-            input: Input::Video {
-                path:         PathBuf::from(file),
-                temp:         self.args.temp.clone(),
-                chunk_method: ChunkMethod::Segment,
-                is_proxy:     false,
-                cache_mode:   self.args.cache_mode,
-            },
-            proxy: self.args.proxy.as_ref().map(|proxy| Input::Video {
-                path:         proxy.as_path().to_path_buf(),
-                temp:         self.args.temp.clone(),
-                chunk_method: ChunkMethod::Segment,
-                is_proxy:     true,
-                cache_mode:   self.args.cache_mode,
-            }),
-            source_cmd: ffmpeg_gen_cmd,
-            proxy_cmd: None,
-            output_ext: output_ext.to_owned(),
+        let (input, proxy) = self.video_chunk_inputs(Path::new(file), ChunkMethod::Segment);
+        self.new_chunk(
             index,
-            start_frame: 0,
-            end_frame: num_frames,
+            (0, num_frames),
             frame_rate,
-            video_params: overrides.as_ref().map_or_else(
-                || self.args.video_params.clone(),
-                |ovr| ovr.video_params.clone(),
-            ),
-            passes: overrides.as_ref().map_or(self.args.passes, |ovr| ovr.passes),
-            encoder: overrides.as_ref().map_or(self.args.encoder, |ovr| ovr.encoder),
-            noise_size: self.args.photon_noise_size,
-            target_quality: overrides.as_ref().map_or_else(
-                || self.args.target_quality.clone(),
-                |ovr| {
-                    ovr.target_quality.clone().unwrap_or_else(|| self.args.target_quality.clone())
+            overrides,
+            input,
+            proxy,
+            ffmpeg_gen_cmd,
+            None,
+        )
+    }
+
+    /// Encode a short section from the middle of the input with the chosen
+    /// encoder settings, to measure the encoding speed and bitrate
+    fn probe_encoder_speed(&self) -> anyhow::Result<SpeedProbe> {
+        const SPEED_PROBE_FRAMES: usize = 120;
+
+        let clip_info = self.clip_info;
+        let frame_rate = clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
+        let probe_frames = SPEED_PROBE_FRAMES.min(clip_info.num_frames);
+        let start_frame = (clip_info.num_frames - probe_frames) / 2;
+        let scene = Scene {
+            start_frame,
+            end_frame: start_frame + probe_frames,
+            zone_overrides: None,
+        };
+
+        let chunk = match (&self.args.input, &self.vs_script) {
+            (
+                Input::VapourSynth {
+                    path,
+                    vspipe_args,
+                    ..
                 },
-            ),
-            tq_cq: None,
-            ignore_frame_mismatch: self.args.ignore_frame_mismatch,
+                _,
+            ) => self.create_vs_chunk(
+                0,
+                path,
+                None,
+                &vspipe_args.iter().map(String::as_str).collect_vec(),
+                &scene,
+                frame_rate,
+            )?,
+            (
+                Input::Video {
+                    ..
+                },
+                Some(vs_script),
+            ) => self.create_vs_chunk(0, vs_script, None, &[], &scene, frame_rate)?,
+            (
+                Input::Video {
+                    path, ..
+                },
+                None,
+            ) => self.create_select_chunk(
+                0,
+                path,
+                scene.start_frame,
+                scene.end_frame,
+                frame_rate,
+                None,
+            )?,
         };
-        let color_range = self.args.input.clip_info()?.color_range;
-        chunk.apply_photon_noise_args(
-            overrides.map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
-            self.args.chroma_noise,
-            color_range,
-        )?;
-        Ok(chunk)
+
+        let probe_temp = Path::new(&self.args.temp).join("speed-probe");
+        create_dir!(probe_temp.join("split"))?;
+        create_dir!(probe_temp.join("encode"))?;
+        let chunk = Chunk {
+            temp: probe_temp.to_string_lossy().to_string(),
+            ..chunk
+        };
+
+        info!("measuring encoder speed on {probe_frames} frames");
+        let start = Instant::now();
+        for current_pass in 1..=chunk.passes {
+            self.create_pipes(
+                &chunk,
+                current_pass,
+                0,
+                1,
+                &ChunkProgress::new(Verbosity::Quiet),
+            )
+            .context("Failed to encode the speed probe")?;
+        }
+        let elapsed = start.elapsed();
+        let size = fs::metadata(chunk.output())?.len();
+
+        if let Err(e) = fs::remove_dir_all(&probe_temp) {
+            warn!("Failed to delete speed probe directory: {e}");
+        }
+
+        Ok(SpeedProbe {
+            fps:             chunk.frames() as f64 / elapsed.as_secs_f64(),
+            bytes_per_frame: size as f64 / chunk.frames() as f64,
+        })
+    }
+
+    /// Raise the worker count and step the encoder speed preset up until the
+    /// encode is estimated to finish within `deadline`. The worker count is
+    /// only raised up to the one Av1an picks on its own, and presets are only
+    /// stepped once more workers are not enough.
+    fn fit_deadline(&mut self, deadline: Duration) -> anyhow::Result<()> {
+        let encoder = self.args.encoder;
+        let frames = self.frames as f64;
+        let estimate = |probe: SpeedProbe, workers: usize| {
+            Duration::from_secs_f64(frames / probe.fps / workers as f64)
+        };
+
+        let mut probe = self.probe_encoder_speed()?;
+        let auto_workers = determine_workers(&self.args)? as usize;
+        let mut workers = if self.args.workers == 0 {
+            auto_workers
+        } else {
+            self.args.workers
+        };
+        let initial_speed = encoder.get_speed(&self.args.video_params);
+        let initial_estimate = estimate(probe, workers);
+
+        let raise_workers = initial_estimate > deadline && workers < auto_workers;
+        if raise_workers {
+            workers = auto_workers;
+            self.args.workers = workers;
+        }
+        while estimate(probe, workers) > deadline {
+            let Some(params) = encoder.faster_speed(self.args.video_params.clone()) else {
+                warn!(
+                    "--deadline {}: the fastest {encoder} preset is estimated to take {}, the \
+                     encode will likely miss the deadline",
+                    HumanDuration(deadline),
+                    HumanDuration(estimate(probe, workers))
+                );
+                break;
+            };
+            self.args.video_params = params;
+            probe = self.probe_encoder_speed()?;
+        }
+        self.speed_probe = Some(probe);
+
+        let speed = encoder.get_speed(&self.args.video_params);
+        if speed == initial_speed && !raise_workers {
+            info!(
+                "--deadline {}: the encode is estimated to take {} with {workers} worker(s), \
+                 keeping {encoder} speed {speed}",
+                HumanDuration(deadline),
+                HumanDuration(initial_estimate)
+            );
+        } else {
+            info!(
+                "--deadline {}: {encoder} speed {initial_speed} -> {speed}, {workers} worker(s), \
+                 estimated {} instead of {}",
+                HumanDuration(deadline),
+                HumanDuration(estimate(probe, workers)),
+                HumanDuration(initial_estimate)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Print what is about to be encoded, and unless `--yes` was passed, ask
+    /// for confirmation before starting. Returns whether encoding should
+    /// start.
+    fn confirm_plan(&mut self, chunks: &[Chunk]) -> anyhow::Result<bool> {
+        let probe = match self.speed_probe {
+            Some(probe) => probe,
+            None => {
+                let probe = self.probe_encoder_speed()?;
+                self.speed_probe = Some(probe);
+                probe
+            },
+        };
+        let frames = chunks.iter().map(Chunk::frames).sum::<usize>();
+        let workers = if self.args.workers == 0 {
+            determine_workers(&self.args)? as usize
+        } else {
+            self.args.workers
+        };
+        let workers = workers.clamp(1, chunks.len().max(1));
+        let estimated_time = Duration::from_secs_f64(frames as f64 / probe.fps / workers as f64);
+        let output_size = (probe.bytes_per_frame * frames as f64) as u64;
+        // Segmenting copies the remaining part of the input into the
+        // temporary directory
+        let split_size = if !self.args.resume
+            && self.args.input.is_video()
+            && matches!(
+                self.args.chunk_method,
+                ChunkMethod::Segment | ChunkMethod::Hybrid | ChunkMethod::Trim
+            ) {
+            fs::metadata(self.args.input.as_path())?.len()
+        } else {
+            0
+        };
+
+        println!("{}", "Encode plan".bold());
+        println!("  chunks: {}, frames: {frames}", chunks.len());
+        println!(
+            "  estimated time: {} with {workers} worker(s), the encoder ran at {:.2} fps with a \
+             single worker",
+            HumanDuration(estimated_time),
+            probe.fps
+        );
+        println!(
+            "  estimated output size: {}, temporary files: {}",
+            HumanBytes(output_size),
+            HumanBytes(output_size + split_size)
+        );
+        println!("  settings:");
+        let settings = self.args.summary();
+        for (key, value) in &settings {
+            println!("    {key}: {value}");
+        }
+        match &self.previous_report {
+            Some(previous) => {
+                let diff = previous.settings_diff(&settings);
+                if diff.is_empty() {
+                    println!("  settings are unchanged from the last run");
+                } else {
+                    println!("  {}", "settings changed since the last run:".yellow());
+                    for line in diff {
+                        println!("    {line}");
+                    }
+                }
+            },
+            None => println!("  no previous run found in the temporary directory"),
+        }
+
+        if self.args.assume_yes {
+            return Ok(true);
+        }
+        ensure!(
+            io::stdin().is_terminal(),
+            "--interactive needs a terminal to ask for confirmation, pass --yes to skip it"
+        );
+        print!("Start encoding? [y/N]: ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
     }
 
     /// Returns unfinished chunks and number of total chunks
     fn load_or_gen_chunk_queue(&self, splits: &[Scene]) -> anyhow::Result<(Vec<Chunk>, usize)> {
         if self.args.resume {
             let mut chunks = read_chunk_queue(self.args.temp.as_ref())?;
+            if chunks.len() < splits.len() {
+                // scene detection of an encode with --sc-lookahead was
+                // interrupted, and finished without it
+                info!(
+                    "chunks.json only has {} of {} chunks, creating them again",
+                    chunks.len(),
+                    splits.len()
+                );
+                chunks = self.create_encoding_queue(splits, 0)?;
+                save_chunk_queue(&self.args.temp, &chunks)?;
+            }
             let num_chunks = chunks.len();
 
             let done = get_done();
@@ -1367,10 +3078,150 @@ impl Av1anContext {
 
             Ok((chunks, num_chunks))
         } else {
-            let chunks = self.create_encoding_queue(splits)?;
+            let mut chunks = self.create_encoding_queue(splits, 0)?;
+            if let Some(strength) = self.args.two_stage {
+                self.two_stage_preview(&mut chunks, strength)?;
+            }
             let num_chunks = chunks.len();
             save_chunk_queue(&self.args.temp, &chunks)?;
             Ok((chunks, num_chunks))
         }
     }
+
+    /// Runs a fast preview encode of every chunk, then sets the quantizer of
+    /// each chunk for the final encode based on how many bits its preview
+    /// needed compared to the rest of the video.
+    fn two_stage_preview(&self, chunks: &mut [Chunk], strength: f32) -> anyhow::Result<()> {
+        // Chunks using target quality already choose their own quantizer
+        let eligible = chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| chunk.target_quality.target.is_none())
+            .filter_map(|(i, chunk)| chunk.encoder.get_q(&chunk.video_params).map(|q| (i, q)))
+            .collect_vec();
+        if eligible.len() < chunks.len() {
+            warn!(
+                "two-stage: {} chunk(s) use target quality or have no q/crf set in their video \
+                 params, these will not be adjusted",
+                chunks.len() - eligible.len()
+            );
+        }
+        if eligible.is_empty() {
+            return Ok(());
+        }
+
+        let preview_temp = Path::new(&self.args.temp).join("preview");
+        create_dir!(preview_temp.join("split"))?;
+        create_dir!(preview_temp.join("encode"))?;
+        let previews = eligible
+            .iter()
+            .map(|&(i, _)| {
+                let chunk = &chunks[i];
+                Chunk {
+                    temp: preview_temp.to_string_lossy().to_string(),
+                    passes: 1,
+                    video_params: chunk.encoder.preview_command(chunk.video_params.clone()),
+                    tq_cq: None,
+                    ..chunk.clone()
+                }
+            })
+            .collect_vec();
+
+        let workers = if self.args.workers == 0 {
+            determine_workers(&self.args)? as usize
+        } else {
+            self.args.workers
+        };
+        let workers = workers.clamp(1, previews.len());
+        let preview_frames = previews.iter().map(Chunk::frames).sum::<usize>() as u64;
+        info!(
+            "two-stage: preview encoding {} chunk(s) with {workers} worker(s)",
+            previews.len()
+        );
+        if self.args.verbosity == Verbosity::Normal {
+            init_progress_bar(preview_frames, 0, None);
+        } else if self.args.verbosity == Verbosity::Verbose {
+            init_multi_progress_bar(preview_frames, workers, 0, (0, previews.len() as u32));
+        }
+
+        let padding = printable_base10_digits(chunks.len() - 1) as usize;
+        let next_preview = AtomicUsize::new(0);
+        thread::scope(|scope| -> anyhow::Result<()> {
+            let handles = (0..workers)
+                .map(|worker_id| {
+                    let previews = &previews;
+                    let next_preview = &next_preview;
+                    scope.spawn(move || -> anyhow::Result<()> {
+                        while let Some(preview) =
+                            previews.get(next_preview.fetch_add(1, atomic::Ordering::SeqCst))
+                        {
+                            let progress = ChunkProgress::new(self.args.verbosity);
+                            for r#try in 1..=self.args.max_tries {
+                                let Err(e) =
+                                    self.create_pipes(preview, 1, worker_id, padding, &progress)
+                                else {
+                                    progress.commit(preview.frames() as u64);
+                                    break;
+                                };
+                                progress.rollback();
+                                if r#try == self.args.max_tries {
+                                    anyhow::bail!(
+                                        "[chunk {index}] preview encode failed {tries} times: {e}",
+                                        index = preview.index,
+                                        tries = self.args.max_tries
+                                    );
+                                }
+                                warn!(
+                                    "Preview encoder failed (on chunk {index}):\n{e}",
+                                    index = preview.index
+                                );
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect_vec();
+            for handle in handles {
+                handle.join().expect("thread should join successfully")?;
+            }
+            Ok(())
+        })?;
+        finish_progress_bar();
+
+        let bits_per_frame = previews
+            .iter()
+            .map(|preview| {
+                Ok(fs::metadata(preview.output())?.len() as f64 * 8.0 / preview.frames() as f64)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let offsets = q_offsets(&bits_per_frame, strength);
+        for (&(i, base_q), offset) in eligible.iter().zip(offsets) {
+            let chunk = &mut chunks[i];
+            let q = (base_q + offset).round().clamp(0.0, chunk.encoder.get_max_q());
+            debug!(
+                "two-stage: chunk {index:05} q {base_q} -> {q}",
+                index = chunk.index
+            );
+            chunk.tq_cq = Some(q);
+        }
+        let (min_q, max_q) = eligible
+            .iter()
+            .filter_map(|&(i, _)| chunks[i].tq_cq)
+            .minmax_by(f32::total_cmp)
+            .into_option()
+            .expect("eligible chunks have a q set");
+        info!("two-stage: final encode q ranges from {min_q} to {max_q}");
+
+        Ok(())
+    }
+}
+
+/// Asks whether to encode `count` chunks that scored worse than
+/// `--min-chunk-score` again
+fn ask_reencode(count: usize) -> anyhow::Result<bool> {
+    print!("Encode the {count} chunk(s) again at a lower quantizer? [y/N]: ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
@@ -0,0 +1,93 @@
+//! Commands that change a running encode
+//!
+//! The commands are read from the `control` file of the temporary directory,
+//! one `key=value` pair per line, e.g. `workers=8`. The file is removed once
+//! it was read, so every command is applied once.
+
+use std::{fs, io, path::Path};
+
+use anyhow::{bail, Context};
+
+/// Name of the file in the temporary directory the commands are read from
+pub(crate) const CONTROL_FILE: &str = "control";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ControlCommands {
+    /// Number of workers to encode with from now on
+    pub workers: Option<usize>,
+}
+
+impl ControlCommands {
+    /// Parses one `key=value` command per line, skipping empty lines and
+    /// lines starting with `#`
+    pub(crate) fn parse(commands: &str) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        for line in commands.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("expected a command of the form `key=value`, found `{line}`");
+            };
+            let value = value.trim();
+            match key.trim() {
+                "workers" => {
+                    let workers = value
+                        .parse::<usize>()
+                        .with_context(|| format!("invalid number of workers `{value}`"))?;
+                    if workers == 0 {
+                        bail!("the number of workers must be at least 1");
+                    }
+                    parsed.workers = Some(workers);
+                },
+                key => bail!("unknown command `{key}`"),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Reads and removes the control file of `temp`. Returns `None` if there is
+/// none.
+pub(crate) fn take_commands(temp: &Path) -> anyhow::Result<Option<ControlCommands>> {
+    let path = temp.join(CONTROL_FILE);
+    let commands = match fs::read_to_string(&path) {
+        Ok(commands) => commands,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    ControlCommands::parse(&commands)
+        .map(Some)
+        .with_context(|| format!("Invalid commands in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            ControlCommands::parse("workers=8\n").expect("should parse"),
+            ControlCommands {
+                workers: Some(8)
+            }
+        );
+        assert_eq!(
+            ControlCommands::parse("# reclaim some cores\n\n workers = 2 \n")
+                .expect("should parse"),
+            ControlCommands {
+                workers: Some(2)
+            }
+        );
+        assert_eq!(
+            ControlCommands::parse("").expect("should parse"),
+            ControlCommands::default()
+        );
+        assert!(ControlCommands::parse("workers=0").is_err());
+        assert!(ControlCommands::parse("workers=many").is_err());
+        assert!(ControlCommands::parse("workers").is_err());
+        assert!(ControlCommands::parse("threads=4").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Packages the state of an encode into a single archive that can be attached
+//! to a bug report
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::available_parallelism,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::capabilities::Capabilities;
+
+/// Files of the temporary directory that describe the encode, none of which
+/// contain media
+const STATE_FILES: &[&str] = &[
+    "chunks.json",
+    "scenes.json",
+    "done.json",
+    "report.json",
+    "sc_checkpoint.json",
+    "options.json",
+];
+
+/// Directories of the temporary directory whose files with one of the
+/// extensions are bundled as well
+const STATE_DIRS: &[(&str, &[&str])] = &[("logs", &["log"]), ("split", &["vpy", "py"])];
+
+/// Files larger than this are left out, as they would make the archive too
+/// large to attach
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The system an encode ran on, without anything identifying the user
+#[derive(Debug, Serialize)]
+struct Environment {
+    av1an_version: &'static str,
+    os:            &'static str,
+    arch:          &'static str,
+    cpus:          usize,
+    /// The `AV1AN_*` environment variables
+    variables:     BTreeMap<String, String>,
+}
+
+impl Environment {
+    fn detect() -> Self {
+        Self {
+            av1an_version: env!("CARGO_PKG_VERSION"),
+            os:            env::consts::OS,
+            arch:          env::consts::ARCH,
+            cpus:          available_parallelism().map_or(1, std::num::NonZero::get),
+            variables:     env::vars().filter(|(key, _)| key.starts_with("AV1AN_")).collect(),
+        }
+    }
+}
+
+/// Returns the files of `temp` that [`bundle_debug`] adds to the archive, as
+/// their paths relative to `temp`
+fn state_files(temp: &Path) -> Vec<PathBuf> {
+    let mut files = STATE_FILES
+        .iter()
+        .map(PathBuf::from)
+        .filter(|file| temp.join(file).is_file())
+        .collect::<Vec<_>>();
+    for (dir, extensions) in STATE_DIRS {
+        let Ok(entries) = fs::read_dir(temp.join(dir)) else {
+            continue;
+        };
+        let mut dir_files = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|ext| extensions.iter().any(|allowed| ext == *allowed))
+            })
+            .filter_map(|path| Some(Path::new(dir).join(path.file_name()?)))
+            .collect::<Vec<_>>();
+        dir_files.sort();
+        files.extend(dir_files);
+    }
+    files
+}
+
+/// Size of the headers and of the blocks the files are padded to in a tar
+/// archive
+const BLOCK: usize = 512;
+
+/// Writes files to a tar archive in the ustar format
+struct TarWriter<W: Write> {
+    writer: W,
+    mtime:  u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Writes `value` as a zero-terminated octal number filling `field`
+    fn octal(field: &mut [u8], value: u64) {
+        let digits = format!("{value:0width$o}", width = field.len() - 1);
+        let (digits_field, terminator) = field.split_at_mut(field.len() - 1);
+        digits_field.copy_from_slice(digits.as_bytes());
+        terminator[0] = 0;
+    }
+
+    /// Appends `data` as the file `name`, which uses `/` as the separator
+    fn append(&mut self, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        // names longer than the name field are split into a prefix and a name
+        let (prefix, name) = if name.len() <= 100 {
+            ("", name)
+        } else {
+            name.match_indices('/')
+                .filter_map(|(index, _)| {
+                    let (prefix, name) = name.split_at(index);
+                    Some((prefix, name.strip_prefix('/')?))
+                })
+                .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100)
+                .with_context(|| format!("{name} is too long for a tar archive"))?
+        };
+
+        let mut header = [0; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        Self::octal(&mut header[100..108], 0o644);
+        Self::octal(&mut header[108..116], 0);
+        Self::octal(&mut header[116..124], 0);
+        Self::octal(&mut header[124..136], data.len() as u64);
+        Self::octal(&mut header[136..148], self.mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        // the checksum is computed with its own field set to spaces
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|&byte| u64::from(byte)).sum();
+        Self::octal(&mut header[148..155], checksum);
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        let padding = data.len().next_multiple_of(BLOCK) - data.len();
+        self.writer.write_all(&[0; BLOCK][..padding])?;
+        Ok(())
+    }
+
+    /// Ends the archive with two empty blocks
+    fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0; 2 * BLOCK])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Writes the state of the encode in the temporary directory `temp` to the
+/// tar archive `output`, and returns the paths of the files in it.
+///
+/// The archive contains the scenes, the chunk queue, the progress and the
+/// report with the settings of the encode, the spilled encoder output and the
+/// VapourSynth scripts, laid out as in `temp` under `temp/`. The Av1an logs
+/// in `logs` are added under `logs/`, and the capabilities of the system and
+/// the `AV1AN_*` environment variables as `capabilities.json` and
+/// `environment.json`. Encoded chunks, the source and other media are never
+/// added.
+///
+/// # Errors
+///
+/// Returns an error if `temp` contains none of the files of an encode or the
+/// archive could not be written.
+#[inline]
+pub fn bundle_debug(temp: &Path, output: &Path, logs: &[PathBuf]) -> anyhow::Result<Vec<String>> {
+    let files = state_files(temp);
+    ensure!(
+        files
+            .iter()
+            .any(|file| STATE_FILES.iter().any(|state| file == Path::new(state))),
+        "{} is not the temporary directory of an encode",
+        temp.display()
+    );
+
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let mut archive = TarWriter {
+        writer: BufWriter::new(
+            File::create(output)
+                .with_context(|| format!("Failed to create {}", output.display()))?,
+        ),
+        mtime,
+    };
+    let mut names = Vec::new();
+    let sources = files
+        .into_iter()
+        .map(|file| (temp.join(&file), Path::new("temp").join(file)))
+        .chain(
+            logs.iter()
+                .filter_map(|log| Some((log.clone(), Path::new("logs").join(log.file_name()?)))),
+        );
+    for (path, name) in sources {
+        let size = fs::metadata(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .len();
+        if size > MAX_FILE_SIZE {
+            warn!(
+                "Leaving {} out of the debug bundle, as it is {} MB",
+                path.display(),
+                size / 1024 / 1024
+            );
+            continue;
+        }
+        let name = name.to_string_lossy().replace('\\', "/");
+        archive.append(&name, &fs::read(&path)?)?;
+        names.push(name);
+    }
+
+    for (name, json) in [
+        (
+            "environment.json",
+            serde_json::to_string_pretty(&Environment::detect())?,
+        ),
+        ("capabilities.json", Capabilities::detect().to_json()?),
+    ] {
+        archive.append(name, json.as_bytes())?;
+        names.push(name.to_string());
+    }
+
+    archive
+        .finish()
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tar_headers_are_valid() {
+        let mut archive = TarWriter {
+            writer: Vec::new(),
+            mtime:  0,
+        };
+        archive.append("temp/chunks.json", b"[]").expect("should append");
+        let long_name = format!("{}/av1an.log", "a".repeat(120));
+        archive.append(&long_name, b"log").expect("should append");
+        let bytes = archive.finish().expect("should finish");
+        assert_eq!(bytes.len(), 6 * 512);
+
+        let header = &bytes[..512];
+        assert!(header.starts_with(b"temp/chunks.json\0"));
+        assert_eq!(&header[124..136], b"00000000002\0");
+        assert_eq!(&header[257..263], b"ustar\0");
+        let checksum = header[..148].iter().chain(&[b' '; 8]).chain(&header[156..]);
+        let checksum = checksum.map(|&byte| u64::from(byte)).sum::<u64>();
+        assert_eq!(&header[148..155], format!("{checksum:06o}\0").as_bytes());
+        assert_eq!(&bytes[512..514], b"[]");
+
+        let header = &bytes[1024..1536];
+        assert!(header.starts_with(b"av1an.log\0"));
+        assert_eq!(&header[345..465], "a".repeat(120).as_bytes());
+    }
+
+    #[test]
+    fn tar_rejects_names_it_cannot_split() {
+        let mut archive = TarWriter {
+            writer: Vec::new(),
+            mtime:  0,
+        };
+        // no `/` to split at
+        assert!(archive.append(&"a".repeat(101), b"").is_err());
+        // the prefix would be longer than 155 bytes
+        assert!(archive.append(&format!("{}/log", "a".repeat(156)), b"").is_err());
+        // the name would be longer than 100 bytes
+        assert!(archive.append(&format!("temp/{}", "a".repeat(101)), b"").is_err());
+        assert!(archive.writer.is_empty());
+    }
+
+    #[test]
+    fn only_state_files_are_bundled() {
+        let temp = tempfile::tempdir().expect("should create a temporary directory");
+        let temp = temp.path();
+        for file in [
+            "chunks.json",
+            "scenes.json",
+            "encode/00000.ivf",
+            "split/loadscript.vpy",
+            "split/00000.mkv",
+            "logs/00000_pass1_encoder.log",
+            "audio.mkv",
+        ] {
+            let path = temp.join(file);
+            fs::create_dir_all(path.parent().expect("path should have a parent"))
+                .expect("should create the directory");
+            fs::write(path, b"data").expect("should write the file");
+        }
+
+        assert_eq!(
+            state_files(temp),
+            [
+                "chunks.json",
+                "scenes.json",
+                "logs/00000_pass1_encoder.log",
+                "split/loadscript.vpy"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+}
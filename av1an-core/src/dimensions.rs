@@ -0,0 +1,204 @@
+use std::fmt::Display;
+
+use strum::{EnumString, IntoStaticStr};
+
+use crate::Encoder;
+
+/// AV1 limits each tile to 4096 pixels wide and 4096x2304 pixels in area
+const MAX_TILE_WIDTH: u32 = 4096;
+const MAX_TILE_AREA: u64 = 4096 * 2304;
+
+/// Smallest tile area, in pixels, that [`recommended_av1_tiles`] splits the
+/// frame into. Every tile edge stops prediction and entropy coding from using
+/// the other side, which costs more compression the smaller the tiles get,
+/// while below about 720p a tile adds little threading to the encoder.
+const MIN_EFFICIENT_TILE_AREA: u64 = 1280 * 720;
+
+/// What to do with inputs whose width or height does not cover a whole
+/// number of chroma samples, which encoders like x264 reject
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OddDimensions {
+    /// Pad the right and bottom edges to the next valid size
+    #[strum(serialize = "pad")]
+    Pad,
+    /// Crop the right and bottom edges to the previous valid size
+    #[strum(serialize = "crop")]
+    Crop,
+    /// Exit before encoding
+    #[strum(serialize = "error")]
+    Error,
+}
+
+impl Display for OddDimensions {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+impl OddDimensions {
+    /// FFmpeg filter that makes `resolution` a multiple of `subsampling`, if
+    /// it isn't one already
+    pub(crate) fn filter(self, resolution: (u32, u32), subsampling: (u32, u32)) -> Option<String> {
+        let (width, height) = resolution;
+        let (sub_w, sub_h) = subsampling;
+        if width % sub_w == 0 && height % sub_h == 0 {
+            return None;
+        }
+        match self {
+            Self::Pad => Some(format!(
+                "pad={}:{}",
+                width.next_multiple_of(sub_w),
+                height.next_multiple_of(sub_h)
+            )),
+            Self::Crop => Some(format!(
+                "crop={}:{}:0:0",
+                width - width % sub_w,
+                height - height % sub_h
+            )),
+            Self::Error => None,
+        }
+    }
+}
+
+/// Smallest number of tile columns and rows, each a power of two, that AV1
+/// allows at a resolution
+pub(crate) fn min_av1_tiles(width: u32, height: u32) -> (u32, u32) {
+    let columns = width.div_ceil(MAX_TILE_WIDTH).next_power_of_two();
+    let tiles = (u64::from(width) * u64::from(height)).div_ceil(MAX_TILE_AREA);
+    let rows = u32::try_from(tiles.div_ceil(u64::from(columns)))
+        .unwrap_or(u32::MAX)
+        .next_power_of_two();
+    (columns, rows)
+}
+
+/// Number of tile columns and rows, each a power of two, to encode a
+/// resolution with when every worker has `threads` threads.
+///
+/// The frame is split into as many tiles as it has areas of 1280x720 pixels,
+/// but no more than `threads`, as tiles beyond the threads of a worker only
+/// lose compression without encoding faster. Each split halves the longer
+/// side of the tiles, starting with the columns, and the result is raised to
+/// the minimum AV1 allows.
+pub(crate) fn recommended_av1_tiles(width: u32, height: u32, threads: usize) -> (u32, u32) {
+    let max_tiles = (u64::from(width) * u64::from(height) / MIN_EFFICIENT_TILE_AREA)
+        .clamp(1, threads.max(1) as u64);
+    let tiles = 1u64 << max_tiles.ilog2();
+
+    let (mut columns, mut rows) = (1u32, 1u32);
+    while u64::from(columns * rows) < tiles {
+        if width / columns >= height / rows {
+            columns *= 2;
+        } else {
+            rows *= 2;
+        }
+    }
+    let (min_columns, min_rows) = min_av1_tiles(width, height);
+    (columns.max(min_columns), rows.max(min_rows))
+}
+
+/// Number of tile columns and rows set in the video parameters of an AV1
+/// encoder, if any
+pub(crate) fn av1_tiles(encoder: Encoder, video_params: &[String]) -> Option<(u32, u32)> {
+    let value = |name: &str| -> Option<u32> {
+        video_params.iter().enumerate().find_map(|(index, param)| {
+            if param == name {
+                video_params.get(index + 1)?.parse().ok()
+            } else {
+                param.strip_prefix(name)?.strip_prefix('=')?.parse().ok()
+            }
+        })
+    };
+    let pow2 = |log2: Option<u32>| log2.map(|log2| 1u32 << log2.min(6));
+
+    let (columns, rows) = match encoder {
+        Encoder::aom | Encoder::svt_av1 => {
+            (pow2(value("--tile-columns")), pow2(value("--tile-rows")))
+        },
+        // rav1e arranges a total number of tiles into columns first
+        Encoder::rav1e => (
+            value("--tile-cols").or_else(|| value("--tiles")),
+            value("--tile-rows"),
+        ),
+        // NVENC chooses the AV1 tiles itself unless told otherwise
+        Encoder::vpx | Encoder::x264 | Encoder::x265 | Encoder::av1_nvenc | Encoder::hevc_nvenc => {
+            return None
+        },
+    };
+    if columns.is_none() && rows.is_none() {
+        return None;
+    }
+    Some((columns.unwrap_or(1), rows.unwrap_or(1)))
+}
+
+/// Video parameters of `encoder` that set `columns` tile columns and `rows`
+/// tile rows
+pub(crate) fn av1_tile_params(encoder: Encoder, (columns, rows): (u32, u32)) -> String {
+    match encoder {
+        Encoder::aom => format!(
+            "--tile-columns={} --tile-rows={}",
+            columns.ilog2(),
+            rows.ilog2()
+        ),
+        Encoder::svt_av1 => format!(
+            "--tile-columns {} --tile-rows {}",
+            columns.ilog2(),
+            rows.ilog2()
+        ),
+        _ => format!("--tile-cols {columns} --tile-rows {rows}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn odd_dimensions_filters() {
+        assert_eq!(OddDimensions::Pad.filter((1920, 1080), (2, 2)), None);
+        assert_eq!(
+            OddDimensions::Pad.filter((1279, 721), (2, 2)).as_deref(),
+            Some("pad=1280:722")
+        );
+        assert_eq!(
+            OddDimensions::Crop.filter((1279, 721), (2, 1)).as_deref(),
+            Some("crop=1278:721:0:0")
+        );
+        assert_eq!(OddDimensions::Pad.filter((1279, 721), (1, 1)), None);
+    }
+
+    #[test]
+    fn av1_tile_limits() {
+        assert_eq!(min_av1_tiles(3840, 2160), (1, 1));
+        assert_eq!(min_av1_tiles(7680, 4320), (2, 2));
+        assert_eq!(min_av1_tiles(15360, 8640), (4, 4));
+
+        let params = |params: &[&str]| params.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            av1_tiles(Encoder::aom, &params(&["--cpu-used=4", "--tile-columns=1"])),
+            Some((2, 1))
+        );
+        assert_eq!(
+            av1_tiles(Encoder::svt_av1, &params(&["--tile-rows", "2"])),
+            Some((1, 4))
+        );
+        assert_eq!(
+            av1_tiles(Encoder::rav1e, &params(&["--tiles", "4"])),
+            Some((4, 1))
+        );
+        assert_eq!(av1_tiles(Encoder::aom, &params(&["--cpu-used=4"])), None);
+    }
+
+    #[test]
+    fn recommended_tiles() {
+        assert_eq!(recommended_av1_tiles(1280, 720, 16), (1, 1));
+        assert_eq!(recommended_av1_tiles(1920, 1080, 16), (2, 1));
+        assert_eq!(recommended_av1_tiles(1920, 1080, 1), (1, 1));
+        assert_eq!(recommended_av1_tiles(3840, 2160, 16), (4, 2));
+        assert_eq!(recommended_av1_tiles(3840, 2160, 4), (2, 2));
+        assert_eq!(recommended_av1_tiles(1080, 1920, 16), (1, 2));
+        assert_eq!(recommended_av1_tiles(7680, 4320, 16), (4, 4));
+        // the minimum AV1 allows wins over a single thread
+        assert_eq!(recommended_av1_tiles(7680, 4320, 1), (2, 2));
+    }
+}
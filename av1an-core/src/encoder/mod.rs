@@ -6,7 +6,7 @@ use std::{
     cmp,
     fmt::Display,
     iter::Iterator,
-    path::PathBuf,
+    path::Path,
     process::Command,
     sync::OnceLock,
 };
@@ -67,6 +67,21 @@ const MAXIMUM_SPEED_OLD_SVT_AV1: u8 = 8;
 const MAXIMUM_SPEED_SVT_AV1: u8 = 12;
 const MAXIMUM_SPEED_X264: &str = "medium";
 const MAXIMUM_SPEED_X265: &str = "fast";
+const MAXIMUM_SPEED_NVENC: &str = "p1";
+
+/// Arguments that make FFmpeg read the y4m piped to it, for the NVENC
+/// encoders
+const FFMPEG_Y4M_INPUT: [&str; 9] = [
+    "ffmpeg",
+    "-y",
+    "-hide_banner",
+    "-loglevel",
+    "error",
+    "-f",
+    "yuv4mpegpipe",
+    "-i",
+    "-",
+];
 
 #[expect(non_camel_case_types)]
 #[derive(
@@ -79,6 +94,7 @@ const MAXIMUM_SPEED_X265: &str = "fast";
     Debug,
     strum::EnumString,
     strum::IntoStaticStr,
+    strum::EnumIter,
 )]
 pub enum Encoder {
     aom,
@@ -88,6 +104,8 @@ pub enum Encoder {
     svt_av1,
     x264,
     x265,
+    av1_nvenc,
+    hevc_nvenc,
 }
 
 #[tracing::instrument(level = "debug")]
@@ -125,10 +143,40 @@ pub(crate) fn parse_svt_av1_unprocessed_tokens(output: &[u8]) -> Option<Vec<Stri
     Some(unprocessed_tokens)
 }
 
+/// Parses the bit depths an x264 build supports from `x264 --version`
+///
+/// Builds limited to a single bit depth list it in their configuration line,
+/// e.g. `x264 configuration: --bit-depth=8 --chroma-format=all`.
+pub(crate) fn parse_x264_bit_depths(version: &str) -> Option<Vec<usize>> {
+    let configuration = version.lines().find(|line| line.starts_with("x264 configuration:"))?;
+    let bit_depth = configuration
+        .split_ascii_whitespace()
+        .find_map(|arg| arg.strip_prefix("--bit-depth="));
+    match bit_depth {
+        None | Some("all") => Some(vec![8, 10]),
+        Some(depth) => Some(vec![depth.parse().ok()?]),
+    }
+}
+
+/// Parses the bit depths an x265 build supports from `x265 --version`
+///
+/// The build info line ends with the supported bit depths, e.g.
+/// `x265 [info]: build info [Linux][GCC 11.2.0][64 bit] 8bit+10bit+12bit`.
+pub(crate) fn parse_x265_bit_depths(version: &str) -> Option<Vec<usize>> {
+    let build_info = version.lines().find(|line| line.contains("build info"))?;
+    let depths = build_info
+        .split_ascii_whitespace()
+        .next_back()?
+        .split('+')
+        .map(|depth| depth.strip_suffix("bit")?.parse().ok())
+        .collect::<Option<Vec<usize>>>()?;
+    (!depths.is_empty()).then_some(depths)
+}
+
 #[tracing::instrument(level = "debug")]
 pub(crate) fn svt_av1_supports_quarter_steps(temp: &str) -> bool {
     *SVT_AV1_QUARTER_STEP_SUPPORT.get_or_init(|| {
-        use std::{fs, io::Write, path::Path, process::Stdio};
+        use std::{fs, io::Write, process::Stdio};
 
         let test_file = Path::new(temp).join("test_q.y4m");
         let result = (|| -> Option<bool> {
@@ -200,6 +248,15 @@ impl Encoder {
                 "--input", "-", "-o", output
             ])
             .collect(),
+            // The NVENC encoders are named after their FFmpeg encoders, and
+            // their output extensions after the FFmpeg muxers
+            Self::av1_nvenc | Self::hevc_nvenc => chain!(
+                FFMPEG_Y4M_INPUT.map(ToString::to_string),
+                into_array!["-stats", "-c:v", self.to_string()],
+                params,
+                into_array!["-f", self.output_extension(), output]
+            )
+            .collect(),
         }
     }
 
@@ -277,6 +334,9 @@ impl Encoder {
                 ]
             )
             .collect(),
+            Self::av1_nvenc | Self::hevc_nvenc => {
+                unreachable!("{self} only encodes in one pass")
+            },
         }
     }
 
@@ -354,6 +414,9 @@ impl Encoder {
                 ]
             )
             .collect(),
+            Self::av1_nvenc | Self::hevc_nvenc => {
+                unreachable!("{self} only encodes in one pass")
+            },
         }
     }
 
@@ -481,6 +544,39 @@ impl Encoder {
                 "--scenecut",
                 "0",
             ],
+            // NVENC only keeps a constant quality with VBR and no target bitrate
+            Encoder::av1_nvenc => into_vec![
+                "-preset",
+                "p5",
+                "-tune",
+                "hq",
+                "-rc",
+                "vbr",
+                "-cq",
+                "30",
+                "-b:v",
+                "0",
+                "-g",
+                "9999",
+                "-no-scenecut",
+                "1",
+            ],
+            Encoder::hevc_nvenc => into_vec![
+                "-preset",
+                "p5",
+                "-tune",
+                "hq",
+                "-rc",
+                "vbr",
+                "-cq",
+                "25",
+                "-b:v",
+                "0",
+                "-g",
+                "9999",
+                "-no-scenecut",
+                "1",
+            ],
         }
     }
 
@@ -499,8 +595,8 @@ impl Encoder {
         match self {
             Self::aom | Self::vpx => (15, 55),
             Self::rav1e => (50, 140),
-            Self::svt_av1 => (15, 50),
-            Self::x264 | Self::x265 => (15, 35),
+            Self::svt_av1 | Self::av1_nvenc => (15, 50),
+            Self::x264 | Self::x265 | Self::hevc_nvenc => (15, 35),
         }
     }
 
@@ -508,9 +604,12 @@ impl Encoder {
     #[inline]
     pub fn get_cq_relative_percentage(self, quantizer: usize) -> f64 {
         let percentage = match self {
-            Self::aom | Self::vpx | Self::svt_av1 => quantizer as f64 / 64.0, // 0-63
-            Self::rav1e => quantizer as f64 / 256.0,                          // 0-255
-            Self::x264 | Self::x265 => quantizer as f64 / 52.0,               // 0-51
+            // 0-63
+            Self::aom | Self::vpx | Self::svt_av1 | Self::av1_nvenc => quantizer as f64 / 64.0,
+            // 0-255
+            Self::rav1e => quantizer as f64 / 256.0,
+            // 0-51
+            Self::x264 | Self::x265 | Self::hevc_nvenc => quantizer as f64 / 52.0,
         };
 
         // Clamp to 0-1 in case quantizer is out of expected range
@@ -519,14 +618,16 @@ impl Encoder {
 
     /// Returns help command for encoder
     #[inline]
-    pub const fn help_command(self) -> [&'static str; 2] {
+    pub const fn help_command(self) -> (&'static str, &'static [&'static str]) {
         match self {
-            Self::aom => ["aomenc", "--help"],
-            Self::rav1e => ["rav1e", "--help"],
-            Self::vpx => ["vpxenc", "--help"],
-            Self::svt_av1 => ["SvtAv1EncApp", "--help"],
-            Self::x264 => ["x264", "--fullhelp"],
-            Self::x265 => ["x265", "--fullhelp"],
+            Self::aom => ("aomenc", &["--help"]),
+            Self::rav1e => ("rav1e", &["--help"]),
+            Self::vpx => ("vpxenc", &["--help"]),
+            Self::svt_av1 => ("SvtAv1EncApp", &["--help"]),
+            Self::x264 => ("x264", &["--fullhelp"]),
+            Self::x265 => ("x265", &["--fullhelp"]),
+            Self::av1_nvenc => ("ffmpeg", &["-hide_banner", "-h", "encoder=av1_nvenc"]),
+            Self::hevc_nvenc => ("ffmpeg", &["-hide_banner", "-h", "encoder=hevc_nvenc"]),
         }
     }
 
@@ -594,6 +695,56 @@ impl Encoder {
                         .to_string(),
                 )
             },
+            Self::av1_nvenc | Self::hevc_nvenc => {
+                // FFmpeg is only built with NVENC if it was configured with it
+                let result =
+                    Command::new("ffmpeg").args(["-hide_banner", "-encoders"]).output().ok()?;
+                let stdout = String::from_utf8_lossy(&result.stdout);
+                let codec = <&'static str>::from(self);
+                if !stdout.lines().any(|line| line.split_ascii_whitespace().nth(1) == Some(codec)) {
+                    return None;
+                }
+                let result = Command::new("ffmpeg").arg("-version").output().ok()?;
+                let stdout = String::from_utf8_lossy(&result.stdout);
+                let version = stdout.lines().next()?.strip_prefix("ffmpeg version ")?;
+                Some(format!(
+                    "FFmpeg {}",
+                    version.split_ascii_whitespace().next()?
+                ))
+            },
+        }
+    }
+
+    /// Returns the bit depths supported by the installed encoder binary, or
+    /// `None` if they could not be determined
+    ///
+    /// Unlike [`Encoder::get_format_bit_depth`], which only knows what the
+    /// encoder supports in general, this checks how the binary in PATH was
+    /// built, e.g. whether x264 was built with 10-bit support.
+    #[inline]
+    pub fn supported_bit_depths(self) -> Option<Vec<usize>> {
+        match self {
+            Self::aom => Some(vec![8, 10, 12]),
+            // NVENC encodes 10-bit on every GPU that has an AV1 or HEVC encoder
+            Self::rav1e | Self::svt_av1 | Self::av1_nvenc | Self::hevc_nvenc => Some(vec![8, 10]),
+            Self::vpx => {
+                // High bit depth builds of vpxenc are the only ones with `--bit-depth`
+                let result = Command::new("vpxenc").arg("--help").output().ok()?;
+                let stdout = String::from_utf8_lossy(&result.stdout);
+                Some(if stdout.contains("--bit-depth") {
+                    vec![8, 10, 12]
+                } else {
+                    vec![8]
+                })
+            },
+            Self::x264 => {
+                let result = Command::new("x264").arg("--version").output().ok()?;
+                parse_x264_bit_depths(&String::from_utf8_lossy(&result.stdout))
+            },
+            Self::x265 => {
+                let result = Command::new("x265").arg("--version").output().ok()?;
+                parse_x265_bit_depths(&String::from_utf8_lossy(&result.stderr))
+            },
         }
     }
 
@@ -607,6 +758,7 @@ impl Encoder {
             Self::svt_av1 => "SvtAv1EncApp",
             Self::x264 => "x264",
             Self::x265 => "x265",
+            Self::av1_nvenc | Self::hevc_nvenc => "ffmpeg",
         }
     }
 
@@ -614,23 +766,59 @@ impl Encoder {
     #[inline]
     pub const fn format(self) -> &'static str {
         match self {
-            Self::aom | Self::rav1e | Self::svt_av1 => "av1",
+            Self::aom | Self::rav1e | Self::svt_av1 | Self::av1_nvenc => "av1",
             Self::vpx => "vpx",
             Self::x264 => "h264",
-            Self::x265 => "h265",
+            Self::x265 | Self::hevc_nvenc => "h265",
         }
     }
 
+    /// Whether the encoder runs on the GPU, through FFmpeg
+    #[inline]
+    pub const fn is_hardware(self) -> bool {
+        matches!(self, Self::av1_nvenc | Self::hevc_nvenc)
+    }
+
+    /// Whether the encoder can change the quantizer of a range of frames
+    /// within a chunk, which is used for qp-offset zones
+    #[inline]
+    pub const fn supports_frame_zones(self) -> bool {
+        matches!(self, Self::x264 | Self::x265)
+    }
+
     /// Get the default output extension for the encoder
     #[inline]
     pub const fn output_extension(&self) -> &'static str {
         match &self {
-            Self::aom | Self::rav1e | Self::vpx | Self::svt_av1 => "ivf",
+            Self::aom | Self::rav1e | Self::vpx | Self::svt_av1 | Self::av1_nvenc => "ivf",
             Self::x264 => "264",
-            Self::x265 => "hevc",
+            Self::x265 | Self::hevc_nvenc => "hevc",
         }
     }
 
+    /// Largest width and height the encoder accepts
+    #[inline]
+    pub const fn max_resolution(self) -> (u32, u32) {
+        match self {
+            Self::aom | Self::rav1e => (65536, 65536),
+            Self::vpx => (65535, 65535),
+            Self::svt_av1 => (16384, 8704),
+            Self::x264 | Self::x265 => (16384, 16384),
+            Self::av1_nvenc | Self::hevc_nvenc => (8192, 8192),
+        }
+    }
+
+    /// Whether the encoder rejects a width or height that does not cover a
+    /// whole number of chroma samples, instead of padding the chroma planes
+    /// itself
+    #[inline]
+    pub const fn needs_whole_chroma_samples(self) -> bool {
+        matches!(
+            self,
+            Self::svt_av1 | Self::x264 | Self::x265 | Self::av1_nvenc | Self::hevc_nvenc
+        )
+    }
+
     /// Returns function pointer used for matching Q/CRF arguments in command
     /// line
     fn q_match_fn(self) -> fn(&str) -> bool {
@@ -639,6 +827,7 @@ impl Encoder {
             Self::rav1e => |p| p == "--quantizer",
             Self::svt_av1 => |p| matches!(p, "--qp" | "-q" | "--crf"),
             Self::x264 | Self::x265 => |p| p == "--crf",
+            Self::av1_nvenc | Self::hevc_nvenc => |p| p == "-cq",
         }
     }
 
@@ -646,7 +835,7 @@ impl Encoder {
         match self {
             Self::aom | Self::vpx => (index, format!("--cq-level={}", q.round() as usize)),
             Self::rav1e => (index + 1, (q.round() as usize).to_string()),
-            Self::svt_av1 | Self::x265 | Self::x264 => {
+            Self::svt_av1 | Self::x265 | Self::x264 | Self::av1_nvenc | Self::hevc_nvenc => {
                 let q_str = format_q(q);
                 (index + 1, q_str)
             },
@@ -668,6 +857,10 @@ impl Encoder {
                 let q_str = format_q(q);
                 output.push(q_str);
             },
+            Self::av1_nvenc | Self::hevc_nvenc => {
+                output.push("-cq".into());
+                output.push(format_q(q));
+            },
         }
         output
     }
@@ -687,6 +880,254 @@ impl Encoder {
         params
     }
 
+    /// Returns the q/crf value set in the command line arguments, if any
+    #[inline]
+    pub fn get_q(self, params: &[String]) -> Option<f32> {
+        if params.is_empty() {
+            return None;
+        }
+        let index = list_index(params, self.q_match_fn())?;
+        match self {
+            Self::aom | Self::vpx => params[index].strip_prefix("--cq-level=")?.parse().ok(),
+            _ => params.get(index + 1)?.parse().ok(),
+        }
+    }
+
+    /// Highest q/crf value accepted by the encoder
+    #[inline]
+    pub const fn get_max_q(self) -> f32 {
+        match self {
+            Self::aom | Self::vpx | Self::svt_av1 | Self::av1_nvenc => 63.0,
+            Self::rav1e => 255.0,
+            Self::x264 | Self::x265 | Self::hevc_nvenc => 51.0,
+        }
+    }
+
+    /// Returns command line arguments with the speed setting replaced by the
+    /// encoder's fastest one, for use in preview encodes
+    #[inline]
+    pub fn preview_command(self, params: Vec<String>) -> Vec<String> {
+        let fastest: &[&str] = match self {
+            Self::aom => &["--cpu-used=6"],
+            Self::vpx => &["--cpu-used=5"],
+            Self::rav1e => &["--speed", "10"],
+            Self::svt_av1 => &["--preset", "12"],
+            Self::x264 | Self::x265 => &["--preset", "ultrafast"],
+            Self::av1_nvenc | Self::hevc_nvenc => &["-preset", MAXIMUM_SPEED_NVENC],
+        };
+        let mut params = self.without_speed(params);
+        params.extend(fastest.iter().map(ToString::to_string));
+
+        params
+    }
+
+    /// Returns command line arguments without the speed setting
+    fn without_speed(self, mut params: Vec<String>) -> Vec<String> {
+        match self {
+            Self::aom | Self::vpx => {
+                params.retain(|param| !param.starts_with("--cpu-used="));
+            },
+            Self::rav1e
+            | Self::svt_av1
+            | Self::x264
+            | Self::x265
+            | Self::av1_nvenc
+            | Self::hevc_nvenc => {
+                let flags = self.speed_flags();
+                while let Some(index) =
+                    params.iter().position(|param| flags.contains(&param.as_str()))
+                {
+                    params.remove(index);
+                    if index < params.len() {
+                        params.remove(index);
+                    }
+                }
+            },
+        }
+
+        params
+    }
+
+    /// Flags that set the encoder's speed. For aomenc and vpxenc, the value is
+    /// joined to the flag with `=`.
+    const fn speed_flags(self) -> &'static [&'static str] {
+        match self {
+            Self::aom | Self::vpx => &["--cpu-used="],
+            Self::rav1e => &["--speed", "-s"],
+            Self::svt_av1 | Self::x264 | Self::x265 => &["--preset"],
+            Self::av1_nvenc | Self::hevc_nvenc => &["-preset"],
+        }
+    }
+
+    /// Speed presets of the encoder from slowest to fastest, and the preset
+    /// the encoder uses when none is set
+    fn speed_presets(self) -> (Vec<String>, &'static str) {
+        const X26X_PRESETS: [&str; 10] = [
+            "placebo",
+            "veryslow",
+            "slower",
+            "slow",
+            "medium",
+            "fast",
+            "faster",
+            "veryfast",
+            "superfast",
+            "ultrafast",
+        ];
+        let numeric = |fastest: u8| (0..=fastest).map(|speed| speed.to_string()).collect();
+        match self {
+            Self::aom => (numeric(9), "0"),
+            Self::vpx => (numeric(MAXIMUM_SPEED_VPX), "0"),
+            Self::rav1e => (numeric(MAXIMUM_SPEED_RAV1E), "6"),
+            Self::svt_av1 => (numeric(12), "10"),
+            Self::x264 | Self::x265 => (X26X_PRESETS.map(ToString::to_string).to_vec(), "medium"),
+            Self::av1_nvenc | Self::hevc_nvenc => (
+                (1..=7).rev().map(|preset| format!("p{preset}")).collect(),
+                "p4",
+            ),
+        }
+    }
+
+    /// Returns the speed preset set in the command line arguments, or the
+    /// encoder's default one
+    #[inline]
+    pub fn get_speed(self, params: &[String]) -> String {
+        let flags = self.speed_flags();
+        let speed = match self {
+            Self::aom | Self::vpx => {
+                params.iter().rev().find_map(|param| param.strip_prefix(flags[0]))
+            },
+            _ => params
+                .iter()
+                .rposition(|param| flags.contains(&param.as_str()))
+                .and_then(|index| params.get(index + 1))
+                .map(String::as_str),
+        };
+        speed.unwrap_or_else(|| self.speed_presets().1).to_string()
+    }
+
+    /// Returns command line arguments with the speed preset replaced by the
+    /// next faster one, or `None` if the fastest preset is already set
+    #[inline]
+    pub fn faster_speed(self, params: Vec<String>) -> Option<Vec<String>> {
+        let (presets, _) = self.speed_presets();
+        let speed = self.get_speed(&params);
+        let next = match presets.iter().position(|preset| *preset == speed) {
+            Some(index) => presets.get(index + 1)?,
+            // step from a preset outside of the usual range, e.g. a negative
+            // SVT-AV1 preset, to the slowest usual one
+            None if speed.parse::<i32>().is_ok_and(|speed| speed < 0) => &presets[0],
+            None => return None,
+        };
+        let mut params = self.without_speed(params);
+        if matches!(self, Self::aom | Self::vpx) {
+            params.push(format!("--cpu-used={next}"));
+        } else {
+            params.extend([self.speed_flags()[0].to_string(), next.clone()]);
+        }
+
+        Some(params)
+    }
+
+    /// Flag that sets the encoder's thread count. For aomenc and vpxenc, the
+    /// value is joined to the flag with `=`.
+    const fn threads_flag(self) -> &'static str {
+        match self {
+            Self::aom | Self::vpx => "--threads=",
+            Self::rav1e | Self::x264 => "--threads",
+            Self::svt_av1 => "--lp",
+            Self::x265 => "--pools",
+            Self::av1_nvenc | Self::hevc_nvenc => "-threads",
+        }
+    }
+
+    /// Returns the thread count set in the command line arguments, if any
+    #[inline]
+    pub fn get_threads(self, params: &[String]) -> Option<usize> {
+        let flag = self.threads_flag();
+        match self {
+            Self::aom | Self::vpx => {
+                params.iter().rev().find_map(|param| param.strip_prefix(flag))?.parse().ok()
+            },
+            _ => {
+                let index = params.iter().rposition(|param| param == flag)?;
+                params.get(index + 1)?.parse().ok()
+            },
+        }
+    }
+
+    /// Returns command line arguments with the encoder's thread count replaced
+    /// by `threads`
+    #[inline]
+    pub fn with_threads(self, mut params: Vec<String>, threads: usize) -> Vec<String> {
+        let flag = self.threads_flag();
+        if matches!(self, Self::aom | Self::vpx) {
+            params.retain(|param| !param.starts_with(flag));
+            params.push(format!("{flag}{threads}"));
+            return params;
+        }
+        while let Some(index) = params.iter().position(|param| param == flag) {
+            params.remove(index);
+            if index < params.len() {
+                params.remove(index);
+            }
+        }
+        params.extend([flag.to_string(), threads.to_string()]);
+
+        params
+    }
+
+    /// Whether the encoder can cap the bitrate of constant quality encodes,
+    /// see [`Self::with_bitrate_cap`]
+    #[inline]
+    pub const fn supports_bitrate_cap(self) -> bool {
+        matches!(
+            self,
+            Self::x264 | Self::x265 | Self::svt_av1 | Self::av1_nvenc | Self::hevc_nvenc
+        )
+    }
+
+    /// Returns command line arguments that cap the bitrate at `maxrate` kbps
+    /// with a buffer of `bufsize` kbits, replacing any cap that is already
+    /// set. Returns `None` if the encoder cannot cap the bitrate of constant
+    /// quality encodes.
+    #[inline]
+    pub fn with_bitrate_cap(
+        self,
+        mut params: Vec<String>,
+        maxrate: u32,
+        bufsize: u32,
+    ) -> Option<Vec<String>> {
+        let cap = match self {
+            Self::x264 | Self::x265 => {
+                [("--vbv-maxrate", maxrate.to_string()), ("--vbv-bufsize", bufsize.to_string())]
+            },
+            // SVT-AV1 takes the buffer size in milliseconds
+            Self::svt_av1 => [
+                ("--mbr", maxrate.to_string()),
+                (
+                    "--buf-sz",
+                    (u64::from(bufsize) * 1000 / u64::from(maxrate.max(1))).to_string(),
+                ),
+            ],
+            Self::av1_nvenc | Self::hevc_nvenc => {
+                [("-maxrate", format!("{maxrate}k")), ("-bufsize", format!("{bufsize}k"))]
+            },
+            Self::aom | Self::rav1e | Self::vpx => return None,
+        };
+        for (flag, value) in cap {
+            while let Some(index) = params.iter().position(|param| param == flag) {
+                params.remove(index);
+                if index < params.len() {
+                    params.remove(index);
+                }
+            }
+            params.extend([flag.to_string(), value]);
+        }
+
+        Some(params)
+    }
+
     /// Parses the number of encoded frames
     pub(crate) fn parse_encoded_frames(self, line: &str) -> Option<u64> {
         use crate::parse::*;
@@ -707,6 +1148,20 @@ impl Encoder {
             Self::rav1e => parse_rav1e_frames(line),
             Self::svt_av1 => parse_svt_av1_frames(line),
             Self::x264 | Self::x265 => parse_x26x_frames(line),
+            Self::av1_nvenc | Self::hevc_nvenc => parse_ffmpeg_frames(line),
+        }
+    }
+
+    /// Parses the statistics printed at the end of an encode from the full
+    /// encoder output
+    pub(crate) fn parse_summary(self, output: &str) -> crate::parse::EncoderSummary {
+        use crate::parse::*;
+
+        match self {
+            Self::aom | Self::vpx => parse_aom_vpx_summary(output),
+            Self::svt_av1 => parse_svt_av1_summary(output),
+            Self::rav1e | Self::x264 | Self::x265 => parse_encoded_summary(output),
+            Self::av1_nvenc | Self::hevc_nvenc => parse_ffmpeg_summary(output),
         }
     }
 
@@ -895,6 +1350,23 @@ impl Encoder {
                 "--input",
                 "-",
             ],
+            Self::av1_nvenc | Self::hevc_nvenc => {
+                chain!(FFMPEG_Y4M_INPUT.map(Cow::from), into_array![
+                    "-c:v",
+                    self.to_string(),
+                    "-preset",
+                    MAXIMUM_SPEED_NVENC,
+                    "-rc",
+                    "vbr",
+                    "-cq",
+                    format_q(q),
+                    "-b:v",
+                    "0",
+                    "-g",
+                    "9999",
+                ])
+                .collect()
+            },
         }
     }
 
@@ -942,6 +1414,15 @@ impl Encoder {
                 "--input",
                 "-",
             ],
+            Self::av1_nvenc | Self::hevc_nvenc => {
+                chain!(FFMPEG_Y4M_INPUT.map(Cow::from), into_array![
+                    "-c:v",
+                    self.to_string(),
+                    "-cq",
+                    format_q(q)
+                ])
+                .collect()
+            },
         }
     }
 
@@ -965,44 +1446,56 @@ impl Encoder {
     /// Constructs tuple of commands for target quality probing
     pub fn probe_cmd(
         self,
-        temp: String,
+        probe_dir: &Path,
         chunk_index: usize,
         q: f32,
         pix_fmt: FFPixelFormat,
         probing_rate: usize,
+        probe_res: Option<(u32, u32)>,
+        scaler: &str,
         vmaf_threads: usize,
         custom_video_params: Option<Vec<String>>,
     ) -> (Option<Vec<String>>, Vec<Cow<'static, str>>) {
-        let filters = if probing_rate > 1 {
-            vec![
-                "-vf".to_string(),
-                format!("select=not(mod(n\\,{probing_rate}))"),
-                "-vsync".to_string(),
-                "0".to_string(),
-            ]
-        } else {
-            Vec::new()
-        };
+        let mut video_filters = Vec::new();
+        if probing_rate > 1 {
+            video_filters.push(format!("select=not(mod(n\\,{probing_rate}))"));
+        }
+        // Encoding at the probe resolution is what makes probing faster, the
+        // probes are scaled to it for scoring anyway
+        if let Some((width, height)) = probe_res {
+            video_filters.push(format!("scale={width}:{height}:flags={scaler}"));
+        }
+
+        let mut filters = Vec::new();
+        if !video_filters.is_empty() {
+            filters.push("-vf".to_string());
+            filters.push(video_filters.join(","));
+        }
+        if probing_rate > 1 {
+            filters.push("-vsync".to_string());
+            filters.push("0".to_string());
+        }
 
         let pipe = Some(compose_ffmpeg_pipe(filters, pix_fmt));
 
-        let extension = match self {
-            Encoder::x264 => "264",
-            Encoder::x265 => "hevc",
-            _ => "ivf",
-        };
         let q_str = format_q(q);
-        let probe_name = format!("v_{index:05}_{q_str}.{extension}", index = chunk_index);
+        let probe_name = format!(
+            "v_{index:05}_{q_str}.{extension}",
+            index = chunk_index,
+            extension = self.output_extension()
+        );
 
-        let probe = PathBuf::from(temp).join("split").join(&probe_name);
+        let probe = probe_dir.join(&probe_name);
         let probe_path = probe.to_string_lossy().to_string();
 
         let params: Vec<Cow<str>> = custom_video_params.map_or_else(
             || self.construct_target_quality_command(vmaf_threads, q),
             |mut video_params| {
-                let quantizer_patterns =
-                    ["--cq-level=", "--passes=", "--pass=", "--crf", "--quantizer"];
-                Self::remove_patterns(&mut video_params, &quantizer_patterns);
+                let quantizer_patterns: &[&str] = match self {
+                    Self::av1_nvenc | Self::hevc_nvenc => &["-cq"],
+                    _ => &["--cq-level=", "--passes=", "--pass=", "--crf", "--quantizer"],
+                };
+                Self::remove_patterns(&mut video_params, quantizer_patterns);
 
                 let mut ps = self.construct_target_quality_command_probe_slow(q);
 
@@ -1021,6 +1514,12 @@ impl Encoder {
                 chain!(params, into_array!["-o", probe_path, "-"]).collect()
             },
             Self::x265 => chain!(params, into_array!["-o", probe_path]).collect(),
+            Self::av1_nvenc | Self::hevc_nvenc => chain!(params, into_array![
+                "-f",
+                self.output_extension(),
+                probe_path
+            ])
+            .collect(),
         };
 
         (pipe, output)
@@ -1040,7 +1539,7 @@ impl Encoder {
         }
       };
     }
-        impl_this_function!(x264, x265, vpx, aom, rav1e, svt_av1)
+        impl_this_function!(x264, x265, vpx, aom, rav1e, svt_av1, av1_nvenc, hevc_nvenc)
     }
 }
 
@@ -1107,3 +1606,16 @@ create_get_format_bit_depth_function!(
   10: [YUV420P10LE],
   12: []
 );
+// FFmpeg converts 10-bit input to the P010 or 16-bit formats NVENC takes
+create_get_format_bit_depth_function!(
+  av1_nvenc,
+   8: [YUV420P, NV12],
+  10: [YUV420P10LE],
+  12: []
+);
+create_get_format_bit_depth_function!(
+  hevc_nvenc,
+   8: [YUV420P, NV12, YUV444P],
+  10: [YUV420P10LE, YUV444P10LE],
+  12: []
+);
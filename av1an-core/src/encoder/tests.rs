@@ -1,4 +1,10 @@
-use crate::encoder::parse_svt_av1_version;
+use std::path::Path;
+
+use crate::{
+    encoder::{parse_svt_av1_version, parse_x264_bit_depths, parse_x265_bit_depths, Encoder},
+    ffmpeg::FFPixelFormat,
+    into_vec,
+};
 
 #[test]
 fn svt_av1_parsing() {
@@ -17,3 +23,226 @@ fn svt_av1_parsing() {
         assert_eq!(parse_svt_av1_version(s.as_bytes()), ans);
     }
 }
+
+#[test]
+fn preview_command_replaces_speed() {
+    let params: Vec<String> = into_vec!["--preset", "4", "--crf", "30"];
+    let expected: Vec<String> = into_vec!["--crf", "30", "--preset", "12"];
+    assert_eq!(Encoder::svt_av1.preview_command(params), expected);
+
+    let params: Vec<String> = into_vec!["--cpu-used=3", "--end-usage=q", "--cq-level=28"];
+    let expected: Vec<String> = into_vec!["--end-usage=q", "--cq-level=28", "--cpu-used=6"];
+    let preview = Encoder::aom.preview_command(params);
+    assert_eq!(preview, expected);
+    assert_eq!(Encoder::aom.get_q(&preview), Some(28.0));
+}
+
+#[test]
+fn faster_speed_steps_one_preset() {
+    let params: Vec<String> = into_vec!["--preset", "4", "--crf", "30"];
+    let faster = Encoder::svt_av1
+        .faster_speed(params)
+        .expect("preset 4 should have a faster one");
+    assert_eq!(faster, into_vec!["--crf", "30", "--preset", "5"]
+        as Vec<String>);
+    assert_eq!(Encoder::svt_av1.get_speed(&faster), "5");
+
+    let params: Vec<String> = into_vec!["--cpu-used=9"];
+    assert_eq!(Encoder::aom.faster_speed(params), None);
+
+    // without a preset, the encoder default is stepped from
+    let faster = Encoder::x265.faster_speed(into_vec!["--crf", "20"]);
+    assert_eq!(faster, Some(into_vec!["--crf", "20", "--preset", "fast"]));
+}
+
+#[test]
+fn with_threads_replaces_thread_count() {
+    let params: Vec<String> = into_vec!["--cpu-used=4", "--threads=8"];
+    assert_eq!(Encoder::aom.get_threads(&params), Some(8));
+    let boosted = Encoder::aom.with_threads(params, 16);
+    let expected: Vec<String> = into_vec!["--cpu-used=4", "--threads=16"];
+    assert_eq!(boosted, expected);
+
+    let params: Vec<String> = into_vec!["--lp", "2", "--preset", "6"];
+    assert_eq!(Encoder::svt_av1.get_threads(&params), Some(2));
+    let boosted = Encoder::svt_av1.with_threads(params, 12);
+    let expected: Vec<String> = into_vec!["--preset", "6", "--lp", "12"];
+    assert_eq!(boosted, expected);
+
+    assert_eq!(
+        Encoder::x265.get_threads(&["--preset".to_string(), "slow".to_string()]),
+        None
+    );
+}
+
+#[test]
+fn with_bitrate_cap_replaces_cap() {
+    let params: Vec<String> = into_vec!["--crf", "18", "--vbv-maxrate", "5000"];
+    let capped = Encoder::x264.with_bitrate_cap(params, 20000, 40000);
+    let expected: Vec<String> =
+        into_vec!["--crf", "18", "--vbv-maxrate", "20000", "--vbv-bufsize", "40000"];
+    assert_eq!(capped, Some(expected));
+
+    let params: Vec<String> = into_vec!["--crf", "30"];
+    let capped = Encoder::svt_av1.with_bitrate_cap(params, 8000, 12000);
+    let expected: Vec<String> = into_vec!["--crf", "30", "--mbr", "8000", "--buf-sz", "1500"];
+    assert_eq!(capped, Some(expected));
+
+    assert_eq!(Encoder::aom.with_bitrate_cap(Vec::new(), 8000, 12000), None);
+    assert!(!Encoder::rav1e.supports_bitrate_cap());
+}
+
+#[test]
+fn get_q_from_params() {
+    let params: Vec<String> = into_vec!["--preset", "4", "--crf", "30.5"];
+    assert_eq!(Encoder::svt_av1.get_q(&params), Some(30.5));
+    let params: Vec<String> = into_vec!["--quantizer", "80"];
+    assert_eq!(Encoder::rav1e.get_q(&params), Some(80.0));
+    let params: Vec<String> = into_vec!["--preset", "slow"];
+    assert_eq!(Encoder::x264.get_q(&params), None);
+    assert_eq!(Encoder::x265.get_q(&[]), None);
+}
+
+#[test]
+fn x264_bit_depth_parsing() {
+    let version = "x264 0.164.3095 baee400\nbuilt on Jan  1 2022, gcc: 11.2.0\nx264 \
+                   configuration: --chroma-format=all\nlibx264 configuration: \
+                   --chroma-format=all\n";
+    assert_eq!(parse_x264_bit_depths(version), Some(vec![8, 10]));
+
+    let version =
+        "x264 0.155.2917 0a84d98\nx264 configuration: --bit-depth=8 --chroma-format=all\n";
+    assert_eq!(parse_x264_bit_depths(version), Some(vec![8]));
+
+    assert_eq!(parse_x264_bit_depths("not x264"), None);
+}
+
+#[test]
+fn x265_bit_depth_parsing() {
+    let version = "x265 [info]: HEVC encoder version 3.5\nx265 [info]: build info [Linux][GCC \
+                   11.2.0][64 bit] 8bit+10bit+12bit\n";
+    assert_eq!(parse_x265_bit_depths(version), Some(vec![8, 10, 12]));
+
+    let version = "x265 [info]: build info [Windows][MSVC 1900][64 bit] 10bit\n";
+    assert_eq!(parse_x265_bit_depths(version), Some(vec![10]));
+}
+
+#[test]
+fn parse_encoded_frames_all_encoders() {
+    let test_cases = [
+        (
+            Encoder::aom,
+            "Pass 1/1 frame   84/83     81091B  132314 us 634.85 fps [ETA  unknown]",
+            Some(83),
+        ),
+        (
+            Encoder::vpx,
+            "Pass 2/2 frame  142/141   156465B  208875 us 679.83 fps [ETA  unknown]",
+            Some(141),
+        ),
+        (
+            Encoder::rav1e,
+            "encoded 12 frames, 126.416 fps, 16.32 Kb/s, elap. time: 1m 36s",
+            Some(12),
+        ),
+        (
+            Encoder::svt_av1,
+            "Encoding frame   42 1.23 kbps 2.31 fps",
+            Some(42),
+        ),
+        (
+            Encoder::x264,
+            "25/240 frames, 45.12 fps, 1234.56 kb/s",
+            Some(25),
+        ),
+        (
+            Encoder::x265,
+            "[1.5%] 36/240 frames, 12.3 fps, 45.6 kb/s",
+            Some(36),
+        ),
+        (
+            Encoder::av1_nvenc,
+            "frame=   48 fps= 24 q=31.0 size=     256KiB time=00:00:02.00 bitrate=1048.6kbits/s",
+            Some(48),
+        ),
+        (Encoder::hevc_nvenc, "[hevc_nvenc @ 0x5581] frame=3", None),
+    ];
+
+    for (encoder, line, expected) in test_cases {
+        assert_eq!(encoder.parse_encoded_frames(line), expected, "{encoder}");
+    }
+}
+
+#[test]
+fn parse_summary_dispatches_per_encoder() {
+    let aom = Encoder::aom.parse_summary(
+        "Pass 1/1 frame  240/240   430154B   14338b/f  358461b/s 5134785 us (46.74 fps)",
+    );
+    assert_eq!(aom.size_bytes, Some(430_154));
+    let x264 = Encoder::x264.parse_summary("encoded 240 frames, 45.12 fps, 1234.56 kb/s");
+    assert_eq!(x264.fps, Some(45.12));
+    assert_eq!(x264.bitrate_kbps, Some(1234.56));
+    assert_eq!(
+        Encoder::svt_av1.parse_summary("encoded 240 frames, 45.12 fps, 1234.56 kb/s"),
+        crate::parse::EncoderSummary::default()
+    );
+}
+
+#[test]
+fn nvenc_encodes_through_ffmpeg() {
+    let params =
+        Encoder::av1_nvenc.man_command(Encoder::av1_nvenc.get_default_arguments((1, 1)), 24.0);
+    assert_eq!(Encoder::av1_nvenc.get_q(&params), Some(24.0));
+
+    let command = Encoder::av1_nvenc.compose_1_1_pass(params, "00000.ivf".to_string());
+    assert_eq!(command[0], "ffmpeg");
+    assert!(command.windows(2).any(|args| args == ["-c:v", "av1_nvenc"]));
+    assert!(command.ends_with(&["-f".to_string(), "ivf".to_string(), "00000.ivf".to_string()]));
+
+    let (_, probe) = Encoder::hevc_nvenc.probe_cmd(
+        Path::new("temp"),
+        1,
+        30.0,
+        FFPixelFormat::YUV420P10LE,
+        1,
+        None,
+        "bicubic",
+        1,
+        Some(into_vec!["-preset", "p7", "-cq", "20"]),
+    );
+    assert_eq!(probe.iter().filter(|arg| *arg == "-cq").count(), 1);
+    assert!(probe.ends_with(&[
+        "-f".into(),
+        "hevc".into(),
+        Path::new("temp").join("v_00001_30.hevc").to_string_lossy()
+    ]));
+}
+
+#[test]
+fn probe_cmd_scales_to_probe_res() {
+    let filter = |probing_rate, probe_res| {
+        let (pipe, _) = Encoder::aom.probe_cmd(
+            Path::new("temp"),
+            1,
+            30.0,
+            FFPixelFormat::YUV420P10LE,
+            probing_rate,
+            probe_res,
+            "bicubic",
+            1,
+            None,
+        );
+        let pipe = pipe.expect("probes are piped through ffmpeg");
+        pipe.iter().position(|arg| arg == "-vf").map(|i| pipe[i + 1].clone())
+    };
+
+    assert_eq!(filter(1, None), None);
+    assert_eq!(
+        filter(1, Some((1280, 720))).as_deref(),
+        Some("scale=1280:720:flags=bicubic")
+    );
+    assert_eq!(
+        filter(4, Some((1280, 720))).as_deref(),
+        Some("select=not(mod(n\\,4)),scale=1280:720:flags=bicubic")
+    );
+}
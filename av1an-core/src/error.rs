@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Exit status of errors that happened outside of the stages below
+pub const UNCLASSIFIED_CODE: u8 = 1;
+
+/// Errors of each stage of an encode.
+///
+/// Functions returning [`anyhow::Result`] wrap their errors in these, so that
+/// programs running Av1an can tell which stage failed from
+/// [`Av1anError::code_of`], which is also the exit status of the CLI, without
+/// parsing the message. The codes never change between versions.
+#[derive(Debug, Error)]
+pub enum Av1anError {
+    /// The input, proxy or settings are not valid
+    #[error("{0:#}")]
+    Input(anyhow::Error),
+    /// Scene detection or reading the scenes file failed
+    #[error("{0:#}")]
+    Scd(anyhow::Error),
+    /// Splitting the input into chunks failed
+    #[error("{0:#}")]
+    Chunking(anyhow::Error),
+    /// The encoder failed on a chunk
+    #[error("[chunk {chunk}] pass {pass}: {error:#}")]
+    Encoder {
+        chunk: usize,
+        pass:  u8,
+        error: anyhow::Error,
+    },
+    /// Concatenating the encoded chunks failed
+    #[error("{0:#}")]
+    Concat(anyhow::Error),
+    /// Measuring the quality of a chunk or of the output failed
+    #[error("{0:#}")]
+    Metrics(anyhow::Error),
+}
+
+/// An [`Av1anError`] as written to `report.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportedError {
+    pub code:    u8,
+    pub kind:    String,
+    /// Only set for encoder errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk:   Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pass:    Option<u8>,
+    pub message: String,
+}
+
+impl Av1anError {
+    /// Stable numeric code of the error
+    #[inline]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Self::Input(_) => 10,
+            Self::Scd(_) => 11,
+            Self::Chunking(_) => 12,
+            Self::Encoder {
+                ..
+            } => 13,
+            Self::Concat(_) => 14,
+            Self::Metrics(_) => 15,
+        }
+    }
+
+    /// Stable name of the error, e.g. `scd`
+    #[inline]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Input(_) => "input",
+            Self::Scd(_) => "scd",
+            Self::Chunking(_) => "chunking",
+            Self::Encoder {
+                ..
+            } => "encoder",
+            Self::Concat(_) => "concat",
+            Self::Metrics(_) => "metrics",
+        }
+    }
+
+    /// Returns the outermost [`Av1anError`] in the chain of `error`
+    #[inline]
+    pub fn find(error: &anyhow::Error) -> Option<&Self> {
+        error.chain().find_map(|error| error.downcast_ref::<Self>())
+    }
+
+    /// Returns the code of the outermost [`Av1anError`] in the chain of
+    /// `error`, or [`UNCLASSIFIED_CODE`] if there is none
+    #[inline]
+    pub fn code_of(error: &anyhow::Error) -> u8 {
+        Self::find(error).map_or(UNCLASSIFIED_CODE, Self::code)
+    }
+
+    /// Returns `error` in the form written to `report.json`
+    #[inline]
+    pub fn report(error: &anyhow::Error) -> ReportedError {
+        let found = Self::find(error);
+        let (chunk, pass) = match found {
+            Some(Self::Encoder {
+                chunk,
+                pass,
+                ..
+            }) => (Some(*chunk), Some(*pass)),
+            _ => (None, None),
+        };
+        ReportedError {
+            code: found.map_or(UNCLASSIFIED_CODE, Self::code),
+            kind: found.map_or("other", Self::kind).to_string(),
+            chunk,
+            pass,
+            message: format!("{error:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn codes_survive_context() {
+        let error = anyhow::Error::from(Av1anError::Encoder {
+            chunk: 7,
+            pass:  2,
+            error: anyhow!("aomenc exited with 1"),
+        });
+        let error = Err::<(), _>(error).context("encoding failed").expect_err("should fail");
+        assert_eq!(Av1anError::code_of(&error), 13);
+        assert_eq!(Av1anError::report(&error), ReportedError {
+            code:    13,
+            kind:    "encoder".to_string(),
+            chunk:   Some(7),
+            pass:    Some(2),
+            message: "encoding failed: [chunk 7] pass 2: aomenc exited with 1".to_string(),
+        });
+
+        assert_eq!(
+            Av1anError::code_of(&anyhow!("something else")),
+            UNCLASSIFIED_CODE
+        );
+    }
+}
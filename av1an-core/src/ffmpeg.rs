@@ -1,18 +1,50 @@
 use std::{
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
+    fmt::{Display, Write as _},
+    ops::Range,
     path::{Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
 };
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use av_format::rational::Rational64;
+use num_traits::ToPrimitive;
+use once_cell::sync::OnceCell;
 use path_abs::{PathAbs, PathInfo};
 use serde::{Deserialize, Serialize};
-use tracing::warn;
+use strum::{EnumString, IntoStaticStr};
+use tracing::{info, warn};
 use vapoursynth::format::PresetFormat;
 
-use crate::{into_array, into_vec, ClipInfo, ColorRange, InputPixelFormat};
+use crate::{into_array, into_vec, ClipInfo, ColorRange, Input, InputPixelFormat};
+
+/// Protocol and demuxer options, like headers, cookies or decryption keys,
+/// that FFmpeg and ffprobe are given in front of the input and the proxy
+static INPUT_ARGS: OnceCell<Vec<String>> = OnceCell::new();
+
+/// Set the options FFmpeg and ffprobe are given in front of the input and the
+/// proxy whenever they read them. Must be called before the input is probed.
+#[inline]
+pub fn set_input_args(args: Vec<String>) {
+    INPUT_ARGS.get_or_init(|| args);
+}
+
+/// The options set with [`set_input_args`]
+#[inline]
+pub fn input_args() -> &'static [String] {
+    INPUT_ARGS.get().map_or(&[], Vec::as_slice)
+}
+
+/// FFmpeg arguments that open `source`, which must be the input or the proxy,
+/// with the options set with [`set_input_args`]
+pub(crate) fn source_args(source: &Path) -> Vec<OsString> {
+    input_args()
+        .iter()
+        .map(OsString::from)
+        .chain([OsString::from("-i"), source.into()])
+        .collect()
+}
 
 #[inline]
 pub fn compose_ffmpeg_pipe<S: Into<String>>(
@@ -37,6 +69,28 @@ pub fn compose_ffmpeg_pipe<S: Into<String>>(
     p
 }
 
+/// Returns the global FFmpeg filter args with a chunk's own filter graph
+/// appended, merging it into an existing video filter graph if there is one
+#[inline]
+pub fn chunk_filter_args(global: &[String], chunk_filter: Option<&str>) -> Vec<String> {
+    let mut args = global.to_vec();
+    let Some(chunk_filter) = chunk_filter else {
+        return args;
+    };
+
+    if let Some(index) = args.iter().position(|arg| matches!(arg.as_str(), "-vf" | "-filter:v"))
+        && let Some(graph) = args.get_mut(index + 1)
+    {
+        graph.push(',');
+        graph.push_str(chunk_filter);
+    } else {
+        args.push("-vf".to_string());
+        args.push(chunk_filter.to_string());
+    }
+
+    args
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct FfProbeInfo {
     pub streams: Vec<FfProbeStreamInfo>,
@@ -64,6 +118,7 @@ pub fn get_clip_info(source: &Path) -> anyhow::Result<ClipInfo> {
         .arg("json")
         .arg("-show_entries")
         .arg("stream=width,height,pix_fmt,avg_frame_rate,nb_frames,color_range,color_transfer")
+        .args(input_args())
         .arg(source)
         .output()?
         .stdout;
@@ -91,7 +146,7 @@ pub fn get_clip_info(source: &Path) -> anyhow::Result<ClipInfo> {
         },
         num_frames: match stream_info.nb_frames.as_deref().map(str::parse) {
             Some(Ok(nb_frames)) => nb_frames,
-            _ => get_num_frames(source)?,
+            _ => count_frames(source, input_args())?,
         },
     })
 }
@@ -118,6 +173,11 @@ fn parse_ffprobe_color_range(color_range: &str) -> Option<ColorRange> {
 /// Get frame count using FFmpeg
 #[inline]
 pub fn get_num_frames(source: &Path) -> anyhow::Result<usize> {
+    count_frames(source, &[])
+}
+
+/// Get frame count using FFmpeg, with `options` in front of the source
+fn count_frames(source: &Path, options: &[String]) -> anyhow::Result<usize> {
     let output = Command::new("ffprobe")
         .arg("-v")
         .arg("error")
@@ -128,6 +188,7 @@ pub fn get_num_frames(source: &Path) -> anyhow::Result<usize> {
         .arg("stream=nb_read_packets")
         .arg("-print_format")
         .arg("csv=p=0")
+        .args(options)
         .arg(source)
         .output()?
         .stdout;
@@ -136,13 +197,13 @@ pub fn get_num_frames(source: &Path) -> anyhow::Result<usize> {
         _ => {
             // If we got empty output or a 0 frame count, try using the slower
             // but more reliable method
-            get_num_frames_slow(source)
+            get_num_frames_slow(source, options)
         },
     }
 }
 
 /// Slower but more reliable frame count method
-fn get_num_frames_slow(source: &Path) -> anyhow::Result<usize> {
+fn get_num_frames_slow(source: &Path, options: &[String]) -> anyhow::Result<usize> {
     let output = Command::new("ffprobe")
         .arg("-v")
         .arg("error")
@@ -153,20 +214,23 @@ fn get_num_frames_slow(source: &Path) -> anyhow::Result<usize> {
         .arg("stream=nb_read_frames")
         .arg("-print_format")
         .arg("default=noprint_wrappers=1:nokey=1")
+        .args(options)
         .arg(source)
         .output()?
         .stdout;
     Ok(String::from_utf8_lossy(&output).trim().parse::<usize>()?)
 }
 
-fn parse_frame_rate(rate: &str) -> anyhow::Result<Rational64> {
-    let (numer, denom) = rate
-        .split_once('/')
-        .ok_or_else(|| anyhow::anyhow!("failed to parse frame rate from ffprobe output"))?;
-    Ok(Rational64::new(
-        numer.parse::<i64>()?,
-        denom.parse::<i64>()?,
-    ))
+/// Parses a frame rate written as a fraction like `24000/1001`, as ffprobe
+/// prints them, or as a whole number
+#[inline]
+pub fn parse_frame_rate(rate: &str) -> anyhow::Result<Rational64> {
+    let (numer, denom) = rate.split_once('/').unwrap_or((rate, "1"));
+    let (numer, denom) = (numer.trim().parse::<i64>()?, denom.trim().parse::<i64>()?);
+    if numer <= 0 || denom <= 0 {
+        bail!("frame rate {rate} is not positive");
+    }
+    Ok(Rational64::new(numer, denom))
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -197,6 +261,7 @@ pub fn get_keyframes(source: &Path) -> anyhow::Result<Vec<usize>> {
         .arg("v:0")
         .arg("-show_entries")
         .arg("frame=key_frame")
+        .args(input_args())
         .arg(source)
         .output()?
         .stdout;
@@ -211,36 +276,569 @@ pub fn get_keyframes(source: &Path) -> anyhow::Result<Vec<usize>> {
 /// Returns true if input file have audio in it
 #[inline]
 pub fn has_audio(file: &Path) -> anyhow::Result<bool> {
+    has_streams(file, "a")
+}
+
+#[inline]
+pub fn has_subtitles(file: &Path) -> anyhow::Result<bool> {
+    has_streams(file, "s")
+}
+
+fn has_streams(file: &Path, stream_specifier: &str) -> anyhow::Result<bool> {
+    Ok(stream_count(file, stream_specifier)? > 0)
+}
+
+fn stream_count(file: &Path, stream_specifier: &str) -> anyhow::Result<usize> {
     let output = Command::new("ffprobe")
         .arg("-v")
         .arg("error")
         .arg("-select_streams")
-        .arg("a")
+        .arg(stream_specifier)
         .arg("-show_entries")
         .arg("stream=index")
         .arg("-of")
         .arg("csv=p=0")
+        .args(input_args())
         .arg(file)
         .output()?
         .stdout;
     let output = String::from_utf8_lossy(&output);
-    Ok(!output.trim().is_empty())
+    Ok(output.lines().filter(|line| !line.trim().is_empty()).count())
+}
+
+/// Returns the sample rate of every audio stream of `file`, or 48 kHz if
+/// ffprobe does not report it
+fn audio_sample_rates(file: &Path) -> anyhow::Result<Vec<u32>> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a")
+        .arg("-show_entries")
+        .arg("stream=sample_rate")
+        .arg("-of")
+        .arg("csv=p=0")
+        .args(input_args())
+        .arg(file)
+        .output()?
+        .stdout;
+    let output = String::from_utf8_lossy(&output);
+    Ok(output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.trim().parse().unwrap_or(48_000))
+        .collect())
+}
+
+/// What to do with CEA-608/708 closed captions embedded in the source video
+/// stream, which would otherwise be lost when the video is re-encoded
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CaptionMode {
+    /// Only warn that the captions will be lost
+    #[strum(serialize = "ignore")]
+    Ignore,
+    /// Write the captions to an SRT file next to the output
+    #[strum(serialize = "extract")]
+    Extract,
+    /// Mux the captions into the output as a subtitle track
+    #[strum(serialize = "mux")]
+    Mux,
+}
+
+impl Display for CaptionMode {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+/// Lossless codec of the intermediate that the filtered input is encoded to
+/// with `--lossless-intermediate`
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IntermediateCodec {
+    /// FFV1, which supports every bit depth and compresses better
+    #[strum(serialize = "ffv1")]
+    Ffv1,
+    /// Ut Video, which is faster to encode and decode, but only 8-bit
+    #[strum(serialize = "utvideo")]
+    UtVideo,
+}
+
+impl Display for IntermediateCodec {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+impl IntermediateCodec {
+    /// FFmpeg options that encode the video with the codec. Every frame is a
+    /// keyframe, so the intermediate can be split on any frame.
+    const fn codec_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Ffv1 => {
+                &["-c:v", "ffv1", "-level", "3", "-g", "1", "-slices", "16", "-slicecrc", "1"]
+            },
+            Self::UtVideo => &["-c:v", "utvideo"],
+        }
+    }
+}
+
+/// Encodes the video of `input` with `filter_args` applied to a lossless
+/// intermediate at `output`, without the audio and subtitles. The file is
+/// only created once it is complete.
+pub(crate) fn create_intermediate(
+    input: &Input,
+    filter_args: &[String],
+    codec: IntermediateCodec,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let partial = output.with_extension("part.mkv");
+    let mut ffmpeg = Command::new("ffmpeg");
+    ffmpeg.args(["-hide_banner", "-loglevel", "error", "-y"]);
+    let mut vspipe = match input {
+        Input::Video {
+            path, ..
+        } => {
+            ffmpeg.args(source_args(path)).stdin(Stdio::null());
+            None
+        },
+        Input::VapourSynth {
+            path,
+            vspipe_args,
+            ..
+        } => {
+            let mut vspipe = Command::new("vspipe");
+            for arg in vspipe_args {
+                vspipe.args(["-a", arg]);
+            }
+            let mut vspipe = vspipe
+                .args(["-c", "y4m"])
+                .arg(path)
+                .arg("-")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .context("Failed to run vspipe for the lossless intermediate")?;
+            ffmpeg
+                .args(["-i", "-"])
+                .stdin(vspipe.stdout.take().expect("vspipe stdout should exist"));
+            Some(vspipe)
+        },
+    };
+    let result = ffmpeg
+        .args(filter_args)
+        .args(["-an", "-sn", "-dn"])
+        .args(codec.codec_args())
+        .arg(&partial)
+        .stdout(Stdio::null())
+        .output()
+        .context("Failed to run ffmpeg for the lossless intermediate");
+    if let Some(vspipe) = &mut vspipe {
+        vspipe.kill().ok();
+        vspipe.wait().ok();
+    }
+    let out = result?;
+    if !out.status.success() {
+        bail!(
+            "FFmpeg failed to create the lossless intermediate: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    std::fs::rename(&partial, output)?;
+
+    Ok(())
+}
+
+/// Returns whether the first video stream carries embedded CEA-608/708 closed
+/// captions
+#[inline]
+pub fn has_closed_captions(file: &Path) -> anyhow::Result<bool> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=closed_captions", "-of", "csv=p=0"])
+        .args(input_args())
+        .arg(file)
+        .output()?
+        .stdout;
+    Ok(String::from_utf8_lossy(&output).trim() == "1")
+}
+
+/// Extracts the closed captions embedded in the video stream of `input` to an
+/// SRT file, blocking the current thread.
+///
+/// This decodes the whole video stream, as the captions are stored
+/// alongside the video frames.
+#[inline]
+pub fn extract_closed_captions(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let out = Command::new("ffmpeg")
+        .args(["-y", "-hide_banner", "-loglevel", "error", "-f", "lavfi", "-i"])
+        .arg(format!(
+            "movie={}[out0+subcc]",
+            escape_path_in_filter(input)?
+        ))
+        .args(["-map", "0:s", "-c:s", "srt"])
+        .arg(output)
+        .output()?;
+    anyhow::ensure!(
+        out.status.success(),
+        "FFmpeg failed to extract closed captions: {}",
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+
+    Ok(())
+}
+
+/// Handles the closed captions of `input` according to `mode`, blocking the
+/// current thread.
+///
+/// Returns the path of the extracted captions if they should be muxed into the
+/// output.
+#[inline]
+pub fn handle_closed_captions(
+    input: &Path,
+    temp: &Path,
+    output_file: &Path,
+    mode: CaptionMode,
+) -> anyhow::Result<Option<PathBuf>> {
+    if !has_closed_captions(input)? {
+        return Ok(None);
+    }
+
+    match mode {
+        CaptionMode::Ignore => {
+            warn!(
+                "The source contains closed captions, which will not be kept in the output. Use \
+                 --captions extract or --captions mux to keep them"
+            );
+            Ok(None)
+        },
+        CaptionMode::Extract => {
+            let sidecar = output_file.with_extension("srt");
+            extract_closed_captions(input, &sidecar)?;
+            info!("closed captions written to {}", sidecar.display());
+            Ok(None)
+        },
+        CaptionMode::Mux => {
+            let captions = temp.join("captions.srt");
+            extract_closed_captions(input, &captions)?;
+            Ok(Some(captions))
+        },
+    }
+}
+
+/// Loudness normalization applied to the audio
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AudioNormalization {
+    /// Normalize to the EBU R128 target of -23 LUFS, with a true peak of at
+    /// most -1 dBTP
+    #[strum(serialize = "ebur128")]
+    Ebur128,
+}
+
+impl Display for AudioNormalization {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+impl AudioNormalization {
+    /// Target integrated loudness, loudness range and true peak, as options of
+    /// the loudnorm filter
+    const fn targets(self) -> &'static str {
+        match self {
+            Self::Ebur128 => "I=-23:LRA=7:TP=-1",
+        }
+    }
+}
+
+/// Loudness of an audio stream, as printed by the measurement pass of the
+/// loudnorm filter
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct LoudnessMeasurement {
+    input_i:       String,
+    input_tp:      String,
+    input_lra:     String,
+    input_thresh:  String,
+    target_offset: String,
+}
+
+impl LoudnessMeasurement {
+    /// Returns the loudnorm filter that applies the normalization measured by
+    /// this pass. loudnorm outputs 192 kHz, so the audio is resampled back to
+    /// its `sample_rate`.
+    fn filter(&self, normalization: AudioNormalization, sample_rate: u32) -> String {
+        format!(
+            "loudnorm={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:\
+             offset={}:linear=true,aresample={sample_rate}",
+            normalization.targets(),
+            self.input_i,
+            self.input_tp,
+            self.input_lra,
+            self.input_thresh,
+            self.target_offset
+        )
+    }
+}
+
+/// Parses the JSON the loudnorm filter prints at the end of FFmpeg's output
+fn parse_loudness_measurement(stderr: &str) -> Option<LoudnessMeasurement> {
+    let start = stderr.rfind('{')?;
+    let end = stderr.rfind('}')?;
+    serde_json::from_str(stderr.get(start..=end)?).ok()
+}
+
+/// Measures the loudness of the `stream`th audio stream of `input`, after
+/// the `trim` filter if given, blocking the current thread
+fn measure_loudness(
+    input: &Path,
+    stream: usize,
+    normalization: AudioNormalization,
+    trim: Option<&str>,
+) -> anyhow::Result<LoudnessMeasurement> {
+    let loudnorm = format!("loudnorm={}:print_format=json", normalization.targets());
+    let out = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats"])
+        .args(source_args(input))
+        .args(["-map", &format!("0:a:{stream}"), "-af"])
+        .arg(trim.map_or_else(|| loudnorm.clone(), |trim| format!("{trim},{loudnorm}")))
+        .args(["-f", "null", "-"])
+        .output()?;
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    anyhow::ensure!(
+        out.status.success(),
+        "FFmpeg failed to measure the loudness of audio stream {stream}: {}",
+        stderr.trim()
+    );
+    parse_loudness_measurement(&stderr)
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse the loudness of audio stream {stream}"))
+}
+
+/// Returns the `-filter:a:N` arguments that trim every audio stream of
+/// `input` with the `trim` filter and normalize its loudness, measuring each
+/// stream first
+fn audio_filter_args(
+    input: &Path,
+    normalization: Option<AudioNormalization>,
+    trim: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    if normalization.is_none() && trim.is_none() {
+        return Ok(args);
+    }
+    for (stream, sample_rate) in audio_sample_rates(input)?.into_iter().enumerate() {
+        let mut filters = Vec::from_iter(trim.map(str::to_string));
+        if let Some(normalization) = normalization {
+            let measurement = measure_loudness(input, stream, normalization, trim)?;
+            if measurement.input_i.parse::<f64>().is_ok_and(f64::is_finite) {
+                info!(
+                    "audio stream {stream}: {} LUFS, normalizing to {normalization}",
+                    measurement.input_i
+                );
+                filters.push(measurement.filter(normalization, sample_rate));
+            } else {
+                warn!("Audio stream {stream} is silent, not normalizing its loudness");
+            }
+        }
+        if !filters.is_empty() {
+            args.push(format!("-filter:a:{stream}"));
+            args.push(filters.join(","));
+        }
+    }
+    Ok(args)
+}
+
+/// Returns the audio filter that cuts the frames `ranges` of a video at
+/// `frame_rate` out of an audio stream and joins them, so that the audio stays
+/// in sync with a video of only those frames. `atrim` cuts at the exact
+/// sample rather than at the audio frames around the cut.
+#[inline]
+pub fn audio_trim_filter(ranges: &[Range<usize>], frame_rate: Rational64) -> String {
+    let seconds = |frame: usize| {
+        let time = Rational64::from_integer(frame as i64) / frame_rate;
+        time.to_f64().expect("time is not NaN")
+    };
+    let trim = |range: &Range<usize>| {
+        format!(
+            "atrim=start={:.9}:end={:.9},asetpts=PTS-STARTPTS",
+            seconds(range.start),
+            seconds(range.end)
+        )
+    };
+    if let [range] = ranges {
+        return trim(range);
+    }
+
+    let mut filter = format!("asplit={}", ranges.len());
+    for i in 0..ranges.len() {
+        write!(filter, "[in{i}]").expect("writing to a String should not fail");
+    }
+    for (i, range) in ranges.iter().enumerate() {
+        write!(filter, ";[in{i}]{}[out{i}]", trim(range))
+            .expect("writing to a String should not fail");
+    }
+    filter.push(';');
+    for i in 0..ranges.len() {
+        write!(filter, "[out{i}]").expect("writing to a String should not fail");
+    }
+    write!(filter, "concat=n={}:v=0:a=1", ranges.len())
+        .expect("writing to a String should not fail");
+    filter
+}
+
+/// Returns whether `audio_params` re-encode the audio rather than copying it,
+/// which is required to apply filters to it
+#[inline]
+pub fn audio_params_reencode<S: AsRef<str>>(audio_params: &[S]) -> bool {
+    audio_params.windows(2).any(|pair| {
+        let flag = pair[0].as_ref();
+        (flag == "-acodec" || flag.starts_with("-c:a") || flag.starts_with("-codec:a"))
+            && pair[1].as_ref() != "copy"
+    })
+}
+
+/// Returns the value `audio_params` give one of `options` for the `stream`th
+/// audio stream, set for all streams (`-b`), all audio streams (`-b:a`) or
+/// only that stream (`-b:a:1`). Later values replace earlier ones, as in
+/// FFmpeg.
+fn audio_stream_option<'a>(
+    audio_params: &'a [String],
+    options: &[&str],
+    stream: usize,
+) -> Option<&'a str> {
+    audio_params
+        .windows(2)
+        .rev()
+        .find(|pair| {
+            options.iter().any(|option| {
+                pair[0] == *option
+                    || pair[0] == format!("{option}:a")
+                    || pair[0] == format!("{option}:a:{stream}")
+            })
+        })
+        .map(|pair| pair[1].as_str())
+}
+
+/// Returns the bitrate in kbit/s to encode `channels` audio channels with the
+/// FFmpeg encoder `codec` at, or `None` for lossless codecs and codecs
+/// without a known sensible bitrate
+fn default_audio_bitrate(codec: &str, channels: u32) -> Option<u32> {
+    // mono, stereo, 5.1 and 7.1, layouts in between get the bitrate of the
+    // next larger one
+    let [mono, stereo, surround, surround_71] = match codec {
+        "libopus" | "opus" => [64, 128, 256, 384],
+        "aac" | "libfdk_aac" | "aac_at" => [96, 192, 384, 512],
+        "libvorbis" | "vorbis" => [96, 160, 320, 448],
+        "ac3" | "ac3_fixed" => [192, 224, 448, 640],
+        "eac3" => [96, 192, 384, 768],
+        "libmp3lame" | "mp3" => [128, 192, 320, 320],
+        _ => return None,
+    };
+    Some(match channels {
+        0 | 1 => mono,
+        2 => stereo,
+        3..=6 => surround,
+        _ => surround_71,
+    })
+}
+
+/// Returns the `-b:a:N` arguments that set the bitrate of every audio stream
+/// that `audio_params` re-encode without setting its bitrate or quality, for
+/// the number of channels of the streams, so that FFmpeg's defaults, which
+/// are too low for surround sound with most encoders, are not used
+fn audio_bitrate_args(audio_params: &[String], channels: &[u32]) -> Vec<String> {
+    let mut args = Vec::new();
+    for (stream, &source_channels) in channels.iter().enumerate() {
+        let Some(codec) = audio_stream_option(audio_params, &["-c", "-codec", "-acodec"], stream)
+        else {
+            continue;
+        };
+        if codec == "copy"
+            || audio_stream_option(audio_params, &["-b", "-ab", "-q", "-aq", "-qscale"], stream)
+                .is_some()
+        {
+            continue;
+        }
+        // the audio may be downmixed or upmixed while it is encoded
+        let channels = audio_stream_option(audio_params, &["-ac"], stream)
+            .and_then(|channels| channels.parse().ok())
+            .unwrap_or(source_channels);
+        let Some(bitrate) = default_audio_bitrate(codec, channels) else {
+            continue;
+        };
+        info!("audio stream {stream}: encoding {channels} channels with {codec} at {bitrate}k");
+        args.push(format!("-b:a:{stream}"));
+        args.push(format!("{bitrate}k"));
+    }
+    args
+}
+
+/// Returns the number of channels of every audio stream of `file`
+fn audio_channels(file: &Path) -> anyhow::Result<Vec<u32>> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a")
+        .arg("-show_entries")
+        .arg("stream=channels")
+        .arg("-of")
+        .arg("csv=p=0")
+        .args(input_args())
+        .arg(file)
+        .output()?
+        .stdout;
+    let output = String::from_utf8_lossy(&output);
+    Ok(output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.trim().parse().unwrap_or(2))
+        .collect())
 }
 
 /// Encodes the audio using FFmpeg, blocking the current thread.
 ///
-/// This function returns `Some(output)` if the audio exists and the audio
+/// Subtitle streams of the source, and the extracted closed captions if
+/// given, are copied along with the audio. If `trim` is given, only the audio
+/// of those frame ranges at that frame rate is kept, see
+/// [`audio_trim_filter`], and the subtitles are left out, as their timestamps
+/// would no longer match.
+///
+/// This function returns `Some(output)` if the audio or subtitles exist and
 /// successfully encoded, or `None` otherwise.
 #[inline]
 pub fn encode_audio<S: AsRef<OsStr>>(
     input: impl AsRef<Path> + std::fmt::Debug,
     temp: impl AsRef<Path> + std::fmt::Debug,
     audio_params: &[S],
+    captions: Option<&Path>,
+    normalization: Option<AudioNormalization>,
+    trim: Option<(&[Range<usize>], Rational64)>,
 ) -> anyhow::Result<Option<PathBuf>> {
     let input = input.as_ref();
     let temp = temp.as_ref();
 
-    if has_audio(input)? {
+    let has_output = if trim.is_some() {
+        // without subtitles, there is nothing left if the audio is dropped
+        !audio_params.iter().any(|param| param.as_ref() == "-an") && has_audio(input)?
+    } else {
+        has_audio(input)? || has_subtitles(input)? || captions.is_some()
+    };
+    if has_output {
+        let trim_filter = trim.map(|(ranges, frame_rate)| audio_trim_filter(ranges, frame_rate));
+        // The measurement pass decodes all of the audio, so it is done here
+        // rather than before the encode, where it would delay the video
+        let audio_filters = audio_filter_args(input, normalization, trim_filter.as_deref())?;
+        let audio_params = audio_params
+            .iter()
+            .map(|param| param.as_ref().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let audio_bitrates = if audio_params_reencode(&audio_params) {
+            audio_bitrate_args(&audio_params, &audio_channels(input)?)
+        } else {
+            Vec::new()
+        };
         let audio_file = Path::new(temp).join("audio.mkv");
         let mut encode_audio = Command::new("ffmpeg");
 
@@ -248,11 +846,27 @@ pub fn encode_audio<S: AsRef<OsStr>>(
         encode_audio.stderr(Stdio::piped());
 
         encode_audio.args(["-y", "-hide_banner", "-loglevel", "error"]);
-        encode_audio.args(["-i", &input.to_string_lossy()]);
+        encode_audio.args(source_args(input));
+        let captions = captions.filter(|_| trim.is_none());
+        if let Some(captions) = captions {
+            encode_audio.args(["-i", &captions.to_string_lossy()]);
+        }
         encode_audio.args(["-map_metadata", "0"]);
         encode_audio.args(["-map", "0", "-c", "copy", "-vn", "-dn"]);
+        if trim.is_some() {
+            encode_audio.arg("-sn");
+        }
+        if captions.is_some() {
+            // the captions follow the subtitle streams of the source
+            let index = stream_count(input, "s")?;
+            encode_audio.args(["-map", "1:s"]);
+            encode_audio.arg(format!("-metadata:s:s:{index}"));
+            encode_audio.arg("title=Closed Captions");
+        }
 
-        encode_audio.args(audio_params);
+        encode_audio.args(&audio_params);
+        encode_audio.args(&audio_bitrates);
+        encode_audio.args(&audio_filters);
         encode_audio.arg(&audio_file);
 
         let output = encode_audio.output()?;
@@ -268,6 +882,188 @@ pub fn encode_audio<S: AsRef<OsStr>>(
     }
 }
 
+/// Returns the ffmpeg input and filter arguments that decode the frames
+/// `start..end` of `source`.
+///
+/// Instead of decoding every frame from the start of the source, ffmpeg seeks
+/// to the keyframe before `start` and drops the frames up to it. The seek
+/// position is half a frame before `start`, so that rounding of the
+/// timestamps can never drop the first frame, and the select filter makes
+/// sure that exactly the frames of the window are returned.
+#[inline]
+pub fn select_frames_args(
+    source: &Path,
+    start: usize,
+    end: usize,
+    frame_rate: f64,
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = Vec::new();
+    if start > 0 {
+        let seek = (start as f64 - 0.5) / frame_rate;
+        args.extend(into_vec!["-ss", format!("{seek:.6}")]);
+    }
+    let frames = end - start;
+    args.extend(source_args(source));
+    args.extend(into_vec![
+        "-vf",
+        format!(r"select=between(n\,0\,{last})", last = frames - 1),
+        // stop decoding after the last frame instead of at the end of the source
+        "-frames:v",
+        frames.to_string(),
+    ]);
+    args
+}
+
+/// Where the subtitles burned into the video with `--burn-subs` come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubtitleSource {
+    /// Index of a subtitle track of the input, counting only subtitle tracks
+    Track(usize),
+    /// A separate subtitle file
+    File(PathBuf),
+}
+
+/// Subtitles rendered into the video of every chunk before encoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BurnSubtitles {
+    pub source: SubtitleSource,
+    /// Whether the subtitles are text (e.g. ASS or SRT) rather than images
+    /// (e.g. PGS or VobSub), found by probing them when the settings are
+    /// validated
+    pub text:   bool,
+}
+
+impl FromStr for BurnSubtitles {
+    type Err = anyhow::Error;
+
+    #[inline]
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let source = s.parse().map_or_else(
+            |_| SubtitleSource::File(PathBuf::from(s)),
+            SubtitleSource::Track,
+        );
+        Ok(Self {
+            source,
+            text: false,
+        })
+    }
+}
+
+impl Display for BurnSubtitles {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            SubtitleSource::Track(track) => write!(f, "track {track}"),
+            SubtitleSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Subtitle codecs that are bitmaps, which FFmpeg's `subtitles` filter
+/// cannot render
+const IMAGE_SUBTITLE_CODECS: &[&str] =
+    &["hdmv_pgs_subtitle", "dvd_subtitle", "dvb_subtitle", "xsub"];
+
+impl BurnSubtitles {
+    /// Returns the file the subtitles are read from, and their stream
+    /// specifier within it
+    fn input<'a>(&'a self, source: &'a Path) -> (&'a Path, String) {
+        match &self.source {
+            SubtitleSource::Track(track) => (source, format!("s:{track}")),
+            SubtitleSource::File(path) => (path, "s:0".to_string()),
+        }
+    }
+
+    /// Probe the subtitle stream, setting whether it is text based
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the subtitle stream does not exist.
+    #[inline]
+    pub fn probe(&mut self, source: &Path) -> anyhow::Result<()> {
+        let (file, stream) = self.input(source);
+        let options = if file == source { input_args() } else { &[] };
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", &stream])
+            .args(["-show_entries", "stream=codec_name", "-of", "csv=p=0"])
+            .args(options)
+            .arg(file)
+            .output()?
+            .stdout;
+        let output = String::from_utf8_lossy(&output);
+        let Some(codec) = output.lines().map(str::trim).find(|line| !line.is_empty()) else {
+            bail!("{} has no subtitle stream {stream}", file.display());
+        };
+        self.text = !IMAGE_SUBTITLE_CODECS.contains(&codec);
+        Ok(())
+    }
+}
+
+/// Returns the FFmpeg filter args of a chunk with the subtitles rendered into
+/// it before any other filter, so that they are positioned relative to the
+/// source frame.
+///
+/// The piped chunk starts at timestamp 0, so the subtitle timestamps are
+/// offset by the start of the chunk. Text subtitles are rendered with the
+/// `subtitles` filter, and image subtitles are read as a second input and
+/// overlaid.
+#[inline]
+pub fn burn_subtitles_args(
+    filter_args: &[String],
+    subtitles: &BurnSubtitles,
+    source: &Path,
+    start_frame: usize,
+    frame_rate: f64,
+) -> anyhow::Result<Vec<String>> {
+    let mut args = filter_args.to_vec();
+    let graph = args
+        .iter()
+        .position(|arg| matches!(arg.as_str(), "-vf" | "-filter:v"))
+        .filter(|&index| index + 1 < args.len())
+        .map(|index| {
+            let graph = args.remove(index + 1);
+            args.remove(index);
+            format!(",{graph}")
+        })
+        .unwrap_or_default();
+    let offset = start_frame as f64 / frame_rate;
+
+    let (file, stream) = subtitles.input(source);
+    let mut burn_args = if subtitles.text {
+        let stream_index = match subtitles.source {
+            SubtitleSource::Track(track) => format!(":si={track}"),
+            SubtitleSource::File(_) => String::new(),
+        };
+        into_vec![
+            "-vf",
+            format!(
+                "setpts=PTS+{offset:.6}/TB,subtitles=filename={}{stream_index},\
+                 setpts=PTS-STARTPTS{graph}",
+                escape_path_in_filter(file)?
+            )
+        ]
+    } else {
+        let mut burn_args = Vec::new();
+        if start_frame > 0 {
+            burn_args.extend(into_vec!["-itsoffset", format!("-{offset:.6}")]);
+        }
+        if file == source {
+            burn_args.extend(input_args().iter().cloned());
+        }
+        burn_args.extend(into_vec![
+            "-i",
+            file.to_string_lossy(),
+            "-filter_complex",
+            format!("[0:v][1:{stream}]overlay=eof_action=pass{graph}[burned]"),
+            "-map",
+            "[burned]",
+        ]);
+        burn_args
+    };
+    burn_args.append(&mut args);
+    Ok(burn_args)
+}
+
 /// Escapes paths in ffmpeg filters if on windows
 #[inline]
 pub fn escape_path_in_filter(path: impl AsRef<Path>) -> anyhow::Result<String> {
@@ -287,7 +1083,7 @@ pub fn escape_path_in_filter(path: impl AsRef<Path>) -> anyhow::Result<String> {
 }
 
 /// Pixel formats supported by ffmpeg
-#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize, Deserialize, strum::EnumIter)]
 pub enum FFPixelFormat {
     GBRP,
     GBRP10LE,
@@ -355,6 +1151,67 @@ impl FFPixelFormat {
         }
     }
 
+    /// Number of luma pixels per chroma sample, horizontally and vertically
+    #[inline]
+    pub const fn chroma_subsampling(self) -> (u32, u32) {
+        match self {
+            Self::NV12
+            | Self::NV21
+            | Self::YUV420P
+            | Self::YUV420P10LE
+            | Self::YUV420P12LE
+            | Self::YUVA420P
+            | Self::YUVJ420P => (2, 2),
+            Self::NV16
+            | Self::NV20LE
+            | Self::YUV422P
+            | Self::YUV422P10LE
+            | Self::YUV422P12LE
+            | Self::YUVJ422P => (2, 1),
+            Self::YUV440P | Self::YUV440P10LE | Self::YUV440P12LE => (1, 2),
+            Self::GBRP
+            | Self::GBRP10LE
+            | Self::GBRP12L
+            | Self::GBRP12LE
+            | Self::GRAY10LE
+            | Self::GRAY12L
+            | Self::GRAY12LE
+            | Self::GRAY8
+            | Self::YUV444P
+            | Self::YUV444P10LE
+            | Self::YUV444P12LE
+            | Self::YUVJ444P => (1, 1),
+        }
+    }
+
+    /// Returns the pixel format with the same color layout as this one at
+    /// `bit_depth` bits per component, if there is one
+    #[inline]
+    pub fn with_bit_depth(self, bit_depth: usize) -> Option<Self> {
+        let families = [
+            [Some(Self::GBRP), Some(Self::GBRP10LE), Some(Self::GBRP12LE)],
+            [Some(Self::GRAY8), Some(Self::GRAY10LE), Some(Self::GRAY12LE)],
+            [Some(Self::NV16), Some(Self::NV20LE), None],
+            [Some(Self::YUV420P), Some(Self::YUV420P10LE), Some(Self::YUV420P12LE)],
+            [Some(Self::YUV422P), Some(Self::YUV422P10LE), Some(Self::YUV422P12LE)],
+            [Some(Self::YUV440P), Some(Self::YUV440P10LE), Some(Self::YUV440P12LE)],
+            [Some(Self::YUV444P), Some(Self::YUV444P10LE), Some(Self::YUV444P12LE)],
+        ];
+
+        let format = match self {
+            Self::GBRP12L => Self::GBRP12LE,
+            Self::GRAY12L => Self::GRAY12LE,
+            format => format,
+        };
+        let family = families.iter().find(|family| family.contains(&Some(format)))?;
+        match bit_depth {
+            8 => family[0],
+            10 => family[1],
+            12 => family[2],
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn to_vapoursynth_format(&self) -> anyhow::Result<PresetFormat> {
         Ok(match self {
@@ -492,6 +1349,132 @@ impl FromStr for FFPixelFormat {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_frame_rates() {
+        assert_eq!(
+            parse_frame_rate("24000/1001").expect("should parse"),
+            Rational64::new(24000, 1001)
+        );
+        assert_eq!(
+            parse_frame_rate("60").expect("should parse"),
+            Rational64::from_integer(60)
+        );
+        assert!(parse_frame_rate("0/0").is_err());
+        assert!(parse_frame_rate("59.94").is_err());
+    }
+
+    #[test]
+    fn intermediate_codecs_are_intra_only() {
+        assert_eq!(
+            IntermediateCodec::from_str("ffv1").expect("should parse"),
+            IntermediateCodec::Ffv1
+        );
+        assert!(IntermediateCodec::from_str("huffyuv").is_err());
+        let args = IntermediateCodec::Ffv1.codec_args();
+        let gop = args.iter().position(|&arg| arg == "-g").expect("should set the GOP size");
+        assert_eq!(args[gop + 1], "1");
+        // Ut Video only has intra frames
+        assert_eq!(IntermediateCodec::UtVideo.codec_args(), ["-c:v", "utvideo"]);
+    }
+
+    #[test]
+    fn audio_trim_filters() {
+        let fps = Rational64::new(24000, 1001);
+        assert_eq!(
+            audio_trim_filter(
+                &[Range {
+                    start: 24,
+                    end:   48,
+                }],
+                fps
+            ),
+            "atrim=start=1.001000000:end=2.002000000,asetpts=PTS-STARTPTS"
+        );
+        assert_eq!(
+            audio_trim_filter(&[0..24, 240..264], Rational64::from_integer(24)),
+            "asplit=2[in0][in1];[in0]atrim=start=0.000000000:end=1.000000000,\
+             asetpts=PTS-STARTPTS[out0];[in1]atrim=start=10.000000000:end=11.000000000,\
+             asetpts=PTS-STARTPTS[out1];[out0][out1]concat=n=2:v=0:a=1"
+        );
+    }
+
+    #[test]
+    fn loudness_measurement_filter() {
+        let stderr = r#"[Parsed_loudnorm_0 @ 0x5581c0a1e2c0]
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-23.13",
+	"output_tp" : "-2.00",
+	"output_lra" : "13.50",
+	"output_thresh" : "-34.47",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.13"
+}
+"#;
+        let measurement = parse_loudness_measurement(stderr).expect("measurement should parse");
+        assert_eq!(
+            measurement.filter(AudioNormalization::Ebur128, 44_100),
+            "loudnorm=I=-23:LRA=7:TP=-1:measured_I=-27.61:measured_TP=-4.47:measured_LRA=18.06:\
+             measured_thresh=-39.20:offset=0.13:linear=true,aresample=44100"
+        );
+        assert_eq!(parse_loudness_measurement("Error opening input"), None);
+    }
+
+    #[test]
+    fn select_frames_seeks_before_start() {
+        let source = Path::new("input.mkv");
+        assert_eq!(select_frames_args(source, 0, 48, 24.0), [
+            "-i",
+            "input.mkv",
+            "-vf",
+            r"select=between(n\,0\,47)",
+            "-frames:v",
+            "48"
+        ]);
+        assert_eq!(select_frames_args(source, 240, 300, 24.0), [
+            "-ss",
+            "9.979167",
+            "-i",
+            "input.mkv",
+            "-vf",
+            r"select=between(n\,0\,59)",
+            "-frames:v",
+            "60"
+        ]);
+    }
+
+    #[test]
+    fn audio_params_reencode_codecs() {
+        assert!(!audio_params_reencode(&["-c:a", "copy"]));
+        assert!(audio_params_reencode(&["-c:a", "libopus", "-b:a", "128k"]));
+        assert!(audio_params_reencode(&["-c:a:1", "aac"]));
+        assert!(audio_params_reencode(&["-acodec", "flac"]));
+        assert!(!audio_params_reencode(&["-b:a", "128k"]));
+    }
+
+    #[test]
+    fn audio_bitrates_from_channels() {
+        let params: Vec<String> = into_vec!["-c:a", "libopus"];
+        assert_eq!(audio_bitrate_args(&params, &[2, 6, 8]), [
+            "-b:a:0", "128k", "-b:a:1", "256k", "-b:a:2", "384k"
+        ]);
+
+        // downmixed streams get the bitrate of the output layout
+        let params: Vec<String> = into_vec!["-c:a:0", "aac", "-ac:a:0", "2", "-c:a:1", "flac"];
+        assert_eq!(audio_bitrate_args(&params, &[6, 6]), ["-b:a:0", "192k"]);
+
+        // bitrates and qualities set by the user are kept
+        let params: Vec<String> = into_vec!["-c:a", "libopus", "-b:a:1", "96k"];
+        assert_eq!(audio_bitrate_args(&params, &[6, 2]), ["-b:a:0", "256k"]);
+        let params: Vec<String> = into_vec!["-c:a", "libvorbis", "-q:a", "6"];
+        assert!(audio_bitrate_args(&params, &[2]).is_empty());
+        let params: Vec<String> = into_vec!["-c:a", "copy"];
+        assert!(audio_bitrate_args(&params, &[2]).is_empty());
+    }
+
     #[test]
     fn parse_ffprobe_color_range_aliases() {
         assert_eq!(parse_ffprobe_color_range("pc"), Some(ColorRange::Full));
@@ -506,6 +1489,77 @@ mod tests {
         assert_eq!(parse_ffprobe_color_range("unknown"), None);
     }
 
+    #[test]
+    fn chunk_filter_args_merge() {
+        let global: Vec<String> = into_vec!["-vf", "crop=1920:800", "-sws_flags", "lanczos"];
+        assert_eq!(chunk_filter_args(&global, None), global);
+        assert_eq!(chunk_filter_args(&global, Some("gradfun")), vec![
+            "-vf",
+            "crop=1920:800,gradfun",
+            "-sws_flags",
+            "lanczos"
+        ]);
+        assert_eq!(chunk_filter_args(&[], Some("scale=1280:-2")), vec![
+            "-vf",
+            "scale=1280:-2"
+        ]);
+    }
+
+    #[test]
+    fn burn_image_subtitles_offset_by_chunk_start() {
+        let subtitles: BurnSubtitles = "2".parse().expect("should parse track");
+        assert_eq!(subtitles.source, SubtitleSource::Track(2));
+        let global: Vec<String> = into_vec!["-vf", "crop=1920:800", "-sws_flags", "lanczos"];
+        let args = burn_subtitles_args(&global, &subtitles, Path::new("in.mkv"), 48, 24.0)
+            .expect("should build args");
+        assert_eq!(args, [
+            "-itsoffset",
+            "-2.000000",
+            "-i",
+            "in.mkv",
+            "-filter_complex",
+            "[0:v][1:s:2]overlay=eof_action=pass,crop=1920:800[burned]",
+            "-map",
+            "[burned]",
+            "-sws_flags",
+            "lanczos"
+        ]);
+
+        let subtitles: BurnSubtitles = "subs.sup".parse().expect("should parse file");
+        let args = burn_subtitles_args(&[], &subtitles, Path::new("in.mkv"), 0, 24.0)
+            .expect("should build args");
+        assert_eq!(args, [
+            "-i",
+            "subs.sup",
+            "-filter_complex",
+            "[0:v][1:s:0]overlay=eof_action=pass[burned]",
+            "-map",
+            "[burned]"
+        ]);
+    }
+
+    #[test]
+    fn caption_mode_round_trip() {
+        for mode in [CaptionMode::Ignore, CaptionMode::Extract, CaptionMode::Mux] {
+            assert_eq!(mode.to_string().parse::<CaptionMode>(), Ok(mode));
+        }
+        assert!("copy".parse::<CaptionMode>().is_err());
+    }
+
+    #[test]
+    fn pix_format_with_bit_depth() {
+        assert_eq!(
+            FFPixelFormat::YUV420P10LE.with_bit_depth(8),
+            Some(FFPixelFormat::YUV420P)
+        );
+        assert_eq!(
+            FFPixelFormat::GBRP12L.with_bit_depth(10),
+            Some(FFPixelFormat::GBRP10LE)
+        );
+        assert_eq!(FFPixelFormat::NV20LE.with_bit_depth(12), None);
+        assert_eq!(FFPixelFormat::YUVJ420P.with_bit_depth(8), None);
+    }
+
     #[test]
     fn infer_color_range_from_legacy_jpeg_formats() {
         assert_eq!(
@@ -0,0 +1,320 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+use anyhow::{bail, Context};
+use num_traits::cast::ToPrimitive;
+use strum::{EnumString, IntoStaticStr};
+use tracing::{info, warn};
+
+use crate::{
+    ffmpeg::source_args,
+    scenes::{Scene, ZoneOptions},
+    EncodeArgs,
+};
+
+/// What to do with scenes whose resolution or active area differs from the
+/// rest of the video, as found in concert and TV recordings
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GeometryChanges {
+    /// Do not check the scenes
+    #[strum(serialize = "ignore")]
+    Ignore,
+    /// Exit with the frame ranges of the scenes that differ
+    #[strum(serialize = "error")]
+    Error,
+    /// Scale and pad the scenes that differ to the geometry of the rest of the
+    /// video
+    #[strum(serialize = "fix")]
+    Fix,
+}
+
+impl Display for GeometryChanges {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+/// Frame size of a scene and the area within it that is not black bars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Geometry {
+    width:  u32,
+    height: u32,
+    /// width, height, x and y of the area that is not black bars
+    active: (u32, u32, u32, u32),
+}
+
+impl Geometry {
+    /// Parses the last frame size printed by the `showinfo` filter and the
+    /// last area printed by the `cropdetect` filter
+    fn parse(stderr: &str) -> Option<Self> {
+        let (width, height) = stderr
+            .lines()
+            .filter(|line| line.contains("Parsed_showinfo"))
+            .filter_map(|line| line.split_whitespace().find_map(|field| field.strip_prefix("s:")))
+            .filter_map(|size| size.split_once('x'))
+            .filter_map(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+            .next_back()?;
+        let active = stderr
+            .lines()
+            .filter_map(|line| line.rsplit_once("crop=").map(|(_, crop)| crop.trim()))
+            .filter_map(|crop| {
+                let mut values = crop.split(':').map(str::parse::<u32>);
+                Some((
+                    values.next()?.ok()?,
+                    values.next()?.ok()?,
+                    values.next()?.ok()?,
+                    values.next()?.ok()?,
+                ))
+            })
+            .next_back()
+            // an entirely black sample has no active area
+            .filter(|&(w, h, ..)| w > 0 && h > 0)
+            .unwrap_or((width, height, 0, 0));
+
+        Some(Self {
+            width,
+            height,
+            active,
+        })
+    }
+
+    /// Returns whether the scene would not be encoded like the rest of the
+    /// video: it has a different frame size, or picture outside of the
+    /// active area of the rest of the video. A smaller active area is
+    /// expected in dark scenes, so it is not a change.
+    fn differs_from(&self, reference: &Self) -> bool {
+        let (w, h, x, y) = self.active;
+        let (rw, rh, rx, ry) = reference.active;
+        self.width != reference.width
+            || self.height != reference.height
+            || x < rx
+            || y < ry
+            || x + w > rx + rw
+            || y + h > ry + rh
+    }
+
+    /// FFmpeg filter graph that crops the active area of this scene, scales it
+    /// into the active area of `reference` and pads it to the frame size of
+    /// `reference`
+    fn fix_filter(&self, reference: &Self) -> String {
+        let (w, h, x, y) = self.active;
+        let (rw, rh, rx, ry) = reference.active;
+        format!(
+            "crop={w}:{h}:{x}:{y},scale=w={rw}:h={rh}:force_original_aspect_ratio=decrease:\
+             force_divisible_by=2,pad={width}:{height}:{rx}+({rw}-iw)/2:{ry}+({rh}-ih)/2",
+            width = reference.width,
+            height = reference.height
+        )
+    }
+}
+
+impl Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (w, h, x, y) = self.active;
+        write!(
+            f,
+            "{}x{}, active area {w}x{h} at {x},{y}",
+            self.width, self.height
+        )
+    }
+}
+
+/// Number of frames sampled from the middle of every scene
+const SAMPLE_FRAMES: usize = 8;
+
+/// Decode a few frames from the middle of a scene and detect their geometry
+fn probe_scene(source: &Path, scene: &Scene, frame_rate: f64) -> anyhow::Result<Geometry> {
+    let len = scene.end_frame - scene.start_frame;
+    let frames = len.min(SAMPLE_FRAMES);
+    let start = scene.start_frame + (len - frames) / 2;
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-ss"])
+        .arg(format!("{:.6}", start as f64 / frame_rate))
+        .args(source_args(source))
+        .args(["-map", "0:v:0", "-frames:v"])
+        .arg(frames.to_string())
+        .args(["-vf", "showinfo,cropdetect=round=2:reset=0", "-f", "null", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run ffmpeg to detect the geometry of a scene")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Geometry::parse(&stderr).with_context(|| {
+        format!(
+            "Failed to detect the geometry of frames {}-{}: {}",
+            scene.start_frame,
+            scene.end_frame,
+            stderr.trim()
+        )
+    })
+}
+
+/// Returns the geometry that covers the most frames
+fn reference_geometry(scenes: &[Scene], geometries: &[Geometry]) -> Option<Geometry> {
+    let mut frames: HashMap<Geometry, usize> = HashMap::new();
+    for (scene, geometry) in scenes.iter().zip(geometries) {
+        *frames.entry(*geometry).or_default() += scene.end_frame - scene.start_frame;
+    }
+    frames
+        .into_iter()
+        .max_by_key(|&(_, frames)| frames)
+        .map(|(geometry, _)| geometry)
+}
+
+/// Check the resolution and active area of every scene, and handle the scenes
+/// that differ from the rest of the video according to
+/// [`EncodeArgs::geometry_changes`]
+pub(crate) fn handle_geometry_changes(
+    args: &EncodeArgs,
+    scenes: &mut [Scene],
+) -> anyhow::Result<()> {
+    if args.geometry_changes == GeometryChanges::Ignore || scenes.is_empty() {
+        return Ok(());
+    }
+    if !args.input.is_video() {
+        warn!("--geometry-changes has no effect with VapourSynth script input");
+        return Ok(());
+    }
+
+    info!("Checking the geometry of {} scenes", scenes.len());
+    let source = args.input.as_video_path();
+    let frame_rate = args
+        .input
+        .clip_info()?
+        .frame_rate
+        .to_f64()
+        .expect("frame rate should not be NaN");
+    let workers = thread::available_parallelism().map_or(1, usize::from);
+    let per_worker = scenes.len().div_ceil(workers);
+    let geometries = thread::scope(|scope| {
+        #[expect(
+            clippy::needless_collect,
+            reason = "every thread must be spawned before the first is joined"
+        )]
+        let handles: Vec<_> = scenes
+            .chunks(per_worker)
+            .map(|scenes| {
+                scope.spawn(move || {
+                    scenes
+                        .iter()
+                        .map(|scene| probe_scene(source, scene, frame_rate))
+                        .collect::<anyhow::Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("geometry thread should not panic"))
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?
+    .concat();
+    let Some(reference) = reference_geometry(scenes, &geometries) else {
+        return Ok(());
+    };
+
+    let changed: Vec<_> = scenes
+        .iter_mut()
+        .zip(&geometries)
+        .filter(|(_, geometry)| geometry.differs_from(&reference))
+        .collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    if args.geometry_changes == GeometryChanges::Error {
+        let mut report = String::new();
+        for (scene, geometry) in &changed {
+            writeln!(
+                report,
+                "  frames {}-{}: {geometry}",
+                scene.start_frame, scene.end_frame
+            )?;
+        }
+        bail!(
+            "{} scenes differ from the rest of the video ({reference}):\n{report}Use \
+             --geometry-changes fix to scale and pad them, or split the input",
+            changed.len()
+        );
+    }
+
+    if let Some((scene, _)) = changed.iter().find(|(scene, _)| scene.is_passthrough()) {
+        bail!(
+            "Passthrough zone at frames {}-{} differs from the rest of the video ({reference}) \
+             and cannot be scaled and padded",
+            scene.start_frame,
+            scene.end_frame
+        );
+    }
+
+    for (scene, geometry) in changed {
+        let filter = geometry.fix_filter(&reference);
+        warn!(
+            "frames {}-{} are {geometry}, scaling and padding them to {reference}",
+            scene.start_frame, scene.end_frame
+        );
+        let overrides = scene.zone_overrides.get_or_insert_with(|| ZoneOptions::from_args(args));
+        overrides.ffmpeg_filter = Some(match overrides.ffmpeg_filter.take() {
+            Some(zone_filter) => format!("{filter},{zone_filter}"),
+            None => filter,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LETTERBOXED: Geometry = Geometry {
+        width:  1920,
+        height: 1080,
+        active: (1920, 800, 0, 140),
+    };
+
+    #[test]
+    fn parse_showinfo_and_cropdetect() {
+        let stderr = "[Parsed_showinfo_0 @ 0x55d1] n:   0 pts:      0 pts_time:0 duration:1 \
+                      fmt:yuv420p10le cl:left sar:1/1 s:1920x1080 i:P iskey:1 \
+                      type:I\n[Parsed_cropdetect_1 @ 0x55d2] x1:0 x2:1919 y1:140 y2:939 w:1920 \
+                      h:800 x:0 y:140 pts:0 t:0.000000 limit:0.094118 crop=1920:800:0:140\n";
+        assert_eq!(Geometry::parse(stderr), Some(LETTERBOXED));
+        assert_eq!(
+            Geometry::parse("Invalid data found when processing input"),
+            None
+        );
+    }
+
+    #[test]
+    fn only_picture_outside_the_reference_is_a_change() {
+        // dark scenes have a smaller active area
+        let dark = Geometry {
+            active: (1600, 600, 160, 240),
+            ..LETTERBOXED
+        };
+        assert!(!dark.differs_from(&LETTERBOXED));
+
+        let full_frame = Geometry {
+            active: (1920, 1080, 0, 0),
+            ..LETTERBOXED
+        };
+        assert!(full_frame.differs_from(&LETTERBOXED));
+        assert_eq!(
+            full_frame.fix_filter(&LETTERBOXED),
+            "crop=1920:1080:0:0,scale=w=1920:h=800:force_original_aspect_ratio=decrease:\
+             force_divisible_by=2,pad=1920:1080:0+(1920-iw)/2:140+(800-ih)/2"
+        );
+
+        let sd = Geometry {
+            width:  720,
+            height: 576,
+            active: (720, 576, 0, 0),
+        };
+        assert!(sd.differs_from(&LETTERBOXED));
+    }
+}
@@ -0,0 +1,120 @@
+//! Splits the scenes of an encode between two encoders of the same format by
+//! their complexity, e.g. a fast preset for the easy scenes
+
+use std::collections::BTreeMap;
+
+use av_scenechange::ScenecutResult;
+use tracing::{debug, warn};
+
+use crate::{
+    scenes::{Scene, ZoneOptions},
+    EncodeArgs,
+};
+
+/// Average inter-frame cost scene detection measured for the frames of
+/// `scene` after its first, or `None` if none of them were scored. Static and
+/// slow scenes cost little, busy scenes with a lot of motion much more.
+fn scene_complexity(scene: &Scene, scores: &BTreeMap<usize, ScenecutResult>) -> Option<f64> {
+    let costs = scores
+        .range(scene.start_frame + 1..scene.end_frame)
+        .map(|(_, score)| score.inter_cost)
+        .collect::<Vec<_>>();
+    (!costs.is_empty()).then(|| costs.iter().sum::<f64>() / costs.len() as f64)
+}
+
+/// Encodes the least complex scenes with [`EncodeArgs::hybrid_encoder`],
+/// until they make up [`EncodeArgs::hybrid_share`] of the frames of `scenes`.
+/// Scenes of zones, and scenes without scores, keep their encoder.
+pub(crate) fn apply_hybrid_split(
+    args: &EncodeArgs,
+    mut scenes: Vec<Scene>,
+    scores: &BTreeMap<usize, ScenecutResult>,
+) -> Vec<Scene> {
+    let Some(encoder) = args.hybrid_encoder else {
+        return scenes;
+    };
+
+    let total = scenes.iter().map(|scene| scene.end_frame - scene.start_frame).sum::<usize>();
+    let target = (total as f64 * args.hybrid_share).round() as usize;
+    let mut candidates = scenes
+        .iter()
+        .enumerate()
+        .filter(|(_, scene)| scene.zone_overrides.is_none())
+        .filter_map(|(index, scene)| Some((index, scene_complexity(scene, scores)?)))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        if !scenes.is_empty() {
+            warn!(
+                "no scene complexity is known, so every scene is encoded with {}",
+                args.encoder
+            );
+        }
+        return scenes;
+    }
+    candidates.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    let mut overrides = ZoneOptions::from_args(args);
+    overrides.encoder = encoder;
+    overrides.video_params.clone_from(&args.hybrid_params);
+    if encoder != args.encoder {
+        overrides.passes = encoder.get_default_pass();
+        // probe the scenes with the encoder that encodes them, on its own
+        // quantizer scale
+        if let Some(target_quality) = &mut overrides.target_quality {
+            let (min_q, max_q) = encoder.get_default_cq_range();
+            target_quality.encoder = encoder;
+            target_quality.min_q = min_q as u32;
+            target_quality.max_q = max_q as u32;
+            if target_quality.video_params.is_some() {
+                target_quality.video_params = Some(args.hybrid_params.clone());
+            }
+        }
+    }
+
+    let mut assigned = 0;
+    for (index, _) in candidates {
+        if assigned >= target {
+            break;
+        }
+        let scene = &mut scenes[index];
+        assigned += scene.end_frame - scene.start_frame;
+        scene.zone_overrides = Some(overrides.clone());
+    }
+    debug!("encoding {assigned} of {total} frames with {encoder}");
+    scenes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complexity_skips_the_scene_cut() {
+        let scenes = [0..100, 100..200, 200..300, 300..400]
+            .into_iter()
+            .map(|range| Scene {
+                start_frame:    range.start,
+                end_frame:      range.end,
+                zone_overrides: None,
+            })
+            .collect::<Vec<_>>();
+        let score = |inter_cost| ScenecutResult {
+            inter_cost,
+            imp_block_cost: 0.0,
+            threshold: 1.0,
+            backward_adjusted_cost: 0.0,
+            forward_adjusted_cost: 0.0,
+        };
+        // the third scene has no scores, and the cut at frame 100 is ignored
+        let scores = BTreeMap::from([
+            (50, score(30.0)),
+            (100, score(1000.0)),
+            (150, score(5.0)),
+            (350, score(10.0)),
+        ]);
+
+        let complexity =
+            scenes.iter().map(|scene| scene_complexity(scene, &scores)).collect::<Vec<_>>();
+        assert_eq!(complexity, [Some(30.0), Some(5.0), None, Some(10.0)]);
+    }
+}
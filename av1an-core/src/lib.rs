@@ -1,23 +1,22 @@
 use std::{
-    cmp::max,
     collections::{hash_map::DefaultHasher, HashMap},
     fs::{self, read_to_string, File},
     hash::{Hash, Hasher},
     io::Write,
     path::{Path, PathBuf},
+    str::FromStr,
     string::ToString,
     sync::{
         atomic::{AtomicBool, AtomicUsize},
         Mutex,
     },
     thread::available_parallelism,
-    time::Instant,
+    time::{Instant, SystemTime},
 };
 
 use anyhow::{bail, Context};
 use av1_grain::TransferFunction;
 use av_format::rational::Rational64;
-use chunk::Chunk;
 use dashmap::DashMap;
 use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
@@ -25,12 +24,32 @@ use strum::{Display, EnumString, IntoStaticStr};
 use tracing::info;
 
 pub use crate::{
-    concat::ConcatMethod,
-    context::Av1anContext,
+    chunk::{write_photon_noise_table, Chunk, ChunkNames, NoiseTransfer},
+    concat::{ChunkCheck, ConcatMethod},
+    context::{Av1anContext, COMMAND_LOG_TARGET},
+    dimensions::OddDimensions,
     encoder::Encoder,
-    settings::{EncodeArgs, InputPixelFormat, PixelFormat, PixelFormatConverter},
-    target_quality::{InterpolationMethod, TargetQuality},
-    util::read_in_dir,
+    error::{Av1anError, ReportedError, UNCLASSIFIED_CODE},
+    geometry::GeometryChanges,
+    metrics::{
+        custom::{register_metric, CustomMetric, MetricFrame, MetricFrames, QualityMetric},
+        vmaf::VmafTonemap,
+    },
+    parse::EncoderSummary,
+    progress_bar::set_progress_interval,
+    scenes::{Scene, ZoneOptions},
+    scheduler::{ChunkScheduler, QueueScheduler},
+    settings::{
+        DebandStrength,
+        EncodeArgs,
+        EncoderTier,
+        GlobalConfig,
+        InputPixelFormat,
+        PixelFormat,
+        PixelFormatConverter,
+    },
+    target_quality::{InterpolationMethod, ProbeResCorrection, TargetQuality},
+    util::{parse_duration, parse_trim_point, read_in_dir, seconds_to_frames, TrimPoint},
 };
 use crate::{
     ffmpeg::FFPixelFormat,
@@ -38,33 +57,86 @@ use crate::{
     vapoursynth::{create_vs_file, generate_loadscript_text, CacheSource, LoadscriptArgs},
 };
 
+pub mod bench;
 mod broker;
+pub mod capabilities;
 mod chunk;
+pub mod compare;
 mod concat;
 mod context;
+mod control;
+pub mod debug_bundle;
+mod dimensions;
 mod encoder;
+mod error;
 pub mod ffmpeg;
+mod geometry;
+mod hybrid;
+mod matroska;
 mod metrics {
     pub mod butteraugli;
+    pub mod custom;
+    pub mod reference;
+    pub mod ssimulacra2;
     pub mod statistics;
     pub mod vmaf;
     pub mod xpsnr;
 }
 mod interpol;
+mod output_log;
 mod parse;
+mod pipe;
 mod progress_bar;
+pub mod remote;
+pub mod report;
 mod scene_detect;
+mod scene_preview;
 mod scenes;
+mod scheduler;
+mod schema;
 mod settings;
+mod shared_temp;
 mod split;
+mod static_scenes;
 mod target_quality;
+pub mod temp_check;
+pub mod temp_registry;
+mod two_stage;
 mod util;
 pub mod vapoursynth;
+mod vs_params;
+mod vs_server;
 mod zones;
 
-static CLIP_INFO_CACHE: Lazy<Mutex<HashMap<CacheKey, ClipInfo>>> =
+static CLIP_INFO_CACHE: Lazy<Mutex<HashMap<CacheKey, CachedClipInfo>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+#[derive(Debug, Clone, Copy)]
+struct CachedClipInfo {
+    info:  ClipInfo,
+    /// The state of the input file when `info` was cached, a cached entry is
+    /// only used while the file is unchanged
+    stamp: Option<FileStamp>,
+}
+
+/// The size and modification time of a file, used to detect that an input
+/// was replaced after its clip info was cached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len:      u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            len:      metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 struct CacheKey {
     input:    Input,
@@ -109,6 +181,11 @@ impl Input {
             if ext == "py" || ext == "vpy" {
                 let input_path = path.into();
                 let script_text = read_to_string(input_path.clone())?;
+                let mut vspipe_args = vs_params::resolve_vspipe_args(&script_text, vspipe_args)
+                    .with_context(|| {
+                        format!("Invalid --vspipe-args for {}", input_path.display())
+                    })?;
+                vspipe_args.push(vs_params::script_vspipe_arg(&input_path)?);
                 Ok::<Self, anyhow::Error>(Self::VapourSynth {
                     path: input_path,
                     vspipe_args,
@@ -318,18 +395,23 @@ impl Input {
         }
     }
 
+    /// Returns the clip info of the input, probing it with FFmpeg or
+    /// VapourSynth.
+    ///
+    /// The result is cached for the lifetime of the process, until the input
+    /// file changes size or modification time, or
+    /// [`Input::invalidate_clip_info`] is called.
     #[inline]
     pub fn clip_info(&self) -> anyhow::Result<ClipInfo> {
         const FAIL_MSG: &str = "Failed to get number of frames for input video";
 
         let mut cache = CLIP_INFO_CACHE.lock().expect("mutex should acquire lock");
-        let key = CacheKey {
-            input:    self.clone(),
-            is_proxy: self.is_proxy(),
-        };
-        let cached = cache.get(&key);
-        if let Some(cached) = cached {
-            return Ok(*cached);
+        let key = self.clip_info_key();
+        let stamp = FileStamp::of(self.as_path());
+        if let Some(cached) = cache.get(&key)
+            && cached.stamp == stamp
+        {
+            return Ok(cached.info);
         }
 
         let info = match &self {
@@ -342,25 +424,62 @@ impl Input {
                 vapoursynth::get_clip_info(path, &self.as_vspipe_args_map()?).context(FAIL_MSG)?
             },
         };
-        cache.insert(key, info);
+        cache.insert(key, CachedClipInfo {
+            info,
+            stamp,
+        });
         Ok(info)
     }
 
-    /// Calculates tiles from resolution
-    /// Don't convert tiles to encoder specific representation
-    /// Default video without tiling is 1,1
-    /// Return number of horizontal and vertical tiles
+    /// Supply the clip info of the input when it is already known, so that
+    /// [`Input::clip_info`] does not need to probe it. Probing can take
+    /// several seconds, e.g. on network storage.
+    ///
+    /// Call this before creating an [`Av1anContext`]. The info is discarded
+    /// once the input file changes, like a probed one.
     #[inline]
-    pub fn calculate_tiles(&self) -> (u32, u32) {
-        match self.clip_info().map(|info| info.resolution) {
-            Ok((h, v)) => {
-                // tile range 0-1440 pixels
-                let horizontal = max((h - 1) / 720, 1);
-                let vertical = max((v - 1) / 720, 1);
-
-                (horizontal, vertical)
-            },
-            _ => (1, 1),
+    pub fn provide_clip_info(&self, info: ClipInfo) {
+        let mut cache = CLIP_INFO_CACHE.lock().expect("mutex should acquire lock");
+        cache.insert(self.clip_info_key(), CachedClipInfo {
+            info,
+            stamp: FileStamp::of(self.as_path()),
+        });
+    }
+
+    /// The clip info of the input with its frame rate replaced by
+    /// `fps_override`, if any, for sources whose container reports a wrong
+    /// one. The frame rate is kept for the later calls to
+    /// [`Input::clip_info`], until the input file changes.
+    #[inline]
+    pub fn clip_info_overriding_fps(
+        &self,
+        fps_override: Option<Rational64>,
+    ) -> anyhow::Result<ClipInfo> {
+        let info = self.clip_info()?;
+        let Some(frame_rate) = fps_override.filter(|&frame_rate| frame_rate != info.frame_rate)
+        else {
+            return Ok(info);
+        };
+        let info = ClipInfo {
+            frame_rate,
+            ..info
+        };
+        self.provide_clip_info(info);
+        Ok(info)
+    }
+
+    /// Discard the cached clip info of the input, so that the next call to
+    /// [`Input::clip_info`] probes it again
+    #[inline]
+    pub fn invalidate_clip_info(&self) {
+        let mut cache = CLIP_INFO_CACHE.lock().expect("mutex should acquire lock");
+        cache.remove(&self.clip_info_key());
+    }
+
+    fn clip_info_key(&self) -> CacheKey {
+        CacheKey {
+            input:    self.clone(),
+            is_proxy: self.is_proxy(),
         }
     }
 
@@ -389,8 +508,8 @@ impl Input {
         );
 
         for arg in self.as_vspipe_args_vec()? {
-            let split: Vec<&str> = arg.split_terminator('=').collect();
-            if args_map.set_data(split[0], split[1].as_bytes()).is_err() {
+            let (key, value) = arg.split_once('=').unwrap_or((&arg, ""));
+            if args_map.set_data(key, value.as_bytes()).is_err() {
                 bail!("Failed to split vspipe arguments");
             };
         }
@@ -402,8 +521,8 @@ impl Input {
     pub fn as_vspipe_args_hashmap(&self) -> anyhow::Result<HashMap<String, String>> {
         let mut args_map = HashMap::new();
         for arg in self.as_vspipe_args_vec()? {
-            let split: Vec<&str> = arg.split_terminator('=').collect();
-            args_map.insert(split[0].to_string(), split[1].to_string());
+            let (key, value) = arg.split_once('=').unwrap_or((&arg, ""));
+            args_map.insert(key.to_string(), value.to_string());
         }
         Ok(args_map)
     }
@@ -413,12 +532,30 @@ impl Input {
 struct DoneChunk {
     frames:     usize,
     size_bytes: u64,
+    /// Statistics printed by the encoder, empty for chunks that were not
+    /// encoded, or whose encoder output could not be parsed
+    #[serde(default)]
+    summary:    EncoderSummary,
+    /// Only measured with [`EncodeArgs::min_chunk_score`]
+    #[serde(default)]
+    score:      Option<ChunkScore>,
+}
+
+/// Score of a finished chunk, for [`EncodeArgs::min_chunk_score`]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+struct ChunkScore {
+    /// Score with the metric of Target Quality, lower is better for
+    /// Butteraugli
+    score:     f64,
+    /// Quantizer the chunk was encoded at
+    quantizer: f32,
 }
 
 /// Concurrent data structure for keeping track of the finished chunks in an
 /// encode
 #[derive(Debug, Deserialize, Serialize)]
 struct DoneJson {
+    version:    u64,
     frames:     AtomicUsize,
     done:       DashMap<String, DoneChunk>,
     audio_done: AtomicBool,
@@ -437,6 +574,28 @@ fn init_done(done: DoneJson) -> &'static DoneJson {
     DONE_JSON.get_or_init(|| done)
 }
 
+/// Writes done.json to `temp`. With a `shared` temporary directory, the
+/// chunks that other instances finished are merged in first.
+fn save_done(temp: &Path, shared: bool) -> anyhow::Result<()> {
+    if shared {
+        return shared_temp::write_done(temp);
+    }
+    let mut progress_file = File::create(temp.join("done.json"))?;
+    progress_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+    Ok(())
+}
+
+/// Directory for the first pass statistics, Target Quality probes and metric
+/// logs of `worker_id`, so that workers never share a directory. Work that
+/// does not run on a worker uses `temp/split`.
+fn scratch_dir(temp: impl AsRef<Path>, worker_id: Option<usize>) -> PathBuf {
+    let temp = temp.as_ref();
+    worker_id.map_or_else(
+        || temp.join("split"),
+        |worker_id| temp.join("workers").join(format!("{worker_id:02}")),
+    )
+}
+
 #[inline]
 pub fn list_index(params: &[impl AsRef<str>], is_match: fn(&str) -> bool) -> Option<usize> {
     assert!(!params.is_empty(), "received empty list of parameters");
@@ -461,6 +620,51 @@ pub enum ScenecutMethod {
     Fast,
     #[strum(serialize = "standard")]
     Standard,
+    /// Cut where x264 places keyframes in a fast first pass, for scenes that
+    /// follow the keyframe decisions of an encoder
+    #[strum(serialize = "first-pass")]
+    FirstPass,
+}
+
+/// Where scene detection places the cut of a gradual transition, such as a
+/// crossfade or dissolve, whose frames all differ a little from the previous
+/// one instead of a single frame differing a lot
+#[derive(
+    PartialEq, Eq, Serialize, Deserialize, Debug, Clone, Copy, EnumString, IntoStaticStr, Display,
+)]
+pub enum TransitionCut {
+    /// Wherever scene detection found the largest change
+    #[strum(serialize = "detected")]
+    Detected,
+    /// In the middle of the transition
+    #[strum(serialize = "middle")]
+    Middle,
+    /// On the last frame of the transition, which starts the new shot
+    #[strum(serialize = "end")]
+    End,
+    /// Nowhere, leaving the transition within one scene
+    #[strum(serialize = "none")]
+    NoCut,
+}
+
+/// The decoder that reads the frames for scene detection
+#[derive(
+    PartialEq, Eq, Serialize, Deserialize, Debug, Clone, Copy, EnumString, IntoStaticStr, Display,
+)]
+pub enum ScDecoder {
+    /// VapourSynth for VapourSynth scripts and chunk methods, FFmpeg otherwise
+    #[strum(serialize = "auto")]
+    Auto,
+    /// VapourSynth, indexing a video input with the best installed source
+    /// plugin if the chunk method does not use VapourSynth
+    #[strum(serialize = "vapoursynth")]
+    VapourSynth,
+    /// FFmpeg, reading the video input directly
+    #[strum(serialize = "ffmpeg")]
+    FFmpeg,
+    /// vspipe, piping the output of the VapourSynth script through FFmpeg
+    #[strum(serialize = "y4m-pipe")]
+    Y4mPipe,
 }
 
 #[derive(
@@ -483,6 +687,8 @@ pub enum ChunkMethod {
     Hybrid,
     #[strum(serialize = "segment")]
     Segment,
+    #[strum(serialize = "trim")]
+    Trim,
     #[strum(serialize = "ffms2")]
     FFMS2,
     #[strum(serialize = "lsmash")]
@@ -533,22 +739,144 @@ pub enum VmafFeature {
     Uhd,
 }
 
-#[derive(
-    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
-)]
+/// Metrics the output is scored with against the source after it is
+/// concatenated
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum OutputMetric {
+    VMAF,
+    SSIMULACRA2,
+    /// Minimum XPSNR of the three planes
+    XPSNR,
+    /// A metric registered with [`register_metric`], parsed from its name
+    Custom(CustomMetric),
+}
+
+impl OutputMetric {
+    /// The built-in metrics and their names
+    const BUILT_IN: [(Self, &'static str); 3] =
+        [(Self::VMAF, "vmaf"), (Self::SSIMULACRA2, "ssimulacra2"), (Self::XPSNR, "xpsnr")];
+}
+
+impl From<OutputMetric> for &'static str {
+    #[inline]
+    fn from(metric: OutputMetric) -> Self {
+        match metric {
+            OutputMetric::Custom(metric) => metric.name(),
+            metric => {
+                OutputMetric::BUILT_IN
+                    .iter()
+                    .find(|(built_in, _)| *built_in == metric)
+                    .expect("every other metric is built in")
+                    .1
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for OutputMetric {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(*self))
+    }
+}
+
+impl FromStr for OutputMetric {
+    type Err = String;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::BUILT_IN
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(metric, _)| *metric)
+            .or_else(|| CustomMetric::lookup(s).map(Self::Custom))
+            .ok_or_else(|| format!("unknown metric `{s}`"))
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug)]
 pub enum TargetMetric {
-    #[strum(serialize = "vmaf")]
     VMAF,
-    #[strum(serialize = "ssimulacra2")]
     SSIMULACRA2,
-    #[strum(serialize = "butteraugli-inf")]
     ButteraugliINF,
-    #[strum(serialize = "butteraugli-3")]
     Butteraugli3,
-    #[strum(serialize = "xpsnr")]
     XPSNR,
-    #[strum(serialize = "xpsnr-weighted")]
     XPSNRWeighted,
+    /// A metric registered with [`register_metric`], parsed from its name
+    Custom(CustomMetric),
+}
+
+impl TargetMetric {
+    /// The built-in metrics and their names
+    pub(crate) const BUILT_IN: [(Self, &'static str); 6] = [
+        (Self::VMAF, "vmaf"),
+        (Self::SSIMULACRA2, "ssimulacra2"),
+        (Self::ButteraugliINF, "butteraugli-inf"),
+        (Self::Butteraugli3, "butteraugli-3"),
+        (Self::XPSNR, "xpsnr"),
+        (Self::XPSNRWeighted, "xpsnr-weighted"),
+    ];
+
+    /// Returns whether lower scores mean better quality, as with Butteraugli
+    #[inline]
+    #[must_use]
+    pub fn lower_is_better(self) -> bool {
+        match self {
+            Self::ButteraugliINF | Self::Butteraugli3 => true,
+            Self::Custom(metric) => !metric.metric().higher_is_better(),
+            _ => false,
+        }
+    }
+
+    /// Lowest and highest score of the metric. SSIMULACRA2 scores fall below
+    /// 0 for heavily distorted frames, unlike VMAF scores.
+    pub(crate) fn score_range(self) -> (f64, f64) {
+        match self {
+            Self::VMAF => (0.0, 100.0),
+            Self::SSIMULACRA2 => (f64::NEG_INFINITY, 100.0),
+            Self::ButteraugliINF | Self::Butteraugli3 | Self::XPSNR | Self::XPSNRWeighted => {
+                (0.0, f64::INFINITY)
+            },
+            Self::Custom(_) => (f64::NEG_INFINITY, f64::INFINITY),
+        }
+    }
+}
+
+impl From<TargetMetric> for &'static str {
+    #[inline]
+    fn from(metric: TargetMetric) -> Self {
+        match metric {
+            TargetMetric::Custom(metric) => metric.name(),
+            metric => {
+                TargetMetric::BUILT_IN
+                    .iter()
+                    .find(|(built_in, _)| *built_in == metric)
+                    .expect("every other metric is built in")
+                    .1
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for TargetMetric {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(*self))
+    }
+}
+
+impl FromStr for TargetMetric {
+    type Err = String;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::BUILT_IN
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(metric, _)| *metric)
+            .or_else(|| CustomMetric::lookup(s).map(Self::Custom))
+            .ok_or_else(|| format!("unknown metric `{s}`"))
+    }
 }
 
 /// Determine the optimal number of workers for an encoder
@@ -562,7 +890,7 @@ pub fn determine_workers(args: &EncodeArgs) -> anyhow::Result<u64> {
     let cm_ram = match args.chunk_method {
         ChunkMethod::FFMS2 | ChunkMethod::LSMASH | ChunkMethod::BESTSOURCE => 0.3,
         ChunkMethod::DGDECNV => 0.3,
-        ChunkMethod::Hybrid | ChunkMethod::Select | ChunkMethod::Segment => 0.1,
+        ChunkMethod::Hybrid | ChunkMethod::Select | ChunkMethod::Segment | ChunkMethod::Trim => 0.1,
     };
     let enc_ram = match args.encoder {
         Encoder::aom => 0.4,
@@ -571,6 +899,8 @@ pub fn determine_workers(args: &EncodeArgs) -> anyhow::Result<u64> {
         Encoder::vpx => 0.3,
         Encoder::x264 => 0.7,
         Encoder::x265 => 0.6,
+        // the frames are encoded in the memory of the GPU
+        Encoder::av1_nvenc | Encoder::hevc_nvenc => 0.2,
     };
     // This is a rough estimate of how many cpu cores will be fully loaded by an
     // encoder worker. With rav1e, CPU usage scales with tiles, but not 1:1.
@@ -583,6 +913,8 @@ pub fn determine_workers(args: &EncodeArgs) -> anyhow::Result<u64> {
         Encoder::svt_av1 => 6,
         Encoder::vpx => 3,
         Encoder::x264 | Encoder::x265 => 8,
+        // decoding and piping the frames to FFmpeg
+        Encoder::av1_nvenc | Encoder::hevc_nvenc => 2,
     };
     // memory usage scales with pixel format, expressed as a multiplier of memory
     // usage. Roughly the same behavior was observed accross all encoders.
@@ -601,13 +933,21 @@ pub fn determine_workers(args: &EncodeArgs) -> anyhow::Result<u64> {
     // use total instead of available, because av1an does not resize worker pool
     let ram_gb = system.total_memory() as f64 / 1e9;
 
-    Ok(std::cmp::max(
+    let workers = std::cmp::max(
         std::cmp::min(
             cpu / cpu_threads,
             (ram_gb / (megapixels * (enc_ram + cm_ram) * pix_mult)).round() as u64,
         ),
         1,
-    ))
+    );
+    if args.encoder.is_hardware() {
+        // a few encodes at once keep the NVENC engines of a GPU busy, and
+        // older drivers of consumer GPUs refuse to open any more
+        let gpus = args.gpus.len().max(1) as u64;
+        return Ok(workers.min(4 * gpus));
+    }
+
+    Ok(workers)
 }
 
 #[inline]
@@ -618,13 +958,25 @@ pub fn hash_path(path: &Path) -> String {
     format!("{:x}", s.finish())[..7].to_string()
 }
 
+/// The contents of chunks.json
+#[derive(Serialize, Deserialize)]
+struct ChunkQueue<C> {
+    version: u64,
+    chunks:  C,
+}
+
 fn save_chunk_queue(temp: &str, chunk_queue: &[Chunk]) -> anyhow::Result<()> {
     let mut file = File::create(Path::new(temp).join("chunks.json"))
         .with_context(|| "Failed to create chunks.json file")?;
 
+    let queue = ChunkQueue {
+        version: schema::CHUNKS.version(),
+        chunks:  chunk_queue,
+    };
+
     file
     // serializing chunk_queue as json should never fail, so unwrap is OK here
-    .write_all(serde_json::to_string(&chunk_queue)?.as_bytes())
+    .write_all(serde_json::to_string(&queue)?.as_bytes())
     .with_context(|| format!("Failed to write serialized chunk_queue data to {:?}", &file))?;
 
     Ok(())
@@ -643,7 +995,9 @@ fn read_chunk_queue(temp: &Path) -> anyhow::Result<Vec<Chunk>> {
     let contents = fs::read_to_string(&file)
         .with_context(|| format!("Failed to read chunk queue file {}", file.display()))?;
 
-    Ok(serde_json::from_str(&contents)?)
+    let queue: ChunkQueue<Vec<Chunk>> = schema::CHUNKS.load(&contents)?;
+
+    Ok(queue.chunks)
 }
 
 #[derive(Serialize, Deserialize, Debug, EnumString, IntoStaticStr, Display, Clone)]
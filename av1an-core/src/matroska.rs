@@ -0,0 +1,225 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+};
+
+use anyhow::{bail, ensure, Context};
+use itertools::Itertools;
+use regex::Regex;
+use tracing::debug;
+
+/// Segment linking and ordered chapters of a Matroska file. Players use them
+/// to build a timeline from parts of this file and of other files, while
+/// Av1an only encodes the frames stored in this file.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SegmentLinks {
+    segment_uid:      Option<String>,
+    previous:         Option<String>,
+    next:             Option<String>,
+    has_chapters:     bool,
+    ordered_chapters: bool,
+    /// UIDs of the segments the chapters play from, other than this one
+    chapter_segments: Vec<String>,
+}
+
+impl SegmentLinks {
+    /// UIDs of the other segments the timeline pulls content from
+    fn linked_segments(&self) -> Vec<&str> {
+        self.previous
+            .iter()
+            .chain(&self.next)
+            .chain(&self.chapter_segments)
+            .map(String::as_str)
+            .filter(|uid| Some(*uid) != self.segment_uid.as_deref())
+            .unique()
+            .collect()
+    }
+}
+
+/// Segment UIDs are printed as hex, either as one number or as one `0x` byte
+/// after another
+fn normalize_uid(uid: &str) -> String {
+    uid.split_whitespace()
+        .map(|byte| byte.trim_start_matches("0x"))
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Parses the output of `mkvmerge -J`
+fn parse_identification(json: &str) -> anyhow::Result<SegmentLinks> {
+    let json: serde_json::Value =
+        serde_json::from_str(json).context("Failed to parse the output of mkvmerge -J")?;
+    let properties = &json["container"]["properties"];
+    let uid = |key: &str| properties[key].as_str().map(normalize_uid);
+    Ok(SegmentLinks {
+        segment_uid: uid("segment_uid"),
+        previous: uid("previous_segment_uid"),
+        next: uid("next_segment_uid"),
+        has_chapters: json["chapters"].as_array().is_some_and(|chapters| !chapters.is_empty()),
+        ..SegmentLinks::default()
+    })
+}
+
+/// Parses the chapters written by `mkvextract chapters`
+fn parse_chapters(xml: &str, links: &mut SegmentLinks) {
+    let ordered = Regex::new(r"<EditionFlagOrdered>\s*1\s*</EditionFlagOrdered>")
+        .expect("regex should be valid");
+    let segment = Regex::new(r"<ChapterSegmentUID[^>]*>([^<]*)</ChapterSegmentUID>")
+        .expect("regex should be valid");
+    links.ordered_chapters = ordered.is_match(xml);
+    links.chapter_segments = segment
+        .captures_iter(xml)
+        .map(|captures| normalize_uid(&captures[1]))
+        .filter(|uid| !uid.is_empty())
+        .collect();
+}
+
+fn identify(path: &Path) -> anyhow::Result<SegmentLinks> {
+    let output = Command::new("mkvmerge")
+        .arg("-J")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run mkvmerge")?;
+    ensure!(
+        output.status.success(),
+        "mkvmerge could not identify {}: {}",
+        path.display(),
+        String::from_utf8_lossy(&output.stdout).trim()
+    );
+    parse_identification(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn read_chapters(path: &Path, links: &mut SegmentLinks) -> anyhow::Result<()> {
+    let xml_path = std::env::temp_dir().join(format!("av1an-chapters-{}.xml", process::id()));
+    let status = Command::new("mkvextract")
+        .arg(path)
+        .arg("chapters")
+        .arg(&xml_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .context("Failed to run mkvextract")?;
+    let xml = fs::read_to_string(&xml_path);
+    fs::remove_file(&xml_path).ok();
+    ensure!(
+        status.success(),
+        "mkvextract could not extract the chapters of {}",
+        path.display()
+    );
+    parse_chapters(&xml?, links);
+    Ok(())
+}
+
+/// Returns the Matroska files next to `path` whose segment UID is one of
+/// `uids`
+fn find_segments(path: &Path, uids: &[&str]) -> Vec<(String, PathBuf)> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|file| file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mkv")))
+        .filter(|file| file.as_path() != path)
+        .filter_map(|file| {
+            let uid = identify(&file).ok()?.segment_uid?;
+            uids.contains(&uid.as_str()).then_some((uid, file))
+        })
+        .collect()
+}
+
+/// Fail if the timeline of the Matroska file at `path` uses ordered chapters
+/// or links to other segments, as the output would then miss the content the
+/// timeline pulls from elsewhere, or contain content it skips
+pub(crate) fn check_linked_segments(path: &Path) -> anyhow::Result<()> {
+    let mut links = identify(path)?;
+    if links.has_chapters {
+        read_chapters(path, &mut links)?;
+    }
+    debug!("segment links of {}: {links:?}", path.display());
+
+    let linked = links.linked_segments();
+    if linked.is_empty() && !links.ordered_chapters {
+        return Ok(());
+    }
+    let found = find_segments(path, &linked);
+    let segments = linked
+        .iter()
+        .map(|uid| {
+            found.iter().find(|(found, _)| found == uid).map_or_else(
+                || format!("\n  {uid} (not found next to the input)"),
+                |(_, file)| format!("\n  {uid} ({})", file.display()),
+            )
+        })
+        .collect::<String>();
+
+    if linked.is_empty() {
+        bail!(
+            "{} uses ordered chapters, so players skip or reorder parts of it, but Av1an encodes \
+             every frame in the order it is stored",
+            path.display()
+        );
+    }
+    bail!(
+        "{} uses {}, so players pull content from other files, which Av1an does not encode. \
+         Linked segments:{segments}",
+        path.display(),
+        if links.ordered_chapters {
+            "ordered chapters"
+        } else {
+            "segment linking"
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_segment_links() {
+        let json = r#"{
+            "chapters": [{"num_entries": 3}],
+            "container": {
+                "properties": {
+                    "segment_uid": "0a1b2c3d4e5f60718293a4b5c6d7e8f9",
+                    "next_segment_uid": "ffeeddccbbaa99887766554433221100"
+                },
+                "recognized": true
+            }
+        }"#;
+        let mut links = parse_identification(json).expect("should parse mkvmerge output");
+        assert!(links.has_chapters);
+        assert_eq!(links.linked_segments(), [
+            "ffeeddccbbaa99887766554433221100"
+        ]);
+
+        let xml = r#"<Chapters>
+  <EditionEntry>
+    <EditionFlagOrdered>1</EditionFlagOrdered>
+    <ChapterAtom>
+      <ChapterSegmentUID format="hex">0x0A 0x1B 0x2C 0x3D 0x4E 0x5F 0x60 0x71 0x82 0x93 0xA4 0xB5 0xC6 0xD7 0xE8 0xF9</ChapterSegmentUID>
+    </ChapterAtom>
+    <ChapterAtom>
+      <ChapterSegmentUID format="hex">0x01 0x02</ChapterSegmentUID>
+    </ChapterAtom>
+  </EditionEntry>
+</Chapters>"#;
+        parse_chapters(xml, &mut links);
+        assert!(links.ordered_chapters);
+        // chapters playing from this segment itself are not links
+        assert_eq!(links.linked_segments(), [
+            "ffeeddccbbaa99887766554433221100",
+            "0102"
+        ]);
+
+        let json = r#"{"chapters": [], "container": {"properties": {}}}"#;
+        let links = parse_identification(json).expect("should parse mkvmerge output");
+        assert_eq!(links, SegmentLinks::default());
+    }
+}
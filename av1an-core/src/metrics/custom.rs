@@ -0,0 +1,443 @@
+//! Quality metrics that library users plug into Target Quality
+//!
+//! A metric implementing [`QualityMetric`] is registered under a name with
+//! [`register_metric`], after which the name can be used as a
+//! [`TargetMetric`](crate::TargetMetric) like the built-in metrics, in the
+//! encode arguments as well as in zones. It scores the probes of Target
+//! Quality and, with `--min-chunk-score`, the finished chunks. The name is
+//! also an [`OutputMetric`](crate::OutputMetric), to score the output after
+//! it is concatenated.
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt::{self, Display},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use once_cell::sync::Lazy;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use tracing::info;
+
+use crate::{
+    ffmpeg::FFPixelFormat,
+    metrics::{reference::ReferencePipe, statistics::FrameScores, vmaf::plot_scores},
+    Input,
+    TargetMetric,
+};
+
+/// A frame decoded for a [`QualityMetric`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricFrame {
+    pub width:     usize,
+    pub height:    usize,
+    /// Bits per sample. Samples of more than 8 bits take two little-endian
+    /// bytes.
+    pub bit_depth: usize,
+    /// The Y, U and V planes, in the layout of
+    /// [`QualityMetric::pixel_format`]
+    pub planes:    [Vec<u8>; 3],
+}
+
+/// Frames handed to [`QualityMetric::score`], in display order
+pub type MetricFrames<'a> = dyn Iterator<Item = anyhow::Result<MetricFrame>> + 'a;
+
+/// A quality metric that scores the frames of an encode against the frames
+/// of the source
+///
+/// Both are decoded with FFmpeg to [`Self::pixel_format`] and scaled to the
+/// same resolution, which is the VMAF resolution (`--vmaf-res`) or the probe
+/// resolution (`--probe-res`) while probing. With a probing rate, only the
+/// frames that were encoded as probes are decoded.
+pub trait QualityMetric: Send + Sync {
+    /// Returns one score per frame, scoring every frame of `distorted`
+    /// against the frame of `reference` at the same position. All frames have
+    /// to be read. The scores are aggregated with `--probing-stat`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a frame failed to decode or could not be scored,
+    /// which fails the probe like a crash of a built-in metric.
+    fn score(
+        &self,
+        reference: &mut MetricFrames<'_>,
+        distorted: &mut MetricFrames<'_>,
+    ) -> anyhow::Result<Vec<f64>>;
+
+    /// Whether higher scores mean better quality, as with VMAF, rather than
+    /// lower scores, as with Butteraugli
+    #[inline]
+    fn higher_is_better(&self) -> bool {
+        true
+    }
+
+    /// The pixel format to decode the frames to
+    #[inline]
+    fn pixel_format(&self) -> FFPixelFormat {
+        FFPixelFormat::YUV420P10LE
+    }
+}
+
+static METRICS: Lazy<RwLock<HashMap<&'static str, Arc<dyn QualityMetric>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// A metric registered with [`register_metric`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomMetric(&'static str);
+
+impl CustomMetric {
+    /// Returns the metric registered under `name`, if any
+    #[inline]
+    #[must_use]
+    pub fn lookup(name: &str) -> Option<Self> {
+        let metrics = METRICS.read().expect("lock should not be poisoned");
+        metrics.get_key_value(name).map(|(&name, _)| Self(name))
+    }
+
+    /// The name the metric was registered under
+    #[inline]
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        self.0
+    }
+
+    pub(crate) fn metric(self) -> Arc<dyn QualityMetric> {
+        // metrics are never unregistered, so every handle stays valid
+        Arc::clone(&METRICS.read().expect("lock should not be poisoned")[self.0])
+    }
+}
+
+impl Display for CustomMetric {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl Serialize for CustomMetric {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomMetric {
+    /// Resuming an encode with a custom metric requires the metric to be
+    /// registered again first
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::lookup(&name)
+            .ok_or_else(|| D::Error::custom(format!("the metric `{name}` is not registered")))
+    }
+}
+
+/// Registers `metric` under `name`, so that it can be chosen as the
+/// [`TargetMetric`] with the name, e.g. `--target-metric name` in a zone.
+///
+/// # Errors
+///
+/// Returns an error if `name` is empty, is the name of a built-in metric or
+/// was registered already.
+#[inline]
+pub fn register_metric(
+    name: &str,
+    metric: impl QualityMetric + 'static,
+) -> anyhow::Result<CustomMetric> {
+    ensure!(
+        !name.is_empty() && !name.contains(char::is_whitespace),
+        "the name of a metric must not be empty or contain whitespace"
+    );
+    ensure!(
+        !TargetMetric::BUILT_IN.iter().any(|(_, built_in)| *built_in == name),
+        "`{name}` is the name of a built-in metric"
+    );
+    let mut metrics = METRICS.write().expect("lock should not be poisoned");
+    if metrics.contains_key(name) {
+        bail!("a metric named `{name}` is registered already");
+    }
+    let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+    metrics.insert(name, Arc::new(metric));
+    Ok(CustomMetric(name))
+}
+
+/// Decodes the frames of a y4m stream
+struct Y4mFrames<R: Read> {
+    decoder: y4m::Decoder<R>,
+}
+
+impl<R: Read> Iterator for Y4mFrames<R> {
+    type Item = anyhow::Result<MetricFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let width = self.decoder.get_width();
+        let height = self.decoder.get_height();
+        let bit_depth = self.decoder.get_bit_depth();
+        match self.decoder.read_frame() {
+            Ok(frame) => Some(Ok(MetricFrame {
+                width,
+                height,
+                bit_depth,
+                planes: [
+                    frame.get_y_plane().to_vec(),
+                    frame.get_u_plane().to_vec(),
+                    frame.get_v_plane().to_vec(),
+                ],
+            })),
+            Err(y4m::Error::EOF) => None,
+            Err(e) => Some(Err(anyhow!("failed to decode a frame: {e:?}"))),
+        }
+    }
+}
+
+/// Starts FFmpeg to decode `input` (`-` for stdin) with `filter` to a y4m
+/// stream on its stdout
+fn spawn_decoder(input: &OsStr, filter: &str, stdin: Stdio) -> anyhow::Result<Child> {
+    Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-hide_banner", "-i"])
+        .arg(input)
+        .args(["-vf", filter, "-strict", "-1", "-f", "yuv4mpegpipe", "-"])
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run ffmpeg to decode frames for a custom metric")
+}
+
+/// Scores `encoded` against the frames piped by `reference` with
+/// `metric`, decoding both at `res` with `scaler`, and returns the score of
+/// every frame
+pub(crate) fn run_custom_metric(
+    metric: CustomMetric,
+    encoded: &Path,
+    reference: &ReferencePipe,
+    res: &str,
+    scaler: &str,
+    sample_rate: usize,
+) -> anyhow::Result<Vec<f64>> {
+    run_quality_metric(
+        &*metric.metric(),
+        metric.name(),
+        encoded,
+        reference,
+        res,
+        scaler,
+        sample_rate,
+    )
+}
+
+/// Scores `encoded` against the frames piped by `reference` with
+/// `quality_metric`, which is called `metric` in errors, decoding both at
+/// `res` with `scaler`, and returns the score of every frame
+pub(crate) fn run_quality_metric(
+    quality_metric: &dyn QualityMetric,
+    metric: &str,
+    encoded: &Path,
+    reference: &ReferencePipe,
+    res: &str,
+    scaler: &str,
+    sample_rate: usize,
+) -> anyhow::Result<Vec<f64>> {
+    let scale = format!(
+        "scale={res}:flags={scaler}:force_original_aspect_ratio=decrease,setsar=1,format={}",
+        quality_metric.pixel_format().to_pix_fmt_string()
+    );
+    let select = if sample_rate > 1 {
+        format!("select=not(mod(n\\,{sample_rate})),")
+    } else {
+        String::new()
+    };
+
+    let (reference_processes, reference_stdout) = reference.spawn()?;
+    let mut reference = spawn_decoder(
+        OsStr::new("-"),
+        &format!("{select}{scale}"),
+        reference_stdout,
+    )?;
+    let mut distorted = spawn_decoder(encoded.as_os_str(), &scale, Stdio::null())?;
+
+    let scores = {
+        let decode = |child: &mut Child| -> anyhow::Result<Y4mFrames<ChildStdout>> {
+            let stdout = child.stdout.take().expect("decoder stdout should exist");
+            let decoder = y4m::decode(stdout)
+                .map_err(|e| anyhow!("failed to read the y4m header from ffmpeg: {e:?}"))?;
+            Ok(Y4mFrames {
+                decoder,
+            })
+        };
+        decode(&mut reference).and_then(|mut reference_frames| {
+            let mut distorted_frames = decode(&mut distorted)?;
+            quality_metric.score(&mut reference_frames, &mut distorted_frames)
+        })
+    };
+    if scores.is_err() {
+        let _ = reference.kill();
+        let _ = distorted.kill();
+    }
+    // the frames were dropped, so the decoders exit once they wrote the rest
+    let stderr = [&mut reference, &mut distorted]
+        .into_iter()
+        .filter_map(|child| {
+            let mut stderr = String::new();
+            child.stderr.take()?.read_to_string(&mut stderr).ok()?;
+            Some(stderr)
+        })
+        .collect::<String>();
+    let decoded = [reference.wait(), distorted.wait()]
+        .into_iter()
+        .all(|status| status.is_ok_and(|status| status.success()));
+    drop(reference_processes);
+
+    let scores = scores.with_context(|| {
+        format!(
+            "{metric} failed to score {}: {}",
+            encoded.display(),
+            stderr.trim()
+        )
+    })?;
+    ensure!(
+        decoded,
+        "ffmpeg failed to decode the frames for {metric}, which has to read all of them: {}",
+        stderr.trim()
+    );
+    ensure!(!scores.is_empty(), "{metric} returned no scores");
+    Ok(scores)
+}
+
+/// The file the scores of `encoded` with `metric` are written to by
+/// [`score_output`]
+#[inline]
+pub fn stats_file(encoded: &Path, metric: CustomMetric) -> PathBuf {
+    encoded.with_extension(format!("{metric}.json"))
+}
+
+/// Scores every frame of `encoded` against `source` with `metric`, compared
+/// at the resolution `res`. The scores and their summary are written to
+/// [`stats_file`], and plotted to an SVG next to it.
+///
+/// # Errors
+///
+/// Returns an error if the metric failed to score the frames, or the
+/// statistics file or the plot could not be written.
+#[inline]
+pub fn score_output(
+    metric: CustomMetric,
+    source: &Input,
+    encoded: &Path,
+    res: &str,
+) -> anyhow::Result<FrameScores> {
+    println!(":: {metric} Run");
+    let scores = run_custom_metric(
+        metric,
+        encoded,
+        &ReferencePipe::from_input(source),
+        res,
+        "bicubic",
+        1,
+    )?;
+
+    let scores = FrameScores::new(metric.name(), scores);
+    scores.write(&stats_file(encoded, metric))?;
+    plot_scores(
+        &scores.frames,
+        &encoded.with_extension(format!("{metric}.svg")),
+    )?;
+    info!(
+        "{metric}: mean {:.3}, harmonic mean {:.3}, 5th percentile {:.3}, minimum {:.3}",
+        scores.summary.mean,
+        scores.summary.harmonic_mean,
+        scores.summary.percentile_5,
+        scores.summary.minimum
+    );
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OutputMetric;
+
+    /// Mean absolute difference of the luma samples, where lower is better
+    struct MeanDifference;
+
+    impl QualityMetric for MeanDifference {
+        fn score(
+            &self,
+            reference: &mut MetricFrames<'_>,
+            distorted: &mut MetricFrames<'_>,
+        ) -> anyhow::Result<Vec<f64>> {
+            reference
+                .zip(distorted)
+                .map(|(reference, distorted)| {
+                    let (reference, distorted) = (reference?, distorted?);
+                    let sum = reference.planes[0]
+                        .iter()
+                        .zip(&distorted.planes[0])
+                        .map(|(a, b)| f64::from(a.abs_diff(*b)))
+                        .sum::<f64>();
+                    Ok(sum / reference.planes[0].len() as f64)
+                })
+                .collect()
+        }
+
+        fn higher_is_better(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn registered_metrics_parse_as_metrics() {
+        let metric =
+            register_metric("mean-difference", MeanDifference).expect("should register the metric");
+        assert_eq!(
+            "mean-difference".parse::<TargetMetric>(),
+            Ok(TargetMetric::Custom(metric))
+        );
+        assert_eq!(TargetMetric::Custom(metric).to_string(), "mean-difference");
+        assert!(TargetMetric::Custom(metric).lower_is_better());
+
+        let json = serde_json::to_string(&TargetMetric::Custom(metric)).expect("should serialize");
+        assert_eq!(
+            serde_json::from_str::<TargetMetric>(&json).expect("should deserialize"),
+            TargetMetric::Custom(metric)
+        );
+
+        assert_eq!(
+            "mean-difference".parse::<OutputMetric>(),
+            Ok(OutputMetric::Custom(metric))
+        );
+        assert_eq!(OutputMetric::Custom(metric).to_string(), "mean-difference");
+        assert_eq!(
+            stats_file(Path::new("output.mkv"), metric),
+            Path::new("output.mean-difference.json")
+        );
+
+        assert!(register_metric("mean-difference", MeanDifference).is_err());
+        assert!(register_metric("vmaf", MeanDifference).is_err());
+        assert!("unregistered".parse::<TargetMetric>().is_err());
+        assert!("unregistered".parse::<OutputMetric>().is_err());
+    }
+
+    #[test]
+    fn metric_scores_frames() {
+        let frame = |luma: u8| -> anyhow::Result<MetricFrame> {
+            Ok(MetricFrame {
+                width:     2,
+                height:    2,
+                bit_depth: 8,
+                planes:    [vec![luma; 4], vec![128], vec![128]],
+            })
+        };
+        let scores = MeanDifference
+            .score(
+                &mut [frame(10), frame(20)].into_iter(),
+                &mut [frame(12), frame(20)].into_iter(),
+            )
+            .expect("should score");
+        assert_eq!(scores, vec![2.0, 0.0]);
+    }
+}
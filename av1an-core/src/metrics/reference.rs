@@ -0,0 +1,168 @@
+use std::{
+    ffi::{OsStr, OsString},
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    ffmpeg::{self, compose_ffmpeg_pipe, FFPixelFormat},
+    Input,
+};
+
+/// Command piping the source frames that an encode is compared against as
+/// y4m, optionally through the FFmpeg filters and environment that the
+/// source of the encoder was piped with, so that the frames of both line up
+#[derive(Debug, Clone)]
+pub struct ReferencePipe {
+    cmd:         Vec<OsString>,
+    vspipe_args: Vec<String>,
+    envs:        Vec<(&'static str, String)>,
+    /// FFmpeg filter arguments and the pixel format of the encoder
+    ffmpeg:      Option<(Vec<String>, FFPixelFormat)>,
+}
+
+impl ReferencePipe {
+    #[inline]
+    pub fn new(cmd: &[impl AsRef<OsStr>], vspipe_args: Vec<String>) -> Self {
+        Self {
+            cmd: cmd.iter().map(|arg| arg.as_ref().to_owned()).collect(),
+            vspipe_args,
+            envs: Vec::new(),
+            ffmpeg: None,
+        }
+    }
+
+    /// Pipes all frames of `input`, decoded with FFmpeg if it is a video
+    #[inline]
+    pub fn from_input(input: &Input) -> Self {
+        match input {
+            Input::Video {
+                path, ..
+            } => {
+                let mut cmd: Vec<OsString> = vec!["ffmpeg".into()];
+                cmd.extend(ffmpeg::input_args().iter().map(OsString::from));
+                cmd.push("-i".into());
+                cmd.push(path.into());
+                cmd.extend(["-strict", "-1", "-f", "yuv4mpegpipe", "-"].map(OsString::from));
+                Self::new(&cmd, Vec::new())
+            },
+            Input::VapourSynth {
+                path,
+                vspipe_args,
+                ..
+            } => Self::new(
+                &[
+                    OsStr::new("vspipe"),
+                    OsStr::new("-c"),
+                    OsStr::new("y4m"),
+                    path.as_os_str(),
+                    OsStr::new("-"),
+                ],
+                vspipe_args.clone(),
+            ),
+        }
+    }
+
+    /// Sets an environment variable of the source command, e.g. one read by
+    /// the VapourSynth script of the chunk
+    #[inline]
+    #[must_use]
+    pub fn env(mut self, key: &'static str, value: String) -> Self {
+        self.envs.push((key, value));
+        self
+    }
+
+    /// Pipes the source through FFmpeg with `filter_args`, unless there are
+    /// none
+    #[inline]
+    #[must_use]
+    pub fn ffmpeg_filters(mut self, filter_args: Vec<String>, pix_format: FFPixelFormat) -> Self {
+        if !filter_args.is_empty() {
+            self.ffmpeg = Some((filter_args, pix_format));
+        }
+        self
+    }
+
+    /// Returns whether the source frames are changed before they are compared
+    #[inline]
+    pub fn is_filtered(&self) -> bool {
+        self.ffmpeg.is_some() || !self.envs.is_empty()
+    }
+
+    /// Starts piping the source, returning the processes and the output to
+    /// read the y4m frames from
+    #[inline]
+    pub fn spawn(&self) -> anyhow::Result<(ReferenceProcesses, Stdio)> {
+        let [cmd, args @ ..] = self.cmd.as_slice() else {
+            bail!("the source has no command to pipe it");
+        };
+        let mut source = Command::new(cmd);
+        // Append vspipe python arguments to the environment if there are any
+        for arg in &self.vspipe_args {
+            source.arg("-a").arg(arg);
+        }
+        let mut source = source
+            .args(args)
+            .envs(self.envs.iter().map(|(key, value)| (*key, value)))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to pipe the source")?;
+        let stdout = source.stdout.take().expect("source pipe stdout should exist");
+        let mut processes = ReferenceProcesses(vec![source]);
+
+        let Some((filter_args, pix_format)) = &self.ffmpeg else {
+            return Ok((processes, stdout.into()));
+        };
+        let ffmpeg_pipe = compose_ffmpeg_pipe(filter_args.as_slice(), *pix_format);
+        let [ffmpeg, args @ ..] = ffmpeg_pipe.as_slice() else {
+            unreachable!()
+        };
+        let mut ffmpeg = Command::new(ffmpeg)
+            .args(args)
+            .stdin(stdout)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to pipe the source through FFmpeg")?;
+        let stdout = ffmpeg.stdout.take().expect("ffmpeg pipe stdout should exist");
+        processes.0.push(ffmpeg);
+        Ok((processes, stdout.into()))
+    }
+}
+
+/// Processes of a [`ReferencePipe`], which are stopped once the frames were
+/// compared
+#[derive(Debug)]
+pub struct ReferenceProcesses(Vec<Child>);
+
+impl Drop for ReferenceProcesses {
+    fn drop(&mut self) {
+        for child in &mut self.0 {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unfiltered_without_filter_args() {
+        let reference = ReferencePipe::new(&["vspipe", "chunk.vpy"], Vec::new());
+        assert!(!reference.is_filtered());
+        let reference = reference.ffmpeg_filters(Vec::new(), FFPixelFormat::YUV420P10LE);
+        assert!(!reference.is_filtered());
+        let reference = reference.ffmpeg_filters(
+            vec!["-vf".into(), "crop=1920:800".into()],
+            FFPixelFormat::YUV420P10LE,
+        );
+        assert!(reference.is_filtered());
+        assert!(ReferencePipe::new(&["vspipe"], Vec::new())
+            .env("AV1AN_DEBAND", "medium".to_string())
+            .is_filtered());
+    }
+}
@@ -0,0 +1,273 @@
+//! Scores frames with SSIMULACRA2, measured with Vapoursynth-HIP or
+//! VapourSynth Zig Image Process if either is installed, and with the
+//! `ssimulacra2` crate otherwise
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context};
+use serde_json::Value;
+use ssimulacra2::{
+    compute_frame_ssimulacra2,
+    ColorPrimaries,
+    Frame,
+    MatrixCoefficients,
+    Plane,
+    TransferCharacteristic,
+    Yuv,
+    YuvConfig,
+};
+use tracing::info;
+
+use crate::{
+    metrics::{
+        custom::{run_quality_metric, MetricFrame, MetricFrames, QualityMetric},
+        reference::ReferencePipe,
+        statistics::FrameScores,
+        vmaf::plot_scores,
+    },
+    vapoursynth::{measure_ssimulacra2, VSZipVersion, VapoursynthPlugins},
+    ChunkMethod,
+    Input,
+};
+
+/// SSIMULACRA2 as implemented by the `ssimulacra2` crate, for when neither
+/// VapourSynth plugin can measure it
+struct NativeSsimulacra2;
+
+impl QualityMetric for NativeSsimulacra2 {
+    fn score(
+        &self,
+        reference: &mut MetricFrames<'_>,
+        distorted: &mut MetricFrames<'_>,
+    ) -> anyhow::Result<Vec<f64>> {
+        reference
+            .zip(distorted)
+            .map(|(reference, distorted)| {
+                Ok(compute_frame_ssimulacra2(
+                    to_yuv(reference?)?,
+                    to_yuv(distorted?)?,
+                )?)
+            })
+            .collect()
+    }
+}
+
+/// Converts a 4:2:0 frame of more than 8 bits for the `ssimulacra2` crate.
+/// The frames are scaled by FFmpeg without converting their colors, so they
+/// are compared as limited range BT.709.
+fn to_yuv(frame: MetricFrame) -> anyhow::Result<Yuv<u16>> {
+    let MetricFrame {
+        width,
+        height,
+        bit_depth,
+        planes,
+    } = frame;
+    let chroma = (width.div_ceil(2), height.div_ceil(2), 1);
+    let mut sizes = [(width, height, 0), chroma, chroma].into_iter();
+    let planes = planes.map(|samples| {
+        let (width, height, decimation) = sizes.next().expect("every plane should have a size");
+        let mut plane = Plane::new(width, height, decimation, decimation, 0, 0);
+        plane.copy_from_raw_u8(&samples, width * 2, 2);
+        plane
+    });
+    let config = YuvConfig {
+        bit_depth:                bit_depth as u8,
+        subsampling_x:            1,
+        subsampling_y:            1,
+        full_range:               false,
+        matrix_coefficients:      MatrixCoefficients::BT709,
+        transfer_characteristics: TransferCharacteristic::BT1886,
+        color_primaries:          ColorPrimaries::BT709,
+    };
+    Yuv::new(
+        Frame {
+            planes,
+        },
+        config,
+    )
+    .map_err(|e| anyhow::anyhow!("failed to convert a {width}x{height} frame: {e:?}"))
+}
+
+/// Returns `plugins` if they can measure SSIMULACRA2 of `source`, which
+/// requires Vapoursynth-HIP or VapourSynth Zig Image Process, and a source
+/// that is read by a VapourSynth source filter
+#[inline]
+pub(crate) fn usable_plugins(
+    source: &Input,
+    plugins: Option<VapoursynthPlugins>,
+) -> Option<VapoursynthPlugins> {
+    let readable = match source {
+        Input::VapourSynth {
+            ..
+        } => true,
+        Input::Video {
+            chunk_method, ..
+        } => matches!(
+            chunk_method,
+            ChunkMethod::LSMASH
+                | ChunkMethod::FFMS2
+                | ChunkMethod::BESTSOURCE
+                | ChunkMethod::DGDECNV
+        ),
+    };
+    plugins.filter(|plugins| readable && (plugins.vship || plugins.vszip != VSZipVersion::None))
+}
+
+/// Scores `encoded` against the frames piped by `reference` with the
+/// `ssimulacra2` crate, decoding both at `res` with `scaler`, and returns the
+/// score of every frame
+#[inline]
+pub(crate) fn measure_native(
+    encoded: &Path,
+    reference: &ReferencePipe,
+    res: &str,
+    scaler: &str,
+    sample_rate: usize,
+) -> anyhow::Result<Vec<f64>> {
+    run_quality_metric(
+        &NativeSsimulacra2,
+        "SSIMULACRA2",
+        encoded,
+        reference,
+        res,
+        scaler,
+        sample_rate,
+    )
+}
+
+/// Path of the statistics file with the SSIMULACRA2 scores of `encoded`
+#[inline]
+#[must_use]
+pub fn stats_file(encoded: &Path) -> PathBuf {
+    encoded.with_extension("ssimulacra2.json")
+}
+
+/// Scores every frame of `encoded` against the first `frames` frames of
+/// `source`, with `plugins` if they can read it and the `ssimulacra2` crate
+/// at the resolution `res` otherwise. The scores and their summary are written
+/// to [`stats_file`], and plotted to an SVG next to it. If the output was
+/// scored with VMAF, the scores are also added to the frames of its VMAF log.
+///
+/// # Errors
+///
+/// Returns an error if the frames could not be scored, or the statistics
+/// file or the plot could not be written.
+#[inline]
+pub fn score_output(
+    source: &Input,
+    encoded: &Path,
+    frames: usize,
+    plugins: Option<VapoursynthPlugins>,
+    res: &str,
+) -> anyhow::Result<FrameScores> {
+    println!(":: SSIMULACRA2 Run");
+    let scores = if let Some(plugins) = usable_plugins(source, plugins) {
+        measure_ssimulacra2(source, encoded, (0, frames as u32), None, 1, plugins)?
+    } else {
+        measure_native(
+            encoded,
+            &ReferencePipe::from_input(source),
+            res,
+            "bicubic",
+            1,
+        )?
+    };
+    ensure!(!scores.is_empty(), "no frames of the output were scored");
+
+    let scores = FrameScores::new("ssimulacra2", scores);
+    scores.write(&stats_file(encoded))?;
+    plot_scores(&scores.frames, &encoded.with_extension("ssimulacra2.svg"))?;
+    let vmaf_file = encoded.with_extension("json");
+    if vmaf_file.exists() {
+        add_to_vmaf_log(&vmaf_file, &scores.frames)?;
+    }
+    info!(
+        "SSIMULACRA2: mean {:.3}, median {:.3}, 5th percentile {:.3}, minimum {:.3}",
+        scores.summary.mean,
+        scores.summary.median,
+        scores.summary.percentile_5,
+        scores.summary.minimum
+    );
+    Ok(scores)
+}
+
+/// Adds the score of every frame to the metrics of the frame in the VMAF log
+/// `vmaf_file`, next to its VMAF score
+fn add_to_vmaf_log(vmaf_file: &Path, scores: &[f64]) -> anyhow::Result<()> {
+    let json = fs::read_to_string(vmaf_file)
+        .with_context(|| format!("Failed to read {}", vmaf_file.display()))?;
+    let mut log: Value = serde_json::from_str(&json)?;
+    let frames = log
+        .get_mut("frames")
+        .and_then(Value::as_array_mut)
+        .context("the VMAF log has no frames")?;
+    for (frame, &score) in frames.iter_mut().zip(scores) {
+        if let Some(metrics) = frame.get_mut("metrics").and_then(Value::as_object_mut) {
+            metrics.insert("ssimulacra2".to_string(), score.into());
+        }
+    }
+    fs::write(vmaf_file, serde_json::to_string_pretty(&log)?)
+        .with_context(|| format!("Failed to write {}", vmaf_file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(luma: impl Fn(usize, usize) -> u16) -> MetricFrame {
+        let (width, height) = (16, 16);
+        let luma = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| luma(x, y).to_le_bytes())
+            .collect();
+        let chroma = 512_u16.to_le_bytes().repeat(width * height / 4);
+        MetricFrame {
+            width,
+            height,
+            bit_depth: 10,
+            planes: [luma, chroma.clone(), chroma],
+        }
+    }
+
+    #[test]
+    fn native_scores_frames() {
+        let gradient = |x: usize, y: usize| 64 + (x * 40 + y * 10) as u16;
+        let scores = NativeSsimulacra2
+            .score(
+                &mut [Ok(frame(gradient)), Ok(frame(gradient))].into_iter(),
+                &mut [Ok(frame(gradient)), Ok(frame(|_, _| 512))].into_iter(),
+            )
+            .expect("should score");
+        assert_eq!(scores.len(), 2);
+        assert!(
+            (scores[0] - 100.0).abs() < 1e-6,
+            "identical frame scored {}",
+            scores[0]
+        );
+        assert!(scores[1] < scores[0]);
+    }
+
+    #[test]
+    fn scores_are_added_to_the_vmaf_log() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let vmaf_file = dir.path().join("output.json");
+        fs::write(
+            &vmaf_file,
+            r#"{"frames": [{"frameNum": 0, "metrics": {"vmaf": 95.0}}, {"frameNum": 1, "metrics": {"vmaf": 90.0}}]}"#,
+        )
+        .expect("should write the VMAF log");
+
+        add_to_vmaf_log(&vmaf_file, &[80.5, 70.25]).expect("should add the scores");
+
+        let log: Value = serde_json::from_str(
+            &fs::read_to_string(&vmaf_file).expect("should read the VMAF log"),
+        )
+        .expect("should parse the VMAF log");
+        assert_eq!(log["frames"][0]["metrics"]["vmaf"], 95.0);
+        assert_eq!(log["frames"][0]["metrics"]["ssimulacra2"], 80.5);
+        assert_eq!(log["frames"][1]["metrics"]["ssimulacra2"], 70.25);
+    }
+}
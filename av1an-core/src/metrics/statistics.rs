@@ -1,4 +1,7 @@
-use std::{cmp::Ordering, collections::HashMap};
+use std::{cmp::Ordering, collections::HashMap, fs, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
 
 pub struct MetricStatistics {
     scores: Vec<f64>,
@@ -112,3 +115,82 @@ impl MetricStatistics {
         })
     }
 }
+
+/// Summary of the frame scores of the output with a metric
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreSummary {
+    pub mean:          f64,
+    pub harmonic_mean: f64,
+    pub minimum:       f64,
+    pub percentile_1:  f64,
+    pub percentile_5:  f64,
+    pub percentile_25: f64,
+    pub median:        f64,
+}
+
+impl ScoreSummary {
+    pub fn new(scores: &[f64]) -> Self {
+        let mut statistics = MetricStatistics::new(scores.to_vec());
+        Self {
+            mean:          statistics.mean(),
+            harmonic_mean: statistics.harmonic_mean(),
+            minimum:       statistics.minimum(),
+            percentile_1:  statistics.percentile(1),
+            percentile_5:  statistics.percentile(5),
+            percentile_25: statistics.percentile(25),
+            median:        statistics.median(),
+        }
+    }
+}
+
+/// The frame scores of the output with a metric, as written to its
+/// statistics file next to the output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameScores {
+    pub metric:  String,
+    pub summary: ScoreSummary,
+    /// Score of every frame, by its index
+    pub frames:  Vec<f64>,
+}
+
+impl FrameScores {
+    /// Summarizes `frames`, which must not be empty
+    pub fn new(metric: &str, frames: Vec<f64>) -> Self {
+        Self {
+            metric: metric.to_string(),
+            summary: ScoreSummary::new(&frames),
+            frames,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_scores_are_summarized() {
+        let frames = (1..=100).map(f64::from).collect::<Vec<_>>();
+        let scores = FrameScores::new("ssimulacra2", frames);
+        assert_eq!(scores.summary, ScoreSummary {
+            mean:          50.5,
+            harmonic_mean: 100.0 / (1..=100).map(|x| 1.0 / f64::from(x)).sum::<f64>(),
+            minimum:       1.0,
+            percentile_1:  2.0,
+            percentile_5:  6.0,
+            percentile_25: 26.0,
+            median:        50.5,
+        });
+    }
+}
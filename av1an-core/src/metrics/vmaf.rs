@@ -1,24 +1,72 @@
 use std::{
     cmp::Ordering,
-    ffi::OsStr,
-    path::Path,
+    fs,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    sync::OnceLock,
 };
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
+use av1_grain::TransferFunction;
+use av_format::rational::Rational64;
 use plotters::prelude::*;
 use serde::{Deserialize, Serialize};
-use smallvec::SmallVec;
+use strum::{Display, EnumString, IntoStaticStr};
+use tracing::{info, warn};
 
 use crate::{
     broker::EncoderCrash,
     ffmpeg,
-    ref_smallvec,
-    util::printable_base10_digits,
+    metrics::reference::ReferencePipe,
+    util::{cache_dir, printable_base10_digits, sha1_hex},
     Input,
     VmafFeature,
 };
 
+/// Transfer function of HDR input, which the VMAF models, trained on SDR
+/// video, do not score meaningfully. The reference and the distorted video are
+/// tone mapped to SDR the same way before they are compared.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString, IntoStaticStr,
+)]
+pub enum VmafTonemap {
+    /// PQ if the input reports it, otherwise no tone mapping
+    #[strum(serialize = "auto")]
+    Auto,
+    #[strum(serialize = "pq")]
+    Pq,
+    #[strum(serialize = "hlg")]
+    Hlg,
+}
+
+impl VmafTonemap {
+    /// Resolves [`Self::Auto`] with the transfer function of the input.
+    /// Returns `None` if the input needs no tone mapping.
+    #[inline]
+    #[must_use]
+    pub fn resolve(self, transfer: TransferFunction) -> Option<Self> {
+        match self {
+            Self::Auto => (transfer == TransferFunction::SMPTE2084).then_some(Self::Pq),
+            tonemap => Some(tonemap),
+        }
+    }
+
+    /// FFmpeg filters that tone map a frame with this transfer function and
+    /// BT.2020 primaries to BT.709, followed by a comma
+    fn filter(self) -> String {
+        let transfer = match self {
+            // resolved before scoring
+            Self::Auto | Self::Pq => "smpte2084",
+            Self::Hlg => "arib-std-b67",
+        };
+        format!(
+            "zscale=tin={transfer}:min=bt2020nc:pin=bt2020:rin=tv:t=linear:npl=100,\
+             format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:\
+             r=tv,format=yuv420p10le,"
+        )
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct VmafScore {
     vmaf: f64,
@@ -36,8 +84,13 @@ struct VmafResult {
 
 pub fn plot_vmaf_score_file(scores_file: &Path, plot_path: &Path) -> anyhow::Result<()> {
     let scores = read_vmaf_file(scores_file).with_context(|| "Failed to parse VMAF file")?;
+    plot_scores(&scores, plot_path)
+}
 
-    let mut sorted_scores = scores.clone();
+/// Plots the score of every frame with a metric scored up to 100, with the
+/// percentiles of the scores, as an SVG
+pub fn plot_scores(scores: &[f64], plot_path: &Path) -> anyhow::Result<()> {
+    let mut sorted_scores = scores.to_vec();
     sorted_scores.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less));
 
     let plot_width = 1600 + (printable_base10_digits(scores.len()) * 200);
@@ -125,6 +178,26 @@ pub fn validate_libvmaf() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Checks that FFmpeg has the zscale filter, which tone maps HDR input for
+/// [`VmafTonemap`]
+pub fn validate_tonemap() -> anyhow::Result<()> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-h");
+
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let out = cmd.output()?;
+
+    let stdr = String::from_utf8(out.stderr)?;
+    if !stdr.contains("--enable-libzimg") {
+        return Err(anyhow!(
+            "FFmpeg is not compiled with --enable-libzimg, but VMAF tone mapping was enabled"
+        ));
+    }
+    Ok(())
+}
+
 #[expect(clippy::too_many_arguments)]
 pub fn plot(
     encoded: &Path,
@@ -134,53 +207,49 @@ pub fn plot(
     scaler: &str,
     sample_rate: usize,
     filter: Option<&str>,
+    tonemap: Option<VmafTonemap>,
     threads: usize,
+    cuda: bool,
     probing_vmaf_features: &[VmafFeature],
+    fps_conversion: Option<(Rational64, Rational64)>,
 ) -> anyhow::Result<()> {
+    // both videos are read at this frame rate, so that frames are compared by
+    // their index
+    const FRAMERATE: i64 = 60;
+
     let json_file = encoded.with_extension("json");
     let plot_file = encoded.with_extension("svg");
-    let vspipe_args;
-
     println!(":: VMAF Run");
 
-    let pipe_cmd: SmallVec<[&OsStr; 8]> = match reference {
-        Input::Video {
-            path, ..
-        } => {
-            vspipe_args = vec![];
-            ref_smallvec!(OsStr, 8, [
-                "ffmpeg",
-                "-i",
-                path,
-                "-strict",
-                "-1",
-                "-f",
-                "yuv4mpegpipe",
-                "-"
-            ])
-        },
-        Input::VapourSynth {
-            path,
-            vspipe_args: args,
-            ..
-        } => {
-            vspipe_args = args.to_owned();
-            ref_smallvec!(OsStr, 8, ["vspipe", "-c", "y4m", path, "-"])
+    // drop or repeat reference frames like the filters that converted the
+    // input from the first to the second frame rate
+    let filter = match fps_conversion {
+        Some((input, output)) => {
+            let fps = format!(
+                "fps={}",
+                Rational64::from_integer(FRAMERATE) * output / input
+            );
+            Some(match filter {
+                Some(filter) => format!("{fps},{filter}"),
+                None => fps,
+            })
         },
+        None => filter.map(str::to_owned),
     };
 
     run_vmaf(
         encoded,
-        &pipe_cmd,
-        vspipe_args,
+        &ReferencePipe::from_input(reference),
         &json_file,
         model,
         res,
         scaler,
         sample_rate,
-        filter,
+        filter.as_deref(),
+        tonemap,
         threads,
-        60.0,
+        cuda,
+        FRAMERATE as f64,
         false,
         probing_vmaf_features,
     )?;
@@ -201,22 +270,189 @@ pub fn get_vmaf_model_version(features: &[VmafFeature]) -> &'static str {
     }
 }
 
+/// Whether `model` names one of the models built into libvmaf, like
+/// `vmaf_v0.6.1.json`, rather than a model file
+fn is_builtin_model(model: &Path) -> bool {
+    model.as_os_str().to_string_lossy().ends_with(".json") && !model.is_file()
+}
+
+/// Returns whether libvmaf was built with its models. Some builds leave them
+/// out, and can only load models from files.
+#[inline]
+pub fn has_builtin_models() -> bool {
+    static BUILTIN: OnceLock<bool> = OnceLock::new();
+    *BUILTIN.get_or_init(|| {
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error"])
+            .args(["-f", "lavfi", "-i", "color=s=64x64:d=0.1"])
+            .args(["-f", "lavfi", "-i", "color=s=64x64:d=0.1"])
+            .args(["-lavfi", "[0:v][1:v]libvmaf=model='version=vmaf_v0.6.1'"])
+            .args(["-f", "null", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })
+}
+
+/// Returns whether FFmpeg can compute VMAF on an NVIDIA GPU with the
+/// `libvmaf_cuda` filter, which needs FFmpeg and libvmaf built with CUDA
+/// support and a working CUDA device
+#[inline]
+pub fn has_libvmaf_cuda() -> bool {
+    static CUDA: OnceLock<bool> = OnceLock::new();
+    *CUDA.get_or_init(|| libvmaf_cuda_takes("yuv420p"))
+}
+
+/// Returns whether `libvmaf_cuda` also takes 10-bit frames uploaded as
+/// `p010le`, which keeps the precision of high bit depth sources
+#[inline]
+pub fn has_libvmaf_cuda_p010() -> bool {
+    static P010: OnceLock<bool> = OnceLock::new();
+    *P010.get_or_init(|| has_libvmaf_cuda() && libvmaf_cuda_takes("p010le"))
+}
+
+/// Returns whether FFmpeg can compute VMAF with `libvmaf_cuda` of frames
+/// uploaded to the GPU in `format`
+fn libvmaf_cuda_takes(format: &str) -> bool {
+    Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(["-f", "lavfi", "-i", "color=s=64x64:d=0.1"])
+        .args(["-f", "lavfi", "-i", "color=s=64x64:d=0.1"])
+        .args([
+            "-lavfi",
+            &format!(
+                "[0:v]format={format},hwupload_cuda[dis];[1:v]format={format},hwupload_cuda[ref];\
+                 [dis][ref]libvmaf_cuda"
+            ),
+        ])
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Release of libvmaf the models are downloaded from
+const MODELS_REF: &str = "v3.0.0";
+
+/// Directory downloaded VMAF models are cached in, `~/.cache/av1an/vmaf`
+#[inline]
+pub fn default_model_cache_dir() -> Option<PathBuf> {
+    Some(cache_dir()?.join("vmaf"))
+}
+
+/// Checksum git records for a file with `contents`, which the GitHub API
+/// returns along with the file
+fn git_blob_sha1(contents: &[u8]) -> String {
+    let mut blob = format!("blob {}\0", contents.len()).into_bytes();
+    blob.extend_from_slice(contents);
+    sha1_hex(&blob)
+}
+
+/// File in the libvmaf repository, as returned by the GitHub API
+#[derive(Deserialize)]
+struct RepositoryFile {
+    sha:          String,
+    download_url: String,
+}
+
+/// Downloads `url` with curl, returning its contents
+fn download(url: &str) -> anyhow::Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["--fail", "--location", "--silent", "--show-error", "--retry", "3"])
+        .args(["--header", "Accept: application/vnd.github+json", url])
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run curl to download {url}, is it installed?"))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to download {url} with curl ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Returns the file of the VMAF model `version`, like `vmaf_v0.6.1`, for
+/// libvmaf builds without built-in models. The model is downloaded from the
+/// libvmaf repository into `cache_dir` unless a copy whose checksum still
+/// matches is there already, and the download is checked against the
+/// checksum the repository records for it.
+#[inline]
+pub fn fetch_model(version: &str, cache_dir: &Path) -> anyhow::Result<PathBuf> {
+    let path = cache_dir.join(format!("{version}.json"));
+    let checksum_path = path.with_extension("json.sha1");
+    if let (Ok(contents), Ok(checksum)) = (fs::read(&path), fs::read_to_string(&checksum_path)) {
+        if git_blob_sha1(&contents) == checksum.trim() {
+            return Ok(path);
+        }
+        warn!(
+            "the cached VMAF model {} is corrupted, downloading it again",
+            path.display()
+        );
+    }
+
+    info!("downloading the VMAF model {version} to {}", path.display());
+    let api_url = format!(
+        "https://api.github.com/repos/Netflix/vmaf/contents/model/{version}.json?ref={MODELS_REF}"
+    );
+    let file: RepositoryFile = serde_json::from_slice(&download(&api_url)?)
+        .with_context(|| format!("Failed to parse the response of {api_url}"))?;
+    let contents = download(&file.download_url)?;
+    let checksum = git_blob_sha1(&contents);
+    if checksum != file.sha {
+        bail!(
+            "The download of the VMAF model {version} is corrupted: its checksum is {checksum}, \
+             expected {}",
+            file.sha
+        );
+    }
+
+    fs::create_dir_all(cache_dir).with_context(|| {
+        format!(
+            "Failed to create the VMAF model cache {}",
+            cache_dir.display()
+        )
+    })?;
+    fs::write(&path, &contents)
+        .with_context(|| format!("Failed to write the VMAF model to {}", path.display()))?;
+    fs::write(&checksum_path, checksum)?;
+    Ok(path)
+}
+
 #[expect(clippy::too_many_arguments)]
 pub fn run_vmaf(
     encoded: &Path,
-    reference_pipe_cmd: &[impl AsRef<OsStr>],
-    vspipe_args: Vec<String>,
+    reference: &ReferencePipe,
     stat_file: impl AsRef<Path>,
     model: Option<impl AsRef<Path>>,
     res: &str,
     scaler: &str,
     sample_rate: usize,
     vmaf_filter: Option<&str>,
+    tonemap: Option<VmafTonemap>,
     threads: usize,
+    cuda: bool,
     framerate: f64,
     disable_motion: bool,
     probing_vmaf_features: &[VmafFeature],
 ) -> anyhow::Result<()> {
+    // libvmaf_cuda only takes 4:2:0 frames in GPU memory. FFmpeg picks the
+    // listed format closest to the frames, so high bit depths stay 10-bit if
+    // libvmaf_cuda takes them.
+    let (upload, libvmaf) = if cuda {
+        if has_libvmaf_cuda_p010() {
+            (",format=yuv420p|p010le,hwupload_cuda", "libvmaf_cuda")
+        } else {
+            (",format=yuv420p,hwupload_cuda", "libvmaf_cuda")
+        }
+    } else {
+        ("", "libvmaf")
+    };
     let mut filter = if sample_rate > 1 {
         format!(
             "select=not(mod(n\\,{})),setpts={:.4}*PTS,",
@@ -232,9 +468,10 @@ pub fn run_vmaf(
         filter.push_str(vmaf_filter);
         filter.push(',');
     }
+    let tonemap = tonemap.map(VmafTonemap::filter).unwrap_or_default();
 
     let vmaf = if let Some(model) = model {
-        let model_path = if model.as_ref().as_os_str().to_string_lossy().ends_with(".json") {
+        let model_path = if is_builtin_model(model.as_ref()) {
             format!(
                 "version={}{}",
                 get_vmaf_model_version(probing_vmaf_features),
@@ -248,7 +485,7 @@ pub fn run_vmaf(
             format!("path={}", ffmpeg::escape_path_in_filter(&model)?)
         };
         format!(
-            "[distorted][ref]libvmaf=log_fmt='json':eof_action=endall:log_path={}:model='{}':\
+            "[distorted][ref]{libvmaf}=log_fmt='json':eof_action=endall:log_path={}:model='{}':\
              n_threads={}",
             ffmpeg::escape_path_in_filter(stat_file)?,
             model_path,
@@ -256,7 +493,7 @@ pub fn run_vmaf(
         )
     } else {
         format!(
-            "[distorted][ref]libvmaf=log_fmt='json':eof_action=endall:log_path={}{}:n_threads={}",
+            "[distorted][ref]{libvmaf}=log_fmt='json':eof_action=endall:log_path={}{}:n_threads={}",
             ffmpeg::escape_path_in_filter(stat_file)?,
             if disable_motion {
                 format!(
@@ -270,19 +507,7 @@ pub fn run_vmaf(
         )
     };
 
-    let mut source_pipe = if let [cmd, args @ ..] = reference_pipe_cmd {
-        let mut source_pipe = Command::new(cmd);
-        // Append vspipe python arguments to the environment if there are any
-        for arg in vspipe_args {
-            source_pipe.args(["-a", &arg]);
-        }
-        source_pipe.args(args);
-        source_pipe.stdout(Stdio::piped());
-        source_pipe.stderr(Stdio::null());
-        source_pipe.spawn()?
-    } else {
-        unreachable!()
-    };
+    let (_reference_processes, reference_stdout) = reference.spawn()?;
 
     let mut cmd = Command::new("ffmpeg");
     cmd.args([
@@ -301,19 +526,19 @@ pub fn run_vmaf(
     cmd.args(["-r", &framerate.to_string(), "-i", "-", "-filter_complex"]);
 
     let distorted = format!(
-        "[0:v]scale={}:flags={}:force_original_aspect_ratio=decrease,setpts=PTS-STARTPTS,\
-         setsar=1[distorted];",
-        &res, &scaler
+        "[0:v]{}scale={}:flags={}:force_original_aspect_ratio=decrease,setpts=PTS-STARTPTS,\
+         setsar=1{upload}[distorted];",
+        tonemap, &res, &scaler
     );
     let reference = format!(
-        "[1:v]{}scale={}:flags={}:force_original_aspect_ratio=decrease,setpts=PTS-STARTPTS,\
-         setsar=1[ref];",
-        filter, &res, &scaler
+        "[1:v]{}{}scale={}:flags={}:force_original_aspect_ratio=decrease,setpts=PTS-STARTPTS,\
+         setsar=1{upload}[ref];",
+        filter, tonemap, &res, &scaler
     );
 
     cmd.arg(format!("{distorted}{reference}{vmaf}"));
     cmd.args(["-f", "null", "-"]);
-    cmd.stdin(source_pipe.stdout.take().expect("source_pipe stdout should exist"));
+    cmd.stdin(reference_stdout);
     cmd.stderr(Stdio::piped());
     cmd.stdout(Stdio::null());
 
@@ -336,9 +561,9 @@ pub fn run_vmaf(
 #[expect(clippy::too_many_arguments)]
 pub fn run_vmaf_weighted(
     encoded: &Path,
-    reference_pipe_cmd: &[impl AsRef<OsStr>],
-    vspipe_args: Vec<String>,
+    reference: &ReferencePipe,
     model: Option<impl AsRef<Path>>,
+    tonemap: Option<VmafTonemap>,
     threads: usize,
     framerate: f64,
     disable_motion: bool,
@@ -354,7 +579,7 @@ pub fn run_vmaf_weighted(
     let vmaf_v_path = temp_dir.join(format!("vmaf_v_{}.json", file_stem));
 
     let model_str = if let Some(model) = model {
-        if model.as_ref().as_os_str().to_string_lossy().ends_with(".json") {
+        if is_builtin_model(model.as_ref()) {
             format!(
                 "version={}{}",
                 get_vmaf_model_version(probing_vmaf_features),
@@ -387,18 +612,7 @@ pub fn run_vmaf_weighted(
         )
     };
 
-    let mut source_pipe = if let [cmd, args @ ..] = reference_pipe_cmd {
-        let mut source_pipe = Command::new(cmd);
-        for arg in vspipe_args {
-            source_pipe.args(["-a", &arg]);
-        }
-        source_pipe.args(args);
-        source_pipe.stdout(Stdio::piped());
-        source_pipe.stderr(Stdio::null());
-        source_pipe.spawn()?
-    } else {
-        unreachable!()
-    };
+    let (_reference_processes, reference_stdout) = reference.spawn()?;
 
     let mut cmd = Command::new("ffmpeg");
     cmd.args([
@@ -416,8 +630,9 @@ pub fn run_vmaf_weighted(
     cmd.arg(encoded);
     cmd.args(["-r", &framerate.to_string(), "-i", "-", "-filter_complex"]);
 
+    let tonemap = tonemap.map(VmafTonemap::filter).unwrap_or_default();
     let filter_complex = format!(
-        "[1:v]format=yuv420p[ref];[0:v]format=yuv420p[dis];\
+        "[1:v]{tonemap}format=yuv420p[ref];[0:v]{tonemap}format=yuv420p[dis];\
          [dis]extractplanes=y+u+v[dis_y][dis_u][dis_v];\
          [ref]extractplanes=y+u+v[ref_y][ref_u][ref_v];[dis_y][ref_y]libvmaf=log_path={}:\
          log_fmt=json:n_threads={}:n_subsample=1:model='{}':eof_action=endall[vmaf_y_out];\
@@ -438,14 +653,12 @@ pub fn run_vmaf_weighted(
     cmd.arg(filter_complex);
     cmd.args(["-map", "[vmaf_y_out]", "-map", "[vmaf_u_out]", "-map", "[vmaf_v_out]"]);
     cmd.args(["-an", "-sn", "-dn", "-f", "null", "-"]);
-    cmd.stdin(source_pipe.stdout.take().expect("source_pipe stdout should exist"));
+    cmd.stdin(reference_stdout);
     cmd.stderr(Stdio::piped());
     cmd.stdout(Stdio::null());
 
     let output = cmd.output()?;
 
-    let _ = source_pipe.wait();
-
     if !output.status.success() {
         return Err(anyhow::anyhow!(
             "FFmpeg command failed: {}",
@@ -1,15 +1,24 @@
 use core::f64;
 use std::{
-    ffi::OsStr,
-    path::Path,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, ensure};
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString, IntoStaticStr};
+use tracing::info;
 
-use crate::{broker::EncoderCrash, ffmpeg};
+use crate::{
+    broker::EncoderCrash,
+    ffmpeg,
+    metrics::{reference::ReferencePipe, statistics::FrameScores, vmaf::plot_scores},
+    Input,
+};
+
+/// Score of frames identical to the source, which have an infinite XPSNR
+/// that the statistics file cannot hold
+const IDENTICAL_FRAME_XPSNR: f64 = 100.0;
 
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString, IntoStaticStr,
@@ -43,15 +52,15 @@ pub fn validate_libxpsnr() -> anyhow::Result<()> {
 #[expect(clippy::too_many_arguments)]
 pub fn run_xpsnr(
     encoded: &Path,
-    reference_pipe_cmd: &[impl AsRef<OsStr>],
-    vspipe_args: Vec<String>,
+    reference: &ReferencePipe,
     stat_file: impl AsRef<Path>,
     res: &str,
     scaler: &str,
     sample_rate: usize,
+    vmaf_filter: Option<&str>,
     framerate: f64,
 ) -> anyhow::Result<()> {
-    let filter = if sample_rate > 1 {
+    let mut filter = if sample_rate > 1 {
         format!(
             "select=not(mod(n\\,{})),setpts={:.4}*PTS,",
             sample_rate,
@@ -61,24 +70,18 @@ pub fn run_xpsnr(
         String::new()
     };
 
+    if let Some(vmaf_filter) = vmaf_filter {
+        filter.reserve(1 + vmaf_filter.len());
+        filter.push_str(vmaf_filter);
+        filter.push(',');
+    }
+
     let xpsnr = format!(
         "[distorted][ref]xpsnr=stats_file={}:eof_action=endall",
         ffmpeg::escape_path_in_filter(stat_file)?
     );
 
-    let mut source_pipe = if let [cmd, args @ ..] = reference_pipe_cmd {
-        let mut source_pipe = Command::new(cmd);
-        // Append vspipe python arguments to the environment if there are any
-        for arg in vspipe_args {
-            source_pipe.args(["-a", &arg]);
-        }
-        source_pipe.args(args);
-        source_pipe.stdout(Stdio::piped());
-        source_pipe.stderr(Stdio::null());
-        source_pipe.spawn()?
-    } else {
-        unreachable!()
-    };
+    let (_reference_processes, reference_stdout) = reference.spawn()?;
 
     let mut cmd = Command::new("ffmpeg");
     cmd.args([
@@ -107,7 +110,7 @@ pub fn run_xpsnr(
 
     cmd.arg(format!("{distorted}{reference}{xpsnr}"));
     cmd.args(["-f", "null", "-"]);
-    cmd.stdin(source_pipe.stdout.take().expect("source_pipe stdout should exist"));
+    cmd.stdin(reference_stdout);
     cmd.stderr(Stdio::piped());
     cmd.stdout(Stdio::null());
 
@@ -209,3 +212,63 @@ pub fn weight_xpsnr(y: f64, u: f64, v: f64) -> f64 {
                 / 6.0,
         )
 }
+
+/// Path of the statistics file with the XPSNR scores of `encoded`
+#[inline]
+#[must_use]
+pub fn stats_file(encoded: &Path) -> PathBuf {
+    encoded.with_extension("xpsnr.json")
+}
+
+/// Scores every frame of `encoded` against `source` with the minimum XPSNR
+/// of the three planes, compared at the resolution `res`. `vmaf_filter` is
+/// applied to the frames of `source`, as for VMAF. FFmpeg's log is written to
+/// `temp`. The scores and their summary are written to
+/// [`stats_file`], and plotted to an SVG next to it.
+///
+/// # Errors
+///
+/// Returns an error if FFmpeg failed to score the frames, or the statistics
+/// file or the plot could not be written.
+#[inline]
+pub fn score_output(
+    source: &Input,
+    encoded: &Path,
+    temp: &Path,
+    res: &str,
+    vmaf_filter: Option<&str>,
+) -> anyhow::Result<FrameScores> {
+    // both videos are read at this frame rate, so that frames are compared by
+    // their index
+    const FRAMERATE: f64 = 60.0;
+
+    println!(":: XPSNR Run");
+    let log_file = temp.join("output.xpsnr.log");
+    run_xpsnr(
+        encoded,
+        &ReferencePipe::from_input(source),
+        &log_file,
+        res,
+        "bicubic",
+        1,
+        vmaf_filter,
+        FRAMERATE,
+    )?;
+    let (_, scores) = read_xpsnr_file(&log_file, XPSNRSubMetric::Minimum)?;
+    ensure!(!scores.is_empty(), "no frames of the output were scored");
+
+    let scores = FrameScores::new(
+        "xpsnr",
+        scores.into_iter().map(|score| score.min(IDENTICAL_FRAME_XPSNR)).collect(),
+    );
+    scores.write(&stats_file(encoded))?;
+    plot_scores(&scores.frames, &encoded.with_extension("xpsnr.svg"))?;
+    info!(
+        "XPSNR: mean {:.3}, harmonic mean {:.3}, 5th percentile {:.3}, minimum {:.3}",
+        scores.summary.mean,
+        scores.summary.harmonic_mean,
+        scores.summary.percentile_5,
+        scores.summary.minimum
+    );
+    Ok(scores)
+}
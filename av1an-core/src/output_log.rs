@@ -0,0 +1,127 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use tracing::warn;
+
+/// Output of a process run for a chunk, of which only the most recent lines
+/// up to a size limit are kept in memory. Encoders that print megabytes of
+/// warnings per chunk would otherwise use up the memory of long encodes.
+///
+/// The complete output can be written to a file as well, for when the kept
+/// lines do not show what went wrong.
+#[derive(Debug)]
+pub(crate) struct OutputLog {
+    lines:   VecDeque<String>,
+    /// Size of [`Self::lines`], including their newlines
+    len:     usize,
+    limit:   usize,
+    /// Bytes of output dropped from the front of [`Self::lines`]
+    dropped: usize,
+    spill:   Option<(PathBuf, BufWriter<File>)>,
+}
+
+impl OutputLog {
+    /// Keeps up to `limit` bytes of output, and writes all of it to `spill`
+    /// if given
+    pub(crate) fn new(limit: usize, spill: Option<PathBuf>) -> Self {
+        let spill = spill.and_then(|path| match File::create(&path) {
+            Ok(file) => Some((path, BufWriter::new(file))),
+            Err(e) => {
+                warn!("Failed to create {}: {e}", path.display());
+                None
+            },
+        });
+        Self {
+            lines: VecDeque::new(),
+            len: 0,
+            limit,
+            dropped: 0,
+            spill,
+        }
+    }
+
+    /// Appends a line of output, without its line ending
+    pub(crate) fn push(&mut self, line: &str) {
+        if let Some((path, file)) = &mut self.spill
+            && let Err(e) = writeln!(file, "{line}")
+        {
+            warn!("Failed to write to {}: {e}", path.display());
+            self.spill = None;
+        }
+
+        self.len += line.len() + 1;
+        self.lines.push_back(line.to_string());
+        while self.len > self.limit {
+            let remaining = self.lines.len();
+            let Some(front) = self.lines.front_mut() else {
+                break;
+            };
+            let excess = self.len - self.limit;
+            if remaining == 1 && front.len() + 1 > excess {
+                // a single line over the limit keeps its end
+                let mut cut = excess;
+                while !front.is_char_boundary(cut) {
+                    cut += 1;
+                }
+                front.drain(..cut);
+                self.len -= cut;
+                self.dropped += cut;
+                break;
+            }
+            let front = self.lines.pop_front().expect("line should exist");
+            self.len -= front.len() + 1;
+            self.dropped += front.len() + 1;
+        }
+    }
+
+    /// Returns the kept output, noting how much was dropped before it, and
+    /// flushes the file the complete output is written to
+    pub(crate) fn finish(&mut self) -> String {
+        let mut output = String::with_capacity(self.len + 128);
+        if self.dropped > 0 {
+            let _ = write!(output, "[{} bytes of earlier output dropped", self.dropped);
+            if let Some((path, _)) = &self.spill {
+                let _ = write!(output, ", see {}", path.display());
+            }
+            output.push_str("]\n");
+        }
+        for line in &self.lines {
+            output.push_str(line);
+            output.push('\n');
+        }
+        if let Some((path, file)) = &mut self.spill
+            && let Err(e) = file.flush()
+        {
+            warn!("Failed to write to {}: {e}", path.display());
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_most_recent_output() {
+        let mut log = OutputLog::new(14, None);
+        log.push("first");
+        log.push("second");
+        assert_eq!(log.finish(), "first\nsecond\n");
+
+        log.push("third");
+        assert_eq!(
+            log.finish(),
+            "[6 bytes of earlier output dropped]\nsecond\nthird\n"
+        );
+
+        let mut log = OutputLog::new(4, None);
+        log.push("a very long line");
+        assert_eq!(log.finish(), "[13 bytes of earlier output dropped]\nine\n");
+    }
+}
@@ -9,6 +9,8 @@ mod tests;
 
 use std::{borrow::Cow, collections::HashSet};
 
+use serde::{Deserialize, Serialize};
+
 use crate::encoder::Encoder;
 
 // We can safely always ignore this prefix, as the second number will
@@ -253,6 +255,190 @@ pub fn parse_x26x_frames(s: &str) -> Option<u64> {
         .and_then(|s| s.parse().ok())
 }
 
+/// Parses the progress FFmpeg prints with `-stats`, e.g.
+/// `frame=  120 fps= 60 q=30.0 size=     256KiB time=00:00:05.00`
+pub fn parse_ffmpeg_frames(s: &str) -> Option<u64> {
+    if !s.trim_start().starts_with("frame=") {
+        return None;
+    }
+
+    ffmpeg_stats(s)
+        .find(|(key, _)| *key == "frame")
+        .and_then(|(_, frames)| frames.parse().ok())
+}
+
+/// Returns the keys and values of a statistics line of FFmpeg, whose values
+/// are padded with spaces after the equal sign
+fn ffmpeg_stats(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    let parts: Vec<&str> = line.split('=').collect();
+    (1..parts.len()).filter_map(move |index| {
+        Some((
+            parts[index - 1].split_ascii_whitespace().next_back()?,
+            parts[index].split_ascii_whitespace().next()?,
+        ))
+    })
+}
+
+/// Statistics an encoder prints at the end of an encode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct EncoderSummary {
+    /// Average bitrate in kilobits per second
+    pub bitrate_kbps: Option<f64>,
+    /// Encoding speed in frames per second
+    pub fps:          Option<f64>,
+    /// Size of the encoded output in bytes
+    pub size_bytes:   Option<u64>,
+}
+
+impl EncoderSummary {
+    /// Describe the statistics that were parsed, e.g. `, encoder reported
+    /// 46.74 fps at 358.46 kb/s`, or an empty string if none were
+    #[inline]
+    #[must_use]
+    pub fn describe(&self) -> String {
+        let stats: Vec<String> = [
+            self.fps.map(|fps| format!("{fps:.2} fps")),
+            self.bitrate_kbps.map(|bitrate| format!("{bitrate:.2} kb/s")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if stats.is_empty() {
+            String::new()
+        } else {
+            format!(", encoder reported {}", stats.join(" at "))
+        }
+    }
+}
+
+/// Returns the output lines of an encoder, which may be separated by either
+/// carriage returns or newlines
+fn output_lines(output: &str) -> impl DoubleEndedIterator<Item = &str> {
+    output.split(['\r', '\n']).map(str::trim).filter(|line| !line.is_empty())
+}
+
+pub fn parse_aom_vpx_summary(output: &str) -> EncoderSummary {
+    // Pass 2/2 frame  240/240   430154B   14338b/f  358461b/s 5134785 us (46.74
+    // fps)
+    let Some(line) = output_lines(output).rev().find(|line| line.starts_with("Pass ")) else {
+        return EncoderSummary::default();
+    };
+
+    let mut summary = EncoderSummary::default();
+    let mut prev = None;
+    for token in line.split_ascii_whitespace() {
+        if let Some(bytes) = token.strip_suffix('B') {
+            summary.size_bytes = bytes.parse().ok().or(summary.size_bytes);
+        } else if let Some(bits) = token.strip_suffix("b/s") {
+            summary.bitrate_kbps =
+                bits.parse::<f64>().ok().map(|bits| bits / 1000.0).or(summary.bitrate_kbps);
+        } else if token.starts_with("fps")
+            && let Some(fps) = prev
+        {
+            summary.fps = parse_float(fps).or(summary.fps);
+        }
+        prev = Some(token);
+    }
+    summary
+}
+
+/// Parses the summary line printed by rav1e, x264 and x265:
+///
+/// ```text
+/// encoded 240 frames, 126.416 fps, 16.32 Kb/s, elap. time: 1m 36s
+/// encoded 240 frames, 45.12 fps, 1234.56 kb/s
+/// encoded 240 frames in 5.32s (45.11 fps), 1234.56 kb/s, Avg QP:30.12
+/// ```
+pub fn parse_encoded_summary(output: &str) -> EncoderSummary {
+    let Some(line) = output_lines(output).rev().find(|line| {
+        line.strip_prefix("encoded ")
+            .and_then(|rest| rest.split_ascii_whitespace().nth(1))
+            .is_some_and(|word| word.starts_with("frames"))
+    }) else {
+        return EncoderSummary::default();
+    };
+
+    let mut summary = EncoderSummary::default();
+    let mut prev = None;
+    for token in line.split_ascii_whitespace() {
+        if let Some(value) = prev {
+            if token.starts_with("fps") {
+                summary.fps = parse_float(value);
+            } else if token.to_ascii_lowercase().starts_with("kb/s") {
+                summary.bitrate_kbps = parse_float(value);
+            }
+        }
+        prev = Some(token);
+    }
+    summary
+}
+
+pub fn parse_svt_av1_summary(output: &str) -> EncoderSummary {
+    // SUMMARY --------------------------------- Channel 1
+    // -------------------------------- Total Frames		Frame Rate		Byte Count
+    // Bitrate          240		24.00 fps		     123456		   98.76 kbps
+    // ...
+    // Average Speed:		19.456 fps
+    let stripped = strip_ansi_escape_sequences(output);
+    let lines: Vec<&str> = output_lines(&stripped).collect();
+
+    let mut summary = EncoderSummary::default();
+    if let Some(index) = lines
+        .iter()
+        .position(|line| line.starts_with("Total Frames") && line.contains("Byte Count"))
+        && let Some(values) = lines.get(index + 1)
+    {
+        let values: Vec<&str> = values.split_ascii_whitespace().collect();
+        if let [_, _, "fps", bytes, bitrate, "kbps", ..] = values.as_slice() {
+            summary.size_bytes = bytes.parse().ok();
+            summary.bitrate_kbps = bitrate.parse().ok();
+        }
+    }
+    summary.fps = lines
+        .iter()
+        .rev()
+        .find_map(|line| line.strip_prefix("Average Speed:"))
+        .and_then(|speed| speed.split_ascii_whitespace().next())
+        .and_then(parse_float);
+    summary
+}
+
+/// Parses the last statistics line printed by FFmpeg:
+///
+/// ```text
+/// frame=  240 fps=120 q=-0.0 Lsize=    1234KiB time=00:00:10.00 bitrate=1010.9kbits/s speed=5.01x
+/// ```
+pub fn parse_ffmpeg_summary(output: &str) -> EncoderSummary {
+    let Some(line) = output_lines(output).rev().find(|line| line.starts_with("frame=")) else {
+        return EncoderSummary::default();
+    };
+
+    let mut summary = EncoderSummary::default();
+    for (key, value) in ffmpeg_stats(line) {
+        match key {
+            "fps" => summary.fps = parse_float(value),
+            "bitrate" => {
+                summary.bitrate_kbps = value.strip_suffix("kbits/s").and_then(parse_float);
+            },
+            // older versions of FFmpeg print kibibytes as kB
+            "size" | "Lsize" => {
+                summary.size_bytes = value
+                    .strip_suffix("KiB")
+                    .or_else(|| value.strip_suffix("kB"))
+                    .and_then(|kib| kib.parse::<u64>().ok())
+                    .map(|kib| kib * 1024);
+            },
+            _ => (),
+        }
+    }
+    summary
+}
+
+/// Parses a number such as `45.11` or `(45.11`
+fn parse_float(value: &str) -> Option<f64> {
+    value.trim_matches(|c: char| !c.is_ascii_digit() && c != '.').parse().ok()
+}
+
 fn strip_ansi_escape_sequences(input: &str) -> Cow<'_, str> {
     const ESC: char = '\x1b';
 
@@ -189,3 +189,64 @@ fn aom_vpx_parsing() {
         assert_eq!(parse_aom_vpx_frames(s), ans);
     }
 }
+
+#[test]
+fn encoder_summary_parsing() {
+    assert_eq!(
+        parse_aom_vpx_summary(
+            "Pass 2/2 frame  239/238   421002B\rPass 2/2 frame  240/240   430154B   14338b/f  \
+             358461b/s 5134785 us (46.74 fps)\n"
+        ),
+        EncoderSummary {
+            bitrate_kbps: Some(358.461),
+            fps:          Some(46.74),
+            size_bytes:   Some(430_154),
+        }
+    );
+    assert_eq!(
+        parse_encoded_summary("encoded 240 frames, 126.416 fps, 16.32 Kb/s, elap. time: 1m 36s"),
+        EncoderSummary {
+            bitrate_kbps: Some(16.32),
+            fps:          Some(126.416),
+            size_bytes:   None,
+        }
+    );
+    assert_eq!(
+        parse_encoded_summary(
+            "x265 [info]: frame I: 2\nencoded 240 frames in 5.32s (45.11 fps), 1234.56 kb/s, Avg \
+             QP:30.12"
+        ),
+        EncoderSummary {
+            bitrate_kbps: Some(1234.56),
+            fps:          Some(45.11),
+            size_bytes:   None,
+        }
+    );
+    assert_eq!(
+        parse_svt_av1_summary(
+            "SUMMARY --------------------------------- Channel 1  \
+             --------------------------------\nTotal Frames\t\tFrame Rate\t\tByte \
+             Count\t\tBitrate\n         240\t\t24.00 fps\t\t     123456\t\t   98.76 \
+             kbps\n\nAverage Speed:\t\t19.456 fps\n"
+        ),
+        EncoderSummary {
+            bitrate_kbps: Some(98.76),
+            fps:          Some(19.456),
+            size_bytes:   Some(123_456),
+        }
+    );
+    assert_eq!(
+        parse_ffmpeg_summary(
+            "frame=  120 fps= 60 q=30.0 size=     256KiB time=00:00:05.00 bitrate= 419.4kbits/s \
+             speed=2.5x    \rframe=  240 fps=120 q=-0.0 Lsize=    1234KiB time=00:00:10.00 \
+             bitrate=1010.9kbits/s speed=5.01x    \n"
+        ),
+        EncoderSummary {
+            bitrate_kbps: Some(1010.9),
+            fps:          Some(120.0),
+            size_bytes:   Some(1234 * 1024),
+        }
+    );
+    assert_eq!(parse_encoded_summary("garbage"), EncoderSummary::default());
+    assert_eq!(EncoderSummary::default().describe(), "");
+}
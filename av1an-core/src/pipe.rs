@@ -0,0 +1,183 @@
+//! Transport of the frames of a chunk from the source to the encoder.
+//!
+//! The source, FFmpeg and the encoder are connected by pipes between the
+//! processes, so Av1an never copies the frames itself. Linux gives each pipe
+//! 64 KiB by default, which is a small part of a 4K frame, so the processes
+//! switch for every few rows of a frame. A larger pipe lets them hand over
+//! whole frames.
+//!
+//! With the `memfd` feature on Linux, the frames of a chunk can instead be
+//! written to a file in memory, created with `memfd_create`, that the encoder
+//! reads once the source has written all of them. The processes never wait
+//! on each other, at the cost of keeping the whole chunk in memory.
+
+#[cfg(all(target_os = "linux", feature = "memfd"))]
+use std::ffi::CString;
+#[cfg(all(target_os = "linux", feature = "memfd"))]
+use std::os::fd::{FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::{
+    fs,
+    os::fd::{AsFd, AsRawFd, BorrowedFd},
+};
+use std::{
+    fs::File,
+    io::{self, Seek},
+    process::Stdio,
+};
+
+use tracing::debug;
+
+use crate::ffmpeg::FFPixelFormat;
+
+/// Largest capacity an unprivileged process may give a pipe, set in
+/// `/proc/sys/fs/pipe-max-size`
+#[cfg(target_os = "linux")]
+pub(crate) fn max_pipe_size() -> Option<usize> {
+    fs::read_to_string("/proc/sys/fs/pipe-max-size").ok()?.trim().parse().ok()
+}
+
+/// Sets the capacity of `pipe` to at least `size` bytes, which Linux rounds
+/// up to a power of two number of pages. Returns the new capacity.
+#[cfg(target_os = "linux")]
+fn set_pipe_size(pipe: BorrowedFd, size: usize) -> io::Result<usize> {
+    let size =
+        libc::c_int::try_from(size).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // SAFETY: the descriptor stays open while it is borrowed, and
+    // F_SETPIPE_SZ takes a single int argument
+    let capacity = unsafe { libc::fcntl(pipe.as_raw_fd(), libc::F_SETPIPE_SZ, size) };
+    usize::try_from(capacity).map_err(|_| io::Error::last_os_error())
+}
+
+/// Sets the capacity of `pipe`, the `name` pipe of a chunk, to `size` bytes,
+/// if set. Keeps the default capacity if it cannot be changed.
+#[cfg(target_os = "linux")]
+pub(crate) fn resize(pipe: &impl AsFd, size: Option<usize>, name: &str) {
+    let Some(size) = size else {
+        return;
+    };
+    match set_pipe_size(pipe.as_fd(), size) {
+        Ok(capacity) => debug!("resized the {name} pipe to {capacity} bytes"),
+        Err(e) => debug!("failed to resize the {name} pipe to {size} bytes: {e}"),
+    }
+}
+
+/// Pipes keep their default capacity on other systems
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn resize<T>(_pipe: &T, size: Option<usize>, name: &str) {
+    if let Some(size) = size {
+        debug!("cannot resize the {name} pipe to {size} bytes on this system");
+    }
+}
+
+/// Bytes of a raw frame of `format` at `resolution`, as it is piped to the
+/// encoder
+#[inline]
+pub(crate) fn frame_bytes(
+    resolution: (u32, u32),
+    format: FFPixelFormat,
+    bit_depth: usize,
+) -> usize {
+    let (width, height) = (resolution.0 as usize, resolution.1 as usize);
+    let (sub_w, sub_h) = format.chroma_subsampling();
+    let chroma = width.div_ceil(sub_w as usize) * height.div_ceil(sub_h as usize);
+    (width * height + 2 * chroma) * bit_depth.div_ceil(8)
+}
+
+/// File in memory that the frames of a chunk are written to before the
+/// encoder reads them
+#[derive(Debug)]
+pub(crate) struct FrameBuffer(File);
+
+impl FrameBuffer {
+    /// Creates the buffer of a chunk named `name` if its frames take at most
+    /// `limit` bytes. Chunks without a buffer are piped to the encoder.
+    #[cfg(all(target_os = "linux", feature = "memfd"))]
+    pub(crate) fn new(name: &str, bytes: usize, limit: Option<usize>) -> io::Result<Option<Self>> {
+        if limit.is_none_or(|limit| bytes > limit) {
+            return Ok(None);
+        }
+        let name = CString::new(format!("av1an-{name}")).map_err(io::Error::other)?;
+        // SAFETY: the name is a valid C string, and the descriptor is owned
+        // by nothing else
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: memfd_create returned a new open descriptor
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        debug!("buffering the {bytes} bytes of the frames of chunk {name:?} in memory");
+        Ok(Some(Self(file)))
+    }
+
+    /// Frames are always piped without the `memfd` feature on Linux
+    #[cfg(not(all(target_os = "linux", feature = "memfd")))]
+    pub(crate) fn new(
+        _name: &str,
+        _bytes: usize,
+        _limit: Option<usize>,
+    ) -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// Output of the last process before the encoder, which writes every
+    /// frame to the buffer
+    pub(crate) fn writer(&self) -> io::Result<Stdio> {
+        Ok(self.0.try_clone()?.into())
+    }
+
+    /// Input of the encoder, which reads the frames from the start of the
+    /// buffer. Only valid once the writer has exited.
+    pub(crate) fn into_reader(mut self) -> io::Result<Stdio> {
+        self.0.rewind()?;
+        Ok(self.0.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_bytes_of_formats() {
+        assert_eq!(
+            frame_bytes((3840, 2160), FFPixelFormat::YUV420P10LE, 10),
+            3840 * 2160 * 3
+        );
+        assert_eq!(
+            frame_bytes((1920, 1080), FFPixelFormat::YUV420P, 8),
+            1920 * 1080 * 3 / 2
+        );
+        assert_eq!(
+            frame_bytes((1920, 1080), FFPixelFormat::YUV444P, 8),
+            1920 * 1080 * 3
+        );
+        // odd sizes round the chroma planes up
+        assert_eq!(frame_bytes((3, 3), FFPixelFormat::YUV420P, 8), 9 + 2 * 4);
+    }
+
+    #[cfg(all(target_os = "linux", feature = "memfd"))]
+    #[test]
+    fn buffered_frames_are_read_from_the_start() {
+        use std::process::Command;
+
+        assert!(FrameBuffer::new("00000", 16, None).expect("should check the limit").is_none());
+        assert!(FrameBuffer::new("00000", 16, Some(8))
+            .expect("should check the limit")
+            .is_none());
+        let buffer = FrameBuffer::new("00000", 16, Some(16))
+            .expect("should create the buffer")
+            .expect("the frames should fit");
+        let status = Command::new("printf")
+            .arg("frames")
+            .stdout(buffer.writer().expect("should clone the buffer"))
+            .status()
+            .expect("should run printf");
+        assert!(status.success());
+        let output = Command::new("cat")
+            .stdin(buffer.into_reader().expect("should rewind the buffer"))
+            .output()
+            .expect("should run cat");
+        assert_eq!(output.stdout, b"frames");
+    }
+}
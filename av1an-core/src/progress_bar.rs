@@ -1,4 +1,14 @@
-use std::{fmt::Write, time::Duration};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io::IsTerminal,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
 
 use indicatif::{
     HumanBytes,
@@ -26,8 +36,17 @@ const INDICATIF_PROGRESS_TEMPLATE: &str = "{elapsed_precise:.bold} \
 const INDICATIF_SC_SPINNER_TEMPLATE: &str =
     "{elapsed_precise:.bold} [{wide_bar:.blue/white.dim}]  {pos} frames ({fps:.bold})";
 
+/// Default interval between plain progress lines when stderr is not a
+/// terminal
+const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
 static PROGRESS_BAR: OnceCell<ProgressBar> = OnceCell::new();
 static AUDIO_BYTES: OnceCell<u64> = OnceCell::new();
+static PROGRESS_INTERVAL: OnceCell<Duration> = OnceCell::new();
+static PLAIN_PROGRESS: OnceCell<()> = OnceCell::new();
+/// Frames that were already encoded before resuming, which do not count
+/// towards the fps printed in plain progress lines
+static RESUME_FRAMES: AtomicU64 = AtomicU64::new(0);
 
 pub fn set_audio_size(val: u64) {
     AUDIO_BYTES.get_or_init(|| val);
@@ -37,17 +56,29 @@ pub fn get_audio_size() -> u64 {
     *AUDIO_BYTES.get().unwrap_or(&0u64)
 }
 
+/// Set how often progress is printed as a plain line when stderr is not a
+/// terminal. Must be called before a progress bar is initialized.
+#[inline]
+pub fn set_progress_interval(interval: Duration) {
+    PROGRESS_INTERVAL.get_or_init(|| interval);
+}
+
+/// Frames encoded since the encode was started or resumed
+fn resumed_frames(pos: u64, resume_frames: u64) -> u64 {
+    if pos < resume_frames {
+        resume_frames
+    } else {
+        pos - resume_frames
+    }
+}
+
 #[allow(clippy::unwrap_used, reason = "many unwraps on `write!` to terminal")]
 fn pretty_progress_style(resume_frames: u64) -> ProgressStyle {
     ProgressStyle::default_bar()
         .template(INDICATIF_PROGRESS_TEMPLATE)
         .expect("template is valid")
         .with_key("fps", move |state: &ProgressState, w: &mut dyn Write| {
-            let resume_pos = if state.pos() < resume_frames {
-                resume_frames
-            } else {
-                state.pos() - resume_frames
-            };
+            let resume_pos = resumed_frames(state.pos(), resume_frames);
             if resume_pos == 0 || state.elapsed().as_secs_f32() < f32::EPSILON {
                 write!(w, "0 fps").unwrap();
             } else {
@@ -62,16 +93,12 @@ fn pretty_progress_style(resume_frames: u64) -> ProgressStyle {
         .with_key(
             "fixed_eta",
             move |state: &ProgressState, w: &mut dyn Write| {
-                let resume_pos = if state.pos() < resume_frames {
-                    resume_frames
-                } else {
-                    state.pos() - resume_frames
-                };
+                let resume_pos = resumed_frames(state.pos(), resume_frames);
                 if resume_pos == 0 || state.elapsed().as_secs_f32() < f32::EPSILON {
                     write!(w, "unknown").unwrap();
                 } else {
                     let spf = state.elapsed().as_secs_f32() / resume_pos as f32;
-                    let remaining = state.len().unwrap_or(0) - state.pos();
+                    let remaining = state.len().unwrap_or(0).saturating_sub(state.pos());
                     write!(
                         w,
                         "{:#}",
@@ -96,11 +123,7 @@ fn spinner_style(resume_frames: u64) -> ProgressStyle {
         .template(INDICATIF_SC_SPINNER_TEMPLATE)
         .expect("template is valid")
         .with_key("fps", move |state: &ProgressState, w: &mut dyn Write| {
-            let resume_pos = if state.pos() < resume_frames {
-                resume_frames
-            } else {
-                state.pos() - resume_frames
-            };
+            let resume_pos = resumed_frames(state.pos(), resume_frames);
             if resume_pos == 0 || state.elapsed().as_secs_f32() < f32::EPSILON {
                 write!(w, "0 fps").unwrap();
             } else {
@@ -118,6 +141,86 @@ fn spinner_style(resume_frames: u64) -> ProgressStyle {
         .progress_chars(PROGRESS_CHARS)
 }
 
+/// Returns the progress bar that shows the overall progress, if any
+fn main_progress_bar() -> Option<&'static ProgressBar> {
+    PROGRESS_BAR
+        .get()
+        .or_else(|| MULTI_PROGRESS_BAR.get().and_then(|(_, pbs)| pbs.last()))
+}
+
+/// Describe the progress of `pb` in a single line without any control
+/// sequences, e.g. `[3/10 Chunks] 1200/5000 frames (24%), 12.00 fps, eta 5m`
+fn plain_progress_line(pb: &ProgressBar, resume_frames: u64) -> String {
+    let pos = pb.position();
+    let len = pb.length().unwrap_or(0);
+    let elapsed = pb.elapsed().as_secs_f32();
+    let resume_pos = resumed_frames(pos, resume_frames);
+
+    let mut line = String::new();
+    let prefix = pb.prefix();
+    if !prefix.trim().is_empty() {
+        line.push_str(prefix.trim());
+        line.push(' ');
+    }
+    if len > 0 {
+        let percent = pos as f32 / len as f32 * 100_f32;
+        let _ = write!(line, "{pos}/{len} frames ({percent:.0}%)");
+    } else {
+        let _ = write!(line, "{pos} frames");
+    }
+
+    if resume_pos == 0 || elapsed < f32::EPSILON {
+        line.push_str(", 0 fps");
+        if len > 0 {
+            line.push_str(", eta unknown");
+        }
+    } else {
+        let fps = resume_pos as f32 / elapsed;
+        if fps < 1.0 {
+            let _ = write!(line, ", {:.2} s/fr", 1.0 / fps);
+        } else {
+            let _ = write!(line, ", {fps:.2} fps");
+        }
+        if len > 0 {
+            let remaining = len.saturating_sub(pos);
+            let _ = write!(
+                line,
+                ", eta {:#}",
+                HumanDuration(Duration::from_secs_f32(remaining as f32 / fps))
+            );
+        }
+    }
+    line.push_str(&pb.message());
+    line
+}
+
+/// Returns where progress bars are drawn. When stderr is not a terminal, e.g.
+/// in CI or when redirected to a file, the bars are hidden and the progress
+/// is instead printed as a plain line periodically.
+fn progress_draw_target() -> ProgressDrawTarget {
+    if std::io::stderr().is_terminal() {
+        return ProgressDrawTarget::stderr();
+    }
+
+    PLAIN_PROGRESS.get_or_init(|| {
+        let interval = *PROGRESS_INTERVAL.get_or_init(|| DEFAULT_PROGRESS_INTERVAL);
+        thread::spawn(move || -> ! {
+            loop {
+                thread::sleep(interval);
+                if let Some(pb) = main_progress_bar()
+                    && !pb.is_finished()
+                {
+                    eprintln!(
+                        "{}",
+                        plain_progress_line(pb, RESUME_FRAMES.load(Ordering::Relaxed))
+                    );
+                }
+            }
+        });
+    });
+    ProgressDrawTarget::hidden()
+}
+
 /// Initialize progress bar
 /// Enables steady 100 ms tick
 pub fn init_progress_bar(len: u64, resume_frames: u64, chunks: Option<(u32, u32)>) {
@@ -129,7 +232,8 @@ pub fn init_progress_bar(len: u64, resume_frames: u64, chunks: Option<(u32, u32)
         // Affects scenechange progress.
         PROGRESS_BAR.get_or_init(|| ProgressBar::new(len).with_style(spinner_style(resume_frames)))
     };
-    pb.set_draw_target(ProgressDrawTarget::stderr());
+    RESUME_FRAMES.store(resume_frames, Ordering::Relaxed);
+    pb.set_draw_target(progress_draw_target());
     pb.enable_steady_tick(Duration::from_millis(100));
     pb.reset();
     pb.reset_eta();
@@ -141,28 +245,12 @@ pub fn init_progress_bar(len: u64, resume_frames: u64, chunks: Option<(u32, u32)
 }
 
 pub fn convert_to_progress(resume_frames: u64) {
+    RESUME_FRAMES.store(resume_frames, Ordering::Relaxed);
     if let Some(pb) = PROGRESS_BAR.get() {
         pb.set_style(pretty_progress_style(resume_frames));
     }
 }
 
-pub fn inc_bar(inc: u64) {
-    if let Some(pb) = PROGRESS_BAR.get() {
-        pb.inc(inc);
-    }
-}
-
-pub fn dec_bar(dec: u64) {
-    if let Some(pb) = PROGRESS_BAR.get() {
-        pb.set_position(pb.position().saturating_sub(dec));
-    }
-
-    if let Some((_, pbs)) = MULTI_PROGRESS_BAR.get() {
-        let pb = pbs.last().expect("at least one progress bar exists");
-        pb.set_position(pb.position().saturating_sub(dec));
-    }
-}
-
 #[expect(
     clippy::needless_pass_by_value,
     reason = "https://github.com/rust-lang/rust-clippy/issues/12786"
@@ -183,6 +271,17 @@ pub fn set_pos(pos: u64) {
 }
 
 pub fn finish_progress_bar() {
+    // print the final state, as the last periodic line may be outdated
+    if PLAIN_PROGRESS.get().is_some()
+        && let Some(pb) = main_progress_bar()
+        && !pb.is_finished()
+    {
+        eprintln!(
+            "{}",
+            plain_progress_line(pb, RESUME_FRAMES.load(Ordering::Relaxed))
+        );
+    }
+
     if let Some(pb) = PROGRESS_BAR.get() {
         pb.finish();
     }
@@ -191,10 +290,15 @@ pub fn finish_progress_bar() {
         for pb in pbs {
             pb.finish();
         }
+        for pb in ADDED_WORKER_BARS.lock().expect("mutex should acquire lock").values() {
+            pb.finish();
+        }
     }
 }
 
 static MULTI_PROGRESS_BAR: OnceCell<(MultiProgress, Vec<ProgressBar>)> = OnceCell::new();
+/// Bars of the workers started while encoding, by worker index
+static ADDED_WORKER_BARS: Mutex<BTreeMap<usize, ProgressBar>> = Mutex::new(BTreeMap::new());
 
 pub fn set_len(len: u64) {
     let pb = PROGRESS_BAR.get().expect("progress bar exists");
@@ -230,13 +334,7 @@ pub fn init_multi_progress_bar(len: u64, workers: usize, resume_frames: u64, chu
         let digits = printable_base10_digits(chunks.1 as usize) as usize;
 
         for _ in 1..=workers {
-            let pb = ProgressBar::hidden().with_style(
-                ProgressStyle::default_spinner()
-                    .template("{prefix:.dim} {msg}")
-                    .expect("template is valid"),
-            );
-            pb.set_prefix(format!("[Idle  {digits:digits$}]"));
-            pbs.push(mpb.add(pb));
+            pbs.push(mpb.add(worker_progress_bar(digits)));
         }
 
         let pb = ProgressBar::hidden();
@@ -254,27 +352,135 @@ pub fn init_multi_progress_bar(len: u64, workers: usize, resume_frames: u64, chu
         ));
         pbs.push(mpb.add(pb));
 
-        mpb.set_draw_target(ProgressDrawTarget::stderr());
+        RESUME_FRAMES.store(resume_frames, Ordering::Relaxed);
+        mpb.set_draw_target(progress_draw_target());
 
         (mpb, pbs)
     });
 }
 
+fn worker_progress_bar(digits: usize) -> ProgressBar {
+    let pb = ProgressBar::hidden().with_style(
+        ProgressStyle::default_spinner()
+            .template("{prefix:.dim} {msg}")
+            .expect("template is valid"),
+    );
+    pb.set_prefix(format!("[Idle  {digits:digits$}]"));
+    pb
+}
+
+/// Adds a bar for a worker started while encoding, above the bar of the
+/// overall progress
+pub fn add_mp_worker_bar(worker_idx: usize, chunks: u32) {
+    if let Some((mpb, pbs)) = MULTI_PROGRESS_BAR.get() {
+        let digits = printable_base10_digits(chunks as usize) as usize;
+        let pb = mpb.insert_before(
+            pbs.last().expect("at least one progress bar exists"),
+            worker_progress_bar(digits),
+        );
+        ADDED_WORKER_BARS
+            .lock()
+            .expect("mutex should acquire lock")
+            .insert(worker_idx, pb);
+    }
+}
+
+/// Returns the bar of `worker_idx`, which may have been added while encoding
+fn worker_bar(worker_idx: usize) -> Option<ProgressBar> {
+    let (_, pbs) = MULTI_PROGRESS_BAR.get()?;
+    // the last bar shows the overall progress
+    if worker_idx + 1 < pbs.len() {
+        return Some(pbs[worker_idx].clone());
+    }
+    ADDED_WORKER_BARS
+        .lock()
+        .expect("mutex should acquire lock")
+        .get(&worker_idx)
+        .cloned()
+}
+
 pub fn update_mp_chunk(worker_idx: usize, chunk: usize, padding: usize) {
-    if let Some((_, pbs)) = MULTI_PROGRESS_BAR.get() {
-        pbs[worker_idx].set_prefix(format!("[Chunk {chunk:>padding$}]"));
+    if let Some(pb) = worker_bar(worker_idx) {
+        pb.set_prefix(format!("[Chunk {chunk:>padding$}]"));
     }
 }
 
 pub fn update_mp_msg(worker_idx: usize, msg: String) {
-    if let Some((_, pbs)) = MULTI_PROGRESS_BAR.get() {
-        pbs[worker_idx].set_message(msg);
+    if let Some(pb) = worker_bar(worker_idx) {
+        pb.set_message(msg);
     }
 }
 
-pub fn inc_mp_bar(inc: u64) {
-    if let Some((_, pbs)) = MULTI_PROGRESS_BAR.get() {
-        pbs.last().expect("at least one progress bar exists").inc(inc);
+/// Frames of a chunk counted on the bar of the overall progress while the
+/// chunk is encoded.
+///
+/// Unless the chunk is committed once it is done, its frames are taken off
+/// the bar again when this is dropped, so that a chunk whose encoder
+/// crashed, whose output failed a check or whose worker gave up on it is
+/// counted from the start when it is encoded again.
+#[derive(Debug)]
+pub struct ChunkProgress {
+    /// Bar of the overall progress, `None` if there is none
+    bar:       Option<ProgressBar>,
+    frames:    AtomicU64,
+    committed: bool,
+}
+
+impl ChunkProgress {
+    /// Counts the frames of a chunk on the bar of the overall progress for
+    /// `verbosity`
+    pub fn new(verbosity: Verbosity) -> Self {
+        Self::with_bar(match verbosity {
+            Verbosity::Normal => PROGRESS_BAR.get().cloned(),
+            Verbosity::Verbose => MULTI_PROGRESS_BAR.get().and_then(|(_, pbs)| pbs.last().cloned()),
+            Verbosity::Quiet => None,
+        })
+    }
+
+    fn with_bar(bar: Option<ProgressBar>) -> Self {
+        Self {
+            bar,
+            frames: AtomicU64::new(0),
+            committed: false,
+        }
+    }
+
+    /// Counts `frames` more encoded frames of the chunk
+    pub fn inc(&self, frames: u64) {
+        self.frames.fetch_add(frames, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.inc(frames);
+        }
+    }
+
+    /// Takes the frames counted so far off the bar, before the chunk is
+    /// encoded again
+    pub fn rollback(&self) {
+        let frames = self.frames.swap(0, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.dec(frames);
+        }
+    }
+
+    /// Keeps the chunk on the bar as done with `frames` frames, which
+    /// corrects the count for frames the encoder did not report
+    pub fn commit(mut self, frames: u64) {
+        let counted = self.frames.swap(frames, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            // other workers move the bar at the same time, so only ever
+            // change it by a difference
+            bar.inc(frames);
+            bar.dec(counted);
+        }
+        self.committed = true;
+    }
+}
+
+impl Drop for ChunkProgress {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.rollback();
+        }
     }
 }
 
@@ -325,3 +531,57 @@ pub fn update_progress_bar_estimates(
         update_mp_bar_info(kbps, HumanBytes(est_size as u64), chunks);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_progress_rolls_back_unless_committed() {
+        let pb = ProgressBar::hidden();
+        pb.set_length(1000);
+        pb.set_position(100);
+
+        let crashed = ChunkProgress::with_bar(Some(pb.clone()));
+        let other = ChunkProgress::with_bar(Some(pb.clone()));
+        crashed.inc(40);
+        other.inc(30);
+        assert_eq!(pb.position(), 170);
+
+        // the retry starts over, and the encoder reported 2 frames too few
+        crashed.rollback();
+        assert_eq!(pb.position(), 130);
+        crashed.inc(48);
+        crashed.commit(50);
+        assert_eq!(pb.position(), 180);
+
+        // the worker gave up on the other chunk
+        drop(other);
+        assert_eq!(pb.position(), 150);
+    }
+
+    #[test]
+    fn plain_progress_line_format() {
+        let pb = ProgressBar::hidden();
+        pb.set_length(5000);
+        pb.set_position(1200);
+        pb.set_prefix("[3/10 Chunks] ");
+        pb.set_message(", 1234.5 Kbps, est. 100 MiB");
+        let line = plain_progress_line(&pb, 0);
+        assert!(line.starts_with("[3/10 Chunks] 1200/5000 frames (24%), "));
+        assert!(line.ends_with(", 1234.5 Kbps, est. 100 MiB"));
+        assert!(!line.contains('\x1b'));
+
+        // frames encoded before resuming do not count towards the fps
+        let line = plain_progress_line(&pb, 1200);
+        assert_eq!(
+            line,
+            "[3/10 Chunks] 1200/5000 frames (24%), 0 fps, eta unknown, 1234.5 Kbps, est. 100 MiB"
+        );
+
+        // scene detection does not know the length yet
+        let pb = ProgressBar::hidden();
+        pb.set_position(42);
+        assert_eq!(plain_progress_line(&pb, 42), "42 frames, 0 fps");
+    }
+}
@@ -0,0 +1,167 @@
+//! Inputs given as URLs instead of local paths
+//!
+//! Chunk methods and scene detection seek around in the source and read it
+//! several times, which is slow and error-prone over the network, so remote
+//! inputs are downloaded into a local cache once before chunking. The cache is
+//! keyed by URL and kept between runs, so resuming or re-encoding the same
+//! source does not download it again.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use tracing::info;
+
+use crate::{ffmpeg::input_args, hash_path, util::cache_dir};
+
+/// URL schemes of the inputs that are downloaded into the cache
+const SCHEMES: [&str; 3] = ["http://", "https://", "s3://"];
+/// Extensions of HLS and DASH playlists, which FFmpeg remuxes into a single
+/// file instead
+const PLAYLISTS: [&str; 2] = ["m3u8", "mpd"];
+
+/// Returns whether `path` is a URL of a remote input rather than a local path
+#[inline]
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| {
+        SCHEMES.iter().any(|scheme| {
+            path.get(..scheme.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+        })
+    })
+}
+
+/// Default directory remote inputs are cached in, `~/.cache/av1an/inputs` (or
+/// `%LOCALAPPDATA%\av1an\inputs` on Windows)
+#[inline]
+pub fn default_cache_dir() -> Option<PathBuf> {
+    Some(cache_dir()?.join("inputs"))
+}
+
+/// Where the input at `url` is cached, in a directory of its own so that the
+/// file keeps its name, which is used for the default output name
+fn cache_path(url: &str, cache_dir: &Path) -> PathBuf {
+    let url_path = url.split(['?', '#']).next().unwrap_or(url);
+    let name = url_path
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty() && !name.contains(':'))
+        .unwrap_or("input");
+    let path = cache_dir.join(hash_path(Path::new(url))).join(name);
+    if is_playlist(&path) {
+        path.with_extension("mkv")
+    } else {
+        path
+    }
+}
+
+fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| PLAYLISTS.iter().any(|playlist| ext.eq_ignore_ascii_case(playlist)))
+}
+
+/// Returns the local copy of the input at `url`, downloading it into
+/// `cache_dir` first unless it was downloaded before.
+///
+/// HTTP(S) inputs are downloaded with curl, which continues interrupted
+/// downloads, and S3 inputs with the AWS CLI, which reads the credentials and
+/// endpoint from its usual configuration and environment variables. HLS and
+/// DASH playlists are remuxed into a Matroska file by FFmpeg, which is given
+/// the [input args](crate::ffmpeg::set_input_args) for headers, cookies or
+/// decryption keys.
+#[inline]
+pub fn fetch(url: &Path, cache_dir: &Path) -> anyhow::Result<PathBuf> {
+    let url = url.to_str().context("URL of the input is not valid UTF-8")?;
+    let path = cache_path(url, cache_dir);
+    if path.exists() {
+        info!("using the cached copy of {url} at {}", path.display());
+        return Ok(path);
+    }
+
+    let parent = path.parent().expect("cache path should have a parent");
+    fs::create_dir_all(parent)
+        .with_context(|| format!("Failed to create the input cache {}", parent.display()))?;
+    // downloads only get their final name once they are complete
+    let partial = path.with_extension(format!(
+        "{}part",
+        path.extension()
+            .map_or_else(String::new, |ext| format!("{}.", ext.to_string_lossy()))
+    ));
+
+    info!("downloading {url} to {}", path.display());
+    let url_path = url.split(['?', '#']).next().unwrap_or(url);
+    let mut cmd = if is_playlist(Path::new(url_path)) {
+        let mut cmd = Command::new("ffmpeg");
+        cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+            .args(input_args())
+            .args(["-i", url, "-map", "0", "-c", "copy", "-f", "matroska"])
+            .arg(&partial);
+        cmd
+    } else if url.get(..5).is_some_and(|scheme| scheme.eq_ignore_ascii_case("s3://")) {
+        let mut cmd = Command::new("aws");
+        cmd.args(["s3", "cp", "--no-progress", url]).arg(&partial);
+        cmd
+    } else {
+        let mut cmd = Command::new("curl");
+        cmd.args(["--fail", "--location", "--silent", "--show-error", "--retry", "3"])
+            .args(["--continue-at", "-", "--output"])
+            .arg(&partial)
+            .arg(url);
+        cmd
+    };
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let output =
+        cmd.stdin(Stdio::null()).stdout(Stdio::null()).output().with_context(|| {
+            format!("Failed to run {program} to download {url}, is it installed?")
+        })?;
+    if !output.status.success() {
+        bail!(
+            "Failed to download {url} with {program} ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    fs::rename(&partial, &path)
+        .with_context(|| format!("Failed to move the download to {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_input_paths() {
+        assert!(is_remote(Path::new("https://example.com/media/ep01.mkv")));
+        assert!(is_remote(Path::new("S3://bucket/ep01.mkv")));
+        assert!(!is_remote(Path::new("/media/ep01.mkv")));
+        assert!(!is_remote(Path::new("http.mkv")));
+
+        let cache = Path::new("/cache");
+        let path = cache_path("https://example.com/media/ep01.mkv?token=abc", cache);
+        assert_eq!(
+            path.file_name().and_then(|name| name.to_str()),
+            Some("ep01.mkv")
+        );
+        assert_eq!(path.parent().and_then(Path::parent), Some(cache));
+        assert_ne!(
+            path,
+            cache_path("https://example.com/other/ep01.mkv", cache)
+        );
+        assert_eq!(
+            cache_path("https://example.com/live/master.m3u8?token=abc", cache)
+                .file_name()
+                .and_then(|name| name.to_str()),
+            Some("master.mkv")
+        );
+        assert_eq!(
+            cache_path("https://example.com/", cache)
+                .file_name()
+                .and_then(|name| name.to_str()),
+            Some("input")
+        );
+    }
+}
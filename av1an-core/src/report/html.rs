@@ -0,0 +1,258 @@
+//! Renders the report of an encode as a single HTML page, with the charts
+//! embedded as SVG, so that it can be shared without any other files
+
+use std::fmt::Write as _;
+
+use plotters::prelude::*;
+
+use super::{format_duration, Report};
+use crate::metrics::statistics::MetricStatistics;
+
+/// Size of the charts in pixels
+const CHART_SIZE: (u32, u32) = (960, 320);
+
+/// Number of bars of a score distribution
+const DISTRIBUTION_BINS: usize = 40;
+
+const STYLE: &str = concat!(
+    "body{font-family:sans-serif;max-width:1000px;margin:2em auto;color:#222}",
+    "table{border-collapse:collapse;margin-bottom:1em}",
+    "td,th{border:1px solid #ccc;padding:4px 8px;text-align:left}",
+    "th{background:#f4f4f4}",
+    ".failure{background:#fdd;border:1px solid #c33;padding:1em}",
+);
+
+/// An encoded chunk as shown in the HTML report
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkStats {
+    pub index:      usize,
+    pub frames:     usize,
+    /// Duration of the chunk in seconds
+    pub duration:   f64,
+    pub size_bytes: u64,
+    /// Encoding speed reported by the encoder
+    pub encode_fps: Option<f64>,
+}
+
+impl ChunkStats {
+    fn bitrate_kbps(&self) -> f64 {
+        if self.duration > 0.0 {
+            self.size_bytes as f64 * 8.0 / self.duration / 1000.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Escapes the characters of `text` that have a meaning in HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes a table with a header cell and a value cell per row
+fn write_table<'a>(
+    html: &mut String,
+    rows: impl IntoIterator<Item = (&'a str, String)>,
+) -> std::fmt::Result {
+    html.push_str("<table>");
+    for (name, value) in rows {
+        write!(
+            html,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(name),
+            escape(&value)
+        )?;
+    }
+    html.push_str("</table>");
+    Ok(())
+}
+
+/// Renders the bitrate of every chunk as an SVG bar chart
+fn bitrate_chart(chunks: &[ChunkStats]) -> anyhow::Result<String> {
+    let highest = chunks.iter().map(ChunkStats::bitrate_kbps).fold(0.0, f64::max);
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, CHART_SIZE).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0_u32..chunks.len() as u32, 0.0..(highest * 1.1).max(1.0))?;
+        chart.configure_mesh().x_desc("chunk").y_desc("kb/s").disable_x_mesh().draw()?;
+        chart.draw_series((0_u32..).zip(chunks).map(|(x, chunk)| {
+            Rectangle::new(
+                [(x, 0.0), (x + 1, chunk.bitrate_kbps())],
+                BLUE.mix(0.7).filled(),
+            )
+        }))?;
+        root.present()?;
+    }
+    Ok(svg)
+}
+
+/// Counts `scores` in [`DISTRIBUTION_BINS`] bins between the lowest and the
+/// highest score, returning the lowest score, the width of a bin and the
+/// counts
+fn distribution(scores: &[f64]) -> (f64, f64, Vec<u32>) {
+    let (lowest, highest) = scores.iter().copied().filter(|score| score.is_finite()).fold(
+        (f64::INFINITY, f64::NEG_INFINITY),
+        |(lowest, highest), score| (lowest.min(score), highest.max(score)),
+    );
+    if lowest > highest {
+        return (0.0, 1.0, Vec::new());
+    }
+    let width = ((highest - lowest) / DISTRIBUTION_BINS as f64).max(f64::EPSILON);
+    let mut counts = vec![0; DISTRIBUTION_BINS];
+    for score in scores.iter().filter(|score| score.is_finite()) {
+        let bin = (((score - lowest) / width) as usize).min(DISTRIBUTION_BINS - 1);
+        counts[bin] += 1;
+    }
+    (lowest, width, counts)
+}
+
+/// Renders the distribution of `scores` as an SVG histogram
+fn distribution_chart(name: &str, scores: &[f64]) -> anyhow::Result<String> {
+    let (lowest, width, counts) = distribution(scores);
+    let highest_count = counts.iter().copied().max().unwrap_or(0).max(1);
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, CHART_SIZE).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(60)
+            .build_cartesian_2d(
+                lowest..width.mul_add(counts.len().max(1) as f64, lowest),
+                0..highest_count,
+            )?;
+        chart.configure_mesh().x_desc(name).y_desc("count").disable_x_mesh().draw()?;
+        chart.draw_series((0_u32..).zip(&counts).map(|(bin, &count)| {
+            let start = width.mul_add(f64::from(bin), lowest);
+            Rectangle::new(
+                [(start, 0), (start + width, count)],
+                GREEN.mix(0.7).filled(),
+            )
+        }))?;
+        root.present()?;
+    }
+    Ok(svg)
+}
+
+impl Report {
+    /// Renders the report as a self-contained HTML page titled `title`, with
+    /// the bitrate of the encoded `chunks` and the distribution of `scores`,
+    /// a metric name and its scores, if the encode was scored
+    #[inline]
+    pub fn to_html(
+        &self,
+        title: &str,
+        chunks: &[ChunkStats],
+        scores: Option<(&str, &[f64])>,
+    ) -> anyhow::Result<String> {
+        let mut html = String::new();
+        write!(
+            html,
+            "<!DOCTYPE html><html><head><meta \
+             charset=\"utf-8\"><title>{0}</title><style>{STYLE}</style></head><body><h1>{0}</\
+             h1><p>Encoded with Av1an {1}</p>",
+            escape(title),
+            escape(&self.av1an_version)
+        )?;
+
+        if let Some(failure) = &self.failure {
+            write!(
+                html,
+                "<div class=\"failure\"><strong>The encode failed ({}):</strong> {}</div>",
+                escape(&failure.kind),
+                escape(&failure.message)
+            )?;
+        }
+
+        let frames = chunks.iter().map(|chunk| chunk.frames).sum::<usize>();
+        let duration = chunks.iter().map(|chunk| chunk.duration).sum::<f64>();
+        let size = chunks.iter().map(|chunk| chunk.size_bytes).sum::<u64>();
+        let encoder_time = chunks
+            .iter()
+            .map(|chunk| Some(chunk.frames as f64 / chunk.encode_fps.filter(|fps| *fps > 0.0)?))
+            .sum::<Option<f64>>();
+        html.push_str("<h2>Summary</h2>");
+        write_table(
+            &mut html,
+            [
+                ("Chunks", chunks.len().to_string()),
+                ("Frames", frames.to_string()),
+                ("Duration", format_duration(duration)),
+                ("Size", format!("{:.2} MB", size as f64 / 1_000_000.0)),
+                (
+                    "Average bitrate",
+                    format!(
+                        "{:.2} kb/s",
+                        if duration > 0.0 {
+                            size as f64 * 8.0 / duration / 1000.0
+                        } else {
+                            0.0
+                        }
+                    ),
+                ),
+            ]
+            .into_iter()
+            .chain(encoder_time.map(|time| ("Encoder time of all chunks", format_duration(time)))),
+        )?;
+
+        if !chunks.is_empty() {
+            html.push_str("<h2>Bitrate per chunk</h2>");
+            html.push_str(&bitrate_chart(chunks)?);
+        }
+
+        if let Some((name, scores)) = scores.filter(|(_, scores)| !scores.is_empty()) {
+            let mut statistics = MetricStatistics::new(scores.to_vec());
+            write!(html, "<h2>{} distribution</h2>", escape(name))?;
+            html.push_str(&distribution_chart(name, scores)?);
+            write_table(&mut html, [
+                ("Mean", format!("{:.3}", statistics.mean())),
+                ("Median", format!("{:.3}", statistics.median())),
+                ("5th percentile", format!("{:.3}", statistics.percentile(5))),
+                ("Minimum", format!("{:.3}", statistics.minimum())),
+            ])?;
+        }
+
+        if !self.timings.is_empty() {
+            html.push_str("<h2>Timings</h2>");
+            write_table(
+                &mut html,
+                self.timings.iter().map(|timing| {
+                    let mut duration = format_duration(timing.seconds);
+                    if timing.concurrent {
+                        duration.push_str(" (summed over the workers)");
+                    }
+                    (timing.stage.as_str(), duration)
+                }),
+            )?;
+        }
+
+        html.push_str("<h2>Settings</h2>");
+        write_table(
+            &mut html,
+            self.settings.iter().map(|(name, value)| (name.as_str(), value.clone())),
+        )?;
+
+        html.push_str("<h2>Tools</h2>");
+        write_table(
+            &mut html,
+            self.tools.iter().map(|tool| {
+                (
+                    tool.binary.as_str(),
+                    tool.version.clone().unwrap_or_else(|| "not found".to_string()),
+                )
+            }),
+        )?;
+
+        html.push_str("</body></html>\n");
+        Ok(html)
+    }
+}
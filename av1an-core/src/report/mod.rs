@@ -0,0 +1,280 @@
+mod html;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    process::Command,
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+pub use self::html::ChunkStats;
+use crate::{
+    error::{Av1anError, ReportedError},
+    settings::EncodeArgs,
+};
+
+/// The version of an external binary used by an encode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolVersion {
+    pub binary:  String,
+    /// `None` if the binary was not found or its version could not be parsed
+    pub version: Option<String>,
+}
+
+/// How long a stage of an encode took
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage:      String,
+    pub seconds:    f64,
+    /// Whether the stage ran on the workers alongside the other stages, with
+    /// [`Self::seconds`] summed over the workers
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub concurrent: bool,
+}
+
+/// Formats `seconds` as e.g. `1h 02m 03s`
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes:02}m {seconds:02}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds:02}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// Lines of a table of how long each of the `timings` took, and which part of
+/// the `total` run time that is. Concurrent stages overlap the others, so they
+/// are shown without a share.
+#[inline]
+pub fn timing_breakdown(timings: &[StageTiming], total: Duration) -> Vec<String> {
+    let width = timings.iter().map(|timing| timing.stage.len()).max().unwrap_or(0).max(5);
+    let total = total.as_secs_f64();
+    let mut lines = vec!["time spent per stage:".to_string()];
+    for timing in timings {
+        let share = if timing.concurrent {
+            "summed over the workers".to_string()
+        } else if total > 0.0 {
+            format!("{:.1}%", timing.seconds / total * 100.0)
+        } else {
+            String::new()
+        };
+        lines.push(
+            format!(
+                "  {:<width$}  {:>11}  {share}",
+                timing.stage,
+                format_duration(timing.seconds)
+            )
+            .trim_end()
+            .to_string(),
+        );
+    }
+    lines.push(format!(
+        "  {:<width$}  {:>11}",
+        "total",
+        format_duration(total)
+    ));
+    lines
+}
+
+/// Information about an encode that is written to `report.json` in the
+/// temporary directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub av1an_version: String,
+    pub tools:         Vec<ToolVersion>,
+    /// See [`EncodeArgs::summary`]
+    #[serde(default)]
+    pub settings:      BTreeMap<String, String>,
+    /// Error the encode stopped with, if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure:       Option<ReportedError>,
+    /// Stages of the encode in the order they finished, including those of
+    /// the runs before resuming
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timings:       Vec<StageTiming>,
+}
+
+impl Report {
+    /// Capture the settings of an encode, and the versions of the encoder and
+    /// the other external binaries Av1an invokes
+    #[inline]
+    pub fn new(args: &EncodeArgs) -> Self {
+        let encoder = args.encoder;
+        let tools = vec![
+            ToolVersion {
+                binary:  encoder.bin().to_string(),
+                version: encoder.version_text(),
+            },
+            ToolVersion {
+                binary:  "ffmpeg".to_string(),
+                version: command_output("ffmpeg", "-version")
+                    .as_deref()
+                    .and_then(parse_ffmpeg_version),
+            },
+            ToolVersion {
+                binary:  "vspipe".to_string(),
+                version: command_output("vspipe", "--version")
+                    .as_deref()
+                    .and_then(parse_vspipe_version),
+            },
+            ToolVersion {
+                binary:  "mkvmerge".to_string(),
+                version: command_output("mkvmerge", "--version")
+                    .as_deref()
+                    .and_then(parse_mkvmerge_version),
+            },
+        ];
+
+        Self {
+            av1an_version: env!("CARGO_PKG_VERSION").to_string(),
+            tools,
+            settings: args.summary(),
+            failure: None,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Log the captured versions, so that they end up in the header of the
+    /// log file
+    #[inline]
+    pub fn log_versions(&self) {
+        info!("av1an-core {}", self.av1an_version);
+        for tool in &self.tools {
+            info!(
+                "{}: {}",
+                tool.binary,
+                tool.version.as_deref().unwrap_or("not found")
+            );
+        }
+    }
+
+    /// Warn about every tool whose version differs from `previous`, as chunks
+    /// of a resumed encode may then have been encoded by different builds
+    #[inline]
+    pub fn warn_changed_versions(&self, previous: &Self) {
+        for tool in &self.tools {
+            if let Some(old) = previous.tools.iter().find(|old| old.binary == tool.binary)
+                && old.version != tool.version
+            {
+                warn!(
+                    "{} changed from {} to {} since this encode was started, chunks encoded \
+                     before resuming used the old version",
+                    tool.binary,
+                    old.version.as_deref().unwrap_or("not found"),
+                    tool.version.as_deref().unwrap_or("not found")
+                );
+            }
+        }
+    }
+
+    /// Describe every setting in `settings` that differs from the settings of
+    /// this report, one per line
+    #[inline]
+    pub fn settings_diff(&self, settings: &BTreeMap<String, String>) -> Vec<String> {
+        let keys = self.settings.keys().chain(settings.keys());
+        keys.collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter_map(|key| {
+                let old = self.settings.get(key);
+                let new = settings.get(key);
+                (old != new).then(|| {
+                    format!(
+                        "{key}: {} -> {}",
+                        old.map_or("unset", String::as_str),
+                        new.map_or("unset", String::as_str)
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Read a report previously written to the temporary directory
+    #[inline]
+    pub fn read(temp: &Path) -> anyhow::Result<Self> {
+        let path = temp.join("report.json");
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Record the error an encode stopped with in the report in the temporary
+    /// directory
+    #[inline]
+    pub fn record_failure(temp: &Path, error: &anyhow::Error) {
+        let result = Self::read(temp).and_then(|mut report| {
+            report.failure = Some(Av1anError::report(error));
+            report.write(temp)
+        });
+        if let Err(e) = result {
+            warn!("Failed to record the error in the report: {e:#}");
+        }
+    }
+
+    /// Record how long a stage of the encode took in the report in the
+    /// temporary directory
+    #[inline]
+    pub fn record_timing(temp: &Path, timing: StageTiming) {
+        let stage = timing.stage.clone();
+        let result = Self::read(temp).and_then(|mut report| {
+            report.timings.push(timing);
+            report.write(temp)
+        });
+        if let Err(e) = result {
+            warn!("Failed to record the duration of {stage} in the report: {e:#}");
+        }
+    }
+
+    /// Write the report to the temporary directory
+    #[inline]
+    pub fn write(&self, temp: &Path) -> anyhow::Result<()> {
+        let path = temp.join("report.json");
+        let json = serde_json::to_string_pretty(self).expect("serialize should not fail");
+        fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Returns stdout and stderr of a command, or `None` if it could not be run
+fn command_output(binary: &str, arg: &str) -> Option<String> {
+    let output = Command::new(binary).arg(arg).output().ok()?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Some(text)
+}
+
+/// Parses e.g. `ffmpeg version 6.1.1 Copyright (c) 2000-2023 the FFmpeg
+/// developers` from `ffmpeg -version`
+fn parse_ffmpeg_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("ffmpeg version "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(ToString::to_string)
+}
+
+/// Parses e.g. `Core R65` from `vspipe --version`
+fn parse_vspipe_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Core "))
+        .map(|version| version.trim().to_string())
+}
+
+/// Parses e.g. `mkvmerge v80.0 ('Roundabout') 64-bit` from `mkvmerge
+/// --version`
+fn parse_mkvmerge_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("mkvmerge "))
+        .map(|version| version.trim().to_string())
+}
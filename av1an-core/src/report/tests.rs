@@ -0,0 +1,146 @@
+use super::*;
+
+#[test]
+fn parse_tool_versions() {
+    assert_eq!(
+        parse_ffmpeg_version(
+            "ffmpeg version 6.1.1 Copyright (c) 2000-2023 the FFmpeg developers\nbuilt with gcc \
+             13.2.1"
+        )
+        .as_deref(),
+        Some("6.1.1")
+    );
+    assert_eq!(
+        parse_vspipe_version(
+            "VapourSynth Video Processing Library\nCopyright (c) 2012-2023 Fredrik Mellbin\nCore \
+             R65\nAPI R4.0\nAPI R3.6"
+        )
+        .as_deref(),
+        Some("R65")
+    );
+    assert_eq!(
+        parse_mkvmerge_version("mkvmerge v80.0 ('Roundabout') 64-bit\n").as_deref(),
+        Some("v80.0 ('Roundabout') 64-bit")
+    );
+    assert_eq!(parse_ffmpeg_version("command not found"), None);
+}
+
+#[test]
+fn report_round_trip() {
+    let dir = tempfile::tempdir().expect("should create temp dir");
+
+    let report = Report {
+        av1an_version: "0.0.0".to_string(),
+        tools:         vec![ToolVersion {
+            binary:  "aomenc".to_string(),
+            version: Some("v3.8.0".to_string()),
+        }],
+        settings:      BTreeMap::from([("passes".to_string(), "1".to_string())]),
+        failure:       None,
+        timings:       Vec::new(),
+    };
+    report.write(dir.path()).expect("should write report");
+    let read = Report::read(dir.path()).expect("should read report");
+
+    assert_eq!(read.av1an_version, report.av1an_version);
+    assert_eq!(read.tools, report.tools);
+    assert_eq!(read.settings, report.settings);
+    assert_eq!(read.failure, None);
+
+    let error = Av1anError::Concat(anyhow::anyhow!("mkvmerge failed")).into();
+    Report::record_failure(dir.path(), &error);
+    let failure = Report::read(dir.path()).expect("should read report").failure;
+    assert_eq!(
+        failure.map(|failure| (failure.code, failure.kind)),
+        Some((14, "concat".to_string()))
+    );
+}
+
+#[test]
+fn report_settings_diff() {
+    let report = |settings: &[(&str, &str)]| Report {
+        av1an_version: "0.0.0".to_string(),
+        tools:         Vec::new(),
+        settings:      settings.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+        failure:       None,
+        timings:       Vec::new(),
+    };
+    let previous = report(&[("encoder", "aom"), ("passes", "2"), ("zones", "none")]);
+    let current = report(&[("encoder", "aom"), ("passes", "1"), ("two-stage", "2")]);
+
+    assert_eq!(previous.settings_diff(&current.settings), vec![
+        "passes: 2 -> 1",
+        "two-stage: unset -> 2",
+        "zones: none -> unset",
+    ]);
+    assert!(current.settings_diff(&current.settings).is_empty());
+}
+
+#[test]
+fn html_report() {
+    let dir = tempfile::tempdir().expect("should create temp dir");
+    let report = Report {
+        av1an_version: "0.0.0".to_string(),
+        tools:         Vec::new(),
+        settings:      BTreeMap::from([("video-params".to_string(), "--tune <ssim>".to_string())]),
+        failure:       None,
+        timings:       Vec::new(),
+    };
+    report.write(dir.path()).expect("should write report");
+    let timing = StageTiming {
+        stage:      "encoding".to_string(),
+        seconds:    125.0,
+        concurrent: false,
+    };
+    Report::record_timing(dir.path(), timing.clone());
+    let report = Report::read(dir.path()).expect("should read report");
+    assert_eq!(report.timings, [timing]);
+
+    let chunks = (0..3)
+        .map(|index| ChunkStats {
+            index,
+            frames: 48,
+            duration: 2.0,
+            size_bytes: 250_000,
+            encode_fps: Some(12.0),
+        })
+        .collect::<Vec<_>>();
+    let html = report
+        .to_html(
+            "<encode>.mkv",
+            &chunks,
+            Some(("VMAF", &[93.5, 95.0, 96.25])),
+        )
+        .expect("should render the report");
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>&lt;encode&gt;.mkv</title>"));
+    assert!(html.contains("<td>--tune &lt;ssim&gt;</td>"));
+    assert!(html.contains("<th>encoding</th><td>2m 05s</td>"));
+    assert!(html.contains("<th>Average bitrate</th><td>1000.00 kb/s</td>"));
+    assert!(html.contains("VMAF distribution"));
+    assert_eq!(html.matches("<svg").count(), 2);
+}
+
+#[test]
+fn timing_breakdown_shares() {
+    let timing = |stage: &str, seconds, concurrent| StageTiming {
+        stage: stage.to_string(),
+        seconds,
+        concurrent,
+    };
+    let lines = timing_breakdown(
+        &[
+            timing("scene detection", 30.0, false),
+            timing("encoding", 3690.0, false),
+            timing("Target Quality probing", 7200.0, true),
+        ],
+        Duration::from_secs(3800),
+    );
+    assert_eq!(lines, [
+        "time spent per stage:",
+        "  scene detection                 30s  0.8%",
+        "  encoding                 1h 01m 30s  97.1%",
+        "  Target Quality probing   2h 00m 00s  summed over the workers",
+        "  total                    1h 03m 20s",
+    ]);
+}
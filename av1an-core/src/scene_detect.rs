@@ -1,11 +1,17 @@
 use std::{
-    collections::BTreeMap,
-    io::{IsTerminal, Read},
-    process::{Command, Stdio},
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    env,
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{self, Child, ChildStdout, Command, Stdio},
     thread,
 };
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use av_decoders::{DecoderError, DecoderImpl, VapoursynthDecoder, Y4mDecoder};
 use av_scenechange::{
     detect_scene_changes,
@@ -15,22 +21,73 @@ use av_scenechange::{
     ScenecutResult,
 };
 use colored::*;
-use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use smallvec::{smallvec, SmallVec};
+use tracing::{debug, info, warn};
 
 use crate::{
-    ffmpeg::FFPixelFormat,
+    ffmpeg::{source_args, FFPixelFormat},
     into_smallvec,
     progress_bar,
     scenes::Scene,
-    vapoursynth::resize_node,
+    split::scene_split_size,
+    vapoursynth::{resize_node, VapoursynthPlugins},
+    zones::splits_no_split,
+    ChunkMethod,
     Encoder,
     Input,
+    ScDecoder,
     ScenecutMethod,
+    TransitionCut,
     Verbosity,
 };
 
-#[tracing::instrument(level = "debug")]
+/// Returns the input that scene detection reads with `sc_decoder`. A video
+/// input whose chunk method does not use VapourSynth is indexed with the best
+/// installed VapourSynth source plugin if `sc_decoder` needs a VapourSynth
+/// script.
+#[inline]
+pub fn sc_decoder_input<'a>(
+    input: &'a Input,
+    sc_decoder: ScDecoder,
+    vapoursynth_plugins: Option<VapoursynthPlugins>,
+) -> anyhow::Result<Cow<'a, Input>> {
+    let needs_script = matches!(sc_decoder, ScDecoder::VapourSynth | ScDecoder::Y4mPipe);
+    let Input::Video {
+        path,
+        temp,
+        is_proxy,
+        cache_mode,
+        ..
+    } = input
+    else {
+        return Ok(Cow::Borrowed(input));
+    };
+    if !needs_script || input.is_vapoursynth_script() {
+        return Ok(Cow::Borrowed(input));
+    }
+
+    let chunk_method = vapoursynth_plugins.map_or(ChunkMethod::Hybrid, |plugins| {
+        plugins.best_available_chunk_method()
+    });
+    if !matches!(
+        chunk_method,
+        ChunkMethod::LSMASH | ChunkMethod::FFMS2 | ChunkMethod::DGDECNV | ChunkMethod::BESTSOURCE
+    ) {
+        bail!("--sc-decoder {sc_decoder} requires a VapourSynth source plugin");
+    }
+    debug!("indexing the input with {chunk_method} for scene detection");
+    Ok(Cow::Owned(Input::new(
+        path.clone(),
+        Vec::new(),
+        temp,
+        chunk_method,
+        *is_proxy,
+        *cache_mode,
+    )?))
+}
+
+#[tracing::instrument(level = "debug", skip(on_scenes))]
 #[expect(clippy::too_many_arguments)]
 pub fn av_scenechange_detect(
     input: &Input,
@@ -42,7 +99,15 @@ pub fn av_scenechange_detect(
     sc_pix_format: Option<FFPixelFormat>,
     sc_method: ScenecutMethod,
     sc_downscale_height: Option<usize>,
+    sc_decoder: ScDecoder,
+    transitions: TransitionCut,
     zones: &[Scene],
+    no_split: &[Range<usize>],
+    sc_proxy: Option<&Path>,
+    checkpoint: Option<&Path>,
+    split_len: Option<usize>,
+    window: usize,
+    on_scenes: Option<&mut ScenesCallback<'_>>,
 ) -> anyhow::Result<(Vec<Scene>, usize, BTreeMap<usize, ScenecutResult>)> {
     if verbosity != Verbosity::Quiet {
         if std::io::stderr().is_terminal() {
@@ -79,7 +144,15 @@ pub fn av_scenechange_detect(
         sc_pix_format,
         sc_method,
         sc_downscale_height,
+        sc_decoder,
+        transitions,
         zones,
+        no_split,
+        sc_proxy,
+        checkpoint,
+        split_len,
+        window,
+        on_scenes,
     )?;
     let frames = frame_thread.join().expect("should join frame_thread successfully")?;
 
@@ -88,7 +161,139 @@ pub fn av_scenechange_detect(
     Ok((scenes, frames, scores))
 }
 
+/// Frames scene detection analyses between checkpoints, with
+/// [`EncodeArgs::sc_checkpoint`](crate::settings::EncodeArgs::sc_checkpoint)
+pub(crate) const CHECKPOINT_FRAMES: usize = 30_000;
+
+/// Called with the scenes scene detection finished in a window, and the scores
+/// kept so far, while it continues with the next window
+pub type ScenesCallback<'a> =
+    dyn FnMut(&[Scene], &BTreeMap<usize, ScenecutResult>) -> anyhow::Result<()> + 'a;
+
+/// Scenes found so far by an interrupted scene detection pass, written to the
+/// temporary directory after every window of frames
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    /// Hash of the settings the scenes were detected with
+    settings:    u64,
+    /// Frames analysed so far
+    frames_read: usize,
+    /// Start of the scene that was not finished yet
+    open_start:  usize,
+    scenes:      Vec<Scene>,
+    /// Scores of the kept frames, as inter cost, imp block cost, backward and
+    /// forward adjusted cost and threshold
+    scores:      Vec<(usize, [f64; 5])>,
+}
+
+impl Checkpoint {
+    /// Returns the checkpoint at `path` if it was written with the same
+    /// `settings`
+    fn load(path: &Path, settings: u64) -> Option<Self> {
+        let checkpoint = serde_json::from_str::<Self>(&fs::read_to_string(path).ok()?)
+            .map_err(|e| warn!("Ignoring the scene detection checkpoint: {e}"))
+            .ok()?;
+        if checkpoint.settings != settings {
+            info!("scene detection settings changed, ignoring the checkpoint");
+            return None;
+        }
+        Some(checkpoint)
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let partial = path.with_extension("json.tmp");
+        fs::write(&partial, serde_json::to_string(self)?)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    fn scores(&self) -> BTreeMap<usize, ScenecutResult> {
+        self.scores
+            .iter()
+            .map(
+                |&(frame, [inter, imp_block, backward, forward, threshold])| {
+                    (frame, ScenecutResult {
+                        inter_cost: inter,
+                        imp_block_cost: imp_block,
+                        backward_adjusted_cost: backward,
+                        forward_adjusted_cost: forward,
+                        threshold,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    fn set_scores(&mut self, scores: &BTreeMap<usize, ScenecutResult>) {
+        self.scores = scores
+            .iter()
+            .map(|(&frame, score)| {
+                (frame, [
+                    score.inter_cost,
+                    score.imp_block_cost,
+                    score.backward_adjusted_cost,
+                    score.forward_adjusted_cost,
+                    score.threshold,
+                ])
+            })
+            .collect();
+    }
+}
+
+/// Splits the frames into the zones and the ranges between them, which are
+/// detected with their own settings
+fn detection_segments(zones: &[Scene], total_frames: usize) -> Vec<(Range<usize>, Option<&Scene>)> {
+    let mut segments = Vec::with_capacity(2 * zones.len() + 1);
+    let mut frames = 0;
+    for zone in zones {
+        if zone.start_frame > frames {
+            segments.push((frames..zone.start_frame, None));
+        }
+        segments.push((zone.start_frame..zone.end_frame, Some(zone)));
+        frames = zone.end_frame;
+    }
+    if frames < total_frames {
+        segments.push((frames..total_frames, None));
+    }
+    segments
+}
+
+/// Drops the scores of the frames within `closed` scenes no extra split is
+/// placed in, keeping the scores of the first frames of the scenes. Only the
+/// scores of scenes longer than their split size are used to place extra
+/// splits, so this bounds the memory used for long inputs. A `split_len` of 0
+/// only keeps the scores of scenes of zones with their own extra split
+/// length, while `None` keeps every score, for uses that need the scores of
+/// all scenes.
+pub(crate) fn prune_scores(
+    scores: &mut BTreeMap<usize, ScenecutResult>,
+    closed: &[Scene],
+    split_len: Option<usize>,
+) {
+    let (Some(split_len), Some(first), Some(last)) = (split_len, closed.first(), closed.last())
+    else {
+        return;
+    };
+    let (start, end) = (first.start_frame, last.end_frame);
+    scores.retain(|&frame, _| {
+        if frame < start || frame >= end {
+            return true;
+        }
+        let scene = &closed[closed.partition_point(|scene| scene.end_frame <= frame)];
+        frame == scene.start_frame
+            || scene.end_frame - scene.start_frame > scene_split_size(scene, split_len)
+    });
+}
+
 /// Detect scene changes using rav1e scene detector.
+///
+/// The frames are analysed in windows of `window` frames. With a `checkpoint`
+/// path, the scenes found so far are written to it after every window, and
+/// detection continues from the checkpoint if it was interrupted with the same
+/// settings before. `on_scenes` is called with the scenes finished in every
+/// window, including the ones read from the checkpoint. The detector starts
+/// afresh after each window, so no scene cut is placed on the first frame of
+/// a window, nor within the first `min_scene_len` frames after it.
 #[expect(clippy::too_many_arguments)]
 pub fn scene_detect(
     input: &Input,
@@ -100,123 +305,542 @@ pub fn scene_detect(
     sc_pix_format: Option<FFPixelFormat>,
     sc_method: ScenecutMethod,
     sc_downscale_height: Option<usize>,
+    sc_decoder: ScDecoder,
+    transitions: TransitionCut,
     zones: &[Scene],
+    no_split: &[Range<usize>],
+    sc_proxy: Option<&Path>,
+    checkpoint: Option<&Path>,
+    split_len: Option<usize>,
+    window: usize,
+    mut on_scenes: Option<&mut ScenesCallback<'_>>,
 ) -> anyhow::Result<(Vec<Scene>, BTreeMap<usize, ScenecutResult>)> {
-    let (mut decoder, bit_depth) = build_decoder(
+    let settings = {
+        let mut hasher = DefaultHasher::new();
+        format!(
+            "{input:?} {encoder} {total_frames} {min_scene_len} {sc_scaler} {sc_pix_format:?} \
+             {sc_method} {sc_downscale_height:?} {sc_decoder} {transitions} {zones:?} \
+             {no_split:?} {window} {split_len:?}"
+        )
+        .hash(&mut hasher);
+        hasher.finish()
+    };
+    if matches!(sc_method, ScenecutMethod::FirstPass) {
+        // next to the checkpoint, in the temporary directory
+        let stats = checkpoint.map_or_else(
+            || env::temp_dir().join(format!("av1an_sc_first_pass_{}.log", process::id())),
+            |path| path.with_file_name("sc_first_pass.log"),
+        );
+        let keyframes = x264_first_pass(
+            input,
+            total_frames,
+            callback,
+            min_scene_len,
+            &ffmpeg_filters(
+                sc_scaler,
+                // x264 may be built without support for higher bit depths
+                Some(sc_pix_format.unwrap_or(FFPixelFormat::YUV420P)),
+                sc_downscale_height,
+            ),
+            sc_decoder == ScDecoder::Y4mPipe,
+            &stats,
+        )?;
+        let scenes =
+            scenes_from_keyframes(&keyframes, zones, no_split, total_frames, min_scene_len);
+        let scores = BTreeMap::new();
+        if let Some(on_scenes) = on_scenes.as_mut() {
+            on_scenes(&scenes, &scores)?;
+        }
+        return Ok((scenes, scores));
+    }
+
+    let resumed = checkpoint.and_then(|path| Checkpoint::load(path, settings));
+    let sc_proxy = match sc_proxy {
+        Some(path) if resumed.is_some() => {
+            warn!(
+                "The scene detection proxy would lack the frames before the checkpoint, Target \
+                 Quality will use the source"
+            );
+            let _ = fs::remove_file(path);
+            None
+        },
+        sc_proxy => sc_proxy,
+    };
+    let mut state = resumed.unwrap_or_else(|| Checkpoint {
+        settings,
+        ..Checkpoint::default()
+    });
+    let mut scores = state.scores();
+
+    let (mut decoder, bit_depth, proxy_encoder) = build_decoder(
         input,
         encoder,
         sc_scaler,
         sc_pix_format,
         sc_downscale_height,
+        sc_decoder,
+        sc_proxy,
     )?;
-
-    let mut scenes = Vec::new();
-    let mut scores: BTreeMap<usize, ScenecutResult> = BTreeMap::new();
-    let mut cur_zone = zones.first().filter(|frame| frame.start_frame == 0);
-    let mut next_zone_idx = if zones.is_empty() {
-        None
-    } else if cur_zone.is_some() {
-        if zones.len() == 1 {
-            None
-        } else {
-            Some(1)
+    if state.frames_read > 0 {
+        info!(
+            "resuming scene detection at frame {} from the checkpoint",
+            state.frames_read
+        );
+        for _ in 0..state.frames_read {
+            if bit_depth > 8 {
+                decoder.read_video_frame::<u16>()?;
+            } else {
+                decoder.read_video_frame::<u8>()?;
+            }
         }
-    } else {
-        Some(0)
-    };
-    let mut frames_read = 0;
-    loop {
-        let mut min_scene_len = min_scene_len;
-        if let Some(zone) = cur_zone
-            && let Some(ref overrides) = zone.zone_overrides
-        {
-            min_scene_len = overrides.min_scene_len;
+        if let Some(on_scenes) = on_scenes.as_mut() {
+            on_scenes(&state.scenes, &scores)?;
         }
+    }
+
+    for (segment, zone) in detection_segments(zones, total_frames) {
+        let overrides = zone.and_then(|zone| zone.zone_overrides.as_ref());
+        let segment_min_scene_len =
+            overrides.map_or(min_scene_len, |overrides| overrides.min_scene_len);
         let options = DetectionOptions {
-            min_scenecut_distance: Some(min_scene_len),
+            min_scenecut_distance: Some(segment_min_scene_len),
             analysis_speed: match sc_method {
                 ScenecutMethod::Fast => SceneDetectionSpeed::Fast,
                 ScenecutMethod::Standard => SceneDetectionSpeed::Standard,
+                ScenecutMethod::FirstPass => unreachable!("detected from the first pass above"),
             },
             ..DetectionOptions::default()
         };
-        let frame_limit = cur_zone.map_or_else(
-            || {
-                next_zone_idx.map(|next_idx| {
-                    let zone = &zones[next_idx];
-                    zone.start_frame - frames_read
-                })
-            },
-            |zone| Some(zone.end_frame - zone.start_frame),
-        );
-        let callback = callback.map(|cb| {
-            |frames, _keyframes| {
-                cb(frames + frames_read);
+
+        while state.frames_read < segment.end {
+            let frames_read = state.frames_read;
+            let remaining = segment.end - frames_read;
+            // the end of the video is read until the decoder runs out of frames
+            let frame_limit =
+                (segment.end != total_frames || remaining > window).then(|| remaining.min(window));
+            let callback = callback.map(|cb| {
+                move |frames, _keyframes| {
+                    cb(frames + frames_read);
+                }
+            });
+            let sc_result = if bit_depth > 8 {
+                detect_scene_changes::<u16>(
+                    &mut decoder,
+                    options,
+                    frame_limit,
+                    callback.as_ref().map(|cb| cb as &dyn Fn(usize, usize)),
+                )
+            } else {
+                detect_scene_changes::<u8>(
+                    &mut decoder,
+                    options,
+                    frame_limit,
+                    callback.as_ref().map(|cb| cb as &dyn Fn(usize, usize)),
+                )
+            }?;
+            if let Some(limit) = frame_limit
+                && limit != sc_result.frame_count
+            {
+                bail!(
+                    "Scene change: Expected {} frames but saw {}. This may indicate an issue with \
+                     the input or filters.",
+                    limit,
+                    sc_result.frame_count
+                );
             }
-        });
-        let sc_result = if bit_depth > 8 {
-            detect_scene_changes::<u16>(
-                &mut decoder,
-                options,
-                frame_limit,
-                callback.as_ref().map(|cb| cb as &dyn Fn(usize, usize)),
-            )
-        } else {
-            detect_scene_changes::<u8>(
-                &mut decoder,
-                options,
-                frame_limit,
-                callback.as_ref().map(|cb| cb as &dyn Fn(usize, usize)),
-            )
-        }?;
-        if let Some(limit) = frame_limit
-            && limit != sc_result.frame_count
-        {
-            bail!(
-                "Scene change: Expected {} frames but saw {}. This may indicate an issue with the \
-                 input or filters.",
-                limit,
-                sc_result.frame_count
+            scores.extend(sc_result.scores.iter().map(|(k, v)| (k + frames_read, *v)));
+
+            let closed_from = state.scenes.len();
+            // the detector always reports the first frame, which is only a
+            // cut at the start of a segment, not after a checkpoint
+            let cuts = sc_result
+                .scene_changes
+                .iter()
+                .filter(|&&cut| cut > 0)
+                .map(|cut| cut + frames_read)
+                .collect::<Vec<_>>();
+            let cuts = place_transition_cuts(
+                &cuts,
+                &scores,
+                transitions,
+                state.open_start,
+                segment_min_scene_len,
             );
+            for cut in cuts.into_iter().filter(|&cut| !splits_no_split(no_split, cut)) {
+                if cut > state.open_start {
+                    state.scenes.push(Scene {
+                        start_frame:    state.open_start,
+                        end_frame:      cut,
+                        zone_overrides: overrides.cloned(),
+                    });
+                    state.open_start = cut;
+                }
+            }
+            state.frames_read = frame_limit.map_or(segment.end, |limit| frames_read + limit);
+            if state.frames_read == segment.end {
+                state.scenes.push(Scene {
+                    start_frame:    state.open_start,
+                    end_frame:      segment.end,
+                    zone_overrides: overrides.cloned(),
+                });
+                state.open_start = segment.end;
+            }
+            prune_scores(&mut scores, &state.scenes[closed_from..], split_len);
+            if let Some(on_scenes) = on_scenes.as_mut() {
+                on_scenes(&state.scenes[closed_from..], &scores)?;
+            }
+
+            if let Some(path) = checkpoint
+                && state.frames_read < total_frames
+            {
+                state.set_scores(&scores);
+                if let Err(e) = state.save(path) {
+                    warn!("Failed to write the scene detection checkpoint: {e}");
+                }
+            }
         }
-        scores.extend(sc_result.scores.iter().map(|(k, v)| (k + frames_read, *v)));
-
-        let scene_changes = sc_result.scene_changes;
-        for (start, end) in scene_changes.iter().copied().tuple_windows() {
-            scenes.push(Scene {
-                start_frame:    start + frames_read,
-                end_frame:      end + frames_read,
-                zone_overrides: cur_zone.and_then(|zone| zone.zone_overrides.clone()),
-            });
+    }
+    if let Some(path) = checkpoint {
+        let _ = fs::remove_file(path);
+    }
+
+    // The proxy encoder only finishes once its input is closed
+    drop(decoder);
+    if let Some((mut proxy_encoder, path)) = proxy_encoder.zip(sc_proxy) {
+        match proxy_encoder.wait() {
+            Ok(status) if status.success() => {
+                debug!("scene detection proxy written to {}", path.display());
+            },
+            result => {
+                warn!(
+                    "Failed to write the scene detection proxy ({}), Target Quality will use the \
+                     source",
+                    result.map_or_else(|e| e.to_string(), |status| status.to_string())
+                );
+                let _ = fs::remove_file(path);
+            },
         }
+    }
 
-        scenes.push(Scene {
-            start_frame:    scenes.last().map(|scene| scene.end_frame).unwrap_or_default(),
-            end_frame:      frame_limit.map_or(total_frames, |limit| {
-                frames_read += limit;
-                frames_read
-            }),
-            zone_overrides: cur_zone.and_then(|zone| zone.zone_overrides.clone()),
-        });
-        if let Some(next_idx) = next_zone_idx {
-            if cur_zone.is_none_or(|zone| zone.end_frame == zones[next_idx].start_frame) {
-                cur_zone = Some(&zones[next_idx]);
-                next_zone_idx = if next_idx + 1 == zones.len() {
-                    None
-                } else {
-                    Some(next_idx + 1)
+    Ok((state.scenes, scores))
+}
+
+/// Fraction of the scenecut threshold from which the inter cost of a frame
+/// counts as part of a gradual transition
+const TRANSITION_COST: f64 = 0.3;
+
+/// Frames in a row with a raised inter cost from which a cut counts as part of
+/// a gradual transition rather than a hard cut
+const MIN_TRANSITION_FRAMES: usize = 4;
+
+/// Returns the frames of the gradual transition that the cut at `cut` is part
+/// of, which are the frames around it whose inter cost is at least
+/// [`TRANSITION_COST`] of the threshold, or `None` if it is a hard cut
+fn gradual_transition(
+    cut: usize,
+    scores: &BTreeMap<usize, ScenecutResult>,
+) -> Option<Range<usize>> {
+    let raised = |frame: usize| {
+        scores
+            .get(&frame)
+            .is_some_and(|score| score.inter_cost >= TRANSITION_COST * score.threshold)
+    };
+    if !raised(cut) {
+        return None;
+    }
+    let mut start = cut;
+    while start > 0 && raised(start - 1) {
+        start -= 1;
+    }
+    let mut end = cut + 1;
+    while raised(end) {
+        end += 1;
+    }
+    (end - start >= MIN_TRANSITION_FRAMES).then_some(start..end)
+}
+
+/// Moves the `cuts` that are part of a gradual transition to where
+/// `transitions` places them, keeping one cut per transition at most. Cuts
+/// closer than `min_scene_len` frames to the one before them, or to
+/// `previous`, are dropped.
+fn place_transition_cuts(
+    cuts: &[usize],
+    scores: &BTreeMap<usize, ScenecutResult>,
+    transitions: TransitionCut,
+    previous: usize,
+    min_scene_len: usize,
+) -> Vec<usize> {
+    if transitions == TransitionCut::Detected {
+        return cuts.to_vec();
+    }
+    let mut placed = Vec::with_capacity(cuts.len());
+    let mut last_cut = previous;
+    let mut last_transition = None;
+    for &cut in cuts {
+        let cut = match gradual_transition(cut, scores) {
+            None => Some(cut),
+            Some(transition) if last_transition.as_ref() == Some(&transition) => continue,
+            Some(transition) => {
+                debug!(
+                    "gradual transition at frames {}..{}",
+                    transition.start, transition.end
+                );
+                let cut = match transitions {
+                    TransitionCut::Detected => Some(cut),
+                    TransitionCut::Middle => Some(transition.start.midpoint(transition.end)),
+                    TransitionCut::End => Some(transition.end - 1),
+                    TransitionCut::NoCut => None,
                 };
-            } else {
-                cur_zone = None;
+                last_transition = Some(transition);
+                cut
+            },
+        };
+        if let Some(cut) = cut
+            && cut >= last_cut + min_scene_len
+        {
+            placed.push(cut);
+            last_cut = cut;
+        }
+    }
+    placed
+}
+
+/// Runs a fast first pass of x264 over `input`, writing its statistics to
+/// `stats`, and returns the frames it placed an IDR frame on. Scene cuts are
+/// at least `min_scene_len` frames apart, and placed by x264 on its own, as
+/// no maximum keyframe interval is set.
+fn x264_first_pass(
+    input: &Input,
+    total_frames: usize,
+    callback: Option<&dyn Fn(usize)>,
+    min_scene_len: usize,
+    filters: &[String],
+    vspipe: bool,
+    stats: &Path,
+) -> anyhow::Result<Vec<usize>> {
+    let source = spawn_y4m_source(input, vspipe || input.is_vapoursynth_script(), filters)?;
+    let mut x264 = Command::new("x264")
+        .args(["--demuxer", "y4m", "--preset", "veryfast", "--pass", "1", "--progress"])
+        .args(["--keyint", "infinite", "--min-keyint", &min_scene_len.max(1).to_string()])
+        // the macroblock tree does not move keyframes, and would leave a
+        // `.mbtree` file next to the statistics
+        .arg("--no-mbtree")
+        .args(["--frames", &total_frames.to_string(), "--stats"])
+        .arg(stats)
+        // the first pass output is not needed, only the statistics
+        .args(["-o", "-", "-"])
+        .stdin(source)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run x264 for the first pass scene detection")?;
+
+    // the progress is printed on a single line, overwritten with `\r`
+    let mut log = String::new();
+    let stderr = x264.stderr.take().expect("x264 should have stderr");
+    for segment in BufReader::new(stderr).split(b'\r') {
+        for line in String::from_utf8_lossy(&segment?).lines() {
+            match parse_x264_progress(line) {
+                Some(frames) => {
+                    if let Some(callback) = callback {
+                        callback(frames);
+                    }
+                },
+                None => {
+                    log.push_str(line);
+                    log.push('\n');
+                },
             }
-        } else if cur_zone.is_none_or(|zone| zone.end_frame == total_frames) {
-            // End of video
-            break;
-        } else {
-            cur_zone = None;
         }
     }
-    Ok((scenes, scores))
+    let status = x264.wait()?;
+    if !status.success() {
+        bail!("x264 first pass failed ({status}):\n{log}");
+    }
+
+    let keyframes = parse_x264_keyframes(&fs::read_to_string(stats)?)?;
+    let _ = fs::remove_file(stats);
+    debug!(
+        "x264 placed {} keyframes in the first pass",
+        keyframes.len()
+    );
+    Ok(keyframes)
+}
+
+/// Returns the number of frames encoded from a progress line of x264, e.g.
+/// `[12.5%] 125/1000 frames, 250.00 fps, 1234.56 kb/s, eta 0:00:03`
+fn parse_x264_progress(line: &str) -> Option<usize> {
+    let (before, _) = line.split_once(" frames")?;
+    let frames = before.rsplit(['[', ']', ' ']).next()?;
+    frames.split('/').next()?.parse().ok()
+}
+
+/// Returns the frames of the IDR frames in the statistics file of an x264
+/// first pass, in display order. Every line but the first describes a frame
+/// in coded order, e.g. `in:24 out:24 type:I dur:2 ...`.
+fn parse_x264_keyframes(stats: &str) -> anyhow::Result<Vec<usize>> {
+    let mut keyframes = Vec::new();
+    for line in stats.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#')) {
+        let field = |name: &str| line.split_whitespace().find_map(|field| field.strip_prefix(name));
+        let (Some(frame), Some(frame_type)) = (field("in:"), field("type:")) else {
+            bail!("Unexpected line in the x264 statistics: {line}");
+        };
+        if frame_type == "I" {
+            keyframes.push(frame.parse()?);
+        }
+    }
+    keyframes.sort_unstable();
+    Ok(keyframes)
+}
+
+/// Cuts the frames at `keyframes` and at the boundaries of the zones, dropping
+/// the keyframes within the `no_split` ranges, and those closer than the
+/// minimum scene length of their zone to the previous cut
+fn scenes_from_keyframes(
+    keyframes: &[usize],
+    zones: &[Scene],
+    no_split: &[Range<usize>],
+    total_frames: usize,
+    min_scene_len: usize,
+) -> Vec<Scene> {
+    let mut scenes = Vec::new();
+    for (segment, zone) in detection_segments(zones, total_frames) {
+        let overrides = zone.and_then(|zone| zone.zone_overrides.as_ref());
+        let min_scene_len = overrides.map_or(min_scene_len, |overrides| overrides.min_scene_len);
+        let mut start = segment.start;
+        for &cut in keyframes
+            .iter()
+            .filter(|&&cut| segment.contains(&cut) && !splits_no_split(no_split, cut))
+        {
+            if cut > start && cut - start >= min_scene_len {
+                scenes.push(Scene {
+                    start_frame:    start,
+                    end_frame:      cut,
+                    zone_overrides: overrides.cloned(),
+                });
+                start = cut;
+            }
+        }
+        scenes.push(Scene {
+            start_frame:    start,
+            end_frame:      segment.end,
+            zone_overrides: overrides.cloned(),
+        });
+    }
+    scenes
+}
+
+/// Returns the path of the scene detection checkpoint in `temp`
+#[inline]
+pub(crate) fn sc_checkpoint_path(temp: &str) -> PathBuf {
+    Path::new(temp).join("sc_checkpoint.json")
+}
+
+/// Returns where the frames decoded for scene detection are kept with
+/// `--sc-proxy`
+#[inline]
+pub(crate) fn sc_proxy_path(temp: &str) -> PathBuf {
+    Path::new(temp).join("sc_proxy.mkv")
+}
+
+/// Passes everything read from `inner` on to `sink`, the stdin of the proxy
+/// encoder
+struct TeeReader<R, W> {
+    inner: R,
+    /// `None` once the proxy encoder stopped accepting input
+    sink:  Option<W>,
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(sink) = &mut self.sink
+            && let Some(data) = buf.get(..read)
+            && sink.write_all(data).is_err()
+        {
+            // scene detection must not fail because of the proxy, the
+            // encoder exiting early is reported once it is waited on
+            self.sink = None;
+        }
+        Ok(read)
+    }
+}
+
+/// Spawns an FFmpeg process that losslessly encodes the y4m stream written to
+/// its stdin to `path`
+fn spawn_proxy_encoder(path: &Path, input: &Input) -> anyhow::Result<Child> {
+    let frame_rate = input.clip_info()?.frame_rate;
+    Ok(Command::new("ffmpeg")
+        .args(["-y", "-hide_banner", "-loglevel", "error", "-f", "yuv4mpegpipe"])
+        .args(["-r", &frame_rate.to_string(), "-i", "-", "-c:v", "ffv1", "-level", "3"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?)
+}
+
+/// Returns the FFmpeg arguments that downscale the frames to
+/// `sc_downscale_height` and convert them to `sc_pix_format`
+fn ffmpeg_filters(
+    sc_scaler: &str,
+    sc_pix_format: Option<FFPixelFormat>,
+    sc_downscale_height: Option<usize>,
+) -> SmallVec<[String; 4]> {
+    match (sc_downscale_height, sc_pix_format) {
+        (Some(sdh), Some(spf)) => into_smallvec![
+            "-vf",
+            format!(
+                "format={},scale=-2:'min({},ih)':flags={}",
+                spf.to_pix_fmt_string(),
+                sdh,
+                sc_scaler
+            )
+        ],
+        (Some(sdh), None) => {
+            into_smallvec!["-vf", format!("scale=-2:'min({sdh},ih)':flags={sc_scaler}")]
+        },
+        (None, Some(spf)) => into_smallvec!["-pix_fmt", spf.to_pix_fmt_string()],
+        (None, None) => smallvec![],
+    }
+}
+
+/// Spawns FFmpeg to write the frames of `input` to its stdout as y4m, applying
+/// `filters`. With `vspipe`, FFmpeg reads the output of the VapourSynth script
+/// of the input instead of the input itself.
+fn spawn_y4m_source(
+    input: &Input,
+    vspipe: bool,
+    filters: &[String],
+) -> anyhow::Result<ChildStdout> {
+    let mut ffmpeg = Command::new("ffmpeg");
+    if vspipe {
+        let mut vspipe = Command::new("vspipe");
+        for arg in input.as_vspipe_args_vec()? {
+            vspipe.args(["-a", &arg]);
+        }
+        let vspipe_stdout = vspipe
+            .args(["-a", "AV1AN_PERFORM_SCENE_DETECTION=1", "-c", "y4m"])
+            .arg(input.as_script_path())
+            .arg("-")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?
+            .stdout
+            .expect("vspipe should have stdout");
+        ffmpeg.args(["-i", "-"]).stdin(vspipe_stdout);
+    } else {
+        ffmpeg.args(["-r", "1"]).args(source_args(input.as_path())).stdin(Stdio::null());
+    }
+    Ok(ffmpeg
+        .args(filters)
+        .args(["-f", "yuv4mpegpipe", "-strict", "-1", "-"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?
+        .stdout
+        .expect("ffmpeg should have stdout"))
 }
 
 #[tracing::instrument(level = "debug")]
@@ -226,7 +850,9 @@ fn build_decoder(
     sc_scaler: &str,
     sc_pix_format: Option<FFPixelFormat>,
     sc_downscale_height: Option<usize>,
-) -> anyhow::Result<(Decoder, usize)> {
+    sc_decoder: ScDecoder,
+    sc_proxy: Option<&Path>,
+) -> anyhow::Result<(Decoder, usize, Option<Child>)> {
     let clip_info = input.clip_info()?;
     let (input_width, input_height) = clip_info.resolution;
 
@@ -241,7 +867,15 @@ fn build_decoder(
         clip_info.format_info.as_bit_depth()?
     };
 
-    let decoder = if input.is_vapoursynth() || input.is_vapoursynth_script() {
+    let mut proxy_encoder = None;
+    let use_vapoursynth = match sc_decoder {
+        // only the frames piped through FFmpeg can be passed on to the proxy
+        // encoder
+        ScDecoder::Auto => input.is_vapoursynth_script() && sc_proxy.is_none(),
+        ScDecoder::VapourSynth => true,
+        ScDecoder::FFmpeg | ScDecoder::Y4mPipe => false,
+    };
+    let decoder = if use_vapoursynth {
         // VapoursynthDecoder is the only reliable method for downscaling user-provided
         // scripts, and for our generated scripts, it is faster than piping.
 
@@ -287,40 +921,167 @@ fn build_decoder(
         Decoder::from_decoder_impl(DecoderImpl::Vapoursynth(vs_decoder))?
     } else {
         // FFmpeg is faster if the user provides video input
-        let path = input.as_path();
-
-        let filters: SmallVec<[String; 4]> = match (sc_downscale_height, sc_pix_format) {
-            (Some(sdh), Some(spf)) => into_smallvec![
-                "-vf",
-                format!(
-                    "format={},scale=-2:'min({},ih)':flags={}",
-                    spf.to_pix_fmt_string(),
-                    sdh,
-                    sc_scaler
-                )
-            ],
-            (Some(sdh), None) => {
-                into_smallvec!["-vf", format!("scale=-2:'min({sdh},ih)':flags={sc_scaler}")]
-            },
-            (None, Some(spf)) => into_smallvec!["-pix_fmt", spf.to_pix_fmt_string()],
-            (None, None) => smallvec![],
-        };
 
-        let stdout = Command::new("ffmpeg")
-            .args(["-r", "1", "-i"])
-            .arg(path)
-            .args(filters.as_ref())
-            .args(["-f", "yuv4mpegpipe", "-strict", "-1", "-"])
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("ffmpeg should have stdout");
-        let decoder_impl = DecoderImpl::Y4m(Y4mDecoder::new(Box::new(stdout) as Box<dyn Read>)?);
+        let stdout = spawn_y4m_source(
+            input,
+            sc_decoder == ScDecoder::Y4mPipe,
+            &ffmpeg_filters(sc_scaler, sc_pix_format, sc_downscale_height),
+        )?;
+        let reader: Box<dyn Read> = if let Some(path) = sc_proxy {
+            let mut encoder = spawn_proxy_encoder(path, input)?;
+            let sink = encoder.stdin.take();
+            proxy_encoder = Some(encoder);
+            Box::new(TeeReader {
+                inner: stdout,
+                sink,
+            })
+        } else {
+            Box::new(stdout)
+        };
+        let decoder_impl = DecoderImpl::Y4m(Y4mDecoder::new(reader)?);
 
         Decoder::from_decoder_impl(decoder_impl)?
     };
 
-    Ok((decoder, bit_depth))
+    Ok((decoder, bit_depth, proxy_encoder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tee_reader_copies_everything_read() {
+        let mut tee = TeeReader {
+            inner: b"YUV4MPEG2 W2 H2\nFRAME\n".as_slice(),
+            sink:  Some(Vec::new()),
+        };
+        let mut read = Vec::new();
+        tee.read_to_end(&mut read).expect("should read");
+        assert_eq!(tee.sink.as_deref(), Some(read.as_slice()));
+    }
+
+    #[test]
+    #[expect(clippy::single_range_in_vec_init)]
+    fn x264_first_pass_keyframes() {
+        assert_eq!(
+            parse_x264_progress("[12.5%] 125/1000 frames, 250.00 fps, 1234.56 kb/s, eta 0:00:03"),
+            Some(125)
+        );
+        assert_eq!(
+            parse_x264_progress("480 frames: 250.00 fps, 1234.56 kb/s"),
+            Some(480)
+        );
+        assert_eq!(parse_x264_progress("x264 [info]: profile High"), None);
+
+        let stats = "#options: 1920x1080 fps=24/1 timebase=1/24 bitdepth=8\nin:0 out:0 type:I \
+                     dur:2 cpbdur:2 q:20.00 tex:0 mv:0 misc:0 imb:0 pmb:0 smb:0 d:- ref:;\nin:3 \
+                     out:1 type:P dur:2 cpbdur:2 q:22.00 tex:0 mv:0 misc:0 imb:0 pmb:0 smb:0 d:- \
+                     ref:0 ;\nin:1 out:2 type:B dur:2 cpbdur:2 q:24.00 tex:0 mv:0 misc:0 imb:0 \
+                     pmb:0 smb:0 d:- ref:0 ;\nin:120 out:120 type:I dur:2 cpbdur:2 q:20.00 tex:0 \
+                     mv:0 misc:0 imb:0 pmb:0 smb:0 d:- ref:;\nin:130 out:130 type:i dur:2 \
+                     cpbdur:2 q:20.00 tex:0 mv:0 misc:0 imb:0 pmb:0 smb:0 d:- ref:;\nin:64 out:64 \
+                     type:I dur:2 cpbdur:2 q:20.00 tex:0 mv:0 misc:0 imb:0 pmb:0 smb:0 d:- ref:;\n";
+        let keyframes = parse_x264_keyframes(stats).expect("should parse");
+        assert_eq!(keyframes, [0, 64, 120]);
+        assert!(parse_x264_keyframes("out:1 dur:2\n").is_err());
+
+        let scene = |start_frame, end_frame| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: None,
+        };
+        let scenes = scenes_from_keyframes(&keyframes, &[], &[], 200, 10)
+            .into_iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>();
+        assert_eq!(scenes, [(0, 64), (64, 120), (120, 200)]);
+        // cut at the zone even if there is no keyframe
+        let scenes = scenes_from_keyframes(&keyframes, &[scene(100, 150)], &[], 200, 60)
+            .into_iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>();
+        assert_eq!(scenes, [(0, 64), (64, 100), (100, 150), (150, 200)]);
+        // no cut within a no-split range
+        let scenes = scenes_from_keyframes(&keyframes, &[], &[100..130], 200, 10)
+            .into_iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>();
+        assert_eq!(scenes, [(0, 64), (64, 200)]);
+    }
+
+    #[test]
+    fn transition_cuts() {
+        let scores = (0..200)
+            .map(|frame| {
+                let inter_cost = match frame {
+                    50 => 30.0,
+                    104 => 12.0,
+                    100..110 => 5.0,
+                    _ => 1.0,
+                };
+                (frame, ScenecutResult {
+                    inter_cost,
+                    imp_block_cost: 0.0,
+                    backward_adjusted_cost: 0.0,
+                    forward_adjusted_cost: 0.0,
+                    threshold: 10.0,
+                })
+            })
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(gradual_transition(50, &scores), None);
+        assert_eq!(gradual_transition(104, &scores), Some(100..110));
+
+        let place = |transitions, min_scene_len| {
+            place_transition_cuts(&[50, 104, 107], &scores, transitions, 0, min_scene_len)
+        };
+        assert_eq!(place(TransitionCut::Detected, 10), [50, 104, 107]);
+        assert_eq!(place(TransitionCut::Middle, 10), [50, 105]);
+        assert_eq!(place(TransitionCut::End, 10), [50, 109]);
+        assert_eq!(place(TransitionCut::NoCut, 10), [50]);
+        assert_eq!(place(TransitionCut::End, 60), [109]);
+    }
+
+    #[test]
+    fn segments_and_pruned_scores() {
+        let scene = |start_frame, end_frame| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: None,
+        };
+        let zones = [scene(100, 200), scene(200, 250)];
+        let segments = detection_segments(&zones, 400)
+            .into_iter()
+            .map(|(range, zone)| (range, zone.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(segments, [
+            (0..100, false),
+            (100..200, true),
+            (200..250, true),
+            (250..400, false)
+        ]);
+
+        let score = ScenecutResult {
+            inter_cost:             1.0,
+            imp_block_cost:         0.0,
+            backward_adjusted_cost: 0.0,
+            forward_adjusted_cost:  0.0,
+            threshold:              2.0,
+        };
+        let mut scores = (1..400).map(|frame| (frame, score)).collect::<BTreeMap<_, _>>();
+        prune_scores(&mut scores, &[scene(0, 50), scene(50, 300)], Some(100));
+        // the short scene keeps only its first frame, which has no score
+        assert!(!scores.contains_key(&10));
+        assert!(scores.contains_key(&50));
+        assert!(scores.contains_key(&150));
+        assert!(scores.contains_key(&350));
+
+        // nothing is pruned without a split length
+        prune_scores(&mut scores, &[scene(300, 400)], None);
+        assert!(scores.contains_key(&350));
+        // without extra splits, only the first frames of the scenes are kept
+        prune_scores(&mut scores, &[scene(300, 400)], Some(0));
+        assert!(scores.contains_key(&300));
+        assert!(!scores.contains_key(&350));
+    }
 }
@@ -0,0 +1,247 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use av_scenechange::ScenecutResult;
+use itertools::Itertools;
+use tracing::{info, warn};
+
+use crate::{ffmpeg::source_args, scenes::Scene, Input};
+
+/// Thumbnails in each row and each column of a contact sheet
+const SHEET_COLUMNS: usize = 6;
+const SHEET_ROWS: usize = 6;
+const THUMBNAIL_WIDTH: u32 = 320;
+
+/// How far the inter cost of a frame is above the scenecut threshold. Cuts
+/// close to 1 are the ones scene detection was least sure about.
+pub(crate) fn confidence(score: &ScenecutResult) -> f64 {
+    score.inter_cost / score.threshold
+}
+
+/// Text drawn on the thumbnail of the cut at `frame`
+fn label(frame: usize, score: Option<&ScenecutResult>) -> String {
+    score.map_or_else(
+        || format!("{frame}  no score"),
+        |score| format!("{frame}  {:.2}x", confidence(score)),
+    )
+}
+
+/// FFmpeg filter graph that labels the cut frames with `labels`, selects them
+/// and tiles their thumbnails into contact sheets
+fn sheet_filter(cuts: &[(usize, String)], font_size: u32, labels: bool) -> String {
+    let mut filter = String::new();
+    if labels {
+        let (offset, border) = (font_size / 2, font_size / 4);
+        for (frame, label) in cuts {
+            write!(
+                filter,
+                "drawtext=enable=eq(n\\,{frame}):text='{label}':x={offset}:y={offset}:\
+                 fontsize={font_size}:fontcolor=white:box=1:boxcolor=black@0.6:\
+                 boxborderw={border},"
+            )
+            .expect("writing to a string should not fail");
+        }
+    }
+    let select = cuts.iter().map(|(frame, _)| format!("eq(n\\,{frame})")).join("+");
+    write!(
+        filter,
+        "select={select},scale={THUMBNAIL_WIDTH}:-2,tile={SHEET_COLUMNS}x{SHEET_ROWS}:padding=4:\
+         margin=4"
+    )
+    .expect("writing to a string should not fail");
+    filter
+}
+
+/// Paths of the contact sheets for `cuts` cuts written to `output`, numbered
+/// if they don't fit on one sheet
+fn sheet_paths(output: &Path, cuts: usize) -> Vec<PathBuf> {
+    let sheets = cuts.div_ceil(SHEET_COLUMNS * SHEET_ROWS);
+    if sheets <= 1 {
+        return vec![output.to_path_buf()];
+    }
+    (1..=sheets)
+        .map(|sheet| numbered_path(output, &format!("{sheet:03}")))
+        .collect()
+}
+
+fn numbered_path(output: &Path, number: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output.extension().map_or_else(|| "jpg".into(), |ext| ext.to_string_lossy());
+    output.with_file_name(format!("{stem}-{number}.{extension}"))
+}
+
+/// Writes JPEG contact sheets of the first frame of every scene after the
+/// first, labelled with the frame number and the [`confidence`] of the cut, so
+/// that scene detection can be checked before encoding. Returns the paths of
+/// the sheets.
+pub(crate) fn write_contact_sheets(
+    input: &Input,
+    scenes: &[Scene],
+    scores: &BTreeMap<usize, ScenecutResult>,
+    output: &Path,
+    temp: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let cuts = scenes
+        .iter()
+        .skip(1)
+        .map(|scene| {
+            (
+                scene.start_frame,
+                label(scene.start_frame, scores.get(&scene.start_frame)),
+            )
+        })
+        .collect::<Vec<_>>();
+    if cuts.is_empty() {
+        info!("no scene cuts were detected, skipping the contact sheet");
+        return Ok(Vec::new());
+    }
+
+    let font_size = (input.clip_info()?.resolution.1 / 10).max(12);
+    let paths = sheet_paths(output, cuts.len());
+    let pattern = if paths.len() > 1 {
+        // the image2 muxer numbers the sheets
+        numbered_path(
+            &PathBuf::from(output.to_string_lossy().replace('%', "%%")),
+            "%03d",
+        )
+    } else {
+        output.to_path_buf()
+    };
+
+    let script = temp.join("sc_preview_filter.txt");
+    let mut result = Ok(());
+    for labels in [true, false] {
+        fs::write(&script, sheet_filter(&cuts, font_size, labels))?;
+        result = run_ffmpeg(input, &script, &pattern);
+        match &result {
+            Ok(()) => {
+                if !labels {
+                    warn!(
+                        "FFmpeg cannot draw text, which needs libfreetype and fontconfig, so the \
+                         contact sheet has no labels"
+                    );
+                }
+                break;
+            },
+            Err(e) if labels => {
+                warn!("Failed to write a labelled contact sheet, retrying without labels: {e:#}");
+            },
+            Err(_) => (),
+        }
+    }
+    fs::remove_file(&script).ok();
+    result?;
+
+    let mut lowest = cuts
+        .iter()
+        .filter_map(|(frame, _)| scores.get(frame).map(|score| (*frame, confidence(score))))
+        .collect::<Vec<_>>();
+    lowest.sort_unstable_by(|(_, a), (_, b)| a.total_cmp(b));
+    if !lowest.is_empty() {
+        info!(
+            "least confident scene cuts: {}",
+            lowest
+                .iter()
+                .take(5)
+                .map(|(frame, confidence)| format!("{frame} ({confidence:.2}x)"))
+                .join(", ")
+        );
+    }
+    Ok(paths)
+}
+
+fn run_ffmpeg(input: &Input, script: &Path, output: &Path) -> anyhow::Result<()> {
+    let mut ffmpeg = Command::new("ffmpeg");
+    ffmpeg.args(["-hide_banner", "-loglevel", "error", "-y"]);
+    let mut vspipe = match input {
+        Input::Video {
+            path, ..
+        } => {
+            ffmpeg.args(source_args(path)).stdin(Stdio::null());
+            None
+        },
+        Input::VapourSynth {
+            path,
+            vspipe_args,
+            ..
+        } => {
+            let mut vspipe = Command::new("vspipe");
+            for arg in vspipe_args {
+                vspipe.args(["-a", arg]);
+            }
+            let mut vspipe = vspipe
+                .args(["-c", "y4m"])
+                .arg(path)
+                .arg("-")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .context("Failed to run vspipe for the contact sheet")?;
+            ffmpeg
+                .args(["-i", "-"])
+                .stdin(vspipe.stdout.take().expect("vspipe stdout should exist"));
+            Some(vspipe)
+        },
+    };
+    let result = ffmpeg
+        .args(["-map", "0:v:0", "-filter_script:v"])
+        .arg(script)
+        .args(["-vsync", "0", "-q:v", "3"])
+        .arg(output)
+        .stdout(Stdio::null())
+        .output()
+        .context("Failed to run ffmpeg for the contact sheet");
+    if let Some(vspipe) = &mut vspipe {
+        vspipe.kill().ok();
+        vspipe.wait().ok();
+    }
+    let output = result?;
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contact_sheet_filter() {
+        let score = ScenecutResult {
+            inter_cost:             30.0,
+            imp_block_cost:         0.0,
+            backward_adjusted_cost: 0.0,
+            forward_adjusted_cost:  0.0,
+            threshold:              12.0,
+        };
+        let cuts = [(48, label(48, Some(&score))), (120, label(120, None))];
+        assert_eq!(cuts[0].1, "48  2.50x");
+        assert_eq!(cuts[1].1, "120  no score");
+
+        assert_eq!(
+            sheet_filter(&cuts, 40, false),
+            "select=eq(n\\,48)+eq(n\\,120),scale=320:-2,tile=6x6:padding=4:margin=4"
+        );
+        let labelled = sheet_filter(&cuts, 40, true);
+        assert!(labelled.starts_with("drawtext=enable=eq(n\\,48):text='48  2.50x':"));
+        assert!(labelled.ends_with("tile=6x6:padding=4:margin=4"));
+
+        assert_eq!(sheet_paths(Path::new("cuts.jpg"), 36), vec![PathBuf::from(
+            "cuts.jpg"
+        )]);
+        assert_eq!(sheet_paths(Path::new("out/cuts.jpg"), 37), vec![
+            PathBuf::from("out/cuts-001.jpg"),
+            PathBuf::from("out/cuts-002.jpg")
+        ]);
+    }
+}
@@ -3,15 +3,17 @@ mod tests;
 
 use std::{
     collections::{BTreeMap, HashMap},
-    fs::File,
+    fs::{self, File},
     io::Write,
+    ops::Range,
     path::Path,
     process::{exit, Command},
     str::FromStr,
     sync::atomic,
 };
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use av_scenechange::ScenecutResult;
 use itertools::Itertools;
 use nom::{
     branch::alt,
@@ -27,16 +29,29 @@ use tracing::{error, info, warn};
 
 use crate::{
     create_dir,
+    geometry::handle_geometry_changes,
     get_done,
+    hybrid::apply_hybrid_split,
     parse::valid_params,
-    scene_detect::av_scenechange_detect,
+    scene_detect::{
+        av_scenechange_detect,
+        sc_checkpoint_path,
+        sc_decoder_input,
+        sc_proxy_path,
+        CHECKPOINT_FRAMES,
+    },
+    schema,
     settings::{invalid_params, suggest_fix},
     split::extra_splits,
+    static_scenes::{apply_static_ranges, detect_static_ranges},
+    util::seconds_to_frames,
+    zones::{apply_frame_zones, join_no_split, splits_no_split, FrameZone, Zones},
     EncodeArgs,
     Encoder,
     SplitMethod,
     TargetMetric,
     TargetQuality,
+    Verbosity,
 };
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -59,13 +74,40 @@ pub struct ZoneOptions {
     pub extra_splits_len:    Option<usize>,
     pub min_scene_len:       usize,
     pub target_quality:      Option<TargetQuality>,
+    #[serde(default)]
+    pub ffmpeg_filter:       Option<String>,
+    /// Copy the source bitstream for this zone instead of re-encoding it
+    #[serde(default)]
+    pub passthrough:         bool,
+}
+
+impl ZoneOptions {
+    /// The options of the encode itself, for scenes that only differ from the
+    /// rest of the encode in a few encoder parameters
+    pub(crate) fn from_args(args: &EncodeArgs) -> Self {
+        Self {
+            encoder:             args.encoder,
+            passes:              args.passes,
+            video_params:        args.video_params.clone(),
+            photon_noise:        args.photon_noise,
+            photon_noise_height: args.photon_noise_size.1,
+            photon_noise_width:  args.photon_noise_size.0,
+            chroma_noise:        args.chroma_noise,
+            extra_splits_len:    args.extra_splits_len,
+            min_scene_len:       args.min_scene_len,
+            target_quality:      Some(args.target_quality.clone()),
+            ffmpeg_filter:       None,
+            passthrough:         false,
+        }
+    }
 }
 
 impl Scene {
+    #[inline]
     pub fn parse_from_zone(input: &str, args: &EncodeArgs, frames: usize) -> Result<Self> {
         let (_, (start, _, end, _, encoder, reset, zone_args)): (
             _,
-            (usize, _, usize, _, Encoder, bool, &str),
+            (usize, _, usize, _, Option<Encoder>, bool, &str),
         ) = (
             map_res(digit1::<&str, nom::error::Error<&str>>, str::parse),
             many1(char(' ')),
@@ -77,17 +119,20 @@ impl Scene {
                 }
             }),
             many1(char(' ')),
-            map_res(
-                alt((
-                    tag("aom"),
-                    tag("rav1e"),
-                    tag("x264"),
-                    tag("x265"),
-                    tag("vpx"),
-                    tag("svt-av1"),
-                )),
-                Encoder::from_str,
-            ),
+            alt((
+                map(tag("passthrough"), |_| None),
+                map_res(
+                    alt((
+                        tag("aom"),
+                        tag("rav1e"),
+                        tag("x264"),
+                        tag("x265"),
+                        tag("vpx"),
+                        tag("svt-av1"),
+                    )),
+                    |encoder| Encoder::from_str(encoder).map(Some),
+                ),
+            )),
             map(
                 opt(preceded(many1(char(' ')), tag("reset"))),
                 |res: Option<&str>| res.is_some(),
@@ -105,6 +150,12 @@ impl Scene {
         if start >= frames || end > frames {
             bail!("Start and end frames must not be past the end of the video");
         }
+        let Some(encoder) = encoder else {
+            if reset || !zone_args.is_empty() {
+                bail!("Passthrough zones cannot specify encoder parameters");
+            }
+            return Ok(Self::passthrough(start, end, args));
+        };
         if encoder.format() != args.encoder.format() {
             bail!(
                 "Zone specifies using {}, but this cannot be used in the same file as {}",
@@ -154,6 +205,8 @@ impl Scene {
         let mut extra_splits_len = args.extra_splits_len;
         let mut min_scene_len = args.min_scene_len;
 
+        let mut ffmpeg_filter = None;
+
         // Target Quality options
         let mut target_quality = args.target_quality.clone();
 
@@ -178,6 +231,9 @@ impl Scene {
         {
             passes = 1;
         }
+        if encoder.is_hardware() {
+            passes = 1;
+        }
         if let Some(Some(zone_photon_noise)) = zone_args.remove("--photon-noise") {
             photon_noise = Some(zone_photon_noise.parse()?);
         }
@@ -198,6 +254,17 @@ impl Scene {
         if let Some(Some(zone_min_scene_len)) = zone_args.remove("--min-scene-len") {
             min_scene_len = zone_min_scene_len.parse()?;
         }
+        if let Some(Some(zone_min_scene_sec)) = zone_args.remove("--min-scene-sec") {
+            let seconds: f64 = zone_min_scene_sec.parse()?;
+            ensure!(
+                seconds.is_finite() && seconds >= 0.0,
+                "--min-scene-sec must not be negative"
+            );
+            min_scene_len = seconds_to_frames(seconds, args.input.clip_info()?.frame_rate);
+        }
+        if let Some(Some(zone_ffmpeg_filter)) = zone_args.remove("--ffmpeg-filter") {
+            ffmpeg_filter = Some(zone_ffmpeg_filter.to_string());
+        }
         if let Some(Some(zone_target_quality)) = zone_args.remove("--target-quality") {
             let parsed = TargetQuality::parse_target_qp_range(zone_target_quality)
                 .map_err(|e| anyhow!("Invalid --target-quality: {}", e))?;
@@ -269,8 +336,8 @@ impl Scene {
 
         if !args.force {
             let help_text = {
-                let [cmd, arg] = encoder.help_command();
-                String::from_utf8_lossy(&Command::new(cmd).arg(arg).output()?.stdout).to_string()
+                let (cmd, args) = encoder.help_command();
+                String::from_utf8_lossy(&Command::new(cmd).args(args).output()?.stdout).to_string()
             };
             let valid_params = valid_params(&help_text, encoder);
             let interleaved_args: Vec<&str> = raw_zone_args
@@ -339,21 +406,182 @@ impl Scene {
                 extra_splits_len,
                 min_scene_len,
                 target_quality: Some(target_quality),
+                ffmpeg_filter,
+                passthrough: false,
             }),
         })
     }
+
+    /// A zone whose source bitstream is copied to the output without being
+    /// re-encoded
+    fn passthrough(start_frame: usize, end_frame: usize, args: &EncodeArgs) -> Self {
+        Self {
+            start_frame,
+            end_frame,
+            zone_overrides: Some(ZoneOptions {
+                encoder:             args.encoder,
+                passes:              args.passes,
+                video_params:        args.video_params.clone(),
+                photon_noise:        None,
+                photon_noise_height: None,
+                photon_noise_width:  None,
+                chroma_noise:        false,
+                extra_splits_len:    None,
+                min_scene_len:       args.min_scene_len,
+                target_quality:      Some(TargetQuality {
+                    target: None,
+                    ..args.target_quality.clone()
+                }),
+                ffmpeg_filter:       None,
+                passthrough:         true,
+            }),
+        }
+    }
+
+    /// Returns whether this scene is part of a passthrough zone
+    pub(crate) fn is_passthrough(&self) -> bool {
+        self.zone_overrides.as_ref().is_some_and(|ovr| ovr.passthrough)
+    }
+}
+
+/// Merges adjacent scenes of passthrough zones, which can only be cut on the
+/// keyframes of the source, so that each zone is copied as a single chunk.
+pub(crate) fn merge_passthrough_scenes(scenes: Vec<Scene>) -> Vec<Scene> {
+    let mut merged: Vec<Scene> = Vec::with_capacity(scenes.len());
+    for scene in scenes {
+        if let Some(last) = merged.last_mut()
+            && last.is_passthrough()
+            && scene.is_passthrough()
+            && last.end_frame == scene.start_frame
+        {
+            last.end_frame = scene.end_frame;
+        } else {
+            merged.push(scene);
+        }
+    }
+    merged
+}
+
+/// The header written at the top of an editable scenes file
+const EDITABLE_SCENES_HEADER: &str = "\
+# Av1an scenes file for --edit-scenes.
+#
+# Each scene covers the frames from `start` up to, but not including, `end`.
+# Scenes must be contiguous and cover every frame of the video, so when moving
+# a cut point, update the `end` of one scene and the `start` of the next.
+#
+# Overrides can be attached to a scene with a `zone` entry, using the same
+# syntax as a line in a zones file without the frame numbers, e.g.
+#   zone = \"aom reset --cq-level=20\"
+# Scenes exported with zone overrides list them in a `[scenes.overrides]`
+# table instead. A scene may use either `zone` or `overrides`, but not both.
+
+";
+
+/// The human-editable representation of the scenes data used by
+/// `--edit-scenes`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EditableScenes {
+    frames: usize,
+    scenes: Vec<EditableScene>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EditableScene {
+    start:     usize,
+    end:       usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zone:      Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    overrides: Option<ZoneOptions>,
+}
+
+/// Parses an editable scenes file, resolving any zone entries against the
+/// encode args, and validates that the scenes cover the whole video.
+pub(crate) fn parse_editable_scenes(
+    input: &str,
+    args: &EncodeArgs,
+    frames: usize,
+) -> Result<Vec<Scene>> {
+    let editable: EditableScenes =
+        toml::from_str(input).context("Invalid editable scenes file syntax")?;
+    if editable.frames != frames {
+        bail!(
+            "Editable scenes file was created for a video with {} frames, but the input has \
+             {frames} frames",
+            editable.frames
+        );
+    }
+    if editable.scenes.is_empty() {
+        bail!("Editable scenes file does not contain any scenes");
+    }
+
+    let mut next_start = 0;
+    let mut scenes = Vec::with_capacity(editable.scenes.len());
+    for scene in editable.scenes {
+        if scene.start >= scene.end {
+            bail!(
+                "Scene {}-{}: start frame must be earlier than the end frame",
+                scene.start,
+                scene.end
+            );
+        }
+        if scene.start != next_start {
+            bail!(
+                "Scene {}-{}: expected scene to start at frame {next_start}; scenes must be \
+                 sorted and contiguous",
+                scene.start,
+                scene.end
+            );
+        }
+        next_start = scene.end;
+
+        let zone_overrides = match (scene.zone, scene.overrides) {
+            (Some(_), Some(_)) => bail!(
+                "Scene {}-{}: `zone` and `overrides` cannot both be specified",
+                scene.start,
+                scene.end
+            ),
+            (Some(zone), None) => {
+                Scene::parse_from_zone(
+                    &format!("{} {} {}", scene.start, scene.end, zone.trim()),
+                    args,
+                    frames,
+                )
+                .with_context(|| format!("Scene {}-{}: invalid zone", scene.start, scene.end))?
+                .zone_overrides
+            },
+            (None, overrides) => overrides,
+        };
+        scenes.push(Scene {
+            start_frame: scene.start,
+            end_frame: scene.end,
+            zone_overrides,
+        });
+    }
+    if next_start != frames {
+        bail!("Scenes end at frame {next_start}, but the video has {frames} frames");
+    }
+
+    Ok(scenes)
 }
 
 /// This struct is responsible for choosing and building a list of video chunks.
 /// It is responsible for managing both scene detection and extra splits.
 #[derive(Debug)]
 pub struct SceneFactory {
-    data: ScenesData,
+    data:   ScenesData,
+    /// Scenecut scores of the frames, only known after
+    /// [`Self::compute_scenes`] ran scene detection
+    scores: BTreeMap<usize, ScenecutResult>,
 }
 
 /// A serializable data struct containing scenecut data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScenesData {
+    version:      u64,
     frames:       usize,
     scenes:       Option<Vec<Scene>>,
     split_scenes: Option<Vec<Scene>>,
@@ -363,42 +591,67 @@ impl SceneFactory {
     /// Return a new, empty factory for computing scenes and chunks.
     pub fn new() -> Self {
         Self {
-            data: ScenesData {
+            data:   ScenesData {
+                version:      schema::SCENES.version(),
                 frames:       0,
                 scenes:       None,
                 split_scenes: None,
             },
+            scores: BTreeMap::new(),
         }
     }
 
     /// This loads a list of scenes from a JSON file and returns a factory with
     /// the scenes data.
     pub fn from_scenes_file<P: AsRef<Path>>(scene_path: &P) -> anyhow::Result<Self> {
-        let file = File::open(scene_path)?;
-        let mut data: ScenesData = serde_json::from_reader(file).with_context(|| {
+        let contents = fs::read_to_string(scene_path)?;
+        let data: ScenesData = schema::SCENES.load(&contents).with_context(|| {
             format!(
-                "Failed to parse scenes file {}, this likely means that the scenes file is \
-                 corrupted",
+                "Failed to load scenes file {}",
                 scene_path.as_ref().display()
             )
         })?;
-        if data.scenes.is_some() && data.split_scenes.is_none() {
-            // If a user is using a scenes file from an older build of av1an,
-            // we need to copy the list of scenes to the `split_scenes` array.
-            // We won't be able to do special pre-split analysis because the scenes
-            // list in the old format was always post-split, but at least the
-            // encode won't error out.
-            data.split_scenes = data.scenes.clone();
-        }
         get_done().frames.store(data.frames, atomic::Ordering::SeqCst);
 
         Ok(Self {
             data,
+            scores: BTreeMap::new(),
+        })
+    }
+
+    /// This loads a list of scenes from an editable TOML file written by
+    /// [`SceneFactory::write_editable_scenes_file`] and returns a factory with
+    /// the scenes data.
+    pub fn from_editable_scenes_file<P: AsRef<Path>>(
+        scene_path: &P,
+        args: &EncodeArgs,
+        frames: usize,
+    ) -> anyhow::Result<Self> {
+        let input = fs::read_to_string(scene_path)?;
+        let scenes = parse_editable_scenes(&input, args, frames).with_context(|| {
+            format!(
+                "Failed to load editable scenes file {}",
+                scene_path.as_ref().display()
+            )
+        })?;
+        info!(
+            "scenecut: loaded {} scene(s) from editable scenes file",
+            scenes.len()
+        );
+        get_done().frames.store(frames, atomic::Ordering::SeqCst);
+
+        Ok(Self {
+            data:   ScenesData {
+                version: schema::SCENES.version(),
+                frames,
+                scenes: Some(scenes.clone()),
+                split_scenes: Some(scenes),
+            },
+            scores: BTreeMap::new(),
         })
     }
 
     /// Retrieve the pre-extra-split scenes data
-    #[expect(dead_code)]
     pub fn get_scenecuts(&self) -> anyhow::Result<&[Scene]> {
         if self.data.scenes.is_none() {
             bail!("compute_scenes must be called first");
@@ -420,6 +673,11 @@ impl SceneFactory {
         self.data.frames
     }
 
+    /// Scenecut scores of the frames, empty unless scene detection ran
+    pub(crate) const fn scores(&self) -> &BTreeMap<usize, ScenecutResult> {
+        &self.scores
+    }
+
     /// Write the scenes data to the specified file as JSON
     pub fn write_scenes_to_file<P: AsRef<Path>>(&self, scene_path: P) -> anyhow::Result<()> {
         if self.data.scenes.is_none() {
@@ -438,18 +696,58 @@ impl SceneFactory {
         Ok(())
     }
 
+    /// Write the post-extra-split scenes data to the specified file as
+    /// human-editable TOML, to be read back with
+    /// [`SceneFactory::from_editable_scenes_file`]
+    pub fn write_editable_scenes_file<P: AsRef<Path>>(&self, scene_path: P) -> anyhow::Result<()> {
+        let scenes = self.get_split_scenes()?;
+
+        if let Some(parent) = scene_path.as_ref().parent() {
+            create_dir!(parent)?;
+        }
+
+        let editable = EditableScenes {
+            frames: self.data.frames,
+            scenes: scenes
+                .iter()
+                .map(|scene| EditableScene {
+                    start:     scene.start_frame,
+                    end:       scene.end_frame,
+                    zone:      None,
+                    overrides: scene.zone_overrides.clone(),
+                })
+                .collect(),
+        };
+        let toml = toml::to_string(&editable)?;
+
+        let mut file = File::create(scene_path)?;
+        file.write_all(EDITABLE_SCENES_HEADER.as_bytes())?;
+        file.write_all(toml.as_bytes())?;
+
+        Ok(())
+    }
+
     /// This runs scene detection and populates a list of scenes into the
     /// factory. This function must be called before getting the list of scenes
     /// or writing to the file.
-    pub fn compute_scenes(&mut self, args: &EncodeArgs, zones: &[Scene]) -> anyhow::Result<()> {
+    pub fn compute_scenes(&mut self, args: &EncodeArgs, zones: &Zones) -> anyhow::Result<()> {
         // We should only be calling this when scenes haven't been created yet
         debug_assert!(self.data.scenes.is_none());
 
+        let frame_zones = zones.frame_zones.as_slice();
+        let no_split = zones.no_split.as_slice();
+        let zones = zones.scenes.as_slice();
+
         let frames = args.input.clip_info()?.num_frames;
+        let static_ranges = detect_static_ranges(args)?;
 
-        let (mut scenes, frames, scores) = match args.split_method {
+        let (scenes, frames, scores) = match args.split_method {
             SplitMethod::AvScenechange => av_scenechange_detect(
-                args.proxy.as_ref().unwrap_or(&args.input),
+                &*sc_decoder_input(
+                    args.proxy.as_ref().unwrap_or(&args.input),
+                    args.sc_decoder,
+                    args.vapoursynth_plugins,
+                )?,
                 args.encoder,
                 frames,
                 args.min_scene_len,
@@ -458,7 +756,20 @@ impl SceneFactory {
                 args.sc_pix_format,
                 args.sc_method,
                 args.sc_downscale_height,
+                args.sc_decoder,
+                args.sc_transitions,
                 zones,
+                no_split,
+                args.sc_proxy.then(|| sc_proxy_path(&args.temp)).as_deref(),
+                args.sc_checkpoint.then(|| sc_checkpoint_path(&args.temp)).as_deref(),
+                scores_split_len(args),
+                // without checkpoints, the whole input is one window
+                if args.sc_checkpoint {
+                    CHECKPOINT_FRAMES
+                } else {
+                    usize::MAX
+                },
+                None,
             )?,
             SplitMethod::None => {
                 let mut scenes = Vec::with_capacity(2 * zones.len() + 1);
@@ -493,28 +804,7 @@ impl SceneFactory {
 
         self.data.frames = frames;
         get_done().frames.store(frames, atomic::Ordering::SeqCst);
-
-        // Add forced keyframes
-        for kf in &args.force_keyframes {
-            if let Some((scene_pos, s)) =
-                scenes.iter_mut().find_position(|s| (s.start_frame..s.end_frame).contains(kf))
-            {
-                if *kf == s.start_frame {
-                    // Already a keyframe
-                    continue;
-                }
-                // Split this scene into two scenes at the requested keyframe
-                let mut new = s.clone();
-                s.end_frame = *kf;
-                new.start_frame = *kf;
-                scenes.insert(scene_pos + 1, new);
-            } else {
-                warn!(
-                    "scene {kf} was requested as a forced keyframe but video has {frames} frames, \
-                     ignoring"
-                );
-            }
-        }
+        warn_missing_keyframes(args, frames);
 
         if let Some(scene) = scenes.last() {
             assert!(
@@ -525,25 +815,219 @@ impl SceneFactory {
             );
         }
 
-        let scenes_before = scenes.len();
+        let (scenes, split_scenes) = finish_scenes(
+            args,
+            scenes,
+            &scores,
+            frame_zones,
+            no_split,
+            &static_ranges,
+            true,
+        )?;
+        log_scene_counts(args, &scenes, &split_scenes);
         self.data.scenes = Some(scenes);
+        self.data.split_scenes = Some(split_scenes);
+        self.scores = scores;
 
-        if let Some(split_len @ 1..) = args.extra_splits_len {
-            self.data.split_scenes = Some(extra_splits(
-                self.data.scenes.as_deref().expect("scenes is set"),
-                split_len,
-                &scores,
-            ));
-            let scenes_after = self.data.split_scenes.as_ref().expect("split_scenes is set").len();
-            info!(
-                "scenecut: found {scenes_before} scene(s) [with extra_splits ({split_len} \
-                 frames): {scenes_after} scene(s)]"
-            );
-        } else {
-            self.data.split_scenes = self.data.scenes.clone();
-            info!("scenecut: found {scenes_before} scene(s)");
-        }
+        Ok(())
+    }
+
+    /// Like [`Self::compute_scenes`], but hands every batch of split scenes to
+    /// `on_scenes` while scene detection continues, after every `window`
+    /// frames it analysed. Only used with av-scenechange, and without
+    /// `--geometry-changes`, which compares the scenes of the whole video.
+    pub(crate) fn compute_scenes_pipelined(
+        &mut self,
+        args: &EncodeArgs,
+        zones: &Zones,
+        window: usize,
+        on_scenes: &mut dyn FnMut(&[Scene]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        debug_assert!(self.data.scenes.is_none());
+
+        let frame_zones = zones.frame_zones.as_slice();
+        let frames = args.input.clip_info()?.num_frames;
+        get_done().frames.store(frames, atomic::Ordering::SeqCst);
+        warn_missing_keyframes(args, frames);
+
+        let mut scenes = Vec::new();
+        let mut split_scenes = Vec::new();
+        let mut held = Vec::new();
+        let mut finish = |closed: &[Scene],
+                          scores: &BTreeMap<usize, ScenecutResult>,
+                          last: bool|
+         -> anyhow::Result<()> {
+            held.extend_from_slice(closed);
+            // passthrough scenes at the end may be merged with the first ones
+            // of the next window
+            let ready = if last {
+                held.len()
+            } else {
+                held.len() - held.iter().rev().take_while(|scene| scene.is_passthrough()).count()
+            };
+            if ready == 0 {
+                return Ok(());
+            }
+            let (batch, split_batch) = finish_scenes(
+                args,
+                held.drain(..ready).collect(),
+                scores,
+                frame_zones,
+                &zones.no_split,
+                &[],
+                false,
+            )?;
+            on_scenes(&split_batch)?;
+            scenes.extend(batch);
+            split_scenes.extend(split_batch);
+            Ok(())
+        };
+
+        let (_, frames, scores) = av_scenechange_detect(
+            &*sc_decoder_input(
+                args.proxy.as_ref().unwrap_or(&args.input),
+                args.sc_decoder,
+                args.vapoursynth_plugins,
+            )?,
+            args.encoder,
+            frames,
+            args.min_scene_len,
+            // the workers show the progress of the encode instead
+            Verbosity::Quiet,
+            args.scaler.as_str(),
+            args.sc_pix_format,
+            args.sc_method,
+            args.sc_downscale_height,
+            args.sc_decoder,
+            args.sc_transitions,
+            &zones.scenes,
+            &zones.no_split,
+            None,
+            args.sc_checkpoint.then(|| sc_checkpoint_path(&args.temp)).as_deref(),
+            scores_split_len(args),
+            window,
+            Some(
+                &mut |closed: &[Scene], scores: &BTreeMap<usize, ScenecutResult>| {
+                    finish(closed, scores, false)
+                },
+            ),
+        )?;
+        finish(&[], &scores, true)?;
+
+        self.data.frames = frames;
+        get_done().frames.store(frames, atomic::Ordering::SeqCst);
+        log_scene_counts(args, &scenes, &split_scenes);
+        self.data.scenes = Some(scenes);
+        self.data.split_scenes = Some(split_scenes);
+        self.scores = scores;
 
         Ok(())
     }
 }
+
+/// Warns about the forced keyframes past the end of the video
+fn warn_missing_keyframes(args: &EncodeArgs, frames: usize) {
+    for kf in args.force_keyframes.iter().filter(|&&kf| kf >= frames) {
+        warn!(
+            "scene {kf} was requested as a forced keyframe but video has {frames} frames, ignoring"
+        );
+    }
+}
+
+/// Split length scene detection prunes its scores with, see
+/// [`prune_scores`](crate::scene_detect::prune_scores). The hybrid split
+/// measures the complexity of every scene, so it keeps all of them.
+fn scores_split_len(args: &EncodeArgs) -> Option<usize> {
+    args.hybrid_encoder.is_none().then(|| args.extra_splits_len.unwrap_or(0))
+}
+
+/// Applies the forced keyframes, runs of identical frames, passthrough zones,
+/// geometry changes, extra splits and frame zones to detected scenes,
+/// returning the scenes and the split scenes. None of them cuts within the
+/// `no_split` ranges.
+fn finish_scenes(
+    args: &EncodeArgs,
+    mut scenes: Vec<Scene>,
+    scores: &BTreeMap<usize, ScenecutResult>,
+    frame_zones: &[FrameZone],
+    no_split: &[Range<usize>],
+    static_ranges: &[Range<usize>],
+    geometry_changes: bool,
+) -> anyhow::Result<(Vec<Scene>, Vec<Scene>)> {
+    // Add forced keyframes
+    for kf in &args.force_keyframes {
+        if let Some((scene_pos, s)) =
+            scenes.iter_mut().find_position(|s| (s.start_frame..s.end_frame).contains(kf))
+        {
+            if *kf == s.start_frame {
+                // Already a keyframe
+                continue;
+            }
+            if splits_no_split(no_split, *kf) {
+                warn!("forced keyframe {kf} is within a no-split zone, ignoring");
+                continue;
+            }
+            // Split this scene into two scenes at the requested keyframe
+            let mut new = s.clone();
+            s.end_frame = *kf;
+            new.start_frame = *kf;
+            scenes.insert(scene_pos + 1, new);
+        }
+    }
+
+    if !static_ranges.is_empty() {
+        scenes = apply_static_ranges(args, scenes, static_ranges);
+    }
+    if args.hybrid_encoder.is_some() {
+        scenes = apply_hybrid_split(args, scenes, scores);
+    }
+
+    let mut scenes = merge_passthrough_scenes(join_no_split(scenes, no_split));
+    if geometry_changes {
+        handle_geometry_changes(args, &mut scenes)?;
+    }
+
+    let split_len = args.extra_splits_len.unwrap_or(0);
+    let mut split_scenes = if split_len > 0 || has_zone_splits(&scenes) {
+        merge_passthrough_scenes(join_no_split(
+            extra_splits(&scenes, split_len, scores),
+            no_split,
+        ))
+    } else {
+        scenes.clone()
+    };
+
+    if !frame_zones.is_empty() {
+        apply_frame_zones(&mut split_scenes, frame_zones, args);
+    }
+
+    Ok((scenes, split_scenes))
+}
+
+/// Returns whether a zone of the scenes sets its own extra split length
+fn has_zone_splits(scenes: &[Scene]) -> bool {
+    scenes.iter().any(|scene| {
+        scene
+            .zone_overrides
+            .as_ref()
+            .is_some_and(|ovr| ovr.extra_splits_len.is_some_and(|len| len > 0))
+    })
+}
+
+fn log_scene_counts(args: &EncodeArgs, scenes: &[Scene], split_scenes: &[Scene]) {
+    let split_len = args.extra_splits_len.unwrap_or(0);
+    if split_len > 0 || has_zone_splits(scenes) {
+        let split_len = if split_len > 0 {
+            format!("{split_len} frames")
+        } else {
+            "zones only".to_string()
+        };
+        info!(
+            "scenecut: found {} scene(s) [with extra_splits ({split_len}): {} scene(s)]",
+            scenes.len(),
+            split_scenes.len()
+        );
+    } else {
+        info!("scenecut: found {} scene(s)", scenes.len());
+    }
+}
@@ -1,9 +1,27 @@
-use std::str::FromStr;
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use av_scenechange::ScenecutResult;
 
 use crate::{
     context::Av1anContext,
     encoder::Encoder,
-    scenes::{Scene, SceneFactory},
+    scene_detect::prune_scores,
+    scenes::{
+        finish_scenes,
+        merge_passthrough_scenes,
+        parse_editable_scenes,
+        scores_split_len,
+        Scene,
+        SceneFactory,
+        ZoneOptions,
+    },
+    target_quality::ProbeResCorrection,
+    zones::FrameZone,
     InterpolationMethod,
     ProbingStatistic,
     TargetMetric,
@@ -13,85 +31,178 @@ use crate::{
 fn get_test_args() -> Av1anContext {
     use std::path::PathBuf;
 
+    use av1_grain::TransferFunction;
+    use av_format::rational::Rational64;
+
     use crate::{
         concat::ConcatMethod,
-        ffmpeg::FFPixelFormat,
+        dimensions::OddDimensions,
+        ffmpeg::{CaptionMode, FFPixelFormat},
+        geometry::GeometryChanges,
         into_vec,
         settings::{EncodeArgs, InputPixelFormat, PixelFormat},
         vapoursynth::CacheSource,
         ChunkMethod,
+        ChunkNames,
         ChunkOrdering,
+        ClipInfo,
         Input,
+        ScDecoder,
         ScenecutMethod,
         SplitMethod,
+        TransitionCut,
         Verbosity,
     };
 
     let args = EncodeArgs {
-        ffmpeg_filter_args:    Vec::new(),
-        temp:                  String::new(),
-        force:                 false,
-        no_defaults:           false,
-        passes:                2,
-        video_params:          into_vec!["--cq-level=40", "--cpu-used=0", "--aq-mode=1"],
-        output_file:           String::new(),
-        audio_params:          Vec::new(),
-        chunk_method:          ChunkMethod::LSMASH,
-        chunk_order:           ChunkOrdering::Random,
-        concat:                ConcatMethod::FFmpeg,
-        encoder:               Encoder::aom,
-        extra_splits_len:      Some(100),
-        photon_noise:          Some(10),
-        photon_noise_size:     (None, None),
-        chroma_noise:          false,
-        sc_pix_format:         None,
-        keep:                  false,
-        max_tries:             3,
-        min_scene_len:         10,
-        input_pix_format:      InputPixelFormat::FFmpeg {
+        ffmpeg_filter_args:     Vec::new(),
+        intermediate:           None,
+        odd_dimensions:         OddDimensions::Pad,
+        deband:                 None,
+        burn_subs:              None,
+        temp:                   String::new(),
+        relocated_from:         None,
+        temp_registry:          None,
+        force:                  false,
+        no_defaults:            false,
+        passes:                 2,
+        video_params:           into_vec!["--cq-level=40", "--cpu-used=0", "--aq-mode=1"],
+        first_pass_params:      Vec::new(),
+        tier:                   None,
+        output_file:            String::new(),
+        audio_params:           Vec::new(),
+        captions:               CaptionMode::Ignore,
+        audio_normalize:        None,
+        chunk_method:           ChunkMethod::LSMASH,
+        chunk_order:            ChunkOrdering::Random,
+        concat:                 ConcatMethod::FFmpeg,
+        concat_fallback:        true,
+        chunk_names:            ChunkNames::default(),
+        title:                  None,
+        track_language:         None,
+        settings_tags:          false,
+        optimize_mux:           false,
+        verify_chunks:          false,
+        chunk_check:            None,
+        encoder:                Encoder::aom,
+        extra_splits_len:       Some(100),
+        target_chunk_time:      None,
+        deadline:               None,
+        photon_noise:           Some(10),
+        photon_noise_size:      (None, None),
+        chroma_noise:           false,
+        sc_pix_format:          None,
+        keep:                   false,
+        html_report:            false,
+        max_tries:              3,
+        two_stage:              None,
+        interactive:            false,
+        assume_yes:             false,
+        min_scene_len:          10,
+        input_pix_format:       InputPixelFormat::FFmpeg {
             format: FFPixelFormat::YUV420P10LE,
         },
-        input:                 Input::Video {
+        input:                  Input::Video {
             path:         PathBuf::new(),
             temp:         String::new(),
             chunk_method: ChunkMethod::LSMASH,
             is_proxy:     false,
             cache_mode:   CacheSource::SOURCE,
         },
-        proxy:                 None,
-        output_pix_format:     PixelFormat {
+        proxy:                  None,
+        output_pix_format:      PixelFormat {
             format:    FFPixelFormat::YUV420P10LE,
             bit_depth: 10,
         },
-        resume:                false,
-        scenes:                None,
-        split_method:          SplitMethod::AvScenechange,
-        sc_method:             ScenecutMethod::Standard,
-        sc_only:               false,
-        sc_downscale_height:   None,
-        force_keyframes:       Vec::new(),
-        target_quality:        TargetQuality::default("", Encoder::aom),
-        vmaf:                  false,
-        verbosity:             Verbosity::Normal,
-        workers:               1,
-        tiles:                 (1, 1),
-        tile_auto:             false,
-        set_thread_affinity:   None,
-        zones:                 None,
-        scaler:                String::new(),
-        ignore_frame_mismatch: false,
-        vmaf_path:             None,
-        vmaf_res:              "1920x1080".to_string(),
-        vmaf_threads:          None,
-        vmaf_filter:           None,
-        probe_res:             None,
-        vapoursynth_plugins:   None,
-        cache_mode:            CacheSource::SOURCE,
-        pix_format_converter:  crate::PixelFormatConverter::FFMPEG,
+        resume:                 false,
+        shared_temp:            false,
+        scenes:                 None,
+        edit_scenes:            None,
+        split_method:           SplitMethod::AvScenechange,
+        sc_method:              ScenecutMethod::Standard,
+        sc_decoder:             ScDecoder::Auto,
+        sc_transitions:         TransitionCut::Detected,
+        geometry_changes:       GeometryChanges::Ignore,
+        static_scenes:          None,
+        static_params:          Vec::new(),
+        hybrid_encoder:         None,
+        hybrid_params:          Vec::new(),
+        hybrid_share:           0.5,
+        sc_only:                false,
+        sc_preview:             None,
+        sc_downscale_height:    None,
+        sc_proxy:               false,
+        sc_checkpoint:          false,
+        sc_lookahead:           None,
+        force_keyframes:        Vec::new(),
+        target_quality:         TargetQuality::default("", Encoder::aom),
+        min_chunk_score:        None,
+        vmaf:                   false,
+        metrics:                Vec::new(),
+        verbosity:              Verbosity::Normal,
+        workers:                1,
+        tiles:                  (1, 1),
+        tile_auto:              false,
+        set_thread_affinity:    None,
+        tail_boost:             false,
+        afterburner:            0,
+        probe_workers:          0,
+        vs_server:              false,
+        gpus:                   Vec::new(),
+        output_limit:           1 << 20,
+        spill_output:           false,
+        pipe_size:              None,
+        memfd_buffer:           None,
+        gpu_workers:            None,
+        peak_bitrate:           None,
+        peak_buffer:            2.0,
+        zones:                  None,
+        zones_only:             false,
+        trim:                   None,
+        copy_video:             false,
+        scaler:                 String::new(),
+        ignore_frame_mismatch:  false,
+        ignore_linked_segments: false,
+        output_fps:             None,
+        fps_override:           None,
+        vmaf_path:              None,
+        vmaf_res:               "1920x1080".to_string(),
+        vmaf_threads:           None,
+        vmaf_cuda:              false,
+        vmaf_filter:            None,
+        vmaf_tonemap:           None,
+        probe_res:              None,
+        vapoursynth_plugins:    None,
+        cache_mode:             CacheSource::SOURCE,
+        pix_format_converter:   crate::PixelFormatConverter::FFMPEG,
     };
     Av1anContext {
+        clip_info: ClipInfo {
+            num_frames:               6900,
+            format_info:              InputPixelFormat::FFmpeg {
+                format: FFPixelFormat::YUV420P10LE,
+            },
+            frame_rate:               Rational64::new(24, 1),
+            resolution:               (1920, 1080),
+            color_range:              None,
+            transfer_characteristics: TransferFunction::BT1886,
+        },
         vs_script: None,
         vs_proxy_script: None,
+        previous_report: None,
+        speed_probe: None,
+        chunk_scheduler: Mutex::new(None),
+        vs_servers: Mutex::new(HashMap::new()),
+        prepared: false,
+        scenes: None,
+        zone_ranges: None,
+        chunk_queue: None,
+        total_chunks: None,
+        audio_source: None,
+        started: Instant::now(),
+        timings: Vec::new(),
+        probe_time: Mutex::new(Duration::ZERO),
+        res_correction: Mutex::new(ProbeResCorrection::default()),
         frames: 6900,
         args,
         scene_factory: SceneFactory::new(),
@@ -305,3 +416,326 @@ fn validate_zones_target_quality() {
         ))
     );
 }
+
+#[test]
+fn validate_zones_ffmpeg_filter() {
+    let input = "45 729 aom --cq-level=20 --ffmpeg-filter gradfun=radius=16";
+    let mut args = get_test_args();
+    args.args.force = true;
+    let result = Scene::parse_from_zone(input, &args.args, args.frames)
+        .expect("should parse zone successfully");
+
+    let zone_overrides = result.zone_overrides.expect("zone overrides should exist");
+    assert_eq!(
+        zone_overrides.ffmpeg_filter.as_deref(),
+        Some("gradfun=radius=16")
+    );
+    assert!(!zone_overrides.video_params.iter().any(|param| param.contains("gradfun")));
+}
+
+#[test]
+fn editable_scenes_round_trip() {
+    let mut args = get_test_args();
+    args.args.force = true;
+    let zone = Scene::parse_from_zone("100 6900 aom --cq-level=20", &args.args, args.frames)
+        .expect("should parse zone successfully");
+    let mut factory = SceneFactory::new();
+    factory.data.frames = args.frames;
+    factory.data.scenes = Some(vec![
+        Scene {
+            start_frame:    0,
+            end_frame:      100,
+            zone_overrides: None,
+        },
+        zone,
+    ]);
+    factory.data.split_scenes = factory.data.scenes.clone();
+
+    let dir = tempfile::tempdir().expect("should create temp dir");
+    let path = dir.path().join("scenes.toml");
+    factory.write_editable_scenes_file(&path).expect("should write editable scenes");
+    let mut input = std::fs::read_to_string(&path).expect("should read editable scenes");
+    let scenes =
+        parse_editable_scenes(&input, &args.args, args.frames).expect("should parse scenes");
+    assert_eq!(scenes.len(), 2);
+    assert_eq!((scenes[0].start_frame, scenes[0].end_frame), (0, 100));
+    assert!(scenes[0].zone_overrides.is_none());
+    assert_eq!((scenes[1].start_frame, scenes[1].end_frame), (100, 6900));
+    let zone_overrides = scenes[1].zone_overrides.as_ref().expect("zone overrides should exist");
+    assert!(zone_overrides.video_params.contains(&"--cq-level=20".to_owned()));
+
+    // A scene can't have both a zone and exported overrides
+    input = input.replacen("end = 6900\n", "end = 6900\nzone = \"aom\"\n", 1);
+    let err = parse_editable_scenes(&input, &args.args, args.frames)
+        .expect_err("should reject zone with overrides");
+    assert!(err.to_string().contains("cannot both be specified"));
+}
+
+#[test]
+fn editable_scenes_zone_entry() {
+    let mut args = get_test_args();
+    args.args.force = true;
+    let input = "frames = 6900\n[[scenes]]\nstart = 0\nend = 3000\n[[scenes]]\nstart = 3000\nend \
+                 = 6900\nzone = \"aom reset --cq-level=20\"\n";
+    let scenes =
+        parse_editable_scenes(input, &args.args, args.frames).expect("should parse scenes");
+    assert_eq!(scenes.len(), 2);
+    assert!(scenes[0].zone_overrides.is_none());
+    let zone_overrides = scenes[1].zone_overrides.as_ref().expect("zone overrides should exist");
+    assert_eq!(
+        zone_overrides.video_params,
+        vec!["--cq-level=20".to_owned()]
+    );
+}
+
+#[test]
+fn editable_scenes_rejects_invalid_layouts() {
+    let args = get_test_args();
+    for input in [
+        // Gap between scenes
+        "frames = 6900\n[[scenes]]\nstart = 0\nend = 3000\n[[scenes]]\nstart = 3001\nend = 6900\n",
+        // Does not cover the whole video
+        "frames = 6900\n[[scenes]]\nstart = 0\nend = 3000\n",
+        // Wrong frame count
+        "frames = 100\n[[scenes]]\nstart = 0\nend = 100\n",
+        // Empty scene
+        "frames = 6900\n[[scenes]]\nstart = 0\nend = 0\n[[scenes]]\nstart = 0\nend = 6900\n",
+    ] {
+        assert!(
+            parse_editable_scenes(input, &args.args, args.frames).is_err(),
+            "should reject {input:?}"
+        );
+    }
+}
+
+#[test]
+fn validate_zones_passthrough() {
+    let args = get_test_args();
+    let result = Scene::parse_from_zone("48 -1 passthrough", &args.args, args.frames)
+        .expect("should parse zone successfully");
+    assert_eq!(result.start_frame, 48);
+    assert_eq!(result.end_frame, 6900);
+    assert!(result.is_passthrough());
+    let zone_overrides = result.zone_overrides.expect("zone overrides should exist");
+    assert!(zone_overrides
+        .target_quality
+        .expect("target quality should be set")
+        .target
+        .is_none());
+
+    assert!(
+        Scene::parse_from_zone("48 96 passthrough --cq-level=20", &args.args, args.frames).is_err()
+    );
+    assert!(Scene::parse_from_zone("48 96 passthrough reset", &args.args, args.frames).is_err());
+}
+
+#[test]
+fn merge_passthrough_zone_scenes() {
+    let args = get_test_args();
+    let zone = Scene::parse_from_zone("0 30 passthrough", &args.args, args.frames)
+        .expect("should parse zone successfully");
+    let scene = |start_frame, end_frame, passthrough: bool| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides: passthrough.then(|| zone.zone_overrides.clone()).flatten(),
+    };
+    let merged = merge_passthrough_scenes(vec![
+        scene(0, 10, false),
+        scene(10, 20, true),
+        scene(20, 30, true),
+        scene(30, 40, false),
+        scene(40, 50, false),
+        scene(50, 60, true),
+    ]);
+    assert_eq!(
+        merged
+            .iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>(),
+        vec![(0, 10), (10, 30), (30, 40), (40, 50), (50, 60)]
+    );
+}
+
+#[test]
+fn finish_scenes_in_batches() {
+    let mut args = get_test_args();
+    args.args.force_keyframes = vec![15, 45];
+    let scene = |start_frame, end_frame| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides: None,
+    };
+    let frames = |scenes: &[Scene]| {
+        scenes
+            .iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>()
+    };
+    let scores = BTreeMap::new();
+
+    let (_, whole) = finish_scenes(
+        &args.args,
+        vec![scene(0, 10), scene(10, 30), scene(30, 60)],
+        &scores,
+        &[],
+        &[],
+        &[],
+        false,
+    )
+    .expect("should finish scenes");
+    let (_, first) = finish_scenes(
+        &args.args,
+        vec![scene(0, 10), scene(10, 30)],
+        &scores,
+        &[],
+        &[],
+        &[],
+        false,
+    )
+    .expect("should finish scenes");
+    let (_, second) = finish_scenes(
+        &args.args,
+        vec![scene(30, 60)],
+        &scores,
+        &[],
+        &[],
+        &[],
+        false,
+    )
+    .expect("should finish scenes");
+    assert_eq!(frames(&whole), vec![
+        (0, 10),
+        (10, 15),
+        (15, 30),
+        (30, 45),
+        (45, 60)
+    ]);
+    assert_eq!([frames(&first), frames(&second)].concat(), frames(&whole));
+
+    // the forced keyframes are ignored, and the scene cut at 10 is joined
+    let (_, joined) = finish_scenes(
+        &args.args,
+        vec![scene(0, 10), scene(10, 30), scene(30, 60)],
+        &scores,
+        &[],
+        &[5..20, 40..50],
+        &[],
+        false,
+    )
+    .expect("should finish scenes");
+    assert_eq!(frames(&joined), vec![(0, 30), (30, 60)]);
+}
+
+#[test]
+fn hybrid_split_of_short_scenes() {
+    let mut args = get_test_args();
+    args.args.extra_splits_len = Some(100);
+    args.args.hybrid_encoder = Some(Encoder::av1_nvenc);
+    args.args.hybrid_share = 0.1;
+    let scene = |start_frame, end_frame| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides: None,
+    };
+    let scenes = vec![scene(0, 50), scene(50, 300)];
+    // the short first scene is static, the long one busy
+    let mut scores = (1..300)
+        .map(|frame| {
+            (frame, ScenecutResult {
+                inter_cost:             if frame < 50 { 1.0 } else { 10.0 },
+                imp_block_cost:         0.0,
+                backward_adjusted_cost: 0.0,
+                forward_adjusted_cost:  0.0,
+                threshold:              20.0,
+            })
+        })
+        .collect::<BTreeMap<_, _>>();
+    // as scene detection does after every window
+    prune_scores(&mut scores, &scenes, scores_split_len(&args.args));
+
+    let (scenes, _) = finish_scenes(&args.args, scenes, &scores, &[], &[], &[], false)
+        .expect("should finish scenes");
+    let encoder =
+        |scene: &Scene| scene.zone_overrides.as_ref().map_or(args.args.encoder, |ovr| ovr.encoder);
+    assert_eq!(encoder(&scenes[0]), Encoder::av1_nvenc);
+    assert_eq!(encoder(&scenes[1]), args.args.encoder);
+    let target_quality = scenes[0]
+        .zone_overrides
+        .as_ref()
+        .and_then(|ovr| ovr.target_quality.as_ref())
+        .expect("should have target quality options");
+    assert_eq!(target_quality.encoder, Encoder::av1_nvenc);
+}
+
+#[test]
+fn frame_zones_skip_scenes_of_other_encoders() {
+    let mut args = get_test_args();
+    args.args.encoder = Encoder::x264;
+    let mut x265 = ZoneOptions::from_args(&args.args);
+    x265.encoder = Encoder::x265;
+    let scene = |start_frame, end_frame, zone_overrides| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides,
+    };
+    let frame_zones = [FrameZone {
+        start_frame: 0,
+        end_frame:   200,
+        qp_offset:   -6,
+    }];
+
+    let (_, split_scenes) = finish_scenes(
+        &args.args,
+        vec![scene(0, 100, None), scene(100, 200, Some(x265))],
+        &BTreeMap::new(),
+        &frame_zones,
+        &[],
+        &[],
+        false,
+    )
+    .expect("should finish scenes");
+    let has_zones = |scene: &Scene| {
+        scene
+            .zone_overrides
+            .as_ref()
+            .is_some_and(|ovr| ovr.video_params.iter().any(|param| param == "--zones"))
+    };
+    assert!(has_zones(&split_scenes[0]));
+    assert!(!has_zones(&split_scenes[1]));
+}
+
+#[test]
+#[expect(clippy::single_range_in_vec_init)]
+fn finish_scenes_with_static_ranges() {
+    let args = get_test_args();
+    let scene = |start_frame, end_frame| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides: None,
+    };
+
+    let (scenes, _) = finish_scenes(
+        &args.args,
+        vec![scene(0, 100), scene(100, 300)],
+        &BTreeMap::new(),
+        &[],
+        &[],
+        &[150..295],
+        false,
+    )
+    .expect("should finish scenes");
+    assert_eq!(
+        scenes
+            .iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>(),
+        vec![(0, 100), (100, 150), (150, 300)]
+    );
+    assert!(scenes[..2].iter().all(|scene| scene.zone_overrides.is_none()));
+    let overrides = scenes[2].zone_overrides.as_ref().expect("static scene should have overrides");
+    assert_eq!(overrides.video_params, vec![
+        "--cq-level=40",
+        "--aq-mode=1",
+        "--cpu-used=8"
+    ]);
+}
@@ -0,0 +1,142 @@
+use std::{collections::VecDeque, fmt::Debug};
+
+use crate::Chunk;
+
+/// Decides which chunk each worker encodes next.
+///
+/// The encode uses a [`QueueScheduler`] unless another scheduler is set with
+/// [`Av1anContext::set_chunk_scheduler`](crate::Av1anContext::set_chunk_scheduler),
+/// which lets library users order chunks themselves, e.g. to encode the
+/// chunks of a preview range first.
+pub trait ChunkScheduler: Debug + Send {
+    /// Add the chunks that are left to encode. Called once before the workers
+    /// start, with the chunks sorted by
+    /// [`EncodeArgs::chunk_order`](crate::EncodeArgs::chunk_order), and
+    /// again for every batch of scenes detected while encoding with
+    /// [`EncodeArgs::sc_lookahead`](crate::EncodeArgs::sc_lookahead).
+    fn push(&mut self, chunks: Vec<Chunk>);
+
+    /// Returns the chunk worker `worker_id` should encode next.
+    ///
+    /// Returning `None` stops the worker, unless scene detection is still
+    /// running, in which case the worker asks again once more chunks were
+    /// pushed. Chunks that are never returned are not encoded, and the encode
+    /// fails to concatenate until they are encoded by resuming it.
+    fn next(&mut self, worker_id: usize) -> Option<Chunk>;
+
+    /// Returns the number of chunks that are waiting to be encoded
+    fn len(&self) -> usize;
+
+    /// Returns whether no chunks are waiting to be encoded
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Hands out chunks in the order they were pushed
+#[derive(Debug, Default)]
+pub struct QueueScheduler {
+    chunks: VecDeque<Chunk>,
+}
+
+impl ChunkScheduler for QueueScheduler {
+    #[inline]
+    fn push(&mut self, chunks: Vec<Chunk>) {
+        self.chunks.extend(chunks);
+    }
+
+    #[inline]
+    fn next(&mut self, _worker_id: usize) -> Option<Chunk> {
+        self.chunks.pop_front()
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{vapoursynth::CacheSource, ChunkMethod, ChunkNames, Encoder, Input, TargetQuality};
+
+    fn chunk(index: usize) -> Chunk {
+        Chunk {
+            temp: "none".to_owned(),
+            index,
+            input: Input::Video {
+                path:         "test.mkv".into(),
+                temp:         "none".to_owned(),
+                chunk_method: ChunkMethod::LSMASH,
+                is_proxy:     false,
+                cache_mode:   CacheSource::SOURCE,
+            },
+            proxy: None,
+            source_cmd: vec!["".into()],
+            proxy_cmd: None,
+            output_ext: "ivf".to_owned(),
+            start_frame: index * 10,
+            end_frame: index * 10 + 10,
+            frame_rate: 30.0,
+            target_quality: TargetQuality::default("none", Encoder::x264),
+            tq_cq: None,
+            ffmpeg_filter: None,
+            passes: 1,
+            video_params: vec![],
+            encoder: Encoder::x264,
+            noise_size: (None, None),
+            ignore_frame_mismatch: false,
+            output_frame_rate: None,
+            passthrough: false,
+            gpu: None,
+            output_names: ChunkNames::default(),
+        }
+    }
+
+    /// Encodes the chunks that overlap a frame range first
+    #[derive(Debug)]
+    struct PreviewFirst {
+        preview: std::ops::Range<usize>,
+        chunks:  Vec<Chunk>,
+    }
+
+    impl ChunkScheduler for PreviewFirst {
+        fn push(&mut self, chunks: Vec<Chunk>) {
+            self.chunks.extend(chunks);
+        }
+
+        fn next(&mut self, _worker_id: usize) -> Option<Chunk> {
+            let position = self
+                .chunks
+                .iter()
+                .position(|chunk| {
+                    chunk.start_frame < self.preview.end && chunk.end_frame > self.preview.start
+                })
+                .unwrap_or(0);
+            (!self.chunks.is_empty()).then(|| self.chunks.remove(position))
+        }
+
+        fn len(&self) -> usize {
+            self.chunks.len()
+        }
+    }
+
+    fn order(scheduler: &mut dyn ChunkScheduler) -> Vec<usize> {
+        scheduler.push((0..5).map(chunk).collect());
+        std::iter::from_fn(|| scheduler.next(0)).map(|chunk| chunk.index).collect()
+    }
+
+    #[test]
+    fn schedulers_order_chunks() {
+        assert_eq!(order(&mut QueueScheduler::default()), [0, 1, 2, 3, 4]);
+
+        let mut preview = PreviewFirst {
+            preview: 25..35,
+            chunks:  Vec::new(),
+        };
+        assert_eq!(order(&mut preview), [2, 3, 0, 1, 4]);
+        assert!(preview.is_empty());
+    }
+}
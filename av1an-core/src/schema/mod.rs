@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Upgrades the JSON of a file from one schema version to the next
+type Migration = fn(&mut Value) -> anyhow::Result<()>;
+
+/// A versioned file that Av1an writes into the temporary directory, and reads
+/// back when resuming.
+///
+/// Every file carries a top-level `version` field. Files without one were
+/// written before versioning was introduced and are treated as version 0.
+pub(crate) struct Schema {
+    file:       &'static str,
+    /// `migrations[n]` upgrades a file from version `n` to version `n + 1`
+    migrations: &'static [Migration],
+}
+
+pub(crate) const CHUNKS: Schema = Schema {
+    file:       "chunks.json",
+    migrations: &[chunks_v0_to_v1],
+};
+
+pub(crate) const DONE: Schema = Schema {
+    file:       "done.json",
+    migrations: &[add_version_field],
+};
+
+pub(crate) const SCENES: Schema = Schema {
+    file:       "scenes.json",
+    migrations: &[scenes_v0_to_v1],
+};
+
+impl Schema {
+    /// The schema version written by this build of Av1an
+    pub(crate) const fn version(&self) -> u64 {
+        self.migrations.len() as u64
+    }
+
+    /// Parses the contents of a file of this schema, migrating it from older
+    /// versions first if needed.
+    pub(crate) fn load<T: DeserializeOwned>(&self, contents: &str) -> anyhow::Result<T> {
+        let mut value: Value = serde_json::from_str(contents).with_context(|| {
+            format!(
+                "{} is not valid JSON, this likely means it is corrupted",
+                self.file
+            )
+        })?;
+
+        let found = file_version(&value)
+            .with_context(|| format!("{} has an invalid version field", self.file))?;
+        let current = self.version();
+        if found > current {
+            bail!(
+                "{} was written by a newer version of Av1an (schema version {found}, this build \
+                 supports up to {current}). Use a newer build of Av1an to resume this encode, or \
+                 run without --resume to start over",
+                self.file
+            );
+        }
+
+        for (version, migrate) in self.migrations.iter().enumerate().skip(found as usize) {
+            migrate(&mut value).with_context(|| {
+                format!(
+                    "Failed to migrate {} from schema version {version} to {}",
+                    self.file,
+                    version + 1
+                )
+            })?;
+            if let Value::Object(map) = &mut value {
+                map.insert("version".to_string(), Value::from(version + 1));
+            }
+        }
+
+        serde_json::from_value(value).with_context(|| {
+            format!(
+                "Failed to parse {} (schema version {found}). The file may be corrupted or \
+                 written by an incompatible build of Av1an, run without --resume to start over",
+                self.file
+            )
+        })
+    }
+}
+
+fn file_version(value: &Value) -> anyhow::Result<u64> {
+    value.get("version").map_or(Ok(0), |version| {
+        version
+            .as_u64()
+            .with_context(|| format!("expected a non-negative integer, found {version}"))
+    })
+}
+
+fn add_version_field(value: &mut Value) -> anyhow::Result<()> {
+    if !value.is_object() {
+        bail!("expected a JSON object");
+    }
+
+    Ok(())
+}
+
+/// Version 0 stored the chunk queue as a bare array
+fn chunks_v0_to_v1(value: &mut Value) -> anyhow::Result<()> {
+    if !value.is_array() {
+        bail!("expected a JSON array of chunks");
+    }
+    let chunks = value.take();
+    *value = serde_json::json!({ "chunks": chunks });
+
+    Ok(())
+}
+
+/// Version 0 could be missing the `split_scenes` list, in which case the
+/// `scenes` list was always post-split.
+///
+/// We won't be able to do special pre-split analysis on these, but at least
+/// the encode won't error out.
+fn scenes_v0_to_v1(value: &mut Value) -> anyhow::Result<()> {
+    let Value::Object(map) = value else {
+        bail!("expected a JSON object");
+    };
+    if map.get("split_scenes").is_none_or(Value::is_null)
+        && let Some(scenes) = map.get("scenes").filter(|scenes| !scenes.is_null())
+    {
+        let scenes = scenes.clone();
+        map.insert("split_scenes".to_string(), scenes);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use serde_json::{json, Value};
+
+use super::*;
+
+#[test]
+fn load_migrates_legacy_chunk_queue() {
+    let value: Value = CHUNKS.load(r#"[{"index": 0}, {"index": 1}]"#).expect("should load");
+    assert_eq!(
+        value,
+        json!({ "version": CHUNKS.version(), "chunks": [{"index": 0}, {"index": 1}] })
+    );
+}
+
+#[test]
+fn load_migrates_legacy_scenes() {
+    let value: Value = SCENES
+        .load(r#"{"frames": 10, "scenes": [{"start_frame": 0}]}"#)
+        .expect("should load");
+    assert_eq!(value["version"], SCENES.version());
+    assert_eq!(value["split_scenes"], json!([{"start_frame": 0}]));
+
+    let value: Value = SCENES
+        .load(r#"{"frames": 10, "scenes": [], "split_scenes": [{"start_frame": 0}]}"#)
+        .expect("should load");
+    assert_eq!(value["split_scenes"], json!([{"start_frame": 0}]));
+}
+
+#[test]
+fn load_keeps_current_version() {
+    let contents = json!({ "version": DONE.version(), "frames": 10 }).to_string();
+    let value: Value = DONE.load(&contents).expect("should load");
+    assert_eq!(value["frames"], 10);
+}
+
+#[test]
+fn load_rejects_newer_and_invalid_versions() {
+    let contents = json!({ "version": DONE.version() + 1 }).to_string();
+    let err = DONE.load::<Value>(&contents).expect_err("newer versions should be rejected");
+    assert!(err.to_string().contains("newer version of Av1an"));
+
+    assert!(DONE.load::<Value>(r#"{"version": "one"}"#).is_err());
+    assert!(CHUNKS.load::<Value>(r#"{"chunks": "#).is_err());
+}
@@ -1,35 +1,272 @@
 use std::{
     borrow::{Borrow, Cow},
     cmp::Ordering,
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fmt::Display,
+    num::NonZero,
+    ops::Range,
     path::{absolute, Path, PathBuf},
-    process::{exit, Command},
+    process::Command,
+    thread::available_parallelism,
+    time::Duration,
 };
 
-use anyhow::{bail, ensure};
+use anyhow::{bail, ensure, Context};
+use av_format::rational::Rational64;
 use itertools::{chain, Itertools};
 use serde::{Deserialize, Serialize};
 use strum::{EnumString, IntoStaticStr};
-use tracing::warn;
+use tracing::{debug, info, warn};
 
 use crate::{
-    concat::ConcatMethod,
+    concat::{self, ChunkCheck, ConcatMethod, OutputTags},
+    dimensions::{av1_tile_params, av1_tiles, min_av1_tiles, recommended_av1_tiles, OddDimensions},
     encoder::Encoder,
-    ffmpeg::FFPixelFormat,
-    metrics::{vmaf::validate_libvmaf, xpsnr::validate_libxpsnr},
+    ffmpeg::{
+        audio_params_reencode,
+        chunk_filter_args,
+        has_audio,
+        input_args,
+        AudioNormalization,
+        BurnSubtitles,
+        CaptionMode,
+        FFPixelFormat,
+        IntermediateCodec,
+        SubtitleSource,
+    },
+    geometry::GeometryChanges,
+    into_vec,
+    matroska::check_linked_segments,
+    metrics::{
+        vmaf::{
+            has_libvmaf_cuda,
+            has_libvmaf_cuda_p010,
+            validate_libvmaf,
+            validate_tonemap,
+            VmafTonemap,
+        },
+        xpsnr::validate_libxpsnr,
+    },
     parse::valid_params,
     target_quality::TargetQuality,
     vapoursynth::{CacheSource, VSZipVersion, VapoursynthPlugins},
+    zones::{parse_zones, validate_zones},
     ChunkMethod,
+    ChunkNames,
     ChunkOrdering,
     Input,
+    OutputMetric,
+    ScDecoder,
     ScenecutMethod,
     SplitMethod,
     TargetMetric,
+    TransitionCut,
     Verbosity,
 };
 
+/// User-wide defaults, loaded from `config.toml` in the Av1an config
+/// directory.
+///
+/// Every value is optional. Options given on the command line always take
+/// precedence over the values in this file, which in turn take precedence
+/// over Av1an's built-in defaults.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct GlobalConfig {
+    /// Number of workers to spawn [0 = automatic]
+    pub workers:        Option<usize>,
+    /// Directory in which temporary directories are created when `--temp` is
+    /// not given
+    pub temp_root:      Option<PathBuf>,
+    /// Scheduling priority of Av1an and the processes it runs
+    pub niceness:       Option<i32>,
+    /// Chunk method to use when `--chunk-method` is not given
+    #[serde(with = "chunk_method_name")]
+    pub chunk_method:   Option<ChunkMethod>,
+    /// Shell command to run whenever an encode finishes or fails
+    pub notify_command: Option<String>,
+    /// Directory remote inputs are downloaded into
+    pub input_cache:    Option<PathBuf>,
+}
+
+impl GlobalConfig {
+    /// The default location of the config file, `~/.config/av1an/config.toml`
+    /// (or `%APPDATA%\av1an\config.toml` on Windows)
+    #[inline]
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| std::env::home_dir().map(|home| home.join(".config")))
+        }?;
+
+        Some(config_dir.join("av1an").join("config.toml"))
+    }
+
+    /// Loads the config from the given TOML file
+    #[inline]
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Returns this config with every value set in `overrides` replaced
+    #[must_use]
+    #[inline]
+    pub fn merge(self, overrides: Self) -> Self {
+        Self {
+            workers:        overrides.workers.or(self.workers),
+            temp_root:      overrides.temp_root.or(self.temp_root),
+            niceness:       overrides.niceness.or(self.niceness),
+            chunk_method:   overrides.chunk_method.or(self.chunk_method),
+            notify_command: overrides.notify_command.or(self.notify_command),
+            input_cache:    overrides.input_cache.or(self.input_cache),
+        }
+    }
+
+    /// Serializes the config as TOML, leaving out unset values
+    #[inline]
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+/// (De)serializes chunk methods by the same names used on the command line
+mod chunk_method_name {
+    use std::str::FromStr;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::ChunkMethod;
+
+    // serde requires the value to be passed by reference
+    #[expect(clippy::ref_option, clippy::trivially_copy_pass_by_ref)]
+    pub fn serialize<S: Serializer>(
+        method: &Option<ChunkMethod>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match method {
+            Some(method) => serializer.serialize_str(method.into()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<ChunkMethod>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|name| {
+                ChunkMethod::from_str(&name)
+                    .map_err(|_| D::Error::custom(format!("invalid chunk method \"{name}\"")))
+            })
+            .transpose()
+    }
+}
+
+/// A speed and quality tier, whose encoder parameters are merged on top of
+/// Av1an's default encoder parameters
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EncoderTier {
+    /// Slow presets and a low quantizer, for encodes that are kept long term
+    #[strum(serialize = "archival")]
+    Archival,
+    /// A reasonable trade-off between speed and quality
+    #[strum(serialize = "balanced")]
+    Balanced,
+    /// Fast presets and a higher quantizer, for previews and quick encodes
+    #[strum(serialize = "fast")]
+    Fast,
+}
+
+impl Display for EncoderTier {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+impl EncoderTier {
+    /// The encoder parameters of this tier, which replace the matching
+    /// parameters of [`Encoder::get_default_arguments`]
+    #[inline]
+    pub fn video_params(self, encoder: Encoder) -> Vec<String> {
+        match (encoder, self) {
+            (Encoder::aom, Self::Archival) => {
+                into_vec!["--cpu-used=3", "--cq-level=24", "--lag-in-frames=48", "--enable-qm=1"]
+            },
+            (Encoder::aom, Self::Balanced) => into_vec!["--cpu-used=5", "--cq-level=28"],
+            (Encoder::aom, Self::Fast) => into_vec!["--cpu-used=8", "--cq-level=32"],
+            (Encoder::rav1e, Self::Archival) => into_vec!["--speed", "3", "--quantizer", "70"],
+            (Encoder::rav1e, Self::Balanced) => into_vec!["--speed", "6", "--quantizer", "100"],
+            (Encoder::rav1e, Self::Fast) => into_vec!["--speed", "10", "--quantizer", "130"],
+            (Encoder::vpx, Self::Archival) => into_vec!["--cpu-used=1", "--cq-level=24"],
+            (Encoder::vpx, Self::Balanced) => into_vec!["--cpu-used=2", "--cq-level=30"],
+            (Encoder::vpx, Self::Fast) => into_vec!["--cpu-used=4", "--cq-level=34"],
+            (Encoder::svt_av1, Self::Archival) => {
+                into_vec!["--preset", "2", "--crf", "22", "--enable-qm", "1"]
+            },
+            (Encoder::svt_av1, Self::Balanced) => into_vec!["--preset", "5", "--crf", "27"],
+            (Encoder::svt_av1, Self::Fast) => into_vec!["--preset", "8", "--crf", "32"],
+            (Encoder::x264 | Encoder::x265, Self::Archival) => {
+                into_vec!["--preset", "veryslow", "--crf", "18"]
+            },
+            (Encoder::x264 | Encoder::x265, Self::Balanced) => {
+                into_vec!["--preset", "slow", "--crf", "23"]
+            },
+            (Encoder::x264 | Encoder::x265, Self::Fast) => {
+                into_vec!["--preset", "faster", "--crf", "28"]
+            },
+            (Encoder::av1_nvenc, Self::Archival) => {
+                into_vec!["-preset", "p7", "-multipass", "fullres", "-cq", "24"]
+            },
+            (Encoder::av1_nvenc, Self::Balanced) => into_vec!["-preset", "p5", "-cq", "30"],
+            (Encoder::av1_nvenc, Self::Fast) => into_vec!["-preset", "p2", "-cq", "36"],
+            (Encoder::hevc_nvenc, Self::Archival) => {
+                into_vec!["-preset", "p7", "-multipass", "fullres", "-cq", "20"]
+            },
+            (Encoder::hevc_nvenc, Self::Balanced) => into_vec!["-preset", "p5", "-cq", "25"],
+            (Encoder::hevc_nvenc, Self::Fast) => into_vec!["-preset", "p2", "-cq", "30"],
+        }
+    }
+}
+
+/// Strength of the debanding filter applied to every chunk before encoding,
+/// which hides banding that low bitrate encodes would otherwise make worse
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DebandStrength {
+    #[strum(serialize = "weak")]
+    Weak,
+    #[strum(serialize = "medium")]
+    Medium,
+    #[strum(serialize = "strong")]
+    Strong,
+}
+
+impl Display for DebandStrength {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+impl DebandStrength {
+    /// The FFmpeg `gradfun` filter of this strength, used when the f3kdb
+    /// VapourSynth plugin is not available
+    #[inline]
+    pub const fn gradfun_filter(self) -> &'static str {
+        match self {
+            Self::Weak => "gradfun=strength=0.6:radius=12",
+            Self::Medium => "gradfun=strength=1.2:radius=16",
+            Self::Strong => "gradfun=strength=2.4:radius=24",
+        }
+    }
+}
+
 #[derive(EnumString, IntoStaticStr, Debug, PartialEq, Clone, Copy)]
 pub enum PixelFormatConverter {
     #[strum(serialize = "ffmpeg")]
@@ -86,29 +323,76 @@ impl InputPixelFormat {
 #[expect(clippy::struct_excessive_bools)]
 #[derive(Debug)]
 pub struct EncodeArgs {
-    pub input:       Input,
-    pub proxy:       Option<Input>,
-    pub temp:        String,
-    pub output_file: String,
-
-    pub chunk_method:          ChunkMethod,
-    pub chunk_order:           ChunkOrdering,
-    pub scaler:                String,
-    pub scenes:                Option<PathBuf>,
-    pub split_method:          SplitMethod,
-    pub sc_pix_format:         Option<FFPixelFormat>,
-    pub sc_method:             ScenecutMethod,
-    pub sc_only:               bool,
-    pub sc_downscale_height:   Option<usize>,
-    pub extra_splits_len:      Option<usize>,
-    pub min_scene_len:         usize,
-    pub force_keyframes:       Vec<usize>,
-    pub ignore_frame_mismatch: bool,
-
-    pub max_tries: usize,
+    pub input:          Input,
+    pub proxy:          Option<Input>,
+    pub temp:           String,
+    /// Temporary directory that was asked for, if it was moved to
+    /// [`Self::temp`] on local storage. It is copied back after encoding if
+    /// [`Self::keep`] is set.
+    pub relocated_from: Option<PathBuf>,
+    /// Directory whose registry named the temporary directory, unless it was
+    /// passed with `--temp`. The encode is registered there once it starts.
+    pub temp_registry:  Option<PathBuf>,
+    pub output_file:    String,
+
+    pub chunk_method:           ChunkMethod,
+    pub chunk_order:            ChunkOrdering,
+    pub scaler:                 String,
+    pub scenes:                 Option<PathBuf>,
+    pub edit_scenes:            Option<PathBuf>,
+    pub split_method:           SplitMethod,
+    pub sc_pix_format:          Option<FFPixelFormat>,
+    pub sc_method:              ScenecutMethod,
+    pub sc_decoder:             ScDecoder,
+    pub sc_transitions:         TransitionCut,
+    pub geometry_changes:       GeometryChanges,
+    /// Minimum length of a run of identical frames that is encoded with
+    /// [`Self::static_params`]
+    pub static_scenes:          Option<usize>,
+    /// Encoder parameters merged into the video parameters of the runs of
+    /// identical frames, the fastest presets of the encoder if empty
+    pub static_params:          Vec<String>,
+    /// Second encoder of the same format, for the least complex scenes
+    pub hybrid_encoder:         Option<Encoder>,
+    /// Video parameters of [`Self::hybrid_encoder`], merged into its defaults
+    pub hybrid_params:          Vec<String>,
+    /// Share of the frames encoded with [`Self::hybrid_encoder`]
+    pub hybrid_share:           f64,
+    pub sc_only:                bool,
+    /// JPEG contact sheet of the detected scene cuts and their scores
+    pub sc_preview:             Option<PathBuf>,
+    pub sc_downscale_height:    Option<usize>,
+    pub sc_proxy:               bool,
+    /// Save the progress of scene detection every 30000 frames, so that it can
+    /// be resumed
+    pub sc_checkpoint:          bool,
+    /// Encode the scenes found so far while scene detection continues,
+    /// handing them to the workers after every this many frames it analysed
+    pub sc_lookahead:           Option<usize>,
+    pub extra_splits_len:       Option<usize>,
+    pub target_chunk_time:      Option<Duration>,
+    pub deadline:               Option<Duration>,
+    pub min_scene_len:          usize,
+    pub force_keyframes:        Vec<usize>,
+    pub ignore_frame_mismatch:  bool,
+    pub ignore_linked_segments: bool,
+    /// Frame rate the --ffmpeg filters convert the input to, used for the
+    /// expected number of frames, the progress, concatenation and VMAF
+    pub output_fps:             Option<Rational64>,
+    /// Frame rate of the input, replacing the one its container reports
+    pub fps_override:           Option<Rational64>,
+
+    pub max_tries:   usize,
+    pub two_stage:   Option<f32>,
+    pub interactive: bool,
+    pub assume_yes:  bool,
 
     pub passes:               u8,
     pub video_params:         Vec<String>,
+    /// Merged into the video parameters of the first pass of two-pass
+    /// encodes, e.g. to analyze faster in the first pass
+    pub first_pass_params:    Vec<String>,
+    pub tier:                 Option<EncoderTier>,
     pub tiles:                (u32, u32), /* tile (cols, rows) count; log2 will be
                                            * applied
                                            * later
@@ -116,121 +400,840 @@ pub struct EncodeArgs {
     pub encoder:              Encoder,
     pub workers:              usize,
     pub set_thread_affinity:  Option<usize>,
+    /// Give the last chunks of the encode more encoder threads once there are
+    /// fewer chunks left than workers
+    pub tail_boost:           bool,
+    /// Extra workers that only run the first pass of multi-pass chunks, so
+    /// that the workers can start the later passes as soon as it finishes
+    pub afterburner:          usize,
+    /// Extra workers that only run Target Quality, handing the probed chunks
+    /// to the workers, which then only encode probed chunks
+    pub probe_workers:        usize,
+    /// Serve the frames of VapourSynth chunks from one persistent environment
+    /// per worker instead of a new vspipe process per chunk
+    pub vs_server:            bool,
+    /// CUDA devices the chunks are assigned to in turn
+    pub gpus:                 Vec<u32>,
+    /// Most chunks encoded on each of [`Self::gpus`] at once [None =
+    /// unlimited]
+    pub gpu_workers:          Option<usize>,
+    /// Bytes of the output of each process of a chunk kept in memory for the
+    /// error report
+    pub output_limit:         usize,
+    /// Write the complete output of the processes of every chunk to the
+    /// `logs` folder of the temporary directory
+    pub spill_output:         bool,
+    /// Capacity in bytes of the pipes between the source, FFmpeg and the
+    /// encoder, on Linux
+    pub pipe_size:            Option<usize>,
+    /// Largest chunk in bytes whose frames are buffered in memory before the
+    /// encoder reads them, instead of being piped, on Linux with the `memfd`
+    /// feature
+    pub memfd_buffer:         Option<usize>,
     pub photon_noise:         Option<u8>,
     pub photon_noise_size:    (Option<u32>, Option<u32>), // Width and Height
     pub chroma_noise:         bool,
+    /// Peak bitrate in kbps that every chunk is capped at
+    pub peak_bitrate:         Option<u32>,
+    /// Length in seconds of the buffer of [`Self::peak_bitrate`]
+    pub peak_buffer:          f64,
     pub zones:                Option<PathBuf>,
+    /// Only encode the frames inside the zones, along with their audio
+    pub zones_only:           bool,
+    /// Only encode these frames of the input, along with their audio
+    pub trim:                 Option<Range<usize>>,
+    /// Copy the video stream instead of encoding it, only encoding the audio
+    pub copy_video:           bool,
     pub cache_mode:           CacheSource,
     pub pix_format_converter: PixelFormatConverter,
 
     // FFmpeg params
     pub ffmpeg_filter_args: Vec<String>,
+    /// Encode the filtered input to a lossless intermediate once, and split
+    /// the chunks from it instead of filtering every chunk
+    pub intermediate:       Option<IntermediateCodec>,
+    /// How to make a width or height the encoder rejects encodable
+    pub odd_dimensions:     OddDimensions,
+    pub deband:             Option<DebandStrength>,
+    pub burn_subs:          Option<BurnSubtitles>,
     pub audio_params:       Vec<String>,
+    pub captions:           CaptionMode,
+    pub audio_normalize:    Option<AudioNormalization>,
     pub input_pix_format:   InputPixelFormat,
     pub output_pix_format:  PixelFormat,
 
     pub verbosity:   Verbosity,
     pub resume:      bool,
+    /// Share the temporary directory with Av1an instances on other machines,
+    /// which encode different chunks of the same encode
+    pub shared_temp: bool,
     pub keep:        bool,
+    /// Write the report as an HTML page next to the output
+    pub html_report: bool,
     pub force:       bool,
     pub no_defaults: bool,
     pub tile_auto:   bool,
 
     pub concat:         ConcatMethod,
+    /// Paths of the encoded chunks in the encode directory
+    pub chunk_names:    ChunkNames,
+    /// Title written to the output
+    pub title:          Option<String>,
+    /// Language of the video track of the output
+    pub track_language: Option<String>,
+    /// Write the encoder and its parameters as tags of the video track
+    pub settings_tags:  bool,
+    /// Move the seek index of the output to the front after concatenating
+    pub optimize_mux:   bool,
+    pub verify_chunks:  bool,
+    pub chunk_check:    Option<ChunkCheck>,
     pub target_quality: TargetQuality,
     pub vmaf:           bool,
+    /// Metrics other than VMAF, which is [`Self::vmaf`], the output is
+    /// scored with
+    pub metrics:        Vec<OutputMetric>,
     pub vmaf_path:      Option<PathBuf>,
     pub vmaf_res:       String,
     pub probe_res:      Option<String>,
     pub vmaf_threads:   Option<usize>,
+    /// Compute VMAF on the GPU with libvmaf_cuda, if FFmpeg supports it
+    pub vmaf_cuda:      bool,
     pub vmaf_filter:    Option<String>,
+    pub vmaf_tonemap:   Option<VmafTonemap>,
 
+    /// Lowest score with the metric of [`Self::target_quality`] a chunk may
+    /// have for the encode to be concatenated (highest for Butteraugli)
+    pub min_chunk_score:     Option<f64>,
+    /// Concatenate with FFmpeg if mkvmerge fails
+    pub concat_fallback:     bool,
     pub vapoursynth_plugins: Option<VapoursynthPlugins>,
 }
 
 impl EncodeArgs {
+    /// The settings that affect the encoded output, as human readable values,
+    /// for comparing different runs
+    #[inline]
+    pub fn summary(&self) -> BTreeMap<String, String> {
+        fn or_none(value: Option<impl ToString>) -> String {
+            value.map_or_else(|| "none".to_string(), |value| value.to_string())
+        }
+
+        BTreeMap::from([
+            ("encoder".to_string(), self.encoder.to_string()),
+            ("video-params".to_string(), self.video_params.join(" ")),
+            ("passes".to_string(), self.passes.to_string()),
+            (
+                "pix-format".to_string(),
+                self.output_pix_format.format.to_pix_fmt_string().to_string(),
+            ),
+            ("chunk-method".to_string(), self.chunk_method.to_string()),
+            ("split-method".to_string(), self.split_method.to_string()),
+            ("extra-split".to_string(), or_none(self.extra_splits_len)),
+            ("min-scene-len".to_string(), self.min_scene_len.to_string()),
+            ("photon-noise".to_string(), or_none(self.photon_noise)),
+            (
+                "peak-bitrate".to_string(),
+                or_none(
+                    self.peak_bitrate
+                        .map(|peak| format!("{peak} kbps, {}s buffer", self.peak_buffer)),
+                ),
+            ),
+            (
+                "zones".to_string(),
+                or_none(self.zones.as_ref().map(|zones| zones.display())),
+            ),
+            ("zones-only".to_string(), self.zones_only.to_string()),
+            ("copy-video".to_string(), self.copy_video.to_string()),
+            (
+                "trim".to_string(),
+                or_none(self.trim.as_ref().map(|trim| format!("{}-{}", trim.start, trim.end))),
+            ),
+            ("ffmpeg".to_string(), self.ffmpeg_filter_args.join(" ")),
+            ("intermediate".to_string(), or_none(self.intermediate)),
+            ("output-fps".to_string(), or_none(self.output_fps)),
+            ("fps-override".to_string(), or_none(self.fps_override)),
+            (
+                "hybrid-encoder".to_string(),
+                or_none(self.hybrid_encoder.map(|encoder| {
+                    format!(
+                        "{encoder} for {:.0}% of the frames, {}",
+                        self.hybrid_share * 100.0,
+                        self.hybrid_params.join(" ")
+                    )
+                })),
+            ),
+            (
+                "odd-dimensions".to_string(),
+                self.odd_dimensions.to_string(),
+            ),
+            (
+                "geometry-changes".to_string(),
+                self.geometry_changes.to_string(),
+            ),
+            ("deband".to_string(), or_none(self.deband)),
+            ("burn-subs".to_string(), or_none(self.burn_subs.as_ref())),
+            ("audio-params".to_string(), self.audio_params.join(" ")),
+            ("captions".to_string(), self.captions.to_string()),
+            (
+                "audio-normalize".to_string(),
+                self.audio_normalize.map_or_else(|| "none".to_string(), |n| n.to_string()),
+            ),
+            (
+                "target-quality".to_string(),
+                or_none(
+                    self.target_quality
+                        .target
+                        .map(|(min, max)| format!("{min}-{max} {}", self.target_quality.metric)),
+                ),
+            ),
+            ("two-stage".to_string(), or_none(self.two_stage)),
+        ])
+    }
+
+    /// The tags written to the output when concatenating
+    pub(crate) fn output_tags(&self) -> OutputTags {
+        OutputTags {
+            title:            self.title.clone(),
+            language:         self.track_language.clone(),
+            encoder_settings: self
+                .settings_tags
+                .then(|| (self.encoder.to_string(), self.video_params.join(" "))),
+        }
+    }
+
+    /// Validate the settings, filling in the encoder defaults. Every problem
+    /// found is reported at once, so that they can all be fixed before
+    /// running again.
     #[inline]
     pub fn validate(&mut self) -> anyhow::Result<()> {
+        let mut problems = Problems::default();
+
+        self.validate_output(&mut problems);
+        let input_exists = self.validate_input(&mut problems);
+        if self.copy_video {
+            // nothing is encoded, so the encoder and chunk settings do not matter
+            self.validate_copy_video(&mut problems);
+            return problems.into_result();
+        }
+        if input_exists && self.burn_subs.is_some() {
+            self.validate_burn_subs(&mut problems);
+        }
+        if input_exists && let Some(codec) = self.intermediate {
+            self.validate_intermediate(codec, &mut problems);
+        }
+        if self.target_quality.target.is_some() || self.min_chunk_score.is_some() {
+            self.validate_target_quality(&mut problems);
+        }
+        if input_exists && self.vmaf_tonemap.is_some() {
+            self.validate_vmaf_tonemap(&mut problems);
+        }
+        if self.vmaf_cuda {
+            self.detect_vmaf_cuda();
+        }
+        let encoder_found = self.validate_binaries(&mut problems);
+        self.validate_chunk_method(&mut problems);
+        self.validate_sc_decoder(&mut problems);
+        self.warn_ignored_options();
+        if self.metrics.contains(&OutputMetric::XPSNR) {
+            problems.check(
+                validate_libxpsnr(),
+                Some("install FFmpeg with XPSNR or remove `--metric xpsnr`"),
+            );
+        }
+
+        if encoder_found {
+            problems.check(self.validate_output_pix_format(), None::<&str>);
+        }
+        if input_exists {
+            self.validate_dimensions(&mut problems);
+        }
+
+        if self.tile_auto
+            && let Some(tiles) = self.recommended_tiles()
+        {
+            self.tiles = tiles;
+        }
+
+        if !self.no_defaults {
+            let mut defaults = self.encoder.get_default_arguments(self.tiles);
+            if let Some(tier) = self.tier {
+                defaults = merge_params(defaults, &tier.video_params(self.encoder));
+            }
+            self.video_params = merge_params(defaults, &self.video_params);
+        } else if self.tile_auto
+            && self.tiles != (1, 1)
+            && matches!(
+                self.encoder,
+                Encoder::aom | Encoder::rav1e | Encoder::svt_av1
+            )
+            && av1_tiles(self.encoder, &self.video_params).is_none()
+        {
+            self.video_params.extend(
+                av1_tile_params(self.encoder, self.tiles)
+                    .split_whitespace()
+                    .map(ToString::to_string),
+            );
+        }
+
+        if let Some(hybrid_encoder) = self.hybrid_encoder {
+            self.validate_hybrid_encoder(hybrid_encoder, &mut problems);
+        }
+
+        self.validate_video_params(&mut problems);
+
+        if !self.force {
+            if encoder_found {
+                self.validate_encoder_params(&mut problems);
+            }
+            if input_exists {
+                self.validate_tiles(&mut problems);
+            }
+            self.check_rate_control();
+        }
+
+        // Zones are parsed relative to the final video parameters
+        if input_exists && self.zones.is_some() {
+            problems.check(
+                self.input
+                    .clip_info()
+                    .and_then(|clip_info| parse_zones(self, clip_info.num_frames))
+                    .and_then(|zones| validate_zones(self, &zones.scenes)),
+                Some("fix the zone in the zones file passed with --zones"),
+            );
+        }
+        if self.zones_only {
+            self.validate_zones_only(&mut problems, input_exists);
+        }
+        if self.trim.is_some() {
+            self.validate_partial_encode("--trim-start/--trim-end", &mut problems, input_exists);
+        }
+
+        problems.into_result()
+    }
+
+    /// Checks that only the zones of the input can be encoded with
+    /// `--zones-only`
+    fn validate_zones_only(&self, problems: &mut Problems, input_exists: bool) {
+        if self.zones.is_none() {
+            problems.push(
+                "--zones-only requires a zones file",
+                Some("pass the frames to encode with --zones"),
+            );
+        }
+        self.validate_partial_encode("--zones-only", problems, input_exists);
+    }
+
+    /// Checks that the video stream of the input can be copied with
+    /// `--copy-video`, and disables the options that only apply to encoding it
+    fn validate_copy_video(&mut self, problems: &mut Problems) {
+        if !self.input.is_video() {
+            problems.push(
+                "--copy-video requires a video input, as a VapourSynth script has no video stream \
+                 to copy",
+                Some("pass the video file with --input"),
+            );
+        }
+        let ignored = [
+            ("--ffmpeg", !self.ffmpeg_filter_args.is_empty()),
+            ("--zones", self.zones.is_some()),
+            ("--trim-start/--trim-end", self.trim.is_some()),
+            ("--target-quality", self.target_quality.target.is_some()),
+            ("--output-fps", self.output_fps.is_some()),
+            ("--fps-override", self.fps_override.is_some()),
+            ("--hybrid-encoder", self.hybrid_encoder.is_some()),
+            ("--burn-subs", self.burn_subs.is_some()),
+            ("--vmaf", self.vmaf),
+            ("--metric", !self.metrics.is_empty()),
+        ];
+        for (option, _) in ignored.iter().filter(|(_, set)| *set) {
+            warn!("{option} has no effect with --copy-video, as the video is not encoded");
+        }
+        self.vmaf = false;
+        self.metrics.clear();
+    }
+
+    /// Checks that `option`, which leaves out part of the input, can cut the
+    /// chunks and the audio
+    fn validate_partial_encode(&self, option: &str, problems: &mut Problems, input_exists: bool) {
+        if self.input.is_video()
+            && matches!(
+                self.chunk_method,
+                ChunkMethod::Segment | ChunkMethod::Hybrid
+            )
+        {
+            problems.push(
+                format!(
+                    "{option} cannot be used with chunk method {}, which splits the whole input",
+                    self.chunk_method
+                ),
+                Some("use another --chunk-method"),
+            );
+        }
+        if input_exists
+            && self.input.is_video()
+            && !audio_params_reencode(&self.audio_params)
+            && !self.audio_params.iter().any(|param| param == "-an")
+            && has_audio(self.input.as_video_path()).unwrap_or(false)
+        {
+            problems.push(
+                format!("{option} cannot cut copied audio"),
+                Some(
+                    "pass an audio encoder with --audio-params, e.g. -a \"-c:a libopus -b:a \
+                     128k\", or drop the audio with -a -an",
+                ),
+            );
+        }
+    }
+
+    /// Resolves `--vmaf-tonemap auto` with the transfer function of the input
+    /// and checks that FFmpeg can tone map
+    fn validate_vmaf_tonemap(&mut self, problems: &mut Problems) {
+        if self.vmaf_tonemap == Some(VmafTonemap::Auto)
+            && let Ok(clip_info) = self.input.clip_info()
+        {
+            self.vmaf_tonemap = VmafTonemap::Auto.resolve(clip_info.transfer_characteristics);
+            if self.vmaf_tonemap.is_none() {
+                warn!(
+                    "--vmaf-tonemap has no effect, as the input is not PQ. Pass --vmaf-tonemap \
+                     hlg if it is HLG."
+                );
+            }
+        }
+        self.target_quality.vmaf_tonemap = self.vmaf_tonemap;
+
+        if self.vmaf_tonemap.is_some() {
+            problems.check(
+                validate_tonemap(),
+                Some("install FFmpeg with libzimg or remove --vmaf-tonemap"),
+            );
+        }
+    }
+
+    /// Falls back to computing VMAF on the CPU if FFmpeg cannot use
+    /// libvmaf_cuda
+    fn detect_vmaf_cuda(&mut self) {
+        let uses_vmaf = self.vmaf
+            || self.zones.is_some()
+            || (self.target_quality.target.is_some()
+                && matches!(self.target_quality.metric, TargetMetric::VMAF));
+        if !uses_vmaf {
+            warn!("--vmaf-cuda has no effect without --vmaf or --target-quality with VMAF");
+            self.vmaf_cuda = false;
+        } else if has_libvmaf_cuda() {
+            info!("computing VMAF on the GPU with libvmaf_cuda");
+            if let Some(bit_depth) = self.input_bit_depth().filter(|&bit_depth| bit_depth > 8)
+                && !has_libvmaf_cuda_p010()
+            {
+                warn!(
+                    "libvmaf_cuda of this FFmpeg only takes 8-bit frames, so the VMAF of the \
+                     {bit_depth}-bit input is computed from frames converted to 8-bit"
+                );
+            }
+        } else {
+            warn!(
+                "FFmpeg cannot compute VMAF with libvmaf_cuda, it needs FFmpeg and libvmaf built \
+                 with CUDA support and an NVIDIA GPU. Falling back to libvmaf on the CPU."
+            );
+            self.vmaf_cuda = false;
+        }
+        self.target_quality.vmaf_cuda = self.vmaf_cuda;
+    }
+
+    /// Checks the output container, audio and subtitle settings
+    fn validate_output(&self, problems: &mut Problems) {
         if self.concat == ConcatMethod::Ivf
             && !matches!(
                 self.encoder,
                 Encoder::rav1e | Encoder::aom | Encoder::svt_av1 | Encoder::vpx
             )
         {
-            bail!(".ivf only supports VP8, VP9, and AV1");
+            problems.push(
+                ".ivf only supports VP8, VP9, and AV1",
+                Some("use --concat mkvmerge or --concat ffmpeg"),
+            );
+        }
+        if let Some(normalization) = self.audio_normalize {
+            if !audio_params_reencode(&self.audio_params) {
+                problems.push(
+                    format!("--audio-normalize {normalization} cannot be applied to copied audio"),
+                    Some(
+                        "pass an audio encoder with --audio-params, e.g. -a \"-c:a libopus -b:a \
+                         128k\"",
+                    ),
+                );
+            }
+            if !self.input.is_video() {
+                warn!(
+                    "Audio cannot be read from a VapourSynth script, --audio-normalize \
+                     {normalization} has no effect"
+                );
+            }
+        }
+        if (self.title.is_some() || self.track_language.is_some())
+            && self.concat == ConcatMethod::Ivf
+        {
+            problems.push(
+                "--title and --track-language require a container that supports tags",
+                Some("use --concat mkvmerge or --concat ffmpeg"),
+            );
+        }
+        if let Some(language) = &self.track_language
+            && !is_language_tag(language)
+        {
+            problems.push(
+                format!("invalid --track-language {language:?}"),
+                Some("pass an ISO 639-2 code or a BCP 47 tag, e.g. eng or en-US"),
+            );
+        }
+        if self.captions == CaptionMode::Mux && self.concat == ConcatMethod::Ivf {
+            problems.push(
+                "--captions mux requires a container that supports subtitles",
+                Some("use --concat mkvmerge or --concat ffmpeg"),
+            );
+        }
+        if self.captions != CaptionMode::Ignore && !self.input.is_video() {
+            warn!(
+                "Closed captions cannot be read from a VapourSynth script, --captions {} has no \
+                 effect",
+                self.captions
+            );
         }
 
-        ensure!(self.max_tries > 0);
+        if self.peak_bitrate.is_some() {
+            if !self.encoder.supports_bitrate_cap() {
+                problems.push(
+                    format!(
+                        "{} cannot cap the bitrate of constant quality encodes",
+                        self.encoder
+                    ),
+                    Some("use --peak-bitrate with x264, x265 or svt-av1"),
+                );
+            }
+            if self.peak_bitrate == Some(0) {
+                problems.push("--peak-bitrate must be at least 1", None::<&str>);
+            }
+            if !self.peak_buffer.is_finite() || self.peak_buffer <= 0.0 {
+                problems.push("--peak-buffer must be more than 0 seconds", None::<&str>);
+            }
+        }
 
-        ensure!(
-            self.input.as_path().exists(),
-            "Input file {:?} does not exist!",
-            self.input
-        );
+        if let Some(score) = self.min_chunk_score
+            && (!score.is_finite() || score < 0.0)
+        {
+            problems.push(
+                format!("invalid --min-chunk-score {score}"),
+                Some("pass a score of the --target-metric, e.g. 90 for VMAF"),
+            );
+        }
 
-        if let Some(proxy) = &self.proxy {
-            ensure!(
-                proxy.as_path().exists(),
-                "Proxy file {:?} does not exist!",
-                proxy
+        if self.shared_temp && self.sc_lookahead.is_some() {
+            problems.push(
+                "--shared-temp cannot be used with --sc-lookahead, as the chunks must be known \
+                 before other instances join the encode",
+                Some("remove --sc-lookahead"),
+            );
+        }
+        if self.interactive && self.sc_lookahead.is_some() {
+            problems.push(
+                "--interactive cannot be used with --sc-lookahead, as the chunks are not known \
+                 before encoding starts",
+                Some("remove either --interactive or --sc-lookahead"),
             );
+        }
 
-            // Frame count must match
-            let input_frame_count = self.input.clip_info()?.num_frames;
-            let proxy_frame_count = proxy.clip_info()?.num_frames;
+        if self.max_tries == 0 {
+            problems.push("--max-tries must be at least 1", None::<&str>);
+        }
+        if self.gpu_workers == Some(0) {
+            problems.push("--gpu-workers must be at least 1", None::<&str>);
+        }
 
-            ensure!(
-                input_frame_count == proxy_frame_count,
-                "Input and Proxy do not have the same number of frames! ({input_frame_count} != \
-                 {proxy_frame_count})",
+        if let Some(strength) = self.two_stage {
+            if !(strength.is_finite() && strength > 0.0) {
+                problems.push("Two-stage strength must be greater than 0", None::<&str>);
+            }
+            if self.target_quality.target.is_some() {
+                problems.push(
+                    "--two-stage cannot be used together with --target-quality",
+                    Some("remove either --two-stage or --target-quality"),
+                );
+            }
+        }
+        if self.target_chunk_time.is_some_and(|target| target.is_zero()) {
+            problems.push("Target chunk time must be greater than 0", None::<&str>);
+        }
+        if self.sc_lookahead == Some(0) {
+            problems.push(
+                "Scene detection lookahead must be greater than 0",
+                None::<&str>,
             );
         }
+    }
 
-        if self.target_quality.target.is_some() && self.input.is_vapoursynth() {
-            let input_absolute_path = absolute(self.input.as_path())?;
-            if !input_absolute_path.starts_with(std::env::current_dir()?) {
-                warn!(
-                    "Target Quality with VapourSynth script file input not in current working \
-                     directory. It is recommended to run in the same directory."
+    /// Checks that the input and proxy exist and match. Returns whether the
+    /// input exists.
+    fn validate_input(&self, problems: &mut Problems) -> bool {
+        if !self.input.as_path().exists() {
+            problems.push(
+                format!("Input file {:?} does not exist!", self.input),
+                Some("check the path passed with -i"),
+            );
+            return false;
+        }
+
+        if let Some(proxy) = &self.proxy {
+            if proxy.as_path().exists() {
+                // Frame count must match
+                problems.check(
+                    self.input.clip_info().and_then(|input| {
+                        let input_frame_count = input.num_frames;
+                        let proxy_frame_count = proxy.clip_info()?.num_frames;
+                        ensure!(
+                            input_frame_count == proxy_frame_count,
+                            "Input and Proxy do not have the same number of frames! \
+                             ({input_frame_count} != {proxy_frame_count})",
+                        );
+                        Ok(())
+                    }),
+                    Some("use a proxy made from the same source without dropping frames"),
+                );
+            } else {
+                problems.push(
+                    format!("Proxy file {proxy:?} does not exist!"),
+                    Some("check the path passed with --proxy"),
                 );
             }
         }
-        if self.target_quality.target.is_some() {
-            match self.target_quality.metric {
-                TargetMetric::VMAF => validate_libvmaf()?,
-                TargetMetric::SSIMULACRA2 => self.validate_ssimulacra2()?,
-                TargetMetric::ButteraugliINF => self.validate_butteraugli_inf()?,
-                TargetMetric::Butteraugli3 => self.validate_butteraugli_3()?,
-                TargetMetric::XPSNR | TargetMetric::XPSNRWeighted => self
-                    .validate_xpsnr(self.target_quality.metric, self.target_quality.probing_rate)?,
+
+        if self.input.is_video()
+            && !self.ignore_linked_segments
+            && self
+                .input
+                .as_path()
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("mkv"))
+        {
+            if which::which("mkvmerge").is_ok() && which::which("mkvextract").is_ok() {
+                problems.check(
+                    check_linked_segments(self.input.as_path()),
+                    Some(
+                        "flatten the timeline into a single file first, e.g. with mkvmerge \
+                         --split parts: and appending the parts with +, or pass \
+                         --ignore-linked-segments to encode only the frames stored in the input",
+                    ),
+                );
+            } else {
+                warn!(
+                    "mkvmerge and mkvextract were not found, so the input was not checked for \
+                     ordered chapters or linked segments, which are encoded without the frames of \
+                     other files"
+                );
             }
         }
 
+        true
+    }
+
+    /// Checks that the subtitles to burn in exist, and probes whether they are
+    /// text or images
+    fn validate_burn_subs(&mut self, problems: &mut Problems) {
+        if self.chunk_method == ChunkMethod::Segment {
+            problems.push(
+                "--burn-subs cannot be used with the segment chunk method, as the chunks do not \
+                 know where they start in the input",
+                Some("choose a different chunk method with -m"),
+            );
+        }
+        let Some(subtitles) = &mut self.burn_subs else {
+            return;
+        };
+        if let SubtitleSource::Track(_) = subtitles.source
+            && !self.input.is_video()
+        {
+            problems.push(
+                "Subtitle tracks cannot be read from a VapourSynth script",
+                Some("pass a subtitle file to --burn-subs instead"),
+            );
+            return;
+        }
+        problems.check(
+            subtitles.probe(self.input.as_path()),
+            Some("pass the index of a subtitle track of the input, or a subtitle file"),
+        );
+    }
+
+    /// Checks that the lossless intermediate can hold the input and be split
+    /// by the chunk method
+    fn validate_intermediate(&self, codec: IntermediateCodec, problems: &mut Problems) {
+        if self.chunk_method == ChunkMethod::DGDECNV {
+            problems.push(
+                "DGDecNV cannot decode the lossless intermediate of --lossless-intermediate",
+                Some("choose a different chunk method with -m"),
+            );
+        }
+        if codec == IntermediateCodec::UtVideo
+            && let Some(bit_depth) = self.input_bit_depth()
+            && bit_depth > 8
+        {
+            problems.push(
+                format!("Ut Video cannot losslessly hold the {bit_depth}-bit input"),
+                Some("use --lossless-intermediate ffv1"),
+            );
+        }
+    }
+
+    /// Bit depth of the input, if its clip info can be read
+    fn input_bit_depth(&self) -> Option<usize> {
+        let clip_info = self.input.clip_info().ok()?;
+        Some(match clip_info.format_info {
+            InputPixelFormat::VapourSynth {
+                bit_depth,
+            } => bit_depth,
+            InputPixelFormat::FFmpeg {
+                format,
+            } => format.get_format_bit_depth_usize(),
+        })
+    }
+
+    /// Checks that the metric used for Target Quality can be measured
+    fn validate_target_quality(&self, problems: &mut Problems) {
+        if self.input.is_vapoursynth()
+            && absolute(self.input.as_path()).is_ok_and(|path| {
+                std::env::current_dir().is_ok_and(|current_dir| !path.starts_with(current_dir))
+            })
+        {
+            warn!(
+                "Target Quality with VapourSynth script file input not in current working \
+                 directory. It is recommended to run in the same directory."
+            );
+        }
+
+        let metric = self.target_quality.metric;
+        let result = match metric {
+            TargetMetric::VMAF => validate_libvmaf(),
+            // measured with the `ssimulacra2` crate without the plugins
+            TargetMetric::SSIMULACRA2 => Ok(()),
+            TargetMetric::ButteraugliINF => self.validate_butteraugli_inf(),
+            TargetMetric::Butteraugli3 => self.validate_butteraugli_3(),
+            TargetMetric::XPSNR | TargetMetric::XPSNRWeighted => {
+                self.validate_xpsnr(metric, self.target_quality.probing_rate)
+            },
+            // registering the metric is all it needs
+            TargetMetric::Custom(_) => Ok(()),
+        };
+        problems.check(
+            result,
+            Some(format!(
+                "install the missing dependency or choose a different --target-metric than \
+                 {metric}"
+            )),
+        );
+
+        // a target beyond the scores of the metric, e.g. SSIMULACRA2 above 100,
+        // would be searched for with every probe
+        let (lowest, highest) = metric.score_range();
+        if let Some((min, max)) = self.target_quality.target
+            && (min > highest || max < lowest)
+        {
+            problems.push(
+                format!(
+                    "--target-quality {min}-{max} is outside the {metric} scores, which range \
+                     from {lowest} to {highest}"
+                ),
+                Some("pass a target range of the --target-metric"),
+            );
+        }
+
+        // the VapourSynth metrics read the chunk scripts themselves, so they
+        // cannot see the FFmpeg filters or debanding of the encoder
+        if self.min_chunk_score.is_some()
+            && self.proxy.is_none()
+            && (!self.ffmpeg_filter_args.is_empty()
+                || self.deband.is_some()
+                || self.burn_subs.is_some())
+            && matches!(
+                metric,
+                TargetMetric::SSIMULACRA2
+                    | TargetMetric::ButteraugliINF
+                    | TargetMetric::Butteraugli3
+            )
+        {
+            problems.push(
+                format!(
+                    "--min-chunk-score with {metric} cannot score chunks against the source as \
+                     --ffmpeg, --deband or --burn-subs filtered it for the encoder"
+                ),
+                Some("score chunks with `--target-metric vmaf` or `--target-metric xpsnr`"),
+            );
+        }
+
+        if let Some(vmaf_path) = self.target_quality.model.as_ref()
+            && !vmaf_path.exists()
+        {
+            problems.push(
+                format!("VMAF model {} does not exist", vmaf_path.display()),
+                Some("check the path passed with --vmaf-path"),
+            );
+        }
+
+        if self.target_quality.probes < 4 {
+            warn!("Target quality with fewer than 4 probes is experimental and not recommended");
+        }
+    }
+
+    /// Returns whether the output is concatenated with FFmpeg if concatenating
+    /// with mkvmerge fails. FFmpeg cannot concatenate the raw HEVC of x265 or
+    /// aomenc output with keyframe filtering mode 2.
+    pub(crate) fn falls_back_to_ffmpeg(&self) -> bool {
+        self.concat == ConcatMethod::MKVMerge
+            && self.concat_fallback
+            && self.encoder != Encoder::x265
+            && !(self.encoder == Encoder::aom
+                && self.video_params.iter().any(|param| param == "--enable-keyframe-filtering=2"))
+    }
+
+    /// Checks that the external binaries used for the encode are installed.
+    /// Returns whether the encoder was found.
+    fn validate_binaries(&self, problems: &mut Problems) -> bool {
         if which::which("ffmpeg").is_err() {
-            bail!("FFmpeg not found. Is it installed in system path?");
+            problems.push(
+                "FFmpeg not found. Is it installed in system path?",
+                Some("install FFmpeg and add it to the PATH"),
+            );
         }
 
         if self.concat == ConcatMethod::MKVMerge && which::which("mkvmerge").is_err() {
-            if self.sc_only {
+            if self.falls_back_to_ffmpeg() {
+                warn!(
+                    "mkvmerge not found, but `--concat mkvmerge` was specified. The output will \
+                     be concatenated with FFmpeg instead."
+                );
+            } else if self.sc_only {
                 warn!(
                     "mkvmerge not found, but `--concat mkvmerge` was specified. Make sure to \
                      install mkvmerge or specify a different concatenation method (e.g. `--concat \
                      ffmpeg`) before encoding."
                 );
             } else {
-                bail!(
+                problems.push(
                     "mkvmerge not found, but `--concat mkvmerge` was specified. Is it installed \
-                     in system path?"
+                     in system path?",
+                    Some("install MKVToolNix, or use `--concat ffmpeg`"),
                 );
             }
         }
 
-        if self.encoder == Encoder::x265 && self.concat != ConcatMethod::MKVMerge {
-            bail!(
-                "mkvmerge is required for concatenating x265, as x265 outputs raw HEVC bitstream \
-                 files without the timestamps correctly set, which FFmpeg cannot concatenate \
-                 properly into a mkv file. Specify mkvmerge as the concatenation method by \
-                 setting `--concat mkvmerge`."
+        if matches!(self.encoder, Encoder::x265 | Encoder::hevc_nvenc)
+            && self.concat != ConcatMethod::MKVMerge
+        {
+            problems.push(
+                format!(
+                    "mkvmerge is required for concatenating {}, as it outputs raw HEVC bitstream \
+                     files without the timestamps correctly set, which FFmpeg cannot concatenate \
+                     properly into a mkv file.",
+                    self.encoder
+                ),
+                Some("specify mkvmerge as the concatenation method by setting `--concat mkvmerge`"),
             );
         }
 
@@ -242,98 +1245,441 @@ impl EncodeArgs {
             );
         }
 
-        if self.chunk_method == ChunkMethod::LSMASH {
-            ensure!(
-                self.vapoursynth_plugins.is_some_and(|p| p.lsmash),
-                "LSMASH is not installed, but it was specified as the chunk method"
+        let encoder_bin = self.encoder.bin();
+        let encoder_found = which::which(encoder_bin).is_ok();
+        if !encoder_found {
+            problems.push(
+                format!("Encoder {encoder_bin} not found. Is it installed in the system path?"),
+                Some(format!(
+                    "install {encoder_bin} and add it to the PATH, or choose a different encoder \
+                     with -e"
+                )),
+            );
+        } else if self.encoder.is_hardware() && self.encoder.version_text().is_none() {
+            problems.push(
+                format!("FFmpeg was built without the {} encoder", self.encoder),
+                Some("install a build of FFmpeg with NVENC, or choose a different encoder with -e"),
             );
         }
-        if self.chunk_method == ChunkMethod::FFMS2 {
-            ensure!(
-                self.vapoursynth_plugins.is_some_and(|p| p.ffms2),
-                "FFMS2 is not installed, but it was specified as the chunk method"
+        encoder_found
+    }
+
+    /// Checks that the chunks of `hybrid_encoder` can be concatenated with the
+    /// chunks of the encoder, filling in its defaults. The chunks are always
+    /// verified before concatenating, as a mismatch in parameters such as the
+    /// color primaries would otherwise go unnoticed until playback.
+    fn validate_hybrid_encoder(&mut self, hybrid_encoder: Encoder, problems: &mut Problems) {
+        if hybrid_encoder.format() != self.encoder.format() {
+            problems.push(
+                format!(
+                    "--hybrid-encoder {hybrid_encoder} does not produce {}, so its chunks cannot \
+                     be concatenated with the chunks of {}",
+                    self.encoder.format(),
+                    self.encoder
+                ),
+                Some("choose a hybrid encoder of the same format, e.g. svt-av1 with aom"),
             );
+            return;
         }
-        if self.chunk_method == ChunkMethod::DGDECNV && which::which("dgindexnv").is_err() {
-            ensure!(
-                self.vapoursynth_plugins.is_some_and(|p| p.dgdecnv),
-                "Either DGDecNV is not installed or DGIndexNV is not in system path, but it was \
-                 specified as the chunk method"
+        let bin = hybrid_encoder.bin();
+        if which::which(bin).is_err() {
+            problems.push(
+                format!("Hybrid encoder {bin} not found. Is it installed in the system path?"),
+                Some(format!("install {bin} and add it to the PATH")),
             );
+            return;
         }
-        if self.chunk_method == ChunkMethod::BESTSOURCE {
-            ensure!(
-                self.vapoursynth_plugins.is_some_and(|p| p.bestsource),
-                "BestSource is not installed, but it was specified as the chunk method"
+        if hybrid_encoder.is_hardware() && hybrid_encoder.version_text().is_none() {
+            problems.push(
+                format!("FFmpeg was built without the {hybrid_encoder} encoder"),
+                Some("install a build of FFmpeg with NVENC"),
             );
+            return;
         }
-        if self.chunk_method == ChunkMethod::Select {
-            warn!("It is not recommended to use the \"select\" chunk method, as it is very slow");
+        let format = self.output_pix_format.format;
+        let supported = hybrid_encoder.get_format_bit_depth(format).is_ok_and(|depth| {
+            hybrid_encoder
+                .supported_bit_depths()
+                .is_none_or(|depths| depths.contains(&depth))
+        });
+        if !supported {
+            problems.push(
+                format!("Output pixel format {format:?} is not supported by {hybrid_encoder}"),
+                Some("choose a pixel format both encoders support with --pix-format"),
+            );
         }
 
-        if self.ignore_frame_mismatch {
-            warn!(
-                "The output video's frame count may differ, and target metric calculations may be \
-                 incorrect"
+        if !self.no_defaults {
+            self.hybrid_params = merge_params(
+                hybrid_encoder.get_default_arguments(self.tiles),
+                &self.hybrid_params,
             );
         }
+        if !self.verify_chunks {
+            debug!("verifying the chunks before concatenating, as they use two encoders");
+            self.verify_chunks = true;
+        }
+    }
 
-        if let Some(vmaf_path) = self.target_quality.model.as_ref() {
-            ensure!(vmaf_path.exists());
+    /// Checks that the plugin needed by the chunk method is installed
+    fn validate_chunk_method(&self, problems: &mut Problems) {
+        let fix = Some("install it, or choose a different chunk method with -m");
+        match self.chunk_method {
+            ChunkMethod::LSMASH if !self.vapoursynth_plugins.is_some_and(|p| p.lsmash) => {
+                problems.push(
+                    "LSMASH is not installed, but it was specified as the chunk method",
+                    fix,
+                );
+            },
+            ChunkMethod::FFMS2 if !self.vapoursynth_plugins.is_some_and(|p| p.ffms2) => {
+                problems.push(
+                    "FFMS2 is not installed, but it was specified as the chunk method",
+                    fix,
+                );
+            },
+            ChunkMethod::DGDECNV
+                if which::which("dgindexnv").is_err()
+                    && !self.vapoursynth_plugins.is_some_and(|p| p.dgdecnv) =>
+            {
+                problems.push(
+                    "Either DGDecNV is not installed or DGIndexNV is not in system path, but it \
+                     was specified as the chunk method",
+                    fix,
+                );
+            },
+            ChunkMethod::BESTSOURCE if !self.vapoursynth_plugins.is_some_and(|p| p.bestsource) => {
+                problems.push(
+                    "BestSource is not installed, but it was specified as the chunk method",
+                    fix,
+                );
+            },
+            ChunkMethod::Select => {
+                warn!(
+                    "It is not recommended to use the \"select\" chunk method, as it is very slow"
+                );
+            },
+            _ => {},
         }
+    }
 
-        if self.target_quality.probes < 4 {
-            warn!("Target quality with fewer than 4 probes is experimental and not recommended");
+    /// Checks that the scene detection decoder can read the input, and that
+    /// the scene detection method can run
+    fn validate_sc_decoder(&self, problems: &mut Problems) {
+        match self.sc_decoder {
+            ScDecoder::FFmpeg if !self.input.is_video() => {
+                problems.push(
+                    "--sc-decoder ffmpeg cannot read a VapourSynth script",
+                    Some("use --sc-decoder vapoursynth or --sc-decoder y4m-pipe"),
+                );
+            },
+            ScDecoder::VapourSynth | ScDecoder::Y4mPipe
+                if !self.input.is_vapoursynth_script()
+                    && !self.vapoursynth_plugins.is_some_and(|p| {
+                        p.best_available_chunk_method() != ChunkMethod::Hybrid
+                    }) =>
+            {
+                problems.push(
+                    format!(
+                        "--sc-decoder {} requires a VapourSynth source plugin to index the input",
+                        self.sc_decoder
+                    ),
+                    Some(
+                        "install lsmash, ffms2, dgdecnv or bestsource, or use --sc-decoder ffmpeg",
+                    ),
+                );
+            },
+            _ => {},
         }
 
-        let encoder_bin = self.encoder.bin();
-        if which::which(encoder_bin).is_err() {
-            bail!(
-                "Encoder {} not found. Is it installed in the system path?",
-                encoder_bin
+        if matches!(self.sc_method, ScenecutMethod::FirstPass)
+            && matches!(self.split_method, SplitMethod::AvScenechange)
+            && which::which("x264").is_err()
+        {
+            problems.push(
+                "--sc-method first-pass requires x264, which was not found",
+                Some("install x264, or use --sc-method standard"),
             );
         }
+    }
+
+    /// Warns about options that have no effect with the other settings
+    fn warn_ignored_options(&mut self) {
+        if self.sc_transitions != TransitionCut::Detected {
+            let ignored = if matches!(self.split_method, SplitMethod::None) {
+                Some("with --split-method none")
+            } else if matches!(self.sc_method, ScenecutMethod::FirstPass) {
+                Some("with --sc-method first-pass, which has no frame scores")
+            } else if self.scenes.as_ref().is_some_and(|path| path.exists()) {
+                Some("with an existing --scenes file")
+            } else {
+                None
+            };
+            if let Some(ignored) = ignored {
+                warn!("--sc-transitions has no effect {ignored}");
+                self.sc_transitions = TransitionCut::Detected;
+            }
+        }
+        if self.sc_proxy {
+            let ignored = if self.target_quality.target.is_none() {
+                Some("without --target-quality".to_string())
+            } else if matches!(self.split_method, SplitMethod::None) {
+                Some("with --split-method none".to_string())
+            } else if !self.input.is_video() {
+                Some("with VapourSynth script input".to_string())
+            } else if matches!(self.sc_method, ScenecutMethod::FirstPass) {
+                Some("with --sc-method first-pass".to_string())
+            } else if self.sc_decoder == ScDecoder::VapourSynth {
+                Some(
+                    "with --sc-decoder vapoursynth, as only frames piped through FFmpeg are kept"
+                        .to_string(),
+                )
+            } else if !matches!(
+                self.chunk_method,
+                ChunkMethod::LSMASH
+                    | ChunkMethod::FFMS2
+                    | ChunkMethod::DGDECNV
+                    | ChunkMethod::BESTSOURCE
+            ) {
+                Some(format!(
+                    "with chunk method {}, which encodes the probes from the source",
+                    self.chunk_method
+                ))
+            } else {
+                None
+            };
+            if let Some(ignored) = ignored {
+                warn!("--sc-proxy has no effect {ignored}");
+                self.sc_proxy = false;
+            }
+        }
 
-        if self.tile_auto {
-            self.tiles = self.input.calculate_tiles();
+        if self.sc_checkpoint {
+            let ignored = if matches!(self.split_method, SplitMethod::None) {
+                Some("with --split-method none")
+            } else if matches!(self.sc_method, ScenecutMethod::FirstPass) {
+                Some("with --sc-method first-pass")
+            } else {
+                None
+            };
+            if let Some(ignored) = ignored {
+                warn!("--sc-checkpoint has no effect {ignored}");
+                self.sc_checkpoint = false;
+            }
         }
 
-        if !self.no_defaults {
-            if self.video_params.is_empty() {
-                self.video_params = self.encoder.get_default_arguments(self.tiles);
+        if self.sc_lookahead.is_some() {
+            let ignored = if matches!(self.split_method, SplitMethod::None) {
+                Some("with --split-method none".to_string())
+            } else if self.scenes.is_some() || self.edit_scenes.is_some() {
+                Some("when the scenes are read from a file".to_string())
+            } else if self.sc_only || self.sc_preview.is_some() {
+                Some("with --sc-only or --sc-preview".to_string())
+            } else if self.two_stage.is_some() {
+                Some("with --two-stage, which needs every chunk".to_string())
+            } else if self.sc_proxy {
+                Some("with --sc-proxy".to_string())
+            } else if matches!(self.sc_method, ScenecutMethod::FirstPass) {
+                Some("with --sc-method first-pass, which finds every scene at once".to_string())
+            } else if self.geometry_changes != GeometryChanges::Ignore {
+                Some("with --geometry-changes".to_string())
+            } else if self.static_scenes.is_some() {
+                Some("with --static-scenes, which decodes the whole video first".to_string())
+            } else if self.zones_only {
+                Some("with --zones-only".to_string())
+            } else if self.trim.is_some() {
+                Some("with --trim-start or --trim-end".to_string())
+            } else if self.input.is_video()
+                && matches!(
+                    self.chunk_method,
+                    ChunkMethod::Segment | ChunkMethod::Hybrid | ChunkMethod::Trim
+                )
+            {
+                Some(format!(
+                    "with chunk method {}, which splits the input at every scene first",
+                    self.chunk_method
+                ))
+            } else {
+                None
+            };
+            if let Some(ignored) = ignored {
+                warn!("--sc-lookahead has no effect {ignored}");
+                self.sc_lookahead = None;
+            }
+        }
+
+        if (self.zones_only || self.trim.is_some()) && self.vmaf {
+            warn!(
+                "--vmaf has no effect with --zones-only, --trim-start or --trim-end, as the \
+                 output is not the whole input"
+            );
+            self.vmaf = false;
+        }
+        if !self.metrics.is_empty() {
+            let ignored = if self.zones_only || self.trim.is_some() {
+                Some(
+                    "with --zones-only, --trim-start or --trim-end, as the output is not the \
+                     whole input",
+                )
+            } else if self.output_fps.is_some() {
+                Some("with --output-fps, as the frames of the output are not those of the input")
             } else {
-                // merge video_params with defaults, overriding defaults
-                // TODO: consider using hashmap to store program arguments instead of string
-                // vector
-                let default_video_params = self.encoder.get_default_arguments(self.tiles);
-                let mut skip = false;
-                let mut _default_params: Vec<String> = Vec::new();
-                for param in default_video_params {
-                    if skip && !(param.starts_with("-") && param != "-1") {
-                        skip = false;
-                        continue;
-                    }
-
-                    skip = false;
-                    if (param.starts_with("-") && param != "-1")
-                        && self.video_params.contains(&param)
-                    {
-                        skip = true;
-                        continue;
-                    }
-
-                    _default_params.push(param);
+                None
+            };
+            if let Some(ignored) = ignored {
+                for metric in &self.metrics {
+                    warn!("--metric {metric} has no effect {ignored}");
                 }
-                self.video_params = chain!(_default_params, self.video_params.clone()).collect();
+                self.metrics.clear();
+            }
+        }
+
+        if self.tail_boost && self.set_thread_affinity.is_some() {
+            warn!(
+                "--tail-boost has no effect with --set-thread-affinity, as workers are pinned to \
+                 a fixed number of threads"
+            );
+        }
+
+        if self.afterburner > 0 && self.passes < 2 && self.zones.is_none() {
+            warn!("--afterburner has no effect with a single pass");
+        }
+
+        if self.probe_workers > 0 {
+            if self.target_quality.target.is_none() && self.zones.is_none() {
+                warn!("--probe-workers has no effect without --target-quality");
+                self.probe_workers = 0;
+            } else if self.afterburner > 0 {
+                warn!(
+                    "--afterburner has no effect with --probe-workers, the first passes run after \
+                     probing"
+                );
+                self.afterburner = 0;
+            }
+        }
+
+        if self.target_quality.keep_probes
+            && self.target_quality.target.is_none()
+            && self.zones.is_none()
+        {
+            warn!("--keep-probes has no effect without --target-quality");
+            self.target_quality.keep_probes = false;
+        }
+
+        if self.hybrid_encoder.is_some() {
+            let ignored = if matches!(self.split_method, SplitMethod::None) {
+                Some("with --split-method none, which measures no scene complexity")
+            } else if self.scenes.as_ref().is_some_and(|path| path.exists()) {
+                Some("with an existing --scenes file")
+            } else {
+                None
+            };
+            if let Some(ignored) = ignored {
+                warn!("--hybrid-encoder has no effect {ignored}");
+                self.hybrid_encoder = None;
+            }
+        }
+
+        if self.optimize_mux && !concat::can_optimize_mux(Path::new(&self.output_file)) {
+            warn!(
+                "--optimize-mux has no effect with {}, which has no seek index to move",
+                self.output_file
+            );
+            self.optimize_mux = false;
+        }
+
+        if self.output_fps.is_some() && self.ffmpeg_filter_args.is_empty() && self.zones.is_none() {
+            warn!(
+                "--output-fps has no effect without --ffmpeg filters, the frame rate of \
+                 VapourSynth scripts is already read from their output"
+            );
+            self.output_fps = None;
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(size) = self.pipe_size
+            && let Some(max) = crate::pipe::max_pipe_size()
+            && size > max
+        {
+            warn!(
+                "--pipe-size is limited to {} KiB by /proc/sys/fs/pipe-max-size",
+                max / 1024
+            );
+            self.pipe_size = Some(max);
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.pipe_size.is_some() {
+            warn!("--pipe-size has no effect on this system, pipes can only be resized on Linux");
+            self.pipe_size = None;
+        }
+        #[cfg(not(all(target_os = "linux", feature = "memfd")))]
+        if self.memfd_buffer.is_some() {
+            warn!(
+                "--memfd-buffer has no effect, Av1an was built without the memfd feature or not \
+                 for Linux"
+            );
+            self.memfd_buffer = None;
+        }
+
+        if !self.first_pass_params.is_empty() && self.passes == 1 && self.zones.is_none() {
+            warn!("--first-pass-params has no effect with one-pass encoding");
+        }
+
+        if self.gpus.is_empty() {
+            if self.gpu_workers.is_some() {
+                warn!("--gpu-workers has no effect without --gpus");
             }
+        } else if self.input.is_video()
+            && self.chunk_method != ChunkMethod::DGDECNV
+            && !self.encoder.is_hardware()
+            && !self.hybrid_encoder.is_some_and(Encoder::is_hardware)
+        {
+            warn!(
+                "--gpus only affects CUDA applications such as DGDecNV, NVENC or VapourSynth \
+                 filters, which chunk method {} does not use",
+                self.chunk_method
+            );
+        }
+
+        if !input_args().is_empty()
+            && self.input.is_video()
+            && matches!(
+                self.chunk_method,
+                ChunkMethod::LSMASH
+                    | ChunkMethod::FFMS2
+                    | ChunkMethod::DGDECNV
+                    | ChunkMethod::BESTSOURCE
+            )
+        {
+            warn!(
+                "--ffmpeg-input-args are not passed to the VapourSynth source filter of chunk \
+                 method {}, use --chunk-method hybrid, select or segment if it cannot read the \
+                 input without them",
+                self.chunk_method
+            );
+        }
+
+        if self.ignore_frame_mismatch {
+            warn!(
+                "The output video's frame count may differ, and target metric calculations may be \
+                 incorrect"
+            );
         }
+    }
 
+    /// Checks the video parameters and the settings that depend on them
+    fn validate_video_params(&mut self, problems: &mut Problems) {
         if let Some(strength) = self.photon_noise {
             if strength > 64 {
-                bail!("Valid strength values for photon noise are 0-64");
+                problems.push(
+                    "Valid strength values for photon noise are 0-64",
+                    None::<&str>,
+                );
             }
             if ![Encoder::aom, Encoder::rav1e, Encoder::svt_av1].contains(&self.encoder) {
-                bail!("Photon noise synth is only supported with aomenc, rav1e, and svt-av1");
+                problems.push(
+                    "Photon noise synth is only supported with aomenc, rav1e, and svt-av1",
+                    Some("remove --photon-noise, or use one of these encoders"),
+                );
             }
         }
 
@@ -341,9 +1687,10 @@ impl EncodeArgs {
             && self.concat != ConcatMethod::MKVMerge
             && self.video_params.iter().any(|param| param == "--enable-keyframe-filtering=2")
         {
-            bail!(
+            problems.push(
                 "keyframe filtering mode 2 currently only works when using mkvmerge as the concat \
-                 method"
+                 method",
+                Some("use `--concat mkvmerge`, or a different --enable-keyframe-filtering mode"),
             );
         }
 
@@ -355,18 +1702,189 @@ impl EncodeArgs {
             self.passes = 1;
         }
 
-        if !self.force {
-            self.validate_encoder_params()?;
-            self.check_rate_control();
+        if self.encoder.is_hardware() && self.passes != 1 {
+            warn!(
+                "{} encodes in one pass, pass `-multipass fullres` in the video parameters for \
+                 NVENC's own two-pass mode",
+                self.encoder
+            );
+            self.passes = 1;
+        }
+    }
+
+    /// Checks that the encoder accepts the resolution of the input. Pads or
+    /// crops a width or height that does not cover a whole number of chroma
+    /// samples, and raises the tiles of the default video parameters to the
+    /// number AV1 requires at the resolution.
+    fn validate_dimensions(&mut self, problems: &mut Problems) {
+        let Ok(clip_info) = self.input.clip_info() else {
+            return;
+        };
+        if self
+            .ffmpeg_filter_args
+            .iter()
+            .any(|arg| matches!(arg.as_str(), "-vf" | "-filter:v"))
+        {
+            debug!("not checking the input resolution, as --ffmpeg may change it");
+            return;
+        }
+        let (width, height) = clip_info.resolution;
+
+        let (max_width, max_height) = self.encoder.max_resolution();
+        if width > max_width || height > max_height {
+            problems.push(
+                format!(
+                    "{width}x{height} is larger than the {max_width}x{max_height} {} supports",
+                    self.encoder
+                ),
+                Some(format!(
+                    "downscale with --ffmpeg \"-vf \
+                     scale={max_width}:{max_height}:force_original_aspect_ratio=decrease\", or \
+                     use a different encoder"
+                )),
+            );
+        }
+
+        let format = self.output_pix_format.format;
+        let (sub_w, sub_h) = format.chroma_subsampling();
+        if self.encoder.needs_whole_chroma_samples() && (width % sub_w != 0 || height % sub_h != 0)
+        {
+            let problem = format!(
+                "{} cannot encode {width}x{height} as {}, whose chroma planes are subsampled \
+                 {sub_w}x{sub_h}",
+                self.encoder,
+                format.to_pix_fmt_string()
+            );
+            if let Some(filter) = self.odd_dimensions.filter((width, height), (sub_w, sub_h)) {
+                info!("{problem}, applying `{filter}`");
+                self.ffmpeg_filter_args =
+                    chunk_filter_args(&self.ffmpeg_filter_args, Some(&filter));
+            } else {
+                problems.push(
+                    problem,
+                    Some("pass --odd-dimensions pad or --odd-dimensions crop"),
+                );
+            }
+        }
+
+        if matches!(
+            self.encoder,
+            Encoder::aom | Encoder::rav1e | Encoder::svt_av1
+        ) {
+            let (columns, rows) = min_av1_tiles(width, height);
+            if self.tiles.0 < columns || self.tiles.0 * self.tiles.1 < columns * rows {
+                self.tiles = (self.tiles.0.max(columns), self.tiles.1.max(rows));
+                debug!(
+                    "raised the default tiles to {}x{} for {width}x{height}",
+                    self.tiles.0, self.tiles.1
+                );
+            }
         }
+    }
+
+    /// Threads each worker has to encode with, or all of them if the number of
+    /// workers is chosen automatically, as that depends on the tiles
+    fn threads_per_worker(&self) -> usize {
+        let threads = available_parallelism().map_or(1, NonZero::get);
+        threads.checked_div(self.workers).map_or(threads, |threads| threads.max(1))
+    }
+
+    /// Tiles to encode the input with, see [`recommended_av1_tiles`]
+    fn recommended_tiles(&self) -> Option<(u32, u32)> {
+        let (width, height) = self.input.clip_info().ok()?.resolution;
+        Some(recommended_av1_tiles(
+            width,
+            height,
+            self.threads_per_worker(),
+        ))
+    }
+
+    /// Checks that the tiles set in the video parameters are allowed at the
+    /// resolution of the input, and warns if they are more than it benefits
+    /// from
+    fn validate_tiles(&self, problems: &mut Problems) {
+        let Some((columns, rows)) = av1_tiles(self.encoder, &self.video_params) else {
+            return;
+        };
+        let Ok(clip_info) = self.input.clip_info() else {
+            return;
+        };
+        let (width, height) = clip_info.resolution;
+        let (min_columns, min_rows) = min_av1_tiles(width, height);
+        if columns < min_columns || columns * rows < min_columns * min_rows {
+            problems.push(
+                format!(
+                    "AV1 needs at least {min_columns}x{min_rows} tiles at {width}x{height}, but \
+                     the video parameters set {columns}x{rows}"
+                ),
+                Some(format!(
+                    "use `{}`, or pass --force to use the video parameters anyway",
+                    av1_tile_params(self.encoder, (columns.max(min_columns), rows.max(min_rows)))
+                )),
+            );
+            return;
+        }
+        let threads = self.threads_per_worker();
+        let recommended = recommended_av1_tiles(width, height, threads);
+        if columns * rows > recommended.0 * recommended.1 {
+            warn!(
+                "The video parameters set {columns}x{rows} tiles, but {width}x{height} with \
+                 {threads} thread(s) per worker only benefits from {}x{}. More tiles lose \
+                 compression efficiency without encoding faster; `{}` would be better",
+                recommended.0,
+                recommended.1,
+                av1_tile_params(self.encoder, recommended)
+            );
+        }
+    }
+
+    /// Checks that the installed encoder binary supports the bit depth of the
+    /// output pixel format, switching to the same color layout at a
+    /// supported bit depth if it doesn't
+    fn validate_output_pix_format(&mut self) -> anyhow::Result<()> {
+        let format = self.output_pix_format.format;
+        let bit_depth = self.encoder.get_format_bit_depth(format)?;
+        let Some(supported) = self.encoder.supported_bit_depths() else {
+            return Ok(());
+        };
+        if supported.contains(&bit_depth) {
+            return Ok(());
+        }
+
+        let fallback = supported.iter().rev().find_map(|&depth| {
+            format
+                .with_bit_depth(depth)
+                .filter(|fallback| {
+                    self.encoder.get_format_bit_depth(*fallback).is_ok_and(|d| d == depth)
+                })
+                .map(|fallback| (fallback, depth))
+        });
+        let Some((fallback, fallback_depth)) = fallback else {
+            bail!(
+                "{} was built without {bit_depth}-bit support, which is required for output pixel \
+                 format {format:?}",
+                self.encoder.bin()
+            );
+        };
+        warn!(
+            "{} was built without {bit_depth}-bit support, using output pixel format {fallback:?} \
+             instead of {format:?}",
+            self.encoder.bin()
+        );
+        self.output_pix_format = PixelFormat {
+            format:    fallback,
+            bit_depth: fallback_depth,
+        };
+        self.target_quality.pix_format = fallback;
 
         Ok(())
     }
 
-    fn validate_encoder_params(&self) -> anyhow::Result<()> {
+    fn validate_encoder_params(&self, problems: &mut Problems) {
         let video_params: Vec<&str> = self
             .video_params
             .iter()
+            .chain(&self.first_pass_params)
             .filter_map(|param| {
                 if param.starts_with('-') && [Encoder::aom, Encoder::vpx].contains(&self.encoder) {
                     // These encoders require args to be passed using an equal sign,
@@ -380,29 +1898,35 @@ impl EncodeArgs {
             })
             .collect();
 
-        let help_text = {
-            let [cmd, arg] = self.encoder.help_command();
-            String::from_utf8_lossy(&Command::new(cmd).arg(arg).output()?.stdout).to_string()
+        let (cmd, args) = self.encoder.help_command();
+        let help_text = match Command::new(cmd).args(args).output() {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+            Err(e) => {
+                problems.push(
+                    format!(
+                        "Failed to run `{cmd} {}` to check the video parameters: {e}",
+                        args.join(" ")
+                    ),
+                    Some("pass --force to skip checking the video parameters"),
+                );
+                return;
+            },
         };
         let valid_params = valid_params(&help_text, self.encoder);
-        let invalid_params = invalid_params(&video_params, &valid_params);
 
-        for wrong_param in &invalid_params {
-            eprintln!(
-                "'{}' isn't a valid parameter for {}",
-                wrong_param, self.encoder,
+        for wrong_param in invalid_params(&video_params, &valid_params) {
+            let fix = suggest_fix(wrong_param, &valid_params).map_or_else(
+                || "pass --force to use it anyway".to_string(),
+                |suggestion| format!("did you mean '{suggestion}'? Pass --force to use it anyway"),
+            );
+            problems.push(
+                format!(
+                    "'{wrong_param}' isn't a valid parameter for {}",
+                    self.encoder
+                ),
+                Some(fix),
             );
-            if let Some(suggestion) = suggest_fix(wrong_param, &valid_params) {
-                eprintln!("\tDid you mean '{suggestion}'?");
-            }
-        }
-
-        if !invalid_params.is_empty() {
-            println!("\nTo continue anyway, run av1an with '--force'");
-            exit(1);
         }
-
-        Ok(())
     }
 
     /// Warns if rate control was not specified in encoder arguments
@@ -447,22 +1971,6 @@ impl EncodeArgs {
         }
     }
 
-    #[inline]
-    pub fn validate_ssimulacra2(&self) -> anyhow::Result<()> {
-        ensure!(
-            self.vapoursynth_plugins.is_some_and(|p| p.vship)
-                || self.vapoursynth_plugins.is_some_and(|p| p.vszip != VSZipVersion::None),
-            "SSIMULACRA2 metric requires either Vapoursynth-HIP or VapourSynth Zig Image Process \
-             to be installed"
-        );
-        self.ensure_chunk_method(
-            "Chunk method must be lsmash, ffms2, bestsource, or dgdecnv for SSIMULACRA2"
-                .to_string(),
-        )?;
-
-        Ok(())
-    }
-
     #[inline]
     pub fn validate_butteraugli_inf(&self) -> anyhow::Result<()> {
         ensure!(
@@ -534,6 +2042,102 @@ impl EncodeArgs {
     }
 }
 
+/// Whether `language` looks like an ISO 639-2 code or a BCP 47 tag that
+/// mkvmerge accepts: a language subtag of 2 or 3 letters, followed by subtags
+/// of letters and digits
+fn is_language_tag(language: &str) -> bool {
+    let mut subtags = language.split('-');
+    subtags.next().is_some_and(|primary| {
+        (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic())
+    }) && subtags.all(|subtag| {
+        (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Merges `params` into `defaults`, dropping every default parameter (and its
+/// value) that `params` sets as well
+// TODO: consider using hashmap to store program arguments instead of string
+// vector
+pub(crate) fn merge_params(defaults: Vec<String>, params: &[String]) -> Vec<String> {
+    fn is_flag(param: &str) -> bool {
+        param.starts_with('-') && param != "-1"
+    }
+    // aomenc and vpxenc take the value after an equal sign, e.g. `--cq-level=30`
+    fn key(param: &str) -> &str {
+        param.split('=').next().unwrap_or(param)
+    }
+
+    let overridden: HashSet<&str> =
+        params.iter().filter(|param| is_flag(param)).map(|param| key(param)).collect();
+
+    let mut skip = false;
+    let mut merged = Vec::new();
+    for param in defaults {
+        if skip && !is_flag(&param) {
+            skip = false;
+            continue;
+        }
+
+        skip = false;
+        if is_flag(&param) && overridden.contains(key(&param)) {
+            skip = true;
+            continue;
+        }
+
+        merged.push(param);
+    }
+    chain!(merged, params.iter().cloned()).collect()
+}
+
+/// A problem with the settings found by [`EncodeArgs::validate`]
+#[derive(Debug)]
+struct Problem {
+    message: String,
+    /// How the problem can be fixed, if it is not obvious from the message
+    fix:     Option<String>,
+}
+
+/// Collects every problem with the settings, so that they can be reported
+/// together instead of only the first one
+#[derive(Debug, Default)]
+struct Problems {
+    problems: Vec<Problem>,
+}
+
+impl Problems {
+    fn push(&mut self, message: impl Into<String>, fix: Option<impl Into<String>>) {
+        self.problems.push(Problem {
+            message: message.into(),
+            fix:     fix.map(Into::into),
+        });
+    }
+
+    /// Records the error of a check that failed
+    fn check(&mut self, result: anyhow::Result<()>, fix: Option<impl Into<String>>) {
+        if let Err(e) = result {
+            self.push(format!("{e:#}"), fix);
+        }
+    }
+
+    fn into_result(self) -> anyhow::Result<()> {
+        let describe = |problem: &Problem| {
+            problem.fix.as_ref().map_or_else(
+                || problem.message.clone(),
+                |fix| format!("{}\n  help: {fix}", problem.message),
+            )
+        };
+        match self.problems.as_slice() {
+            [] => Ok(()),
+            [problem] => bail!("{}", describe(problem)),
+            problems => bail!(
+                "Found {} problems with the settings:\n\n{}",
+                problems.len(),
+                problems.iter().map(|problem| format!("- {}", describe(problem))).join("\n")
+            ),
+        }
+    }
+}
+
 #[must_use]
 pub(crate) fn invalid_params<'a>(
     params: &'a [&'a str],
@@ -597,3 +2201,122 @@ pub(crate) fn insert_noise_table_params(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tier_params_override_defaults() {
+        let defaults = Encoder::svt_av1.get_default_arguments((1, 1));
+        let tiered = merge_params(defaults, &EncoderTier::Fast.video_params(Encoder::svt_av1));
+        assert_eq!(tiered, [
+            "--keyint", "0", "--scd", "0", "--rc", "0", "--preset", "8", "--crf", "32"
+        ]);
+        let video_params: Vec<String> = into_vec!["--crf", "20"];
+        assert_eq!(merge_params(tiered, &video_params), [
+            "--keyint", "0", "--scd", "0", "--rc", "0", "--preset", "8", "--crf", "20"
+        ]);
+
+        let defaults = Encoder::aom.get_default_arguments((1, 1));
+        let tiered = merge_params(defaults, &EncoderTier::Archival.video_params(Encoder::aom));
+        assert_eq!(tiered, [
+            "--threads=8",
+            "--end-usage=q",
+            "--disable-kf",
+            "--kf-max-dist=9999",
+            "--cpu-used=3",
+            "--cq-level=24",
+            "--lag-in-frames=48",
+            "--enable-qm=1"
+        ]);
+    }
+
+    #[test]
+    fn first_pass_params_override_video_params() {
+        let video_params: Vec<String> =
+            into_vec!["--end-usage=q", "--cpu-used=3", "--cq-level=24", "--enable-qm=1"];
+        let first_pass_params: Vec<String> = into_vec!["--cpu-used=6", "--enable-qm=0"];
+        assert_eq!(merge_params(video_params, &first_pass_params), [
+            "--end-usage=q",
+            "--cq-level=24",
+            "--cpu-used=6",
+            "--enable-qm=0"
+        ]);
+    }
+
+    #[test]
+    fn language_tags() {
+        for language in ["en", "eng", "jpn", "en-US", "zh-Hant-TW", "de-CH-1996"] {
+            assert!(is_language_tag(language), "{language} should be valid");
+        }
+        for language in ["", "e", "english", "en_US", "en-", "-en", "en-toolongsubtag"] {
+            assert!(!is_language_tag(language), "{language} should be invalid");
+        }
+    }
+
+    #[test]
+    fn problems_are_reported_together() {
+        assert!(Problems::default().into_result().is_ok());
+
+        let mut problems = Problems::default();
+        problems.push("FFmpeg not found", Some("install FFmpeg"));
+        assert_eq!(
+            problems.into_result().expect_err("should fail").to_string(),
+            "FFmpeg not found\n  help: install FFmpeg"
+        );
+
+        let mut problems = Problems::default();
+        problems.push("FFmpeg not found", Some("install FFmpeg"));
+        problems.check(Ok(()), None::<&str>);
+        problems.check(
+            Err(anyhow::anyhow!("Zones file contains overlapping zones")),
+            None::<&str>,
+        );
+        assert_eq!(
+            problems.into_result().expect_err("should fail").to_string(),
+            "Found 2 problems with the settings:\n\n- FFmpeg not found\n  help: install FFmpeg\n- \
+             Zones file contains overlapping zones"
+        );
+    }
+
+    #[test]
+    fn global_config_parse() {
+        let config: GlobalConfig = toml::from_str(
+            "workers = 4\ntemp-root = \"/tmp/av1an\"\nniceness = 10\nchunk-method = \"lsmash\"\n",
+        )
+        .expect("config should parse");
+        assert_eq!(config.workers, Some(4));
+        assert_eq!(config.temp_root, Some(PathBuf::from("/tmp/av1an")));
+        assert_eq!(config.niceness, Some(10));
+        assert_eq!(config.chunk_method, Some(ChunkMethod::LSMASH));
+        assert_eq!(config.notify_command, None);
+
+        assert!(toml::from_str::<GlobalConfig>("wrokers = 4").is_err());
+        assert!(toml::from_str::<GlobalConfig>("chunk-method = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn global_config_merge_and_dump() {
+        let file = GlobalConfig {
+            workers: Some(4),
+            chunk_method: Some(ChunkMethod::FFMS2),
+            ..GlobalConfig::default()
+        };
+        let cli = GlobalConfig {
+            workers: Some(8),
+            niceness: Some(5),
+            ..GlobalConfig::default()
+        };
+        let merged = file.merge(cli);
+        assert_eq!(merged.workers, Some(8));
+        assert_eq!(merged.niceness, Some(5));
+        assert_eq!(merged.chunk_method, Some(ChunkMethod::FFMS2));
+
+        let dumped = merged.to_toml().expect("config should serialize");
+        assert_eq!(
+            toml::from_str::<GlobalConfig>(&dumped).expect("dumped config should parse"),
+            merged
+        );
+    }
+}
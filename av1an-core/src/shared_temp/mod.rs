@@ -0,0 +1,259 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, ProcessesToUpdate, System};
+use tracing::{debug, warn};
+
+use crate::{get_done, schema, DoneJson};
+
+/// Directory of the temporary directory with one claim file per chunk
+const CLAIMS_DIR: &str = "claims";
+/// Claim of the instance that concatenates the output
+const CONCAT_CLAIM: &str = "concat.claim";
+/// Held while an instance updates done.json
+const DONE_LOCK: &str = "done.json.lock";
+
+/// How long an instance waits for the lock of done.json before taking it
+/// over, as the instance holding it likely died while writing
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const LOCK_POLL: Duration = Duration::from_millis(50);
+
+/// How often an instance updates the modification time of its claims, to show
+/// that it is still running
+const HEARTBEAT: Duration = Duration::from_secs(60);
+/// How long a claim can go without a heartbeat before it counts as stale, as
+/// its instance likely was killed. Covers instances of other machines, whose
+/// processes cannot be checked.
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The Av1an instance that created a claim or lock file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Owner {
+    host: String,
+    pid:  u32,
+    /// Seconds since the Unix epoch
+    time: u64,
+}
+
+impl Owner {
+    fn current() -> Self {
+        Self {
+            host: System::host_name().unwrap_or_default(),
+            pid:  process::id(),
+            time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        }
+    }
+
+    /// Whether the owner is a process of this machine that is no longer
+    /// running. The processes of other machines cannot be checked.
+    fn is_dead(&self) -> bool {
+        if self.host != System::host_name().unwrap_or_default() {
+            return false;
+        }
+        let pid = Pid::from_u32(self.pid);
+        let mut system = System::new();
+        system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+        system.process(pid).is_none()
+    }
+}
+
+/// Creates `path` for this instance, unless it already exists. Creating a
+/// file that must not exist is atomic on local and network file systems, so
+/// only one instance gets each claim.
+fn create_claim(path: &Path) -> anyhow::Result<bool> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => {
+            let owner =
+                serde_json::to_string(&Owner::current()).expect("serialize should not fail");
+            file.write_all(owner.as_bytes())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            Ok(true)
+        },
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to create {}", path.display())),
+    }
+}
+
+fn read_owner(path: &Path) -> Option<Owner> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Whether the instance of the claim at `path` is no longer running, or has
+/// not updated the claim for [`CLAIM_TIMEOUT`]
+fn is_stale(path: &Path) -> bool {
+    let Some(owner) = read_owner(path) else {
+        return false;
+    };
+    owner.is_dead()
+        || fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > CLAIM_TIMEOUT))
+}
+
+/// Updates the modification time of the claims of this instance in `claims`
+fn touch_own_claims(claims: &Path) -> io::Result<()> {
+    let current = Owner::current();
+    for entry in fs::read_dir(claims)? {
+        let path = entry?.path();
+        if read_owner(&path)
+            .is_some_and(|owner| owner.host == current.host && owner.pid == current.pid)
+        {
+            File::options().write(true).open(&path)?.set_modified(SystemTime::now())?;
+        }
+    }
+    Ok(())
+}
+
+/// Starts a thread that updates the claims of this instance every
+/// [`HEARTBEAT`], so that other instances do not take them over as stale. It
+/// stops once the claims directory was removed.
+pub(crate) fn start_heartbeat(temp: &Path) {
+    let claims = temp.join(CLAIMS_DIR);
+    thread::spawn(move || loop {
+        thread::sleep(HEARTBEAT);
+        match touch_own_claims(&claims) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => warn!("Failed to update the claims in {}: {e}", claims.display()),
+        }
+    });
+}
+
+fn chunk_claim_path(temp: &Path, name: &str) -> PathBuf {
+    temp.join(CLAIMS_DIR).join(format!("{name}.claim"))
+}
+
+/// Removes the stale claims (see [`is_stale`]) of chunks that were not
+/// finished, so that the chunks their instances were encoding are encoded
+/// again. Also creates the claims directory.
+pub(crate) fn clear_stale_claims(temp: &Path) -> anyhow::Result<()> {
+    let claims = temp.join(CLAIMS_DIR);
+    fs::create_dir_all(&claims)
+        .with_context(|| format!("Failed to create {}", claims.display()))?;
+    let done = get_done();
+    for entry in fs::read_dir(&claims)? {
+        let path = entry?.path();
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !done.done.contains_key(name) && is_stale(&path) {
+            debug!("removing the claim of chunk {name}, whose instance is no longer running");
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Claims the chunk `name` for this instance. Returns `false` if another
+/// instance already claimed it, and is encoding or has encoded it.
+pub(crate) fn claim_chunk(temp: &Path, name: &str) -> anyhow::Result<bool> {
+    create_claim(&chunk_claim_path(temp, name))
+}
+
+/// Gives up the claim of a chunk that this instance did not finish, so that
+/// another instance can encode it
+pub(crate) fn release_chunk(temp: &Path, name: &str) {
+    let path = chunk_claim_path(temp, name);
+    if let Err(e) = fs::remove_file(&path) {
+        warn!("Failed to release the claim {}: {e}", path.display());
+    }
+}
+
+/// Claims the concatenation of the output for this instance. Returns `false`
+/// if another instance already concatenates it.
+pub(crate) fn claim_concat(temp: &Path) -> anyhow::Result<bool> {
+    create_claim(&temp.join(CONCAT_CLAIM))
+}
+
+/// Lock of done.json, released when dropped
+struct DoneLock(PathBuf);
+
+impl DoneLock {
+    fn acquire(temp: &Path) -> anyhow::Result<Self> {
+        let path = temp.join(DONE_LOCK);
+        let mut waited = Duration::ZERO;
+        while !create_claim(&path)? {
+            if waited >= LOCK_TIMEOUT {
+                warn!(
+                    "{} was held for more than {}s, taking it over",
+                    path.display(),
+                    LOCK_TIMEOUT.as_secs()
+                );
+                fs::remove_file(&path).ok();
+                waited = Duration::ZERO;
+                continue;
+            }
+            thread::sleep(LOCK_POLL);
+            waited += LOCK_POLL;
+        }
+        Ok(Self(path))
+    }
+}
+
+impl Drop for DoneLock {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// Adds the chunks that other instances finished to `done`
+fn merge_done(done: &DoneJson, other: DoneJson) {
+    for (name, chunk) in other.done {
+        done.done.entry(name).or_insert(chunk);
+    }
+    if other.audio_done.load(atomic::Ordering::SeqCst) {
+        done.audio_done.store(true, atomic::Ordering::SeqCst);
+    }
+    done.frames.fetch_max(
+        other.frames.load(atomic::Ordering::SeqCst),
+        atomic::Ordering::SeqCst,
+    );
+}
+
+/// Reads done.json while holding its lock, and adds the chunks that other
+/// instances finished to the chunks of this instance
+pub(crate) fn refresh_done(temp: &Path) -> anyhow::Result<()> {
+    let _lock = DoneLock::acquire(temp)?;
+    read_into_done(temp)
+}
+
+fn read_into_done(temp: &Path) -> anyhow::Result<()> {
+    let path = temp.join("done.json");
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            merge_done(get_done(), schema::DONE.load(&contents)?);
+            Ok(())
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Writes done.json with the chunks of every instance. The file is replaced
+/// at once, so that other instances never read a partially written file.
+pub(crate) fn write_done(temp: &Path) -> anyhow::Result<()> {
+    let _lock = DoneLock::acquire(temp)?;
+    read_into_done(temp)?;
+
+    let path = temp.join("done.json");
+    let partial = temp.join(format!("done.json.{}", process::id()));
+    fs::write(&partial, serde_json::to_string(get_done())?)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    if let Err(e) = fs::rename(&partial, &path) {
+        fs::remove_file(&partial).ok();
+        bail!("Failed to write {}: {e}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+use super::*;
+use crate::{DashMap, DoneChunk};
+
+fn done_json(chunks: &[&str], audio_done: bool) -> DoneJson {
+    DoneJson {
+        version:    schema::DONE.version(),
+        frames:     AtomicUsize::new(100),
+        done:       chunks
+            .iter()
+            .map(|name| {
+                (name.to_string(), DoneChunk {
+                    frames:     10,
+                    size_bytes: 1000,
+                    summary:    Default::default(),
+                    score:      None,
+                })
+            })
+            .collect::<DashMap<_, _>>(),
+        audio_done: AtomicBool::new(audio_done),
+    }
+}
+
+#[test]
+fn chunks_are_claimed_once() {
+    let temp = tempfile::tempdir().expect("should create temp dir");
+    fs::create_dir_all(temp.path().join(CLAIMS_DIR)).expect("should create claims dir");
+
+    assert!(claim_chunk(temp.path(), "00000").expect("should claim"));
+    assert!(!claim_chunk(temp.path(), "00000").expect("should check claim"));
+    assert!(claim_chunk(temp.path(), "00001").expect("should claim"));
+
+    let owner = read_owner(&chunk_claim_path(temp.path(), "00000")).expect("should read owner");
+    assert_eq!(owner.pid, process::id());
+    assert!(!owner.is_dead());
+
+    release_chunk(temp.path(), "00000");
+    assert!(claim_chunk(temp.path(), "00000").expect("should claim again"));
+
+    assert!(claim_concat(temp.path()).expect("should claim concat"));
+    assert!(!claim_concat(temp.path()).expect("should check concat claim"));
+}
+
+#[test]
+fn claims_of_dead_processes_are_detected() {
+    let owner = Owner {
+        pid: u32::MAX - 1,
+        ..Owner::current()
+    };
+    assert!(owner.is_dead());
+
+    // processes of other machines are assumed to be running
+    let owner = Owner {
+        host: "another-machine".to_string(),
+        pid: u32::MAX - 1,
+        ..Owner::current()
+    };
+    assert!(!owner.is_dead());
+}
+
+#[test]
+fn claims_without_heartbeat_are_stale() {
+    let temp = tempfile::tempdir().expect("should create temp dir");
+    let claims = temp.path().join(CLAIMS_DIR);
+    fs::create_dir_all(&claims).expect("should create claims dir");
+    let age = |path: &Path| {
+        File::options()
+            .write(true)
+            .open(path)
+            .expect("should open claim")
+            .set_modified(SystemTime::now() - 2 * CLAIM_TIMEOUT)
+            .expect("should set modification time");
+    };
+
+    // a claim of another machine is only stale once it is not updated
+    let other = chunk_claim_path(temp.path(), "00000");
+    let owner = Owner {
+        host: "another-machine".to_string(),
+        ..Owner::current()
+    };
+    fs::write(
+        &other,
+        serde_json::to_string(&owner).expect("should serialize"),
+    )
+    .expect("should write claim");
+    assert!(!is_stale(&other));
+    age(&other);
+    assert!(is_stale(&other));
+
+    // the heartbeat only updates the claims of this instance
+    assert!(claim_chunk(temp.path(), "00001").expect("should claim"));
+    let own = chunk_claim_path(temp.path(), "00001");
+    age(&own);
+    assert!(is_stale(&own));
+    touch_own_claims(&claims).expect("should update claims");
+    assert!(!is_stale(&own));
+    assert!(is_stale(&other));
+}
+
+#[test]
+fn done_chunks_of_other_instances_are_merged() {
+    let done = done_json(&["00000", "00001"], false);
+    merge_done(&done, done_json(&["00001", "00002"], true));
+
+    let mut names: Vec<_> = done.done.iter().map(|entry| entry.key().clone()).collect();
+    names.sort();
+    assert_eq!(names, ["00000", "00001", "00002"]);
+    assert!(done.audio_done.load(atomic::Ordering::SeqCst));
+
+    merge_done(&done, done_json(&[], false));
+    assert!(done.audio_done.load(atomic::Ordering::SeqCst));
+}
+
+#[test]
+fn done_lock_is_released_when_dropped() {
+    let temp = tempfile::tempdir().expect("should create temp dir");
+    let lock = DoneLock::acquire(temp.path()).expect("should lock");
+    assert!(temp.path().join(DONE_LOCK).exists());
+    drop(lock);
+    assert!(!temp.path().join(DONE_LOCK).exists());
+    drop(DoneLock::acquire(temp.path()).expect("should lock again"));
+}
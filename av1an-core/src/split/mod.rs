@@ -4,14 +4,19 @@ mod tests;
 use std::{
     cmp::min,
     collections::BTreeMap,
-    path::Path,
+    ffi::OsString,
+    fs,
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     string::ToString,
 };
 
 use av_scenechange::ScenecutResult;
+use itertools::Itertools;
 
-use crate::scenes::Scene;
+use crate::{ffmpeg::source_args, into_vec, scenes::Scene};
 
 pub fn segment(
     input: impl AsRef<Path>,
@@ -25,8 +30,8 @@ pub fn segment(
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
-    cmd.args(["-hide_banner", "-y", "-i"]);
-    cmd.arg(input);
+    cmd.args(["-hide_banner", "-y"]);
+    cmd.args(source_args(input));
     cmd.args(["-map", "0:V:0", "-an", "-c", "copy", "-avoid_negative_ts", "1", "-vsync", "0"]);
 
     if segments.is_empty() {
@@ -47,6 +52,154 @@ pub fn segment(
     Ok(())
 }
 
+/// Losslessly copies the frames `start..end` of the first video stream of
+/// `input` to `output`, for chunks of passthrough zones.
+///
+/// `start` must be a keyframe and `end` either a keyframe or the end of the
+/// input, as the bitstream is copied as is. The output format is guessed
+/// from the extension of `output`.
+pub fn cut_passthrough(
+    input: &Path,
+    output: &Path,
+    start: usize,
+    end: usize,
+) -> anyhow::Result<()> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-y"]);
+    cmd.args(source_args(input));
+    cmd.args([
+        "-map",
+        "0:V:0",
+        "-an",
+        "-c",
+        "copy",
+        "-avoid_negative_ts",
+        "1",
+        "-fps_mode",
+        "passthrough",
+    ]);
+    cmd.args(["-frames:v", &end.to_string()]);
+
+    if start == 0 {
+        cmd.arg(output);
+        let out = cmd.output()?;
+        anyhow::ensure!(out.status.success(), "FFmpeg failed to cut: {out:#?}");
+        return Ok(());
+    }
+
+    // the segment muxer writes the frames before `start` to a separate file,
+    // which is discarded
+    let cut_dir = output.with_extension("cut");
+    fs::create_dir_all(&cut_dir)?;
+    let ext = output.extension().unwrap_or_default().to_string_lossy();
+    cmd.args(["-f", "segment", "-segment_frames", &start.to_string()]);
+    cmd.arg(cut_dir.join(format!("%d.{ext}")));
+    let out = cmd.output()?;
+    anyhow::ensure!(out.status.success(), "FFmpeg failed to cut: {out:#?}");
+
+    fs::rename(cut_dir.join(format!("1.{ext}")), output)?;
+    fs::remove_dir_all(&cut_dir)?;
+
+    Ok(())
+}
+
+/// Returns the requested split frames that do not land on a keyframe.
+///
+/// FFmpeg's segment muxer can only cut on keyframes, so any split returned
+/// here would be silently moved to the next keyframe by [`segment`].
+pub fn unaligned_splits(splits: &[usize], keyframes: &[usize]) -> Vec<usize> {
+    splits
+        .iter()
+        .copied()
+        .filter(|frame| keyframes.binary_search(frame).is_err())
+        .collect()
+}
+
+/// Returns the frames to segment the input on for the trim chunk method: the
+/// keyframe at or before the start of every range, so that each range can be
+/// decoded starting from the segment that contains its first frame.
+pub fn keyframe_splits(starts: impl IntoIterator<Item = usize>, keyframes: &[usize]) -> Vec<usize> {
+    let mut splits: Vec<usize> = starts
+        .into_iter()
+        .filter_map(|start| {
+            let index = keyframes.partition_point(|&kf| kf <= start);
+            index.checked_sub(1).map(|index| keyframes[index])
+        })
+        .filter(|&kf| kf > 0)
+        .collect();
+    splits.sort_unstable();
+    splits.dedup();
+    splits
+}
+
+/// Returns the indices of the segments produced by [`segment`] on `splits`
+/// that contain the frames `start..end`, and the first frame of the first of
+/// these segments.
+pub fn segment_span(splits: &[usize], start: usize, end: usize) -> (Range<usize>, usize) {
+    let first = splits.partition_point(|&split| split <= start);
+    let last = splits.partition_point(|&split| split < end);
+    let offset = first.checked_sub(1).map_or(0, |index| splits[index]);
+    (first..last + 1, offset)
+}
+
+/// Returns the FFmpeg input arguments and video filter that decode exactly
+/// the frames `start..end` from the segments produced by [`segment`] on
+/// `splits`.
+///
+/// Chunks that span several segments read them through the concat demuxer,
+/// from a list that is written next to the segments.
+pub fn trim_input(
+    files: &[PathBuf],
+    splits: &[usize],
+    index: usize,
+    start: usize,
+    end: usize,
+) -> anyhow::Result<(Vec<OsString>, String)> {
+    let (span, offset) = segment_span(splits, start, end);
+    let segments = files
+        .get(span)
+        .ok_or_else(|| anyhow::anyhow!("ffmpeg produced fewer segments than requested"))?;
+
+    let input_args = if let [file] = segments {
+        into_vec!["-i", file]
+    } else {
+        let list_path = segments[0].with_file_name(format!("trim_{index:05}.ffconcat"));
+        let list = iter::once("ffconcat version 1.0".to_string())
+            .chain(segments.iter().map(|file| {
+                let name = file.file_name().expect("segment should have a file name");
+                format!("file '{}'", name.to_string_lossy())
+            }))
+            .join("\n");
+        fs::write(&list_path, list)?;
+        into_vec!["-f", "concat", "-safe", "0", "-i", list_path]
+    };
+    let filter = format!(
+        "trim=start_frame={}:end_frame={},setpts=PTS-STARTPTS",
+        start - offset,
+        end - offset
+    );
+
+    Ok((input_args, filter))
+}
+
+/// Returns the length above which `scene` is split. Scenes of a zone use the
+/// zone's `--extra-split` instead of `split_size`, and a length of 0 disables
+/// extra splitting.
+pub(crate) fn scene_split_size(scene: &Scene, split_size: usize) -> usize {
+    scene
+        .zone_overrides
+        .as_ref()
+        .map_or(Some(split_size), |ovr| ovr.extra_splits_len)
+        .filter(|&len| len > 0)
+        .unwrap_or(usize::MAX)
+}
+
+/// Splits every scene that is longer than its split size (see
+/// [`scene_split_size`]) into scenes of roughly equal length.
+///
+/// The new scenes are only ever placed within an existing scene, so they never
+/// straddle a zone boundary, and inherit the zone overrides of the scene they
+/// were split from.
 pub fn extra_splits(
     scenes: &[Scene],
     split_size: usize,
@@ -75,10 +228,7 @@ fn simple_extra_splits(scenes: &[Scene], split_size: usize) -> Vec<Scene> {
 
     for scene in scenes {
         let distance = scene.end_frame - scene.start_frame;
-        let split_size = scene
-            .zone_overrides
-            .as_ref()
-            .map_or(split_size, |ovr| ovr.extra_splits_len.unwrap_or(usize::MAX));
+        let split_size = scene_split_size(scene, split_size);
         if distance > split_size {
             let additional_splits = (distance / split_size) + 1;
             for n in 1..additional_splits {
@@ -125,10 +275,7 @@ fn enhanced_extra_splits(
 
     for scene in scenes {
         let mut distance = scene.end_frame - scene.start_frame;
-        let split_size = scene
-            .zone_overrides
-            .as_ref()
-            .map_or(split_size, |ovr| ovr.extra_splits_len.unwrap_or(usize::MAX));
+        let split_size = scene_split_size(scene, split_size);
         while distance > split_size {
             let minimum_split_count = distance / split_size;
             let middle_point = distance / (minimum_split_count + 1);
@@ -157,6 +304,8 @@ fn enhanced_extra_splits(
                     })
                 })
                 .collect::<Vec<(usize, f64)>>();
+            // Scores may be missing for the frames of this scene, e.g. for zones
+            // that were not passed through scene detection
             let split_point = split_scores
                 .iter()
                 .max_by_key(|(_, score)| {
@@ -164,8 +313,7 @@ fn enhanced_extra_splits(
                     // It would be great if there was a `NonNaNF64` type that implemented `Ord`.
                     (*score * 10000.0).round() as u64
                 })
-                .expect("split scores is not empty")
-                .0;
+                .map_or(middle_point, |&(size, _)| size);
 
             let new_scene = Scene {
                 start_frame,
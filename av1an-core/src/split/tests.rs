@@ -120,6 +120,8 @@ fn extra_split_preserves_zone_overrides() {
                     chroma_noise:        false,
                     video_params:        into_vec!["--speed", "8"],
                     target_quality:      None,
+                    ffmpeg_filter:       None,
+                    passthrough:         false,
                 }),
             },
             Scene {
@@ -161,6 +163,8 @@ fn extra_split_preserves_zone_overrides() {
                     chroma_noise:        false,
                     video_params:        into_vec!["--speed", "3"],
                     target_quality:      None,
+                    ffmpeg_filter:       None,
+                    passthrough:         false,
                 }),
             },
             Scene {
@@ -202,3 +206,101 @@ fn extra_split_preserves_zone_overrides() {
         }
     }
 }
+
+#[test]
+fn unaligned_splits_all_on_keyframes() {
+    let keyframes = [0usize, 48, 96, 240, 300];
+    assert!(unaligned_splits(&[48, 240], &keyframes).is_empty());
+}
+
+#[test]
+fn unaligned_splits_off_keyframe_grid() {
+    let keyframes = [0usize, 48, 96, 240, 300];
+    assert_eq!(unaligned_splits(&[48, 100, 240, 299], &keyframes), vec![
+        100, 299
+    ]);
+}
+
+#[test]
+fn keyframe_splits_nearest_before() {
+    let keyframes = [0, 48, 96, 144, 192];
+    assert_eq!(keyframe_splits([0, 50, 96, 100, 150], &keyframes), vec![
+        48, 96, 144
+    ]);
+    assert!(keyframe_splits([0, 30], &keyframes).is_empty());
+}
+
+#[test]
+fn segment_span_ranges() {
+    let splits = [48, 96, 144];
+    assert_eq!(segment_span(&splits, 0, 30), (0..1, 0));
+    assert_eq!(segment_span(&splits, 50, 96), (1..2, 48));
+    assert_eq!(segment_span(&splits, 100, 150), (2..4, 96));
+    assert_eq!(segment_span(&[], 10, 20), (0..1, 0));
+}
+
+fn zone(start_frame: usize, end_frame: usize, extra_splits_len: Option<usize>) -> Scene {
+    Scene {
+        start_frame,
+        end_frame,
+        zone_overrides: Some(ZoneOptions {
+            encoder: Encoder::aom,
+            passes: 1,
+            extra_splits_len,
+            min_scene_len: 12,
+            photon_noise: None,
+            photon_noise_height: None,
+            photon_noise_width: None,
+            chroma_noise: false,
+            video_params: into_vec!["--cq-level=20"],
+            target_quality: None,
+            ffmpeg_filter: None,
+            passthrough: false,
+        }),
+    }
+}
+
+#[test]
+fn extra_split_zone_boundaries() {
+    let scenes = [
+        Scene {
+            start_frame:    0,
+            end_frame:      200,
+            zone_overrides: None,
+        },
+        // split by the zone's own length even though global splits are off
+        zone(200, 500, Some(100)),
+        // zone that disables extra splits
+        zone(500, 900, Some(0)),
+        // exactly the split size, so not split
+        zone(900, 1000, Some(100)),
+        zone(1000, 1001, Some(100)),
+    ];
+    let starts = |done: &[Scene]| done.iter().map(|scene| scene.start_frame).collect::<Vec<_>>();
+
+    let done = extra_splits(&scenes, 0, &BTreeMap::new());
+    assert_eq!(starts(&done), [0, 200, 275, 350, 425, 500, 900, 1000]);
+
+    // scores that do not cover the zone fall back to even splits
+    let scores = BTreeMap::from([(100, ScenecutResult {
+        inter_cost:             1.0,
+        imp_block_cost:         1.0,
+        threshold:              1.0,
+        backward_adjusted_cost: 1.0,
+        forward_adjusted_cost:  1.0,
+    })]);
+    let done = extra_splits(&scenes, 150, &scores);
+    assert_eq!(starts(&done), [0, 100, 200, 275, 350, 425, 500, 900, 1000]);
+
+    for scene in &done {
+        let boundary = scenes
+            .iter()
+            .find(|zone| (zone.start_frame..zone.end_frame).contains(&scene.start_frame))
+            .expect("scene should be within the input");
+        assert!(scene.end_frame <= boundary.end_frame);
+        assert_eq!(
+            scene.zone_overrides.as_ref().map(|ovr| ovr.extra_splits_len),
+            boundary.zone_overrides.as_ref().map(|ovr| ovr.extra_splits_len)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    ops::Range,
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use num_traits::cast::ToPrimitive;
+use tracing::{info, warn};
+
+use crate::{
+    encoder::Encoder,
+    ffmpeg::source_args,
+    into_vec,
+    scenes::{Scene, ZoneOptions},
+    settings::merge_params,
+    EncodeArgs,
+};
+
+/// Encoder parameters for runs of identical frames when no `--static-params`
+/// are given: the fastest presets that still leave the quality to the
+/// parameters of the rest of the encode
+fn default_static_params(encoder: Encoder) -> Vec<String> {
+    match encoder {
+        Encoder::aom => into_vec!["--cpu-used=8"],
+        Encoder::rav1e => into_vec!["--speed", "10"],
+        Encoder::vpx => into_vec!["--cpu-used=5"],
+        Encoder::svt_av1 => into_vec!["--preset", "12"],
+        Encoder::x264 | Encoder::x265 => into_vec!["--preset", "veryfast"],
+        Encoder::av1_nvenc | Encoder::hevc_nvenc => into_vec!["-preset", "p1"],
+    }
+}
+
+/// Parses the frame ranges of the freezes the `freezedetect` filter printed.
+/// A freeze without an end lasts until the end of the video.
+fn parse_freezedetect(stderr: &str, frame_rate: f64, frames: usize) -> Vec<Range<usize>> {
+    let to_frame = |seconds: &str| {
+        seconds
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|seconds| (seconds * frame_rate).round().to_usize())
+            .map(|frame| frame.min(frames))
+    };
+
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for line in stderr.lines() {
+        if let Some((_, time)) = line.split_once("lavfi.freezedetect.freeze_start:") {
+            start = to_frame(time);
+        } else if let Some((_, time)) = line.split_once("lavfi.freezedetect.freeze_end:")
+            && let Some((start, end)) = start.take().zip(to_frame(time))
+            && start < end
+        {
+            ranges.push(start..end);
+        }
+    }
+    if let Some(start) = start
+        && start < frames
+    {
+        ranges.push(start..frames);
+    }
+    ranges
+}
+
+/// Decodes the whole input and returns the frame ranges of the runs of at
+/// least [`EncodeArgs::static_scenes`] identical frames
+pub(crate) fn detect_static_ranges(args: &EncodeArgs) -> anyhow::Result<Vec<Range<usize>>> {
+    let Some(min_frames) = args.static_scenes else {
+        return Ok(Vec::new());
+    };
+    if !args.input.is_video() {
+        warn!("--static-scenes has no effect with VapourSynth script input");
+        return Ok(Vec::new());
+    }
+
+    info!("Detecting runs of identical frames");
+    let clip_info = args.input.clip_info()?;
+    let frame_rate = clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats"])
+        .args(source_args(args.input.as_video_path()))
+        .args(["-map", "0:v:0", "-vf"])
+        .arg(format!(
+            "freezedetect=n=-60dB:d={:.6}",
+            min_frames as f64 / frame_rate
+        ))
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run ffmpeg to detect runs of identical frames")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!(
+            "Failed to detect runs of identical frames: {}",
+            stderr.trim()
+        );
+    }
+
+    let ranges = parse_freezedetect(&stderr, frame_rate, clip_info.num_frames);
+    info!(
+        "found {} run(s) of identical frames, {} frames in total",
+        ranges.len(),
+        ranges.iter().map(ExactSizeIterator::len).sum::<usize>()
+    );
+    Ok(ranges)
+}
+
+/// Moves the start and end of `range` onto a scene cut less than
+/// `min_scene_len` frames away, so that no short scene is left next to it
+fn snap_to_cuts(range: &Range<usize>, scenes: &[Scene], min_scene_len: usize) -> Range<usize> {
+    let snap = |frame: usize| {
+        scenes
+            .iter()
+            .map(|scene| scene.start_frame)
+            .chain(scenes.last().map(|scene| scene.end_frame))
+            .filter(|cut| cut.abs_diff(frame) < min_scene_len)
+            .min_by_key(|cut| cut.abs_diff(frame))
+            .unwrap_or(frame)
+    };
+    snap(range.start)..snap(range.end)
+}
+
+/// Splits the scenes at the start and end of every run of identical frames,
+/// and encodes the scenes within the runs with [`EncodeArgs::static_params`].
+/// Scenes of zones keep the parameters of their zone.
+pub(crate) fn apply_static_ranges(
+    args: &EncodeArgs,
+    mut scenes: Vec<Scene>,
+    ranges: &[Range<usize>],
+) -> Vec<Scene> {
+    let params = if args.static_params.is_empty() {
+        default_static_params(args.encoder)
+    } else {
+        args.static_params.clone()
+    };
+
+    for range in ranges {
+        let range = snap_to_cuts(range, &scenes, args.min_scene_len);
+        if range.is_empty() {
+            continue;
+        }
+        for cut in [range.start, range.end] {
+            if let Some(pos) =
+                scenes.iter().position(|scene| scene.start_frame < cut && cut < scene.end_frame)
+            {
+                let mut new = scenes[pos].clone();
+                scenes[pos].end_frame = cut;
+                new.start_frame = cut;
+                scenes.insert(pos + 1, new);
+            }
+        }
+        for scene in scenes.iter_mut().filter(|scene| {
+            scene.zone_overrides.is_none()
+                && range.start <= scene.start_frame
+                && scene.end_frame <= range.end
+        }) {
+            let mut overrides = ZoneOptions::from_args(args);
+            overrides.video_params = merge_params(overrides.video_params, &params);
+            scene.zone_overrides = Some(overrides);
+        }
+    }
+    scenes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_freezes() {
+        let stderr = "[freezedetect @ 0x55d1] lavfi.freezedetect.freeze_start: 2\n[freezedetect @ \
+                      0x55d1] lavfi.freezedetect.freeze_duration: 3.5\n[freezedetect @ 0x55d1] \
+                      lavfi.freezedetect.freeze_end: 5.5\nframe=  300 fps=0.0 q=-0.0 Lsize=N/A \
+                      time=00:00:12.00\n[freezedetect @ 0x55d1] lavfi.freezedetect.freeze_start: \
+                      10\n";
+        assert_eq!(parse_freezedetect(stderr, 24.0, 300), vec![
+            48..132,
+            240..300
+        ]);
+        assert!(parse_freezedetect("", 24.0, 300).is_empty());
+    }
+
+    #[test]
+    fn runs_snap_to_nearby_cuts() {
+        let scenes = [0..100, 100..300]
+            .into_iter()
+            .map(|range| Scene {
+                start_frame:    range.start,
+                end_frame:      range.end,
+                zone_overrides: None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(snap_to_cuts(&(110..200), &scenes, 24), 100..200);
+        assert_eq!(snap_to_cuts(&(150..290), &scenes, 24), 150..300);
+        assert_eq!(snap_to_cuts(&(150..250), &scenes, 24), 150..250);
+    }
+}
@@ -2,16 +2,18 @@ use std::{
     borrow::Cow,
     cmp::{self, Ordering},
     collections::HashSet,
-    io::Read,
+    fs,
+    io::{self, Read},
     path::{Path, PathBuf},
     process::{Child, Stdio},
     str::FromStr,
+    sync::Mutex,
     thread::{self, available_parallelism},
 };
 
 use anyhow::{anyhow, bail};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 use crate::{
     broker::EncoderCrash,
@@ -28,12 +30,17 @@ use crate::{
     },
     metrics::{
         butteraugli::ButteraugliSubMetric,
+        custom::run_custom_metric,
+        reference::ReferencePipe,
+        ssimulacra2,
         statistics::MetricStatistics,
-        vmaf::{get_vmaf_model_version, read_vmaf_file, run_vmaf, run_vmaf_weighted},
+        vmaf::{get_vmaf_model_version, read_vmaf_file, run_vmaf, run_vmaf_weighted, VmafTonemap},
         xpsnr::{read_xpsnr_file, run_xpsnr, XPSNRSubMetric},
     },
     progress_bar::update_mp_msg,
+    scratch_dir,
     vapoursynth::{measure_butteraugli, measure_ssimulacra2, measure_xpsnr, VapoursynthPlugins},
+    ChunkScore,
     Encoder,
     ProbingStatistic,
     ProbingStatisticName,
@@ -41,6 +48,43 @@ use crate::{
     VmafFeature,
 };
 
+/// Differences between the scores of probes encoded and scored without
+/// `--probe-res` and at the probe resolution, learned from the first chunks
+/// of an encode with `--probe-res-correction`. Each encode learns its own.
+#[derive(Debug, Default)]
+pub struct ProbeResCorrection {
+    samples: Vec<f64>,
+    /// Number of chunks currently probed at full resolution
+    pending: usize,
+}
+
+impl ProbeResCorrection {
+    /// The offset to add to a score measured at the probe resolution to
+    /// estimate the score at full resolution
+    fn offset(&self) -> f64 {
+        if self.samples.is_empty() {
+            0.0
+        } else {
+            self.samples.iter().sum::<f64>() / self.samples.len() as f64
+        }
+    }
+
+    /// Returns whether another chunk should be probed at full resolution to
+    /// learn the offset from, reserving it if so
+    fn reserve(&mut self, chunks: usize) -> bool {
+        let reserve = self.samples.len() + self.pending < chunks;
+        if reserve {
+            self.pending += 1;
+        }
+        reserve
+    }
+
+    fn record(&mut self, sample: Option<f64>) {
+        self.pending -= 1;
+        self.samples.extend(sample);
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InterpolationMethod {
     Linear,
@@ -74,9 +118,15 @@ impl FromStr for InterpolationMethod {
 pub struct TargetQuality {
     pub vmaf_res:              String,
     pub probe_res:             Option<(u32, u32)>,
+    #[serde(default)]
+    pub probe_res_correction:  usize,
     pub vmaf_scaler:           String,
     pub vmaf_filter:           Option<String>,
+    pub vmaf_tonemap:          Option<VmafTonemap>,
     pub vmaf_threads:          usize,
+    /// Compute VMAF on the GPU with libvmaf_cuda
+    #[serde(default)]
+    pub vmaf_cuda:             bool,
     pub model:                 Option<PathBuf>,
     pub probing_rate:          usize,
     pub probes:                u32,
@@ -94,6 +144,9 @@ pub struct TargetQuality {
     pub vspipe_args:           Vec<String>,
     pub probing_vmaf_features: Vec<VmafFeature>,
     pub probing_statistic:     ProbingStatistic,
+    /// Keep the probes in `temp/probes`, with a manifest of their scores
+    #[serde(default)]
+    pub keep_probes:           bool,
 }
 
 impl TargetQuality {
@@ -102,11 +155,14 @@ impl TargetQuality {
         Self {
             vmaf_res: "1920x1080".to_string(),
             probe_res: Some((1920, 1080)),
+            probe_res_correction: 0,
             vmaf_scaler: "bicubic".to_string(),
             vmaf_filter: None,
+            vmaf_tonemap: None,
             vmaf_threads: available_parallelism()
                 .expect("Unrecoverable: Failed to get thread count")
                 .get(),
+            vmaf_cuda: false,
             model: None,
             probing_rate: 1,
             probes: 4,
@@ -127,6 +183,7 @@ impl TargetQuality {
                 name:  ProbingStatisticName::Automatic,
                 value: None,
             },
+            keep_probes: false,
         }
     }
 
@@ -136,11 +193,20 @@ impl TargetQuality {
         chunk: &Chunk,
         worker_id: Option<usize>,
         plugins: Option<VapoursynthPlugins>,
+        probe_res_correction: &Mutex<ProbeResCorrection>,
     ) -> anyhow::Result<f32> {
         anyhow::ensure!(self.target.is_some(), "Target must be some");
         let target = self.target.expect("target is some");
+        let scratch = scratch_dir(&chunk.temp, worker_id);
         // History of probe results as quantizer-score pairs
         let mut quantizer_score_history: Vec<(f32, f64)> = vec![];
+        // Probes copied to the probes directory, by their quantizer
+        let mut kept_probes: Vec<(f32, String)> = vec![];
+        let correction = if self.probe_res.is_some() {
+            probe_res_correction.lock().expect("mutex should acquire lock").offset()
+        } else {
+            0.0
+        };
 
         let update_progress_bar = |next_quantizer: f32| {
             if let Some(worker_id) = worker_id {
@@ -174,12 +240,11 @@ impl TargetQuality {
                 upper_quantizer_limit,
                 &quantizer_score_history,
                 // Invert for butteraugli
-                match self.metric {
-                    TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
-                        let (min, max) = target;
-                        (-max, -min)
-                    },
-                    _ => target,
+                if self.metric.lower_is_better() {
+                    let (min, max) = target;
+                    (-max, -min)
+                } else {
+                    target
                 },
                 self.interp_method,
                 step,
@@ -197,18 +262,27 @@ impl TargetQuality {
             update_progress_bar(next_quantizer);
 
             let score = {
-                let value = self.probe(chunk, next_quantizer, plugins)?;
+                let value = self.probe(chunk, &scratch, next_quantizer, plugins, self.probe_res)?
+                    + correction;
+                if self.keep_probes {
+                    kept_probes.extend(
+                        self.keep_probe(chunk, &scratch, next_quantizer)
+                            .map(|file| (next_quantizer, file)),
+                    );
+                }
 
                 // Butteraugli is an inverse metric, invert score for comparisons
-                match self.metric {
-                    TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -value,
-                    _ => value,
+                if self.metric.lower_is_better() {
+                    -value
+                } else {
+                    value
                 }
             };
             let score_within_range = within_range(
-                match self.metric {
-                    TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score,
-                    _ => score,
+                if self.metric.lower_is_better() {
+                    -score
+                } else {
+                    score
                 },
                 target,
             );
@@ -224,9 +298,10 @@ impl TargetQuality {
                 break;
             }
 
-            let target_range = match self.metric {
-                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => (-target.1, -target.0),
-                _ => target,
+            let target_range = if self.metric.lower_is_better() {
+                (-target.1, -target.0)
+            } else {
+                target
             };
 
             if score > target_range.1 {
@@ -251,9 +326,10 @@ impl TargetQuality {
             .iter()
             .filter(|(_, score)| {
                 within_range(
-                    match self.metric {
-                        TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score,
-                        _ => *score,
+                    if self.metric.lower_is_better() {
+                        -score
+                    } else {
+                        *score
                     },
                     target,
                 )
@@ -265,13 +341,10 @@ impl TargetQuality {
                 quantizer_score_history
                     .iter()
                     .min_by(|(_, score1), (_, score2)| {
-                        let score_1 = match self.metric {
-                            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score1,
-                            _ => *score1,
-                        };
-                        let score_2 = match self.metric {
-                            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score2,
-                            _ => *score2,
+                        let (score_1, score_2) = if self.metric.lower_is_better() {
+                            (-score1, -score2)
+                        } else {
+                            (*score1, *score2)
                         };
                         let difference1 = (score_1 - target_midpoint).abs();
                         let difference2 = (score_2 - target_midpoint).abs();
@@ -290,29 +363,189 @@ impl TargetQuality {
             &chunk.name(),
             final_quantizer_score.0,
             // Inverse reverse metrics
-            match self.metric {
-                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
-                    -final_quantizer_score.1
-                },
-                _ => final_quantizer_score.1,
+            if self.metric.lower_is_better() {
+                -final_quantizer_score.1
+            } else {
+                final_quantizer_score.1
             },
             skip_reason,
         );
 
+        if !kept_probes.is_empty() {
+            let probes = kept_probes
+                .into_iter()
+                .filter_map(|(quantizer, file)| {
+                    let score =
+                        quantizer_score_history.iter().find(|(probed, _)| *probed == quantizer)?.1;
+                    Some(KeptProbe {
+                        chunk: chunk.name(),
+                        quantizer,
+                        score: if self.metric.lower_is_better() {
+                            -score
+                        } else {
+                            score
+                        },
+                        file,
+                        chosen: quantizer == final_quantizer_score.0,
+                    })
+                })
+                .collect();
+            if let Err(e) = record_kept_probes(&Path::new(&self.temp).join("probes"), probes) {
+                warn!(
+                    "chunk {}: failed to update the probe manifest: {e}",
+                    chunk.name()
+                );
+            }
+        }
+
+        if self.probe_res.is_some()
+            && probe_res_correction
+                .lock()
+                .expect("mutex should acquire lock")
+                .reserve(self.probe_res_correction)
+        {
+            let probe_res_score = if self.metric.lower_is_better() {
+                -final_quantizer_score.1
+            } else {
+                final_quantizer_score.1
+            } - correction;
+            // Probe the chosen quantizer again as if `--probe-res` was not set
+            let sample = match self.probe(chunk, &scratch, final_quantizer_score.0, plugins, None) {
+                Ok(full_res_score) => Some(full_res_score - probe_res_score),
+                Err(e) => {
+                    warn!(
+                        "chunk {}: failed to probe at full resolution for the probe resolution \
+                         correction: {e}",
+                        chunk.name()
+                    );
+                    None
+                },
+            };
+
+            let mut learned = probe_res_correction.lock().expect("mutex should acquire lock");
+            learned.record(sample);
+            debug!(
+                "chunk {}: probe resolution correction is now {:.4} ({} chunks)",
+                chunk.name(),
+                learned.offset(),
+                learned.samples.len()
+            );
+        }
+
         Ok(final_quantizer_score.0)
     }
 
     fn probe(
         &self,
         chunk: &Chunk,
+        scratch: &Path,
         quantizer: f32,
         plugins: Option<VapoursynthPlugins>,
+        probe_res: Option<(u32, u32)>,
+    ) -> anyhow::Result<f64> {
+        let probe_name = self.encode_probe(chunk, scratch, quantizer, probe_res)?;
+        self.score(
+            chunk,
+            None,
+            scratch,
+            &probe_name,
+            quantizer,
+            plugins,
+            probe_res,
+        )
+    }
+
+    /// Path of the probe of `chunk` at quantizer `q` in `scratch`
+    fn probe_path(&self, chunk: &Chunk, scratch: &Path, q: f32) -> PathBuf {
+        let q_str = crate::encoder::format_q(q);
+        scratch.join(format!(
+            "v_{index:05}_{q_str}.{extension}",
+            index = chunk.index,
+            extension = self.encoder.output_extension()
+        ))
+    }
+
+    /// Copies the probe of `chunk` at `quantizer` to `temp/probes`, returning
+    /// its file name there, or `None` if it could not be copied
+    fn keep_probe(&self, chunk: &Chunk, scratch: &Path, quantizer: f32) -> Option<String> {
+        let probe = self.probe_path(chunk, scratch, quantizer);
+        let file = probe.file_name()?.to_string_lossy().into_owned();
+        let dir = Path::new(&self.temp).join("probes");
+        if let Err(e) = fs::create_dir_all(&dir).and_then(|()| fs::copy(&probe, dir.join(&file))) {
+            warn!(
+                "chunk {}: failed to keep the probe {file}: {e}",
+                chunk.name()
+            );
+            return None;
+        }
+        Some(file)
+    }
+
+    /// Scores the finished encode of `chunk` with the metric of Target
+    /// Quality, comparing every frame against the source, or against
+    /// `reference` if the source was filtered for the encoder. Unlike the
+    /// scores of Target Quality, lower Butteraugli scores are better.
+    pub(crate) fn score_chunk(
+        &self,
+        chunk: &Chunk,
+        reference: Option<&ReferencePipe>,
+        worker_id: Option<usize>,
+        plugins: Option<VapoursynthPlugins>,
+    ) -> anyhow::Result<ChunkScore> {
+        let full = Self {
+            probing_rate: 1,
+            ..self.clone()
+        };
+        let quantizer = chunk
+            .tq_cq
+            .or_else(|| chunk.encoder.get_q(&chunk.video_params))
+            .unwrap_or_default();
+        let score = full.score(
+            chunk,
+            reference,
+            &scratch_dir(&chunk.temp, worker_id),
+            Path::new(&chunk.output()),
+            quantizer,
+            plugins,
+            None,
+        )?;
+        Ok(ChunkScore {
+            score,
+            quantizer,
+        })
+    }
+
+    /// Scores `probe_name`, an encode of `chunk` at `quantizer`, against
+    /// `reference` or else the unfiltered source, keeping the metric logs in
+    /// `scratch`
+    #[expect(clippy::too_many_arguments)]
+    fn score(
+        &self,
+        chunk: &Chunk,
+        reference: Option<&ReferencePipe>,
+        scratch: &Path,
+        probe_name: &Path,
+        quantizer: f32,
+        plugins: Option<VapoursynthPlugins>,
+        probe_res: Option<(u32, u32)>,
     ) -> anyhow::Result<f64> {
-        let probe_name = self.encode_probe(chunk, quantizer)?;
         let reference_pipe_cmd =
             chunk.proxy_cmd.as_ref().map_or(chunk.source_cmd.as_slice(), |proxy_cmd| {
                 proxy_cmd.as_slice()
             });
+        let unfiltered = ReferencePipe::new(reference_pipe_cmd, self.vspipe_args.clone());
+        let reference = reference.unwrap_or(&unfiltered);
+        // the VapourSynth metrics read the script of the chunk themselves
+        let ensure_unfiltered = || {
+            if reference.is_filtered() {
+                bail!(
+                    "{} cannot compare against the source as it was filtered for the encoder, use \
+                     VMAF or XPSNR to score chunks encoded with --ffmpeg, --deband or --burn-subs",
+                    self.metric
+                );
+            }
+            Ok(())
+        };
 
         let aggregate_frame_scores = |scores: Vec<f64>| -> anyhow::Result<f64> {
             let mut statistics = MetricStatistics::new(scores);
@@ -396,10 +629,10 @@ impl TargetQuality {
 
                 let vmaf_scores = if use_weighted {
                     run_vmaf_weighted(
-                        &probe_name,
-                        reference_pipe_cmd,
-                        self.vspipe_args.clone(),
+                        probe_name,
+                        reference,
                         model,
+                        self.vmaf_tonemap,
                         self.vmaf_threads,
                         chunk.frame_rate,
                         disable_motion,
@@ -415,24 +648,23 @@ impl TargetQuality {
                         })
                     })?
                 } else {
-                    let fl_path = std::path::Path::new(&chunk.temp)
-                        .join("split")
-                        .join(format!("{index}.json", index = chunk.index));
+                    let fl_path = scratch.join(format!("{index}.json", index = chunk.index));
 
                     run_vmaf(
-                        &probe_name,
-                        reference_pipe_cmd,
-                        self.vspipe_args.clone(),
+                        probe_name,
+                        reference,
                         &fl_path,
                         model,
-                        &self.probe_res.map_or_else(
+                        &probe_res.map_or_else(
                             || self.vmaf_res.clone(),
                             |(width, height)| format!("{width}x{height}"),
                         ),
                         &self.vmaf_scaler,
                         self.probing_rate,
                         self.vmaf_filter.as_deref(),
+                        self.vmaf_tonemap,
                         self.vmaf_threads,
+                        self.vmaf_cuda,
                         chunk.frame_rate,
                         disable_motion,
                         &self.probing_vmaf_features,
@@ -444,22 +676,34 @@ impl TargetQuality {
                 aggregate_frame_scores(vmaf_scores)
             },
             TargetMetric::SSIMULACRA2 => {
-                let scores = if let Some(plugins) = plugins {
+                let source = chunk.proxy.as_ref().unwrap_or(&chunk.input);
+                let scores = if let Some(plugins) = ssimulacra2::usable_plugins(source, plugins) {
+                    ensure_unfiltered()?;
                     measure_ssimulacra2(
-                        chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                        &probe_name,
+                        source,
+                        probe_name,
                         (chunk.start_frame as u32, chunk.end_frame as u32),
-                        self.probe_res,
+                        probe_res,
                         self.probing_rate,
                         plugins,
                     )?
                 } else {
-                    bail!("SSIMULACRA2 requires Vapoursynth to be installed");
+                    ssimulacra2::measure_native(
+                        probe_name,
+                        reference,
+                        &probe_res.map_or_else(
+                            || self.vmaf_res.clone(),
+                            |(width, height)| format!("{width}x{height}"),
+                        ),
+                        &self.vmaf_scaler,
+                        self.probing_rate,
+                    )?
                 };
 
                 aggregate_frame_scores(scores)
             },
             TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
+                ensure_unfiltered()?;
                 let scores = if let Some(plugins) = plugins {
                     measure_butteraugli(
                         match self.metric {
@@ -468,9 +712,9 @@ impl TargetQuality {
                             _ => unreachable!(),
                         },
                         chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                        &probe_name,
+                        probe_name,
                         (chunk.start_frame as u32, chunk.end_frame as u32),
-                        self.probe_res,
+                        probe_res,
                         self.probing_rate,
                         plugins,
                     )?
@@ -487,13 +731,14 @@ impl TargetQuality {
                     XPSNRSubMetric::Weighted
                 };
                 if self.probing_rate > 1 {
+                    ensure_unfiltered()?;
                     let scores = if let Some(plugins) = plugins {
                         measure_xpsnr(
                             submetric,
                             chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                            &probe_name,
+                            probe_name,
                             (chunk.start_frame as u32, chunk.end_frame as u32),
-                            self.probe_res,
+                            probe_res,
                             self.probing_rate,
                             plugins,
                         )?
@@ -503,20 +748,19 @@ impl TargetQuality {
 
                     aggregate_frame_scores(scores)
                 } else {
-                    let fl_path =
-                        Path::new(&chunk.temp).join("split").join(format!("{}.json", chunk.index));
+                    let fl_path = scratch.join(format!("{}.json", chunk.index));
 
                     run_xpsnr(
-                        &probe_name,
-                        reference_pipe_cmd,
-                        self.vspipe_args.clone(),
+                        probe_name,
+                        reference,
                         &fl_path,
-                        &self.probe_res.map_or_else(
+                        &probe_res.map_or_else(
                             || self.vmaf_res.clone(),
                             |(width, height)| format!("{width}x{height}"),
                         ),
                         &self.vmaf_scaler,
                         self.probing_rate,
+                        self.vmaf_filter.as_deref(),
                         chunk.frame_rate,
                     )?;
 
@@ -528,10 +772,31 @@ impl TargetQuality {
                     }
                 }
             },
+            TargetMetric::Custom(metric) => {
+                let scores = run_custom_metric(
+                    metric,
+                    probe_name,
+                    reference,
+                    &probe_res.map_or_else(
+                        || self.vmaf_res.clone(),
+                        |(width, height)| format!("{width}x{height}"),
+                    ),
+                    &self.vmaf_scaler,
+                    self.probing_rate,
+                )?;
+
+                aggregate_frame_scores(scores)
+            },
         }
     }
 
-    fn encode_probe(&self, chunk: &Chunk, q: f32) -> Result<PathBuf, Box<EncoderCrash>> {
+    fn encode_probe(
+        &self,
+        chunk: &Chunk,
+        scratch: &Path,
+        q: f32,
+        probe_res: Option<(u32, u32)>,
+    ) -> Result<PathBuf, Box<EncoderCrash>> {
         let vmaf_threads = if self.vmaf_threads == 0 {
             vmaf_auto_threads(self.workers)
         } else {
@@ -539,11 +804,13 @@ impl TargetQuality {
         };
 
         let cmd = self.encoder.probe_cmd(
-            self.temp.clone(),
+            scratch,
             chunk.index,
             q,
             self.pix_format,
             self.probing_rate,
+            probe_res,
+            &self.vmaf_scaler,
             vmaf_threads,
             self.video_params.clone(),
         );
@@ -555,6 +822,7 @@ impl TargetQuality {
             let mut source = if let [pipe_cmd, args @ ..] = &*source_cmd {
                 std::process::Command::new(pipe_cmd)
                     .args(args)
+                    .envs(chunk.gpu_env())
                     .stderr(std::process::Stdio::piped())
                     .stdout(std::process::Stdio::piped())
                     .spawn()
@@ -674,16 +942,7 @@ impl TargetQuality {
             Ok(())
         })?;
 
-        let extension = match self.encoder {
-            crate::encoder::Encoder::x264 => "264",
-            crate::encoder::Encoder::x265 => "hevc",
-            _ => "ivf",
-        };
-
-        let q_str = crate::encoder::format_q(q);
-        let probe_name = format!("v_{index:05}_{q_str}.{extension}", index = chunk.index);
-
-        Ok(std::path::Path::new(&chunk.temp).join("split").join(&probe_name))
+        Ok(self.probe_path(chunk, scratch, q))
     }
 
     #[inline]
@@ -1041,10 +1300,7 @@ pub fn log_probes(
     sorted_quantizer_scores
         .sort_by(|(q1, _), (q2, _)| q1.partial_cmp(q2).unwrap_or(std::cmp::Ordering::Equal));
     // Butteraugli is an inverse metric and needs to be inverted back before display
-    if matches!(
-        metric,
-        TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3
-    ) {
+    if metric.lower_is_better() {
         sorted_quantizer_scores = sorted_quantizer_scores
             .iter()
             .map(|(quantizer, score)| (*quantizer, -score))
@@ -1081,6 +1337,40 @@ pub fn log_probes(
     );
 }
 
+/// A probe kept in `temp/probes` with `--keep-probes`, as listed in its
+/// `manifest.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeptProbe {
+    pub chunk:     String,
+    pub quantizer: f32,
+    /// Score of the probe, corrected for `--probe-res-correction` if set
+    pub score:     f64,
+    /// Name of the probe in `temp/probes`
+    pub file:      String,
+    /// Whether the quantizer of the probe was chosen for the chunk
+    pub chosen:    bool,
+}
+
+/// Serializes the updates of the probe manifest of the workers
+static PROBE_MANIFEST: Mutex<()> = Mutex::new(());
+
+/// Adds `probes`, those of a single chunk, to `manifest.json` in `dir`,
+/// replacing the probes of that chunk from before a resume
+fn record_kept_probes(dir: &Path, probes: Vec<KeptProbe>) -> anyhow::Result<()> {
+    let _lock = PROBE_MANIFEST.lock().expect("mutex should acquire lock");
+    let path = dir.join("manifest.json");
+    let mut manifest: Vec<KeptProbe> = match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    manifest.retain(|kept| probes.iter().all(|probe| probe.chunk != kept.chunk));
+    manifest.extend(probes);
+    manifest.sort_by(|a, b| a.chunk.cmp(&b.chunk).then(a.quantizer.total_cmp(&b.quantizer)));
+    fs::write(path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1172,4 +1462,55 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn probe_res_correction_learns_offset() {
+        let mut correction = ProbeResCorrection::default();
+        assert_eq!(correction.offset(), 0.0);
+
+        // only as many chunks as requested are probed at full resolution,
+        // including those still being probed
+        assert!(correction.reserve(2));
+        assert!(correction.reserve(2));
+        assert!(!correction.reserve(2));
+
+        correction.record(Some(1.5));
+        // a failed full resolution probe frees its reservation
+        correction.record(None);
+        assert!(correction.reserve(2));
+        correction.record(Some(2.5));
+        assert!(!correction.reserve(2));
+        assert_eq!(correction.offset(), 2.0);
+    }
+
+    #[test]
+    fn kept_probes_replace_those_of_the_chunk() {
+        let dir = tempfile::tempdir().expect("should create a temporary directory");
+        let probe = |chunk: &str, quantizer: f32, chosen| KeptProbe {
+            chunk: chunk.to_string(),
+            quantizer,
+            score: 90.0,
+            file: format!("v_{chunk}_{quantizer}.ivf"),
+            chosen,
+        };
+
+        record_kept_probes(dir.path(), vec![probe("00001", 30.0, true)]).expect("should record");
+        record_kept_probes(dir.path(), vec![
+            probe("00000", 35.0, false),
+            probe("00000", 25.0, true),
+        ])
+        .expect("should record");
+        // a resumed chunk is probed again
+        record_kept_probes(dir.path(), vec![probe("00001", 28.0, true)]).expect("should record");
+
+        let manifest: Vec<KeptProbe> = serde_json::from_str(
+            &fs::read_to_string(dir.path().join("manifest.json")).expect("should read"),
+        )
+        .expect("should parse");
+        assert_eq!(manifest, [
+            probe("00000", 25.0, true),
+            probe("00000", 35.0, false),
+            probe("00001", 28.0, true),
+        ]);
+    }
 }
@@ -0,0 +1,284 @@
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use itertools::Itertools;
+use strum::{EnumString, IntoStaticStr};
+use sysinfo::Disks;
+use tracing::{info, warn};
+
+/// What to do when the temporary directory is read-only, nearly full or on a
+/// network file system
+#[derive(EnumString, IntoStaticStr, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TempRelocation {
+    /// Move the temporary directory to local storage, and copy it back once
+    /// the encode finished if it is kept
+    #[strum(serialize = "auto")]
+    Auto,
+    /// Keep the temporary directory where it is
+    #[strum(serialize = "never")]
+    Never,
+}
+
+impl Display for TempRelocation {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(<&'static str>::from(self))
+    }
+}
+
+/// File systems whose every file operation is a round trip over the network
+const NETWORK_FILE_SYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb",
+    "smb2",
+    "smb3",
+    "smbfs",
+    "afpfs",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+];
+
+/// Average time of creating and removing a file above which the temporary
+/// directory counts as slow, as Av1an creates and removes files for every
+/// chunk
+const SLOW_FILE_OPERATION: Duration = Duration::from_millis(20);
+
+/// Number of files created and removed to measure [`SLOW_FILE_OPERATION`]
+const LATENCY_SAMPLES: u32 = 8;
+
+/// Why a temporary directory is unsuited for encoding
+#[derive(Debug, PartialEq, Eq)]
+enum TempProblem {
+    ReadOnly(String),
+    LowSpace { available: u64, needed: u64 },
+    Network(String),
+    Slow(Duration),
+}
+
+impl TempProblem {
+    /// Returns whether encoding cannot start with this problem, rather than
+    /// only being slow or possibly running out of space later
+    const fn is_fatal(&self) -> bool {
+        matches!(self, Self::ReadOnly(_))
+    }
+}
+
+impl Display for TempProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadOnly(error) => write!(f, "it is not writable ({error})"),
+            Self::LowSpace {
+                available,
+                needed,
+            } => write!(
+                f,
+                "only {} MB are free, but the encode needs about {} MB",
+                available / 1_000_000,
+                needed / 1_000_000
+            ),
+            Self::Network(file_system) => write!(f, "it is on a {file_system} network share"),
+            Self::Slow(latency) => {
+                write!(f, "creating a file in it takes {} ms", latency.as_millis())
+            },
+        }
+    }
+}
+
+/// The disk `path` is on, which is the one with the longest mount point that
+/// contains it
+fn disk_of<'a>(disks: &'a Disks, path: &Path) -> Option<&'a sysinfo::Disk> {
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+}
+
+/// Returns whether `path` is a Windows UNC path, e.g. `\\server\share`
+fn is_unc_path(path: &Path) -> bool {
+    let path = path.as_os_str().to_string_lossy();
+    path.starts_with(r"\\") && !path.starts_with(r"\\?\")
+}
+
+/// Creates and removes a few files in `temp`, and returns the average time
+/// this took
+fn measure_latency(temp: &Path) -> io::Result<Duration> {
+    let start = Instant::now();
+    for i in 0..LATENCY_SAMPLES {
+        let file = temp.join(format!(".av1an-latency-{i}"));
+        fs::write(&file, b"av1an")?;
+        fs::remove_file(&file)?;
+    }
+    Ok(start.elapsed() / LATENCY_SAMPLES)
+}
+
+/// Checks that `temp` is writable, has `needed` bytes free and is not on a
+/// network file system. The directory is created if it does not exist yet.
+fn check_temp_dir(temp: &Path, needed: u64) -> Vec<TempProblem> {
+    if let Err(e) = fs::create_dir_all(temp) {
+        return vec![TempProblem::ReadOnly(e.to_string())];
+    }
+    let latency = match measure_latency(temp) {
+        Ok(latency) => latency,
+        Err(e) => return vec![TempProblem::ReadOnly(e.to_string())],
+    };
+
+    let mut problems = Vec::new();
+    let path = dunce::canonicalize(temp).unwrap_or_else(|_| temp.to_path_buf());
+    let disks = Disks::new_with_refreshed_list();
+    if let Some(disk) = disk_of(&disks, &path) {
+        if disk.available_space() < needed {
+            problems.push(TempProblem::LowSpace {
+                available: disk.available_space(),
+                needed,
+            });
+        }
+        let file_system = disk.file_system().to_string_lossy().to_ascii_lowercase();
+        if NETWORK_FILE_SYSTEMS.contains(&file_system.as_str()) {
+            problems.push(TempProblem::Network(file_system));
+        }
+    } else if is_unc_path(&path) {
+        problems.push(TempProblem::Network("SMB".to_string()));
+    }
+    if latency > SLOW_FILE_OPERATION
+        && !problems.iter().any(|problem| matches!(problem, TempProblem::Network(_)))
+    {
+        problems.push(TempProblem::Slow(latency));
+    }
+    problems
+}
+
+/// Directory on local storage that the temporary directory `temp` is moved to
+/// by [`TempRelocation::Auto`]. The same directory is used when the encode is
+/// resumed. It is named after the full path of `temp`, so that temporary
+/// directories of the same name in different places are not mixed up.
+fn local_temp_dir(temp: &Path) -> PathBuf {
+    let absolute = std::path::absolute(temp).unwrap_or_else(|_| temp.to_path_buf());
+    // `temp` may not exist yet, but its parent usually does
+    let canonical = absolute
+        .parent()
+        .and_then(|parent| dunce::canonicalize(parent).ok())
+        .zip(absolute.file_name())
+        .map_or_else(|| absolute.clone(), |(parent, name)| parent.join(name));
+    let hash = crate::hash_path(&canonical);
+    let name = canonical.file_name().map_or_else(
+        || hash.clone(),
+        |name| format!("{}-{hash}", name.to_string_lossy()),
+    );
+    env::temp_dir().join("av1an").join(name)
+}
+
+/// Returns the directory to use as the temporary directory `temp`, which
+/// should have about `needed` bytes free.
+///
+/// If `temp` is read-only, nearly full, on a network file system or slow,
+/// [`TempRelocation::Auto`] moves it to local storage, as long as that has
+/// none of these problems. An encode that was moved is resumed from local
+/// storage as well.
+///
+/// # Errors
+///
+/// Returns an error if `temp` is not writable and cannot be moved.
+#[inline]
+pub fn choose_temp_dir(
+    temp: &Path,
+    needed: u64,
+    relocation: TempRelocation,
+    resume: bool,
+) -> anyhow::Result<PathBuf> {
+    let local = local_temp_dir(temp);
+    let relocate = relocation == TempRelocation::Auto && local != temp;
+    if relocate && resume && local.join("chunks.json").exists() {
+        info!(
+            "resuming from the local temporary directory {}",
+            local.display()
+        );
+        return Ok(local);
+    }
+
+    let problems = check_temp_dir(temp, needed);
+    if problems.is_empty() {
+        return Ok(temp.to_path_buf());
+    }
+    let reasons = problems.iter().join(", ");
+    if relocate && check_temp_dir(&local, needed).is_empty() {
+        // only removed if nothing was written to it yet
+        let _ = fs::remove_dir(temp);
+        warn!(
+            "The temporary directory {} is unsuited for encoding, as {reasons}. Using {} instead, \
+             which is copied back after encoding if --keep is set",
+            temp.display(),
+            local.display()
+        );
+        return Ok(local);
+    }
+    if problems.iter().any(TempProblem::is_fatal) {
+        bail!(
+            "The temporary directory {} cannot be used, as {reasons}. Pass a writable directory \
+             with --temp",
+            temp.display()
+        );
+    }
+    warn!(
+        "The temporary directory {} may slow down or fail the encode, as {reasons}",
+        temp.display()
+    );
+    Ok(temp.to_path_buf())
+}
+
+/// Copies the contents of `from` into `to`, creating it if needed
+pub(crate) fn copy_dir_all(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let destination = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &destination)?;
+        } else {
+            fs::copy(entry.path(), destination)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_temp_dir_keeps_the_name() {
+        let local = local_temp_dir(Path::new("/mnt/nas/.av1an-1234"));
+        assert_eq!(
+            local.parent(),
+            Some(env::temp_dir().join("av1an").as_path())
+        );
+        assert!(local
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with(".av1an-1234-")));
+        // temporary directories of the same name elsewhere are moved elsewhere
+        assert_ne!(local, local_temp_dir(Path::new("/mnt/other/.av1an-1234")));
+        assert_eq!(local, local_temp_dir(Path::new("/mnt/nas/.av1an-1234")));
+        assert!(is_unc_path(Path::new(r"\\nas\encodes\temp")));
+        assert!(!is_unc_path(Path::new(r"\\?\C:\temp")));
+    }
+
+    #[test]
+    fn writable_directory_has_no_fatal_problem() {
+        let temp = tempfile::tempdir().expect("should create a temporary directory");
+        let problems = check_temp_dir(&temp.path().join("temp"), 0);
+        assert!(!problems.iter().any(TempProblem::is_fatal));
+        assert!(temp.path().join("temp").is_dir());
+    }
+}
@@ -0,0 +1,223 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, ProcessesToUpdate, System};
+use tracing::{debug, warn};
+
+use crate::hash_path;
+
+/// Name of the registry file, which is kept in the directory the temporary
+/// directories are created in
+const REGISTRY_FILE: &str = ".av1an-temp.json";
+
+/// A temporary directory created for an encode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RegistryEntry {
+    /// Name of the temporary directory, relative to the registry
+    temp:   String,
+    input:  PathBuf,
+    output: PathBuf,
+    /// Process ID of the Av1an instance that last used the directory
+    pid:    u32,
+}
+
+/// Keeps track of which encode each temporary directory belongs to, so that
+/// an encode can be resumed although its temporary directory has a unique
+/// name
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Registry {
+    entries: Vec<RegistryEntry>,
+}
+
+impl Registry {
+    fn read(root: &Path) -> Self {
+        let path = root.join(REGISTRY_FILE);
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!(
+                "Ignoring invalid temporary directory registry {}: {e}",
+                path.display()
+            );
+            Self::default()
+        })
+    }
+
+    /// Write the registry, dropping every entry whose temporary directory no
+    /// longer exists
+    fn write(mut self, root: &Path) -> anyhow::Result<()> {
+        self.entries.retain(|entry| root.join(&entry.temp).is_dir());
+        let path = root.join(REGISTRY_FILE);
+        if self.entries.is_empty() {
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+            return Ok(());
+        }
+
+        // write to a temporary file first, so that an instance reading the
+        // registry at the same time never sees a partially written file
+        let partial = root.join(format!("{REGISTRY_FILE}.{}", process::id()));
+        let json = serde_json::to_string_pretty(&self).expect("serialize should not fail");
+        fs::write(&partial, json)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        fs::rename(&partial, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Returns the most recently registered entry of the encode of `input` to
+    /// `output` whose temporary directory still exists
+    fn find(&mut self, root: &Path, input: &Path, output: &Path) -> Option<&mut RegistryEntry> {
+        self.entries.iter_mut().rev().find(|entry| {
+            entry.input == input && entry.output == output && root.join(&entry.temp).is_dir()
+        })
+    }
+}
+
+/// Returns the name of a temporary directory for an encode of `input` to
+/// `output` started at `time` by the process `pid`
+fn temp_dir_name(input: &Path, output: &Path, time: SystemTime, pid: u32, attempt: u32) -> String {
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    output.hash(&mut hasher);
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().hash(&mut hasher);
+    pid.hash(&mut hasher);
+    attempt.hash(&mut hasher);
+    format!(".{:016x}", hasher.finish())
+}
+
+fn is_running(pid: u32) -> bool {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+    system.process(pid).is_some()
+}
+
+/// Holds an exclusive lock on the registry in `root` until it is dropped, so
+/// that concurrent instances do not lose each other's entries
+fn lock_registry(root: &Path) -> anyhow::Result<File> {
+    fs::create_dir_all(root)
+        .with_context(|| format!("Failed to create directory {}", root.display()))?;
+    let path = root.join(format!("{REGISTRY_FILE}.lock"));
+    let lock =
+        File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+    lock.lock().with_context(|| format!("Failed to lock {}", path.display()))?;
+    Ok(lock)
+}
+
+/// Returns the temporary directory of an encode of `input` to `output`
+/// within `root`, or within the current directory if `root` is `None`.
+///
+/// A new encode gets a new directory with a unique name, which is created by
+/// [`register_temp_dir`] once the encode starts. When resuming, the directory
+/// of the most recent encode of the same input to the same output is reused,
+/// unless another Av1an instance is still encoding into it. Directories
+/// created by older versions, which were named after only the input, are
+/// also found when resuming.
+///
+/// # Errors
+///
+/// Returns an error if the encode being resumed is still running in another
+/// instance.
+#[inline]
+pub fn resolve_temp_dir(
+    root: Option<&Path>,
+    input: &Path,
+    output: &Path,
+    resume: bool,
+) -> anyhow::Result<PathBuf> {
+    let root = root.unwrap_or_else(|| Path::new("."));
+    let legacy = root.join(format!(".{}", hash_path(input)));
+    let input = std::path::absolute(input)?;
+    let output = std::path::absolute(output)?;
+    let pid = process::id();
+    let mut registry = Registry::read(root);
+
+    if resume {
+        if let Some(entry) = registry.find(root, &input, &output) {
+            if entry.pid != pid && is_running(entry.pid) {
+                bail!(
+                    "The encode of {} is still running in another Av1an instance (process {}), \
+                     using temporary directory {}",
+                    input.display(),
+                    entry.pid,
+                    root.join(&entry.temp).display()
+                );
+            }
+            let temp = root.join(&entry.temp);
+            debug!("resuming from temporary directory {}", temp.display());
+            return Ok(temp);
+        }
+
+        if legacy.is_dir() {
+            return Ok(legacy);
+        }
+    }
+
+    let time = SystemTime::now();
+    let name = (0..=u32::MAX)
+        .map(|attempt| temp_dir_name(&input, &output, time, pid, attempt))
+        .find(|name| {
+            !root.join(name).exists() && !registry.entries.iter().any(|entry| entry.temp == *name)
+        })
+        .expect("some name should be free");
+    Ok(root.join(name))
+}
+
+/// Records in the registry of `root` that the encode of `input` to `output`
+/// uses the temporary directory `temp` in this process, which takes over the
+/// entry of a resumed encode.
+///
+/// # Errors
+///
+/// Returns an error if the registry cannot be locked or written, or if
+/// another Av1an instance started encoding into `temp` in the meantime.
+#[inline]
+pub fn register_temp_dir(
+    root: &Path,
+    temp: &Path,
+    input: &Path,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let Some(name) = temp.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+        return Ok(());
+    };
+    let input = std::path::absolute(input)?;
+    let output = std::path::absolute(output)?;
+    let pid = process::id();
+
+    let _lock = lock_registry(root)?;
+    let mut registry = Registry::read(root);
+    if let Some(entry) = registry.entries.iter_mut().find(|entry| entry.temp == name) {
+        if entry.pid != pid && is_running(entry.pid) {
+            bail!(
+                "The encode of {} is still running in another Av1an instance (process {}), using \
+                 temporary directory {}",
+                input.display(),
+                entry.pid,
+                temp.display()
+            );
+        }
+        entry.pid = pid;
+    } else {
+        registry.entries.push(RegistryEntry {
+            temp: name,
+            input,
+            output,
+            pid,
+        });
+    }
+    registry.write(root)
+}
@@ -0,0 +1,72 @@
+use super::*;
+
+/// Resolves the temporary directory of an encode and starts it, as the
+/// encode's initialization does
+fn start_encode(root: &Path, input: &Path, output: &Path, resume: bool) -> PathBuf {
+    let temp =
+        resolve_temp_dir(Some(root), input, output, resume).expect("should resolve temp dir");
+    fs::create_dir_all(&temp).expect("should create temp dir");
+    register_temp_dir(root, &temp, input, output).expect("should register temp dir");
+    temp
+}
+
+#[test]
+fn new_encodes_get_unique_temp_dirs() {
+    let root = tempfile::tempdir().expect("should create temp dir");
+    let input = Path::new("input.mkv");
+    let output = Path::new("output.mkv");
+
+    let first = start_encode(root.path(), input, output, false);
+    let second = start_encode(root.path(), input, output, false);
+    assert_ne!(first, second);
+    assert_eq!(Registry::read(root.path()).entries.len(), 2);
+
+    // resuming picks the most recent encode of the same input and output
+    let resumed = start_encode(root.path(), input, output, true);
+    assert_eq!(resumed, second);
+    assert_eq!(Registry::read(root.path()).entries.len(), 2);
+
+    // once a directory is removed, its entry is dropped
+    fs::remove_dir_all(&second).expect("should remove temp dir");
+    let resumed = start_encode(root.path(), input, output, true);
+    assert_eq!(resumed, first);
+    assert_eq!(Registry::read(root.path()).entries.len(), 1);
+}
+
+#[test]
+fn resolving_creates_nothing() {
+    let root = tempfile::tempdir().expect("should create temp dir");
+    let temp = resolve_temp_dir(
+        Some(root.path()),
+        Path::new("input.mkv"),
+        Path::new("output.mkv"),
+        false,
+    )
+    .expect("should resolve temp dir");
+    assert!(!temp.exists());
+    assert_eq!(
+        fs::read_dir(root.path()).expect("should read temp root").count(),
+        0
+    );
+}
+
+#[test]
+fn resume_without_registry_entry() {
+    let root = tempfile::tempdir().expect("should create temp dir");
+    let input = Path::new("input.mkv");
+
+    // resuming a different output starts a new encode
+    start_encode(root.path(), input, Path::new("a.mkv"), false);
+    start_encode(root.path(), input, Path::new("b.mkv"), true);
+    assert_eq!(Registry::read(root.path()).entries.len(), 2);
+
+    // directories named after the input by older versions are still resumed
+    let root = tempfile::tempdir().expect("should create temp dir");
+    let legacy = root.path().join(format!(".{}", hash_path(input)));
+    fs::create_dir_all(&legacy).expect("should create legacy temp dir");
+    assert_eq!(
+        resolve_temp_dir(Some(root.path()), input, Path::new("a.mkv"), true)
+            .expect("should resolve temp dir"),
+        legacy
+    );
+}
@@ -0,0 +1,33 @@
+#[cfg(test)]
+mod tests;
+
+/// Bitrate deviations beyond this many doublings (or halvings) from the
+/// reference do not move the quantizer any further
+const MAX_DOUBLINGS: f64 = 2.0;
+
+/// Computes the quantizer offset of each chunk for the final encode of a
+/// two-stage encode, from the bits per frame its preview encode needed.
+///
+/// Chunks that needed more bits than the geometric mean of all chunks get a
+/// higher quantizer, and chunks that needed fewer get a lower one, by
+/// `strength` per doubling of bitrate. This keeps quality roughly constant
+/// while evening out the size of the hardest and easiest chunks.
+pub(crate) fn q_offsets(bits_per_frame: &[f64], strength: f32) -> Vec<f32> {
+    let valid = bits_per_frame.iter().copied().filter(|bpf| *bpf > 0.0);
+    let count = valid.clone().count();
+    if count == 0 {
+        return vec![0.0; bits_per_frame.len()];
+    }
+    let log_mean = valid.map(f64::log2).sum::<f64>() / count as f64;
+
+    bits_per_frame
+        .iter()
+        .map(|&bpf| {
+            if bpf <= 0.0 {
+                return 0.0;
+            }
+            let doublings = (bpf.log2() - log_mean).clamp(-MAX_DOUBLINGS, MAX_DOUBLINGS);
+            (f64::from(strength) * doublings) as f32
+        })
+        .collect()
+}
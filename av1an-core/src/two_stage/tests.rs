@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn q_offsets_equal_bitrates() {
+    let offsets = q_offsets(&[1000.0, 1000.0, 1000.0], 2.0);
+    assert_eq!(offsets, vec![0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn q_offsets_scale_with_doublings() {
+    // Geometric mean is 1000
+    let offsets = q_offsets(&[500.0, 1000.0, 2000.0], 2.0);
+    assert!((offsets[0] + 2.0).abs() < 1e-4);
+    assert!(offsets[1].abs() < 1e-4);
+    assert!((offsets[2] - 2.0).abs() < 1e-4);
+}
+
+#[test]
+fn q_offsets_clamped_and_empty_chunks_ignored() {
+    let offsets = q_offsets(&[1.0, 1_000_000.0, 0.0], 3.0);
+    assert!((offsets[0] + 6.0).abs() < 1e-4);
+    assert!((offsets[1] - 6.0).abs() < 1e-4);
+    assert_eq!(offsets[2], 0.0);
+    assert!(q_offsets(&[0.0, 0.0], 2.0).iter().all(|offset| *offset == 0.0));
+}
@@ -1,7 +1,14 @@
 #[cfg(test)]
 mod tests;
 
-use std::path::{Path, PathBuf};
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use av_format::rational::Rational64;
+use num_traits::ToPrimitive;
 
 /// Count the number of elements passed to this macro.
 ///
@@ -132,3 +139,161 @@ pub fn read_in_dir(path: &Path) -> anyhow::Result<impl Iterator<Item = PathBuf>>
         d.file_type().map_or(None, |file_type| (!file_type.is_dir()).then(|| d.path()))
     }))
 }
+
+/// Number of frames closest to `seconds` at `frame_rate`
+#[inline]
+pub fn seconds_to_frames(seconds: f64, frame_rate: Rational64) -> usize {
+    let frame_rate = frame_rate.to_f64().expect("frame rate should not be NaN");
+    (seconds * frame_rate).round() as usize
+}
+
+/// Parses a duration such as `90`, `45s`, `10m`, `1.5h` or `1h30m`
+///
+/// A number without a unit is interpreted as seconds.
+#[inline]
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {s:?}, expected e.g. 90s, 10m or 1h30m");
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|_| invalid());
+    }
+
+    let mut secs = 0.0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let multiplier = match c {
+            's' => 1.0,
+            'm' => 60.0,
+            'h' => 3600.0,
+            _ => return Err(invalid()),
+        };
+        let value: f64 = number.parse().map_err(|_| invalid())?;
+        secs = value.mul_add(multiplier, secs);
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+
+    Duration::try_from_secs_f64(secs).map_err(|_| invalid())
+}
+
+/// A point of the input to trim it at, given as a frame or a time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrimPoint {
+    Frame(usize),
+    Time(Duration),
+}
+
+impl TrimPoint {
+    /// The frame of this point in a video with `frame_rate`
+    #[inline]
+    #[must_use]
+    pub fn to_frame(self, frame_rate: Rational64) -> usize {
+        match self {
+            Self::Frame(frame) => frame,
+            Self::Time(time) => seconds_to_frames(time.as_secs_f64(), frame_rate),
+        }
+    }
+}
+
+/// Parses a frame such as `1200f`, a timestamp such as `1:02:03.5` or `2:30`,
+/// or a duration as accepted by [`parse_duration`]
+#[inline]
+pub fn parse_trim_point(s: &str) -> Result<TrimPoint, String> {
+    let s = s.trim();
+    if let Some(frame) = s.strip_suffix('f') {
+        return frame
+            .parse()
+            .map(TrimPoint::Frame)
+            .map_err(|_| format!("invalid frame {s:?}, expected e.g. 1200f"));
+    }
+    if s.contains(':') {
+        let invalid = || format!("invalid timestamp {s:?}, expected e.g. 1:02:03.5 or 2:30");
+        let mut secs = 0.0_f64;
+        for (i, part) in s.split(':').enumerate() {
+            let value: f64 = part.parse().map_err(|_| invalid())?;
+            if i > 2 || !value.is_finite() || value < 0.0 {
+                return Err(invalid());
+            }
+            secs = secs.mul_add(60.0, value);
+        }
+        return Duration::try_from_secs_f64(secs).map(TrimPoint::Time).map_err(|_| invalid());
+    }
+    parse_duration(s).map(TrimPoint::Time)
+}
+
+/// Directory Av1an caches downloads in between runs, `~/.cache/av1an` (or
+/// `%LOCALAPPDATA%\av1an` on Windows)
+#[inline]
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    let cache_dir = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::home_dir().map(|home| home.join(".cache")))
+    }?;
+
+    Some(cache_dir.join("av1an"))
+}
+
+/// SHA-1 digest of `data` as lowercase hex, for checking downloads against
+/// the checksums git records for them
+pub(crate) fn sha1_hex(data: &[u8]) -> String {
+    let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..40 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    state.iter().fold(String::with_capacity(40), |mut hex, word| {
+        write!(hex, "{word:08x}").expect("writing to a String should not fail");
+        hex
+    })
+}
@@ -1,4 +1,8 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, time::Duration};
+
+use av_format::rational::Rational64;
+
+use super::{parse_duration, parse_trim_point, seconds_to_frames, sha1_hex, TrimPoint};
 
 #[test]
 fn count_macro() {
@@ -33,3 +37,71 @@ fn inplace_vec_is_sound() {
 
     assert_eq!(v1, v2);
 }
+
+#[test]
+fn parse_duration_units() {
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+    assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+    assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("2m30s"), Ok(Duration::from_secs(150)));
+
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("10x").is_err());
+    assert!(parse_duration("m").is_err());
+    assert!(parse_duration("-5").is_err());
+}
+
+#[test]
+fn seconds_to_frames_by_frame_rate() {
+    assert_eq!(seconds_to_frames(1.0, Rational64::new(24000, 1001)), 24);
+    assert_eq!(seconds_to_frames(1.0, Rational64::from_integer(50)), 50);
+    assert_eq!(seconds_to_frames(0.5, Rational64::new(60000, 1001)), 30);
+    assert_eq!(seconds_to_frames(0.0, Rational64::from_integer(60)), 0);
+}
+
+#[test]
+fn sha1_digests() {
+    assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    // spans several blocks
+    assert_eq!(
+        sha1_hex(&[b'a'; 1000]),
+        "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+    );
+}
+
+#[test]
+fn parse_trim_points() {
+    assert_eq!(parse_trim_point("1200f"), Ok(TrimPoint::Frame(1200)));
+    assert_eq!(
+        parse_trim_point("1:02:03.5"),
+        Ok(TrimPoint::Time(Duration::from_secs_f64(3723.5)))
+    );
+    assert_eq!(
+        parse_trim_point("2:30"),
+        Ok(TrimPoint::Time(Duration::from_secs(150)))
+    );
+    assert_eq!(
+        parse_trim_point("75.5"),
+        Ok(TrimPoint::Time(Duration::from_secs_f64(75.5)))
+    );
+    assert_eq!(
+        parse_trim_point("90s"),
+        Ok(TrimPoint::Time(Duration::from_secs(90)))
+    );
+    assert!(parse_trim_point("f").is_err());
+    assert!(parse_trim_point("1:2:3:4").is_err());
+    assert!(parse_trim_point("1:-5").is_err());
+
+    let ntsc = Rational64::new(24000, 1001);
+    assert_eq!(
+        parse_trim_point("10s").map(|point| point.to_frame(ntsc)),
+        Ok(240)
+    );
+    assert_eq!(
+        parse_trim_point("500f").map(|point| point.to_frame(ntsc)),
+        Ok(500)
+    );
+}
@@ -1,12 +1,20 @@
 use std::{
-    fmt::Display,
+    env,
+    fmt::{Display, Write as _},
     fs::{create_dir_all, File},
-    io::Write,
+    io::{self, Write},
+    ops::Range,
     path::{absolute, Path, PathBuf},
-    process::Command,
+    process::{self, Command},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+        PoisonError,
+    },
 };
 
 use anyhow::{anyhow, bail, Context};
+use av1_grain::TransferFunction;
 use av_format::rational::Rational64;
 use path_abs::{PathAbs, PathInfo};
 use serde::{Deserialize, Serialize};
@@ -24,6 +32,7 @@ use crate::{
         butteraugli::ButteraugliSubMetric,
         xpsnr::{weight_xpsnr, XPSNRSubMetric},
     },
+    vs_params::script_prelude,
     ClipInfo,
     ColorRange,
     Input,
@@ -57,6 +66,7 @@ pub struct VapoursynthPlugins {
     pub julek:      bool,
     pub vszip:      VSZipVersion,
     pub vship:      bool,
+    pub neo_f3kdb:  bool,
 }
 
 impl VapoursynthPlugins {
@@ -107,6 +117,7 @@ pub fn get_vapoursynth_plugins() -> anyhow::Result<VapoursynthPlugins> {
             VSZipVersion::None
         },
         vship:      core.get_plugin_by_id(PluginId::Vship.as_str())?.is_some(),
+        neo_f3kdb:  core.get_plugin_by_id(PluginId::NeoF3kdb.as_str())?.is_some(),
     })
 }
 
@@ -117,6 +128,49 @@ fn is_vszip_r7_or_newer(plugin: Plugin) -> anyhow::Result<bool> {
     Ok(plugin.get_plugin_function_by_name("XPSNR")?.is_some())
 }
 
+/// Held while the working directory of the process is changed by
+/// [`in_dir`]
+static WORKING_DIR: Mutex<()> = Mutex::new(());
+
+/// Runs `f` with `dir` as the working directory, and changes it back
+/// afterwards. The working directory is shared by every thread, so only one
+/// thread changes it at a time, and the paths Av1an passes around, like the
+/// temporary directory, are absolute.
+fn in_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> io::Result<T> {
+    let _lock = WORKING_DIR.lock().unwrap_or_else(PoisonError::into_inner);
+    let previous = env::current_dir()?;
+    env::set_current_dir(dir)?;
+    let result = f();
+    env::set_current_dir(previous)?;
+    Ok(result)
+}
+
+/// Evaluates the script of `source` from memory.
+///
+/// Scripts of VapourSynth inputs are evaluated with their own folder as the
+/// working directory, as vspipe does, so that they can open files next to
+/// them with relative paths. They also get a prelude that sets `__file__` and
+/// lets them import modules next to them.
+pub(crate) fn eval_source(environment: &mut Environment, source: &Input) -> anyhow::Result<()> {
+    let script = source.as_script_text()?;
+    match source {
+        Input::VapourSynth {
+            path, ..
+        } => {
+            let script = format!("{}{script}", script_prelude(path)?);
+            let path = absolute(path)?;
+            let dir = path.parent().unwrap_or(&path);
+            in_dir(dir, || environment.eval_script(&script)).with_context(|| {
+                format!("Failed to change to the folder of {}", path.display())
+            })??;
+        },
+        Input::Video {
+            ..
+        } => environment.eval_script(&script)?,
+    }
+    Ok(())
+}
+
 #[inline]
 pub fn get_clip_info(source: &Input, vspipe_args_map: &OwnedMap) -> anyhow::Result<ClipInfo> {
     const CONTEXT_MSG: &str = "get_clip_info";
@@ -126,16 +180,14 @@ pub fn get_clip_info(source: &Input, vspipe_args_map: &OwnedMap) -> anyhow::Resu
     if environment.set_variables(vspipe_args_map).is_err() {
         bail!("Failed to set vspipe arguments");
     };
-    if source.is_vapoursynth() {
-        environment
-            .eval_file(source.as_path(), EvalFlags::SetWorkingDir)
-            .context(CONTEXT_MSG)?;
-    } else {
-        environment.eval_script(&source.as_script_text()?).context(CONTEXT_MSG)?;
-    }
+    eval_source(&mut environment, source).context(CONTEXT_MSG)?;
 
     let (node, _) = environment.get_output(OUTPUT_INDEX)?;
     let info = node.info();
+    if info.resolution == Property::Variable || info.format.color_family() == ColorFamily::Undefined
+    {
+        return Err(variable_format_error(&node));
+    }
 
     Ok(ClipInfo {
         num_frames:               get_num_frames(&info)?,
@@ -152,6 +204,77 @@ pub fn get_clip_info(source: &Input, vspipe_args_map: &OwnedMap) -> anyhow::Resu
     })
 }
 
+/// Most frame ranges listed by [`variable_format_error`]
+const MAX_FORMAT_RANGES: usize = 20;
+
+/// Describes the frames of a clip whose format or resolution varies, which
+/// cannot be piped to the encoders as y4m, by reading every frame and listing
+/// the ranges of frames with the same format and resolution
+fn variable_format_error(node: &Node) -> anyhow::Error {
+    info!("The clip has a variable format or resolution, checking every frame");
+    let frames = (0..node.info().num_frames).map(|n| {
+        node.get_frame(n).map(|frame| {
+            (
+                frame.format().name().to_string(),
+                frame.width(0),
+                frame.height(0),
+            )
+        })
+    });
+    let mut formats = Vec::new();
+    for (n, frame) in frames.enumerate() {
+        match frame {
+            Ok(frame) => formats.push(frame),
+            Err(e) => {
+                return anyhow!(
+                    "The clip has a variable format or resolution, and frame {n} failed to \
+                     render: {e}"
+                );
+            },
+        }
+    }
+    let ranges = frame_ranges(formats);
+
+    let mut message =
+        "The clip has a variable format or resolution, which cannot be encoded:".to_string();
+    for (frames, (format, width, height)) in ranges.iter().take(MAX_FORMAT_RANGES) {
+        let _ = write!(
+            message,
+            "\n  frames {}-{}: {format} {width}x{height}",
+            frames.start,
+            frames.end - 1
+        );
+    }
+    if ranges.len() > MAX_FORMAT_RANGES {
+        let _ = write!(
+            message,
+            "\n  and {} more ranges",
+            ranges.len() - MAX_FORMAT_RANGES
+        );
+    }
+    if let Some((_, (format, width, height))) = ranges.first() {
+        let _ = write!(
+            message,
+            "\nConvert every frame to the same format and resolution at the end of the script, \
+             e.g. with\n  clip = core.resize.Bicubic(clip, width={width}, height={height}, \
+             format=vs.{format})"
+        );
+    }
+    anyhow!(message)
+}
+
+/// Groups consecutive frames with equal values into ranges of frames
+fn frame_ranges<T: PartialEq>(values: impl IntoIterator<Item = T>) -> Vec<(Range<usize>, T)> {
+    let mut ranges: Vec<(Range<usize>, T)> = Vec::new();
+    for (frame, value) in values.into_iter().enumerate() {
+        match ranges.last_mut() {
+            Some((frames, last)) if *last == value => frames.end = frame + 1,
+            _ => ranges.push((frame..frame + 1, value)),
+        }
+    }
+    ranges
+}
+
 /// Get the number of frames from an environment that has already been
 /// evaluated on a script.
 fn get_num_frames(info: &VideoInfo) -> anyhow::Result<usize> {
@@ -256,6 +379,7 @@ enum PluginId {
     Julek,
     Vszip,
     Vship,
+    NeoF3kdb,
 }
 
 impl PluginId {
@@ -270,6 +394,7 @@ impl PluginId {
             PluginId::Julek => "com.julek.plugin",
             PluginId::Vszip => "com.julek.vszip",
             PluginId::Vship => "com.lumen.vship",
+            PluginId::NeoF3kdb => "com.vapoursynth.neo_f3kdb",
         }
     }
 }
@@ -286,6 +411,7 @@ fn get_plugin(core: CoreRef, plugin_id: PluginId) -> anyhow::Result<Plugin> {
 }
 
 #[cfg(test)]
+#[expect(clippy::items_after_test_module)]
 mod tests {
     use super::*;
 
@@ -295,6 +421,46 @@ mod tests {
         assert_eq!(map_vapoursynth_color_range(1), Some(ColorRange::Limited));
         assert_eq!(map_vapoursynth_color_range(2), None);
     }
+
+    #[test]
+    fn source_bit_depth_downconversion() {
+        let check = |chunk_method, source, clip| {
+            check_source_bit_depth(chunk_method, source, clip, TransferFunction::SMPTE2084)
+        };
+        assert!(check(ChunkMethod::DGDECNV, 10, 10).is_ok());
+        assert!(check(ChunkMethod::LSMASH, 8, 10).is_ok());
+
+        let err = check(ChunkMethod::DGDECNV, 10, 8).expect_err("8-bit output should fail");
+        assert!(err.to_string().contains("10-bit HDR source as 8-bit"));
+        assert!(err.to_string().contains("fulldepth"));
+        assert!(check(ChunkMethod::FFMS2, 12, 10).is_err());
+    }
+
+    #[test]
+    fn frame_ranges_of_equal_formats() {
+        assert_eq!(frame_ranges(["a", "a", "b", "b", "b", "a"]), vec![
+            (0..2, "a"),
+            (2..5, "b"),
+            (5..6, "a")
+        ]);
+        assert!(frame_ranges(Vec::<u8>::new()).is_empty());
+    }
+
+    #[test]
+    fn relative_paths_from_script_folder() {
+        let dir = tempfile::tempdir().expect("should create a temporary directory");
+        std::fs::write(dir.path().join("grain.tbl"), "table").expect("should write the file");
+        let previous = env::current_dir().expect("should get the working directory");
+
+        let contents = in_dir(dir.path(), || std::fs::read_to_string("grain.tbl"))
+            .expect("should change the working directory")
+            .expect("should read the file relative to the folder");
+        assert_eq!(contents, "table");
+        assert_eq!(
+            env::current_dir().expect("should get the working directory"),
+            previous
+        );
+    }
 }
 
 fn import_lsmash<'core>(
@@ -750,6 +916,43 @@ pub fn create_vs_file(loadscript_args: &LoadscriptArgs) -> anyhow::Result<(PathB
     Ok((load_script_path, cache_file_already_exists))
 }
 
+/// Write a loadscript that reads `source` with BestSource, next to the
+/// loadscript of the chunk method, for chunks that the chunk method decodes
+/// wrongly. Returns the path and the text of the script.
+#[inline]
+pub fn create_bestsource_vs_file(
+    temp: &str,
+    source: &Path,
+    cache_mode: CacheSource,
+) -> anyhow::Result<(PathBuf, String)> {
+    let (load_script_text, _) = generate_loadscript_text(&LoadscriptArgs {
+        temp,
+        source,
+        chunk_method: ChunkMethod::BESTSOURCE,
+        is_proxy: false,
+        cache_mode,
+    })?;
+    let split_folder = Path::new(temp).join("split");
+    create_dir_all(&split_folder)?;
+
+    let load_script_path = split_folder.join("loadscript_bestsource.vpy");
+    if std::fs::read_to_string(&load_script_path).ok().as_deref() != Some(load_script_text.as_str())
+    {
+        // other workers may read the script while it is written, so it is
+        // written under another name and replaced at once
+        static PARTIAL: AtomicUsize = AtomicUsize::new(0);
+        let partial = split_folder.join(format!(
+            "loadscript_bestsource.vpy.{}-{}",
+            process::id(),
+            PARTIAL.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&partial, &load_script_text)?;
+        std::fs::rename(&partial, &load_script_path)?;
+    }
+
+    Ok((load_script_path, load_script_text))
+}
+
 pub struct LoadscriptArgs<'a> {
     pub temp:         &'a str,
     pub source:       &'a Path,
@@ -840,6 +1043,36 @@ pub fn generate_loadscript_text(
     Ok((load_script_text, cache_file_already_exists))
 }
 
+/// Ensure that the clip a source filter outputs for a video input has the bit
+/// depth of the input itself, as chunks are piped to the encoder at the bit
+/// depth of that clip
+pub(crate) fn check_source_bit_depth(
+    chunk_method: ChunkMethod,
+    source_bit_depth: usize,
+    clip_bit_depth: usize,
+    transfer: TransferFunction,
+) -> anyhow::Result<()> {
+    if clip_bit_depth >= source_bit_depth {
+        return Ok(());
+    }
+
+    let hint = if chunk_method == ChunkMethod::DGDECNV {
+        ", make sure DGDecNV is recent enough to support the fulldepth option of DGSource, and \
+         that the GPU can decode this source at full bit depth"
+    } else {
+        ", use a different chunk method"
+    };
+    bail!(
+        "The {chunk_method} chunk method would decode the {source_bit_depth}-bit {}source as \
+         {clip_bit_depth}-bit, silently discarding precision before encoding{hint}",
+        if transfer == TransferFunction::SMPTE2084 {
+            "HDR "
+        } else {
+            ""
+        }
+    );
+}
+
 #[inline]
 pub fn get_source_chunk<'core>(
     core: CoreRef<'core>,
@@ -902,10 +1135,7 @@ pub fn measure_butteraugli(
     let mut environment = Environment::new()?;
     let args = source.as_vspipe_args_map()?;
     environment.set_variables(&args)?;
-    // Cannot use eval_file because it causes file system access errors during
-    // Target Quality probing
-    // Consider using eval_file only when source is not in CWD
-    environment.eval_script(&source.as_script_text()?)?;
+    eval_source(&mut environment, source)?;
     let core = environment.get_core()?;
 
     let source_node = environment.get_output(0)?.0;
@@ -941,9 +1171,7 @@ pub fn measure_ssimulacra2(
     let mut environment = Environment::new()?;
     let args = source.as_vspipe_args_map()?;
     environment.set_variables(&args)?;
-    // Cannot use eval_file because it causes file system access errors during
-    // Target Quality probing
-    environment.eval_script(&source.as_script_text()?)?;
+    eval_source(&mut environment, source)?;
     let core = environment.get_core()?;
 
     let source_node = environment.get_output(0)?.0;
@@ -980,9 +1208,7 @@ pub fn measure_xpsnr(
     let mut environment = Environment::new()?;
     let args = source.as_vspipe_args_map()?;
     environment.set_variables(&args)?;
-    // Cannot use eval_file because it causes file system access errors during
-    // Target Quality probing
-    environment.eval_script(&source.as_script_text()?)?;
+    eval_source(&mut environment, source)?;
     let core = environment.get_core()?;
 
     let source_node = environment.get_output(0)?.0;
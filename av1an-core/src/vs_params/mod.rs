@@ -0,0 +1,235 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashSet,
+    path::{absolute, Path},
+};
+
+use anyhow::{bail, Context};
+use strum::{Display, EnumString};
+
+/// Prefix of script variables that are set by Av1an itself
+const RESERVED_PREFIX: &str = "AV1AN_";
+
+/// Comment prefix a VapourSynth script uses to declare a parameter, e.g.
+/// `# av1an-param: strength: float = 1.0`
+const DECLARATION_PREFIX: &str = "av1an-param:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum ParamType {
+    Int,
+    Float,
+    Bool,
+    Str,
+}
+
+impl ParamType {
+    /// Validate `value` against this type, returning the form that is passed
+    /// to the script.
+    ///
+    /// Booleans are passed as `1` or `0`, so that `bool(int(value))` works in
+    /// the script.
+    fn normalize(self, value: &str) -> anyhow::Result<String> {
+        Ok(match self {
+            Self::Int => value.parse::<i64>().map(|_| value.to_string())?,
+            Self::Float => value.parse::<f64>().map(|_| value.to_string())?,
+            Self::Bool => match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => "1".to_string(),
+                "0" | "false" | "no" | "off" => "0".to_string(),
+                _ => bail!("expected a boolean (true/false, yes/no, on/off or 1/0)"),
+            },
+            Self::Str => value.to_string(),
+        })
+    }
+}
+
+/// A parameter declared by a VapourSynth script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ScriptParam {
+    pub name:    String,
+    pub ty:      ParamType,
+    pub default: Option<String>,
+}
+
+/// Parse the parameters declared in the comments of a VapourSynth script.
+///
+/// Each declaration is a comment line of the form
+/// `# av1an-param: <name>: <type> [= <default>]`, where the type is one of
+/// `int`, `float`, `bool` or `str`.
+pub(crate) fn parse_declared_params(script_text: &str) -> anyhow::Result<Vec<ScriptParam>> {
+    let mut params: Vec<ScriptParam> = Vec::new();
+
+    for (line_number, line) in script_text.lines().enumerate() {
+        let Some(declaration) = line
+            .trim_start()
+            .strip_prefix('#')
+            .and_then(|comment| comment.trim_start().strip_prefix(DECLARATION_PREFIX))
+        else {
+            continue;
+        };
+        let param = parse_declaration(declaration.trim()).with_context(|| {
+            format!("Invalid parameter declaration on line {}", line_number + 1)
+        })?;
+        if params.iter().any(|declared| declared.name == param.name) {
+            bail!(
+                "Parameter {} is declared more than once (line {})",
+                param.name,
+                line_number + 1
+            );
+        }
+        params.push(param);
+    }
+
+    Ok(params)
+}
+
+fn parse_declaration(declaration: &str) -> anyhow::Result<ScriptParam> {
+    let (name, rest) =
+        declaration.split_once(':').context("expected `<name>: <type> [= <default>]`")?;
+    let name = name.trim();
+    validate_name(name)?;
+
+    let (ty, default) = match rest.split_once('=') {
+        Some((ty, default)) => (ty, Some(default.trim())),
+        None => (rest, None),
+    };
+    let ty = ty.trim().parse::<ParamType>().with_context(|| {
+        format!(
+            "unknown type {:?}, expected int, float, bool or str",
+            ty.trim()
+        )
+    })?;
+    let default = default
+        .map(|default| {
+            ty.normalize(default)
+                .with_context(|| format!("default value {default:?} is not a valid {ty}"))
+        })
+        .transpose()?;
+
+    Ok(ScriptParam {
+        name: name.to_string(),
+        ty,
+        default,
+    })
+}
+
+fn validate_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        bail!("{name:?} is not a valid Python identifier");
+    }
+    if name.starts_with(RESERVED_PREFIX) {
+        bail!(
+            "{name} uses the {RESERVED_PREFIX} prefix, which is reserved for Av1an's own variables"
+        );
+    }
+
+    Ok(())
+}
+
+/// Validate the `--vspipe-args` passed to a VapourSynth script.
+///
+/// Every argument must be a `key=value` pair. If the script declares any
+/// parameters, every key must be one of them, the value must match the
+/// declared type, and declared parameters that are not passed get their
+/// default value. Scripts that declare no parameters accept any keys.
+///
+/// Returns the arguments to pass to vspipe.
+pub(crate) fn resolve_vspipe_args(
+    script_text: &str,
+    args: Vec<String>,
+) -> anyhow::Result<Vec<String>> {
+    let declared = parse_declared_params(script_text)?;
+
+    let mut resolved = Vec::with_capacity(args.len());
+    let mut passed: HashSet<String> = HashSet::new();
+    for arg in args {
+        let Some((key, value)) = arg.split_once('=') else {
+            bail!("vspipe argument {arg:?} is not of the form key=value");
+        };
+        validate_name(key).with_context(|| format!("Invalid vspipe argument {arg:?}"))?;
+        if !passed.insert(key.to_string()) {
+            bail!("vspipe argument {key} is passed more than once");
+        }
+
+        if declared.is_empty() {
+            resolved.push(arg);
+            continue;
+        }
+        let Some(param) = declared.iter().find(|param| param.name == key) else {
+            bail!(
+                "The script does not declare a parameter named {key}, declared parameters are: {}",
+                declared.iter().map(|param| param.name.as_str()).collect::<Vec<_>>().join(", ")
+            );
+        };
+        let value = param
+            .ty
+            .normalize(value)
+            .with_context(|| format!("Invalid value {value:?} for {} parameter {key}", param.ty))?;
+        resolved.push(format!("{key}={value}"));
+    }
+
+    for param in declared.iter().filter(|param| !passed.contains(&param.name)) {
+        let Some(default) = &param.default else {
+            bail!(
+                "The script requires the {} parameter {}, pass it with --vspipe-args \
+                 \"{}=<value>\"",
+                param.ty,
+                param.name,
+                param.name
+            );
+        };
+        resolved.push(format!("{}={default}", param.name));
+    }
+
+    Ok(resolved)
+}
+
+/// Variables that are passed to the VapourSynth script of every chunk, so
+/// that the script can adapt its filtering to the frames being encoded.
+///
+/// `AV1AN_CHUNK_END` is exclusive.
+pub(crate) fn chunk_vspipe_args(
+    index: usize,
+    start_frame: usize,
+    end_frame: usize,
+    temp: &Path,
+) -> [String; 4] {
+    [
+        format!("{RESERVED_PREFIX}CHUNK_INDEX={index}"),
+        format!("{RESERVED_PREFIX}CHUNK_START={start_frame}"),
+        format!("{RESERVED_PREFIX}CHUNK_END={end_frame}"),
+        format!("{RESERVED_PREFIX}TEMP={}", temp.display()),
+    ]
+}
+
+/// Variable with the absolute path of a VapourSynth script that is passed as
+/// the input, which is set wherever Av1an evaluates the script
+pub(crate) fn script_vspipe_arg(script: &Path) -> anyhow::Result<String> {
+    let script = absolute(script)
+        .with_context(|| format!("Failed to resolve the path of {}", script.display()))?;
+    Ok(format!("{RESERVED_PREFIX}SCRIPT={}", script.display()))
+}
+
+/// Python run before a script that is evaluated from memory, so that it
+/// behaves as if it was evaluated from the file `script`: `__file__` is set,
+/// and the directory of the script is searched first for imports.
+///
+/// The working directory is not changed, as it is shared by all threads of
+/// Av1an. The prelude is a single line, so that only the line numbers of
+/// errors shift.
+pub(crate) fn script_prelude(script: &Path) -> anyhow::Result<String> {
+    let script = absolute(script)
+        .with_context(|| format!("Failed to resolve the path of {}", script.display()))?;
+    let dir = script.parent().unwrap_or(&script);
+    // JSON strings are valid Python string literals
+    Ok(format!(
+        "import sys as _av1an_sys; __file__ = {}; _av1an_sys.path.insert(0, {}); del _av1an_sys\n",
+        serde_json::to_string(&script).context("the path of the script is not valid UTF-8")?,
+        serde_json::to_string(dir).context("the path of the script is not valid UTF-8")?
+    ))
+}
@@ -0,0 +1,96 @@
+use super::*;
+use crate::into_vec;
+
+const SCRIPT: &str = r#"import vapoursynth as vs
+# av1an-param: strength: float = 1.5
+#av1an-param: denoise: bool
+# av1an-param: label: str = "film"
+core = vs.core
+"#;
+
+#[test]
+fn parse_declared_params_script() {
+    let params = parse_declared_params(SCRIPT).expect("declarations should be valid");
+    assert_eq!(params, vec![
+        ScriptParam {
+            name:    "strength".to_string(),
+            ty:      ParamType::Float,
+            default: Some("1.5".to_string()),
+        },
+        ScriptParam {
+            name:    "denoise".to_string(),
+            ty:      ParamType::Bool,
+            default: None,
+        },
+        ScriptParam {
+            name:    "label".to_string(),
+            ty:      ParamType::Str,
+            default: Some("\"film\"".to_string()),
+        },
+    ]);
+}
+
+#[test]
+fn parse_declared_params_invalid() {
+    assert!(parse_declared_params("# av1an-param: x: complex").is_err());
+    assert!(parse_declared_params("# av1an-param: x: int = one").is_err());
+    assert!(parse_declared_params("# av1an-param: 1x: int").is_err());
+    assert!(parse_declared_params("# av1an-param: AV1AN_X: int").is_err());
+    assert!(parse_declared_params("# av1an-param: x: int\n# av1an-param: x: str").is_err());
+}
+
+#[test]
+fn resolve_vspipe_args_declared() {
+    let args: Vec<String> = into_vec!["denoise=yes", "strength=2"];
+    let resolved = resolve_vspipe_args(SCRIPT, args).expect("arguments should be valid");
+    let expected: Vec<String> = into_vec!["denoise=1", "strength=2", "label=\"film\""];
+    assert_eq!(resolved, expected);
+}
+
+#[test]
+fn resolve_vspipe_args_rejects_invalid() {
+    let missing: Vec<String> = into_vec!["strength=2"];
+    assert!(resolve_vspipe_args(SCRIPT, missing).is_err());
+    let undeclared: Vec<String> = into_vec!["denoise=1", "sharpen=1"];
+    assert!(resolve_vspipe_args(SCRIPT, undeclared).is_err());
+    let wrong_type: Vec<String> = into_vec!["denoise=1", "strength=high"];
+    assert!(resolve_vspipe_args(SCRIPT, wrong_type).is_err());
+    let duplicate: Vec<String> = into_vec!["denoise=1", "denoise=0"];
+    assert!(resolve_vspipe_args(SCRIPT, duplicate).is_err());
+}
+
+#[test]
+fn resolve_vspipe_args_undeclared_script() {
+    let args: Vec<String> = into_vec!["message=fluffy kittens", "expr=a=b"];
+    assert_eq!(
+        resolve_vspipe_args("core = vs.core", args.clone()).expect("arguments should be valid"),
+        args
+    );
+
+    let malformed: Vec<String> = into_vec!["message"];
+    assert!(resolve_vspipe_args("core = vs.core", malformed).is_err());
+    let reserved: Vec<String> = into_vec!["AV1AN_CHUNK_START=0"];
+    assert!(resolve_vspipe_args("core = vs.core", reserved).is_err());
+}
+
+#[test]
+fn script_variables() {
+    let script = Path::new("scripts").join("filter.vpy");
+    let absolute_script = absolute(&script).expect("should resolve the path");
+    assert_eq!(
+        script_vspipe_arg(&script).expect("should resolve the path"),
+        format!("AV1AN_SCRIPT={}", absolute_script.display())
+    );
+
+    let prelude = script_prelude(&script).expect("should resolve the path");
+    assert_eq!(prelude.lines().count(), 1);
+    assert!(prelude.contains(&format!(
+        "__file__ = {};",
+        serde_json::to_string(&absolute_script).expect("should serialize")
+    )));
+    assert!(prelude.contains(&format!(
+        "path.insert(0, {});",
+        serde_json::to_string(absolute_script.parent().expect("should have a parent"))
+            .expect("should serialize")
+    )));
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufWriter, PipeReader, PipeWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use tracing::debug;
+use vapoursynth::{
+    format::{ColorFamily, SampleType},
+    prelude::*,
+};
+
+use crate::{vapoursynth::eval_source, Input};
+
+/// Frames of a chunk that a [`VsServer`] writes to its pipe
+struct Request {
+    start:  usize,
+    /// Inclusive, like the `-e` argument of vspipe
+    end:    usize,
+    output: PipeWriter,
+    errors: PipeWriter,
+}
+
+/// Persistent VapourSynth environment of one worker.
+///
+/// Evaluates the script of the worker's first chunk once, then serves the
+/// frames of every following chunk from the same environment, so that short
+/// chunks don't pay for evaluating the script and opening the source index
+/// again like a new vspipe process does.
+#[derive(Debug)]
+pub(crate) struct VsServer {
+    script:   PathBuf,
+    requests: mpsc::Sender<Request>,
+}
+
+impl VsServer {
+    /// Starts a server for the script of `input`, which must be an
+    /// [`Input::VapourSynth`]
+    pub(crate) fn spawn(input: Input) -> anyhow::Result<Self> {
+        ensure!(
+            input.is_vapoursynth(),
+            "vspipe server needs a VapourSynth input"
+        );
+        let script = input.as_path().to_path_buf();
+        let (requests, receiver) = mpsc::channel::<Request>();
+        thread::Builder::new()
+            .name("vs-server".to_owned())
+            .spawn(move || {
+                let environment = evaluate(&input);
+                for mut request in receiver {
+                    let result = environment.as_ref().map_err(|e| anyhow!("{e:#}")).and_then(
+                        |environment| {
+                            serve(environment, request.start, request.end, request.output)
+                        },
+                    );
+                    if let Err(e) = result {
+                        writeln!(request.errors, "Error: {e:#}").ok();
+                    }
+                }
+            })
+            .context("Failed to start the vspipe server")?;
+        debug!("started a vspipe server for {}", script.display());
+        Ok(Self {
+            script,
+            requests,
+        })
+    }
+
+    /// Script the server evaluated
+    pub(crate) fn script(&self) -> &Path {
+        &self.script
+    }
+
+    /// Requests the frames `start..=end` as y4m. Returns the pipe the frames
+    /// are written to and the pipe errors are written to, which both close
+    /// once the request is done.
+    pub(crate) fn request(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> anyhow::Result<(PipeReader, PipeReader)> {
+        let (frames, output) = io::pipe()?;
+        let (errors_reader, errors) = io::pipe()?;
+        self.requests
+            .send(Request {
+                start,
+                end,
+                output,
+                errors,
+            })
+            .map_err(|_| anyhow!("vspipe server stopped"))?;
+        Ok((frames, errors_reader))
+    }
+}
+
+fn evaluate(input: &Input) -> anyhow::Result<Environment> {
+    let mut environment =
+        Environment::new().context("Failed to create a VapourSynth environment")?;
+    let variables = input.as_vspipe_args_map()?;
+    if environment.set_variables(&variables).is_err() {
+        bail!("Failed to set vspipe arguments");
+    }
+    eval_source(&mut environment, input)
+        .with_context(|| format!("Failed to evaluate {}", input.as_path().display()))?;
+    Ok(environment)
+}
+
+/// Name of the y4m colorspace of a format, the same one vspipe writes
+fn y4m_colorspace(
+    color_family: ColorFamily,
+    sample_type: SampleType,
+    bits: u8,
+    sub_sampling: (u8, u8),
+) -> anyhow::Result<String> {
+    ensure!(
+        sample_type == SampleType::Integer,
+        "y4m output does not support float samples"
+    );
+    let colorspace = match color_family {
+        ColorFamily::Gray => {
+            return Ok(if bits > 8 {
+                format!("mono{bits}")
+            } else {
+                "mono".to_owned()
+            });
+        },
+        ColorFamily::YUV => match sub_sampling {
+            (1, 1) => "420",
+            (1, 0) => "422",
+            (0, 0) => "444",
+            (2, 2) => "410",
+            (2, 0) => "411",
+            (0, 1) => "440",
+            (w, h) => bail!("y4m output does not support a chroma subsampling of {w}x{h}"),
+        },
+        _ => bail!("y4m output only supports YUV and gray clips"),
+    };
+    Ok(if bits > 8 {
+        format!("{colorspace}p{bits}")
+    } else {
+        colorspace.to_owned()
+    })
+}
+
+fn serve(
+    environment: &Environment,
+    start: usize,
+    end: usize,
+    output: PipeWriter,
+) -> anyhow::Result<()> {
+    let (node, _) = environment.get_output(0)?;
+    let info = node.info();
+    ensure!(
+        end < info.num_frames,
+        "frame {end} is past the end of the clip, which has {} frames",
+        info.num_frames
+    );
+    let Property::Constant(resolution) = info.resolution else {
+        bail!("Cannot output clips with varying dimensions");
+    };
+    let Property::Constant(framerate) = info.framerate else {
+        bail!("Cannot output clips with varying framerate");
+    };
+    let format = info.format;
+    let colorspace = y4m_colorspace(
+        format.color_family(),
+        format.sample_type(),
+        format.bits_per_sample(),
+        (format.sub_sampling_w(), format.sub_sampling_h()),
+    )?;
+
+    let mut output = BufWriter::new(output);
+    writeln!(
+        output,
+        "YUV4MPEG2 C{colorspace} W{} H{} F{}:{} Ip A0:0 XLENGTH={}",
+        resolution.width,
+        resolution.height,
+        framerate.numerator,
+        framerate.denominator,
+        end + 1 - start
+    )?;
+
+    // keep as many frames in flight as the core has threads, like vspipe
+    let in_flight = environment.get_core()?.info().num_threads.max(1);
+    let (sender, receiver) = mpsc::channel();
+    let mut requested = start;
+    let request_next = |requested: &mut usize| {
+        if *requested <= end {
+            let sender = sender.clone();
+            node.get_frame_async(*requested, move |frame, n, _| {
+                sender.send((n, frame.map_err(|e| e.to_string()))).ok();
+            });
+            *requested += 1;
+        }
+    };
+    for _ in 0..in_flight {
+        request_next(&mut requested);
+    }
+
+    let mut ready = BTreeMap::new();
+    for n in start..=end {
+        let frame = loop {
+            if let Some(frame) = ready.remove(&n) {
+                break frame;
+            }
+            let (received, frame) = receiver.recv().context("VapourSynth stopped")?;
+            ready.insert(received, frame);
+        };
+        let frame = frame.map_err(|e| anyhow!("Failed to get frame {n}: {e}"))?;
+        request_next(&mut requested);
+
+        output.write_all(b"FRAME\n")?;
+        for plane in 0..format.plane_count() {
+            for row in 0..frame.height(plane) {
+                output.write_all(frame.data_row(plane, row))?;
+            }
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn y4m_colorspaces() {
+        let colorspace = |family, bits, sub_sampling| {
+            y4m_colorspace(family, SampleType::Integer, bits, sub_sampling)
+                .expect("format should be supported")
+        };
+        assert_eq!(colorspace(ColorFamily::YUV, 8, (1, 1)), "420");
+        assert_eq!(colorspace(ColorFamily::YUV, 10, (1, 1)), "420p10");
+        assert_eq!(colorspace(ColorFamily::YUV, 12, (0, 0)), "444p12");
+        assert_eq!(colorspace(ColorFamily::Gray, 8, (0, 0)), "mono");
+        assert_eq!(colorspace(ColorFamily::Gray, 16, (0, 0)), "mono16");
+        assert!(y4m_colorspace(ColorFamily::RGB, SampleType::Integer, 8, (0, 0)).is_err());
+        assert!(y4m_colorspace(ColorFamily::YUV, SampleType::Float, 32, (1, 1)).is_err());
+    }
+}
@@ -1,40 +1,365 @@
-use std::fs;
+use std::{fs, io, mem, ops::Range, path::Path, sync::OnceLock};
 
-use anyhow::bail;
+use anyhow::{anyhow, bail, Context};
+use itertools::Itertools;
+use tracing::warn;
 
 use crate::{
+    concat::{chunk_param_mismatches, probe_chunk, ChunkParams},
+    ffmpeg::{get_keyframes, FFPixelFormat},
     metrics::vmaf::validate_libvmaf,
-    scenes::Scene,
+    scenes::{Scene, ZoneOptions},
     EncodeArgs,
+    Encoder,
     TargetMetric,
     TargetQuality,
 };
 
-pub(crate) fn parse_zones(args: &EncodeArgs, frames: usize) -> anyhow::Result<Vec<Scene>> {
-    let mut zones = Vec::new();
+/// A zone that only offsets the quantizer of its frames. On encoders that
+/// support frame-level zones, it does not split the scenes it overlaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FrameZone {
+    pub start_frame: usize,
+    /// Exclusive, like [`Scene::end_frame`]
+    pub end_frame:   usize,
+    /// Negative offsets increase the quality
+    pub qp_offset:   i32,
+}
+
+impl FrameZone {
+    /// Parses a zone line of the form `start end qp-offset <offset>`, or
+    /// returns `None` if the line is a regular zone
+    fn parse(line: &str, frames: usize) -> anyhow::Result<Option<Self>> {
+        let mut fields = line.split_whitespace();
+        let (Some(start), Some(end), Some("qp-offset")) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Ok(None);
+        };
+        let (Some(offset), None) = (fields.next(), fields.next()) else {
+            bail!(
+                "qp-offset zones take a single offset and no encoder parameters, e.g. `0 100 \
+                 qp-offset -4`"
+            );
+        };
+
+        let range = parse_zone_range(start, end, frames)?;
+        let qp_offset = offset.parse().with_context(|| format!("Invalid qp offset: {offset}"))?;
+
+        Ok(Some(Self {
+            start_frame: range.start,
+            end_frame: range.end,
+            qp_offset,
+        }))
+    }
+
+    /// Turns the zone into a regular zone with an offset quantizer, for
+    /// encoders that do not support frame-level zones
+    fn into_scene(self, args: &EncodeArgs) -> anyhow::Result<Scene> {
+        let encoder = args.encoder;
+        let q = encoder.get_q(&args.video_params).with_context(|| {
+            format!(
+                "qp-offset zones change the quantizer of {encoder}, but --video-params does not \
+                 set one"
+            )
+        })?;
+        // the quantizer of rav1e ranges up to 255 instead of 63
+        let step = if encoder == Encoder::rav1e { 4.0 } else { 1.0 };
+        #[expect(clippy::cast_precision_loss, reason = "qp offsets are small")]
+        let q = (self.qp_offset as f32).mul_add(step, q).clamp(0.0, encoder.get_max_q());
+
+        let mut overrides = ZoneOptions::from_args(args);
+        overrides.video_params = encoder.man_command(overrides.video_params, q);
+        Ok(Scene {
+            start_frame:    self.start_frame,
+            end_frame:      self.end_frame,
+            zone_overrides: Some(overrides),
+        })
+    }
+}
+
+/// Parses the start and end frame of a zone, where an end of `-1` is the end
+/// of the video
+fn parse_zone_range(start: &str, end: &str, frames: usize) -> anyhow::Result<Range<usize>> {
+    let start_frame: usize =
+        start.parse().with_context(|| format!("Invalid zone start frame: {start}"))?;
+    let end_frame = if end == "-1" {
+        frames
+    } else {
+        end.parse().with_context(|| format!("Invalid zone end frame: {end}"))?
+    };
+    if start_frame >= end_frame {
+        bail!("Start frame must be earlier than the end frame");
+    }
+    if start_frame >= frames || end_frame > frames {
+        bail!("Start and end frames must not be past the end of the video");
+    }
+    Ok(start_frame..end_frame)
+}
+
+/// Parses a zone line of the form `start end no-split`, or returns `None` if
+/// the line is another zone
+fn parse_no_split(line: &str, frames: usize) -> anyhow::Result<Option<Range<usize>>> {
+    let mut fields = line.split_whitespace();
+    let (Some(start), Some(end), Some("no-split")) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Ok(None);
+    };
+    if fields.next().is_some() {
+        bail!("no-split zones take no encoder parameters, e.g. `1200 1260 no-split`");
+    }
+    parse_zone_range(start, end, frames).map(Some)
+}
+
+/// Returns whether a scene cut at `frame` would split one of the `no_split`
+/// ranges
+pub(crate) fn splits_no_split(no_split: &[Range<usize>], frame: usize) -> bool {
+    no_split.iter().any(|range| range.start < frame && frame < range.end)
+}
+
+/// Joins the scenes that start within one of the `no_split` ranges to the
+/// scene before them, which keeps its settings
+pub(crate) fn join_no_split(scenes: Vec<Scene>, no_split: &[Range<usize>]) -> Vec<Scene> {
+    let mut joined: Vec<Scene> = Vec::with_capacity(scenes.len());
+    for scene in scenes {
+        match joined.last_mut() {
+            Some(last) if splits_no_split(no_split, scene.start_frame) => {
+                last.end_frame = scene.end_frame;
+            },
+            _ => joined.push(scene),
+        }
+    }
+    joined
+}
+
+/// The zones of a zones file
+#[derive(Debug, Default)]
+pub(crate) struct Zones {
+    pub scenes:      Vec<Scene>,
+    /// Only set if the encoder supports frame-level zones, otherwise they are
+    /// part of `scenes`
+    pub frame_zones: Vec<FrameZone>,
+    /// Frames that no scene cut is placed within, in order and with
+    /// overlapping ranges merged
+    pub no_split:    Vec<Range<usize>>,
+}
+
+impl Zones {
+    /// Frames covered by any zone, in order, with the ranges of adjacent zones
+    /// merged
+    pub fn frame_ranges(&self) -> Vec<Range<usize>> {
+        let mut zones = self
+            .scenes
+            .iter()
+            .map(|zone| zone.start_frame..zone.end_frame)
+            .chain(self.frame_zones.iter().map(|zone| zone.start_frame..zone.end_frame))
+            .collect::<Vec<_>>();
+        zones.sort_unstable_by_key(|range| range.start);
+        let mut ranges: Vec<Range<usize>> = Vec::with_capacity(zones.len());
+        for zone in zones {
+            match ranges.last_mut() {
+                Some(last) if zone.start <= last.end => last.end = last.end.max(zone.end),
+                _ => ranges.push(zone),
+            }
+        }
+        ranges
+    }
+}
+
+/// Zones read from stdin with `--zones -`, as stdin can only be read once
+static STDIN_ZONES: OnceLock<Result<String, String>> = OnceLock::new();
+
+/// Whether `path`, the path of `--zones`, reads the zones from stdin
+pub(crate) fn reads_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Reads the zones of `path`, or of stdin if it is `-`
+fn read_zones(path: &Path) -> anyhow::Result<String> {
+    if reads_stdin(path) {
+        return STDIN_ZONES
+            .get_or_init(|| io::read_to_string(io::stdin()).map_err(|e| e.to_string()))
+            .clone()
+            .map_err(|e| anyhow!("Failed to read the zones from stdin: {e}"));
+    }
+    fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the zones file {}", path.display()))
+}
+
+/// Returns the first two of `zones`, ranges of frames by the number of the
+/// line they were read from, that overlap
+fn overlapping_lines(zones: &mut [(usize, Range<usize>)]) -> Option<(usize, usize)> {
+    zones.sort_unstable_by_key(|(_, range)| range.start);
+    zones
+        .windows(2)
+        .find(|pair| pair[0].1.end > pair[1].1.start)
+        .map(|pair| (pair[0].0.min(pair[1].0), pair[0].0.max(pair[1].0)))
+}
+
+pub(crate) fn parse_zones(args: &EncodeArgs, frames: usize) -> anyhow::Result<Zones> {
+    let mut zones = Zones::default();
     if let Some(ref zones_file) = args.zones {
-        let input = fs::read_to_string(zones_file)?;
-        for zone_line in input.lines().map(str::trim).filter(|line| !line.is_empty()) {
-            zones.push(Scene::parse_from_zone(zone_line, args, frames)?);
+        let source = if reads_stdin(zones_file) {
+            "stdin".to_string()
+        } else {
+            zones_file.display().to_string()
+        };
+        let input = read_zones(zones_file)?;
+        // frames of the zones and qp-offset zones by their line number
+        let mut scene_lines = Vec::new();
+        let mut frame_zone_lines = Vec::new();
+        for (number, zone_line) in (1..).zip(input.lines()) {
+            let zone_line = zone_line.trim();
+            if zone_line.is_empty() {
+                continue;
+            }
+            let mut parse = || -> anyhow::Result<()> {
+                if let Some(range) = parse_no_split(zone_line, frames)? {
+                    zones.no_split.push(range);
+                } else if let Some(frame_zone) = FrameZone::parse(zone_line, frames)? {
+                    frame_zone_lines.push((number, frame_zone.start_frame..frame_zone.end_frame));
+                    zones.frame_zones.push(frame_zone);
+                } else {
+                    let scene = Scene::parse_from_zone(zone_line, args, frames)?;
+                    scene_lines.push((number, scene.start_frame..scene.end_frame));
+                    zones.scenes.push(scene);
+                }
+                Ok(())
+            };
+            parse().with_context(|| format!("zone on line {number} of {source}"))?;
+        }
+
+        if let Some((first, second)) = overlapping_lines(&mut frame_zone_lines) {
+            bail!("The qp-offset zones on lines {first} and {second} of {source} overlap");
         }
-        zones.sort_unstable_by_key(|zone| zone.start_frame);
-        for i in 0..zones.len() - 1 {
-            let current_zone = &zones[i];
-            let next_zone = &zones[i + 1];
-            if current_zone.end_frame > next_zone.start_frame {
-                bail!("Zones file contains overlapping zones");
+        zones.frame_zones.sort_unstable_by_key(|zone| zone.start_frame);
+        if !zones.frame_zones.is_empty() {
+            if args.encoder.supports_frame_zones() {
+                if args.video_params.iter().any(|param| param == "--zones") {
+                    bail!("qp-offset zones cannot be used together with --zones in --video-params");
+                }
+            } else {
+                warn!(
+                    "{} does not support frame-level zones, qp-offset zones split the scenes they \
+                     overlap instead",
+                    args.encoder
+                );
+                for frame_zone in mem::take(&mut zones.frame_zones) {
+                    zones.scenes.push(frame_zone.into_scene(args)?);
+                }
+                scene_lines.append(&mut frame_zone_lines);
+            }
+        }
+
+        if let Some((first, second)) = overlapping_lines(&mut scene_lines) {
+            bail!("The zones on lines {first} and {second} of {source} overlap");
+        }
+        zones.scenes.sort_unstable_by_key(|zone| zone.start_frame);
+
+        zones.no_split.sort_unstable_by_key(|range| range.start);
+        zones.no_split = zones.no_split.into_iter().fold(Vec::new(), |mut merged, range| {
+            match merged.last_mut() {
+                Some(last) if range.start < last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+            merged
+        });
+        for zone in &zones.scenes {
+            for frame in [zone.start_frame, zone.end_frame] {
+                if splits_no_split(&zones.no_split, frame) {
+                    bail!(
+                        "The zone {}..{} starts or ends within a no-split zone, which its scene \
+                         cut would split",
+                        zone.start_frame,
+                        zone.end_frame
+                    );
+                }
             }
         }
     }
     Ok(zones)
 }
 
+/// Returns the value of the `--zones` parameter of x264 and x265 for the frame
+/// zones that overlap the frames `start..end` of a chunk, relative to the
+/// chunk
+fn frame_zones_param(frame_zones: &[FrameZone], start: usize, end: usize) -> Option<String> {
+    let param = frame_zones
+        .iter()
+        .filter(|zone| zone.start_frame < end && zone.end_frame > start)
+        .map(|zone| {
+            let first = zone.start_frame.max(start) - start;
+            // the end frame of x264 and x265 zones is inclusive
+            let last = zone.end_frame.min(end) - 1 - start;
+            // 6 steps of the quantizer halve or double the bitrate
+            let factor = (-f64::from(zone.qp_offset) / 6.0).exp2();
+            format!("{first},{last},b={factor:.3}")
+        })
+        .join("/");
+    (!param.is_empty()).then_some(param)
+}
+
+/// Adds the frame zones to the encoder parameters of every scene they overlap.
+/// Scenes that a zone or the hybrid split encodes with another encoder are
+/// left as they are, as the frame zones were only checked against
+/// [`EncodeArgs::encoder`].
+pub(crate) fn apply_frame_zones(
+    scenes: &mut [Scene],
+    frame_zones: &[FrameZone],
+    args: &EncodeArgs,
+) {
+    let mut skipped = 0;
+    for scene in scenes.iter_mut().filter(|scene| !scene.is_passthrough()) {
+        let Some(param) = frame_zones_param(frame_zones, scene.start_frame, scene.end_frame) else {
+            continue;
+        };
+        if scene.zone_overrides.as_ref().is_some_and(|ovr| {
+            ovr.encoder != args.encoder
+                || !ovr.encoder.supports_frame_zones()
+                || ovr.video_params.iter().any(|param| param == "--zones")
+        }) {
+            skipped += 1;
+            continue;
+        }
+        let overrides = scene.zone_overrides.get_or_insert_with(|| ZoneOptions::from_args(args));
+        overrides.video_params.extend(["--zones".to_string(), param]);
+    }
+    if skipped > 0 {
+        warn!(
+            "qp-offset zones are not applied to {skipped} scenes encoded with another encoder or \
+             with their own --zones"
+        );
+    }
+}
+
+/// Returns the parts of `scenes` within `ranges`, which leaves out every frame
+/// outside of them
+pub(crate) fn scenes_in_ranges(scenes: &[Scene], ranges: &[Range<usize>]) -> Vec<Scene> {
+    scenes
+        .iter()
+        .flat_map(|scene| {
+            ranges
+                .iter()
+                .filter(|range| range.start < scene.end_frame && range.end > scene.start_frame)
+                .map(|range| Scene {
+                    start_frame:    range.start.max(scene.start_frame),
+                    end_frame:      range.end.min(scene.end_frame),
+                    zone_overrides: scene.zone_overrides.clone(),
+                })
+        })
+        .collect()
+}
+
 pub(crate) fn validate_zones(args: &EncodeArgs, zones: &[Scene]) -> anyhow::Result<()> {
     if zones.is_empty() {
         // No zones to validate
         return Ok(());
     }
 
+    if zones.iter().any(Scene::is_passthrough) {
+        validate_passthrough_zones(args, zones)?;
+    }
+
     let tq_used_and = |condition: &dyn Fn(&TargetQuality) -> bool| {
         zones.iter().any(|zone| {
             zone.zone_overrides
@@ -50,10 +375,6 @@ pub(crate) fn validate_zones(args: &EncodeArgs, zones: &[Scene]) -> anyhow::Resu
         validate_libvmaf()?;
     }
 
-    if tq_used_and_metric_is(TargetMetric::SSIMULACRA2) {
-        args.validate_ssimulacra2()?;
-    }
-
     // Using butteraugli-INF, validate butteraugli-INF
     if tq_used_and_metric_is(TargetMetric::ButteraugliINF) {
         args.validate_butteraugli_inf()?;
@@ -84,3 +405,226 @@ pub(crate) fn validate_zones(args: &EncodeArgs, zones: &[Scene]) -> anyhow::Resu
 
     Ok(())
 }
+
+/// Passthrough zones copy the source bitstream, so the source must already be
+/// in the output format, and each zone must start and end on a keyframe
+fn validate_passthrough_zones(args: &EncodeArgs, zones: &[Scene]) -> anyhow::Result<()> {
+    if !args.input.is_video() {
+        bail!("Passthrough zones require a video input, not a VapourSynth script");
+    }
+    if !args.ffmpeg_filter_args.is_empty() {
+        bail!(
+            "Passthrough zones cannot be used with --ffmpeg, as the filters would not apply to \
+             them"
+        );
+    }
+    if args.deband.is_some() {
+        bail!("Passthrough zones cannot be used with --deband, as it would not apply to them");
+    }
+    if args.burn_subs.is_some() {
+        bail!(
+            "Passthrough zones cannot be used with --burn-subs, as the subtitles would not be \
+             burned into them"
+        );
+    }
+    if args.output_fps.is_some() {
+        bail!(
+            "Passthrough zones cannot be used with --output-fps, as they keep the frame rate of \
+             the source"
+        );
+    }
+
+    let source = args.input.as_path();
+    let params = probe_chunk(source)?;
+    let mismatches = passthrough_mismatches(&params, args.encoder, args.output_pix_format.format)?;
+    if !mismatches.is_empty() {
+        bail!(
+            "Passthrough zones require the source to match the encoder output, but the source {}",
+            mismatches.join(", ")
+        );
+    }
+
+    let frames = args.input.clip_info()?.num_frames;
+    let keyframes = get_keyframes(source)?;
+    for zone in zones.iter().filter(|zone| zone.is_passthrough()) {
+        let unaligned: Vec<usize> = [zone.start_frame, zone.end_frame]
+            .into_iter()
+            .filter(|&frame| frame < frames && keyframes.binary_search(&frame).is_err())
+            .collect();
+        if !unaligned.is_empty() {
+            bail!(
+                "Passthrough zone {}-{} must start and end on keyframes of the source, but frames \
+                 {unaligned:?} are not keyframes",
+                zone.start_frame,
+                zone.end_frame
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns how the video stream of the source, `params`, differs from the
+/// chunks `encoder` produces in `output_format`. The encoded chunks keep the
+/// resolution and colors of the source, as filters are rejected with
+/// passthrough zones.
+fn passthrough_mismatches(
+    params: &ChunkParams,
+    encoder: Encoder,
+    output_format: FFPixelFormat,
+) -> anyhow::Result<Vec<String>> {
+    let bit_depth = encoder.get_format_bit_depth(output_format)?;
+    let encoded = ChunkParams {
+        pix_fmt: Some(output_format.to_pix_fmt_string().to_string()),
+        ..params.clone()
+    };
+    Ok(chunk_param_mismatches(params, &encoded, encoder, bit_depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_zones_param_is_relative_to_chunk() {
+        let frame_zones = [
+            FrameZone {
+                start_frame: 10,
+                end_frame:   20,
+                qp_offset:   -6,
+            },
+            FrameZone {
+                start_frame: 90,
+                end_frame:   150,
+                qp_offset:   6,
+            },
+        ];
+        assert_eq!(
+            frame_zones_param(&frame_zones, 0, 100).as_deref(),
+            Some("10,19,b=2.000/90,99,b=0.500")
+        );
+        assert_eq!(
+            frame_zones_param(&frame_zones, 100, 200).as_deref(),
+            Some("0,49,b=0.500")
+        );
+        assert_eq!(frame_zones_param(&frame_zones, 20, 90), None);
+    }
+
+    #[test]
+    fn overlapping_zones_name_their_lines() {
+        assert_eq!(overlapping_lines(&mut [(1, 0..10), (3, 10..20)]), None);
+        assert_eq!(
+            overlapping_lines(&mut [(2, 50..60), (4, 0..10), (7, 55..70)]),
+            Some((2, 7))
+        );
+        assert_eq!(overlapping_lines(&mut []), None);
+    }
+
+    #[test]
+    fn parse_frame_zone() {
+        assert_eq!(
+            FrameZone::parse("100 -1 qp-offset -4", 500).expect("should parse"),
+            Some(FrameZone {
+                start_frame: 100,
+                end_frame:   500,
+                qp_offset:   -4,
+            })
+        );
+        assert_eq!(
+            FrameZone::parse("100 200 x264 --crf 20", 500).expect("should parse"),
+            None
+        );
+        assert!(FrameZone::parse("100 200 qp-offset -4 --crf 20", 500).is_err());
+        assert!(FrameZone::parse("200 100 qp-offset 2", 500).is_err());
+    }
+
+    #[test]
+    #[expect(clippy::single_range_in_vec_init)]
+    fn scenes_in_zone_ranges() {
+        let scene = |start_frame, end_frame| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: None,
+        };
+        let zones = Zones {
+            scenes:      vec![scene(100, 200), scene(200, 250), scene(400, 450)],
+            frame_zones: vec![FrameZone {
+                start_frame: 440,
+                end_frame:   500,
+                qp_offset:   -2,
+            }],
+            no_split:    vec![120..180],
+        };
+        let ranges = zones.frame_ranges();
+        assert_eq!(ranges, [100..250, 400..500]);
+
+        let scenes = [scene(0, 150), scene(150, 300), scene(300, 600)];
+        let kept = scenes_in_ranges(&scenes, &ranges)
+            .iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, [(100, 150), (150, 250), (400, 500)]);
+    }
+
+    #[test]
+    #[expect(clippy::single_range_in_vec_init)]
+    fn no_split_ranges() {
+        assert_eq!(
+            parse_no_split("1200 -1 no-split", 1500).expect("should parse"),
+            Some(1200..1500)
+        );
+        assert_eq!(
+            parse_no_split("100 200 qp-offset -4", 500).expect("should parse"),
+            None
+        );
+        assert!(parse_no_split("100 200 no-split --crf 20", 500).is_err());
+
+        let scene = |start_frame, end_frame| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: None,
+        };
+        let scenes = [scene(0, 100), scene(100, 150), scene(150, 200), scene(200, 300)];
+        let joined = join_no_split(scenes.to_vec(), &[90..180])
+            .iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>();
+        assert_eq!(joined, [(0, 200), (200, 300)]);
+        assert!(!splits_no_split(&[90..180], 90));
+        assert!(!splits_no_split(&[90..180], 180));
+    }
+
+    #[test]
+    fn passthrough_source_must_match_the_output() {
+        let source = ChunkParams {
+            codec_name:      "av1".to_string(),
+            width:           1920,
+            height:          1080,
+            pix_fmt:         Some("yuv420p10le".to_string()),
+            color_range:     Some("tv".to_string()),
+            color_primaries: Some("bt709".to_string()),
+            color_transfer:  Some("bt709".to_string()),
+            color_space:     Some("bt709".to_string()),
+        };
+        let mismatches = |source: &ChunkParams, encoder, output_format| {
+            passthrough_mismatches(source, encoder, output_format).expect("should compare")
+        };
+        assert!(mismatches(&source, Encoder::svt_av1, FFPixelFormat::YUV420P10LE).is_empty());
+
+        assert_eq!(
+            mismatches(&source, Encoder::x265, FFPixelFormat::YUV420P10LE),
+            ["codec is av1, expected h265 output"]
+        );
+        let source = ChunkParams {
+            pix_fmt: Some("yuv420p".to_string()),
+            ..source
+        };
+        assert_eq!(
+            mismatches(&source, Encoder::svt_av1, FFPixelFormat::YUV420P10LE),
+            [
+                "bit depth is 8, expected 10",
+                "pixel format is yuv420p, other chunks are yuv420p10le"
+            ]
+        );
+    }
+}
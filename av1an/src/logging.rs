@@ -5,6 +5,7 @@ use std::{
     io::IsTerminal,
 };
 
+use av1an_core::COMMAND_LOG_TARGET;
 use once_cell::sync::OnceCell;
 use path_abs::{PathAbs, PathInfo};
 use tracing_appender::{
@@ -63,6 +64,14 @@ pub fn init_logging(
         file_enabled: true,
     });
 
+    // Configure the command lines of every chunk
+    module_configs.insert(COMMAND_LOG_TARGET, ModuleConfig {
+        console_level,
+        file_level,
+        console_enabled: true,
+        file_enabled: true,
+    });
+
     // Allow override through environment variables
     if let Ok(rust_log) = env::var("RUST_LOG") {
         for directive in rust_log.split(',') {
@@ -76,6 +85,12 @@ pub fn init_logging(
         }
     }
 
+    // The log file always has the command lines of every chunk, so that the
+    // failures of quiet runs can still be reproduced
+    if let Some(config) = module_configs.get_mut(COMMAND_LOG_TARGET) {
+        config.file_level = config.file_level.max(LevelFilter::DEBUG);
+    }
+
     // Create our filters
     let console_filter = {
         let mut filter = String::new();
@@ -2,37 +2,72 @@ use std::{
     fmt::Write as FmtWrite,
     io::{self, Write as IoWrite},
     panic,
-    path::{Path, PathBuf},
-    process::{self, exit},
+    path::{absolute, Path, PathBuf},
+    process::{self, exit, ExitCode},
     thread::available_parallelism,
+    time::Duration,
 };
 
 use anyhow::{anyhow, bail, ensure, Context};
 use av1an_core::{
-    ffmpeg::FFPixelFormat,
+    bench::{available_chunk_methods, bench_chunk_methods, recommend_chunk_method},
+    capabilities::Capabilities,
+    compare::{compare_encodes, CompareMetric, CompareOptions},
+    debug_bundle::bundle_debug,
+    determine_workers,
+    ffmpeg::{
+        self,
+        AudioNormalization,
+        BurnSubtitles,
+        CaptionMode,
+        FFPixelFormat,
+        IntermediateCodec,
+    },
     hash_path,
     into_vec,
+    parse_duration,
+    parse_trim_point,
     read_in_dir,
+    remote,
+    seconds_to_frames,
+    set_progress_interval,
+    temp_check::{choose_temp_dir, TempRelocation},
+    temp_registry::resolve_temp_dir,
     vapoursynth::{get_vapoursynth_plugins, CacheSource, VSZipVersion},
+    write_photon_noise_table,
     Av1anContext,
+    Av1anError,
+    ChunkCheck,
     ChunkMethod,
+    ChunkNames,
     ChunkOrdering,
     ConcatMethod,
+    DebandStrength,
     EncodeArgs,
     Encoder,
+    EncoderTier,
+    GeometryChanges,
+    GlobalConfig,
     Input,
     InputPixelFormat,
     InterpolationMethod,
+    NoiseTransfer,
+    OddDimensions,
+    OutputMetric,
     PixelFormat,
     PixelFormatConverter,
+    ScDecoder,
     ScenecutMethod,
     SplitMethod,
     TargetMetric,
     TargetQuality,
+    TransitionCut,
+    TrimPoint,
     Verbosity,
     VmafFeature,
+    VmafTonemap,
 };
-use clap::{value_parser, CommandFactory, Parser};
+use clap::{value_parser, Args, CommandFactory, Parser, Subcommand};
 use clap_complete::generate;
 use num_traits::cast::ToPrimitive;
 use once_cell::sync::OnceCell;
@@ -43,14 +78,21 @@ use crate::logging::{init_logging, DEFAULT_LOG_LEVEL};
 
 mod logging;
 
-fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     let orig_hook = panic::take_hook();
     // Catch panics in child threads
     panic::set_hook(Box::new(move |panic_info| {
         orig_hook(panic_info);
         process::exit(1);
     }));
-    run()
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            // the stage that failed, see `Av1anError::code`
+            ExitCode::from(Av1anError::code_of(&e))
+        },
+    }
 }
 
 // needs to be static, runtime allocated string to avoid evil hacks to
@@ -99,13 +141,17 @@ fn version() -> &'static str {
   rav1e   : {}
   x264    : {}
   x265    : {}
-  vpxenc  : {}",
+  vpxenc  : {}
+  av1_nvenc  : {}
+  hevc_nvenc : {}",
             Encoder::aom.version_text().as_deref().unwrap_or("Not found"),
             Encoder::svt_av1.version_text().as_deref().unwrap_or("Not found"),
             Encoder::rav1e.version_text().as_deref().unwrap_or("Not found"),
             Encoder::x264.version_text().as_deref().unwrap_or("Not found"),
             Encoder::x265.version_text().as_deref().unwrap_or("Not found"),
-            Encoder::vpx.version_text().as_deref().unwrap_or("Not found")
+            Encoder::vpx.version_text().as_deref().unwrap_or("Not found"),
+            Encoder::av1_nvenc.version_text().as_deref().unwrap_or("Not found"),
+            Encoder::hevc_nvenc.version_text().as_deref().unwrap_or("Not found")
         )
     }
 
@@ -176,12 +222,22 @@ fn version() -> &'static str {
 /// Cross-platform command-line AV1 / VP9 / HEVC / H264 encoding framework with
 /// per-scene quality encoding
 #[derive(Parser, Debug)]
-#[clap(name = "av1an", version = version())]
+#[clap(
+    name = "av1an",
+    version = version(),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct CliOpts {
+    #[clap(subcommand)]
+    pub command: Option<Commands>,
+
     /// Input file to encode
     ///
-    /// Can be a video or VapourSynth (.py, .vpy) script.
-    #[clap(short, required = true)]
+    /// Can be a video or VapourSynth (.py, .vpy) script. Videos can also be
+    /// http://, https:// or s3:// URLs, which are downloaded into
+    /// --input-cache before encoding.
+    #[clap(short, required_unless_present = "dump_config")]
     pub input: Vec<PathBuf>,
 
     /// Input proxy file for Scene Detection and Target Quality
@@ -194,17 +250,54 @@ pub struct CliOpts {
     #[clap(long)]
     pub proxy: Vec<PathBuf>,
 
+    /// Directory remote inputs and proxies are downloaded into
+    ///
+    /// If not specified, ~/.cache/av1an/inputs (or %LOCALAPPDATA%\av1an\inputs
+    /// on Windows) is used. Downloads are kept, so encoding the same URL again
+    /// reuses them.
+    #[clap(long, value_name = "DIR")]
+    pub input_cache: Option<PathBuf>,
+
+    /// FFmpeg protocol and demuxer options for reading the input and proxy
+    ///
+    /// Given to every ffmpeg and ffprobe process that reads the input or the
+    /// proxy, in front of it. Used for headers, cookies or decryption keys,
+    /// e.g. --ffmpeg-input-args "-headers 'Cookie: session=abc'" or
+    /// --ffmpeg-input-args "-decryption_key 00112233445566778899aabbccddeeff".
+    /// The VapourSynth chunk methods cannot be given these options.
+    #[clap(long, allow_hyphen_values = true)]
+    pub ffmpeg_input_args: Option<String>,
+
     /// Video output file
     #[clap(short)]
     pub output_file: Option<PathBuf>,
 
     /// Temporary directory to use
     ///
-    /// If not specified, the temporary directory name is a hash of the input
-    /// file name.
+    /// If not specified, every encode gets a temporary directory with a unique
+    /// name, so that several instances of Av1an never share one. The
+    /// directories are tracked in `.av1an-temp.json` next to them, which is
+    /// used to find the temporary directory of an encode with --resume.
     #[clap(long)]
     pub temp: Option<PathBuf>,
 
+    /// What to do when the temporary directory is read-only, nearly full or
+    /// on a network share
+    ///
+    /// The temporary directory is checked before encoding, instead of the
+    /// encode failing or slowing down once the chunks are written to it.
+    ///
+    /// auto - Move the temporary directory to the temporary directory of the
+    /// system, if that has none of these problems. With --keep, it is copied
+    /// back once the encode finished.
+    ///
+    /// never - Keep the temporary directory where it is, and only warn about
+    /// the problems. This is the default.
+    ///
+    /// The temporary directory is never moved with --shared-temp.
+    #[clap(long, default_value_t = TempRelocation::Never)]
+    pub relocate_temp: TempRelocation,
+
     /// Disable printing progress to the terminal
     #[clap(short, long, conflicts_with = "verbose")]
     pub quiet: bool,
@@ -213,6 +306,14 @@ pub struct CliOpts {
     #[clap(long)]
     pub verbose: bool,
 
+    /// Seconds between progress lines when stderr is not a terminal
+    ///
+    /// When the output is redirected to a file or a CI log, the progress bar
+    /// is replaced by a plain line with the encoded frames, fps, ETA and
+    /// chunks, which is printed every this many seconds.
+    #[clap(long, default_value_t = 30, value_parser = value_parser!(u64).range(1..))]
+    pub progress_interval: u64,
+
     /// Log file location
     ///
     /// If not specified, the log file location will be `./logs/av1an.log` and
@@ -220,6 +321,13 @@ pub struct CliOpts {
     #[clap(short, long)]
     pub log_file: Option<String>,
 
+    /// Set log level for the console (does not affect log file log level)
+    ///
+    /// Takes the same values as --log-level. If not specified, the log level
+    /// is warn with --quiet, and info otherwise.
+    #[clap(long, ignore_case = true)]
+    pub verbosity: Option<LevelFilter>,
+
     /// Set log level for log file (does not affect command-line log level)
     ///
     /// error: Designates very serious errors.
@@ -232,6 +340,9 @@ pub struct CliOpts {
     ///
     /// trace: Designates very low priority, often extremely verbose,
     /// information. Includes rav1e scenechange decision info.
+    ///
+    /// The command lines run for every chunk are always written to the log
+    /// file, even if the log level is lower than debug.
     #[clap(long, default_value_t = DEFAULT_LOG_LEVEL, ignore_case = true)]
     // "off" is also an allowed value for LevelFilter but we just disable the user from setting it
     pub log_level: LevelFilter,
@@ -244,10 +355,29 @@ pub struct CliOpts {
     #[clap(short, long)]
     pub resume: bool,
 
+    /// Share the temporary directory with Av1an instances on other machines
+    ///
+    /// Every instance encodes the same input into the same --temp directory
+    /// on shared storage, and the instances split the chunks between them.
+    /// Start the other instances once the first one is encoding. The last
+    /// instance to finish concatenates the output.
+    #[clap(long, requires = "temp")]
+    pub shared_temp: bool,
+
     /// Do not delete the temporary folder after encoding has finished
     #[clap(short, long)]
     pub keep: bool,
 
+    /// Write an HTML report of the encode next to the output
+    ///
+    /// The report is a single page with the bitrate of every chunk, the
+    /// distribution of the VMAF scores (with --vmaf) or the chunk scores of
+    /// target quality, the time each stage took, the settings and the versions
+    /// of the tools. It is written as <output>.html once the output was
+    /// concatenated.
+    #[clap(long)]
+    pub html_report: bool,
+
     /// Do not check if the encoder arguments specified by -v/--video-params are
     /// valid.
     #[clap(long)]
@@ -265,14 +395,104 @@ pub struct CliOpts {
     #[clap(short = 'n', conflicts_with = "overwrite")]
     pub never_overwrite: bool,
 
+    /// Print the encode plan (chunks, estimated duration and disk usage,
+    /// settings changed since the last run) and ask for confirmation before
+    /// encoding
+    #[clap(long)]
+    pub interactive: bool,
+
+    /// Answer yes to all confirmation prompts, including overwriting the
+    /// output file
+    #[clap(long, conflicts_with = "never_overwrite")]
+    pub yes: bool,
+
     /// Maximum number of chunk restarts for an encode
     #[clap(long, default_value_t = 3, value_parser = value_parser!(u32).range(1..))]
     pub max_tries: u32,
 
+    /// Output of each encoder and pipe process kept in memory per chunk, in
+    /// KiB
+    ///
+    /// Only the most recent output is kept and shown when a chunk fails, so
+    /// that an encoder printing megabytes of warnings per chunk cannot use up
+    /// the memory.
+    #[clap(
+        long,
+        default_value_t = 1024,
+        value_name = "KIB",
+        value_parser = value_parser!(u32).range(1..)
+    )]
+    pub output_limit: u32,
+
+    /// Write the complete output of the encoder and pipe processes of every
+    /// chunk to the "logs" folder of the temporary directory
+    ///
+    /// Useful when the output kept in memory with --output-limit does not
+    /// show why a chunk failed.
+    #[clap(long)]
+    pub spill_output: bool,
+
+    /// Capacity of the pipes between the source, ffmpeg and the encoder of
+    /// each chunk, in KiB (Linux only)
+    ///
+    /// Linux pipes hold 64 KiB by default, a small part of a 4K frame. Larger
+    /// pipes let the processes hand over whole frames, which lowers the CPU
+    /// time spent switching between them with many high resolution workers.
+    /// Limited to /proc/sys/fs/pipe-max-size, 1024 KiB by default.
+    #[clap(long, value_name = "KIB", value_parser = value_parser!(u32).range(64..))]
+    pub pipe_size: Option<u32>,
+
+    /// Buffer the frames of chunks of at most this many MiB in memory before
+    /// encoding them, instead of piping them (Linux only, requires the memfd
+    /// feature)
+    ///
+    /// The source writes every frame of the chunk to a file in memory, which
+    /// the encoder reads once it is complete, so the processes never wait on
+    /// each other. Each worker keeps its whole chunk in memory, so with
+    /// uncompressed 4K 10-bit frames of about 24 MiB, 240 frames take about
+    /// 5.6 GiB.
+    /// Larger chunks are piped.
+    #[clap(long, value_name = "MIB")]
+    pub memfd_buffer: Option<u32>,
+
     /// Number of workers to spawn [0 = automatic]
+    ///
+    /// If 0, the `workers` value from the config file is used if there is one.
     #[clap(short, long, default_value_t = 0)]
     pub workers: usize,
 
+    /// Scheduling priority of Av1an and the processes it runs, as with `nice`
+    ///
+    /// Only supported on Unix-like systems. Lowering the niceness below its
+    /// current value usually requires elevated privileges.
+    #[clap(long, allow_hyphen_values = true)]
+    pub niceness: Option<i32>,
+
+    /// Shell command to run whenever an encode finishes or fails
+    ///
+    /// The command is given the `AV1AN_STATUS` (`success` or `failure`) and
+    /// `AV1AN_OUTPUT` environment variables, e.g.
+    /// --notify-command 'notify-send "av1an: $AV1AN_STATUS" "$AV1AN_OUTPUT"'
+    #[clap(long, value_name = "COMMAND")]
+    pub notify_command: Option<String>,
+
+    /// Config file with user-wide defaults
+    ///
+    /// If not specified, ~/.config/av1an/config.toml (or
+    /// %APPDATA%\av1an\config.toml on Windows) is used if it exists. Options
+    /// given on the command line take precedence over the config file.
+    #[clap(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Ignore the config file
+    #[clap(long, conflicts_with = "config")]
+    pub no_config: bool,
+
+    /// Print the effective config, merged from the config file and the command
+    /// line, as TOML and exit
+    #[clap(long, conflicts_with = "input")]
+    pub dump_config: bool,
+
     /// Pin each worker to a specific set of threads of this size (disabled by
     /// default)
     ///
@@ -282,6 +502,55 @@ pub struct CliOpts {
     #[clap(long)]
     pub set_thread_affinity: Option<usize>,
 
+    /// Raise the encoder's thread count for the last chunks of the encode
+    ///
+    /// Once fewer chunks are left to start than there are workers, the
+    /// remaining chunks are encoded with the threads of the idle workers,
+    /// shortening the tail at the end of the encode where only one or two
+    /// chunks are still being encoded.
+    #[clap(long)]
+    pub tail_boost: bool,
+
+    /// Number of extra workers that run the first pass of upcoming chunks
+    /// (disabled by default)
+    ///
+    /// With two-pass encoding, these workers only run first passes and hand
+    /// each chunk over to the regular workers as soon as its first pass
+    /// finishes. The regular workers run the second passes of those chunks
+    /// before starting new ones, so the lighter first passes run alongside
+    /// the second passes, which keeps the CPU busy on machines with many
+    /// cores. Has no effect with a single pass.
+    #[clap(long, default_value_t = 0)]
+    pub afterburner: usize,
+
+    /// Number of extra workers that run Target Quality for upcoming chunks
+    /// (disabled by default)
+    ///
+    /// Normally each worker probes a chunk and then encodes it. With probe
+    /// workers, only they probe chunks, and hand each chunk over to the
+    /// regular workers as soon as its quantizer is found. The regular workers
+    /// then only encode probed chunks, so probing overlaps with encoding and
+    /// both can be sized to the machine independently. Has no effect without
+    /// --target-quality, and replaces --afterburner.
+    #[clap(long, default_value_t = 0)]
+    pub probe_workers: usize,
+
+    /// CUDA devices to assign the chunks to in turn, e.g. --gpus 0,1
+    ///
+    /// Each chunk's source, encoder and target quality probes run with
+    /// CUDA_VISIBLE_DEVICES set to the GPU the chunk was assigned to, which
+    /// spreads DGDecNV decoding, CUDA VapourSynth filters and hardware
+    /// encoders over the GPUs of multi-GPU systems.
+    #[clap(long, value_delimiter = ',')]
+    pub gpus: Vec<u32>,
+
+    /// Maximum number of chunks encoded on each GPU at once
+    ///
+    /// Workers wait for a GPU to free up when all of them are at the limit.
+    /// Requires --gpus. [default: unlimited]
+    #[clap(long)]
+    pub gpu_workers: Option<usize>,
+
     /// Scaler used for scene detection (if --sc-downscale-height XXXX is used)
     /// and VMAF calculation
     ///
@@ -292,9 +561,24 @@ pub struct CliOpts {
 
     /// Pass python argument(s) to the script environment
     /// --vspipe-args "message=fluffy kittens" "head=empty"
+    ///
+    /// If the script declares parameters with `# av1an-param: <name>: <type>
+    /// [= <default>]` comments, the arguments are validated against them.
     #[clap(long, num_args(0..))]
     pub vspipe_args: Vec<String>,
 
+    /// Serve the frames of VapourSynth chunks from a persistent environment
+    /// per worker
+    ///
+    /// Instead of starting a new vspipe process for every chunk, each worker
+    /// evaluates the script once and reads the frames of all its chunks from
+    /// it, which saves evaluating the script and opening the source index
+    /// again for every chunk. Scripts that read the built-in `AV1AN_CHUNK_*`
+    /// variables, --deband with f3kdb and pixel format conversion in
+    /// VapourSynth still start vspipe for every chunk.
+    #[clap(long)]
+    pub vs_server: bool,
+
     /// File location for scenes
     #[clap(short, long, help_heading = "Scene Detection")]
     pub scenes: Option<PathBuf>,
@@ -305,6 +589,27 @@ pub struct CliOpts {
     #[clap(long, requires("scenes"), help_heading = "Scene Detection")]
     pub sc_only: bool,
 
+    /// Write a JPEG contact sheet of the detected scene cuts to this file
+    ///
+    /// Every cut is shown with its frame number and its score, how far the
+    /// frame's inter cost is above the scenecut threshold, so that cuts close
+    /// to 1.00x stand out as the ones scene detection was least sure about.
+    /// Useful with --sc-only to check scene detection on a new source before
+    /// encoding. Cuts that don't fit on one sheet of 36 are written to
+    /// numbered sheets, e.g. cuts-001.jpg.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_preview: Option<PathBuf>,
+
+    /// Export the detected scenes to an editable TOML file before encoding
+    ///
+    /// If the file does not exist, it is written after scene detection and
+    /// Av1an waits for Enter so that cut points can be moved, or zone
+    /// overrides attached, before the file is read back in for the encode.
+    /// If the file already exists, scene detection is skipped and its
+    /// contents are used as-is.
+    #[clap(long, value_name = "FILE", help_heading = "Scene Detection")]
+    pub edit_scenes: Option<PathBuf>,
+
     /// Method used to determine chunk boundaries
     ///
     /// "av-scenechange" uses an algorithm to analyze which frames of the video
@@ -321,9 +626,99 @@ pub struct CliOpts {
     ///
     /// Fast: Very fast, but less accurate. Determines keyframes based on the
     /// raw difference between pixels.
+    ///
+    /// First-pass: Runs a fast x264 first pass and cuts where it placed
+    /// keyframes. Requires x264, regardless of the encoder.
     #[clap(long, default_value_t = ScenecutMethod::Standard, help_heading = "Scene Detection")]
     pub sc_method: ScenecutMethod,
 
+    /// Decoder that reads the frames for scene detection
+    ///
+    /// auto - VapourSynth for VapourSynth scripts and the lsmash, ffms2,
+    /// dgdecnv and bestsource chunk methods, FFmpeg otherwise.
+    ///
+    /// vapoursynth - VapourSynth. A video input is indexed with the best
+    /// installed source plugin if the chunk method does not use VapourSynth,
+    /// e.g. for MPEG-2 sources whose fields FFmpeg does not handle well.
+    ///
+    /// ffmpeg - FFmpeg, reading the video input directly. Not supported with
+    /// VapourSynth script input.
+    ///
+    /// y4m-pipe - vspipe, piping the output of the VapourSynth script through
+    /// FFmpeg. A video input is indexed like with vapoursynth.
+    #[clap(long, default_value_t = ScDecoder::Auto, help_heading = "Scene Detection")]
+    pub sc_decoder: ScDecoder,
+
+    /// Where to cut gradual transitions such as crossfades and dissolves
+    ///
+    /// Scene detection places a cut wherever a frame differs most from the
+    /// previous one, which in a crossfade is any frame in the middle of it.
+    /// Transitions are told apart from hard cuts by the run of frames around
+    /// the cut that all differ a little from the previous one.
+    ///
+    /// detected - Cut where scene detection found the largest change.
+    ///
+    /// middle - Cut in the middle of the transition.
+    ///
+    /// end - Cut on the last frame of the transition, so that the new shot
+    /// starts with a keyframe.
+    ///
+    /// none - Do not cut transitions, leaving them within one scene.
+    ///
+    /// Not supported with --sc-method first-pass.
+    #[clap(long, default_value_t = TransitionCut::Detected, help_heading = "Scene Detection")]
+    pub sc_transitions: TransitionCut,
+
+    /// Check every scene for a resolution or letterbox change
+    ///
+    /// Recordings of concerts and TV broadcasts may switch resolution or
+    /// black bars mid-file, which makes the encoder crash or encode the
+    /// changed scenes with the wrong frame size.
+    ///
+    /// ignore - Do not check the scenes.
+    ///
+    /// error - Exit after scene detection with the frame ranges of the scenes
+    /// that differ from the rest of the video.
+    ///
+    /// fix - Crop, scale and pad the scenes that differ to the resolution and
+    /// active area of the rest of the video. Only supported with video input.
+    #[clap(long, default_value_t = GeometryChanges::Ignore, help_heading = "Scene Detection")]
+    pub geometry_changes: GeometryChanges,
+
+    /// Encode runs of identical frames with faster encoder parameters
+    ///
+    /// Slideshows, screen recordings and static credits repeat the same
+    /// picture for seconds at a time, which the encoder compresses to almost
+    /// nothing but still spends its full effort on. Before scene detection,
+    /// the input is decoded once to find the runs of identical frames lasting
+    /// at least this many seconds. They are split into their own scenes,
+    /// which are encoded with --static-params.
+    ///
+    /// Scenes of zones keep the parameters of their zone. Only supported with
+    /// video input.
+    #[clap(
+        long,
+        num_args(0..=1),
+        default_missing_value = "2",
+        value_name = "SECONDS",
+        help_heading = "Scene Detection"
+    )]
+    pub static_scenes: Option<f64>,
+
+    /// Encoder parameters for the runs of identical frames found with
+    /// --static-scenes
+    ///
+    /// Merged into the video parameters of the rest of the encode. By
+    /// default, the fastest preset of the encoder is used, e.g. "--preset 12"
+    /// for svt-av1.
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        requires = "static_scenes",
+        help_heading = "Scene Detection"
+    )]
+    pub static_params: Option<String>,
+
     /// Optional downscaling for scene detection
     ///
     /// Specify as the desired maximum height to scale to (e.g. "720" to
@@ -339,6 +734,47 @@ pub struct CliOpts {
     #[clap(long, help_heading = "Scene Detection")]
     pub sc_pix_format: Option<FFPixelFormat>,
 
+    /// Keep the frames decoded for scene detection as a proxy for Target
+    /// Quality
+    ///
+    /// The frames are encoded losslessly to a file in the temporary directory
+    /// while scene detection reads them, after --sc-downscale-height and
+    /// --sc-pix-format are applied. Target Quality then encodes and scores
+    /// the probes from this file, as with --proxy, instead of decoding the
+    /// source again, which reduces reads from slow or network storage.
+    ///
+    /// Requires video input and a VapourSynth chunk method. Scene detection
+    /// then decodes the input with FFmpeg, as only frames piped through FFmpeg
+    /// can be kept, so it has no effect with --sc-decoder vapoursynth.
+    #[clap(long, conflicts_with = "proxy", help_heading = "Scene Detection")]
+    pub sc_proxy: bool,
+
+    /// Save the progress of scene detection, so that --resume continues it
+    ///
+    /// Scene detection analyses the input in windows of 30000 frames, and
+    /// saves the scenes found so far to the temporary directory after each.
+    /// The detector starts afresh in every window, so no scene cut is placed
+    /// on the first frame of a window, nor within --min-scene-len frames
+    /// after it. The scenes can thus differ slightly from those found without
+    /// it.
+    ///
+    /// Has no effect with --split-method none or --sc-method first-pass.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_checkpoint: bool,
+
+    /// Start encoding while scene detection is still running
+    ///
+    /// Scene detection hands the scenes it found to the workers in batches,
+    /// one after every this many frames it analysed, instead of detecting the
+    /// scenes of the whole video before the first chunk is encoded. The value
+    /// is the size of a batch, scene detection does not look ahead of it. No
+    /// scene cut is placed on the first frame of a batch, nor within
+    /// --min-scene-len frames after it, and --chunk-order only orders the
+    /// chunks of each batch. With --sc-checkpoint, the scenes of every batch
+    /// are saved so that --resume continues scene detection.
+    #[clap(long, value_name = "FRAMES", help_heading = "Scene Detection")]
+    pub sc_lookahead: Option<usize>,
+
     /// Maximum scene length
     ///
     /// When a scenecut is found whose distance to the previous scenecut is
@@ -355,10 +791,42 @@ pub struct CliOpts {
     #[clap(long, default_value_t = 10.0, help_heading = "Scene Detection")]
     pub extra_split_sec: f64,
 
+    /// Target wall time for encoding a single chunk, e.g. 90s, 10m or 1h30m
+    ///
+    /// Before scene detection, a short section of the video is encoded with the
+    /// chosen encoder settings to measure the encoding speed, which is then
+    /// used to set the maximum scene length so that each chunk takes roughly
+    /// this long to encode. The speed is measured with a single encoder
+    /// running, so chunks may take longer when many workers run at once.
+    #[clap(long, value_parser = parse_duration, conflicts_with = "extra_split", help_heading = "Scene Detection")]
+    pub target_chunk_time: Option<Duration>,
+
+    /// Wall time the encode should finish within, e.g. 6h or 1h30m
+    ///
+    /// Before scene detection, a short section of the video is encoded to
+    /// estimate how long the encode takes with the chosen settings. If the
+    /// estimate exceeds the deadline, the worker count is raised to the one
+    /// Av1an would pick on its own, and then the encoder speed preset is
+    /// stepped up one at a time until the estimate fits. The chosen preset and
+    /// worker count are reported before encoding starts.
+    #[clap(long, value_parser = parse_duration, help_heading = "Encoding")]
+    pub deadline: Option<Duration>,
+
     /// Minimum number of frames for a scenecut
     #[clap(long, default_value_t = 24, help_heading = "Scene Detection")]
     pub min_scene_len: usize,
 
+    /// Minimum length of a scene, in seconds
+    ///
+    /// Converted to frames with the frame rate of the input, so that the same
+    /// settings work for 24, 50 and 60 fps sources alike.
+    #[clap(
+        long,
+        conflicts_with = "min_scene_len",
+        help_heading = "Scene Detection"
+    )]
+    pub min_scene_sec: Option<f64>,
+
     /// Comma-separated list of frames to force as keyframes
     ///
     /// Can be useful for improving seeking with chapters, etc.
@@ -368,6 +836,9 @@ pub struct CliOpts {
     pub force_keyframes: Option<String>,
 
     /// Video encoder to use
+    ///
+    /// av1_nvenc and hevc_nvenc encode on an NVIDIA GPU through FFmpeg,
+    /// which must be built with NVENC.
     #[clap(short, long, default_value_t = Encoder::svt_av1, help_heading = "Encoding")]
     pub encoder: Encoder,
 
@@ -377,11 +848,28 @@ pub struct CliOpts {
     /// syntax cannot be used. For example, CRF is specified in ffmpeg via
     /// "-crf <CRF>", but the x264 binary takes this value with double
     /// dashes, as in "--crf <CRF>". See the --help output of each encoder for
-    /// a list of valid options. This list of parameters will be merged into
-    /// Av1an's default set of encoder parameters.
+    /// a list of valid options. The NVENC encoders take the options of
+    /// FFmpeg's encoder instead, e.g. "-preset p5 -cq 30". This list of
+    /// parameters will be merged into Av1an's default set of encoder
+    /// parameters.
     #[clap(short, long, allow_hyphen_values = true, help_heading = "Encoding")]
     pub video_params: Option<String>,
 
+    /// Speed and quality tier of Av1an's default encoder parameters
+    ///
+    /// archival - Slow presets and a low quantizer, for encodes that are kept
+    /// long term.
+    ///
+    /// balanced - A reasonable trade-off between speed and quality.
+    ///
+    /// fast - Fast presets and a higher quantizer, for previews and quick
+    /// encodes.
+    ///
+    /// The tier replaces the speed and quality parameters of the defaults, so
+    /// parameters given with --video-params still take precedence.
+    #[clap(long, conflicts_with = "no_defaults", help_heading = "Encoding")]
+    pub tier: Option<EncoderTier>,
+
     /// Number of encoder passes
     ///
     /// Since aom and vpx benefit from two-pass mode even with constant quality
@@ -395,9 +883,77 @@ pub struct CliOpts {
     #[clap(short, long, value_parser = value_parser!(u8).range(1..=2), help_heading = "Encoding")]
     pub passes: Option<u8>,
 
-    /// Estimate tile count from source
+    /// Parameters for the first pass of two-pass encoding
+    ///
+    /// These are merged into the video parameters of the first pass only,
+    /// overriding the parameters with the same name. The first pass only
+    /// collects statistics for the second, so a faster speed setting or
+    /// lighter analysis (e.g. "--cpu-used=6" for aomenc) speeds up the
+    /// encode with little to no loss in quality. Not applied to zones that
+    /// use a different encoder.
+    #[clap(long, allow_hyphen_values = true, help_heading = "Encoding")]
+    pub first_pass_params: Option<String>,
+
+    /// Second encoder for the least complex scenes
+    ///
+    /// Scenes are ranked by the inter-frame cost scene detection measured,
+    /// and the least complex ones are encoded with this encoder until they
+    /// make up --hybrid-share of the frames, e.g. av1_nvenc on the GPU for the
+    /// easy scenes and svt-av1 for the rest. The encoder must produce the
+    /// same format as --encoder, and the chunks are verified as with
+    /// --verify-chunks before concatenating. Scenes of zones keep their
+    /// encoder.
+    #[clap(long, help_heading = "Encoding")]
+    pub hybrid_encoder: Option<Encoder>,
+
+    /// Parameters for --hybrid-encoder
     ///
-    /// Worker estimation will consider tile count accordingly.
+    /// Merged into the default parameters of the hybrid encoder, like
+    /// --video-params.
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        requires = "hybrid_encoder",
+        help_heading = "Encoding"
+    )]
+    pub hybrid_params: Option<String>,
+
+    /// Share of the frames encoded with --hybrid-encoder, between 0 and 1
+    #[clap(
+        long,
+        default_value_t = 0.5,
+        requires = "hybrid_encoder",
+        help_heading = "Encoding"
+    )]
+    pub hybrid_share: f64,
+
+    /// Run a fast preview encode first, and use its per-chunk bitrates to pick
+    /// the q/crf of each chunk for the final encode
+    ///
+    /// Every chunk is first encoded with the encoder's fastest speed setting.
+    /// Chunks that needed more bits than average are then given a higher
+    /// q/crf for the final encode, and chunks that needed fewer bits a lower
+    /// one. The value is the change in q/crf per doubling of bitrate, and
+    /// deviations of more than 4x the average bitrate are capped.
+    ///
+    /// The q/crf from the video params is used as the base value for each
+    /// chunk. Cannot be used with --target-quality.
+    #[clap(
+        long,
+        num_args(0..=1),
+        default_missing_value = "2",
+        value_name = "STRENGTH",
+        help_heading = "Encoding"
+    )]
+    pub two_stage: Option<f32>,
+
+    /// Choose the tiles from the resolution and the threads per worker
+    ///
+    /// The frame is split into about one tile per 1280x720 pixels, but no more
+    /// tiles than each worker has threads. The tiles are set in the default
+    /// video parameters, or added to the video parameters with --no-defaults,
+    /// unless they set tiles already. Worker estimation will consider tile
+    /// count accordingly.
     #[clap(long, help_heading = "Encoding")]
     pub tile_auto: bool,
 
@@ -410,6 +966,76 @@ pub struct CliOpts {
     )]
     pub ffmpeg_filter_args: Option<String>,
 
+    /// Frame rate the --ffmpeg filters convert the input to, e.g. 60000/1001
+    ///
+    /// Declare this when the filters interpolate or drop frames, like fps or
+    /// minterpolate, so that the expected number of frames of each chunk, the
+    /// progress, the frame rate forced when concatenating with mkvmerge and
+    /// the VMAF reference all follow the output frame rate without
+    /// --ignore-frame-mismatch.
+    #[clap(long, help_heading = "Encoding")]
+    pub output_fps: Option<String>,
+
+    /// Frame rate of the input, e.g. 24000/1001, replacing the one its
+    /// container reports
+    ///
+    /// For sources with broken frame rate metadata. The chunks, the frame rate
+    /// forced when concatenating with mkvmerge, the progress and its estimates
+    /// and the conversion of seconds to frames, e.g. in --zones or
+    /// --trim-start, all use this frame rate.
+    #[clap(long, help_heading = "Encoding")]
+    pub fps_override: Option<String>,
+
+    /// Encode the filtered input to a lossless intermediate once, and split
+    /// the chunks from it
+    ///
+    /// The --ffmpeg filters, --deband, --burn-subs and any filtering in a
+    /// VapourSynth script then run once over the whole input instead of once
+    /// per chunk and probe, at the cost of the disk space of a lossless copy
+    /// in the temporary folder. Worth it when the filters are slower than the
+    /// encoder. "ffv1" supports every bit depth, "utvideo" is faster but only
+    /// supports 8-bit inputs.
+    #[clap(
+        long = "lossless-intermediate",
+        num_args(0..=1),
+        default_missing_value = "ffv1",
+        value_name = "CODEC",
+        help_heading = "Encoding"
+    )]
+    pub intermediate: Option<IntermediateCodec>,
+
+    /// What to do if the encoder cannot encode the width or height of the
+    /// input
+    ///
+    /// x264, x265 and SVT-AV1 reject a width or height that is not a multiple
+    /// of the chroma subsampling of the output pixel format, e.g. an odd width
+    /// with yuv420p10le. "pad" pads the right and bottom edges, "crop" crops
+    /// them, and "error" exits before encoding.
+    #[clap(long, default_value_t = OddDimensions::Pad, help_heading = "Encoding")]
+    pub odd_dimensions: OddDimensions,
+
+    /// Deband every chunk before encoding
+    ///
+    /// Low bitrate encodes tend to make banding in gradients worse, debanding
+    /// the source first hides it. If the chunks are read through VapourSynth
+    /// and the neo_f3kdb plugin is installed, it is used, otherwise FFmpeg's
+    /// gradfun filter is appended to the filters given with --ffmpeg.
+    ///
+    /// No grain is added by the filter, use --photon-noise instead.
+    #[clap(long, help_heading = "Encoding")]
+    pub deband: Option<DebandStrength>,
+
+    /// Burn subtitles into the video
+    ///
+    /// Either the index of a subtitle track of the input, counting only
+    /// subtitle tracks and starting at 0, or the path of a subtitle file. Both
+    /// text (e.g. ASS, SRT) and image (e.g. PGS, VobSub) subtitles are
+    /// supported, and are rendered before the filters given with --ffmpeg.
+    ///
+    /// Cannot be used with the segment chunk method.
+    #[clap(long, help_heading = "Encoding")]
+    pub burn_subs: Option<BurnSubtitles>,
+
     /// Audio encoding parameters (ffmpeg syntax)
     ///
     /// If not specified, "-c:a copy" is used.
@@ -431,11 +1057,42 @@ pub struct CliOpts {
     #[clap(short, long, allow_hyphen_values = true, help_heading = "Encoding")]
     pub audio_params: Option<String>,
 
+    /// What to do with CEA-608/708 closed captions embedded in the source
+    /// video stream, which are lost when the video is re-encoded
+    ///
+    /// ignore - Warn that the source has closed captions, and drop them.
+    ///
+    /// extract - Write the captions to an .srt file next to the output.
+    ///
+    /// mux - Add the captions to the output as a subtitle track. Not supported
+    /// with --concat ivf.
+    #[clap(long, default_value_t = CaptionMode::Ignore, help_heading = "Encoding")]
+    pub captions: CaptionMode,
+
+    /// Normalize the loudness of the audio
+    ///
+    /// ebur128 - Two-pass loudness normalization to the EBU R128 target of -23
+    /// LUFS. The loudness of every audio track is measured first, alongside the
+    /// video encode.
+    ///
+    /// Requires an audio encoder to be set with --audio-params.
+    #[clap(long, help_heading = "Encoding")]
+    pub audio_normalize: Option<AudioNormalization>,
+
     /// Ignore any detected mismatch between scene frame count and encoder frame
     /// count
     #[clap(long, help_heading = "Encoding")]
     pub ignore_frame_mismatch: bool,
 
+    /// Encode a Matroska input that uses ordered chapters or segment linking
+    ///
+    /// Players build the timeline of such files from parts of them and of
+    /// other linked files, while Av1an only encodes the frames stored in the
+    /// input, in the order they are stored. By default, Av1an exits when it
+    /// detects this, listing the linked segments it found.
+    #[clap(long, help_heading = "Encoding")]
+    pub ignore_linked_segments: bool,
+
     /// Method used for piping exact ranges of frames to the encoder
     ///
     /// Methods that require an external vapoursynth plugin:
@@ -471,15 +1128,19 @@ pub struct CliOpts {
     /// number of irrelevant frames until relevant frames are decoded and piped
     /// to the encoder.
     ///
-    /// select - Extremely slow, but accurate. Does not require intermediate
-    /// files. Decodes from the first frame to the requested frame,
-    /// without skipping irrelevant frames (causing quadratic decoding
-    /// complexity).
+    /// select - Slow, but accurate. Does not require intermediate files.
+    /// Seeks to the keyframe before the requested frame and decodes from
+    /// there, which relies on the timestamps of the source being accurate.
     ///
     /// segment - Create chunks based on keyframes in the source. Not frame
     /// exact, as it can only split on keyframes in the source.
     /// Requires intermediate files (which can be large).
     ///
+    /// trim - Segments the source on the keyframe before each chunk, then
+    /// decodes the exact frames of the chunk from its segments with ffmpeg's
+    /// trim filter. Accurate and much faster than select, but requires
+    /// intermediate files (which can be large).
+    ///
     /// Default: bestsource (if available), otherwise lsmash (if available),
     /// otherwise ffms2 (if available), otherwise DGDecNV (if available),
     /// otherwise hybrid.
@@ -555,10 +1216,98 @@ pub struct CliOpts {
     #[clap(short, long, default_value_t = ConcatMethod::MKVMerge, help_heading = "Encoding")]
     pub concat: ConcatMethod,
 
+    /// Fail instead of concatenating with ffmpeg if concatenating with mkvmerge
+    /// fails
+    ///
+    /// By default, if mkvmerge is not installed or fails, e.g. because the
+    /// headers of the chunks do not match, Av1an warns and concatenates with
+    /// ffmpeg instead, so that the encoded chunks still produce an output.
+    /// x265 and aomenc with --enable-keyframe-filtering=2 are never
+    /// concatenated with ffmpeg.
+    #[clap(long, help_heading = "Encoding")]
+    pub no_concat_fallback: bool,
+
+    /// Template of the paths of the encoded chunks in the encode directory,
+    /// relative to it and without the extension
+    ///
+    /// {index} is replaced by the index of the chunk and {shard} by the index
+    /// divided by 1000, both optionally zero-padded, e.g. {index:05}. Paths
+    /// with directories, such as {shard:03}/{index:05}, spread the chunks of
+    /// long encodes over several directories. The concatenation and resuming
+    /// find the chunks with the template of the encode.
+    #[clap(long, default_value_t = ChunkNames::default(), help_heading = "Encoding")]
+    pub chunk_names: ChunkNames,
+
+    /// Title of the output file
+    #[clap(long, help_heading = "Encoding")]
+    pub title: Option<String>,
+
+    /// Language of the video track of the output, as an ISO 639-2 code (eng)
+    /// or a BCP 47 tag (en-US)
+    #[clap(long, help_heading = "Encoding")]
+    pub track_language: Option<String>,
+
+    /// Do not write the encoder and the video parameters as ENCODER and
+    /// ENCODER_SETTINGS tags of the video track of the output
+    #[clap(long, help_heading = "Encoding")]
+    pub no_settings_tags: bool,
+
+    /// Move the seek index of the output to the start of the file after
+    /// concatenating
+    ///
+    /// Remuxes the output with ffmpeg, putting the cues of Matroska outputs or
+    /// the moov atom of MP4 outputs in front of the video, so that players
+    /// reading the output over a network share start and seek without first
+    /// reading the end of the file. If ffmpeg cannot move the index, Av1an
+    /// warns and keeps the output as muxed.
+    #[clap(long, help_heading = "Encoding")]
+    pub optimize_mux: bool,
+
+    /// Probe every encoded chunk with ffprobe before concatenating, and abort
+    /// if their codec parameters (resolution, bit depth, color config) are
+    /// inconsistent or do not match the expected encoder output
+    #[clap(long, help_heading = "Encoding")]
+    pub verify_chunks: bool,
+
+    /// Check every chunk for corruption right after it is encoded
+    ///
+    /// The codec and bit depth in the headers are checked, and every packet
+    /// is parsed with FFmpeg without decoding it. A corrupt chunk is encoded
+    /// again, like a chunk whose encoder crashed.
+    #[clap(long, help_heading = "Encoding")]
+    pub check_chunks: bool,
+
+    /// Like --check-chunks, but also decode every frame of each chunk
+    ///
+    /// AV1 chunks are decoded with dav1d if it is installed, and with FFmpeg
+    /// otherwise.
+    #[clap(long, help_heading = "Encoding")]
+    pub strict_verify: bool,
+
     /// FFmpeg pixel format
     #[clap(long, default_value = "yuv420p10le", help_heading = "Encoding")]
     pub pix_format: FFPixelFormat,
 
+    /// Cap the bitrate of every chunk at this peak, in kbps
+    ///
+    /// The encoder keeps encoding at constant quality, except where that
+    /// would exceed the peak, so that the output fits the buffer constraints
+    /// of streaming devices. Supported by x264, x265 and svt-av1.
+    #[clap(long, help_heading = "Encoding")]
+    pub peak_bitrate: Option<u32>,
+
+    /// Length of the buffer of --peak-bitrate, in seconds
+    ///
+    /// Chunks that are shorter than the buffer get a buffer as long as they
+    /// are, as every chunk starts with a full buffer.
+    #[clap(
+        long,
+        default_value_t = 2.0,
+        requires = "peak_bitrate",
+        help_heading = "Encoding"
+    )]
+    pub peak_buffer: f64,
+
     /// Path to a file specifying zones within the video with differing encoder
     /// settings.
     ///
@@ -605,9 +1354,61 @@ pub struct CliOpts {
     /// - `--min-scene-len`
     /// - `--passes`
     /// - `--photon-noise` (aomenc/rav1e only)
+    ///
+    /// A zone of the form `start_frame end_frame qp-offset <offset>`
+    /// only offsets the quantizer of its frames, where negative offsets
+    /// increase the quality. With x264 and x265, it is passed to the
+    /// encoder's own --zones option and does not split scenes. Other
+    /// encoders split the scenes it overlaps and offset the quantizer
+    /// set in --video-params instead.
+    ///
+    /// `-` reads the zones from stdin instead of a file, e.g. from a script
+    /// generating them. Errors in the zones name the line they are on.
     #[clap(long, help_heading = "Encoding", verbatim_doc_comment)]
     pub zones: Option<PathBuf>,
 
+    /// Only encode the frames inside the zones of --zones
+    ///
+    /// The audio is cut to the same frames and joined, so that it stays in
+    /// sync with the partial output. The audio has to be re-encoded with
+    /// --audio-params for this, and subtitles are left out.
+    #[clap(long, requires = "zones", help_heading = "Encoding")]
+    pub zones_only: bool,
+
+    /// Start encoding at this frame or time of the input
+    ///
+    /// A frame is given with an "f" suffix, e.g. 1200f, and a time as a
+    /// timestamp such as 1:02:03.5 or 2:30, or as a duration such as 90s or
+    /// 1m30s. Scene detection still runs on the whole input. The audio is cut
+    /// to the same frames, so it has to be re-encoded with --audio-params,
+    /// and subtitles are left out.
+    #[clap(
+        long,
+        value_parser = parse_trim_point,
+        value_name = "FRAME|TIME",
+        help_heading = "Encoding"
+    )]
+    pub trim_start: Option<TrimPoint>,
+
+    /// Stop encoding before this frame or time of the input
+    ///
+    /// Takes the same values as --trim-start.
+    #[clap(
+        long,
+        value_parser = parse_trim_point,
+        value_name = "FRAME|TIME",
+        help_heading = "Encoding"
+    )]
+    pub trim_end: Option<TrimPoint>,
+
+    /// Copy the video stream of the input instead of encoding it
+    ///
+    /// Only the audio is encoded with --audio-params and muxed with the copied
+    /// video, e.g. to fix the audio of files that are already encoded. Scene
+    /// detection, chunking and the encoder settings are skipped.
+    #[clap(long, help_heading = "Encoding")]
+    pub copy_video: bool,
+
     /// Set chunk cache index mode
     ///
     /// source - Place source cache next to video.
@@ -635,9 +1436,23 @@ pub struct CliOpts {
     #[clap(long, help_heading = "VMAF")]
     pub vmaf: bool,
 
+    /// Metrics to score the output with against the input after
+    /// concatenating
+    ///
+    /// The score of every frame and a summary of the scores are written to
+    /// <output>.<metric>.json, and plotted to <output>.<metric>.svg. `vmaf`
+    /// is the same as --vmaf. `ssimulacra2` is measured with Vapoursynth-HIP
+    /// or VapourSynth Zig Image Process with chunk method lsmash, ffms2,
+    /// bestsource or dgdecnv, and with a slower built-in implementation
+    /// otherwise. `xpsnr` requires FFmpeg with the xpsnr filter.
+    #[clap(long = "metric", num_args = 1.., help_heading = "VMAF")]
+    pub metrics: Vec<OutputMetric>,
+
     /// Path to VMAF model (used by --vmaf and --target-quality)
     ///
-    /// If not specified, ffmpeg's default is used.
+    /// If not specified, ffmpeg's default is used. If libvmaf was built
+    /// without its models, the model is downloaded and cached in
+    /// ~/.cache/av1an/vmaf instead.
     #[clap(long, help_heading = "VMAF")]
     pub vmaf_path: Option<PathBuf>,
 
@@ -651,21 +1466,70 @@ pub struct CliOpts {
     /// Resolution used for Target Quality metric calculation in the form of
     /// `widthxheight` where width and height are positive integers
     ///
-    /// If not specified, the output video will be scaled to the resolution of
+    /// The probes are encoded at this resolution, and the source is scaled to
+    /// it for scoring, which makes probing high resolution content several
+    /// times faster. If not specified, the probes are encoded at the input
+    /// resolution and the output video will be scaled to the resolution of
     /// the input video.
     #[clap(long, help_heading = "Target Quality")]
     pub probe_res: Option<String>,
 
+    /// Number of chunks to learn a correction for --probe-res from
+    ///
+    /// The quantizer chosen for each of the first chunks is probed again
+    /// without --probe-res. The average difference between those scores and
+    /// the scores at the probe resolution is added to the probe scores of all
+    /// later chunks, so that they estimate the quality at full resolution.
+    #[clap(
+        long,
+        default_value_t = 0,
+        requires = "probe_res",
+        help_heading = "Target Quality"
+    )]
+    pub probe_res_correction: usize,
+
     /// Number of threads to use for target quality VMAF calculation
     #[clap(long, help_heading = "VMAF")]
     pub vmaf_threads: Option<usize>,
 
+    /// Compute VMAF on an NVIDIA GPU with libvmaf_cuda
+    ///
+    /// Applies to --vmaf and to Target Quality with VMAF. Needs FFmpeg and
+    /// libvmaf built with CUDA support. If they are not, VMAF is computed on
+    /// the CPU with --vmaf-threads threads instead.
+    #[clap(long, help_heading = "VMAF")]
+    pub vmaf_cuda: bool,
+
     /// Filter applied to source at VMAF calcualation
     ///
     /// This option should be specified if the source is cropped, for example.
     #[clap(long, help_heading = "VMAF")]
     pub vmaf_filter: Option<String>,
 
+    /// Tone map HDR video to SDR before calculating VMAF
+    ///
+    /// The VMAF models are trained on SDR video, so scores of PQ or HLG
+    /// encodes measured against their source are misleading. With this
+    /// option, the reference and the encode are both converted from the given
+    /// transfer function to BT.709 with the same tone mapping before they are
+    /// compared. The scores are then comparable to those of SDR encodes.
+    ///
+    /// Applies to target quality with VMAF and to --vmaf. Requires FFmpeg with
+    /// libzimg.
+    ///
+    /// auto - Tone map if the input is PQ (SMPTE 2084)
+    ///
+    /// pq - Tone map from PQ
+    ///
+    /// hlg - Tone map from HLG (ARIB STD-B67), which is not detected by auto
+    #[clap(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "auto",
+        help_heading = "VMAF"
+    )]
+    pub vmaf_tonemap: Option<VmafTonemap>,
+
     /// Target a metric score range for encoding (disabled by default)
     ///
     /// For each chunk, target quality uses an algorithm to find the
@@ -673,8 +1537,9 @@ pub struct CliOpts {
     /// range. Target quality mode is much slower than normal encoding, but
     /// can improve the consistency of quality in some cases.
     ///
-    /// The VMAF and SSIMULACRA2 score ranges are 0-100 (where 0 is the worst
-    /// quality, and 100 is the best).
+    /// The VMAF score range is 0-100 (where 0 is the worst quality, and 100
+    /// is the best). SSIMULACRA2 scores range up to 100 as the best quality,
+    /// and fall below 0 for heavily distorted frames.
     ///
     /// The butteraugli score minimum is 0 as the best quality and increases as
     /// quality decreases towards infinity.
@@ -684,10 +1549,22 @@ pub struct CliOpts {
     ///
     /// Specify as a range: --target-quality 75-85 for VMAF/SSIMULACRA2
     /// or --target-quality 1.0-1.5 for butteraugli metrics.
-    /// Floating-point values are allowed for all metrics.
+    /// Floating-point values are allowed for all metrics. Ranges outside the
+    /// scores of the metric are rejected.
     #[clap(long, help_heading = "Target Quality", value_parser = TargetQuality::parse_target_qp_range)]
     pub target_quality: Option<(f64, f64)>,
 
+    /// Refuse to concatenate while any chunk scores below this (above it for
+    /// butteraugli metrics)
+    ///
+    /// Every chunk is scored with --target-metric once it is encoded. Before
+    /// concatenating, the chunks below the threshold are listed, and in an
+    /// interactive terminal Av1an offers to re-encode them at a lower
+    /// quantizer until they pass. Otherwise the encode stops with its
+    /// temporary folder kept, so that no known bad chunk is muxed.
+    #[clap(long, help_heading = "Target Quality")]
+    pub min_chunk_score: Option<f64>,
+
     /// Quantizer range bounds for target quality search (disabled by default)
     ///
     /// Specifies the minimum and maximum quantizer/CRF/qp values to use during
@@ -736,9 +1613,9 @@ pub struct CliOpts {
     ///
     /// vmaf - Requires FFmpeg with VMAF enabled.
     ///
-    /// ssimulacra2 - Requires Vapoursynth-HIP or VapourSynth-Zig Image Process
-    /// plugin. Also requires Chunk method to be set to "lsmash", "ffms2",
-    /// "bestsource", or "dgdecnv".
+    /// ssimulacra2 - Measured with the Vapoursynth-HIP or VapourSynth-Zig Image
+    /// Process plugin if the Chunk method is "lsmash", "ffms2", "bestsource",
+    /// or "dgdecnv", and with a slower built-in implementation otherwise.
     ///
     /// butteraugli-inf - Uses the Infinite-Norm value of butteraugli with a
     /// target intensity of 203 nits. Requires Vapoursynth-HIP or Julek
@@ -835,6 +1712,452 @@ pub struct CliOpts {
     ///   "harmonic" works as expected when there are no negative scores. Use with caution with target metrics such as "ssimulacra2".
     #[clap(long, default_value_t = String::from("auto"), help_heading = "Target Quality", verbatim_doc_comment)]
     pub probing_stat: String,
+    /// Keep the probes of Target Quality in temp/probes
+    ///
+    /// The probes are kept after the encode, even without --keep, with a
+    /// manifest.json that lists the chunk, quantizer and score of each probe
+    /// and which quantizer was chosen for the chunk.
+    #[clap(long, help_heading = "Target Quality")]
+    pub keep_probes: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Decode a sample of chunks with every available chunk method and
+    /// recommend the best one for this source
+    ///
+    /// Each method is timed and the decoded frames are compared between
+    /// methods, so methods that are fast but not frame accurate for this
+    /// source/container (e.g. segment on a source with sparse keyframes) are
+    /// not recommended.
+    BenchChunkMethods(BenchChunkMethodsOpts),
+
+    /// Write the photon noise grain table that --photon-noise would use,
+    /// without running an encode
+    ///
+    /// The table can be inspected, edited, or passed to the encoder directly,
+    /// e.g. with --film-grain-table for aomenc and SvtAv1EncApp or
+    /// --photon-noise-table for rav1e.
+    GrainTable(GrainTableOpts),
+
+    /// List the installed encoders, the bit depths and pixel formats they
+    /// support, VapourSynth plugins, chunk methods and concatenation tools
+    ///
+    /// With --json, the list is printed as JSON for frontends that build their
+    /// options from what is installed.
+    Capabilities(CapabilitiesOpts),
+
+    /// Score finished encodes against their source with VMAF, SSIMULACRA2 or
+    /// XPSNR, and compare them in a report and plots
+    ///
+    /// The frames of the encodes are compared by their index. Encodes with a
+    /// different frame rate than the source are compared against the source
+    /// converted to their frame rate.
+    Compare(CompareOpts),
+
+    /// Package the state of an encode into an archive to attach to a bug
+    /// report
+    ///
+    /// The archive contains the scenes, chunks, progress, settings and logs
+    /// of the encode and the capabilities of this system, but no media.
+    BundleDebug(BundleDebugOpts),
+}
+
+#[derive(Args, Debug)]
+pub struct BenchChunkMethodsOpts {
+    /// Input video to benchmark
+    #[clap(short)]
+    pub input: PathBuf,
+
+    /// Temporary directory to use
+    ///
+    /// If not specified, the temporary directory name is a hash of the input
+    /// file name.
+    #[clap(long)]
+    pub temp: Option<PathBuf>,
+
+    /// Do not delete the temporary folder after benchmarking has finished
+    #[clap(short, long)]
+    pub keep: bool,
+
+    /// Number of chunks to sample, spread evenly over the input
+    #[clap(long, default_value_t = 5, value_parser = value_parser!(u32).range(1..))]
+    pub samples: u32,
+
+    /// Length of each sampled chunk in frames
+    #[clap(long, default_value_t = 240, value_parser = value_parser!(u32).range(1..))]
+    pub sample_frames: u32,
+
+    /// Only benchmark these chunk methods instead of every available one
+    #[clap(short = 'm', long, num_args = 1..)]
+    pub chunk_methods: Vec<ChunkMethod>,
+
+    /// Set chunk cache index mode
+    ///
+    /// source - Place source cache next to video.
+    ///
+    /// temp - Place source cache in temp directory.
+    #[clap(long, default_value_t = CacheSource::TEMP)]
+    pub cache_mode: CacheSource,
+}
+
+#[derive(Args, Debug)]
+pub struct CompareOpts {
+    /// Encodes to compare
+    #[clap(required = true)]
+    pub encodes: Vec<PathBuf>,
+
+    /// Source the encodes are scored against
+    ///
+    /// Can be a video or VapourSynth (.py, .vpy) script.
+    #[clap(short, long)]
+    pub reference: PathBuf,
+
+    /// Metrics to score the encodes with
+    #[clap(short, long, num_args = 1.., default_values_t = [CompareMetric::VMAF])]
+    pub metrics: Vec<CompareMetric>,
+
+    /// Path of the report, without an extension
+    ///
+    /// The comparison is written to <output>.json, and the frame scores of
+    /// every metric are plotted to <output>_<metric>.svg.
+    #[clap(short, long, default_value = "comparison")]
+    pub output: PathBuf,
+
+    /// Path to VMAF model
+    ///
+    /// If not specified, ffmpeg's default is used.
+    #[clap(long)]
+    pub vmaf_path: Option<PathBuf>,
+
+    /// Resolution that VMAF and XPSNR compare the videos at
+    ///
+    /// If set to inputres, the videos are compared at the resolution of the
+    /// reference.
+    #[clap(long, default_value = "1920x1080")]
+    pub vmaf_res: String,
+
+    /// Scaler used to scale the videos to --vmaf-res
+    #[clap(long, default_value = "bicubic")]
+    pub scaler: String,
+
+    /// Number of threads to use for VMAF calculation
+    #[clap(long)]
+    pub vmaf_threads: Option<usize>,
+
+    /// Temporary directory to use
+    ///
+    /// If not specified, the temporary directory name is a hash of the
+    /// reference file name.
+    #[clap(long)]
+    pub temp: Option<PathBuf>,
+
+    /// Do not delete the temporary folder after comparing
+    #[clap(short, long)]
+    pub keep: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BundleDebugOpts {
+    /// Temporary directory of the encode
+    pub temp: PathBuf,
+
+    /// Path of the archive to write
+    #[clap(short, long, default_value = "av1an-debug.tar")]
+    pub output: PathBuf,
+
+    /// Av1an log files to add to the archive
+    ///
+    /// If not specified, the most recent log in ./logs is added.
+    #[clap(long, num_args = 1..)]
+    pub log: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct CapabilitiesOpts {
+    /// Print the capabilities as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct GrainTableOpts {
+    /// ISO setting of the simulated film grain
+    ///
+    /// --photon-noise <strength> uses ISO 100 * strength.
+    #[clap(long, value_parser = value_parser!(u32).range(1..=6400))]
+    pub iso: u32,
+
+    /// Resolution of the video the table is used for, as widthxheight
+    #[clap(long, value_parser = TargetQuality::parse_probe_res)]
+    pub resolution: (u32, u32),
+
+    /// Transfer characteristics of the video
+    ///
+    /// bt1886 - SDR content.
+    ///
+    /// pq - HDR content using the SMPTE ST 2084 (PQ) transfer function.
+    #[clap(long, default_value_t = NoiseTransfer::Bt1886)]
+    pub transfer: NoiseTransfer,
+
+    /// Add grain to the chroma planes as well
+    #[clap(long)]
+    pub chroma_noise: bool,
+
+    /// Generate the table for full range instead of limited range video
+    #[clap(long)]
+    pub full_range: bool,
+
+    /// Path of the grain table to write
+    #[clap(short, long)]
+    pub output: PathBuf,
+}
+
+impl GrainTableOpts {
+    fn run(&self) -> anyhow::Result<()> {
+        write_photon_noise_table(
+            &self.output,
+            self.iso,
+            self.resolution,
+            self.transfer,
+            self.chroma_noise,
+            self.full_range,
+        )?;
+        println!(
+            "Wrote grain table for ISO {} at {}x{} ({}) to {}",
+            self.iso,
+            self.resolution.0,
+            self.resolution.1,
+            self.transfer,
+            self.output.display()
+        );
+        Ok(())
+    }
+}
+
+impl CapabilitiesOpts {
+    fn run(&self) -> anyhow::Result<()> {
+        let capabilities = Capabilities::detect();
+        if self.json {
+            println!("{}", capabilities.to_json()?);
+            return Ok(());
+        }
+
+        let found = |found: bool| if found { "found" } else { "not found" };
+        println!("av1an {}\n\nEncoders", capabilities.av1an_version);
+        for encoder in &capabilities.encoders {
+            match &encoder.version {
+                Some(version) => println!(
+                    "  {:<8} {version}\n           bit depths: {}\n           pixel formats: {}",
+                    encoder.name,
+                    encoder
+                        .bit_depths
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    encoder.pixel_formats.join(", ")
+                ),
+                None => println!("  {:<8} not found ({})", encoder.name, encoder.binary),
+            }
+        }
+        println!("\nVapourSynth plugins");
+        match &capabilities.vapoursynth {
+            Some(plugins) => {
+                for (plugin, available) in plugins {
+                    println!("  {plugin:<28} {}", found(*available));
+                }
+            },
+            None => println!("  VapourSynth not found"),
+        }
+        println!(
+            "\nChunk methods\n  {}",
+            capabilities.chunk_methods.join(", ")
+        );
+        println!("\nConcatenation methods");
+        for (method, available) in &capabilities.concat_methods {
+            println!("  {method:<28} {}", found(*available));
+        }
+        println!("\nTools");
+        for (tool, available) in &capabilities.tools {
+            println!("  {tool:<28} {}", found(*available));
+        }
+        for (metric, available) in &capabilities.ffmpeg_metrics {
+            println!("  {:<28} {}", format!("ffmpeg {metric}"), found(*available));
+        }
+        Ok(())
+    }
+}
+
+impl BundleDebugOpts {
+    fn run(&self) -> anyhow::Result<()> {
+        let logs = if self.log.is_empty() {
+            // the default log file, which is rotated daily
+            std::fs::read_dir("logs")
+                .into_iter()
+                .flatten()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("av1an.log"))
+                .max_by_key(|entry| entry.metadata().and_then(|metadata| metadata.modified()).ok())
+                .map(|entry| entry.path())
+                .into_iter()
+                .collect()
+        } else {
+            self.log.clone()
+        };
+
+        let files = bundle_debug(&self.temp, &self.output, &logs)?;
+        for file in &files {
+            println!("  {file}");
+        }
+        println!(
+            "Wrote {} files to {}. The files contain the paths of the input and the output, so \
+             check them before sharing the archive.",
+            files.len(),
+            self.output.display()
+        );
+        Ok(())
+    }
+}
+
+impl CompareOpts {
+    fn run(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.reference.is_file(),
+            "Reference {} does not exist",
+            self.reference.display()
+        );
+        for encode in &self.encodes {
+            ensure!(
+                encode.is_file(),
+                "Encode {} does not exist",
+                encode.display()
+            );
+        }
+        let temp = self.temp.as_ref().map_or_else(
+            || format!(".{}_compare", hash_path(&self.reference)),
+            |path| path.to_string_lossy().to_string(),
+        );
+        // SSIMULACRA2 is measured with VapourSynth, which needs a source filter
+        let chunk_method = if self.metrics.contains(&CompareMetric::SSIMULACRA2) {
+            get_vapoursynth_plugins()?.best_available_chunk_method()
+        } else {
+            ChunkMethod::Hybrid
+        };
+
+        let comparison = Input::new(
+            self.reference.as_path(),
+            Vec::new(),
+            &temp,
+            chunk_method,
+            false,
+            CacheSource::TEMP,
+        )
+        .and_then(|reference| {
+            let res = if self.vmaf_res == "inputres" {
+                let (width, height) = reference.clip_info()?.resolution;
+                format!("{width}x{height}")
+            } else {
+                self.vmaf_res.clone()
+            };
+            let options = CompareOptions {
+                metrics: self.metrics.clone(),
+                vmaf_model: self.vmaf_path.clone(),
+                res,
+                scaler: self.scaler.clone(),
+                threads: self
+                    .vmaf_threads
+                    .unwrap_or_else(|| available_parallelism().map_or(1, std::num::NonZero::get)),
+            };
+            compare_encodes(&reference, &self.encodes, &options, Path::new(&temp))
+        });
+        if !self.keep
+            && let Err(e) = std::fs::remove_dir_all(&temp)
+        {
+            warn!("Failed to delete temp directory: {e}");
+        }
+        let comparison = comparison?;
+
+        println!("\n{}", comparison.table());
+        let report = self.output.with_extension("json");
+        comparison.write(&report)?;
+        println!("Wrote the comparison to {}", report.display());
+        for &metric in &self.metrics {
+            let mut plot = self.output.clone().into_os_string();
+            plot.push(format!("_{metric}.svg"));
+            let plot = PathBuf::from(plot);
+            comparison.plot(metric, &plot)?;
+            println!("Plotted the {metric} scores to {}", plot.display());
+        }
+        Ok(())
+    }
+}
+
+impl BenchChunkMethodsOpts {
+    fn run(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.input.is_file(),
+            "Input file {} does not exist",
+            self.input.display()
+        );
+        let temp = self.temp.as_ref().map_or_else(
+            || format!(".{}_bench", hash_path(&self.input)),
+            |path| path.to_string_lossy().to_string(),
+        );
+        let methods = if self.chunk_methods.is_empty() {
+            available_chunk_methods(get_vapoursynth_plugins().ok())
+        } else {
+            self.chunk_methods.clone()
+        };
+
+        let results = bench_chunk_methods(
+            &self.input,
+            &temp,
+            &methods,
+            self.samples as usize,
+            self.sample_frames as usize,
+            self.cache_mode,
+        );
+        if !self.keep
+            && let Err(e) = std::fs::remove_dir_all(&temp)
+        {
+            warn!("Failed to delete temp directory: {e}");
+        }
+        let results = results?;
+
+        println!(
+            "{:<12} {:>10} {:>10} {:>10} {:>15}  accurate",
+            "method", "setup", "decode", "fps", "frames"
+        );
+        for result in &results {
+            println!(
+                "{:<12} {:>9.2}s {:>9.2}s {:>10.2} {:>15}  {}",
+                result.method.to_string(),
+                result.setup_time.as_secs_f64(),
+                result.decode_time.as_secs_f64(),
+                result.fps(),
+                format!("{}/{}", result.frames_decoded, result.frames_expected),
+                result.error.as_ref().map_or_else(
+                    || {
+                        if result.is_frame_accurate() {
+                            "yes".to_string()
+                        } else {
+                            format!("no ({} mismatched samples)", result.mismatched_samples)
+                        }
+                    },
+                    |error| format!("failed: {error}"),
+                )
+            );
+        }
+
+        if let Some(method) = recommend_chunk_method(&results) {
+            println!("\nRecommended chunk method: {method} (-m {method})");
+        } else {
+            println!("\nNo chunk method decoded every sample accurately");
+        }
+
+        Ok(())
+    }
 }
 
 impl CliOpts {
@@ -864,13 +2187,16 @@ impl CliOpts {
         Ok(TargetQuality {
             vmaf_res: self.vmaf_res.clone(),
             probe_res,
+            probe_res_correction: self.probe_res_correction,
             vmaf_scaler: self.scaler.clone(),
             vmaf_filter: self.vmaf_filter.clone(),
+            vmaf_tonemap: self.vmaf_tonemap,
             vmaf_threads: self.vmaf_threads.unwrap_or_else(|| {
                 available_parallelism()
                     .expect("Unrecoverable: Failed to get thread count")
                     .get()
             }),
+            vmaf_cuda: self.vmaf_cuda,
             model: self.vmaf_path.clone(),
             probes: self.probes,
             target: self.target_quality,
@@ -892,6 +2218,7 @@ impl CliOpts {
                 self.probing_vmaf_features.clone()
             },
             probing_statistic,
+            keep_probes: self.keep_probes,
         })
     }
 }
@@ -943,20 +2270,39 @@ pub(crate) fn resolve_file_paths(
 
 /// Returns vector of Encode args ready to be fed to encoder
 #[tracing::instrument(level = "debug")]
-pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
+pub fn parse_cli(args: &CliOpts, config: &GlobalConfig) -> anyhow::Result<Vec<EncodeArgs>> {
     let input_paths = &*args.input;
     let proxy_paths = &*args.proxy;
 
+    let input_cache = config.input_cache.clone().or_else(remote::default_cache_dir);
+    let resolve = |path: &Path| -> anyhow::Result<Vec<PathBuf>> {
+        if remote::is_remote(path) {
+            let cache = input_cache
+                .as_deref()
+                .context("No directory to download remote inputs into, set --input-cache")?;
+            Ok(vec![remote::fetch(path, cache)?])
+        } else {
+            Ok(resolve_file_paths(path)?.collect())
+        }
+    };
+
     let mut inputs = Vec::new();
     for path in input_paths {
-        inputs.extend(resolve_file_paths(path)?);
+        inputs.extend(resolve(path)?);
     }
 
     let mut proxies = Vec::new();
     for path in proxy_paths {
-        proxies.extend(resolve_file_paths(path)?);
+        proxies.extend(resolve(path)?);
     }
 
+    // stdin holds the zones, so it cannot answer the prompts
+    let zones_from_stdin = args.zones.as_deref() == Some(Path::new("-"));
+    ensure!(
+        !(zones_from_stdin && args.interactive),
+        "--interactive cannot ask for confirmation while --zones reads stdin"
+    );
+
     let mut valid_args: Vec<EncodeArgs> = Vec::with_capacity(inputs.len());
 
     // Don't hard error, we can proceed if Vapoursynth isn't available
@@ -973,9 +2319,10 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
                     bail!("Failed to get parent directory of path: {:?}", path);
                 }
 
-                if !args.overwrite
+                if !(args.overwrite || args.yes)
                     && path.exists()
                     && (args.never_overwrite
+                        || zones_from_stdin
                         || !confirm(&format!(
                             "Output file {} exists. Do you want to overwrite it? [y/N]: ",
                             path.file_name().expect("file name should exist").display()
@@ -997,9 +2344,10 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
                     args.encoder
                 );
 
-                if !args.overwrite
+                if !(args.overwrite || args.yes)
                     && Path::new(&output_file).exists()
                     && (args.never_overwrite
+                        || zones_from_stdin
                         || !confirm(&format!(
                             "Default output file {} exists. Do you want to overwrite it? [y/N]: ",
                             output_file
@@ -1009,16 +2357,42 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
                     exit(0);
                 }
 
-                output_file
+                absolute(&output_file)?.to_string_lossy().to_string()
             }
         };
 
-        let temp = args.temp.as_ref().map_or_else(
-            || format!(".{}", hash_path(input.as_path())),
-            |path| path.to_string_lossy().to_string(),
-        );
+        let requested_temp = match args.temp.as_ref() {
+            Some(path) => path.clone(),
+            None => resolve_temp_dir(
+                config.temp_root.as_deref(),
+                input.as_path(),
+                Path::new(&output_file),
+                args.resume,
+            )?,
+        };
+        // the registry keeps track of the temporary directories it names
+        let temp_registry = args
+            .temp
+            .is_none()
+            .then(|| config.temp_root.clone().unwrap_or_else(|| PathBuf::from(".")));
+        let temp_dir = choose_temp_dir(
+            &requested_temp,
+            // the chunks of the segment and hybrid methods are about as large
+            // as the input
+            std::fs::metadata(input.as_path()).map_or(0, |metadata| metadata.len()),
+            if args.shared_temp {
+                TempRelocation::Never
+            } else {
+                args.relocate_temp
+            },
+            args.resume,
+        )?;
+        let relocated_from = (temp_dir != requested_temp).then_some(requested_temp);
+        // VapourSynth scripts are evaluated in their own folder, which changes
+        // the working directory of the whole process while they are
+        let temp = absolute(&temp_dir)?.to_string_lossy().to_string();
 
-        let chunk_method = args.chunk_method.unwrap_or_else(|| {
+        let chunk_method = config.chunk_method.unwrap_or_else(|| {
             vapoursynth_plugins.map_or(ChunkMethod::Hybrid, |p| p.best_available_chunk_method())
         });
         let scaler = {
@@ -1039,7 +2413,7 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
         };
 
         let input = Input::new(
-            input,
+            absolute(&input)?,
             args.vspipe_args.clone(),
             temp.as_str(),
             chunk_method,
@@ -1052,7 +2426,7 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
         let proxy_path = proxies.get(index).or_else(|| proxies.first());
         let proxy = if let Some(path) = proxy_path {
             Some(Input::new(
-                path,
+                absolute(path)?,
                 args.vspipe_args.clone(),
                 temp.as_str(),
                 chunk_method,
@@ -1076,6 +2450,12 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
         } else {
             Vec::new()
         };
+        let first_pass_params = if let Some(args) = args.first_pass_params.as_ref() {
+            shlex::split(args)
+                .ok_or_else(|| anyhow!("Failed to split first pass encoder arguments"))?
+        } else {
+            Vec::new()
+        };
         let output_pix_format = PixelFormat {
             format:    args.pix_format,
             bit_depth: args.encoder.get_format_bit_depth(args.pix_format)?,
@@ -1092,31 +2472,46 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
                     .ok(),
             });
 
-        let target_quality = args.target_quality_params(
+        let mut target_quality = args.target_quality_params(
             temp.clone(),
             probe_video_params,
             copied_params,
             output_pix_format.format,
         )?;
+        target_quality.workers = config.workers.unwrap_or(0);
 
         // Instantiates VapourSynth cache(s) if applicable
-        let clip_info = input.clip_info()?;
+        let fps_override = args
+            .fps_override
+            .as_deref()
+            .map(ffmpeg::parse_frame_rate)
+            .transpose()
+            .context("Invalid --fps-override")?;
+        let clip_info = input.clip_info_overriding_fps(fps_override)?;
         if let Some(proxy) = &proxy {
-            proxy.clip_info()?;
+            proxy.clip_info_overriding_fps(fps_override)?;
         }
         // TODO make an actual constructor for this
-        let arg = EncodeArgs {
+        let mut arg = EncodeArgs {
             ffmpeg_filter_args: if let Some(args) = args.ffmpeg_filter_args.as_ref() {
                 shlex::split(args)
                     .ok_or_else(|| anyhow!("Failed to split ffmpeg filter arguments"))?
             } else {
                 Vec::new()
             },
+            intermediate: args.intermediate,
+            odd_dimensions: args.odd_dimensions,
+            deband: args.deband,
+            burn_subs: args.burn_subs.clone(),
             temp: temp.clone(),
+            relocated_from,
+            temp_registry,
             force: args.force,
             no_defaults: args.no_defaults,
             passes: args.passes.unwrap_or_else(|| args.encoder.get_default_pass()),
             video_params: video_params.clone(),
+            first_pass_params,
+            tier: args.tier,
             output_file,
             audio_params: if let Some(args) = args.audio_params.as_ref() {
                 shlex::split(args)
@@ -1124,9 +2519,25 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             } else {
                 into_vec!["-c:a", "copy"]
             },
+            captions: args.captions,
+            audio_normalize: args.audio_normalize,
             chunk_method,
             chunk_order: args.chunk_order,
             concat: args.concat,
+            concat_fallback: !args.no_concat_fallback,
+            chunk_names: args.chunk_names.clone(),
+            title: args.title.clone(),
+            track_language: args.track_language.clone(),
+            settings_tags: !args.no_settings_tags,
+            optimize_mux: args.optimize_mux,
+            verify_chunks: args.verify_chunks,
+            chunk_check: if args.strict_verify {
+                Some(ChunkCheck::Strict)
+            } else if args.check_chunks {
+                Some(ChunkCheck::Quick)
+            } else {
+                None
+            },
             encoder: args.encoder,
             extra_splits_len: match args.extra_split {
                 Some(0) => None,
@@ -1142,8 +2553,23 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             chroma_noise: args.chroma_noise,
             sc_pix_format: args.sc_pix_format,
             keep: args.keep,
+            html_report: args.html_report,
             max_tries: args.max_tries as usize,
-            min_scene_len: args.min_scene_len,
+            two_stage: args.two_stage,
+            interactive: args.interactive,
+            assume_yes: args.yes,
+            target_chunk_time: args.target_chunk_time,
+            deadline: args.deadline,
+            min_scene_len: match args.min_scene_sec {
+                Some(seconds) => {
+                    ensure!(
+                        seconds.is_finite() && seconds >= 0.0,
+                        "--min-scene-sec must not be negative"
+                    );
+                    seconds_to_frames(seconds, clip_info.frame_rate)
+                },
+                None => args.min_scene_len,
+            },
             cache_mode: args.cache_mode,
             pix_format_converter: args.pix_format_converter,
             input_pix_format: {
@@ -1177,31 +2603,119 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             proxy,
             output_pix_format,
             resume: args.resume,
+            shared_temp: args.shared_temp,
             scenes: args.scenes.clone(),
+            edit_scenes: args.edit_scenes.clone(),
             split_method: args.split_method.clone(),
             sc_method: args.sc_method,
+            sc_decoder: args.sc_decoder,
+            sc_transitions: args.sc_transitions,
+            geometry_changes: args.geometry_changes,
+            static_scenes: match args.static_scenes {
+                Some(seconds) => {
+                    ensure!(
+                        seconds.is_finite() && seconds > 0.0,
+                        "--static-scenes must be positive"
+                    );
+                    Some(seconds_to_frames(seconds, clip_info.frame_rate).max(1))
+                },
+                None => None,
+            },
+            static_params: match args.static_params.as_ref() {
+                Some(params) => shlex::split(params)
+                    .ok_or_else(|| anyhow!("Failed to split --static-params"))?,
+                None => Vec::new(),
+            },
+            hybrid_encoder: args.hybrid_encoder,
+            hybrid_params: match args.hybrid_params.as_ref() {
+                Some(params) => shlex::split(params)
+                    .ok_or_else(|| anyhow!("Failed to split --hybrid-params"))?,
+                None => Vec::new(),
+            },
+            hybrid_share: {
+                ensure!(
+                    (0.0..=1.0).contains(&args.hybrid_share),
+                    "--hybrid-share must be between 0 and 1"
+                );
+                args.hybrid_share
+            },
             sc_only: args.sc_only,
+            sc_preview: args.sc_preview.clone(),
             sc_downscale_height: args.sc_downscale_height,
+            sc_proxy: args.sc_proxy,
+            sc_checkpoint: args.sc_checkpoint,
+            sc_lookahead: args.sc_lookahead,
             force_keyframes: parse_comma_separated_numbers(
                 args.force_keyframes.as_deref().unwrap_or(""),
             )?,
             target_quality,
-            vmaf: args.vmaf,
+            min_chunk_score: args.min_chunk_score,
+            vmaf: args.vmaf || args.metrics.contains(&OutputMetric::VMAF),
+            metrics: args.metrics.iter().fold(Vec::new(), |mut metrics, &metric| {
+                if metric != OutputMetric::VMAF && !metrics.contains(&metric) {
+                    metrics.push(metric);
+                }
+                metrics
+            }),
             vmaf_path: args.vmaf_path.clone(),
             vmaf_res: args.vmaf_res.clone(),
             probe_res: args.probe_res.clone(),
             vmaf_threads: args.vmaf_threads,
+            vmaf_cuda: args.vmaf_cuda,
             vmaf_filter: args.vmaf_filter.clone(),
+            vmaf_tonemap: args.vmaf_tonemap,
             verbosity,
-            workers: args.workers,
+            workers: config.workers.unwrap_or(0),
             tiles: (1, 1), // default value; will be adjusted if tile_auto set
             tile_auto: args.tile_auto,
             set_thread_affinity: args.set_thread_affinity,
+            tail_boost: args.tail_boost,
+            afterburner: args.afterburner,
+            probe_workers: args.probe_workers,
+            vs_server: args.vs_server,
+            gpus: args.gpus.clone(),
+            gpu_workers: args.gpu_workers,
+            output_limit: args.output_limit as usize * 1024,
+            spill_output: args.spill_output,
+            pipe_size: args.pipe_size.map(|size| size as usize * 1024),
+            memfd_buffer: args.memfd_buffer.map(|size| size as usize * 1024 * 1024),
+            peak_bitrate: args.peak_bitrate,
+            peak_buffer: args.peak_buffer,
             zones: args.zones.clone(),
+            zones_only: args.zones_only,
+            trim: if args.trim_start.is_some() || args.trim_end.is_some() {
+                let start = args.trim_start.map_or(0, |start| start.to_frame(clip_info.frame_rate));
+                let end = args.trim_end.map_or(clip_info.num_frames, |end| {
+                    end.to_frame(clip_info.frame_rate).min(clip_info.num_frames)
+                });
+                ensure!(
+                    start < end,
+                    "--trim-start must be before --trim-end and the end of the input, which has \
+                     {} frames",
+                    clip_info.num_frames
+                );
+                Some(start..end)
+            } else {
+                None
+            },
+            copy_video: args.copy_video,
             scaler,
             ignore_frame_mismatch: args.ignore_frame_mismatch,
+            ignore_linked_segments: args.ignore_linked_segments,
+            output_fps: args
+                .output_fps
+                .as_deref()
+                .map(ffmpeg::parse_frame_rate)
+                .transpose()
+                .context("Invalid --output-fps")?,
+            fps_override,
             vapoursynth_plugins,
         };
+        // Target Quality splits the threads between the workers, so it needs
+        // the worker count Av1an picks on its own when none was given
+        if arg.target_quality.workers == 0 {
+            arg.target_quality.workers = determine_workers(&arg)? as usize;
+        }
 
         valid_args.push(arg);
     }
@@ -1209,6 +2723,87 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
     Ok(valid_args)
 }
 
+/// Loads the config file (unless disabled) and merges the command line options
+/// over it
+fn effective_config(args: &CliOpts) -> anyhow::Result<GlobalConfig> {
+    let file_config = if args.no_config {
+        GlobalConfig::default()
+    } else if let Some(path) = &args.config {
+        GlobalConfig::load(path)?
+    } else {
+        match GlobalConfig::default_path() {
+            Some(path) if path.exists() => GlobalConfig::load(&path)?,
+            _ => GlobalConfig::default(),
+        }
+    };
+
+    Ok(file_config.merge(GlobalConfig {
+        workers:        (args.workers != 0).then_some(args.workers),
+        temp_root:      None,
+        niceness:       args.niceness,
+        chunk_method:   args.chunk_method,
+        notify_command: args.notify_command.clone(),
+        input_cache:    args.input_cache.clone(),
+    }))
+}
+
+/// Sets the niceness of this process, which is inherited by the encoders and
+/// other processes it spawns
+fn set_niceness(niceness: i32) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        // SAFETY: setpriority has no memory safety requirements, a `who` of 0
+        // refers to the calling process
+        let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, niceness) };
+        ensure!(
+            result == 0,
+            "Failed to set niceness to {niceness}: {}",
+            io::Error::last_os_error()
+        );
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        bail!("Setting the niceness to {niceness} is not supported on this platform");
+    }
+}
+
+/// Runs the notification command, if there is one, for a finished encode
+fn notify(command: Option<&str>, output: &str, error: Option<&anyhow::Error>) {
+    let Some(command) = command else {
+        return;
+    };
+
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = process::Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = process::Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command)
+        .env(
+            "AV1AN_STATUS",
+            if error.is_none() {
+                "success"
+            } else {
+                "failure"
+            },
+        )
+        .env("AV1AN_OUTPUT", output);
+    if let Some(error) = error {
+        cmd.env("AV1AN_ERROR_CODE", Av1anError::code_of(error).to_string());
+    }
+    let status = cmd.status();
+    match status {
+        Ok(status) if !status.success() => warn!("Notification command failed: {status}"),
+        Err(e) => warn!("Failed to run notification command: {e}"),
+        Ok(_) => (),
+    }
+}
+
 #[instrument]
 pub fn run() -> anyhow::Result<()> {
     let cli_options = CliOpts::parse();
@@ -1231,20 +2826,55 @@ pub fn run() -> anyhow::Result<()> {
         }
     };
 
+    set_progress_interval(Duration::from_secs(cli_options.progress_interval));
+    if let Some(args) = cli_options.ffmpeg_input_args.as_ref() {
+        ffmpeg::set_input_args(
+            shlex::split(args).ok_or_else(|| anyhow!("Failed to split ffmpeg input arguments"))?,
+        );
+    }
+
     // Initialize logging before fully parsing CLI options
     init_logging(
-        match verbosity {
+        cli_options.verbosity.unwrap_or(match verbosity {
             Verbosity::Quiet => LevelFilter::WARN,
             Verbosity::Normal => LevelFilter::INFO,
             Verbosity::Verbose => LevelFilter::INFO,
-        },
+        }),
         log_file,
         log_level,
     )?;
 
-    let args = parse_cli(&cli_options)?;
+    if let Some(command) = &cli_options.command {
+        return match command {
+            Commands::BenchChunkMethods(opts) => opts.run(),
+            Commands::GrainTable(opts) => opts.run(),
+            Commands::Capabilities(opts) => opts.run(),
+            Commands::Compare(opts) => opts.run(),
+            Commands::BundleDebug(opts) => opts.run(),
+        };
+    }
+
+    let config = effective_config(&cli_options)?;
+    if cli_options.dump_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+    if let Some(niceness) = config.niceness
+        && let Err(e) = set_niceness(niceness)
+    {
+        warn!("{e}");
+    }
+
+    let args = parse_cli(&cli_options, &config)?;
     for arg in args {
-        Av1anContext::new(arg)?.encode_file()?;
+        let output = arg.output_file.clone();
+        let result = Av1anContext::new(arg).and_then(|mut context| context.encode_file());
+        notify(
+            config.notify_command.as_deref(),
+            &output,
+            result.as_ref().err(),
+        );
+        result?;
     }
 
     Ok(())
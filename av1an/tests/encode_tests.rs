@@ -1380,3 +1380,15 @@ fn encode_test_sc_pix_format_vpy_input() {
     assert!(output_file.exists());
     assert!(output_file.metadata().unwrap().len() > 0);
 }
+
+#[test]
+fn dump_config_without_input() {
+    let mut cmd = Command::new(cargo_bin!("av1an"));
+
+    let assert = cmd.args(["--dump-config", "--no-config", "--workers", "3"]).assert().success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).into_owned();
+    assert!(
+        stdout.contains("workers = 3"),
+        "unexpected config: {stdout}"
+    );
+}